@@ -0,0 +1,114 @@
+//! Enforcement of coordinate ACLs
+//!
+//! A caller's key id is the scope saved searches use: `key:` and the first
+//! 16 hex digits of the bearer token's SHA3-256, or `anonymous`. Handlers
+//! check the coordinate's `acl` metadata (`bms_core::acl`) before touching
+//! it. The admin token bypasses ACLs, and every bypass of a denying ACL is
+//! written to the audit log.
+
+use crate::handlers::{is_admin, ApiResult, AppError};
+use crate::saved_search::owner_scope;
+use crate::state::AppState;
+use axum::http::HeaderMap;
+use bms_core::types::CoordId;
+use bms_core::{Access, Acl};
+use bms_storage::BmsFacade;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use tracing::warn;
+
+/// Error code for a request the coordinate's ACL does not allow
+pub(crate) const ACL_DENIED: &str = "acl_denied";
+
+/// Who is making a request
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Caller {
+    pub key_id: String,
+    pub admin: bool,
+}
+
+impl Caller {
+    pub fn from_headers(app: &AppState, headers: &HeaderMap) -> Self {
+        Self {
+            key_id: owner_scope(headers),
+            admin: is_admin(app, headers),
+        }
+    }
+
+    /// Non-admin caller with a known key id, e.g. a saved search's owner
+    pub fn key(key_id: impl Into<String>) -> Self {
+        Self {
+            key_id: key_id.into(),
+            admin: false,
+        }
+    }
+
+    /// Whether the ACL in `metadata` allows `access`, without the admin bypass
+    ///
+    /// A malformed ACL (only possible in rows written before validation)
+    /// allows nobody.
+    fn listed(&self, metadata: Option<&HashMap<String, Value>>, access: Access) -> bool {
+        match metadata.map(Acl::from_metadata).transpose() {
+            Ok(None | Some(None)) => true,
+            Ok(Some(Some(acl))) => acl.allows(access, &self.key_id),
+            Err(_) => false,
+        }
+    }
+
+    /// Check `access` against a coordinate's metadata
+    pub fn check(
+        &self,
+        coord_id: &CoordId,
+        metadata: Option<&HashMap<String, Value>>,
+        access: Access,
+    ) -> ApiResult<()> {
+        if self.listed(metadata, access) {
+            return Ok(());
+        }
+        if self.admin {
+            warn!(target: "bms::audit", coord_id = %coord_id, key_id = %self.key_id, %access,
+                "Admin token bypassed coordinate ACL");
+            return Ok(());
+        }
+        Err(AppError::ForbiddenCode {
+            code: ACL_DENIED,
+            message: format!("Key {} has no {} access to {}", self.key_id, access, coord_id),
+        })
+    }
+
+    /// Whether a listing may include a coordinate with this metadata
+    ///
+    /// Admin bypasses are counted into `bypassed` so the caller can log them
+    /// once per listing.
+    pub fn can_read(&self, metadata: Option<&HashMap<String, Value>>, bypassed: &mut usize) -> bool {
+        if self.listed(metadata, Access::Read) {
+            return true;
+        }
+        if self.admin {
+            *bypassed += 1;
+        }
+        self.admin
+    }
+
+    /// Audit-log the coordinates a listing showed only because of the admin token
+    pub fn log_bypassed(&self, listing: &str, bypassed: usize) {
+        if bypassed > 0 {
+            warn!(target: "bms::audit", key_id = %self.key_id, listing, bypassed,
+                "Admin token bypassed coordinate ACLs");
+        }
+    }
+}
+
+/// Check that `caller` has `access` to `coord_id`
+///
+/// A coordinate that does not exist has no ACL, so the handler's own
+/// not-found handling applies.
+pub async fn authorize(facade: &BmsFacade, caller: &Caller, coord_id: &CoordId, access: Access) -> ApiResult<()> {
+    let metadata = facade
+        .repository()
+        .get_coordinate(coord_id)
+        .await?
+        .and_then(|c| c.metadata);
+    caller.check(coord_id, metadata.as_ref(), access)
+}
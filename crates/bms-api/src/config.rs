@@ -0,0 +1,416 @@
+//! Configuration file and hot reload
+//!
+//! Settings are `BMS_*` environment variables. `BMS_CONFIG_FILE` names a
+//! file of `KEY=value` lines that fills in the settings the environment does
+//! not set; the binaries read it before anything else looks at the
+//! environment. SIGHUP or `POST /admin/reload-config` reads the file again.
+//! The whole file is validated first, and any error keeps the running
+//! configuration. Settings in `HOT_SETTINGS` take effect at once; any other
+//! setting that changed is reported as needing a restart.
+
+use crate::limits::{self, BodyLimits};
+use crate::state::AppState;
+use bms_core::DeltaPolicy;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tracing::{error, info};
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+/// Environment variable naming the config file
+pub const CONFIG_FILE_VAR: &str = "BMS_CONFIG_FILE";
+
+/// Settings a reload applies without a restart
+pub const HOT_SETTINGS: &[&str] = &[
+    "BMS_LOG",
+    "BMS_READ_BODY_LIMIT",
+    "BMS_STORE_BODY_LIMIT",
+    "BMS_IMPORT_BODY_LIMIT",
+    "BMS_RECALL_RESPONSE_LIMIT",
+    "BMS_MAX_OPS",
+    "BMS_MAX_STATE_BYTES",
+    "BMS_MAX_VALUE_BYTES",
+    "BMS_ALLOWED_OPS",
+    "BMS_STRICT_JSON",
+    "BMS_SEARCH_CACHE_TTL_SECS",
+    "BMS_SEARCH_CACHE_MAX",
+];
+
+/// Setting names and values
+pub type Settings = HashMap<String, String>;
+
+static SOURCE: OnceLock<ConfigSource> = OnceLock::new();
+static LOG_FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+/// Where the settings of this process come from
+#[derive(Debug, Clone, Default)]
+pub struct ConfigSource {
+    path: Option<PathBuf>,
+    /// Settings of the environment itself; these win over the file
+    environment: Settings,
+    /// Every setting as the process started with it
+    startup: Settings,
+}
+
+impl ConfigSource {
+    /// Read `BMS_CONFIG_FILE`, if set, into the environment and remember
+    /// where settings came from for later reloads
+    ///
+    /// Call before anything reads the environment; the first call wins.
+    pub fn load() -> anyhow::Result<Self> {
+        let environment = environment_settings();
+        let path = environment.get(CONFIG_FILE_VAR).filter(|p| !p.is_empty()).map(PathBuf::from);
+        let mut startup = environment.clone();
+        if let Some(path) = &path {
+            let file = read_file(path).map_err(|errors| anyhow::anyhow!(errors.join("; ")))?;
+            for (key, value) in file {
+                if !environment.contains_key(&key) {
+                    std::env::set_var(&key, &value);
+                    startup.insert(key, value);
+                }
+            }
+        }
+        let source = Self { path, environment, startup };
+        Ok(SOURCE.get_or_init(|| source).clone())
+    }
+
+    /// The source `load` read, or the bare environment
+    pub fn current() -> Self {
+        SOURCE.get().cloned().unwrap_or_else(|| {
+            let environment = environment_settings();
+            Self { path: None, startup: environment.clone(), environment }
+        })
+    }
+
+    /// Reload from `path` instead, keeping what the process started with
+    pub fn with_file(self, path: impl Into<PathBuf>) -> Self {
+        Self { path: Some(path.into()), ..self }
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// The file's settings under those of the environment
+    fn read(&self) -> Result<Settings, Vec<String>> {
+        let Some(path) = &self.path else {
+            return Err(vec![format!("no config file; set {} to reload from one", CONFIG_FILE_VAR)]);
+        };
+        let mut settings: Settings = read_file(path)?.into_iter().collect();
+        settings.extend(self.environment.clone());
+        Ok(settings)
+    }
+
+    /// Settings other than the hot ones whose value differs from startup
+    fn restart_required(&self, settings: &Settings) -> Vec<String> {
+        let mut keys: Vec<String> = settings
+            .keys()
+            .chain(self.startup.keys())
+            .filter(|key| !HOT_SETTINGS.contains(&key.as_str()) && key.as_str() != CONFIG_FILE_VAR)
+            .filter(|key| settings.get(*key) != self.startup.get(*key))
+            .cloned()
+            .collect();
+        keys.sort();
+        keys.dedup();
+        keys
+    }
+}
+
+/// Whether an environment variable is a setting
+fn is_setting(key: &str) -> bool {
+    key.starts_with("BMS_") || key == "RUST_LOG"
+}
+
+fn environment_settings() -> Settings {
+    std::env::vars().filter(|(key, _)| is_setting(key)).collect()
+}
+
+/// `KEY=value` lines of a config file, in order
+///
+/// Blank lines and `#` comments are skipped, and a value may be quoted.
+/// Every malformed line is reported, with its number.
+pub fn read_file(path: &Path) -> Result<Vec<(String, String)>, Vec<String>> {
+    let text = std::fs::read_to_string(path).map_err(|e| vec![format!("{}: {}", path.display(), e)])?;
+    let mut settings = Vec::new();
+    let mut errors = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            errors.push(format!("{}:{}: expected KEY=value", path.display(), number + 1));
+            continue;
+        };
+        let key = key.trim();
+        if !is_setting(key) {
+            errors.push(format!("{}:{}: {} is not a BMS setting", path.display(), number + 1, key));
+            continue;
+        }
+        let value = value.trim();
+        let value = value
+            .strip_prefix('"')
+            .and_then(|v| v.strip_suffix('"'))
+            .unwrap_or(value);
+        settings.push((key.to_string(), value.to_string()));
+    }
+    if errors.is_empty() {
+        Ok(settings)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Reads settings, collecting every invalid value rather than stopping at
+/// the first
+pub(crate) struct SettingsParser<'a> {
+    settings: &'a Settings,
+    errors: Vec<String>,
+}
+
+impl<'a> SettingsParser<'a> {
+    pub(crate) fn new(settings: &'a Settings) -> Self {
+        Self { settings, errors: Vec::new() }
+    }
+
+    /// `key` parsed, or `default` when unset
+    pub(crate) fn get<T>(&mut self, key: &str, default: T) -> T
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        match self.settings.get(key) {
+            None => default,
+            Some(value) => value.trim().parse().unwrap_or_else(|e| {
+                self.errors.push(format!("{}: invalid value {:?}: {}", key, value, e));
+                default
+            }),
+        }
+    }
+
+    /// `key` as a switch, accepting the values `env_flag` does; unset is off
+    pub(crate) fn flag(&mut self, key: &str) -> bool {
+        let Some(value) = self.settings.get(key) else {
+            return false;
+        };
+        match value.trim().to_ascii_lowercase().as_str() {
+            "" | "0" | "false" | "no" | "off" => false,
+            "1" | "true" | "yes" | "on" => true,
+            _ => {
+                self.errors.push(format!("{}: invalid value {:?}: expected on or off", key, value));
+                false
+            }
+        }
+    }
+
+    pub(crate) fn error(&mut self, message: impl Into<String>) {
+        self.errors.push(message.into());
+    }
+}
+
+/// Settings that can change while the server runs
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeConfig {
+    /// `BMS_LOG` filter directives; `None` logs `RUST_LOG` plus info
+    pub log_filter: Option<String>,
+    pub body_limits: BodyLimits,
+    /// Limits on the deltas stores produce
+    pub delta_policy: DeltaPolicy,
+    /// Reject store bodies with a key twice in one object
+    pub strict_json: bool,
+    pub search_cache_ttl: Duration,
+    /// 0 disables the search cache
+    pub search_cache_max: usize,
+}
+
+impl Default for RuntimeConfig {
+    fn default() -> Self {
+        Self::from_settings(&Settings::new()).expect("defaults are valid")
+    }
+}
+
+impl RuntimeConfig {
+    /// Validate every hot setting; the errors name each bad one
+    pub fn from_settings(settings: &Settings) -> Result<Self, Vec<String>> {
+        let mut parser = SettingsParser::new(settings);
+        let log_filter = settings.get("BMS_LOG").filter(|f| !f.trim().is_empty()).cloned();
+        if let Some(Err(e)) = log_filter.as_deref().map(log_filter_for) {
+            parser.error(format!("BMS_LOG: {}", e));
+        }
+        let body_limits = BodyLimits::from_settings(&mut parser);
+        if let Err(e) = body_limits.validate() {
+            parser.error(e.to_string());
+        }
+        let config = Self {
+            log_filter,
+            body_limits,
+            delta_policy: limits::delta_policy_from_settings(&mut parser),
+            strict_json: parser.flag("BMS_STRICT_JSON"),
+            search_cache_ttl: Duration::from_secs(parser.get("BMS_SEARCH_CACHE_TTL_SECS", 10)),
+            search_cache_max: parser.get("BMS_SEARCH_CACHE_MAX", 256),
+        };
+        if parser.errors.is_empty() {
+            Ok(config)
+        } else {
+            Err(parser.errors)
+        }
+    }
+
+    /// The hot settings that differ between `self` and `other`
+    pub fn changed(&self, other: &Self) -> Vec<&'static str> {
+        let (a, b) = (&self.body_limits, &other.body_limits);
+        [
+            ("BMS_LOG", self.log_filter != other.log_filter),
+            ("BMS_READ_BODY_LIMIT", a.read != b.read),
+            ("BMS_STORE_BODY_LIMIT", a.store != b.store),
+            ("BMS_IMPORT_BODY_LIMIT", a.import != b.import),
+            ("BMS_RECALL_RESPONSE_LIMIT", a.recall_response != b.recall_response),
+            ("BMS_MAX_OPS", self.delta_policy.max_ops != other.delta_policy.max_ops),
+            ("BMS_MAX_STATE_BYTES", self.delta_policy.max_state_bytes != other.delta_policy.max_state_bytes),
+            ("BMS_MAX_VALUE_BYTES", self.delta_policy.max_value_bytes != other.delta_policy.max_value_bytes),
+            ("BMS_ALLOWED_OPS", self.delta_policy.allowed_ops != other.delta_policy.allowed_ops),
+            ("BMS_STRICT_JSON", self.strict_json != other.strict_json),
+            ("BMS_SEARCH_CACHE_TTL_SECS", self.search_cache_ttl != other.search_cache_ttl),
+            ("BMS_SEARCH_CACHE_MAX", self.search_cache_max != other.search_cache_max),
+        ]
+        .into_iter()
+        .filter_map(|(key, changed)| changed.then_some(key))
+        .collect()
+    }
+}
+
+/// What a reload changed
+#[derive(Debug, Clone, Serialize)]
+pub struct ReloadReport {
+    /// Hot settings now in effect with a new value
+    pub changed: Vec<&'static str>,
+    /// Settings that changed but only take effect after a restart
+    pub restart_required: Vec<String>,
+}
+
+/// Read the config file again and apply it to `app`
+///
+/// On any error nothing is applied and every problem found is returned.
+pub fn reload(app: &AppState) -> Result<ReloadReport, Vec<String>> {
+    let result = app.config_source.read().and_then(|settings| {
+        let config = RuntimeConfig::from_settings(&settings)?;
+        Ok((config, app.config_source.restart_required(&settings)))
+    });
+    let (config, restart_required) = match result {
+        Ok(loaded) => loaded,
+        Err(errors) => {
+            error!("Configuration not reloaded: {}", errors.join("; "));
+            return Err(errors);
+        }
+    };
+
+    let changed = app.config.load().changed(&config);
+    if changed.contains(&"BMS_LOG") {
+        set_log_filter(config.log_filter.as_deref());
+    }
+    app.search_cache.resize(config.search_cache_ttl, config.search_cache_max);
+    app.config.store(std::sync::Arc::new(config));
+
+    info!(
+        "Configuration reloaded; changed: [{}], restart required: [{}]",
+        changed.join(", "),
+        restart_required.join(", ")
+    );
+    Ok(ReloadReport { changed, restart_required })
+}
+
+/// `directives`, or `RUST_LOG` plus info when there are none
+fn log_filter_for(directives: &str) -> Result<EnvFilter, String> {
+    EnvFilter::try_new(directives).map_err(|e| e.to_string())
+}
+
+fn default_log_filter() -> EnvFilter {
+    EnvFilter::from_default_env().add_directive(tracing::Level::INFO.into())
+}
+
+/// Install the global subscriber, filtered by `BMS_LOG` or else `RUST_LOG`,
+/// with a filter that reloads can replace
+pub fn init_tracing<W>(writer: W) -> anyhow::Result<()>
+where
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let filter = match std::env::var("BMS_LOG").ok().filter(|f| !f.trim().is_empty()) {
+        Some(directives) => log_filter_for(&directives).map_err(|e| anyhow::anyhow!("BMS_LOG: {}", e))?,
+        None => default_log_filter(),
+    };
+    let (filter, handle) = reload::Layer::new(filter);
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer().with_target(false).with_writer(writer))
+        .init();
+    let _ = LOG_FILTER.set(handle);
+    Ok(())
+}
+
+/// Swap the log filter, if `init_tracing` installed the subscriber
+fn set_log_filter(directives: Option<&str>) {
+    let Some(handle) = LOG_FILTER.get() else {
+        return;
+    };
+    // Validated before the reload got here
+    let filter = directives.map_or_else(|| Ok(default_log_filter()), log_filter_for);
+    if let Err(e) = filter.map_err(|e| e.to_string()).and_then(|f| handle.reload(f).map_err(|e| e.to_string())) {
+        error!("Failed to swap the log filter: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_file_lines() {
+        let path = std::env::temp_dir().join(format!("bms-config-lines-{}.env", std::process::id()));
+        std::fs::write(&path, "# limits\nBMS_READ_BODY_LIMIT = 2048\n\nBMS_LOG=\"bms=debug\"\n").unwrap();
+        assert_eq!(
+            read_file(&path).unwrap(),
+            vec![
+                ("BMS_READ_BODY_LIMIT".to_string(), "2048".to_string()),
+                ("BMS_LOG".to_string(), "bms=debug".to_string()),
+            ]
+        );
+
+        std::fs::write(&path, "BMS_READ_BODY_LIMIT\nHOME=/tmp\nBMS_SEARCH_CACHE_MAX=8\n").unwrap();
+        let errors = read_file(&path).unwrap_err();
+        assert_eq!(errors.len(), 2, "{:?}", errors);
+        assert!(errors[0].ends_with(":1: expected KEY=value"), "{:?}", errors);
+        assert!(errors[1].contains(":2: HOME is not a BMS setting"), "{:?}", errors);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_every_bad_setting_is_reported() {
+        let settings: Settings = [
+            ("BMS_SEARCH_CACHE_MAX", "lots"),
+            ("BMS_READ_BODY_LIMIT", "4096"),
+            ("BMS_STORE_BODY_LIMIT", "1024"),
+            ("BMS_STRICT_JSON", "maybe"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+        let errors = RuntimeConfig::from_settings(&settings).unwrap_err();
+        assert_eq!(errors.len(), 3, "{:?}", errors);
+        assert!(errors.iter().any(|e| e.starts_with("BMS_SEARCH_CACHE_MAX")));
+        assert!(errors.iter().any(|e| e.starts_with("BMS_STRICT_JSON")));
+        assert!(errors.iter().any(|e| e.contains("larger than BMS_STORE_BODY_LIMIT")));
+
+        let defaults = RuntimeConfig::default();
+        let mut changed = defaults.clone();
+        changed.search_cache_max = 8;
+        changed.log_filter = Some("debug".to_string());
+        assert_eq!(defaults.changed(&changed), vec!["BMS_LOG", "BMS_SEARCH_CACHE_MAX"]);
+    }
+}
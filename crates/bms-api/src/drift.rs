@@ -0,0 +1,222 @@
+//! Skipping re-embeds for heads whose text barely changed
+//!
+//! Most stores touch a counter or a timestamp, and embedding the new head
+//! again costs a model call for a vector that would hardly move. Each cache
+//! entry keeps a sketch of the text it was embedded from: the smallest
+//! `SKETCH_SIZE` hashes of its lowercased word tokens, leaving out tokens
+//! without a letter (counters, timestamps). When a head changes, the Jaccard
+//! similarity of the two token sets is estimated from the sketches. Above
+//! the threshold the entry only moves its head marker and keeps the
+//! embedding and the sketch, so small changes cannot add up unnoticed. A
+//! threshold of 1.0 turns the check off.
+
+use crate::state::CachedEmbedding;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Default similarity above which a changed head keeps its embedding
+pub const DEFAULT_DRIFT_THRESHOLD: f64 = 0.9;
+
+/// Token hashes kept per sketch; token sets up to this size compare exactly
+pub const SKETCH_SIZE: usize = 128;
+
+/// Smallest token hashes of an embedded text, sorted and distinct
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextSketch(Vec<u64>);
+
+impl TextSketch {
+    pub fn new(text: &str) -> Self {
+        let mut hashes: Vec<u64> = text
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|token| token.chars().any(char::is_alphabetic))
+            .map(|token| token_hash(&token.to_lowercase()))
+            .collect();
+        hashes.sort_unstable();
+        hashes.dedup();
+        hashes.truncate(SKETCH_SIZE);
+        Self(hashes)
+    }
+
+    /// Estimated Jaccard similarity of the two token sets
+    ///
+    /// Of the `SKETCH_SIZE` smallest hashes in either sketch, the share that
+    /// is in both.
+    pub fn similarity(&self, other: &TextSketch) -> f64 {
+        let (a, b) = (&self.0, &other.0);
+        let (mut i, mut j, mut union, mut shared) = (0, 0, 0, 0);
+        while union < SKETCH_SIZE && (i < a.len() || j < b.len()) {
+            match (a.get(i), b.get(j)) {
+                (Some(x), Some(y)) if x == y => {
+                    shared += 1;
+                    i += 1;
+                    j += 1;
+                }
+                (Some(x), Some(y)) if x < y => i += 1,
+                (Some(_), None) => i += 1,
+                _ => j += 1,
+            }
+            union += 1;
+        }
+        if union == 0 {
+            1.0
+        } else {
+            shared as f64 / union as f64
+        }
+    }
+}
+
+/// FNV-1a with a SplitMix64 finalizer: stable across runs, unlike
+/// `DefaultHasher`, and well mixed, so the smallest hashes are a fair sample
+fn token_hash(token: &str) -> u64 {
+    let mut hash = token
+        .bytes()
+        .fold(0xcbf29ce484222325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3));
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d049bb133111eb);
+    hash ^ (hash >> 31)
+}
+
+/// Counters reported in `/stats`
+#[derive(Debug, Clone, Serialize)]
+pub struct DriftStats {
+    pub threshold: f64,
+    /// Changed heads that kept their embedding
+    pub skipped: u64,
+    /// Changed heads embedded again
+    pub recomputed: u64,
+}
+
+/// Similarity threshold for re-embedding changed heads, with counters
+pub struct DriftPolicy {
+    threshold: f64,
+    skipped: AtomicU64,
+    recomputed: AtomicU64,
+}
+
+impl DriftPolicy {
+    /// `threshold` is clamped to 0.0..=1.0; 1.0 re-embeds every changed head
+    pub fn new(threshold: f64) -> Self {
+        Self {
+            threshold: threshold.clamp(0.0, 1.0),
+            skipped: AtomicU64::new(0),
+            recomputed: AtomicU64::new(0),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.threshold < 1.0
+    }
+
+    /// Sketch of a text about to be embedded; `None` while the check is off
+    pub fn sketch(&self, text: &str) -> Option<TextSketch> {
+        self.is_enabled().then(|| TextSketch::new(text))
+    }
+
+    /// Keep `entry` for a head that changed to `head_hash` if its text is
+    /// close enough to the embedded one
+    ///
+    /// On a skip only the head marker moves. Entries without a sketch are
+    /// always embedded again.
+    pub fn try_keep(&self, entry: &mut CachedEmbedding, head_hash: &str, sketch: Option<&TextSketch>) -> bool {
+        let keep = match (&entry.sketch, sketch) {
+            (Some(embedded), Some(current)) => embedded.similarity(current) > self.threshold,
+            _ => false,
+        };
+        if keep {
+            entry.head_hash = head_hash.to_string();
+            self.skipped.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.recomputed.fetch_add(1, Ordering::Relaxed);
+        }
+        keep
+    }
+
+    pub fn stats(&self) -> DriftStats {
+        DriftStats {
+            threshold: self.threshold,
+            skipped: self.skipped.load(Ordering::Relaxed),
+            recomputed: self.recomputed.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    fn cached(text: &str, policy: &DriftPolicy) -> CachedEmbedding {
+        CachedEmbedding {
+            head_hash: text.to_string(),
+            embedding: vec![1.0],
+            author: None,
+            created_at: chrono::Utc::now(),
+            custom: HashMap::new(),
+            sketch: policy.sketch(text),
+            facets: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_counter_changes_keep_the_embedding() {
+        let policy = DriftPolicy::new(DEFAULT_DRIFT_THRESHOLD);
+        let note = |views: u32, body: &str| {
+            json!({
+                "title": "Quarterly planning notes",
+                "body": body,
+                "views": views,
+                "updated_at": format!("2026-10-16T09:{:02}:00Z", views),
+            })
+            .to_string()
+        };
+        let body = "Agreed to move the launch to March and hire two more engineers for the platform team";
+        let mut entry = cached(&note(5, body), &policy);
+
+        // Counter and timestamp only: the marker moves, the embedding stays
+        let counted = note(6, body);
+        assert!(policy.try_keep(&mut entry, &counted, policy.sketch(&counted).as_ref()));
+        assert_eq!(entry.head_hash, counted);
+        assert_eq!(entry.sketch, policy.sketch(&note(5, body)));
+
+        // New content is embedded again
+        let rewritten = note(7, "Launch cancelled; the budget goes to reliability work on storage instead");
+        assert!(!policy.try_keep(&mut entry, &rewritten, policy.sketch(&rewritten).as_ref()));
+        assert_eq!(entry.head_hash, counted);
+        assert_eq!((policy.stats().skipped, policy.stats().recomputed), (1, 1));
+
+        // In a small state one changed word is drift
+        let mut small = cached(&json!({"status": "open", "count": 5}).to_string(), &policy);
+        let bumped = json!({"status": "open", "count": 6}).to_string();
+        assert!(policy.try_keep(&mut small, &bumped, policy.sketch(&bumped).as_ref()));
+        let closed = json!({"status": "closed", "count": 6}).to_string();
+        assert!(!policy.try_keep(&mut small, &closed, policy.sketch(&closed).as_ref()));
+    }
+
+    #[test]
+    fn test_threshold_one_always_embeds() {
+        let off = DriftPolicy::new(1.0);
+        let text = json!({"note": "unchanged text"}).to_string();
+        assert!(off.sketch(&text).is_none());
+        let mut entry = cached(&text, &off);
+        assert!(!off.try_keep(&mut entry, "next", off.sketch(&text).as_ref()));
+
+        // Entries cached without a sketch are embedded again as well
+        let on = DriftPolicy::new(0.5);
+        assert!(!on.try_keep(&mut entry, "next", on.sketch(&text).as_ref()));
+        assert_eq!(on.stats().recomputed, 1);
+    }
+
+    #[test]
+    fn test_similarity_of_large_texts_is_estimated() {
+        let words: Vec<String> = (0..1000).map(|n| format!("word{}", n)).collect();
+        let full = TextSketch::new(&words.join(" "));
+        assert_eq!(full.0.len(), SKETCH_SIZE);
+        assert_eq!(full.similarity(&full), 1.0);
+
+        let half = TextSketch::new(&words[..500].join(" "));
+        let estimate = full.similarity(&half);
+        assert!((0.35..0.65).contains(&estimate), "{}", estimate);
+        assert_eq!(TextSketch::new("").similarity(&TextSketch::new("")), 1.0);
+    }
+}
@@ -0,0 +1,117 @@
+//! Embedding model behind `/search`
+//!
+//! With the `vector` feature this wraps the FastEmbed generator. Without it
+//! `Embedder` is an empty enum, so `AppState::embedder` is always `None` and
+//! search answers with a capability error instead of pulling in ONNX.
+
+#[cfg(feature = "vector")]
+mod imp {
+    use crate::server::env_or;
+    use bms_vector::batch::DEFAULT_BATCH_SIZE;
+    use bms_vector::{embed_in_batches, BatchStats, EmbeddingGenerator, VectorError};
+    use tokio::sync::Mutex;
+
+    /// Embedding model with its batching settings and counters
+    pub struct Embedder {
+        generator: Mutex<Model>,
+        /// Max head states embedded per model call
+        batch_size: usize,
+        stats: std::sync::Mutex<BatchStats>,
+    }
+
+    // Only one is ever built, so the size difference costs nothing
+    #[cfg_attr(test, allow(clippy::large_enum_variant))]
+    enum Model {
+        FastEmbed(EmbeddingGenerator),
+        /// Deterministic stand-in so tests can search without a model download
+        #[cfg(test)]
+        Fake(fn(&str) -> Vec<f32>),
+    }
+
+    impl Model {
+        fn generate(&mut self, text: &str) -> Result<Vec<f32>, VectorError> {
+            match self {
+                Model::FastEmbed(generator) => generator.generate(text),
+                #[cfg(test)]
+                Model::Fake(embed) => Ok(embed(text)),
+            }
+        }
+
+        fn generate_batch(&mut self, texts: &[String]) -> Result<Vec<Vec<f32>>, VectorError> {
+            match self {
+                Model::FastEmbed(generator) => generator.generate_batch(texts.iter().map(String::as_str).collect()),
+                #[cfg(test)]
+                Model::Fake(embed) => Ok(texts.iter().map(|t| embed(t)).collect()),
+            }
+        }
+    }
+
+    impl Embedder {
+        /// Load the default model; fails when it cannot be loaded or downloaded
+        pub fn load() -> Result<Self, String> {
+            let generator = EmbeddingGenerator::new().map_err(|e| e.to_string())?;
+            Ok(Self::with_model(Model::FastEmbed(generator)))
+        }
+
+        /// Embedder whose vectors come from `embed`
+        #[cfg(test)]
+        pub fn fake(embed: fn(&str) -> Vec<f32>) -> Self {
+            Self::with_model(Model::Fake(embed))
+        }
+
+        fn with_model(model: Model) -> Self {
+            Self {
+                generator: Mutex::new(model),
+                batch_size: env_or("BMS_EMBED_BATCH_SIZE", DEFAULT_BATCH_SIZE),
+                stats: std::sync::Mutex::new(BatchStats::default()),
+            }
+        }
+
+        pub async fn embed_query(&self, text: &str) -> Result<Vec<f32>, String> {
+            self.generator.lock().await.generate(text).map_err(|e| e.to_string())
+        }
+
+        /// One result per text, in order; see `bms_vector::batch` and
+        /// `bms_core::extract`
+        pub async fn embed_texts(&self, texts: &[String]) -> Vec<Result<Vec<f32>, String>> {
+            let mut generator = self.generator.lock().await;
+            let mut stats = self.stats.lock().unwrap_or_else(|e| e.into_inner());
+            embed_in_batches(texts, self.batch_size, &mut stats, |chunk| generator.generate_batch(chunk))
+            .into_iter()
+            .map(|result| result.map_err(|e| e.to_string()))
+            .collect()
+        }
+
+        /// Batch counters for `/stats`
+        pub fn stats(&self) -> serde_json::Value {
+            let stats = self.stats.lock().unwrap_or_else(|e| e.into_inner()).clone();
+            serde_json::to_value(stats).unwrap_or_default()
+        }
+    }
+}
+
+#[cfg(not(feature = "vector"))]
+mod imp {
+    /// Never constructed: this build has no embedding model
+    pub enum Embedder {}
+
+    impl Embedder {
+        pub fn load() -> Result<Self, String> {
+            Err("built without the `vector` feature".to_string())
+        }
+
+        pub async fn embed_query(&self, _text: &str) -> Result<Vec<f32>, String> {
+            match *self {}
+        }
+
+        pub async fn embed_texts(&self, _texts: &[String]) -> Vec<Result<Vec<f32>, String>> {
+            match *self {}
+        }
+
+        pub fn stats(&self) -> serde_json::Value {
+            match *self {}
+        }
+    }
+}
+
+pub use imp::Embedder;
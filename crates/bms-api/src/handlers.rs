@@ -1,20 +1,41 @@
 use axum::{
+    body::{Body, Bytes},
     extract::{Path, Query, State},
-    http::StatusCode,
+    http::{header, HeaderMap, StatusCode},
     response::{IntoResponse, Json},
 };
+use bms_core::extract::{facet_text, split_facets, ExtractedText};
+use bms_core::humanize;
+use bms_core::importance::{self, DEFAULT_IMPORTANCE};
+use bms_core::filter::{metadata_tags, FilterExpr, FilterRecord};
 use bms_core::{
-    types::*, CoordinateGenerator, DeltaEngine, MerkleChain,
+    redact, types::*, Access, Canonicalizer, CoordinateGenerator, DeltaEngine, DeltaPolicy, DiffOptions, DiffSummary,
+    HashAlgorithm, MerkleChain, OpsStats, PatchRatios,
 };
+use bms_storage::facade::{
+    AppendOutcome, Head, IndexStatus, SnapshotStatus, StoreHead, StoreOutcome, StoreParams, StorePrecondition,
+    StoreTimings, StoreWarning,
+};
+use bms_storage::models::{SavedSearch, StateSize};
+use bms_storage::BmsFacade;
+use bms_storage::planner::{self, AppliedAction, PlanAction, Recommendation};
+use futures_util::StreamExt;
 use serde::{Deserialize, Serialize};
 use sha3::Digest;
 use std::collections::HashMap;
 use std::sync::Arc;
-use tracing::info;
+use tracing::{debug, info, warn};
 
-use crate::state::{AppState, CachedEmbedding};
+use crate::acl::{self, Caller};
+use crate::config::{self, ReloadReport};
+use crate::drift::TextSketch;
+use crate::embedder::Embedder;
+use crate::saved_search::{self, SavedRunResponse};
+use crate::search_cache::SearchKey;
+use crate::state::{AppState, CachedEmbedding, FacetEmbedding};
+use crate::sync;
 
-type ApiResult<T> = std::result::Result<T, AppError>;
+pub(crate) type ApiResult<T> = std::result::Result<T, AppError>;
 
 #[derive(Debug, Deserialize)]
 pub struct StoreRequest {
@@ -22,138 +43,420 @@ pub struct StoreRequest {
     pub state: serde_json::Value,
     pub metadata: Option<HashMap<String, serde_json::Value>>,
     pub author: Option<String>,
+    /// Reject the store with 409 unless the head delta has this ID.
+    /// An `If-Match` header takes precedence when both are present.
+    pub expected_head_delta_id: Option<String>,
+    /// Reject the store with 409 unless the head has this chain hash, the
+    /// body form of `If-Match`; at most one of the two `expected_` fields
+    pub expected_head_hash: Option<String>,
+    /// Array diff strategy for this store (defaults to the coordinate's `diff` metadata)
+    pub diff_options: Option<DiffOptions>,
+    /// Historical timestamp for the delta (requires the admin token)
+    pub created_at_override: Option<chrono::DateTime<chrono::Utc>>,
+    /// Author of each computed op, in op order; a count that does not match
+    /// the ops answers 400
+    pub op_authors: Option<Vec<String>>,
+    /// Create the coordinate as scratch space: left out of `/coords` and
+    /// `/search`, and deleted once idle unless promoted
+    #[serde(default)]
+    pub ephemeral: bool,
+    /// Namespace to create the coordinate in, such as `agents/planner`;
+    /// a generated coordinate ID differs per namespace
+    pub namespace: Option<String>,
+    /// Chain hash function of a new coordinate, `sha3-256` (default) or
+    /// `blake3`; an existing coordinate keeps its own
+    pub hash_algorithm: Option<HashAlgorithm>,
+    /// Existing coordinate a new one branches from; listed under
+    /// `GET /coords/:id/children`
+    pub parent_coord_id: Option<String>,
+    /// Without `coord_hint`, the child of `parent_coord_id` to store to:
+    /// the same label always names the same child
+    pub child_label: Option<String>,
+}
+
+impl StoreRequest {
+    /// Put the hint and parent in canonical form, see `parse_coord_id`
+    async fn normalize_ids(&mut self, facade: &BmsFacade) -> ApiResult<()> {
+        for id in [&mut self.coord_hint, &mut self.parent_coord_id].into_iter().flatten() {
+            *id = parse_coord_id(facade, id).await?.0;
+        }
+        Ok(())
+    }
+
+    /// Coordinate the request names up front: the hint, or the child it derives
+    fn named_target(&self) -> ApiResult<Option<CoordId>> {
+        match (&self.coord_hint, &self.parent_coord_id, &self.child_label) {
+            (_, None, Some(_)) => Err(AppError::BadRequest("child_label needs a parent_coord_id".to_string())),
+            (Some(hint), _, _) => Ok(Some(CoordId(hint.clone()))),
+            (None, Some(parent), Some(label)) => {
+                Ok(Some(CoordinateGenerator::derive_child(&CoordId(parent.clone()), label)))
+            }
+            (None, Some(_), None) => Err(AppError::BadRequest(
+                "parent_coord_id needs a child_label or a coord_hint".to_string(),
+            )),
+            (None, None, None) => Ok(None),
+        }
+    }
+}
+
+/// Error code for a timestamp override sent without the admin token
+const OVERRIDE_FORBIDDEN: &str = "created_at_override_forbidden";
+
+/// Error code for a write sent to a standby
+const READ_ONLY: &str = "read_only";
+
+/// Error code for a patch op that does not apply to the current head
+const PATCH_REJECTED: &str = "patch_rejected";
+
+/// Error code for a delta outside the configured `DeltaPolicy`
+const POLICY_VIOLATION: &str = "policy_violation";
+
+fn check_override_allowed(app: &AppState, headers: &HeaderMap, req: &StoreRequest) -> ApiResult<()> {
+    if req.created_at_override.is_some() && !is_admin(app, headers) {
+        return Err(AppError::ForbiddenCode {
+            code: OVERRIDE_FORBIDDEN,
+            message: "created_at_override requires the admin token".to_string(),
+        });
+    }
+    Ok(())
+}
+
+fn check_namespace(namespace: Option<&str>) -> ApiResult<()> {
+    if let Some(namespace) = namespace {
+        CoordinateGenerator::validate_namespace(namespace).map_err(|e| AppError::BadRequest(e.to_string()))?;
+    }
+    Ok(())
 }
 
 #[derive(Debug, Serialize)]
 pub struct StoreResponse {
     pub coord_id: String,
+    pub head: StoreHead,
+    pub snapshot: SnapshotStatus,
+    pub indexed: IndexStatus,
+    pub deduplicated: bool,
+    /// Stable warning codes; unknown codes must be ignored
+    pub warnings: Vec<StoreWarning>,
+    /// Only with `?explain=true`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timings: Option<StoreTimings>,
+    /// Deprecated, same as `head.delta_id`; removed in the next release
     pub delta_id: String,
+    /// Deprecated, same as `snapshot == "created"`; removed in the next release
     pub snapshot_created: bool,
 }
 
+impl From<StoreOutcome> for StoreResponse {
+    fn from(outcome: StoreOutcome) -> Self {
+        Self {
+            coord_id: outcome.coord_id.0,
+            delta_id: outcome.head.delta_id.0.clone(),
+            snapshot_created: outcome.snapshot == SnapshotStatus::Created,
+            head: outcome.head,
+            snapshot: outcome.snapshot,
+            indexed: outcome.indexed,
+            deduplicated: outcome.deduplicated,
+            warnings: outcome.warnings,
+            timings: outcome.timings,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ExplainQuery {
+    /// Include per-phase timings in store responses
+    #[serde(default)]
+    pub explain: bool,
+}
+
+/// Precondition from a store body's `expected_head_delta_id` or
+/// `expected_head_hash`
+fn body_precondition(req: &StoreRequest) -> ApiResult<Option<StorePrecondition>> {
+    match (&req.expected_head_delta_id, &req.expected_head_hash) {
+        (Some(_), Some(_)) => Err(AppError::BadRequest(
+            "Send expected_head_delta_id or expected_head_hash, not both".to_string(),
+        )),
+        (Some(delta_id), None) => Ok(Some(StorePrecondition::HeadDeltaId(DeltaId(delta_id.clone())))),
+        (None, Some(hash)) => Ok(Some(StorePrecondition::HeadChainHash(Hash(hash.clone())))),
+        (None, None) => Ok(None),
+    }
+}
+
 /// Store a new state
+///
+/// Supports conditional writes: `If-Match: "<head chain_hash>"` answers 412
+/// when the head moved, `expected_head_delta_id` or `expected_head_hash` in
+/// the body answers 409.
 pub async fn store_state(
     State(app): State<Arc<AppState>>,
-    Json(req): Json<StoreRequest>,
-) -> ApiResult<Json<StoreResponse>> {
+    Query(query): Query<ExplainQuery>,
+    headers: HeaderMap,
+    Json(mut req): Json<StoreRequest>,
+) -> ApiResult<impl IntoResponse> {
     info!("Storing new state");
+    req.normalize_ids(&app.facade).await?;
+    check_override_allowed(&app, &headers, &req)?;
+    check_namespace(req.namespace.as_deref())?;
+    if let Some(target) = req.named_target()? {
+        let caller = Caller::from_headers(&app, &headers);
+        acl::authorize(&app.facade, &caller, &target, Access::Write).await?;
+    }
 
-    // Generate or retrieve coordinate
-    let coord_id = if let Some(hint) = req.coord_hint {
-        CoordId(hint)
-    } else {
-        CoordinateGenerator::generate_now(&req.state)?
+    let if_match = parse_if_match(&headers)?;
+    let precondition = match (&if_match, body_precondition(&req)?) {
+        (Some(chain_hash), _) => Some(StorePrecondition::HeadChainHash(chain_hash.clone())),
+        (None, precondition) => precondition,
     };
 
-    // Check if coordinate exists, if not create it
-    if !app.repository.coordinate_exists(&coord_id).await? {
-        let coordinate = Coordinate {
-            id: coord_id.clone(),
-            rune_alias: None,
-            created_at: chrono::Utc::now(),
-            metadata: req.metadata,
-        };
-        app.repository.insert_coordinate(&coordinate).await?;
-        info!("Created new coordinate: {}", coord_id);
+    // Like any metadata, the flag only applies to a coordinate being created
+    let mut metadata = req.metadata;
+    if req.ephemeral {
+        metadata
+            .get_or_insert_with(HashMap::new)
+            .insert(EPHEMERAL_METADATA_KEY.to_string(), serde_json::Value::Bool(true));
     }
 
-    // Get previous deltas
-    let deltas = app.repository.get_deltas(&coord_id).await?;
-    let delta_count = deltas.len() as u32;
+    let policy = app.config.load().delta_policy.clone();
+    check_state_size(&policy, &req.state)?;
 
-    // Get previous state for delta computation
-    let prev_state = if let Some(snapshot) = app.repository.get_latest_snapshot(&coord_id).await? {
-        // Reconstruct from snapshot
-        bms_core::SnapshotManager::reconstruct(&snapshot, &deltas[..])?
-    } else if deltas.is_empty() {
-        // First state for this coordinate
-        serde_json::json!({})
-    } else {
-        // Reconstruct from all deltas
-        let mut state = serde_json::json!({});
-        for delta in &deltas {
-            DeltaEngine::apply_delta(&mut state, &delta.ops)?;
+    // Note: Design alignment - we do NOT generate/store embeddings here
+    // Vectors are search metadata (ephemeral), not canonical storage
+    // Embeddings are computed on-demand during search and cached
+    let result = app
+        .facade
+        .store(StoreParams {
+            coord_id: req.coord_hint.map(CoordId),
+            state: req.state,
+            metadata,
+            author: req.author,
+            precondition,
+            diff_options: req.diff_options,
+            explain: query.explain,
+            created_at: req.created_at_override,
+            op_authors: req.op_authors,
+            policy: Some(policy),
+            namespace: req.namespace,
+            hash_algorithm: req.hash_algorithm,
+            parent_coord_id: req.parent_coord_id.map(CoordId),
+            child_label: req.child_label,
+        })
+        .await;
+
+    let outcome = match result {
+        Ok(outcome) => outcome,
+        Err(bms_core::error::BmsError::PreconditionFailed { expected, actual, head_chain_hash }) => {
+            let message = format!(
+                "Precondition failed: expected head {}, found {}",
+                expected, actual
+            );
+            return Err(if if_match.is_some() {
+                AppError::PreconditionFailed {
+                    message,
+                    etag: head_chain_hash,
+                }
+            } else {
+                AppError::Conflict(message)
+            });
         }
-        state
+        Err(
+            e @ (bms_core::error::BmsError::InvalidTimestamp(_)
+            | bms_core::error::BmsError::OpAuthorsMismatch { .. }
+            | bms_core::error::BmsError::InvalidArrayKey { .. }),
+        ) => return Err(AppError::BadRequest(e.to_string())),
+        Err(e) => return Err(e.into()),
     };
 
-    // Compute delta
-    let ops = DeltaEngine::compute_delta(&prev_state, &req.state)?;
-    let delta_hash = DeltaEngine::hash_delta(&ops)?;
-    let delta_id = DeltaEngine::generate_delta_id(&ops)?;
+    Ok((
+        [(header::ETAG, format_etag(&outcome.head.chain_hash))],
+        Json(StoreResponse::from(outcome)),
+    ))
+}
 
-    // Get parent info
-    let (parent_id, parent_hash) = if let Some(last_delta) = deltas.last() {
-        (Some(last_delta.id.clone()), Some(last_delta.chain_hash.clone()))
-    } else {
-        (None, None)
-    };
+#[derive(Debug, Deserialize)]
+pub struct StoreGroupRequest {
+    pub items: Vec<StoreRequest>,
+}
 
-    // Compute chain hash
-    let chain_hash = if let Some(ref ph) = parent_hash {
-        MerkleChain::compute_chain_hash(ph, &delta_hash)
-    } else {
-        delta_hash.clone()
+#[derive(Debug, Serialize)]
+pub struct StoreGroupResponse {
+    pub items: Vec<StoreResponse>,
+}
+
+/// Store several states atomically
+///
+/// Either every item is stored or none is. An item whose
+/// `expected_head_delta_id` or `expected_head_hash` does not match answers
+/// 409 naming the item.
+pub async fn store_group(
+    State(app): State<Arc<AppState>>,
+    Query(query): Query<ExplainQuery>,
+    headers: HeaderMap,
+    Json(mut req): Json<StoreGroupRequest>,
+) -> ApiResult<Json<StoreGroupResponse>> {
+    if req.items.is_empty() {
+        return Err(AppError::BadRequest("Store group has no items".to_string()));
+    }
+    let caller = Caller::from_headers(&app, &headers);
+    let policy = app.config.load().delta_policy.clone();
+    for item in &mut req.items {
+        item.normalize_ids(&app.facade).await?;
+        check_override_allowed(&app, &headers, item)?;
+        check_namespace(item.namespace.as_deref())?;
+        check_state_size(&policy, &item.state)?;
+        if let Some(target) = item.named_target()? {
+            acl::authorize(&app.facade, &caller, &target, Access::Write).await?;
+        }
+    }
+    info!("Storing group of {} states", req.items.len());
+
+    let items = req
+        .items
+        .into_iter()
+        .map(|item| {
+            Ok(StoreParams {
+                precondition: body_precondition(&item)?,
+                coord_id: item.coord_hint.map(CoordId),
+                state: item.state,
+                metadata: item.metadata,
+                author: item.author,
+                diff_options: item.diff_options,
+                explain: query.explain,
+                created_at: item.created_at_override,
+                op_authors: item.op_authors,
+                policy: Some(policy.clone()),
+                namespace: item.namespace,
+                hash_algorithm: item.hash_algorithm,
+                parent_coord_id: item.parent_coord_id.map(CoordId),
+                child_label: item.child_label,
+            })
+        })
+        .collect::<ApiResult<Vec<_>>>()?;
+
+    let outcomes = match app.facade.store_group(items).await {
+        Ok(outcomes) => outcomes,
+        Err(e @ bms_core::error::BmsError::GroupConflict { .. }) => {
+            return Err(AppError::Conflict(e.to_string()))
+        }
+        Err(
+            e @ (bms_core::error::BmsError::InvalidState(_)
+            | bms_core::error::BmsError::InvalidTimestamp(_)
+            | bms_core::error::BmsError::OpAuthorsMismatch { .. }
+            | bms_core::error::BmsError::InvalidArrayKey { .. }),
+        ) => {
+            return Err(AppError::BadRequest(e.to_string()))
+        }
+        Err(e) => return Err(e.into()),
     };
 
-    // Prepare author for delta (unused now but kept for future telic coherence)
-    let _author_name_for_metadata = req
-        .author
-        .as_deref()
-        .unwrap_or("unknown")
-        .to_string();
-
-    // Create delta
-    let delta = Delta {
-        id: delta_id.clone(),
-        coord_id: coord_id.clone(),
-        parent_id,
-        parent_hash,
-        delta_hash,
-        chain_hash,
-        ops,
-        created_at: chrono::Utc::now(),
-        tags: None,
-        author: req.author.clone(),
+    Ok(Json(StoreGroupResponse {
+        items: outcomes.into_iter().map(StoreResponse::from).collect(),
+    }))
+}
+
+/// Refuse a state over the policy's size limit before it is diffed
+fn check_state_size(policy: &DeltaPolicy, state: &serde_json::Value) -> ApiResult<()> {
+    policy.check_state(state).map_err(|e| AppError::TooLarge {
+        message: e.to_string(),
+        stream: None,
+    })
+}
+
+/// Whether the request carries the configured admin bearer token
+pub(crate) fn is_admin(app: &AppState, headers: &HeaderMap) -> bool {
+    let Some(admin_token) = app.admin_token.as_deref() else {
+        return false;
     };
 
-    // Store delta
-    app.repository.insert_delta(&delta).await?;
+    bearer_token(headers).is_some_and(|token| token == admin_token)
+}
 
-    // Note: Design alignment - we do NOT generate/store embeddings here
-    // Vectors are search metadata (ephemeral), not canonical storage
-    // Embeddings are computed on-demand during search and cached
+/// Token of an `Authorization: Bearer` header
+pub(crate) fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::trim)
+}
+
+/// Format a head chain hash as a strong entity tag
+fn format_etag(chain_hash: &Hash) -> String {
+    format!("\"{}\"", chain_hash.0)
+}
 
-    // Check if snapshot needed
-    let mut snapshot_created = false;
-    if app.snapshot_manager.should_snapshot(delta_count + 1) {
-        let snapshot = app.snapshot_manager.create_snapshot(
-            coord_id.clone(),
-            delta_id.clone(),
-            req.state.clone(),
-        )?;
-        app.repository.insert_snapshot(&snapshot).await?;
-        snapshot_created = true;
-        info!("Created snapshot for coordinate: {}", coord_id);
+/// Extract the expected head chain hash from an `If-Match` header
+fn parse_if_match(headers: &HeaderMap) -> ApiResult<Option<Hash>> {
+    let Some(value) = headers.get(header::IF_MATCH) else {
+        return Ok(None);
+    };
+
+    let value = value
+        .to_str()
+        .map_err(|_| AppError::BadRequest("Invalid If-Match header".to_string()))?
+        .trim();
+
+    if value.starts_with("W/") {
+        return Err(AppError::BadRequest(
+            "Weak entity tags cannot be used with If-Match".to_string(),
+        ));
     }
 
-    Ok(Json(StoreResponse {
-        coord_id: coord_id.0,
-        delta_id: delta_id.0,
-        snapshot_created,
-    }))
+    Ok(Some(Hash(value.trim_matches('"').to_string())))
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SearchRequest {
     pub query: String,
     pub limit: Option<usize>,
     pub author: Option<String>,
+    // Accepted for CLI compatibility; head states carry no tags to filter on yet
     pub tags: Option<Vec<String>>,
     pub min_score: Option<f32>,
+    /// Share of the final score taken from coordinate importance (0 to 1)
+    pub importance_weight: Option<f32>,
+    /// Results to skip before the first one returned
+    pub offset: Option<usize>,
+    /// Skip the result cache for this search; the fresh result is still cached
+    #[serde(default)]
+    pub no_cache: bool,
+    /// Coordinate metadata values results must have; only keys listed in
+    /// `BMS_INDEX_METADATA_KEYS` can be filtered on
+    pub metadata: Option<HashMap<String, serde_json::Value>>,
+    /// Text matched against single facets, e.g. `{"topic": "database
+    /// migrations"}`; only coordinates with every facet are returned. Keys
+    /// must be listed in `BMS_INDEX_FACETS`
+    pub facets: Option<HashMap<String, String>>,
+    /// Share of the score taken from facet similarity (0 to 1, default 0.5)
+    pub facet_weight: Option<f32>,
+    /// Filter expression in the JSON form of `bms_core::filter`, e.g.
+    /// `{"and": [{"tag": "urgent"}, {"eq": {"field": "/status", "value": "open"}}]}`;
+    /// replaces `author`, `tags` and `metadata`, which cannot be combined with it
+    pub filter_expr: Option<serde_json::Value>,
 }
 
-#[derive(Debug, Serialize)]
+impl SearchRequest {
+    /// Validate and compile `filter_expr`, resolving relative dates now
+    fn compile_filter(&self) -> ApiResult<Option<FilterExpr>> {
+        let Some(expr) = &self.filter_expr else {
+            return Ok(None);
+        };
+        if self.author.is_some() || self.tags.is_some() || self.metadata.is_some() {
+            return Err(AppError::BadRequest(
+                "filter_expr cannot be combined with author, tags or metadata; put those conditions in the expression"
+                    .to_string(),
+            ));
+        }
+        FilterExpr::from_json(expr, chrono::Utc::now())
+            .map(Some)
+            .map_err(|e| AppError::BadRequest(e.to_string()))
+    }
+}
+
+/// Share of the score taken from facet similarity when a search has facets
+/// but no `facet_weight`
+pub const DEFAULT_FACET_WEIGHT: f32 = 0.5;
+
+#[derive(Debug, Clone, Serialize)]
 pub struct SearchResponseItem {
     pub coord_id: String,
     pub score: f32,
@@ -166,36 +469,341 @@ pub struct SearchResponse {
 
 /// Semantic search endpoint
 /// Design: builds index on-demand from coordinate heads, caches embeddings by head hash
+/// Identical searches within the cache TTL reuse the result list until the next write
 pub async fn search(
     State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
     Json(req): Json<SearchRequest>,
 ) -> ApiResult<Json<SearchResponse>> {
-    info!("Performing semantic search: query={}, limit={}", req.query, req.limit.unwrap_or(10));
+    let caller = Caller::from_headers(&app, &headers);
+    let limit = req.limit.unwrap_or(10);
+    let offset = req.offset.unwrap_or(0);
+    info!("Performing semantic search: query={}, limit={}, offset={}", req.query, limit, offset);
+
+    let key = SearchKey::new(
+        &req.query,
+        req.author.as_deref(),
+        req.tags.as_deref(),
+        limit,
+        offset,
+        req.min_score,
+        req.importance_weight,
+    )
+    .with_metadata(req.metadata.as_ref())
+    .with_facets(req.facets.as_ref(), req.facet_weight)
+    .with_filter(req.filter_expr.as_ref())
+    .with_caller(&caller);
+    let generation = app.facade.generation();
+    let results = app
+        .search_cache
+        .get_or_compute(&key, generation, req.no_cache, || run_search(&app, &caller, &req, limit, offset))
+        .await?;
+
+    info!("Returning {} search results", results.len());
+
+    Ok(Json(SearchResponse { results }))
+}
+
+/// Body of `POST /searches`: a name plus the fields of a search request
+#[derive(Debug, Deserialize)]
+pub struct SaveSearchRequest {
+    pub name: String,
+    /// Notified with the new results found by periodic runs
+    pub webhook_url: Option<String>,
+    #[serde(flatten)]
+    pub search: SearchRequest,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SavedSearchResponse {
+    pub name: String,
+    pub search: serde_json::Value,
+    pub webhook_url: Option<String>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub last_run_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl From<SavedSearch> for SavedSearchResponse {
+    fn from(saved: SavedSearch) -> Self {
+        SavedSearchResponse {
+            name: saved.name,
+            search: saved.request,
+            webhook_url: saved.webhook_url,
+            created_at: saved.created_at,
+            last_run_at: saved.last_run_at,
+        }
+    }
+}
+
+/// Save a search under a name, replacing any of the caller's with that name
+pub async fn save_search(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(req): Json<SaveSearchRequest>,
+) -> ApiResult<(StatusCode, Json<SavedSearchResponse>)> {
+    let valid_name = (1..=64).contains(&req.name.len())
+        && req.name.chars().all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c));
+    if !valid_name {
+        return Err(AppError::BadRequest(
+            "name must be 1-64 letters, digits, '-', '_' or '.'".to_string(),
+        ));
+    }
+    if let Some(url) = &req.webhook_url {
+        if !(url.starts_with("http://") || url.starts_with("https://")) {
+            return Err(AppError::BadRequest("webhook_url must be an http(s) URL".to_string()));
+        }
+    }
+    // Stored as written, so relative dates are resolved again on every run
+    req.search.compile_filter()?;
+
+    let saved = SavedSearch {
+        owner: saved_search::owner_scope(&headers),
+        name: req.name,
+        request: serde_json::to_value(&req.search)
+            .map_err(|e| AppError::BmsError(bms_core::error::BmsError::Serialization(e)))?,
+        webhook_url: req.webhook_url,
+        created_at: chrono::Utc::now(),
+        last_run_at: None,
+    };
+    let created = app.facade.repository().save_search(&saved).await?;
+    info!("Saved search {} ({})", saved.name, if created { "created" } else { "replaced" });
+
+    let status = if created { StatusCode::CREATED } else { StatusCode::OK };
+    Ok((status, Json(saved.into())))
+}
+
+/// The caller's saved searches
+pub async fn list_saved_searches(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> ApiResult<Json<Vec<SavedSearchResponse>>> {
+    let owner = saved_search::owner_scope(&headers);
+    let searches = app.facade.repository().list_saved_searches(Some(&owner)).await?;
+    Ok(Json(searches.into_iter().map(Into::into).collect()))
+}
+
+/// One of the caller's saved searches; other keys' searches are not found
+async fn find_saved_search(app: &AppState, headers: &HeaderMap, name: &str) -> ApiResult<SavedSearch> {
+    let owner = saved_search::owner_scope(headers);
+    app.facade
+        .repository()
+        .get_saved_search(&owner, name)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Saved search not found: {}", name)))
+}
+
+pub async fn get_saved_search(
+    State(app): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+) -> ApiResult<Json<SavedSearchResponse>> {
+    Ok(Json(find_saved_search(&app, &headers, &name).await?.into()))
+}
+
+pub async fn delete_saved_search(
+    State(app): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+) -> ApiResult<StatusCode> {
+    let owner = saved_search::owner_scope(&headers);
+    if !app.facade.repository().delete_saved_search(&owner, &name).await? {
+        return Err(AppError::NotFound(format!("Saved search not found: {}", name)));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Run a saved search, marking results that are new since its last run
+pub async fn run_saved_search(
+    State(app): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+) -> ApiResult<Json<SavedRunResponse>> {
+    let saved = find_saved_search(&app, &headers, &name).await?;
+    let report = saved_search::run(&app, &saved).await?;
+    info!("Saved search {} returned {} results, {} new", name, report.results.len(), report.new);
+    Ok(Json(report))
+}
+
+/// Head that needs a fresh embedding during search
+struct StaleHead {
+    coord_id: CoordId,
+    extracted: ExtractedText,
+    head_hash: String,
+    author: Option<String>,
+    custom: HashMap<String, serde_json::Value>,
+    sketch: Option<TextSketch>,
+}
+
+/// Indexed head scored against the query
+struct Candidate {
+    coord_id: CoordId,
+    embedding: Vec<f32>,
+    /// Only filled for searches with facets
+    facets: HashMap<String, FacetEmbedding>,
+}
+
+/// Whether indexed metadata copies have every value in `filter`
+pub(crate) fn metadata_matches(
+    filter: &HashMap<String, serde_json::Value>,
+    custom: &HashMap<String, serde_json::Value>,
+) -> bool {
+    filter.iter().all(|(key, value)| custom.get(key) == Some(value))
+}
+
+/// Whether a cache entry embedded exactly these facet lines
+fn same_facets(cached: &CachedEmbedding, extracted: &ExtractedText) -> bool {
+    cached.facets.len() == extracted.facets.len()
+        && extracted
+            .facets
+            .iter()
+            .all(|facet| cached.facets.get(&facet.key).is_some_and(|c| c.text == facet.text))
+}
+
+/// Main score blended with the mean similarity of each facet query to the
+/// coordinate's facet of that key; a missing facet counts as 0
+pub(crate) fn facet_blend(
+    score: f32,
+    queries: &[(String, Vec<f32>)],
+    facets: &HashMap<String, FacetEmbedding>,
+    weight: f32,
+) -> f32 {
+    if queries.is_empty() {
+        return score;
+    }
+    let facet_score = queries
+        .iter()
+        .map(|(key, query)| facets.get(key).map_or(0.0, |f| cosine_similarity(query, &f.embedding)))
+        .sum::<f32>()
+        / queries.len() as f32;
+    (1.0 - weight) * score + weight * facet_score
+}
+
+/// Embed each text and its facet lines in one batched call
+///
+/// A text fails when it or any of its facets does.
+async fn embed_extracted(
+    embedder: &Embedder,
+    extracted: &[&ExtractedText],
+) -> Vec<Result<(Vec<f32>, HashMap<String, FacetEmbedding>), String>> {
+    let mut texts: Vec<String> = extracted.iter().map(|e| e.text.clone()).collect();
+    texts.extend(extracted.iter().flat_map(|e| e.facets.iter().map(|f| f.text.clone())));
+    let mut embeddings = embedder.embed_texts(&texts).await.into_iter();
+    let mut next = || embeddings.next().unwrap_or_else(|| Err("no embedding returned".to_string()));
+
+    let mains: Vec<_> = extracted.iter().map(|_| next()).collect();
+    mains
+        .into_iter()
+        .zip(extracted)
+        .map(|(main, e)| {
+            let mut facets = HashMap::new();
+            let mut failed = None;
+            for facet in &e.facets {
+                match next() {
+                    Ok(embedding) => {
+                        facets.insert(facet.key.clone(), FacetEmbedding { text: facet.text.clone(), embedding });
+                    }
+                    Err(e) => failed = Some(e),
+                }
+            }
+            match (main, failed) {
+                (Ok(embedding), None) => Ok((embedding, facets)),
+                (Err(e), _) | (_, Some(e)) => Err(e),
+            }
+        })
+        .collect()
+}
+
+/// Embed the query and rank every coordinate head `caller` can read against it
+pub(crate) async fn run_search(
+    app: &AppState,
+    caller: &Caller,
+    req: &SearchRequest,
+    limit: usize,
+    offset: usize,
+) -> ApiResult<Vec<SearchResponseItem>> {
+    let Some(embedder) = app.embedder.as_ref() else {
+        return Err(AppError::Unavailable(
+            "Vector search unavailable: this server has no embedding model".to_string(),
+        ));
+    };
+    if let Some(key) = req
+        .metadata
+        .iter()
+        .flat_map(|filter| filter.keys())
+        .find(|key| !app.index_metadata_keys.contains(key))
+    {
+        return Err(AppError::BadRequest(format!(
+            "Metadata key {:?} is not indexed; add it to BMS_INDEX_METADATA_KEYS",
+            key
+        )));
+    }
+    if let Some(key) = req
+        .facets
+        .iter()
+        .flat_map(|facets| facets.keys())
+        .find(|key| !app.index_facets.facets.contains(key))
+    {
+        return Err(AppError::BadRequest(format!(
+            "Facet {:?} is not indexed; add it to BMS_INDEX_FACETS",
+            key
+        )));
+    }
+    let filter = req.compile_filter()?;
+    let facet_weight = req.facet_weight.unwrap_or(DEFAULT_FACET_WEIGHT);
+    if !(0.0..=1.0).contains(&facet_weight) {
+        return Err(AppError::BadRequest("facet_weight must be between 0 and 1".to_string()));
+    }
+    let filtered_out = |custom: &HashMap<String, serde_json::Value>| {
+        req.metadata.as_ref().is_some_and(|filter| !metadata_matches(filter, custom))
+    };
+    let embedding_error =
+        |e: String| AppError::BmsError(bms_core::error::BmsError::Other(format!("Embedding error: {}", e)));
 
     // Generate embedding for query
-    let query_embedding = {
-        let mut generator = app.embedding_generator.lock().await;
-        generator
-            .generate(&req.query)
-            .map_err(|e| AppError::BmsError(bms_core::error::BmsError::Other(format!(
-                "Embedding error: {}",
-                e
-            ))))?
+    let query_embedding = embedder.embed_query(&req.query).await.map_err(embedding_error)?;
+
+    // Each facet query is embedded as the line its facet was indexed as
+    let mut facet_queries: Vec<(String, Vec<f32>)> = Vec::new();
+    for (key, value) in req.facets.iter().flatten() {
+        let Some(text) = facet_text(key, &serde_json::Value::String(value.clone())) else {
+            return Err(AppError::BadRequest(format!("Facet {:?} has no text to match", key)));
+        };
+        facet_queries.push((key.clone(), embedder.embed_query(&text).await.map_err(embedding_error)?));
+    }
+    let lacks_facet = |extracted: &ExtractedText| {
+        facet_queries
+            .iter()
+            .any(|(key, _)| !extracted.facets.iter().any(|facet| &facet.key == key))
     };
 
-    // Get all coordinates from DB
-    let coords = app.repository.list_coordinates(None).await?;
+    // Get all coordinates from DB, letting SQLite apply what it can of the filter
+    let coords = match filter.as_ref().and_then(FilterExpr::pushdown) {
+        Some(pushed) => app.facade.repository().list_coordinates_where(&pushed, None).await?,
+        None => app.facade.repository().list_coordinates(None, None).await?,
+    };
     info!("Found {} coordinates to index", coords.len());
 
     // Build or update in-memory index
     let mut cache = app.embedding_cache.lock().await;
-    let mut coord_embeddings: Vec<(bms_core::CoordId, Vec<f32>, String, chrono::DateTime<chrono::Utc>)> = Vec::new();
+    let mut candidates: Vec<Candidate> = Vec::new();
+    // Heads whose cached embedding is missing or stale, embedded in batches below
+    let mut stale: Vec<StaleHead> = Vec::new();
+    let mut bypassed = 0;
 
     for coord in coords {
+        // Ephemeral coordinates stay out of the index until promoted
+        if coord.is_ephemeral() {
+            continue;
+        }
+        // Ranking only what the caller can read keeps pages full
+        if !caller.can_read(coord.metadata.as_ref(), &mut bypassed) {
+            continue;
+        }
+
         // Filter by author if specified
         if let Some(ref filter_author) = req.author {
             // Get latest delta to check author
-            let deltas = app.repository.get_deltas(&coord.id).await?;
+            let deltas = app.facade.repository().get_deltas(&coord.id).await?;
             if let Some(last_delta) = deltas.last() {
                 if last_delta.author.as_deref() != Some(filter_author) {
                     continue;
@@ -204,88 +812,128 @@ pub async fn search(
         }
 
         // Reconstruct head state
-        let deltas = app.repository.get_deltas(&coord.id).await?;
-        if deltas.is_empty() {
+        let Some(Head { state: head_state, deltas, .. }) = app.facade.head(&coord.id).await? else {
             continue; // Skip empty coordinates
+        };
+        if let Some(filter) = &filter {
+            let tags = metadata_tags(coord.metadata.as_ref());
+            let record = FilterRecord {
+                author: deltas.last().and_then(|d| d.author.as_deref()),
+                tags: &tags,
+                created_at: Some(coord.created_at),
+                metadata: coord.metadata.as_ref(),
+                state: Some(&head_state),
+            };
+            if !filter.matches(&record) {
+                continue;
+            }
         }
 
-        let head_state = if let Some(snapshot) = app.repository.get_latest_snapshot(&coord.id).await? {
-            bms_core::SnapshotManager::reconstruct(&snapshot, &deltas[..])?
-        } else {
-            let mut state = serde_json::json!({});
-            for delta in &deltas {
-                bms_core::DeltaEngine::apply_delta(&mut state, &delta.ops)?;
+        // Hash the embedded text, facets included, for the cache key
+        let extracted = app.index_facets.extract(&head_state, coord.metadata.as_ref());
+        if lacks_facet(&extracted) {
+            continue;
+        }
+        let text = &extracted.text;
+        let head_hash = format!("{:x}", sha3::Sha3_256::digest(text.as_bytes()));
+
+        let mut sketch = None;
+        let fresh = match cache.get_mut(&coord.id) {
+            Some(cached) if cached.head_hash == head_hash => Some(cached),
+            // The head changed, but its text may be close enough to keep the
+            // embedding; changed facets are always embedded again
+            Some(cached) if same_facets(cached, &extracted) => {
+                sketch = app.embed_drift.sketch(text);
+                app.embed_drift
+                    .try_keep(cached, &head_hash, sketch.as_ref())
+                    .then_some(cached)
             }
-            state
+            _ => None,
         };
-
-        // Compute hash of head state for cache key
-        let head_hash = format!("{:x}", sha3::Sha3_256::digest(
-            serde_json::to_string(&head_state).unwrap_or_default().as_bytes()
-        ));
-
-        // Check cache or generate embedding
-        let embedding = if let Some(cached) = cache.get(&coord.id) {
-            if cached.head_hash == head_hash {
-                // Cache hit
-                cached.embedding.clone()
-            } else {
-                // Cache miss - head changed, regenerate
-                let mut generator = app.embedding_generator.lock().await;
-                let emb = generator
-                    .generate_from_state(&head_state)
-                    .map_err(|e| AppError::BmsError(bms_core::error::BmsError::Other(format!(
-                        "Embedding error: {}",
-                        e
-                    ))))?;
-                
-                // Update cache
-                cache.insert(coord.id.clone(), CachedEmbedding {
-                    head_hash: head_hash.clone(),
-                    embedding: emb.clone(),
+        match fresh {
+            // Cache hit; metadata filters read the indexed copies
+            Some(cached) => {
+                if !filtered_out(&cached.custom) {
+                    candidates.push(Candidate {
+                        coord_id: coord.id.clone(),
+                        embedding: cached.embedding.clone(),
+                        facets: if facet_queries.is_empty() { HashMap::new() } else { cached.facets.clone() },
+                    });
+                }
+            }
+            // Not cached, or the head drifted
+            None => {
+                let custom = sync::index_metadata(&app.index_metadata_keys, coord.metadata.as_ref());
+                if filtered_out(&custom) {
+                    continue;
+                }
+                stale.push(StaleHead {
+                    coord_id: coord.id.clone(),
                     author: deltas.last().and_then(|d| d.author.clone()),
-                    created_at: chrono::Utc::now(),
+                    sketch: sketch.or_else(|| app.embed_drift.sketch(text)),
+                    extracted,
+                    head_hash,
+                    custom,
                 });
-                emb
             }
-        } else {
-            // Not in cache, generate
-            let mut generator = app.embedding_generator.lock().await;
-            let emb = generator
-                .generate_from_state(&head_state)
-                .map_err(|e| AppError::BmsError(bms_core::error::BmsError::Other(format!(
-                    "Embedding error: {}",
-                    e
-                ))))?;
-            
-            // Add to cache
-            cache.insert(coord.id.clone(), CachedEmbedding {
-                head_hash: head_hash.clone(),
-                embedding: emb.clone(),
-                author: deltas.last().and_then(|d| d.author.clone()),
+        }
+    }
+
+    if !stale.is_empty() {
+        let texts: Vec<&ExtractedText> = stale.iter().map(|head| &head.extracted).collect();
+        let embeddings = embed_extracted(embedder, &texts).await;
+
+        for (head, embedding) in stale.into_iter().zip(embeddings) {
+            let StaleHead { coord_id, extracted, head_hash, author, custom, sketch } = head;
+            // A state the model rejects is left out rather than failing the search
+            let (embedding, facets) = match embedding {
+                Ok(embedded) => embedded,
+                Err(e) => {
+                    warn!("Skipping {} in search, embedding failed: {}", coord_id, e);
+                    continue;
+                }
+            };
+            if let (_, Some(section)) = split_facets(&extracted.text) {
+                debug!("Embedded {} with facets:\n{}", coord_id, section);
+            }
+            cache.insert(coord_id.clone(), CachedEmbedding {
+                head_hash,
+                embedding: embedding.clone(),
+                author,
                 created_at: chrono::Utc::now(),
+                custom,
+                sketch,
+                facets: facets.clone(),
             });
-            emb
-        };
-
-        let created_at = deltas.last().map(|d| d.created_at).unwrap_or_else(chrono::Utc::now);
-        coord_embeddings.push((coord.id.clone(), embedding, head_hash, created_at));
+            candidates.push(Candidate { coord_id, embedding, facets });
+        }
     }
 
     // Drop cache lock before heavy computation
     drop(cache);
+    caller.log_bypassed("search", bypassed);
 
-    info!("Indexed {} coordinate embeddings", coord_embeddings.len());
+    info!("Indexed {} coordinate embeddings", candidates.len());
 
-    // Compute cosine similarity scores
-    let mut results: Vec<(bms_core::CoordId, f32)> = coord_embeddings
+    // Compute cosine similarity scores, blended with facet similarity
+    let mut results: Vec<(bms_core::CoordId, f32)> = candidates
         .iter()
-        .map(|(coord_id, embedding, _, _)| {
-            let score = cosine_similarity(&query_embedding, embedding);
-            (coord_id.clone(), score)
+        .map(|candidate| {
+            let score = cosine_similarity(&query_embedding, &candidate.embedding);
+            let score = facet_blend(score, &facet_queries, &candidate.facets, facet_weight);
+            (candidate.coord_id.clone(), score)
         })
         .collect();
 
+    // Blend in importance; min_score applies to the blended score
+    if let Some(weight) = req.importance_weight.filter(|w| *w > 0.0) {
+        let importance = effective_importance(app).await?;
+        for (coord_id, score) in &mut results {
+            let value = importance.get(coord_id).copied().unwrap_or(DEFAULT_IMPORTANCE);
+            *score = importance::blend(*score, value, weight);
+        }
+    }
+
     // Filter by min_score if provided
     if let Some(min_score) = req.min_score {
         results.retain(|(_, score)| *score >= min_score);
@@ -294,21 +942,51 @@ pub async fn search(
     // Sort by score descending
     results.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
 
-    // Take top k
-    let limit = req.limit.unwrap_or(10);
-    results.truncate(limit);
-
-    let items: Vec<SearchResponseItem> = results
+    // Take the requested page of the top results
+    let items = results
         .into_iter()
+        .skip(offset)
+        .take(limit)
         .map(|(coord_id, score)| SearchResponseItem {
             coord_id: coord_id.0,
             score,
         })
         .collect();
 
-    info!("Returning {} search results", items.len());
+    Ok(items)
+}
 
-    Ok(Json(SearchResponse { results: items }))
+/// Embed a coordinate's head into the search cache now rather than on the
+/// next search
+///
+/// Returns false without an embedding model or deltas.
+async fn index_head(app: &AppState, coord_id: &CoordId) -> ApiResult<bool> {
+    let Some(embedder) = app.embedder.as_ref() else {
+        return Ok(false);
+    };
+    let Some(Head { state, deltas, .. }) = app.facade.head(coord_id).await? else {
+        return Ok(false);
+    };
+    let metadata = app.facade.repository().get_coordinate(coord_id).await?.and_then(|c| c.metadata);
+
+    let extracted = app.index_facets.extract(&state, metadata.as_ref());
+    let head_hash = format!("{:x}", sha3::Sha3_256::digest(extracted.text.as_bytes()));
+    let (embedding, facets) = embed_extracted(embedder, &[&extracted])
+        .await
+        .pop()
+        .unwrap_or_else(|| Err("no embedding returned".to_string()))
+        .map_err(|e| AppError::BmsError(bms_core::error::BmsError::Other(format!("Embedding error: {}", e))))?;
+
+    app.embedding_cache.lock().await.insert(coord_id.clone(), CachedEmbedding {
+        head_hash,
+        embedding,
+        author: deltas.last().and_then(|d| d.author.clone()),
+        created_at: chrono::Utc::now(),
+        custom: sync::index_metadata(&app.index_metadata_keys, metadata.as_ref()),
+        sketch: app.embed_drift.sketch(&extracted.text),
+        facets,
+    });
+    Ok(true)
 }
 
 /// Compute cosine similarity between two vectors
@@ -330,7 +1008,11 @@ fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
 
 #[derive(Debug, Deserialize)]
 pub struct RecallQuery {
+    /// Return the state as of this delta instead of the head
     pub delta_id: Option<String>,
+    /// Skip redaction rules (requires the admin token)
+    #[serde(default)]
+    pub unredacted: bool,
 }
 
 #[derive(Debug, Serialize)]
@@ -341,43 +1023,182 @@ pub struct RecallResponse {
 }
 
 /// Recall a state by coordinate ID
+///
+/// States larger than the recall response limit answer 413 with the path of
+/// the streaming endpoint.
 pub async fn recall_state(
     State(app): State<Arc<AppState>>,
     Path(coord_id_str): Path<String>,
-    Query(_query): Query<RecallQuery>,
-) -> ApiResult<Json<RecallResponse>> {
-    let coord_id = CoordId(coord_id_str);
-    info!("Recalling state for coordinate: {}", coord_id);
+    Query(query): Query<RecallQuery>,
+    headers: HeaderMap,
+) -> ApiResult<impl IntoResponse> {
+    let coord_id = coord_or_alias(&app, coord_id_str).await?;
+    let (etag, response) = recall_head(&app, coord_id, &query, &headers).await?;
+
+    if let Some(max) = app.config.load().body_limits.recall_response {
+        let size = Canonicalizer::canonical_len(&response.state)?;
+        if size > max {
+            return Err(AppError::TooLarge {
+                message: format!(
+                    "State of {} is {} bytes, more than the {} byte recall limit",
+                    response.coord_id, size, max
+                ),
+                stream: Some(format!("/recall/{}/stream", response.coord_id)),
+            });
+        }
+    }
 
-    // Get all deltas
-    let deltas = app.repository.get_deltas(&coord_id).await?;
-    let delta_count = deltas.len() as u32;
+    Ok(([(header::ETAG, etag)], Json(response)))
+}
 
-    if deltas.is_empty() {
-        return Err(AppError::NotFound(format!(
-            "No deltas found for coordinate: {}",
-            coord_id
-        )));
+/// Bytes handed to the connection at a time by `recall_state_stream`
+const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Recall a state of any size as a streamed response
+///
+/// The body is the same JSON as `/recall/:coord_id`, serialized in chunks
+/// rather than into one buffer.
+pub async fn recall_state_stream(
+    State(app): State<Arc<AppState>>,
+    Path(coord_id_str): Path<String>,
+    Query(query): Query<RecallQuery>,
+    headers: HeaderMap,
+) -> ApiResult<impl IntoResponse> {
+    let coord_id = coord_or_alias(&app, coord_id_str).await?;
+    let (etag, response) = recall_head(&app, coord_id, &query, &headers).await?;
+
+    let (tx, rx) = tokio::sync::mpsc::channel(4);
+    tokio::task::spawn_blocking(move || {
+        let mut writer = std::io::BufWriter::with_capacity(STREAM_CHUNK_SIZE, ChunkWriter(tx));
+        let written = serde_json::to_writer(&mut writer, &response)
+            .map_err(std::io::Error::from)
+            .and_then(|_| std::io::Write::flush(&mut writer));
+        if let Err(e) = written {
+            warn!("Streaming recall of {} stopped: {}", response.coord_id, e);
+        }
+    });
+    let chunks = futures_util::stream::unfold(rx, |mut rx| async move { rx.recv().await.map(|chunk| (chunk, rx)) });
+
+    Ok((
+        [(header::ETAG, etag), (header::CONTENT_TYPE, "application/json".to_string())],
+        Body::from_stream(chunks),
+    ))
+}
+
+/// Sends each write to a streamed response body
+struct ChunkWriter(tokio::sync::mpsc::Sender<std::io::Result<Bytes>>);
+
+impl std::io::Write for ChunkWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0
+            .blocking_send(Ok(Bytes::copy_from_slice(buf)))
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::BrokenPipe, "client disconnected"))?;
+        Ok(buf.len())
     }
 
-    // Get latest snapshot
-    let state = if let Some(snapshot) = app.repository.get_latest_snapshot(&coord_id).await? {
-        // Reconstruct from snapshot
-        bms_core::SnapshotManager::reconstruct(&snapshot, &deltas[..])?
-    } else {
-        // Reconstruct from all deltas
-        let mut state = serde_json::json!({});
-        for delta in &deltas {
-            DeltaEngine::apply_delta(&mut state, &delta.ops)?;
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Coordinate named in a path by ID or rune alias
+///
+/// Only what is not a canonical generated ID is looked up as an alias, so
+/// recalls by ID cost no extra query. The alias goes first otherwise: a
+/// `word-word-word` alias can read as a generated ID to `CoordId::parse`.
+async fn coord_or_alias(app: &AppState, input: String) -> ApiResult<CoordId> {
+    if CoordinateGenerator::validate(&input).is_ok() {
+        return Ok(CoordId(input));
+    }
+    if let Some(coordinate) = app.facade.repository().get_coordinate_by_alias(&input).await? {
+        return Ok(coordinate.id);
+    }
+    Ok(parse_coord_id(&app.facade, &input).await?)
+}
+
+/// Coordinate ID as a client sent it, see `CoordId::parse`
+///
+/// An ID that does not parse is still taken as is when a coordinate has it,
+/// so chains stored before IDs were checked stay reachable.
+pub(crate) async fn parse_coord_id(facade: &BmsFacade, input: &str) -> bms_core::Result<CoordId> {
+    match CoordId::parse(input) {
+        Ok(coord_id) => Ok(coord_id),
+        Err(e) => {
+            let legacy = CoordId(input.to_string());
+            if facade.repository().coordinate_exists(&legacy).await? {
+                return Ok(legacy);
+            }
+            Err(e)
+        }
+    }
+}
+
+/// Reconstruct and redact the head for a recall, with its ETag
+async fn recall_head(
+    app: &AppState,
+    coord_id: CoordId,
+    query: &RecallQuery,
+    headers: &HeaderMap,
+) -> ApiResult<(String, RecallResponse)> {
+    info!("Recalling state for coordinate: {}", coord_id);
+    acl::authorize(&app.facade, &Caller::from_headers(app, headers), &coord_id, Access::Read).await?;
+
+    if query.unredacted {
+        if !is_admin(app, headers) {
+            return Err(AppError::Forbidden(
+                "unredacted recall requires the admin token".to_string(),
+            ));
+        }
+        warn!(target: "bms::audit", coord_id = %coord_id, "unredacted recall");
+    }
+
+    let (state, delta_count, chain_hash) = match &query.delta_id {
+        // Historical states are replayed from the start of the chain
+        Some(delta_id) => {
+            let deltas = app.facade.repository().get_deltas(&coord_id).await?;
+            let target = DeltaId(delta_id.clone());
+            let state = DeltaEngine::replay_to(&deltas, &target).map_err(|e| match e {
+                bms_core::error::BmsError::DeltaNotFound(id) => {
+                    AppError::NotFound(format!("Delta {} is not in the chain of {}", id, coord_id))
+                }
+                e => e.into(),
+            })?;
+            let chain = DeltaEngine::chain_to(&deltas, &target)?;
+            (state, chain.len(), chain.last().map(|d| d.chain_hash.clone()))
+        }
+        // Reconstruct from the latest snapshot and forward deltas
+        None => {
+            let Some(head) = app.facade.head(&coord_id).await? else {
+                return Err(AppError::NotFound(format!(
+                    "No deltas found for coordinate: {}",
+                    coord_id
+                )));
+            };
+            let chain_hash = head.deltas.last().map(|d| d.chain_hash.clone());
+            (head.state, head.deltas.len(), chain_hash)
         }
+    };
+    app.access_tracker.record_read(&coord_id);
+
+    // The chain hash of the returned state doubles as the ETag for
+    // conditional stores, so a historical one never matches the head
+    let etag = chain_hash.as_ref().map(format_etag).unwrap_or_default();
+
+    // Redaction only shapes the response; stored data and the ETag are unchanged
+    let state = if query.unredacted {
         state
+    } else {
+        redact(&state, &app.facade.redaction_rules(&coord_id).await?)
     };
 
-    Ok(Json(RecallResponse {
-        coord_id: coord_id.0,
-        state,
-        delta_count,
-    }))
+    Ok((
+        etag,
+        RecallResponse {
+            coord_id: coord_id.0,
+            state,
+            delta_count: delta_count as u32,
+        },
+    ))
 }
 
 #[derive(Debug, Serialize)]
@@ -387,20 +1208,28 @@ pub struct VerifyResponse {
     pub total_deltas: usize,
     pub chain_valid: bool,
     pub first_break: Option<usize>,
+    /// Which link of the delta at `first_break` fails
+    pub break_reason: Option<String>,
 }
 
 /// Verify chain integrity
+///
+/// Checks each delta against its ops and against the delta before it, see
+/// `MerkleChain::verify_linked_chain`.
 pub async fn verify_chain(
     State(app): State<Arc<AppState>>,
     Path(coord_id_str): Path<String>,
+    headers: HeaderMap,
 ) -> ApiResult<Json<VerifyResponse>> {
-    let coord_id = CoordId(coord_id_str);
+    let coord_id = parse_coord_id(&app.facade, &coord_id_str).await?;
     info!("Verifying chain for coordinate: {}", coord_id);
+    acl::authorize(&app.facade, &Caller::from_headers(&app, &headers), &coord_id, Access::Read).await?;
 
-    let deltas = app.repository.get_deltas(&coord_id).await?;
+    let deltas = app.facade.repository().get_deltas(&coord_id).await?;
+    let algorithm = app.facade.repository().get_hash_algorithm(&coord_id).await?;
     let total = deltas.len();
 
-    let (verified, first_break) = MerkleChain::verify_chain_integrity(&deltas);
+    let (verified, first_break) = MerkleChain::verify_linked_chain_integrity_with(&deltas, algorithm);
 
     Ok(Json(VerifyResponse {
         coord_id: coord_id.0,
@@ -412,6 +1241,7 @@ pub async fn verify_chain(
         } else {
             None
         },
+        break_reason: first_break.map(|e| e.to_string()),
     }))
 }
 
@@ -419,61 +1249,1007 @@ pub async fn verify_chain(
 pub async fn create_snapshot(
     State(app): State<Arc<AppState>>,
     Path(coord_id_str): Path<String>,
+    headers: HeaderMap,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let coord_id = CoordId(coord_id_str);
+    let coord_id = parse_coord_id(&app.facade, &coord_id_str).await?;
     info!("Creating snapshot for coordinate: {}", coord_id);
+    acl::authorize(&app.facade, &Caller::from_headers(&app, &headers), &coord_id, Access::Write).await?;
 
-    // Reconstruct current state
-    let deltas = app.repository.get_deltas(&coord_id).await?;
-    if deltas.is_empty() {
+    let Some(snapshot) = app.facade.create_snapshot(&coord_id).await? else {
         return Err(AppError::NotFound(format!(
             "No deltas found for coordinate: {}",
             coord_id
         )));
-    }
-
-    let state = if let Some(snapshot) = app.repository.get_latest_snapshot(&coord_id).await? {
-        bms_core::SnapshotManager::reconstruct(&snapshot, &deltas[..])?
-    } else {
-        let mut state = serde_json::json!({});
-        for delta in &deltas {
-            DeltaEngine::apply_delta(&mut state, &delta.ops)?;
-        }
-        state
     };
 
-    let head_delta_id = deltas.last().unwrap().id.clone();
-    let snapshot = app
-        .snapshot_manager
-        .create_snapshot(coord_id, head_delta_id, state)?;
-
-    app.repository.insert_snapshot(&snapshot).await?;
-
     Ok(Json(serde_json::json!({
         "snapshot_id": snapshot.id.0,
         "state_hash": snapshot.state_hash.0,
     })))
 }
 
+#[derive(Debug, Serialize)]
+pub struct CoordinateResponse {
+    #[serde(flatten)]
+    pub coordinate: Coordinate,
+    /// Current importance after decay
+    pub importance: f32,
+    /// Canonical size of the head state, if it has been measured
+    pub state_bytes: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListQuery {
+    /// Maximum coordinates to list (default 100)
+    pub limit: Option<i64>,
+    /// `created` (default, newest first) or `size` (largest head first)
+    pub sort: Option<String>,
+    /// Also list ephemeral coordinates
+    #[serde(default)]
+    pub include_ephemeral: bool,
+    /// Only list this namespace and the ones nested under it; `default`
+    /// lists coordinates created without a namespace
+    pub namespace: Option<String>,
+}
+
 /// List coordinates
 pub async fn list_coordinates(
     State(app): State<Arc<AppState>>,
-) -> ApiResult<Json<Vec<Coordinate>>> {
-    let coords = app.repository.list_coordinates(Some(100)).await?;
-    Ok(Json(coords))
-}
+    Query(query): Query<ListQuery>,
+    Query(format): Query<HumanizeQuery>,
+    headers: HeaderMap,
+) -> ApiResult<Json<serde_json::Value>> {
+    let limit = query.limit.unwrap_or(100).max(1);
+    let by_size = match query.sort.as_deref() {
+        None | Some("created") => false,
+        Some("size") => true,
+        Some(other) => {
+            return Err(AppError::BadRequest(format!(
+                "Unknown sort order: {} (expected created or size)",
+                other
+            )))
+        }
+    };
+
+    let repo = app.facade.repository();
+    let sizes: HashMap<CoordId, u64> = repo
+        .list_state_sizes(None)
+        .await?
+        .into_iter()
+        .map(|s| (s.coord_id, s.state_bytes))
+        .collect();
+    // Ephemeral and unreadable coordinates are left out after the query, so
+    // it cannot limit
+    check_namespace(query.namespace.as_deref())?;
+    let mut coords = repo.list_coordinates(query.namespace.as_deref(), Some(i64::MAX)).await?;
+    if !query.include_ephemeral {
+        coords.retain(|c| !c.is_ephemeral());
+    }
+    let caller = Caller::from_headers(&app, &headers);
+    let mut bypassed = 0;
+    coords.retain(|c| caller.can_read(c.metadata.as_ref(), &mut bypassed));
+    caller.log_bypassed("coords", bypassed);
+    if by_size {
+        // Unmeasured heads sort last
+        coords.sort_by_key(|c| std::cmp::Reverse(sizes.get(&c.id).copied()));
+    }
+    coords.truncate(limit as usize);
+    let importance = effective_importance(&app).await?;
+
+    let response = coords
+        .into_iter()
+        .map(|coordinate| CoordinateResponse {
+            importance: importance
+                .get(&coordinate.id)
+                .copied()
+                .unwrap_or(DEFAULT_IMPORTANCE),
+            state_bytes: sizes.get(&coordinate.id).copied(),
+            coordinate,
+        })
+        .collect::<Vec<_>>();
+
+    respond(&response, format)
+}
+
+/// Current importance of every coordinate
+async fn effective_importance(app: &AppState) -> ApiResult<HashMap<CoordId, f32>> {
+    let now = chrono::Utc::now();
+    let stored = app.facade.repository().get_importance(None).await?;
+    Ok(stored
+        .into_iter()
+        .map(|i| (i.coord_id.clone(), i.effective(&app.importance, now)))
+        .collect())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReinforceRequest {
+    /// Added to the current importance; negative values demote
+    pub delta: f32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReinforceResponse {
+    pub coord_id: String,
+    pub importance: f32,
+}
+
+/// Add to a coordinate's decayed importance
+pub async fn reinforce_coordinate(
+    State(app): State<Arc<AppState>>,
+    Path(coord_id_str): Path<String>,
+    headers: HeaderMap,
+    Json(req): Json<ReinforceRequest>,
+) -> ApiResult<Json<ReinforceResponse>> {
+    if !req.delta.is_finite() {
+        return Err(AppError::BadRequest("delta must be a finite number".to_string()));
+    }
+
+    let coord_id = parse_coord_id(&app.facade, &coord_id_str).await?;
+    acl::authorize(&app.facade, &Caller::from_headers(&app, &headers), &coord_id, Access::Write).await?;
+    let Some(importance) = app
+        .facade
+        .repository()
+        .reinforce_importance(&coord_id, req.delta, &app.importance, chrono::Utc::now())
+        .await?
+    else {
+        return Err(AppError::NotFound(format!("Coordinate not found: {}", coord_id)));
+    };
+
+    Ok(Json(ReinforceResponse {
+        coord_id: coord_id.0,
+        importance,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RollbackRequest {
+    /// Number of deltas to undo
+    #[serde(default = "default_rollback_steps")]
+    pub steps: usize,
+    pub author: Option<String>,
+}
+
+fn default_rollback_steps() -> usize {
+    1
+}
+
+/// Undo the last deltas of a coordinate by appending their inverse
+pub async fn rollback_coordinate(
+    State(app): State<Arc<AppState>>,
+    Path(coord_id_str): Path<String>,
+    headers: HeaderMap,
+    Json(req): Json<RollbackRequest>,
+) -> ApiResult<impl IntoResponse> {
+    let coord_id = parse_coord_id(&app.facade, &coord_id_str).await?;
+    acl::authorize(&app.facade, &Caller::from_headers(&app, &headers), &coord_id, Access::Write).await?;
+    if app.facade.repository().get_coordinate(&coord_id).await?.is_none() {
+        return Err(AppError::NotFound(format!("Coordinate not found: {}", coord_id)));
+    }
+
+    let outcome = match app.facade.rollback(&coord_id, req.steps, req.author).await {
+        Ok(outcome) => outcome,
+        Err(e @ bms_core::error::BmsError::InvalidState(_)) => return Err(AppError::BadRequest(e.to_string())),
+        Err(e @ bms_core::error::BmsError::PreconditionFailed { .. }) => {
+            return Err(AppError::Conflict(e.to_string()))
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    Ok((
+        [(header::ETAG, format_etag(&outcome.head.chain_hash))],
+        Json(StoreResponse::from(outcome)),
+    ))
+}
+
+/// Delete a coordinate and its history (requires the admin token)
+pub async fn delete_coordinate(
+    State(app): State<Arc<AppState>>,
+    Path(coord_id_str): Path<String>,
+    headers: HeaderMap,
+) -> ApiResult<StatusCode> {
+    if !is_admin(&app, &headers) {
+        return Err(AppError::Forbidden(
+            "deleting coordinates requires the admin token".to_string(),
+        ));
+    }
+
+    let coord_id = parse_coord_id(&app.facade, &coord_id_str).await?;
+    acl::authorize(&app.facade, &Caller::from_headers(&app, &headers), &coord_id, Access::Write).await?;
+    match app.facade.delete_coordinate(&coord_id).await {
+        Ok(true) => {}
+        Ok(false) => {
+            return Err(AppError::NotFound(format!("Coordinate not found: {}", coord_id)));
+        }
+        Err(e @ bms_core::error::BmsError::CoordinateReferenced { .. }) => {
+            return Err(AppError::Conflict(e.to_string()));
+        }
+        Err(e) => return Err(e.into()),
+    }
+    warn!(target: "bms::audit", coord_id = %coord_id, "coordinate deleted");
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Serialize)]
+pub struct PromoteResponse {
+    pub coord_id: String,
+    /// False when the coordinate was not ephemeral
+    pub promoted: bool,
+    /// Whether the head was embedded into the search index right away
+    pub indexed: bool,
+}
+
+/// Clear a coordinate's ephemeral flag and index its head
+pub async fn promote_coordinate(
+    State(app): State<Arc<AppState>>,
+    Path(coord_id_str): Path<String>,
+    headers: HeaderMap,
+) -> ApiResult<Json<PromoteResponse>> {
+    let coord_id = parse_coord_id(&app.facade, &coord_id_str).await?;
+    acl::authorize(&app.facade, &Caller::from_headers(&app, &headers), &coord_id, Access::Write).await?;
+    let Some(promoted) = app.facade.promote(&coord_id).await? else {
+        return Err(AppError::NotFound(format!("Coordinate not found: {}", coord_id)));
+    };
+    let indexed = promoted && index_head(&app, &coord_id).await?;
+    if promoted {
+        warn!(target: "bms::audit", coord_id = %coord_id, indexed, "coordinate promoted");
+    }
+
+    Ok(Json(PromoteResponse {
+        coord_id: coord_id.0,
+        promoted,
+        indexed,
+    }))
+}
+
+#[derive(Debug, Serialize)]
+pub struct MetadataResponse {
+    pub coord_id: String,
+    pub metadata: HashMap<String, serde_json::Value>,
+}
+
+/// Merge a JSON object into a coordinate's metadata; `null` removes a key
+///
+/// Changing redaction rules requires the admin token, since removing them
+/// exposes redacted values on recall. Any patch, including one to the `acl`
+/// document, requires write access under the current ACL.
+pub async fn patch_metadata(
+    State(app): State<Arc<AppState>>,
+    Path(coord_id_str): Path<String>,
+    headers: HeaderMap,
+    Json(patch): Json<HashMap<String, serde_json::Value>>,
+) -> ApiResult<Json<MetadataResponse>> {
+    let redaction = [redact::REDACT_METADATA_KEY, redact::REDACT_MODE_METADATA_KEY];
+    if redaction.iter().any(|key| patch.contains_key(*key)) && !is_admin(&app, &headers) {
+        return Err(AppError::Forbidden(
+            "changing redaction rules requires the admin token".to_string(),
+        ));
+    }
+
+    let coord_id = parse_coord_id(&app.facade, &coord_id_str).await?;
+    acl::authorize(&app.facade, &Caller::from_headers(&app, &headers), &coord_id, Access::Write).await?;
+    let metadata = match app.facade.patch_metadata(&coord_id, patch).await {
+        Ok(Some(metadata)) => metadata,
+        Ok(None) => {
+            return Err(AppError::NotFound(format!("Coordinate not found: {}", coord_id)));
+        }
+        Err(
+            e @ (bms_core::error::BmsError::InvalidState(_)
+            | bms_core::error::BmsError::InvalidArrayKey { .. }),
+        ) => return Err(AppError::BadRequest(e.to_string())),
+        Err(e) => return Err(e.into()),
+    };
+
+    Ok(Json(MetadataResponse {
+        coord_id: coord_id.0,
+        metadata,
+    }))
+}
+
+#[derive(Debug, Serialize)]
+pub struct LinkEntry {
+    pub pointer: String,
+    pub to_coord: String,
+    /// The target coordinate does not exist
+    pub dangling: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LinksResponse {
+    pub coord_id: String,
+    pub links: Vec<LinkEntry>,
+}
+
+/// Coordinates referenced by a coordinate's head
+///
+/// Links under redacted fields are left out.
+pub async fn get_links(
+    State(app): State<Arc<AppState>>,
+    Path(coord_id_str): Path<String>,
+    headers: HeaderMap,
+) -> ApiResult<Json<LinksResponse>> {
+    let coord_id = parse_coord_id(&app.facade, &coord_id_str).await?;
+    acl::authorize(&app.facade, &Caller::from_headers(&app, &headers), &coord_id, Access::Read).await?;
+    let repo = app.facade.repository();
+    if !repo.coordinate_exists(&coord_id).await? {
+        return Err(AppError::NotFound(format!("Coordinate not found: {}", coord_id)));
+    }
+
+    let mut links = repo.get_links(&coord_id).await?;
+    let rules = app.facade.redaction_rules(&coord_id).await?;
+    if !rules.is_empty() {
+        if let Some(head) = app.facade.head(&coord_id).await? {
+            let visible = redact(&head.state, &rules);
+            links.retain(|l| {
+                visible.pointer(&l.pointer).and_then(|v| v.as_str()) == Some(l.to_coord.as_str())
+            });
+        }
+    }
+
+    let mut entries = Vec::with_capacity(links.len());
+    for link in links {
+        entries.push(LinkEntry {
+            dangling: !repo.coordinate_exists(&link.to_coord).await?,
+            pointer: link.pointer,
+            to_coord: link.to_coord.0,
+        });
+    }
+
+    Ok(Json(LinksResponse {
+        coord_id: coord_id.0,
+        links: entries,
+    }))
+}
+
+#[derive(Debug, Serialize)]
+pub struct BacklinkEntry {
+    pub from_coord: String,
+    pub pointer: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BacklinksResponse {
+    pub coord_id: String,
+    pub backlinks: Vec<BacklinkEntry>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChildrenResponse {
+    pub coord_id: String,
+    pub children: Vec<Coordinate>,
+}
+
+/// Coordinates derived from a coordinate, oldest first
+///
+/// Answers for deleted coordinates too, listing the children left behind.
+/// Children the caller may not read are left out.
+pub async fn get_children(
+    State(app): State<Arc<AppState>>,
+    Path(coord_id_str): Path<String>,
+    headers: HeaderMap,
+) -> ApiResult<Json<ChildrenResponse>> {
+    let coord_id = parse_coord_id(&app.facade, &coord_id_str).await?;
+    let caller = Caller::from_headers(&app, &headers);
+    acl::authorize(&app.facade, &caller, &coord_id, Access::Read).await?;
+    let mut children = app.facade.repository().get_children(&coord_id).await?;
+    let mut bypassed = 0;
+    children.retain(|c| caller.can_read(c.metadata.as_ref(), &mut bypassed));
+    caller.log_bypassed("children", bypassed);
+
+    Ok(Json(ChildrenResponse { coord_id: coord_id.0, children }))
+}
+
+/// Coordinates whose heads reference a coordinate
+///
+/// Answers for deleted coordinates too, listing the links left dangling.
+pub async fn get_backlinks(
+    State(app): State<Arc<AppState>>,
+    Path(coord_id_str): Path<String>,
+    headers: HeaderMap,
+) -> ApiResult<Json<BacklinksResponse>> {
+    let coord_id = parse_coord_id(&app.facade, &coord_id_str).await?;
+    acl::authorize(&app.facade, &Caller::from_headers(&app, &headers), &coord_id, Access::Read).await?;
+    let backlinks = app.facade.repository().get_backlinks(&coord_id).await?;
+
+    Ok(Json(BacklinksResponse {
+        coord_id: coord_id.0,
+        backlinks: backlinks
+            .into_iter()
+            .map(|l| BacklinkEntry {
+                from_coord: l.from_coord.0,
+                pointer: l.pointer,
+            })
+            .collect(),
+    }))
+}
+
+#[derive(Debug, Serialize)]
+pub struct HeadResponse {
+    pub coord_id: String,
+    #[serde(flatten)]
+    pub head: StoreHead,
+}
+
+/// Head delta of a coordinate, without reconstructing its state
+pub async fn get_head(
+    State(app): State<Arc<AppState>>,
+    Path(coord_id_str): Path<String>,
+    headers: HeaderMap,
+) -> ApiResult<Json<HeadResponse>> {
+    let coord_id = parse_coord_id(&app.facade, &coord_id_str).await?;
+    acl::authorize(&app.facade, &Caller::from_headers(&app, &headers), &coord_id, Access::Read).await?;
+    let deltas = app.facade.repository().get_deltas(&coord_id).await?;
+    let Some(last) = deltas.last() else {
+        return Err(AppError::NotFound(format!("Coordinate not found: {}", coord_id)));
+    };
+
+    Ok(Json(HeadResponse {
+        head: StoreHead {
+            delta_id: last.id.clone(),
+            chain_hash: last.chain_hash.clone(),
+            seq: deltas.len() as u64,
+        },
+        coord_id: coord_id.0,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HistoryQuery {
+    /// Only deltas with at least one op by this author
+    pub author: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OpAuthorCount {
+    pub author: String,
+    pub ops: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HistoryEntry {
+    /// Position in the chain, starting at 1
+    pub seq: u64,
+    pub delta_id: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    pub ops: usize,
+    /// Op counts per author, most ops first
+    pub ops_by_author: Vec<OpAuthorCount>,
+    /// e.g. `3 ops by planner, 1 by executor`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub authorship: Option<String>,
+    /// Computed patch size relative to the new state
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub patch_ratio: Option<f64>,
+    /// Whether the state was stored whole instead of the patch
+    pub full_replace: bool,
+    /// Paths the delta added, removed, and modified, leaving out redacted ones
+    pub changes: DiffSummary,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HistoryResponse {
+    pub coord_id: String,
+    pub entries: Vec<HistoryEntry>,
+    /// Patch ratios of the listed deltas
+    pub patch_ratios: PatchRatios,
+}
+
+/// Summary of each delta of a coordinate, oldest first
+///
+/// Lists who wrote which ops and the paths they touched, but not the values,
+/// and leaves out ops on redacted paths.
+pub async fn get_history(
+    State(app): State<Arc<AppState>>,
+    Path(coord_id_str): Path<String>,
+    Query(query): Query<HistoryQuery>,
+    Query(format): Query<HumanizeQuery>,
+    headers: HeaderMap,
+) -> ApiResult<Json<serde_json::Value>> {
+    let coord_id = parse_coord_id(&app.facade, &coord_id_str).await?;
+    acl::authorize(&app.facade, &Caller::from_headers(&app, &headers), &coord_id, Access::Read).await?;
+    let deltas = app.facade.repository().get_deltas(&coord_id).await?;
+    if deltas.is_empty() {
+        return Err(AppError::NotFound(format!("Coordinate not found: {}", coord_id)));
+    }
+    let rules = app.facade.redaction_rules(&coord_id).await?;
+
+    let entries = deltas
+        .iter()
+        .enumerate()
+        .filter(|(_, d)| match &query.author {
+            Some(author) => d.ops_by_author().iter().any(|(a, _)| a == author),
+            None => true,
+        })
+        .map(|(i, d)| HistoryEntry {
+            seq: i as u64 + 1,
+            delta_id: d.id.0.clone(),
+            created_at: d.created_at,
+            author: d.author.clone(),
+            ops: d.ops.len(),
+            ops_by_author: d
+                .ops_by_author()
+                .into_iter()
+                .map(|(author, ops)| OpAuthorCount { author: author.to_string(), ops })
+                .collect(),
+            authorship: d.authorship_summary(),
+            patch_ratio: d.patch_ratio(),
+            full_replace: d.is_full_replace(),
+            changes: DeltaEngine::describe_ops(
+                &d.ops.iter().filter(|op| !rules.touches(op)).cloned().collect::<Vec<_>>(),
+            ),
+        })
+        .collect::<Vec<_>>();
+    let mut patch_ratios = PatchRatios::default();
+    for entry in &entries {
+        if let Some(ratio) = entry.patch_ratio {
+            patch_ratios.record(ratio, entry.full_replace);
+        }
+    }
+
+    respond(
+        &HistoryResponse {
+            coord_id: coord_id.0,
+            entries,
+            patch_ratios,
+        },
+        format,
+    )
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeltasQuery {
+    /// Chain hash to list from, exclusive; the whole chain when absent
+    pub after: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeltasResponse {
+    pub coord_id: String,
+    pub metadata: Option<HashMap<String, serde_json::Value>>,
+    pub deltas: Vec<Delta>,
+}
+
+/// Raw deltas of a coordinate, oldest first (requires the admin token)
+///
+/// Deltas carry unredacted ops, so this is an admin route like the oplog.
+pub async fn get_deltas(
+    State(app): State<Arc<AppState>>,
+    Path(coord_id_str): Path<String>,
+    Query(query): Query<DeltasQuery>,
+    headers: HeaderMap,
+) -> ApiResult<Json<DeltasResponse>> {
+    if !is_admin(&app, &headers) {
+        return Err(AppError::Forbidden("reading raw deltas requires the admin token".to_string()));
+    }
+
+    let coord_id = parse_coord_id(&app.facade, &coord_id_str).await?;
+    acl::authorize(&app.facade, &Caller::from_headers(&app, &headers), &coord_id, Access::Read).await?;
+    let repo = app.facade.repository();
+    let Some(coordinate) = repo.get_coordinate(&coord_id).await? else {
+        return Err(AppError::NotFound(format!("Coordinate not found: {}", coord_id)));
+    };
+    let mut deltas = repo.get_deltas(&coord_id).await?;
+    if let Some(after) = &query.after {
+        let Some(pos) = deltas.iter().position(|d| &d.chain_hash.0 == after) else {
+            return Err(AppError::Conflict(format!(
+                "{} is not in the chain of {}",
+                after, coord_id
+            )));
+        };
+        deltas.drain(..=pos);
+    }
+
+    Ok(Json(DeltasResponse {
+        coord_id: coord_id.0,
+        metadata: coordinate.metadata,
+        deltas,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AppendDeltasRequest {
+    pub deltas: Vec<Delta>,
+    /// Metadata for the coordinate if this creates it
+    pub metadata: Option<HashMap<String, serde_json::Value>>,
+}
+
+/// Append deltas computed by another instance (requires the admin token)
+///
+/// Used by `bms sync push`. Deltas keep their own timestamps, which is why
+/// this is limited to the admin token like timestamp overrides.
+///
+/// A JSON body is buffered and capped at the store limit. With
+/// `Content-Type: application/x-ndjson` the body is read as a stream of one
+/// delta per line, optionally preceded by a `{"metadata": ...}` line, and
+/// appended in batches; only the import limit caps its total size. A failed
+/// stream keeps the batches appended before the failure, and running it
+/// again skips them.
+pub async fn append_deltas(
+    State(app): State<Arc<AppState>>,
+    Path(coord_id_str): Path<String>,
+    headers: HeaderMap,
+    body: Body,
+) -> ApiResult<Json<AppendOutcome>> {
+    if !is_admin(&app, &headers) {
+        return Err(AppError::Forbidden("appending deltas requires the admin token".to_string()));
+    }
+
+    let coord_id = parse_coord_id(&app.facade, &coord_id_str).await?;
+    acl::authorize(&app.facade, &Caller::from_headers(&app, &headers), &coord_id, Access::Write).await?;
+    let ndjson = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/x-ndjson"));
+    let outcome = if ndjson {
+        append_stream(&app, &coord_id, body).await?
+    } else {
+        let max = app.config.load().body_limits.store;
+        let body = axum::body::to_bytes(body, max).await.map_err(|_| AppError::TooLarge {
+            message: format!(
+                "JSON import bodies are limited to {} bytes; send application/x-ndjson to stream larger imports",
+                max
+            ),
+            stream: None,
+        })?;
+        let req: AppendDeltasRequest = serde_json::from_slice(&body)
+            .map_err(|e| AppError::BadRequest(format!("Invalid append request: {}", e)))?;
+        app.facade
+            .append_deltas(&coord_id, req.deltas, req.metadata)
+            .await
+            .map_err(append_error)?
+    };
+
+    if outcome.appended > 0 {
+        warn!(
+            target: "bms::audit",
+            coord_id = %coord_id,
+            appended = outcome.appended,
+            "synced deltas appended"
+        );
+    }
+    Ok(Json(outcome))
+}
+
+/// Deltas appended per facade call while streaming an NDJSON import
+const IMPORT_BATCH_SIZE: usize = 256;
+
+/// Read an NDJSON import line by line, appending every `IMPORT_BATCH_SIZE` deltas
+async fn append_stream(app: &AppState, coord_id: &CoordId, body: Body) -> ApiResult<AppendOutcome> {
+    let limits = app.config.load().body_limits;
+    let mut chunks = body.into_data_stream();
+    let mut import = StreamedImport::default();
+    let mut pending = Vec::new();
+    let mut received = 0;
+
+    while let Some(chunk) = chunks.next().await {
+        let chunk = chunk.map_err(|e| AppError::BadRequest(format!("Reading import body: {}", e)))?;
+        received += chunk.len();
+        if let Some(max) = limits.import.filter(|&max| received > max) {
+            return Err(AppError::TooLarge {
+                message: format!("Import bodies are limited to {} bytes", max),
+                stream: None,
+            });
+        }
+
+        pending.extend_from_slice(&chunk);
+        let mut start = 0;
+        while let Some(end) = pending[start..].iter().position(|&b| b == b'\n') {
+            import.push_line(app, coord_id, &pending[start..start + end]).await?;
+            start += end + 1;
+        }
+        pending.drain(..start);
+        if pending.len() > limits.store {
+            return Err(AppError::TooLarge {
+                message: format!("Import lines are limited to {} bytes", limits.store),
+                stream: None,
+            });
+        }
+    }
+    import.push_line(app, coord_id, &pending).await?;
+    import.finish(app, coord_id).await
+}
+
+/// Deltas of a streamed import waiting for the next batch
+#[derive(Default)]
+struct StreamedImport {
+    metadata: Option<HashMap<String, serde_json::Value>>,
+    batch: Vec<Delta>,
+    /// Totals over the batches appended so far
+    outcome: Option<AppendOutcome>,
+}
+
+impl StreamedImport {
+    async fn push_line(&mut self, app: &AppState, coord_id: &CoordId, line: &[u8]) -> ApiResult<()> {
+        let line = line.trim_ascii();
+        if line.is_empty() {
+            return Ok(());
+        }
+        let invalid = |e: serde_json::Error| AppError::BadRequest(format!("Invalid import line: {}", e));
+        let mut value: serde_json::Value = serde_json::from_slice(line).map_err(invalid)?;
+
+        let is_header = value.as_object().is_some_and(|o| o.len() == 1 && o.contains_key("metadata"));
+        if is_header {
+            if self.outcome.is_some() || !self.batch.is_empty() {
+                return Err(AppError::BadRequest(
+                    "The metadata line must come before the first delta".to_string(),
+                ));
+            }
+            self.metadata = serde_json::from_value(value["metadata"].take()).map_err(invalid)?;
+            return Ok(());
+        }
+
+        self.batch.push(serde_json::from_value(value).map_err(invalid)?);
+        if self.batch.len() >= IMPORT_BATCH_SIZE {
+            self.flush(app, coord_id).await?;
+        }
+        Ok(())
+    }
+
+    async fn flush(&mut self, app: &AppState, coord_id: &CoordId) -> ApiResult<()> {
+        let deltas = std::mem::take(&mut self.batch);
+        let outcome = app
+            .facade
+            .append_deltas(coord_id, deltas, self.metadata.clone())
+            .await
+            .map_err(append_error)?;
+        self.outcome = Some(match self.outcome.take() {
+            Some(total) => AppendOutcome {
+                appended: total.appended + outcome.appended,
+                already_present: total.already_present + outcome.already_present,
+                coordinate_created: total.coordinate_created || outcome.coordinate_created,
+                ..outcome
+            },
+            None => outcome,
+        });
+        Ok(())
+    }
+
+    /// Append the last batch; an import without deltas reports the current head
+    async fn finish(mut self, app: &AppState, coord_id: &CoordId) -> ApiResult<AppendOutcome> {
+        if !self.batch.is_empty() || self.outcome.is_none() {
+            self.flush(app, coord_id).await?;
+        }
+        Ok(self.outcome.expect("flushed at least once"))
+    }
+}
+
+fn append_error(err: bms_core::error::BmsError) -> AppError {
+    use bms_core::error::BmsError;
+    match err {
+        BmsError::PreconditionFailed { expected, actual, .. } => AppError::Conflict(format!(
+            "Diverged: delta extends {}, head is {}",
+            expected, actual
+        )),
+        e @ (BmsError::MerkleChainBroken { .. }
+        | BmsError::ChainLinkBroken { .. }
+        | BmsError::HashMismatch { .. }
+        | BmsError::OpAuthorsMismatch { .. }
+        | BmsError::InvalidSignature(_)
+        | BmsError::InvalidState(_)
+        | BmsError::DeltaCompression(_)) => AppError::BadRequest(e.to_string()),
+        e => e.into(),
+    }
+}
 
 /// Get storage statistics
 pub async fn get_stats(
     State(app): State<Arc<AppState>>,
+    Query(format): Query<HumanizeQuery>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let stats = app.repository.get_stats().await?;
+    let stats = app.facade.repository().get_stats().await?;
 
-    Ok(Json(serde_json::json!({
+    let response = serde_json::json!({
         "coordinates": stats.coordinate_count,
         "deltas": stats.delta_count,
         "snapshots": stats.snapshot_count,
-    })))
+        "blobs": stats.blob_count,
+        "materialized_heads": stats.head_state_count,
+        "materialized_head_bytes": stats.head_state_bytes,
+        "patch_ratios": app.facade.patch_ratios(),
+        "coord_filter": app.facade.coord_filter_stats(),
+        "search_cache": app.search_cache.is_enabled().then(|| app.search_cache.stats()),
+        "embedding_batches": app.embedder.as_ref().map(|e| e.stats()),
+        "embedding_drift": app.embedder.as_ref().map(|_| app.embed_drift.stats()),
+        "state_size_warning_bytes": app.facade.state_size_warning(),
+    });
+    respond(&response, format)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LargestStatsQuery {
+    pub limit: Option<i64>,
+}
+
+/// List the coordinates with the largest head states
+///
+/// Sizes are recorded by each store; coordinates written before sizes were
+/// tracked are missing until `bms stats --recompute-sizes` runs.
+pub async fn get_largest_stats(
+    State(app): State<Arc<AppState>>,
+    Query(query): Query<LargestStatsQuery>,
+    Query(format): Query<HumanizeQuery>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let limit = query.limit.unwrap_or(20).clamp(1, 1000);
+    let largest = app.facade.repository().list_state_sizes(Some(limit)).await?;
+    respond::<Vec<StateSize>>(&largest, format)
+}
+
+#[derive(Debug, Serialize)]
+pub struct CoordStatsResponse {
+    pub coord_id: String,
+    /// Head state size times the delta count, what storing every state would cost
+    pub original_bytes: usize,
+    /// Delta ops as stored
+    pub compressed_bytes: usize,
+    pub compression_ratio: f64,
+    pub delta_count: u32,
+    /// Ops of the chain by kind, and their canonical size
+    pub ops: OpsStats,
+}
+
+/// Compression of one coordinate's chain
+pub async fn get_coord_stats(
+    State(app): State<Arc<AppState>>,
+    Path(coord_id_str): Path<String>,
+    Query(format): Query<HumanizeQuery>,
+    headers: HeaderMap,
+) -> ApiResult<Json<serde_json::Value>> {
+    let coord_id = parse_coord_id(&app.facade, &coord_id_str).await?;
+    acl::authorize(&app.facade, &Caller::from_headers(&app, &headers), &coord_id, Access::Read).await?;
+    let stats = app
+        .facade
+        .compression_stats(&coord_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Coordinate not found: {}", coord_id)))?;
+    respond(
+        &CoordStatsResponse {
+            coord_id: coord_id.0,
+            original_bytes: stats.original_bytes,
+            compressed_bytes: stats.compressed_bytes,
+            compression_ratio: stats.compression_ratio,
+            delta_count: stats.delta_count,
+            ops: stats.ops,
+        },
+        format,
+    )
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HotStatsQuery {
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HotCoordinateResponse {
+    pub coord_id: String,
+    pub read_count: u64,
+    pub last_read_at: chrono::DateTime<chrono::Utc>,
+    pub delta_count: u64,
+    /// Current importance after decay
+    pub importance: f32,
+    /// Whether the head embedding is currently cached
+    pub cached: bool,
+}
+
+/// List the most-read coordinates
+///
+/// Counts come from the last flush of the read statistics, so reads made in
+/// the current flush interval are not included yet.
+pub async fn get_hot_stats(
+    State(app): State<Arc<AppState>>,
+    Query(query): Query<HotStatsQuery>,
+    Query(format): Query<HumanizeQuery>,
+) -> ApiResult<Json<serde_json::Value>> {
+    if !app.access_tracker.is_enabled() {
+        return Err(AppError::BadRequest(
+            "Read statistics are disabled (BMS_ACCESS_STATS=0)".to_string(),
+        ));
+    }
+
+    let limit = query.limit.unwrap_or(20).clamp(1, 1000);
+    let hot = app.facade.repository().get_hot_coordinates(limit).await?;
+    let importance = effective_importance(&app).await?;
+
+    let cache = app.embedding_cache.lock().await;
+    let response = hot
+        .into_iter()
+        .map(|h| HotCoordinateResponse {
+            cached: cache.contains_key(&h.coord_id),
+            importance: importance.get(&h.coord_id).copied().unwrap_or(DEFAULT_IMPORTANCE),
+            coord_id: h.coord_id.0,
+            read_count: h.read_count,
+            last_read_at: h.last_read_at,
+            delta_count: h.delta_count,
+        })
+        .collect::<Vec<_>>();
+
+    respond(&response, format)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PlanQuery {
+    pub limit: Option<usize>,
+}
+
+/// Ranked maintenance recommendations (requires the admin token)
+pub async fn get_plan(
+    State(app): State<Arc<AppState>>,
+    Query(query): Query<PlanQuery>,
+    Query(format): Query<HumanizeQuery>,
+    headers: HeaderMap,
+) -> ApiResult<Json<serde_json::Value>> {
+    if !is_admin(&app, &headers) {
+        return Err(AppError::Forbidden("the plan requires the admin token".to_string()));
+    }
+
+    let mut plan = planner::plan_store(&app.facade, &app.cost_model()).await?;
+    plan.truncate(query.limit.unwrap_or(50));
+    respond::<Vec<Recommendation>>(&plan, format)
+}
+
+/// Apply one plan action and report actuals against the estimate (requires the admin token)
+pub async fn apply_plan_action(
+    State(app): State<Arc<AppState>>,
+    Query(format): Query<HumanizeQuery>,
+    headers: HeaderMap,
+    Json(action): Json<PlanAction>,
+) -> ApiResult<Json<serde_json::Value>> {
+    if !is_admin(&app, &headers) {
+        return Err(AppError::Forbidden(
+            "applying plan actions requires the admin token".to_string(),
+        ));
+    }
+
+    let coord_id = action.coord_id();
+    if !app.facade.repository().coordinate_exists(coord_id).await? {
+        return Err(AppError::NotFound(format!("Coordinate not found: {}", coord_id)));
+    }
+
+    let applied = planner::apply(&app.facade, &action, &app.cost_model()).await?;
+    warn!(target: "bms::audit", coord_id = %coord_id, action = ?action, "plan action applied");
+
+    respond::<AppliedAction>(&applied, format)
+}
+
+/// Key id of the caller, as listed in coordinate ACLs
+pub async fn whoami(State(app): State<Arc<AppState>>, headers: HeaderMap) -> Json<Caller> {
+    Json(Caller::from_headers(&app, &headers))
+}
+
+/// Read the config file again and apply what can change without a restart
+///
+/// Nothing changes when the file has any error; all of them are returned.
+pub async fn reload_config(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> ApiResult<Json<ReloadReport>> {
+    if !is_admin(&app, &headers) {
+        return Err(AppError::Forbidden("reloading the config requires the admin token".to_string()));
+    }
+    let report = config::reload(&app)
+        .map_err(|errors| AppError::BadRequest(format!("Config not reloaded: {}", errors.join("; "))))?;
+    warn!(target: "bms::audit", changed = ?report.changed, "config reloaded");
+    Ok(Json(report))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HumanizeQuery {
+    /// Add `*_human` companions for timestamps and byte counts
+    #[serde(default)]
+    pub humanize: bool,
+}
+
+/// Serialize a response, decorating it when the request asked for `?humanize=true`
+///
+/// Relative times are computed against the time the response is built.
+fn respond<T: Serialize>(value: &T, format: HumanizeQuery) -> ApiResult<Json<serde_json::Value>> {
+    let mut value = serde_json::to_value(value).map_err(bms_core::BmsError::from)?;
+    if format.humanize {
+        humanize::decorate(&mut value, chrono::Utc::now());
+    }
+    Ok(Json(value))
 }
 
 // Error handling
@@ -481,25 +2257,191 @@ pub async fn get_stats(
 pub enum AppError {
     BmsError(bms_core::error::BmsError),
     NotFound(String),
+    BadRequest(String),
+    Conflict(String),
+    Forbidden(String),
+    /// 403 with a machine-readable `code` next to the message
+    ForbiddenCode {
+        code: &'static str,
+        message: String,
+    },
+    /// Capability missing from this build or deployment
+    Unavailable(String),
+    /// 413, with the path of a streaming alternative if there is one
+    TooLarge {
+        message: String,
+        stream: Option<String>,
+    },
+    PreconditionFailed {
+        message: String,
+        /// Current head ETag, if the coordinate has a head
+        etag: Option<String>,
+    },
+    /// 422 naming the patch op that does not apply to the head
+    PatchRejected {
+        message: String,
+        op_index: usize,
+        path: String,
+    },
+    /// 422 for a delta outside the configured `DeltaPolicy`
+    PolicyViolation(String),
 }
 
 impl From<bms_core::error::BmsError> for AppError {
     fn from(err: bms_core::error::BmsError) -> Self {
-        AppError::BmsError(err)
+        match err {
+            bms_core::error::BmsError::ReadOnly(reason) => AppError::ForbiddenCode {
+                code: READ_ONLY,
+                message: format!("Writes are not accepted: {}", reason),
+            },
+            bms_core::error::BmsError::PatchRejected { index, ref path, .. } => AppError::PatchRejected {
+                message: err.to_string(),
+                op_index: index,
+                path: path.clone(),
+            },
+            err @ bms_core::error::BmsError::PolicyViolation(_) => AppError::PolicyViolation(err.to_string()),
+            err @ bms_core::error::BmsError::CoordinateCollision(_) => AppError::Conflict(err.to_string()),
+            err @ bms_core::error::BmsError::InvalidCoordinate(_) => AppError::BadRequest(err.to_string()),
+            err => AppError::BmsError(err),
+        }
     }
 }
 
 impl IntoResponse for AppError {
     fn into_response(self) -> axum::response::Response {
+        let mut etag = None;
+        let mut code = None;
+        let mut stream = None;
+        let mut rejected = None;
         let (status, message) = match self {
             AppError::BmsError(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
             AppError::NotFound(msg) => (StatusCode::NOT_FOUND, msg),
+            AppError::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg),
+            AppError::Conflict(msg) => (StatusCode::CONFLICT, msg),
+            AppError::Forbidden(msg) => (StatusCode::FORBIDDEN, msg),
+            AppError::ForbiddenCode { code: c, message } => {
+                code = Some(c);
+                (StatusCode::FORBIDDEN, message)
+            }
+            AppError::Unavailable(msg) => (StatusCode::NOT_IMPLEMENTED, msg),
+            AppError::TooLarge { message, stream: path } => {
+                stream = path;
+                (StatusCode::PAYLOAD_TOO_LARGE, message)
+            }
+            AppError::PreconditionFailed { message, etag: current } => {
+                etag = current.map(|h| format_etag(&Hash(h)));
+                (StatusCode::PRECONDITION_FAILED, message)
+            }
+            AppError::PatchRejected { message, op_index, path } => {
+                code = Some(PATCH_REJECTED);
+                rejected = Some((op_index, path));
+                (StatusCode::UNPROCESSABLE_ENTITY, message)
+            }
+            AppError::PolicyViolation(message) => {
+                code = Some(POLICY_VIOLATION);
+                (StatusCode::UNPROCESSABLE_ENTITY, message)
+            }
         };
 
-        let body = Json(serde_json::json!({
+        let mut body = serde_json::json!({
             "error": message
-        }));
+        });
+        if let Some(code) = code {
+            body["code"] = serde_json::json!(code);
+        }
+        if let Some(stream) = stream {
+            body["stream"] = serde_json::json!(stream);
+        }
+        if let Some((op_index, path)) = rejected {
+            body["op_index"] = serde_json::json!(op_index);
+            body["path"] = serde_json::json!(path);
+        }
+        let body = Json(body);
+
+        let mut response = (status, body).into_response();
+        if let Some(etag) = etag.and_then(|e| e.parse().ok()) {
+            response.headers_mut().insert(header::ETAG, etag);
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bms_core::extract::ExtractionConfig;
+    use serde_json::json;
+    use std::hash::{DefaultHasher, Hash, Hasher};
+
+    /// Bag of words hashed into a few buckets, standing in for the model
+    fn embed(text: &str) -> Vec<f32> {
+        let mut vector = vec![0.0; 64];
+        for word in text.split(|c: char| !c.is_alphanumeric()).filter(|w| !w.is_empty()) {
+            let mut hasher = DefaultHasher::new();
+            word.to_lowercase().hash(&mut hasher);
+            vector[(hasher.finish() % 64) as usize] += 1.0;
+        }
+        vector
+    }
+
+    #[test]
+    fn test_coordinate_found_by_its_topic_facet() {
+        let config = ExtractionConfig::from_list("topic");
+        let topic = |value: &str| HashMap::from([("topic".to_string(), json!(value))]);
+        let heads = [
+            ("bread", json!({"note": "rye bread needs a long proof"}), Some(topic("database migrations"))),
+            ("hike", json!({"note": "weekend hike along the ridge"}), None),
+            ("garden", json!({"note": "tomatoes want more sun"}), Some(topic("gardening"))),
+        ];
+        let indexed: Vec<(&str, Vec<f32>, HashMap<String, FacetEmbedding>)> = heads
+            .iter()
+            .map(|(name, state, metadata)| {
+                let extracted = config.extract(state, metadata.as_ref());
+                let facets = extracted
+                    .facets
+                    .iter()
+                    .map(|f| (f.key.clone(), FacetEmbedding { text: f.text.clone(), embedding: embed(&f.text) }))
+                    .collect();
+                (*name, embed(&extracted.text), facets)
+            })
+            .collect();
+        let rank = |query: &str, facets: &[(String, Vec<f32>)]| {
+            let mut scores: Vec<(&str, f32)> = indexed
+                .iter()
+                .filter(|(_, _, indexed)| facets.iter().all(|(key, _)| indexed.contains_key(key)))
+                .map(|(name, embedding, indexed)| {
+                    let score = cosine_similarity(&embed(query), embedding);
+                    (*name, facet_blend(score, facets, indexed, DEFAULT_FACET_WEIGHT))
+                })
+                .collect();
+            scores.sort_by(|a, b| b.1.total_cmp(&a.1));
+            scores
+        };
+
+        // The body says nothing about databases; the appended topic does
+        assert_eq!(rank("database migrations", &[])[0].0, "bread");
 
-        (status, body).into_response()
+        // A facet query only sees coordinates with the facet, ranked by it
+        let facet = vec![("topic".to_string(), embed(&facet_text("topic", &json!("database migrations")).unwrap()))];
+        let ranked = rank("schema changes", &facet);
+        assert_eq!(ranked.iter().map(|(name, _)| *name).collect::<Vec<_>>(), vec!["bread", "garden"]);
+        assert!(ranked[0].1 >= DEFAULT_FACET_WEIGHT * 0.99, "{:?}", ranked);
+        assert_eq!(facet_blend(0.3, &[], &HashMap::new(), 0.5), 0.3);
+    }
+
+    #[tokio::test]
+    async fn test_rejected_patch_answers_422_with_the_op() {
+        let err = bms_core::error::BmsError::PatchRejected {
+            index: 2,
+            path: "/items/7".to_string(),
+            reason: "path is invalid".to_string(),
+        };
+        let response = AppError::from(err).into_response();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], PATCH_REJECTED);
+        assert_eq!((body["op_index"].clone(), body["path"].clone()), (json!(2), json!("/items/7")));
+        assert_eq!(body["error"], "Op 2 cannot be applied at /items/7: path is invalid");
     }
 }
@@ -0,0 +1,28 @@
+//! BMS HTTP and WebSocket API
+//!
+//! The router, shared state, and background tasks behind the `bms-api`
+//! binary. `bms serve` mounts the same router in the CLI process.
+//!
+//! Vector search needs the default `vector` feature. Without it the crate
+//! does not depend on bms-vector (and so on FastEmbed or ONNX), and
+//! `/search` answers 501.
+
+mod acl;
+pub mod config;
+mod drift;
+mod embedder;
+mod handlers;
+mod limits;
+mod replication;
+mod saved_search;
+mod search_cache;
+mod server;
+mod state;
+mod sync;
+mod ws;
+
+pub use config::{init_tracing, ConfigSource, ReloadReport, RuntimeConfig};
+pub use limits::BodyLimits;
+pub use replication::{ReplicationConfig, ReplicationError, ReplicationStatus, Replicator};
+pub use server::{build_state, router, serve, Listen};
+pub use state::AppState;
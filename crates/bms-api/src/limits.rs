@@ -0,0 +1,163 @@
+//! Request body and response size limits per route class
+//!
+//! Small JSON requests (search, metadata patches, admin actions) are capped
+//! at the read limit and `/store` and `/store/group` at the store limit, both
+//! through `DefaultBodyLimit` on their part of the router. Delta imports read
+//! their body as a stream and count bytes themselves, so an import of any
+//! size is never held in memory whole. Recalls whose state is larger than
+//! the recall limit answer 413 and point at `/recall/:id/stream`.
+//!
+//! Stores are also held to a `DeltaPolicy`: a state over its size limit
+//! answers 413 before it is diffed, and a delta breaking any other limit
+//! answers 422.
+//!
+//! All of them can be changed by a config reload; see `config`.
+
+use crate::config::SettingsParser;
+use anyhow::{bail, Result};
+use bms_core::policy::{DeltaPolicy, OpKind};
+
+/// Body limit for search, read, and admin requests
+pub const DEFAULT_READ_BODY_LIMIT: usize = 64 * 1024;
+
+/// Body limit for stores; twice the default state size warning, leaving
+/// room for metadata and pretty-printed JSON
+pub const DEFAULT_STORE_BODY_LIMIT: usize = 32 * 1024 * 1024;
+
+/// Largest state `/recall` answers in one buffered response
+pub const DEFAULT_RECALL_RESPONSE_LIMIT: usize = 32 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BodyLimits {
+    pub read: usize,
+    /// Also caps WebSocket messages, buffered JSON imports, and each line of
+    /// a streamed import
+    pub store: usize,
+    /// `None` accepts streamed imports of any size
+    pub import: Option<usize>,
+    /// `None` answers every recall in one response
+    pub recall_response: Option<usize>,
+}
+
+impl Default for BodyLimits {
+    fn default() -> Self {
+        Self {
+            read: DEFAULT_READ_BODY_LIMIT,
+            store: DEFAULT_STORE_BODY_LIMIT,
+            import: None,
+            recall_response: Some(DEFAULT_RECALL_RESPONSE_LIMIT),
+        }
+    }
+}
+
+impl BodyLimits {
+    /// Read the `BMS_*_LIMIT` settings; `0` lifts the import and recall limits
+    pub(crate) fn from_settings(settings: &mut SettingsParser<'_>) -> Self {
+        Self {
+            read: settings.get("BMS_READ_BODY_LIMIT", DEFAULT_READ_BODY_LIMIT),
+            store: settings.get("BMS_STORE_BODY_LIMIT", DEFAULT_STORE_BODY_LIMIT),
+            import: Some(settings.get("BMS_IMPORT_BODY_LIMIT", 0)).filter(|&max| max > 0),
+            recall_response: Some(settings.get("BMS_RECALL_RESPONSE_LIMIT", DEFAULT_RECALL_RESPONSE_LIMIT))
+                .filter(|&max| max > 0),
+        }
+    }
+
+    /// Reject limits that would accept a request on one route class but the
+    /// same data on a larger class not
+    pub fn validate(&self) -> Result<()> {
+        if self.read == 0 || self.store == 0 {
+            bail!("BMS_READ_BODY_LIMIT and BMS_STORE_BODY_LIMIT must be positive");
+        }
+        if self.read > self.store {
+            bail!(
+                "BMS_READ_BODY_LIMIT ({}) is larger than BMS_STORE_BODY_LIMIT ({})",
+                self.read,
+                self.store
+            );
+        }
+        if let Some(import) = self.import.filter(|&import| self.store > import) {
+            bail!(
+                "BMS_STORE_BODY_LIMIT ({}) is larger than BMS_IMPORT_BODY_LIMIT ({})",
+                self.store,
+                import
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Read `BMS_MAX_OPS`, `BMS_MAX_STATE_BYTES`, `BMS_MAX_VALUE_BYTES`, and the
+/// comma-separated `BMS_ALLOWED_OPS`; `0` or an empty list lifts a limit
+pub(crate) fn delta_policy_from_settings(settings: &mut SettingsParser<'_>) -> DeltaPolicy {
+    let allowed: String = settings.get("BMS_ALLOWED_OPS", String::new());
+    let mut allowed_ops = Vec::new();
+    for kind in allowed.split(',').map(str::trim).filter(|kind| !kind.is_empty()) {
+        match kind.parse::<OpKind>() {
+            Ok(kind) => allowed_ops.push(kind),
+            Err(e) => settings.error(format!("BMS_ALLOWED_OPS: {}", e)),
+        }
+    }
+    if allowed_ops.is_empty() {
+        allowed_ops = OpKind::ALL.to_vec();
+    }
+    DeltaPolicy {
+        max_ops: Some(settings.get("BMS_MAX_OPS", 0)).filter(|&max| max > 0),
+        max_state_bytes: Some(settings.get("BMS_MAX_STATE_BYTES", 0)).filter(|&max| max > 0),
+        max_value_bytes: Some(settings.get("BMS_MAX_VALUE_BYTES", 0)).filter(|&max| max > 0),
+        allowed_ops: allowed_ops.into_iter().collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{RuntimeConfig, Settings};
+
+    #[test]
+    fn test_inconsistent_limits_are_rejected() {
+        assert!(BodyLimits::default().validate().is_ok());
+
+        let store_over_import = BodyLimits {
+            import: Some(DEFAULT_STORE_BODY_LIMIT / 2),
+            ..BodyLimits::default()
+        };
+        let error = store_over_import.validate().unwrap_err().to_string();
+        assert!(error.contains("BMS_IMPORT_BODY_LIMIT"), "{}", error);
+
+        let read_over_store = BodyLimits {
+            read: DEFAULT_STORE_BODY_LIMIT + 1,
+            ..BodyLimits::default()
+        };
+        assert!(read_over_store.validate().is_err());
+        assert!(BodyLimits { store: 0, read: 0, ..BodyLimits::default() }.validate().is_err());
+
+        // Equal limits are consistent
+        let equal = BodyLimits {
+            import: Some(DEFAULT_STORE_BODY_LIMIT),
+            ..BodyLimits::default()
+        };
+        assert!(equal.validate().is_ok());
+    }
+
+    #[test]
+    fn test_delta_policy_settings() {
+        let parse = |pairs: &[(&str, &str)]| {
+            let settings: Settings = pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+            RuntimeConfig::from_settings(&settings).map(|config| config.delta_policy)
+        };
+        assert_eq!(parse(&[]).unwrap(), DeltaPolicy::default());
+
+        let policy = parse(&[
+            ("BMS_MAX_OPS", "100"),
+            ("BMS_MAX_STATE_BYTES", "0"),
+            ("BMS_MAX_VALUE_BYTES", "4096"),
+            ("BMS_ALLOWED_OPS", "add, replace,remove"),
+        ])
+        .unwrap();
+        assert_eq!((policy.max_ops, policy.max_state_bytes, policy.max_value_bytes), (Some(100), None, Some(4096)));
+        assert_eq!(policy.allowed_ops, [OpKind::Add, OpKind::Replace, OpKind::Remove].into());
+
+        let errors = parse(&[("BMS_ALLOWED_OPS", "add,splice"), ("BMS_MAX_OPS", "-1")]).unwrap_err();
+        assert_eq!(errors.len(), 2, "{:?}", errors);
+    }
+}
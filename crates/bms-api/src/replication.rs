@@ -0,0 +1,586 @@
+//! Replication from a primary to a warm standby
+//!
+//! The primary serves its oplog: `GET /replicate/changes?since_lsn=N` streams
+//! the entries after `N` as NDJSON, with payloads, in the format of
+//! `bms oplog export`, and `POST /replicate/check` tells a standby whether
+//! the heads it sampled are part of the primary's chains. A standby
+//! (`BMS_REPLICATE_FROM`, or `bms replicate`) checks once per connection,
+//! then pulls pages and applies them through `BmsFacade::apply_replicated`,
+//! recording the applied LSN after each batch. Its facade is read-only
+//! while replication is configured.
+
+use crate::handlers::{is_admin, ApiResult, AppError};
+use crate::state::AppState;
+use axum::{
+    body::{Body, Bytes},
+    extract::{Query, State},
+    http::{header, HeaderMap},
+    response::IntoResponse,
+    Json,
+};
+use bms_core::types::{CoordId, Hash};
+use bms_core::BmsError;
+use bms_storage::oplog::{self, OplogEntry};
+use bms_storage::BmsFacade;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{error, warn};
+
+/// Response header with the primary's highest LSN when the page was read
+pub const LAST_LSN_HEADER: &str = "x-bms-last-lsn";
+
+/// Entries per `/replicate/changes` response unless the request asks for fewer
+pub const DEFAULT_PAGE_SIZE: i64 = 1000;
+
+/// Largest page a request may ask for
+const MAX_PAGE_SIZE: i64 = 10_000;
+
+/// Entries applied per facade call, and so between recorded positions
+const APPLY_BATCH_SIZE: usize = 256;
+
+/// Heads a standby sends to `/replicate/check`
+pub const DEFAULT_CHECK_SAMPLE: usize = 32;
+
+/// Longest wait between retries after failures
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Deserialize)]
+pub struct ChangesQuery {
+    #[serde(default)]
+    pub since_lsn: i64,
+    pub limit: Option<i64>,
+}
+
+/// Oplog entries after `since_lsn` with their payloads, as NDJSON (requires
+/// the admin token)
+///
+/// Entries carry unredacted data, like `/coords/:id/deltas`. At most `limit`
+/// entries are sent; a standby asks again from the last LSN it received.
+pub async fn changes(
+    State(app): State<Arc<AppState>>,
+    Query(query): Query<ChangesQuery>,
+    headers: HeaderMap,
+) -> ApiResult<impl IntoResponse> {
+    if !is_admin(&app, &headers) {
+        return Err(AppError::Forbidden("replication requires the admin token".to_string()));
+    }
+
+    let repo = app.facade.repository();
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let last_lsn = repo.max_lsn().await?;
+    let entries = repo.get_oplog(query.since_lsn, limit).await?;
+
+    // Payloads are read as the body is sent, so a page of large states is
+    // never held in memory at once
+    let (tx, rx) = tokio::sync::mpsc::channel::<std::io::Result<Bytes>>(16);
+    let facade = app.facade.clone();
+    tokio::spawn(async move {
+        for mut entry in entries {
+            let line = async {
+                entry.payload = facade.repository().oplog_payload(&entry).await?;
+                let mut line = serde_json::to_vec(&entry)?;
+                line.push(b'\n');
+                Ok::<_, BmsError>(Bytes::from(line))
+            };
+            let chunk = line.await.map_err(std::io::Error::other);
+            let failed = chunk.is_err();
+            if tx.send(chunk).await.is_err() || failed {
+                break;
+            }
+        }
+    });
+    let lines = futures_util::stream::unfold(rx, |mut rx| async move { rx.recv().await.map(|chunk| (chunk, rx)) });
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/x-ndjson".to_string()),
+            (header::HeaderName::from_static(LAST_LSN_HEADER), last_lsn.to_string()),
+        ],
+        Body::from_stream(lines),
+    ))
+}
+
+/// Standby head to compare with the primary's chain
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HeadCheck {
+    pub coord_id: CoordId,
+    pub chain_hash: Hash,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CheckRequest {
+    /// Last LSN the standby applied
+    pub lsn: i64,
+    pub heads: Vec<HeadCheck>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CheckResponse {
+    pub last_lsn: i64,
+    /// Lowest LSN still in the primary's oplog; entries before it were trimmed
+    pub oldest_lsn: Option<i64>,
+    /// Coordinates whose standby head is not in the primary's chain
+    pub diverged: Vec<CoordId>,
+}
+
+/// Compare a standby's sampled heads with this instance's chains (requires
+/// the admin token)
+pub async fn check(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(req): Json<CheckRequest>,
+) -> ApiResult<Json<CheckResponse>> {
+    if !is_admin(&app, &headers) {
+        return Err(AppError::Forbidden("replication requires the admin token".to_string()));
+    }
+
+    let repo = app.facade.repository();
+    let heads: Vec<(CoordId, Hash)> = req.heads.into_iter().map(|h| (h.coord_id, h.chain_hash)).collect();
+    Ok(Json(CheckResponse {
+        last_lsn: repo.max_lsn().await?,
+        oldest_lsn: repo.min_lsn().await?,
+        diverged: oplog::diverged_heads(repo, req.lsn, &heads).await?,
+    }))
+}
+
+/// Where a standby replicates from and how often it asks
+#[derive(Debug, Clone)]
+pub struct ReplicationConfig {
+    /// Base URL of the primary's API
+    pub source: String,
+    /// The primary's admin token
+    pub token: Option<String>,
+    /// Wait between polls once caught up, and the first retry delay
+    pub poll_interval: Duration,
+    pub page_size: i64,
+    pub check_sample: usize,
+}
+
+impl ReplicationConfig {
+    pub fn new(source: impl Into<String>) -> Self {
+        Self {
+            source: source.into().trim_end_matches('/').to_string(),
+            token: None,
+            poll_interval: Duration::from_secs(1),
+            page_size: DEFAULT_PAGE_SIZE,
+            check_sample: DEFAULT_CHECK_SAMPLE,
+        }
+    }
+}
+
+/// Why a pull stopped
+#[derive(Debug)]
+pub enum ReplicationError {
+    /// The standby holds data the primary does not; replication stops
+    Diverged(String),
+    /// Network, HTTP, or storage failure; retried
+    Failed(String),
+}
+
+impl std::fmt::Display for ReplicationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReplicationError::Diverged(msg) => write!(f, "standby diverged from its source: {}", msg),
+            ReplicationError::Failed(msg) => write!(f, "replication failed: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ReplicationError {}
+
+impl From<BmsError> for ReplicationError {
+    fn from(err: BmsError) -> Self {
+        match err {
+            // A delta that does not extend the standby's head
+            e @ BmsError::MerkleChainBroken { .. } => ReplicationError::Diverged(e.to_string()),
+            e => ReplicationError::Failed(e.to_string()),
+        }
+    }
+}
+
+impl From<reqwest::Error> for ReplicationError {
+    fn from(err: reqwest::Error) -> Self {
+        ReplicationError::Failed(err.to_string())
+    }
+}
+
+/// Progress of a standby, as reported by `/health`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ReplicationStatus {
+    pub source: String,
+    pub applied_lsn: i64,
+    /// The primary's highest LSN at the last pull
+    pub source_lsn: Option<i64>,
+    pub last_error: Option<String>,
+    /// Set once divergence stopped replication
+    pub stopped: bool,
+}
+
+/// Pulls a primary's oplog into a standby's facade
+pub struct Replicator {
+    facade: Arc<BmsFacade>,
+    client: reqwest::Client,
+    config: ReplicationConfig,
+    status: Mutex<ReplicationStatus>,
+}
+
+impl Replicator {
+    pub fn new(facade: Arc<BmsFacade>, config: ReplicationConfig) -> Self {
+        let status = ReplicationStatus {
+            source: config.source.clone(),
+            ..ReplicationStatus::default()
+        };
+        Self {
+            facade,
+            client: reqwest::Client::new(),
+            config,
+            status: Mutex::new(status),
+        }
+    }
+
+    pub fn status(&self) -> ReplicationStatus {
+        self.status.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    fn update_status(&self, update: impl FnOnce(&mut ReplicationStatus)) {
+        update(&mut self.status.lock().unwrap_or_else(|e| e.into_inner()));
+    }
+
+    /// Last source LSN applied here
+    ///
+    /// The recorded position can lag the oplog after a crash mid-batch, and
+    /// the oplog can be trimmed below it, so the higher of the two wins.
+    pub async fn position(&self) -> Result<i64, ReplicationError> {
+        let repo = self.facade.repository();
+        let recorded = repo.get_replication_lsn(&self.config.source).await?.unwrap_or(0);
+        Ok(recorded.max(repo.max_lsn().await?))
+    }
+
+    fn request(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match &self.config.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+
+    /// Refuse to continue unless the standby is a prefix of the primary
+    pub async fn check(&self) -> Result<(), ReplicationError> {
+        let lsn = self.position().await?;
+        let heads = oplog::sample_heads(self.facade.repository(), self.config.check_sample)
+            .await?
+            .into_iter()
+            .map(|(coord_id, chain_hash)| HeadCheck { coord_id, chain_hash })
+            .collect();
+        let url = format!("{}/replicate/check", self.config.source);
+        let response = self.request(self.client.post(url)).json(&CheckRequest { lsn, heads }).send().await?;
+        if !response.status().is_success() {
+            return Err(ReplicationError::Failed(format!("check answered {}", response.status())));
+        }
+        let checked: CheckResponse = response.json().await?;
+
+        if lsn > checked.last_lsn {
+            return Err(ReplicationError::Diverged(format!(
+                "standby is at LSN {} but the source only has {}",
+                lsn, checked.last_lsn
+            )));
+        }
+        if let Some(oldest) = checked.oldest_lsn.filter(|&oldest| oldest > lsn + 1) {
+            return Err(ReplicationError::Diverged(format!(
+                "source oplog starts at LSN {} but the standby needs {}; seed it from a newer backup",
+                oldest,
+                lsn + 1
+            )));
+        }
+        if !checked.diverged.is_empty() {
+            let ids: Vec<&str> = checked.diverged.iter().map(CoordId::as_str).collect();
+            return Err(ReplicationError::Diverged(format!(
+                "heads of {} are not in the source's chains",
+                ids.join(", ")
+            )));
+        }
+        self.update_status(|s| s.source_lsn = Some(checked.last_lsn));
+        Ok(())
+    }
+
+    /// Fetch and apply one page of entries, returning how many were received
+    pub async fn pull(&self) -> Result<u64, ReplicationError> {
+        let since = self.position().await?;
+        let url = format!("{}/replicate/changes", self.config.source);
+        let request = self
+            .client
+            .get(url)
+            .query(&[("since_lsn", since), ("limit", self.config.page_size)]);
+        let mut response = self.request(request).send().await?;
+        if !response.status().is_success() {
+            return Err(ReplicationError::Failed(format!("changes answered {}", response.status())));
+        }
+        let source_lsn = response
+            .headers()
+            .get(LAST_LSN_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok());
+
+        let mut received = 0;
+        let mut expected = since + 1;
+        let mut pending = Vec::new();
+        let mut batch = Vec::with_capacity(APPLY_BATCH_SIZE);
+        while let Some(chunk) = response.chunk().await? {
+            pending.extend_from_slice(&chunk);
+            while let Some(end) = pending.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = pending.drain(..=end).collect();
+                let entry: OplogEntry = serde_json::from_slice(&line).map_err(BmsError::from)?;
+                // LSNs are dense, so a gap means the source trimmed entries mid-follow
+                if entry.lsn != expected {
+                    return Err(ReplicationError::Diverged(format!(
+                        "expected LSN {} from the source, got {}",
+                        expected, entry.lsn
+                    )));
+                }
+                expected += 1;
+                received += 1;
+                batch.push(entry);
+                if batch.len() == APPLY_BATCH_SIZE {
+                    self.apply(std::mem::take(&mut batch)).await?;
+                }
+            }
+        }
+        if !pending.iter().all(u8::is_ascii_whitespace) {
+            return Err(ReplicationError::Failed("changes ended mid-entry".to_string()));
+        }
+        if !batch.is_empty() {
+            self.apply(batch).await?;
+        }
+
+        self.update_status(|s| {
+            s.source_lsn = source_lsn.or(s.source_lsn);
+            s.last_error = None;
+        });
+        Ok(received)
+    }
+
+    async fn apply(&self, batch: Vec<OplogEntry>) -> Result<(), ReplicationError> {
+        let report = self.facade.apply_replicated(&batch).await?;
+        if let Some(lsn) = report.last_lsn {
+            self.facade.repository().set_replication_lsn(&self.config.source, lsn).await?;
+            self.update_status(|s| s.applied_lsn = lsn);
+        }
+        Ok(())
+    }
+
+    /// Check, then pull until a page comes back short
+    ///
+    /// Returns the number of entries received.
+    pub async fn catch_up(&self) -> Result<u64, ReplicationError> {
+        self.check().await?;
+        self.pull_all().await
+    }
+
+    async fn pull_all(&self) -> Result<u64, ReplicationError> {
+        let mut total = 0;
+        loop {
+            let received = self.pull().await?;
+            total += received;
+            if received < self.config.page_size as u64 {
+                return Ok(total);
+            }
+        }
+    }
+
+    /// Follow the primary until divergence stops replication
+    ///
+    /// Failures are retried with exponential backoff. The standby is checked
+    /// on the first connection and again after every failure.
+    pub async fn run(&self) {
+        let mut checked = false;
+        let mut backoff = self.config.poll_interval;
+        loop {
+            let pulled = if checked { self.pull_all().await } else { self.catch_up().await };
+            match pulled {
+                Ok(_) => {
+                    checked = true;
+                    backoff = self.config.poll_interval;
+                    tokio::time::sleep(self.config.poll_interval).await;
+                }
+                Err(e @ ReplicationError::Diverged(_)) => {
+                    error!("{}; replication stopped", e);
+                    self.update_status(|s| {
+                        s.last_error = Some(e.to_string());
+                        s.stopped = true;
+                    });
+                    return;
+                }
+                Err(e) => {
+                    warn!("{}; retrying in {:?}", e, backoff);
+                    checked = false;
+                    self.update_status(|s| s.last_error = Some(e.to_string()));
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::{build_state, router};
+    use bms_core::SnapshotManager;
+    use bms_storage::BmsRepository;
+    use serde_json::{json, Value};
+    use std::collections::HashMap;
+
+    fn db_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("bms-replication-{}-{}.db", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path.to_str().unwrap().to_string()
+    }
+
+    /// Serve `state` on an ephemeral port, returning its base URL
+    async fn serve(state: Arc<AppState>) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, router(state)).await.unwrap() });
+        url
+    }
+
+    async fn primary(name: &str) -> (Arc<AppState>, String) {
+        let mut state = build_state(&db_path(name), false).await.unwrap();
+        Arc::get_mut(&mut state).unwrap().admin_token = Some("root".to_string());
+        let url = serve(state.clone()).await;
+        (state, url)
+    }
+
+    async fn standby(name: &str, source: &str) -> (Arc<AppState>, String) {
+        let path = db_path(name);
+        let mut state = build_state(&path, false).await.unwrap();
+        let repository = BmsRepository::new(&path).await.unwrap();
+        let facade = BmsFacade::new(repository, SnapshotManager::new(bms_core::DEFAULT_SNAPSHOT_INTERVAL))
+            .with_read_only(format!("standby of {}", source));
+        Arc::get_mut(&mut state).unwrap().facade = Arc::new(facade);
+        let url = serve(state.clone()).await;
+        (state, url)
+    }
+
+    fn config(source: &str) -> ReplicationConfig {
+        ReplicationConfig {
+            token: Some("root".to_string()),
+            poll_interval: Duration::from_millis(20),
+            page_size: 7,
+            ..ReplicationConfig::new(source)
+        }
+    }
+
+    async fn burst(client: &reqwest::Client, url: &str, round: usize) {
+        for coord in ["ALPHA", "BETA", "GAMMA", "DELTA"] {
+            for n in 0..6 {
+                let body = json!({
+                    "coord_hint": coord,
+                    "state": {"turn": format!("{}-{}-{}", coord, round, n), "related": ["ALPHA"]},
+                    "metadata": {"links": ["/related/*"], "materialize_head": coord == "BETA"},
+                });
+                let response = client.post(format!("{}/store", url)).json(&body).send().await.unwrap();
+                assert!(response.status().is_success(), "{}", response.text().await.unwrap());
+            }
+        }
+    }
+
+    /// Coordinate counts and every head chain hash and state
+    async fn contents(facade: &BmsFacade) -> (Vec<u64>, HashMap<CoordId, (Hash, Value)>) {
+        let repo = facade.repository();
+        let stats = repo.get_stats().await.unwrap();
+        let counts = vec![stats.coordinate_count, stats.delta_count, stats.snapshot_count];
+        let mut heads = HashMap::new();
+        for coord_id in repo.list_coordinate_ids().await.unwrap() {
+            let head = facade.head(&coord_id).await.unwrap().unwrap();
+            let chain_hash = head.deltas.last().unwrap().chain_hash.clone();
+            heads.insert(coord_id, (chain_hash, head.state));
+        }
+        (counts, heads)
+    }
+
+    #[tokio::test]
+    async fn test_standby_converges_with_primary() {
+        let client = reqwest::Client::new();
+        let (primary, primary_url) = primary("primary").await;
+        let (standby, standby_url) = standby("standby", &primary_url).await;
+        burst(&client, &primary_url, 0).await;
+        let snapshot = client.post(format!("{}/snapshot/GAMMA", primary_url)).send().await.unwrap();
+        assert!(snapshot.status().is_success());
+
+        // Without the admin token the oplog stays closed
+        let changes = client.get(format!("{}/replicate/changes", primary_url)).send().await.unwrap();
+        assert_eq!(changes.status(), 403);
+
+        let replicator = Arc::new(Replicator::new(standby.facade.clone(), config(&primary_url)));
+        let received = replicator.catch_up().await.unwrap();
+        assert_eq!(received as i64, primary.facade.repository().max_lsn().await.unwrap());
+        assert_eq!(contents(&standby.facade).await, contents(&primary.facade).await);
+        assert_eq!(
+            standby.facade.repository().list_links(false).await.unwrap(),
+            primary.facade.repository().list_links(false).await.unwrap()
+        );
+        let beta = CoordId("BETA".to_string());
+        assert!(standby.facade.head(&beta).await.unwrap().unwrap().replay.materialized);
+
+        // Writes to the standby are refused, over HTTP and in the facade
+        let store = json!({"coord_hint": "ALPHA", "state": {"turn": "local"}});
+        let response = client.post(format!("{}/store", standby_url)).json(&store).send().await.unwrap();
+        assert_eq!(response.status(), 403);
+        assert_eq!(response.json::<Value>().await.unwrap()["code"], "read_only");
+        let deleted = standby.facade.delete_coordinate(&beta).await;
+        assert!(matches!(deleted, Err(BmsError::ReadOnly(_))), "{:?}", deleted);
+
+        // Following picks up a second burst, a metadata patch, and a delete
+        let follower = replicator.clone();
+        let following = tokio::spawn(async move { follower.run().await });
+        burst(&client, &primary_url, 1).await;
+        let patch = HashMap::from([("project".to_string(), json!("apollo"))]);
+        primary.facade.patch_metadata(&beta, patch).await.unwrap();
+        primary.facade.delete_coordinate(&CoordId("DELTA".to_string())).await.unwrap();
+        let target = primary.facade.repository().max_lsn().await.unwrap();
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while replicator.status().applied_lsn < target {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .expect("standby caught up");
+        following.abort();
+        assert_eq!(contents(&standby.facade).await, contents(&primary.facade).await);
+        let metadata = standby.facade.repository().get_coordinate(&beta).await.unwrap().unwrap().metadata;
+        assert_eq!(metadata.unwrap()["project"], json!("apollo"));
+
+        // The position survives a restart, even once the standby's oplog is trimmed
+        standby.facade.repository().record_backup_marker(None).await.unwrap();
+        standby.facade.repository().trim_oplog(1).await.unwrap();
+        let restarted = Replicator::new(standby.facade.clone(), config(&primary_url));
+        assert_eq!(restarted.position().await.unwrap(), target);
+        assert_eq!(restarted.catch_up().await.unwrap(), 0);
+        assert!(restarted.status().last_error.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_diverged_standby_is_refused() {
+        let client = reqwest::Client::new();
+        let (_primary, primary_url) = primary("diverged-primary").await;
+        burst(&client, &primary_url, 0).await;
+
+        // A database with its own history for a replicated coordinate
+        let path = db_path("diverged-standby");
+        let repository = BmsRepository::new(&path).await.unwrap();
+        let facade = Arc::new(BmsFacade::new(repository, SnapshotManager::new(100)));
+        let params = bms_storage::StoreParams {
+            coord_id: Some(CoordId("ALPHA".to_string())),
+            state: json!({"turn": "elsewhere"}),
+            ..Default::default()
+        };
+        facade.store(params).await.unwrap();
+
+        let replicator = Replicator::new(facade.clone(), config(&primary_url));
+        let result = replicator.catch_up().await;
+        assert!(matches!(&result, Err(ReplicationError::Diverged(msg)) if msg.contains("ALPHA")), "{:?}", result);
+        assert_eq!(facade.repository().get_stats().await.unwrap().delta_count, 1);
+    }
+}
@@ -0,0 +1,273 @@
+//! Saved searches and change detection between their runs
+//!
+//! A saved search keeps a `/search` request body under a name, scoped to the
+//! bearer token that saved it. Each run compares its results with the last
+//! run's by coordinate and head chain hash, then replaces the stored set. A
+//! periodic task runs the searches that have a webhook and posts new results
+//! to it.
+
+use crate::acl::Caller;
+use crate::handlers::{bearer_token, run_search, ApiResult, AppError, SearchRequest, SearchResponseItem};
+use crate::state::AppState;
+use axum::http::HeaderMap;
+use bms_core::types::{CoordId, Hash};
+use bms_storage::models::{SavedResult, SavedSearch};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sha3::{Digest, Sha3_256};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Owner of searches saved without a bearer token
+pub const ANONYMOUS_SCOPE: &str = "anonymous";
+
+/// Owner scope of a request
+///
+/// Derived from a digest of the bearer token, so the token itself is never
+/// stored and each key only sees the searches it saved.
+pub fn owner_scope(headers: &HeaderMap) -> String {
+    match bearer_token(headers).filter(|t| !t.is_empty()) {
+        Some(token) => {
+            let digest = format!("{:x}", Sha3_256::digest(token.as_bytes()));
+            format!("key:{}", &digest[..16])
+        }
+        None => ANONYMOUS_SCOPE.to_string(),
+    }
+}
+
+/// How a result relates to the previous run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResultChange {
+    /// Not in the previous results
+    New,
+    /// In the previous results with a different head
+    Updated,
+    Unchanged,
+}
+
+impl ResultChange {
+    fn classify(previous: &[SavedResult], coord_id: &CoordId, head_hash: &Hash) -> Self {
+        match previous.iter().find(|p| &p.coord_id == coord_id) {
+            None => ResultChange::New,
+            Some(p) if &p.head_hash != head_hash => ResultChange::Updated,
+            Some(_) => ResultChange::Unchanged,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SavedRunItem {
+    pub coord_id: String,
+    pub score: f32,
+    pub head_hash: String,
+    pub change: ResultChange,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SavedRunResponse {
+    pub name: String,
+    pub ran_at: DateTime<Utc>,
+    /// `None` on the first run, when every result is new
+    pub previous_run_at: Option<DateTime<Utc>>,
+    pub results: Vec<SavedRunItem>,
+    /// Results classified as new
+    pub new: usize,
+    /// Coordinates in the previous results but not in these
+    pub dropped: Vec<String>,
+}
+
+/// Run a saved search and record its results
+///
+/// Results are filtered by the owner's read access, without the admin
+/// bypass, so a webhook never receives coordinates its owner cannot read.
+pub async fn run(app: &AppState, saved: &SavedSearch) -> ApiResult<SavedRunResponse> {
+    let req: SearchRequest = serde_json::from_value(saved.request.clone())
+        .map_err(|e| AppError::BadRequest(format!("Saved search {} is invalid: {}", saved.name, e)))?;
+    let items = run_search(app, &Caller::key(&saved.owner), &req, req.limit.unwrap_or(10), req.offset.unwrap_or(0)).await?;
+    record_run(app, saved, items).await
+}
+
+/// Classify ranked results against the previous run and store them as the
+/// new baseline
+pub async fn record_run(
+    app: &AppState,
+    saved: &SavedSearch,
+    items: Vec<SearchResponseItem>,
+) -> ApiResult<SavedRunResponse> {
+    let repo = app.facade.repository();
+    let previous = repo.get_saved_search_results(&saved.owner, &saved.name).await?;
+
+    let mut results = Vec::with_capacity(items.len());
+    let mut current = Vec::with_capacity(items.len());
+    for item in items {
+        let coord_id = CoordId(item.coord_id);
+        let Some(head) = app.facade.head(&coord_id).await? else {
+            continue; // Deleted since it was ranked
+        };
+        let head_hash = head.deltas.last().expect("head has deltas").chain_hash.clone();
+        results.push(SavedRunItem {
+            change: ResultChange::classify(&previous, &coord_id, &head_hash),
+            coord_id: coord_id.0.clone(),
+            score: item.score,
+            head_hash: head_hash.0.clone(),
+        });
+        current.push(SavedResult { coord_id, head_hash });
+    }
+
+    let dropped = previous
+        .iter()
+        .filter(|p| !current.iter().any(|c| c.coord_id == p.coord_id))
+        .map(|p| p.coord_id.0.clone())
+        .collect();
+    let ran_at = Utc::now();
+    repo.record_saved_search_run(&saved.owner, &saved.name, &current, ran_at)
+        .await?;
+
+    Ok(SavedRunResponse {
+        name: saved.name.clone(),
+        ran_at,
+        previous_run_at: saved.last_run_at,
+        new: results.iter().filter(|r| r.change == ResultChange::New).count(),
+        results,
+        dropped,
+    })
+}
+
+/// Run every saved search with a webhook on each tick and post new results
+pub async fn run_watcher(app: Arc<AppState>, mut interval: tokio::time::Interval) {
+    let client = match reqwest::Client::builder().timeout(Duration::from_secs(10)).build() {
+        Ok(client) => client,
+        Err(e) => {
+            warn!("Saved search webhooks disabled: {}", e);
+            return;
+        }
+    };
+
+    loop {
+        interval.tick().await;
+        let searches = match app.facade.repository().list_saved_searches(None).await {
+            Ok(searches) => searches,
+            Err(e) => {
+                warn!("Listing saved searches failed: {}", e);
+                continue;
+            }
+        };
+
+        for saved in searches {
+            let Some(url) = saved.webhook_url.as_deref() else {
+                continue;
+            };
+            let report = match run(&app, &saved).await {
+                Ok(report) if report.new > 0 => report,
+                Ok(_) => continue,
+                Err(e) => {
+                    warn!("Saved search {} failed: {:?}", saved.name, e);
+                    continue;
+                }
+            };
+
+            let new: Vec<&SavedRunItem> =
+                report.results.iter().filter(|r| r.change == ResultChange::New).collect();
+            let body = serde_json::json!({
+                "name": report.name,
+                "ran_at": report.ran_at,
+                "new": new,
+            });
+            match client.post(url).json(&body).send().await {
+                Ok(response) if response.status().is_success() => {
+                    info!("Notified {} of {} new result(s) for {}", url, new.len(), saved.name);
+                }
+                Ok(response) => warn!("Webhook for {} answered {}", saved.name, response.status()),
+                Err(e) => warn!("Webhook for {} failed: {}", saved.name, e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bms_storage::StoreParams;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_runs_report_changes_since_the_last_run() {
+        let db_path = std::env::temp_dir().join(format!("bms-saved-search-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&db_path);
+        let app = crate::build_state(db_path.to_str().unwrap(), false).await.unwrap();
+        let store = |coord: &str, n: i64| {
+            let app = app.clone();
+            let coord = CoordId(coord.to_string());
+            async move {
+                app.facade
+                    .store(StoreParams {
+                        coord_id: Some(coord),
+                        state: json!({"note": n}),
+                        ..Default::default()
+                    })
+                    .await
+                    .unwrap();
+            }
+        };
+        let hits = |coords: &[&str]| -> Vec<SearchResponseItem> {
+            coords
+                .iter()
+                .map(|c| SearchResponseItem { coord_id: c.to_string(), score: 0.5 })
+                .collect()
+        };
+        let changes = |report: &SavedRunResponse| -> Vec<(String, ResultChange)> {
+            report.results.iter().map(|r| (r.coord_id.clone(), r.change)).collect()
+        };
+
+        let saved = SavedSearch {
+            owner: ANONYMOUS_SCOPE.to_string(),
+            name: "nightly-review".to_string(),
+            request: json!({"query": "notes"}),
+            webhook_url: None,
+            created_at: Utc::now(),
+            last_run_at: None,
+        };
+        app.facade.repository().save_search(&saved).await.unwrap();
+        store("SAVEDA", 1).await;
+        store("SAVEDB", 2).await;
+
+        let first = record_run(&app, &saved, hits(&["SAVEDA", "SAVEDB"])).await.unwrap();
+        assert_eq!(first.new, 2);
+        assert!(first.previous_run_at.is_none());
+
+        // B gets a new head and C appears between runs; a missing coordinate is skipped
+        store("SAVEDB", 3).await;
+        store("SAVEDC", 4).await;
+        let saved = app.facade.repository().get_saved_search(ANONYMOUS_SCOPE, "nightly-review").await.unwrap().unwrap();
+        let second = record_run(&app, &saved, hits(&["SAVEDC", "SAVEDB", "SAVEDA", "MISSING"])).await.unwrap();
+        assert_eq!(
+            changes(&second),
+            [
+                ("SAVEDC".to_string(), ResultChange::New),
+                ("SAVEDB".to_string(), ResultChange::Updated),
+                ("SAVEDA".to_string(), ResultChange::Unchanged),
+            ]
+        );
+        assert_eq!((second.new, second.previous_run_at), (1, Some(first.ran_at)));
+
+        // A result that falls out is reported as dropped, and is new if it returns
+        let third = record_run(&app, &saved, hits(&["SAVEDC"])).await.unwrap();
+        assert_eq!(third.dropped, ["SAVEDA", "SAVEDB"]);
+        let fourth = record_run(&app, &saved, hits(&["SAVEDA"])).await.unwrap();
+        assert_eq!(changes(&fourth), [("SAVEDA".to_string(), ResultChange::New)]);
+    }
+
+    #[test]
+    fn test_owner_scope_is_per_key() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(owner_scope(&headers), ANONYMOUS_SCOPE);
+
+        headers.insert("authorization", "Bearer alpha".parse().unwrap());
+        let alpha = owner_scope(&headers);
+        assert!(alpha.starts_with("key:") && !alpha.contains("alpha"));
+        headers.insert("authorization", "Bearer beta".parse().unwrap());
+        assert_ne!(owner_scope(&headers), alpha);
+    }
+}
@@ -0,0 +1,351 @@
+//! Short-lived cache of search results
+//!
+//! Dashboards repeat the same search every few seconds, and each one pays for
+//! a query embedding plus a scan over every head. Entries are keyed by a hash
+//! of everything that shapes the result list and record the facade generation
+//! they were computed at, so any store or delete makes them stale without
+//! touching the cache.
+
+use crate::acl::Caller;
+use crate::handlers::SearchResponseItem;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Everything that shapes a search result list
+#[derive(Debug, Hash)]
+pub struct SearchKey<'a> {
+    /// Query text with surrounding and repeated whitespace removed
+    pub query: String,
+    pub author: Option<&'a str>,
+    pub tags: Option<&'a [String]>,
+    pub limit: usize,
+    pub offset: usize,
+    /// `f32` bits, since floats are not `Hash`
+    pub min_score: Option<u32>,
+    pub importance_weight: Option<u32>,
+    /// Metadata filter as JSON with sorted keys
+    pub metadata: Option<String>,
+    /// Facet queries as JSON with sorted keys
+    pub facets: Option<String>,
+    pub facet_weight: Option<u32>,
+    /// Filter expression as JSON with sorted keys
+    pub filter_expr: Option<String>,
+    /// Results are filtered by coordinate ACLs, so they differ per caller
+    pub key_id: Option<&'a str>,
+    pub admin: bool,
+}
+
+impl<'a> SearchKey<'a> {
+    pub fn new(
+        query: &str,
+        author: Option<&'a str>,
+        tags: Option<&'a [String]>,
+        limit: usize,
+        offset: usize,
+        min_score: Option<f32>,
+        importance_weight: Option<f32>,
+    ) -> Self {
+        Self {
+            query: query.split_whitespace().collect::<Vec<_>>().join(" "),
+            author,
+            tags,
+            limit,
+            offset,
+            min_score: min_score.map(f32::to_bits),
+            importance_weight: importance_weight.map(f32::to_bits),
+            metadata: None,
+            facets: None,
+            facet_weight: None,
+            filter_expr: None,
+            key_id: None,
+            admin: false,
+        }
+    }
+
+    pub fn with_metadata(mut self, filter: Option<&HashMap<String, Value>>) -> Self {
+        self.metadata = filter.map(|f| Value::Object(f.clone().into_iter().collect()).to_string());
+        self
+    }
+
+    pub fn with_facets(mut self, facets: Option<&HashMap<String, String>>, weight: Option<f32>) -> Self {
+        self.facets = facets.map(|f| {
+            Value::Object(f.iter().map(|(k, v)| (k.clone(), Value::String(v.clone()))).collect()).to_string()
+        });
+        self.facet_weight = weight.map(f32::to_bits);
+        self
+    }
+
+    pub fn with_filter(mut self, expr: Option<&Value>) -> Self {
+        self.filter_expr = expr.map(Value::to_string);
+        self
+    }
+
+    pub fn with_caller(mut self, caller: &'a Caller) -> Self {
+        self.key_id = Some(&caller.key_id);
+        self.admin = caller.admin;
+        self
+    }
+
+    fn digest(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+/// Counters reported in `/stats`
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchCacheStats {
+    pub entries: usize,
+    pub max_entries: usize,
+    pub ttl_secs: u64,
+    pub hits: u64,
+    pub misses: u64,
+    /// Share of lookups answered from the cache
+    pub hit_rate: f64,
+}
+
+struct Entry {
+    results: Vec<SearchResponseItem>,
+    generation: u64,
+    inserted_at: Instant,
+}
+
+/// TTL cache of search results; a `max_entries` of 0 disables it
+///
+/// Both settings can change at runtime through `resize`.
+pub struct SearchCache {
+    ttl_millis: AtomicU64,
+    max_entries: AtomicUsize,
+    entries: Mutex<HashMap<u64, Entry>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl SearchCache {
+    pub fn new(ttl: Duration, max_entries: usize) -> Self {
+        Self {
+            ttl_millis: AtomicU64::new(ttl.as_millis() as u64),
+            max_entries: AtomicUsize::new(max_entries),
+            entries: Mutex::new(HashMap::new()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.max_entries() > 0 && !self.ttl().is_zero()
+    }
+
+    /// Change the TTL and capacity, dropping the oldest entries over it
+    pub fn resize(&self, ttl: Duration, max_entries: usize) {
+        self.ttl_millis.store(ttl.as_millis() as u64, Ordering::Relaxed);
+        self.max_entries.store(max_entries, Ordering::Relaxed);
+        let mut entries = self.lock();
+        while entries.len() > max_entries {
+            let Some(oldest) = entries.iter().min_by_key(|(_, e)| e.inserted_at).map(|(digest, _)| *digest) else {
+                break;
+            };
+            entries.remove(&oldest);
+        }
+    }
+
+    fn ttl(&self) -> Duration {
+        Duration::from_millis(self.ttl_millis.load(Ordering::Relaxed))
+    }
+
+    fn max_entries(&self) -> usize {
+        self.max_entries.load(Ordering::Relaxed)
+    }
+
+    /// Cached results for `key`, or the output of `compute`
+    ///
+    /// `generation` must be read before `compute` starts so that a write
+    /// landing mid-search leaves the stored entry already stale. With
+    /// `bypass` the lookup is skipped but the fresh result is still stored.
+    pub async fn get_or_compute<F, Fut, E>(
+        &self,
+        key: &SearchKey<'_>,
+        generation: u64,
+        bypass: bool,
+        compute: F,
+    ) -> Result<Vec<SearchResponseItem>, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Vec<SearchResponseItem>, E>>,
+    {
+        if !self.is_enabled() {
+            return compute().await;
+        }
+
+        let digest = key.digest();
+        if !bypass {
+            if let Some(results) = self.get(digest, generation) {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(results);
+            }
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+
+        let results = compute().await?;
+        self.insert(digest, generation, results.clone());
+        Ok(results)
+    }
+
+    pub fn stats(&self) -> SearchCacheStats {
+        let hits = self.hits.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+        let lookups = hits + misses;
+        SearchCacheStats {
+            entries: self.lock().len(),
+            max_entries: self.max_entries(),
+            ttl_secs: self.ttl().as_secs(),
+            hits,
+            misses,
+            hit_rate: if lookups == 0 { 0.0 } else { hits as f64 / lookups as f64 },
+        }
+    }
+
+    fn get(&self, digest: u64, generation: u64) -> Option<Vec<SearchResponseItem>> {
+        let mut entries = self.lock();
+        let entry = entries.get(&digest)?;
+        if entry.generation == generation && entry.inserted_at.elapsed() < self.ttl() {
+            return Some(entry.results.clone());
+        }
+        entries.remove(&digest);
+        None
+    }
+
+    fn insert(&self, digest: u64, generation: u64, results: Vec<SearchResponseItem>) {
+        let (ttl, max_entries) = (self.ttl(), self.max_entries());
+        let mut entries = self.lock();
+        if entries.len() >= max_entries && !entries.contains_key(&digest) {
+            // Expired and stale entries go first; the oldest one if none are
+            entries.retain(|_, e| e.generation == generation && e.inserted_at.elapsed() < ttl);
+            if entries.len() >= max_entries {
+                if let Some(oldest) = entries
+                    .iter()
+                    .min_by_key(|(_, e)| e.inserted_at)
+                    .map(|(digest, _)| *digest)
+                {
+                    entries.remove(&oldest);
+                }
+            }
+        }
+        entries.insert(
+            digest,
+            Entry {
+                results,
+                generation,
+                inserted_at: Instant::now(),
+            },
+        );
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<u64, Entry>> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::Infallible;
+
+    fn items(score: f32) -> Vec<SearchResponseItem> {
+        vec![SearchResponseItem {
+            coord_id: "COORD".to_string(),
+            score,
+        }]
+    }
+
+    /// Stands in for the embedding and scan; counts how often it runs
+    async fn search(
+        cache: &SearchCache,
+        key: &SearchKey<'_>,
+        generation: u64,
+        bypass: bool,
+        embeds: &AtomicUsize,
+    ) -> Vec<SearchResponseItem> {
+        cache
+            .get_or_compute(key, generation, bypass, || async {
+                embeds.fetch_add(1, Ordering::SeqCst);
+                Ok::<_, Infallible>(items(0.5))
+            })
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_repeat_search_skips_embedding() {
+        let cache = SearchCache::new(Duration::from_secs(60), 16);
+        let embeds = AtomicUsize::new(0);
+        let key = SearchKey::new("hello  world", None, None, 10, 0, Some(0.2), None);
+
+        search(&cache, &key, 0, false, &embeds).await;
+        let same = SearchKey::new(" hello world ", None, None, 10, 0, Some(0.2), None);
+        search(&cache, &same, 0, false, &embeds).await;
+        assert_eq!(embeds.load(Ordering::SeqCst), 1);
+
+        // min_score and pagination are part of the key
+        search(&cache, &SearchKey::new("hello world", None, None, 10, 0, Some(0.3), None), 0, false, &embeds).await;
+        search(&cache, &SearchKey::new("hello world", None, None, 10, 10, Some(0.2), None), 0, false, &embeds).await;
+        assert_eq!(embeds.load(Ordering::SeqCst), 3);
+
+        // So are the caller, since ACLs filter the results, and the filter expression
+        let caller = Caller::key("key:0123456789abcdef");
+        search(&cache, &SearchKey::new("hello world", None, None, 10, 0, Some(0.2), None).with_caller(&caller), 0, false, &embeds).await;
+        assert_eq!(embeds.load(Ordering::SeqCst), 4);
+        let urgent = serde_json::json!({"tag": "urgent"});
+        search(&cache, &SearchKey::new("hello world", None, None, 10, 0, Some(0.2), None).with_filter(Some(&urgent)), 0, false, &embeds).await;
+        assert_eq!(embeds.load(Ordering::SeqCst), 5);
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.entries), (1, 5, 5));
+    }
+
+    #[tokio::test]
+    async fn test_writes_and_bypass_recompute() {
+        let cache = SearchCache::new(Duration::from_secs(60), 16);
+        let embeds = AtomicUsize::new(0);
+        let key = SearchKey::new("q", None, None, 10, 0, None, None);
+
+        search(&cache, &key, 0, false, &embeds).await;
+        search(&cache, &key, 1, false, &embeds).await;
+        search(&cache, &key, 1, true, &embeds).await;
+        assert_eq!(embeds.load(Ordering::SeqCst), 3);
+
+        search(&cache, &key, 1, false, &embeds).await;
+        assert_eq!(embeds.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_capacity_evicts_oldest() {
+        let cache = SearchCache::new(Duration::from_secs(60), 2);
+        let embeds = AtomicUsize::new(0);
+        for query in ["a", "b", "c"] {
+            search(&cache, &SearchKey::new(query, None, None, 10, 0, None, None), 0, false, &embeds).await;
+        }
+        assert_eq!(cache.stats().entries, 2);
+
+        search(&cache, &SearchKey::new("c", None, None, 10, 0, None, None), 0, false, &embeds).await;
+        search(&cache, &SearchKey::new("a", None, None, 10, 0, None, None), 0, false, &embeds).await;
+        assert_eq!(embeds.load(Ordering::SeqCst), 4);
+
+        // Shrinking keeps the newest entry; growing lets more in
+        cache.resize(Duration::from_secs(60), 1);
+        assert_eq!(cache.stats().entries, 1);
+        search(&cache, &SearchKey::new("a", None, None, 10, 0, None, None), 0, false, &embeds).await;
+        assert_eq!(embeds.load(Ordering::SeqCst), 4);
+        cache.resize(Duration::from_secs(60), 3);
+        search(&cache, &SearchKey::new("b", None, None, 10, 0, None, None), 0, false, &embeds).await;
+        assert_eq!((cache.stats().entries, cache.stats().max_entries), (2, 3));
+    }
+}
@@ -0,0 +1,1783 @@
+//! Server assembly shared by the `bms-api` binary and `bms serve`
+//!
+//! `build_state` reads the `BMS_*` environment, `router` mounts every route,
+//! and `serve` starts the background tasks and listens on TCP or a unix
+//! socket until Ctrl-C.
+
+use crate::drift::{DriftPolicy, DEFAULT_DRIFT_THRESHOLD};
+use crate::embedder::Embedder;
+use crate::config::{self, ConfigSource, RuntimeConfig};
+use crate::handlers::AppError;
+use crate::replication::{self, ReplicationConfig, Replicator};
+use crate::search_cache::SearchCache;
+use crate::state::AppState;
+use crate::{handlers, saved_search, sync, ws};
+use axum::{
+    body::Body,
+    extract::{DefaultBodyLimit, Request, State},
+    http::StatusCode,
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{delete, get, patch, post},
+    Router,
+};
+use bms_core::extract::ExtractionConfig;
+use bms_core::{
+    BmsError, Canonicalizer, CompositePolicy, ImportancePolicy, IntervalPolicy, SizePolicy, SnapshotManager,
+    DEFAULT_SNAPSHOT_INTERVAL,
+};
+use bms_storage::sampler::{IntegritySampler, SamplerConfig};
+use bms_storage::facade::{DEFAULT_CHECKPOINT_INTERVAL, DEFAULT_CHECKPOINT_TTL};
+use bms_storage::{
+    resolve_snapshot_interval, AccessTracker, BmsFacade, BmsRepository, IntegrityChecks, LinkDeletePolicy,
+};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use arc_swap::ArcSwap;
+use tower::{Layer, Service};
+use tower_http::trace::TraceLayer;
+use tracing::{error, info, warn};
+
+/// Where `serve` accepts connections
+#[derive(Debug, Clone)]
+pub enum Listen {
+    Tcp(String),
+    /// Unix domain socket; a stale socket file at the path is replaced
+    Unix(PathBuf),
+}
+
+impl std::fmt::Display for Listen {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Listen::Tcp(addr) => write!(f, "http://{}", addr),
+            Listen::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// Open the database and build the shared state from the environment
+///
+/// With `vector_search` off, or when the model fails to load, the server
+/// runs without `/search`.
+pub async fn build_state(db_path: &str, vector_search: bool) -> anyhow::Result<Arc<AppState>> {
+    // Initialize storage
+    let mut repository = BmsRepository::new(db_path).await?;
+    info!("Database initialized at {}", db_path);
+    // New delta ops are written as BMS_DELTA_ENCODING: json, or cbor with the cbor-ops feature
+    if let Ok(encoding) = std::env::var("BMS_DELTA_ENCODING") {
+        repository = repository.with_delta_encoding(encoding.parse()?);
+    }
+
+    // Initialize embedding generator
+    // Design note: vectors are search metadata, not canonical storage
+    // Embeddings computed on-demand during search, cached in memory
+    let embedder = if vector_search {
+        match Embedder::load() {
+            Ok(embedder) => {
+                info!("Embedding generator initialized");
+                Some(embedder)
+            }
+            Err(e) => {
+                warn!("Vector search unavailable: {}", e);
+                None
+            }
+        }
+    } else {
+        info!("Vector search disabled");
+        None
+    };
+
+    // The interval stored in the database, unless BMS_SNAPSHOT_INTERVAL replaces it
+    let configured_interval = std::env::var("BMS_SNAPSHOT_INTERVAL").ok().and_then(|v| v.parse().ok());
+    let snapshot_interval =
+        resolve_snapshot_interval(&repository, DEFAULT_SNAPSHOT_INTERVAL, configured_interval).await?;
+    // Heads of BMS_SNAPSHOT_MAX_BYTES or more are snapshotted on every store (0 disables)
+    let snapshot_manager = snapshot_manager(snapshot_interval, env_or("BMS_SNAPSHOT_MAX_BYTES", 0));
+
+    // Read statistics (BMS_ACCESS_STATS=0 disables tracking)
+    let access_stats_enabled = std::env::var("BMS_ACCESS_STATS")
+        .map(|v| v != "0" && !v.eq_ignore_ascii_case("false"))
+        .unwrap_or(true);
+
+    // Importance decays lazily; reads bump it when read statistics are enabled
+    let importance = ImportancePolicy {
+        half_life_secs: env_or("BMS_IMPORTANCE_HALF_LIFE_HOURS", 168.0) * 3600.0,
+        access_bump: env_or("BMS_IMPORTANCE_ACCESS_BUMP", 0.01),
+    };
+
+    // Deleting a linked-to coordinate: BMS_LINK_DELETE=warn (default) or block
+    let mut facade = BmsFacade::new(repository, snapshot_manager)
+        .with_link_policy(env_or("BMS_LINK_DELETE", LinkDeletePolicy::Warn));
+    // Stores of heads larger than BMS_STATE_SIZE_WARN_BYTES warn (0 disables)
+    let state_size_warn_bytes: u64 = env_or("BMS_STATE_SIZE_WARN_BYTES", 16 * 1024 * 1024);
+    if state_size_warn_bytes > 0 {
+        facade = facade.with_state_size_warning(state_size_warn_bytes);
+    }
+    // Long replays checkpoint every BMS_CHECKPOINT_INTERVAL deltas (0 disables)
+    facade = facade.with_reconstruction_checkpoints(
+        env_or("BMS_CHECKPOINT_INTERVAL", DEFAULT_CHECKPOINT_INTERVAL),
+        Duration::from_secs(env_or("BMS_CHECKPOINT_TTL_SECS", DEFAULT_CHECKPOINT_TTL.as_secs())),
+    );
+    let replay_budget_secs: u64 = env_or("BMS_REPLAY_BUDGET_SECS", 0);
+    if replay_budget_secs > 0 {
+        facade = facade.with_replay_budget(Duration::from_secs(replay_budget_secs));
+    }
+    // A standby (BMS_REPLICATE_FROM=<primary URL>) only writes what it replicates
+    let replicate_from = std::env::var("BMS_REPLICATE_FROM").ok().filter(|url| !url.is_empty());
+    if let Some(source) = &replicate_from {
+        facade = facade.with_read_only(format!("this instance is a standby of {}", source));
+    }
+    // BMS_STRICT=1 turns on every read-path integrity check and makes the
+    // warnings they would log errors; otherwise each check is opt-in
+    let strict = env_flag("BMS_STRICT");
+    let integrity = if strict {
+        IntegrityChecks::strict()
+    } else {
+        IntegrityChecks {
+            verify_snapshots: env_flag("BMS_VERIFY_SNAPSHOTS"),
+            verify_deltas: env_flag("BMS_VERIFY_DELTAS"),
+            warnings_are_errors: false,
+        }
+    };
+    facade = facade.with_integrity_checks(integrity);
+    let enabled = integrity.enabled();
+    info!(
+        "Integrity checks{}: {}",
+        if strict { " (strict)" } else { "" },
+        if enabled.is_empty() { "none".to_string() } else { enabled.join(", ") }
+    );
+    let coord_filter_fp_rate: f64 = env_or("BMS_COORD_FILTER_FP_RATE", 0.01);
+    if coord_filter_fp_rate > 0.0 {
+        facade = facade.with_coord_filter(coord_filter_fp_rate).await?;
+    }
+    let facade = Arc::new(facade);
+
+    // Integrity sampling (BMS_SAMPLE_SIZE=0 disables it)
+    let sampler_config = SamplerConfig {
+        sample_size: env_or("BMS_SAMPLE_SIZE", 16),
+        interval: Duration::from_secs(env_or("BMS_SAMPLE_INTERVAL_SECS", 3600u64).max(1)),
+        recent_fraction: env_or("BMS_SAMPLE_RECENT_FRACTION", 0.5),
+        concurrency: env_or("BMS_SAMPLE_CONCURRENCY", 2),
+    };
+    let sampler = (sampler_config.sample_size > 0)
+        .then(|| Arc::new(IntegritySampler::new(facade.clone(), sampler_config)));
+
+    // Settings a reload can change; invalid ones refuse to start
+    let config_source = ConfigSource::current();
+    let config = RuntimeConfig::from_settings(&std::env::vars().collect())
+        .map_err(|errors| anyhow::anyhow!(errors.join("; ")))?;
+
+    // Search result cache (BMS_SEARCH_CACHE_MAX=0 disables it)
+    let search_cache = SearchCache::new(config.search_cache_ttl, config.search_cache_max);
+
+    let replicator = replicate_from.map(|source| {
+        let config = ReplicationConfig {
+            token: std::env::var("BMS_REPLICATE_TOKEN").ok().filter(|t| !t.is_empty()),
+            poll_interval: Duration::from_secs(env_or("BMS_REPLICATE_INTERVAL_SECS", 1u64).max(1)),
+            ..ReplicationConfig::new(source)
+        };
+        Arc::new(Replicator::new(facade.clone(), config))
+    });
+
+    Ok(Arc::new(AppState {
+        facade,
+        embedding_cache: Arc::new(Mutex::new(std::collections::HashMap::new())),
+        embedder,
+        embed_drift: DriftPolicy::new(env_or("BMS_EMBED_DRIFT_THRESHOLD", DEFAULT_DRIFT_THRESHOLD)),
+        search_cache,
+        access_tracker: AccessTracker::new(access_stats_enabled).with_importance(importance),
+        admin_token: std::env::var("BMS_ADMIN_TOKEN").ok().filter(|t| !t.is_empty()),
+        sampler,
+        // In strict mode a failed integrity sample also fails the health check
+        degraded_unavailable: strict || env_or("BMS_SAMPLE_DEGRADED_503", false),
+        importance,
+        importance_floor: env_or("BMS_IMPORTANCE_FLOOR", 0.0),
+        index_metadata_keys: std::env::var("BMS_INDEX_METADATA_KEYS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|key| key.trim().to_string())
+            .filter(|key| !key.is_empty())
+            .collect(),
+        index_facets: ExtractionConfig::from_list(&std::env::var("BMS_INDEX_FACETS").unwrap_or_default()),
+        config: ArcSwap::from_pointee(config),
+        config_source,
+        replicator,
+    }))
+}
+
+/// Every API route, bound to `state`
+///
+/// Routes are grouped by the body size they accept; see `limits`. The
+/// limits are read per request, so a config reload changes them.
+pub fn router(state: Arc<AppState>) -> Router {
+    let reads = Router::new()
+        .route("/health", get(health_check))
+        .route("/whoami", get(handlers::whoami))
+        .route("/recall/:coord_id", get(handlers::recall_state))
+        .route("/recall/:coord_id/stream", get(handlers::recall_state_stream))
+        .route("/verify/:coord_id", get(handlers::verify_chain))
+        .route("/snapshot/:coord_id", post(handlers::create_snapshot))
+        .route("/coords", get(handlers::list_coordinates))
+        .route("/coords/:coord_id", delete(handlers::delete_coordinate))
+        .route("/coords/:coord_id/metadata", patch(handlers::patch_metadata))
+        .route("/coords/:coord_id/reinforce", post(handlers::reinforce_coordinate))
+        .route("/coords/:coord_id/promote", post(handlers::promote_coordinate))
+        .route("/coords/:coord_id/rollback", post(handlers::rollback_coordinate))
+        .route("/coords/:coord_id/history", get(handlers::get_history))
+        .route("/coords/:coord_id/head", get(handlers::get_head))
+        .route("/coords/:coord_id/deltas", get(handlers::get_deltas))
+        .route("/coords/:coord_id/links", get(handlers::get_links))
+        .route("/coords/:coord_id/backlinks", get(handlers::get_backlinks))
+        .route("/coords/:coord_id/children", get(handlers::get_children))
+        .route("/stats", get(handlers::get_stats))
+        .route("/stats/hot", get(handlers::get_hot_stats))
+        .route("/stats/largest", get(handlers::get_largest_stats))
+        .route("/stats/:coord_id", get(handlers::get_coord_stats))
+        .route("/admin/plan", get(handlers::get_plan))
+        .route("/admin/plan/apply", post(handlers::apply_plan_action))
+        .route("/admin/reload-config", post(handlers::reload_config))
+        .route("/ws", get(ws::ws_handler))
+        .route("/search", post(handlers::search))
+        .route("/searches", post(handlers::save_search).get(handlers::list_saved_searches))
+        .route(
+            "/searches/:name",
+            get(handlers::get_saved_search).delete(handlers::delete_saved_search),
+        )
+        .route("/searches/:name/run", get(handlers::run_saved_search))
+        .route("/replicate/changes", get(replication::changes))
+        .route("/replicate/check", post(replication::check))
+        .layer(middleware::from_fn_with_state(state.clone(), read_body_limit));
+    let stores = Router::new()
+        .route("/store", post(handlers::store_state))
+        .route("/store/group", post(handlers::store_group))
+        .layer(middleware::from_fn_with_state(state.clone(), store_body_limit));
+    // Imports stream their body and enforce the import limit themselves
+    let imports = Router::new().route("/coords/:coord_id/append-deltas", post(handlers::append_deltas));
+
+    reads
+        .merge(stores)
+        .merge(imports)
+        .layer(TraceLayer::new_for_http())
+        .with_state(state)
+}
+
+async fn read_body_limit(State(state): State<Arc<AppState>>, request: Request, next: Next) -> Response {
+    let limit = state.config.load().body_limits.read;
+    with_body_limit(limit, request, next).await
+}
+
+async fn store_body_limit(State(state): State<Arc<AppState>>, request: Request, next: Next) -> Response {
+    let (limit, strict_json) = {
+        let config = state.config.load();
+        (config.body_limits.store, config.strict_json)
+    };
+    if !strict_json {
+        return with_body_limit(limit, request, next).await;
+    }
+
+    // BMS_STRICT_JSON: serde_json would keep the last of two equal keys, so
+    // the body is read once more first. Any other problem is left to the
+    // handler's extractor to report as usual.
+    let (parts, body) = request.into_parts();
+    let Ok(bytes) = axum::body::to_bytes(body, limit).await else {
+        return AppError::TooLarge {
+            message: format!("Store bodies are limited to {} bytes", limit),
+            stream: None,
+        }
+        .into_response();
+    };
+    if let Ok(text) = std::str::from_utf8(&bytes) {
+        if let Err(e @ BmsError::DuplicateKey { .. }) = Canonicalizer::parse_strict(text) {
+            return AppError::BadRequest(e.to_string()).into_response();
+        }
+    }
+    with_body_limit(limit, Request::from_parts(parts, Body::from(bytes)), next).await
+}
+
+/// `DefaultBodyLimit` with a limit chosen per request; `Next` is always ready
+async fn with_body_limit(limit: usize, request: Request, next: Next) -> Response {
+    match DefaultBodyLimit::max(limit).layer(next).call(request).await {
+        Ok(response) => response,
+        Err(never) => match never {},
+    }
+}
+
+/// Start the background tasks and serve until Ctrl-C
+pub async fn serve(state: Arc<AppState>, listen: Listen) -> anyhow::Result<()> {
+    spawn_background_tasks(&state);
+
+    let app = router(state.clone());
+    match &listen {
+        Listen::Tcp(addr) => {
+            let listener = tokio::net::TcpListener::bind(addr).await?;
+            info!("BMS API listening on {}", listen);
+            axum::serve(listener, app)
+                .with_graceful_shutdown(shutdown_signal())
+                .await?;
+        }
+        #[cfg(unix)]
+        Listen::Unix(path) => {
+            info!("BMS API listening on {}", listen);
+            serve_unix(path, app, shutdown_signal()).await?;
+        }
+        #[cfg(not(unix))]
+        Listen::Unix(_) => anyhow::bail!("Unix sockets are not supported on this platform"),
+    }
+
+    // Final flush so counts recorded since the last tick are not lost
+    flush_access_stats(&state).await;
+    info!("BMS API stopped");
+
+    Ok(())
+}
+
+fn spawn_background_tasks(state: &Arc<AppState>) {
+    // Drop cached embeddings for deleted or renamed coordinates, and update
+    // their metadata copies
+    tokio::spawn(sync::run(
+        state.facade.subscribe_storage(),
+        state.embedding_cache.clone(),
+        state.index_metadata_keys.clone(),
+    ));
+
+    // Repair cache entries that drifted from their coordinates
+    // (BMS_INDEX_REPAIR_INTERVAL_SECS=0 disables it)
+    let index_repair_secs = env_or("BMS_INDEX_REPAIR_INTERVAL_SECS", 600u64);
+    if state.embedder.is_some() && index_repair_secs > 0 {
+        let repair_state = state.clone();
+        tokio::spawn(async move {
+            let period = Duration::from_secs(index_repair_secs);
+            let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+            loop {
+                interval.tick().await;
+                let (facade, cache) = (&repair_state.facade, &repair_state.embedding_cache);
+                match sync::repair(facade, cache, &repair_state.index_metadata_keys).await {
+                    Ok(0) => {}
+                    Ok(repaired) => warn!("Repaired {} drifted search index entries", repaired),
+                    Err(e) => warn!("Search index repair failed: {}", e),
+                }
+            }
+        });
+    }
+
+    if let Some(replicator) = state.replicator.clone() {
+        info!("Replicating from {}", replicator.status().source);
+        tokio::spawn(async move { replicator.run().await });
+    }
+
+    // Ephemeral coordinates idle for BMS_EPHEMERAL_IDLE_SECS are deleted every
+    // BMS_EPHEMERAL_GC_INTERVAL_SECS (0 disables it); a standby only replicates deletes
+    let gc_secs = env_or("BMS_EPHEMERAL_GC_INTERVAL_SECS", 600u64);
+    if state.replicator.is_none() && gc_secs > 0 {
+        let idle = Duration::from_secs(env_or("BMS_EPHEMERAL_IDLE_SECS", 24 * 3600u64));
+        let gc_state = state.clone();
+        tokio::spawn(async move {
+            let period = Duration::from_secs(gc_secs);
+            let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+            loop {
+                interval.tick().await;
+                collect_ephemeral(&gc_state, idle).await;
+            }
+        });
+        info!("Ephemeral coordinates deleted after {}s idle", idle.as_secs());
+    }
+
+    // SIGHUP reads the config file again, like POST /admin/reload-config
+    #[cfg(unix)]
+    if state.config_source.path().is_some() {
+        let reload_state = state.clone();
+        tokio::spawn(async move {
+            use tokio::signal::unix::{signal, SignalKind};
+            let mut hangups = match signal(SignalKind::hangup()) {
+                Ok(hangups) => hangups,
+                Err(e) => {
+                    warn!("Failed to listen for SIGHUP: {}", e);
+                    return;
+                }
+            };
+            while hangups.recv().await.is_some() {
+                // The outcome is logged either way
+                let _ = config::reload(&reload_state);
+            }
+        });
+    }
+
+    // Snapshots deferred by group stores
+    let snapshot_facade = state.facade.clone();
+    tokio::spawn(async move { snapshot_facade.run_snapshot_worker().await });
+
+    // Saved searches with a webhook (BMS_SAVED_SEARCH_INTERVAL_SECS=0 disables it)
+    let saved_search_secs = env_or("BMS_SAVED_SEARCH_INTERVAL_SECS", 3600u64);
+    if state.embedder.is_some() && saved_search_secs > 0 {
+        let period = Duration::from_secs(saved_search_secs);
+        let interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+        tokio::spawn(saved_search::run_watcher(state.clone(), interval));
+        info!("Saved search webhooks checked every {}s", saved_search_secs);
+    }
+
+    if let Some(sampler) = state.sampler.clone() {
+        // The first tick fires immediately, so a sample runs on boot
+        let interval = tokio::time::interval(sampler.config().interval);
+        tokio::spawn(async move {
+            sampler
+                .run(interval, |report| {
+                    error!(
+                        "Integrity sample found {} corrupted coordinates",
+                        report.failures.len()
+                    );
+                })
+                .await;
+        });
+        info!("Integrity sampling enabled");
+    }
+
+    if state.access_tracker.is_enabled() {
+        let access_flush_secs: u64 = env_or("BMS_ACCESS_FLUSH_SECS", 30);
+        let flush_state = state.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(access_flush_secs.max(1)));
+            loop {
+                interval.tick().await;
+                flush_access_stats(&flush_state).await;
+            }
+        });
+        info!("Read statistics enabled (flush every {}s)", access_flush_secs);
+    }
+}
+
+/// Serve HTTP/1.1 (with upgrades, for `/ws`) on a unix socket
+///
+/// axum 0.7 only serves TCP listeners, so connections are handed to hyper
+/// directly.
+#[cfg(unix)]
+async fn serve_unix(
+    path: &std::path::Path,
+    app: Router,
+    shutdown: impl std::future::Future<Output = ()>,
+) -> anyhow::Result<()> {
+    use hyper_util::rt::TokioIo;
+    use hyper_util::service::TowerToHyperService;
+
+    if path.exists() {
+        std::fs::remove_file(path)?;
+    }
+    let listener = tokio::net::UnixListener::bind(path)?;
+    tokio::pin!(shutdown);
+
+    loop {
+        let socket = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((socket, _)) => socket,
+                Err(e) => {
+                    warn!("Failed to accept unix socket connection: {}", e);
+                    continue;
+                }
+            },
+            _ = &mut shutdown => break,
+        };
+        let service = TowerToHyperService::new(app.clone());
+        tokio::spawn(async move {
+            let connection = hyper::server::conn::http1::Builder::new()
+                .serve_connection(TokioIo::new(socket), service)
+                .with_upgrades();
+            if let Err(e) = connection.await {
+                tracing::debug!("Unix socket connection ended: {}", e);
+            }
+        });
+    }
+
+    let _ = std::fs::remove_file(path);
+    Ok(())
+}
+
+async fn shutdown_signal() {
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        warn!("Failed to listen for shutdown signal: {}", e);
+        std::future::pending::<()>().await;
+    }
+    info!("Shutdown signal received");
+}
+
+/// Delete idle ephemeral coordinates, counting reads not yet flushed
+async fn collect_ephemeral(state: &AppState, idle: Duration) {
+    flush_access_stats(state).await;
+    match state.facade.collect_ephemeral(idle, chrono::Utc::now()).await {
+        Ok(deleted) => {
+            for coord_id in deleted {
+                warn!(target: "bms::audit", coord_id = %coord_id, "idle ephemeral coordinate deleted");
+            }
+        }
+        Err(e) => warn!("Ephemeral coordinate collection failed: {}", e),
+    }
+}
+
+async fn flush_access_stats(state: &AppState) {
+    if let Err(e) = state.access_tracker.flush(state.facade.repository()).await {
+        warn!("Failed to flush read statistics: {}", e);
+    }
+}
+
+async fn health_check(
+    State(state): State<Arc<AppState>>,
+) -> (StatusCode, axum::response::Json<serde_json::Value>) {
+    let last_sample_ok = state.sampler.as_ref().and_then(|s| s.last_sample_ok());
+    let integrity = state.facade.integrity_checks();
+    let degraded = last_sample_ok == Some(false);
+
+    let status = if degraded && state.degraded_unavailable {
+        StatusCode::SERVICE_UNAVAILABLE
+    } else {
+        StatusCode::OK
+    };
+
+    (
+        status,
+        axum::response::Json(serde_json::json!({
+            "status": if degraded { "degraded" } else { "ok" },
+            "version": bms_core::VERSION,
+            "last_sample_ok": last_sample_ok,
+            "replication": state.replicator.as_ref().map(|r| r.status()),
+            "capabilities": {
+                "vector_search": state.embedder.is_some(),
+                "strict": integrity.is_strict(),
+                "integrity_checks": integrity.enabled(),
+            },
+        })),
+    )
+}
+
+/// Whether a flag variable is set to anything but empty, `0`, `false`,
+/// `no`, or `off`
+pub(crate) fn env_flag(name: &str) -> bool {
+    std::env::var(name)
+        .map(|v| !matches!(v.to_ascii_lowercase().as_str(), "" | "0" | "false" | "no" | "off"))
+        .unwrap_or(false)
+}
+
+/// Snapshot every `interval`th delta, and every head of `max_bytes` or more
+/// unless that is 0
+fn snapshot_manager(interval: u32, max_bytes: usize) -> SnapshotManager {
+    if max_bytes == 0 {
+        return SnapshotManager::new(interval);
+    }
+    SnapshotManager::new_with_policy(Box::new(CompositePolicy(vec![
+        Box::new(IntervalPolicy(interval)),
+        Box::new(SizePolicy { max_reconstruction_bytes: max_bytes }),
+    ])))
+}
+
+/// Parse an environment variable, falling back to `default` when unset or invalid
+pub(crate) fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limits::BodyLimits;
+    use axum::body::Body;
+    use bms_core::policy::OpKind;
+    use axum::http::Request;
+    use tower::ServiceExt;
+
+    async fn state(name: &str) -> Arc<AppState> {
+        let db_path = std::env::temp_dir().join(format!("bms-server-{}-{}.db", name, std::process::id()));
+        let _ = std::fs::remove_file(&db_path);
+        build_state(db_path.to_str().unwrap(), false).await.unwrap()
+    }
+
+    async fn call(app: Router, request: Request<Body>) -> (StatusCode, serde_json::Value) {
+        let response = app.oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_search_without_embedder_is_a_capability_error() {
+        let app = router(state("search").await);
+
+        let (status, health) = call(app.clone(), Request::get("/health").body(Body::empty()).unwrap()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(health["capabilities"]["vector_search"], false);
+
+        let search = Request::post("/search")
+            .header("content-type", "application/json")
+            .body(Body::from(r#"{"query": "anything"}"#))
+            .unwrap();
+        let (status, body) = call(app.clone(), search).await;
+        assert_eq!(status, StatusCode::NOT_IMPLEMENTED);
+        assert!(body["error"].as_str().unwrap().contains("Vector search unavailable"));
+
+        // Everything else still works
+        let store = Request::post("/store")
+            .header("content-type", "application/json")
+            .body(Body::from(r#"{"coord_hint": "EDGE", "state": {"n": 1}}"#))
+            .unwrap();
+        let (status, body) = call(app, store).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["head"]["seq"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_created_at_override_requires_admin() {
+        let mut state = state("override").await;
+        Arc::get_mut(&mut state).unwrap().admin_token = Some("secret".to_string());
+        let app = router(state);
+        let store = |token: Option<&str>, body: &str| {
+            let mut request = Request::post("/store").header("content-type", "application/json");
+            if let Some(token) = token {
+                request = request.header("authorization", format!("Bearer {}", token));
+            }
+            request.body(Body::from(body.to_string())).unwrap()
+        };
+        let backdated = r#"{"coord_hint": "IMPORT", "state": {"n": 1}, "created_at_override": "2020-01-01T00:00:00Z"}"#;
+
+        let (status, body) = call(app.clone(), store(None, backdated)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body["code"], "created_at_override_forbidden");
+        let (status, _) = call(app.clone(), store(Some("wrong"), backdated)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, _) = call(app.clone(), store(Some("secret"), backdated)).await;
+        assert_eq!(status, StatusCode::OK);
+        let earlier = r#"{"coord_hint": "IMPORT", "state": {"n": 2}, "created_at_override": "2019-01-01T00:00:00Z"}"#;
+        let (status, body) = call(app.clone(), store(Some("secret"), earlier)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body["error"].as_str().unwrap().contains("before the head"));
+
+        let group = Request::post("/store/group")
+            .header("content-type", "application/json")
+            .body(Body::from(format!(r#"{{"items": [{}]}}"#, backdated)))
+            .unwrap();
+        let (status, body) = call(app, group).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body["code"], "created_at_override_forbidden");
+    }
+
+    #[tokio::test]
+    async fn test_generated_id_collision_retries_with_nonce() {
+        let mut state = state("collision").await;
+        Arc::get_mut(&mut state).unwrap().admin_token = Some("secret".to_string());
+        let app = router(state);
+        let at = chrono::DateTime::parse_from_rfc3339("2020-01-01T00:00:00Z").unwrap().to_utc();
+        let generated = |state: &serde_json::Value, nonce: u32| {
+            let id = match nonce {
+                0 => bms_core::CoordinateGenerator::generate(state, &at, bms_core::HashAlgorithm::Sha3_256),
+                n => bms_core::CoordinateGenerator::generate_with_nonce(state, &at, n),
+            };
+            id.unwrap().0
+        };
+        let store = |state: &serde_json::Value| {
+            let body = serde_json::json!({"state": state, "created_at_override": at});
+            keyed("POST", "/store", Some("secret"), Some(body))
+        };
+        let squat = |coord_hint: String| {
+            let body = serde_json::json!({"coord_hint": coord_hint, "state": {"squatter": coord_hint}});
+            keyed("POST", "/store", None, Some(body))
+        };
+
+        // Another state already sits at the ID the store generates
+        let incoming = serde_json::json!({"n": "incoming"});
+        let squatter = generated(&incoming, 0);
+        assert_eq!(call(app.clone(), squat(squatter.clone())).await.0, StatusCode::OK);
+        let (status, body) = call(app.clone(), store(&incoming)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["coord_id"], generated(&incoming, 1));
+        let (_, recalled) = call(app.clone(), keyed("GET", &format!("/recall/{}", squatter), None, None)).await;
+        assert_eq!(recalled["state"], serde_json::json!({"squatter": squatter}));
+
+        // Repeating the store finds the chain it began
+        let (status, body) = call(app.clone(), store(&incoming)).await;
+        assert_eq!((status, &body["coord_id"]), (StatusCode::OK, &generated(&incoming, 1).into()));
+
+        // With every nonce taken the store answers 409
+        let crowded = serde_json::json!({"n": "crowded"});
+        for nonce in 0..=bms_storage::facade::MAX_COLLISION_RETRIES {
+            assert_eq!(call(app.clone(), squat(generated(&crowded, nonce))).await.0, StatusCode::OK);
+        }
+        let (status, body) = call(app, store(&crowded)).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert!(body["error"].as_str().unwrap().contains("Collision"));
+    }
+
+    #[tokio::test]
+    async fn test_links_and_backlinks() {
+        let mut state = state("links").await;
+        Arc::get_mut(&mut state).unwrap().admin_token = Some("secret".to_string());
+        let app = router(state);
+        let id = |seed: &str| bms_core::CoordinateGenerator::generate_now(&serde_json::json!(seed)).unwrap().0;
+        let (target, hidden) = (id("target"), id("hidden"));
+        let post = |uri: &str, body: serde_json::Value| {
+            Request::post(uri)
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+        let get = |uri: String| Request::get(uri).body(Body::empty()).unwrap();
+
+        call(app.clone(), post("/store", serde_json::json!({"coord_hint": target, "state": {"n": 1}}))).await;
+        let source = serde_json::json!({
+            "coord_hint": "SOURCE",
+            "state": {"related": [target], "private": hidden},
+            "metadata": {"links": ["/related/*", "/private"], "redact": ["/private"]}
+        });
+        let (status, _) = call(app.clone(), post("/store", source)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // The redacted reference is not listed
+        let (status, body) = call(app.clone(), get("/coords/SOURCE/links".to_string())).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["links"], serde_json::json!([{"pointer": "/related/0", "to_coord": target, "dangling": false}]));
+        let (_, body) = call(app.clone(), get(format!("/coords/{}/backlinks", target))).await;
+        assert_eq!(body["backlinks"], serde_json::json!([{"from_coord": "SOURCE", "pointer": "/related/0"}]));
+
+        let delete = Request::delete(format!("/coords/{}", target))
+            .header("authorization", "Bearer secret")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(app.clone().oneshot(delete).await.unwrap().status(), StatusCode::NO_CONTENT);
+        let (_, body) = call(app.clone(), get("/coords/SOURCE/links".to_string())).await;
+        assert_eq!(body["links"][0]["dangling"], true);
+
+        let (status, _) = call(app, get("/coords/MISSING/links".to_string())).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_history_attributes_ops() {
+        let app = router(state("history").await);
+        let store = |body: serde_json::Value| {
+            Request::post("/store")
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+        let get = |uri: &str| Request::get(uri).body(Body::empty()).unwrap();
+
+        let (status, _) = call(
+            app.clone(),
+            store(serde_json::json!({
+                "coord_hint": "COWRITE",
+                "state": {"a": 1},
+                "author": "cli",
+                "metadata": {"redact": ["/c"]}
+            })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let cowritten = serde_json::json!({
+            "coord_hint": "COWRITE",
+            "state": {"a": 2, "b": 1, "c": 1},
+            "author": "planner",
+            "op_authors": ["planner", "executor", "planner"]
+        });
+        let (status, _) = call(app.clone(), store(cowritten)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // One author per op, or the store is rejected
+        let short = serde_json::json!({"coord_hint": "COWRITE", "state": {"a": 3}, "op_authors": ["a", "b"]});
+        let (status, body) = call(app.clone(), store(short)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body["error"].as_str().unwrap().ends_with("but 2 op authors"));
+
+        let (status, body) = call(app.clone(), get("/coords/COWRITE/history")).await;
+        assert_eq!(status, StatusCode::OK);
+        let summaries: Vec<&str> = body["entries"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| e["authorship"].as_str().unwrap())
+            .collect();
+        assert_eq!(summaries, ["1 op by cli", "2 ops by planner, 1 by executor"]);
+        // Paths only, and none under a redacted field
+        let changes = &body["entries"][1]["changes"];
+        assert_eq!(changes["added_paths"], serde_json::json!(["/b"]));
+        assert_eq!(changes["modified_paths"], serde_json::json!(["/a"]));
+        assert_eq!(changes["text_summary"], "1 field added, 0 removed, 1 modified");
+
+        let (_, body) = call(app.clone(), get("/coords/COWRITE/history?author=executor")).await;
+        assert_eq!(body["entries"].as_array().unwrap().len(), 1);
+        assert_eq!(body["entries"][0]["seq"], 2);
+        let (status, _) = call(app, get("/coords/MISSING/history")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_recall_by_alias() {
+        let app = router(state("recall-alias").await);
+        let body = serde_json::json!({"state": {"named": true}});
+        let (_, stored) = call(app.clone(), keyed("POST", "/store", None, Some(body))).await;
+        let coord_id = bms_core::types::CoordId(stored["coord_id"].as_str().unwrap().to_string());
+        let alias = bms_core::CoordinateGenerator::generate_alias(&coord_id);
+
+        for uri in [format!("/recall/{}", alias), format!("/recall/{}/stream", alias)] {
+            let (status, body) = call(app.clone(), keyed("GET", &uri, None, None)).await;
+            assert_eq!(status, StatusCode::OK, "{}", uri);
+            assert_eq!(body["coord_id"].as_str(), Some(coord_id.0.as_str()));
+            assert_eq!(body["state"], serde_json::json!({"named": true}));
+        }
+        let (status, _) = call(app, keyed("GET", "/recall/no-such-alias", None, None)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_recall_at_a_delta() {
+        let app = router(state("recall-at").await);
+        for state in [serde_json::json!({"v": 1}), serde_json::json!({"v": 2, "w": 1}), serde_json::json!({"v": 3})] {
+            let body = serde_json::json!({"coord_hint": "HISTORIC", "state": state});
+            assert_eq!(call(app.clone(), keyed("POST", "/store", None, Some(body))).await.0, StatusCode::OK);
+        }
+        let (_, body) = call(app.clone(), keyed("GET", "/coords/HISTORIC/history", None, None)).await;
+        let ids: Vec<String> =
+            body["entries"].as_array().unwrap().iter().map(|e| e["delta_id"].as_str().unwrap().to_string()).collect();
+
+        let (status, body) =
+            call(app.clone(), keyed("GET", &format!("/recall/HISTORIC?delta_id={}", ids[1]), None, None)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["state"], serde_json::json!({"v": 2, "w": 1}));
+        assert_eq!(body["delta_count"], 2);
+        let (_, body) = call(app.clone(), keyed("GET", "/recall/HISTORIC", None, None)).await;
+        assert_eq!((body["state"].clone(), body["delta_count"].clone()), (serde_json::json!({"v": 3}), 3.into()));
+
+        let (status, body) = call(app, keyed("GET", "/recall/HISTORIC?delta_id=0123", None, None)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["error"], "Delta 0123 is not in the chain of HISTORIC");
+    }
+
+    #[tokio::test]
+    async fn test_rollback_restores_an_earlier_state() {
+        let app = router(state("rollback").await);
+        for state in [serde_json::json!({"v": 1}), serde_json::json!({"v": 2, "w": [1]}), serde_json::json!({"v": 3})] {
+            let body = serde_json::json!({"coord_hint": "UNDO", "state": state});
+            assert_eq!(call(app.clone(), keyed("POST", "/store", None, Some(body))).await.0, StatusCode::OK);
+        }
+
+        let body = serde_json::json!({"steps": 2, "author": "ops"});
+        let (status, body) = call(app.clone(), keyed("POST", "/coords/UNDO/rollback", None, Some(body))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["head"]["seq"], 4);
+        let (_, body) = call(app.clone(), keyed("GET", "/recall/UNDO", None, None)).await;
+        assert_eq!(body["state"], serde_json::json!({"v": 1}));
+
+        let (status, _) =
+            call(app.clone(), keyed("POST", "/coords/UNDO/rollback", None, Some(serde_json::json!({"steps": 9})))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = call(app, keyed("POST", "/coords/GONE/rollback", None, Some(serde_json::json!({})))).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_expected_head_hash_rejects_an_interleaved_writer() {
+        let app = router(state("head-hash").await);
+        let store = |body: serde_json::Value| call(app.clone(), keyed("POST", "/store", None, Some(body)));
+        let (_, body) = store(serde_json::json!({"coord_hint": "SHARED", "state": {"count": 1}})).await;
+        let read = body["head"]["chain_hash"].clone();
+
+        // Both writers read the same head; the second to store loses
+        let first = serde_json::json!({"coord_hint": "SHARED", "state": {"count": 2}, "expected_head_hash": read});
+        let (status, body) = store(first).await;
+        assert_eq!(status, StatusCode::OK);
+        let moved = body["head"]["chain_hash"].clone();
+        let second =
+            serde_json::json!({"coord_hint": "SHARED", "state": {"count": 1, "note": "b"}, "expected_head_hash": read});
+        let (status, body) = store(second).await;
+        assert_eq!(status, StatusCode::CONFLICT, "{}", body);
+        let (_, body) = call(app.clone(), keyed("GET", "/recall/SHARED", None, None)).await;
+        assert_eq!(body["state"], serde_json::json!({"count": 2}));
+
+        // Retrying against the new head succeeds
+        let retry =
+            serde_json::json!({"coord_hint": "SHARED", "state": {"count": 2, "note": 2}, "expected_head_hash": moved});
+        assert_eq!(store(retry).await.0, StatusCode::OK);
+
+        let both = serde_json::json!({
+            "coord_hint": "SHARED", "state": {}, "expected_head_hash": moved, "expected_head_delta_id": "x"
+        });
+        assert_eq!(store(both).await.0, StatusCode::BAD_REQUEST);
+        let group = serde_json::json!({"items": [{"coord_hint": "SHARED", "state": {}, "expected_head_hash": read}]});
+        let (status, _) = call(app.clone(), keyed("POST", "/store/group", None, Some(group))).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn test_largest_heads_are_reported() {
+        let app = router(state("largest").await);
+        let get = |uri: &str| Request::get(uri).body(Body::empty()).unwrap();
+        for (coord, len) in [("TINY", 1), ("HUGE", 3000), ("MEDIUM", 200)] {
+            let body = serde_json::json!({"coord_hint": coord, "state": {"text": "x".repeat(len)}});
+            let request = Request::post("/store")
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap();
+            let (status, _) = call(app.clone(), request).await;
+            assert_eq!(status, StatusCode::OK);
+        }
+
+        let (status, body) = call(app.clone(), get("/stats/largest?limit=2&humanize=true")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body[0]["coord_id"], "HUGE");
+        assert_eq!(body[0]["state_bytes"], 3011);
+        assert_eq!(body[0]["state_bytes_human"], "2.9 KiB");
+        assert_eq!(body[1]["coord_id"], "MEDIUM");
+        assert_eq!(body.as_array().unwrap().len(), 2);
+
+        // One coordinate: one delta costs about what storing the state would
+        let (status, body) = call(app.clone(), get("/stats/HUGE?humanize=true")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!((body["delta_count"].as_u64(), body["original_bytes"].as_u64()), (Some(1), Some(3011)));
+        assert_eq!(body["original_bytes_human"], "2.9 KiB");
+        assert!(body["compressed_bytes"].as_u64().unwrap() > 3000);
+        assert_eq!((body["ops"]["add_count"].as_u64(), body["ops"]["remove_count"].as_u64()), (Some(1), Some(0)));
+        assert!(body["ops"]["total_bytes_human"].as_str().unwrap().ends_with(" KiB"));
+        assert_eq!(call(app.clone(), get("/stats/NOWHERE")).await.0, StatusCode::NOT_FOUND);
+
+        let (_, body) = call(app.clone(), get("/coords?sort=size&limit=3")).await;
+        let order: Vec<&str> = body.as_array().unwrap().iter().map(|c| c["id"].as_str().unwrap()).collect();
+        assert_eq!(order, ["HUGE", "MEDIUM", "TINY"]);
+        assert_eq!(body[2]["state_bytes"], 12);
+        let (status, _) = call(app, get("/coords?sort=weight")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_body_limits_per_route_class() {
+        let mut state = state("limits").await;
+        let limits = BodyLimits {
+            read: 1024,
+            store: 4096,
+            import: Some(16 * 1024),
+            recall_response: Some(2048),
+        };
+        let app_state = Arc::get_mut(&mut state).unwrap();
+        app_state.config.store(Arc::new(RuntimeConfig { body_limits: limits, ..RuntimeConfig::default() }));
+        app_state.admin_token = Some("root".to_string());
+        let app = router(state);
+        let status = |request: Request<Body>| {
+            let app = app.clone();
+            async move { app.oneshot(request).await.unwrap().status() }
+        };
+        let post = |uri: &str, content_type: &str, body: String| {
+            Request::post(uri)
+                .header("authorization", "Bearer root")
+                .header("content-type", content_type)
+                .body(Body::from(body))
+                .unwrap()
+        };
+        let store = |len: usize| serde_json::json!({"coord_hint": "LIMITED", "state": {"pad": "x".repeat(len)}}).to_string();
+        let search = |len: usize| serde_json::json!({"query": "x".repeat(len)}).to_string();
+
+        // Search and other small requests stop at the read limit
+        assert_eq!(status(post("/search", "application/json", search(900))).await, StatusCode::NOT_IMPLEMENTED);
+        assert_eq!(status(post("/search", "application/json", search(1100))).await, StatusCode::PAYLOAD_TOO_LARGE);
+
+        // Stores may exceed it up to the store limit
+        assert_eq!(status(post("/store", "application/json", store(1500))).await, StatusCode::OK);
+        assert_eq!(status(post("/store", "application/json", store(5000))).await, StatusCode::PAYLOAD_TOO_LARGE);
+
+        // Recalls above the response limit point at the streaming endpoint
+        let (code, body) = call(app.clone(), Request::get("/recall/LIMITED").body(Body::empty()).unwrap()).await;
+        assert_eq!(code, StatusCode::OK);
+        assert_eq!(body["state"]["pad"].as_str().unwrap().len(), 1500);
+        assert_eq!(status(post("/store", "application/json", store(3000))).await, StatusCode::OK);
+        let (code, body) = call(app.clone(), Request::get("/recall/LIMITED").body(Body::empty()).unwrap()).await;
+        assert_eq!(code, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(body["stream"], "/recall/LIMITED/stream");
+        let (code, body) = call(app.clone(), Request::get("/recall/LIMITED/stream").body(Body::empty()).unwrap()).await;
+        assert_eq!(code, StatusCode::OK);
+        assert_eq!(body["state"]["pad"].as_str().unwrap().len(), 3000);
+        assert_eq!(body["delta_count"], 2);
+
+        // Streamed imports are limited in total, and each line by the store limit
+        let get_deltas = Request::get("/coords/LIMITED/deltas").header("authorization", "Bearer root");
+        let (_, deltas) = call(app.clone(), get_deltas.body(Body::empty()).unwrap()).await;
+        let lines: Vec<String> = deltas["deltas"].as_array().unwrap().iter().map(|d| d.to_string()).collect();
+        let ndjson = format!("{{\"metadata\": null}}\n{}\n", lines.join("\n"));
+        let (code, body) = call(app.clone(), post("/coords/LIMITED/append-deltas", "application/x-ndjson", ndjson)).await;
+        assert_eq!(code, StatusCode::OK, "{}", body);
+        assert_eq!((body["appended"].clone(), body["already_present"].clone()), (0.into(), 2.into()));
+        let oversized = format!("{}\n", lines[0]).repeat(12);
+        let append = post("/coords/LIMITED/append-deltas", "application/x-ndjson", oversized);
+        assert_eq!(status(append).await, StatusCode::PAYLOAD_TOO_LARGE);
+        let append = post("/coords/LIMITED/append-deltas", "application/x-ndjson", "x".repeat(4100));
+        assert_eq!(status(append).await, StatusCode::PAYLOAD_TOO_LARGE);
+
+        // Buffered JSON imports stop at the store limit
+        let json = serde_json::json!({"deltas": deltas["deltas"]}).to_string();
+        assert!(json.len() > limits.store);
+        let append = post("/coords/LIMITED/append-deltas", "application/json", json);
+        assert_eq!(status(append).await, StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn test_delta_policy_limits_stores() {
+        let mut state = state("policy").await;
+        let policy = bms_core::DeltaPolicy {
+            max_ops: Some(2),
+            max_state_bytes: Some(200),
+            allowed_ops: [OpKind::Add, OpKind::Replace].into(),
+            ..Default::default()
+        };
+        let app_state = Arc::get_mut(&mut state).unwrap();
+        app_state.config.store(Arc::new(RuntimeConfig { delta_policy: policy, ..RuntimeConfig::default() }));
+        let app = router(state);
+        let store = |state: serde_json::Value| {
+            keyed("POST", "/store", None, Some(serde_json::json!({"coord_hint": "POLICED", "state": state})))
+        };
+
+        let (code, _) = call(app.clone(), store(serde_json::json!({"a": 1, "b": 2}))).await;
+        assert_eq!(code, StatusCode::OK);
+        let (code, body) = call(app.clone(), store(serde_json::json!({"a": 1}))).await;
+        assert_eq!((code, &body["code"]), (StatusCode::UNPROCESSABLE_ENTITY, &serde_json::json!("policy_violation")));
+        assert!(body["error"].as_str().unwrap().contains("remove op"), "{}", body);
+        let (code, body) = call(app.clone(), store(serde_json::json!({"a": 2, "b": 3, "c": 4}))).await;
+        assert_eq!(code, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(body["error"].as_str().unwrap().contains("3 ops"), "{}", body);
+
+        // An oversized state is refused before it is diffed, in groups too
+        let (code, _) = call(app.clone(), store(serde_json::json!({"a": "x".repeat(300), "b": 2}))).await;
+        assert_eq!(code, StatusCode::PAYLOAD_TOO_LARGE);
+        let group = serde_json::json!({"items": [
+            {"coord_hint": "POLICED", "state": {"a": 5, "b": 2}},
+            {"coord_hint": "OTHER", "state": {"pad": "x".repeat(300)}},
+        ]});
+        let (code, _) = call(app.clone(), keyed("POST", "/store/group", None, Some(group))).await;
+        assert_eq!(code, StatusCode::PAYLOAD_TOO_LARGE);
+
+        let (_, head) = call(app, keyed("GET", "/recall/POLICED", None, None)).await;
+        assert_eq!(head["state"], serde_json::json!({"a": 1, "b": 2}));
+    }
+
+    #[tokio::test]
+    async fn test_strict_json_rejects_duplicate_keys() {
+        let mut state = state("strict-json").await;
+        let config = RuntimeConfig::from_settings(&std::collections::HashMap::from([
+            ("BMS_STRICT_JSON".to_string(), "1".to_string()),
+            ("BMS_READ_BODY_LIMIT".to_string(), "1024".to_string()),
+            ("BMS_STORE_BODY_LIMIT".to_string(), "2048".to_string()),
+        ]))
+        .unwrap();
+        Arc::get_mut(&mut state).unwrap().config.store(Arc::new(config));
+        let app = router(state.clone());
+        let post = |uri: &str, body: String| {
+            Request::post(uri).header("content-type", "application/json").body(Body::from(body)).unwrap()
+        };
+        let duplicated = r#"{"coord_hint": "STRICT", "state": {"role": "user", "role": "admin"}}"#;
+
+        let (code, body) = call(app.clone(), post("/store", duplicated.to_string())).await;
+        assert_eq!(code, StatusCode::BAD_REQUEST);
+        assert!(body["error"].as_str().unwrap().contains(r#""role" in the object at "/state""#), "{}", body);
+        let group = format!(r#"{{"items": [{}]}}"#, duplicated);
+        assert_eq!(call(app.clone(), post("/store/group", group)).await.0, StatusCode::BAD_REQUEST);
+
+        // Other bodies are handled as without the flag
+        let single = r#"{"coord_hint": "STRICT", "state": {"role": "user"}}"#;
+        let (code, _) = call(app.clone(), post("/store", single.to_string())).await;
+        assert_eq!(code, StatusCode::OK);
+        let status = |request: Request<Body>| {
+            let app = app.clone();
+            async move { app.oneshot(request).await.unwrap().status() }
+        };
+        assert_eq!(status(post("/store", "{".to_string())).await, StatusCode::BAD_REQUEST);
+        let large = serde_json::json!({"coord_hint": "STRICT", "state": {"pad": "x".repeat(3000)}}).to_string();
+        assert_eq!(status(post("/store", large)).await, StatusCode::PAYLOAD_TOO_LARGE);
+
+        // Without it the last key wins
+        state.config.store(Arc::new(RuntimeConfig::default()));
+        let (code, _) = call(app.clone(), post("/store", duplicated.to_string())).await;
+        assert_eq!(code, StatusCode::OK);
+        let (_, head) = call(app, keyed("GET", "/recall/STRICT", None, None)).await;
+        assert_eq!(head["state"], serde_json::json!({"role": "admin"}));
+    }
+
+    #[tokio::test]
+    async fn test_config_reload_applies_only_a_valid_file() {
+        let path = std::env::temp_dir().join(format!("bms-server-reload-{}.env", std::process::id()));
+        std::fs::write(&path, "# tighter reads\nBMS_READ_BODY_LIMIT=1024\n").unwrap();
+        let mut state = state("reload").await;
+        let app_state = Arc::get_mut(&mut state).unwrap();
+        app_state.admin_token = Some("root".to_string());
+        app_state.config_source = ConfigSource::current().with_file(&path);
+        let app = router(state.clone());
+        let post = |uri: &str, token: &str, body: String| {
+            Request::post(uri)
+                .header("authorization", format!("Bearer {}", token))
+                .header("content-type", "application/json")
+                .body(Body::from(body))
+                .unwrap()
+        };
+        let search = || post("/search", "", serde_json::json!({"query": "x".repeat(2000)}).to_string());
+        let reload = || post("/admin/reload-config", "root", String::new());
+        let search_status = || {
+            let app = app.clone();
+            async move { app.oneshot(search()).await.unwrap().status() }
+        };
+
+        // The default read limit lets a 2 KB search through
+        assert_eq!(search_status().await, StatusCode::NOT_IMPLEMENTED);
+        let (status, _) = call(app.clone(), post("/admin/reload-config", "wrong", String::new())).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, body) = call(app.clone(), reload()).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body, serde_json::json!({"changed": ["BMS_READ_BODY_LIMIT"], "restart_required": []}));
+        assert_eq!(search_status().await, StatusCode::PAYLOAD_TOO_LARGE);
+
+        // One bad value rejects the whole file; the running limit stays
+        std::fs::write(&path, "BMS_READ_BODY_LIMIT=4096\nBMS_SEARCH_CACHE_MAX=lots\nBMS_LOG=info,[\n").unwrap();
+        let (status, body) = call(app.clone(), reload()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let error = body["error"].as_str().unwrap();
+        assert!(error.contains("BMS_SEARCH_CACHE_MAX") && error.contains("BMS_LOG"), "{}", error);
+        assert_eq!(search_status().await, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(state.config.load().search_cache_max, 256);
+
+        // Fixed, it applies; settings read only at startup are listed instead
+        std::fs::write(&path, "BMS_READ_BODY_LIMIT=4096\nBMS_SEARCH_CACHE_MAX=8\nBMS_DB_PATH=/elsewhere.db\n").unwrap();
+        let (status, body) = call(app.clone(), reload()).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["changed"], serde_json::json!(["BMS_READ_BODY_LIMIT", "BMS_SEARCH_CACHE_MAX"]));
+        assert_eq!(body["restart_required"], serde_json::json!(["BMS_DB_PATH"]));
+        assert_eq!(search_status().await, StatusCode::NOT_IMPLEMENTED);
+        assert_eq!(state.search_cache.stats().max_entries, 8);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_metadata_patches() {
+        let mut state = state("metadata").await;
+        Arc::get_mut(&mut state).unwrap().admin_token = Some("root".to_string());
+        let app = router(state);
+        let patch = |coord: &str, token: &str, body: serde_json::Value| {
+            Request::patch(format!("/coords/{}/metadata", coord))
+                .header("authorization", format!("Bearer {}", token))
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+        let body = serde_json::json!({"coord_hint": "PATCHME", "state": {"v": 1}, "metadata": {"source": "jira"}});
+        let store = Request::post("/store")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        assert_eq!(call(app.clone(), store).await.0, StatusCode::OK);
+
+        let (status, body) =
+            call(app.clone(), patch("PATCHME", "", serde_json::json!({"project": "apollo", "source": null}))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["metadata"], serde_json::json!({"project": "apollo"}));
+
+        // Redaction rules need the admin token; invalid rules are a bad request
+        let redact = serde_json::json!({"redact": ["/v"]});
+        assert_eq!(call(app.clone(), patch("PATCHME", "", redact.clone())).await.0, StatusCode::FORBIDDEN);
+        assert_eq!(call(app.clone(), patch("PATCHME", "root", redact)).await.0, StatusCode::OK);
+        let invalid = serde_json::json!({"links": "/parent"});
+        assert_eq!(call(app.clone(), patch("PATCHME", "", invalid)).await.0, StatusCode::BAD_REQUEST);
+        let (status, _) = call(app, patch("MISSING", "", serde_json::json!({}))).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    /// Request with an optional bearer token and JSON body
+    fn keyed(method: &str, uri: &str, token: Option<&str>, body: Option<serde_json::Value>) -> Request<Body> {
+        let mut builder = Request::builder().method(method).uri(uri).header("content-type", "application/json");
+        if let Some(token) = token {
+            builder = builder.header("authorization", format!("Bearer {}", token));
+        }
+        builder.body(body.map(|b| Body::from(b.to_string())).unwrap_or_default()).unwrap()
+    }
+
+    async fn key_id(app: &Router, token: &str) -> String {
+        let (_, body) = call(app.clone(), keyed("GET", "/whoami", Some(token), None)).await;
+        body["key_id"].as_str().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_acl_limits_keys_per_coordinate() {
+        let mut state = state("acl").await;
+        Arc::get_mut(&mut state).unwrap().admin_token = Some("root".to_string());
+        let app = router(state);
+        let (reader, writer) = (key_id(&app, "reader").await, key_id(&app, "writer").await);
+        assert!(reader.starts_with("key:") && reader != writer);
+        let status = |method: &str, uri: &str, token: Option<&str>, body: Option<serde_json::Value>| {
+            let request = keyed(method, uri, token, body);
+            let app = app.clone();
+            async move { call(app, request).await }
+        };
+        let store = |coord: &str, n: u32| serde_json::json!({"coord_hint": coord, "state": {"coord": coord, "n": n}});
+
+        let acl = serde_json::json!({"acl": {"read": [reader], "write": [writer]}});
+        let shared = serde_json::json!({"coord_hint": "SHARED", "state": {"coord": "SHARED", "n": 1}, "metadata": acl});
+        assert_eq!(status("POST", "/store", Some("writer"), Some(shared)).await.0, StatusCode::OK);
+        assert_eq!(status("POST", "/store", None, Some(store("OPEN", 1))).await.0, StatusCode::OK);
+
+        // A read-only key reads but cannot write or patch
+        assert_eq!(status("GET", "/recall/SHARED", Some("reader"), None).await.0, StatusCode::OK);
+        let (code, body) = status("POST", "/store", Some("reader"), Some(store("SHARED", 2))).await;
+        assert_eq!((code, &body["code"]), (StatusCode::FORBIDDEN, &serde_json::json!("acl_denied")));
+        let open_up = serde_json::json!({"acl": {"read": ["*"], "write": [writer]}});
+        let (code, _) = status("PATCH", "/coords/SHARED/metadata", Some("reader"), Some(open_up.clone())).await;
+        assert_eq!(code, StatusCode::FORBIDDEN);
+        assert_eq!(status("POST", "/store", Some("writer"), Some(store("SHARED", 2))).await.0, StatusCode::OK);
+
+        // Keys the ACL leaves out, anonymous ones included, are denied
+        for token in [Some("other"), None] {
+            for uri in ["/recall/SHARED", "/coords/SHARED/history", "/coords/SHARED/head", "/verify/SHARED"] {
+                let (code, body) = status("GET", uri, token, None).await;
+                assert_eq!((code, body["code"].as_str()), (StatusCode::FORBIDDEN, Some("acl_denied")), "{}", uri);
+            }
+            let (_, listed) = status("GET", "/coords", token, None).await;
+            assert_eq!(listed.as_array().unwrap().len(), 1);
+            assert_eq!(listed[0]["id"], "OPEN");
+        }
+        assert_eq!(status("GET", "/recall/OPEN", Some("other"), None).await.0, StatusCode::OK);
+        let (_, listed) = status("GET", "/coords", Some("reader"), None).await;
+        assert_eq!(listed.as_array().unwrap().len(), 2);
+
+        // The admin token bypasses the ACL
+        assert_eq!(status("GET", "/recall/SHARED", Some("root"), None).await.0, StatusCode::OK);
+
+        // Writers change the ACL through a validated metadata patch
+        let malformed = serde_json::json!({"acl": {"read": "*"}});
+        let (code, _) = status("PATCH", "/coords/SHARED/metadata", Some("writer"), Some(malformed)).await;
+        assert_eq!(code, StatusCode::BAD_REQUEST);
+        let (code, _) = status("PATCH", "/coords/SHARED/metadata", Some("writer"), Some(open_up)).await;
+        assert_eq!(code, StatusCode::OK);
+        assert_eq!(status("GET", "/recall/SHARED", Some("other"), None).await.0, StatusCode::OK);
+        assert_eq!(status("POST", "/store", Some("other"), Some(store("SHARED", 3))).await.0, StatusCode::FORBIDDEN);
+    }
+
+    #[cfg(feature = "vector")]
+    #[tokio::test]
+    async fn test_search_leaves_out_unreadable_coordinates() {
+        let mut state = state("acl-search").await;
+        let setup = Arc::get_mut(&mut state).unwrap();
+        setup.admin_token = Some("root".to_string());
+        setup.embedder = Some(crate::embedder::Embedder::fake(|text| vec![1.0, text.len() as f32 / 100.0]));
+        let app = router(state);
+        let reader = key_id(&app, "reader").await;
+
+        let private = serde_json::json!({
+            "coord_hint": "PRIVATE", "state": {"note": "launch plan"}, "metadata": {"acl": {"read": [reader]}}
+        });
+        let public = serde_json::json!({"coord_hint": "PUBLIC", "state": {"note": "launch party"}});
+        for body in [private, public] {
+            assert_eq!(call(app.clone(), keyed("POST", "/store", Some("root"), Some(body))).await.0, StatusCode::OK);
+        }
+
+        // Same query for each key, so a shared cache entry would leak
+        let found = |token: &'static str| {
+            let app = app.clone();
+            async move {
+                let query = serde_json::json!({"query": "launch"});
+                let (status, body) = call(app, keyed("POST", "/search", Some(token), Some(query))).await;
+                assert_eq!(status, StatusCode::OK);
+                let mut ids: Vec<String> = body["results"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|r| r["coord_id"].as_str().unwrap().to_string())
+                    .collect();
+                ids.sort();
+                ids
+            }
+        };
+        assert_eq!(found("other").await, ["PUBLIC"]);
+        assert_eq!(found("reader").await, ["PRIVATE", "PUBLIC"]);
+        assert_eq!(found("root").await, ["PRIVATE", "PUBLIC"]);
+        assert_eq!(found("other").await, ["PUBLIC"]);
+    }
+
+    #[cfg(feature = "vector")]
+    #[tokio::test]
+    async fn test_search_filter_expression() {
+        let mut state = state("filter-expr").await;
+        Arc::get_mut(&mut state).unwrap().embedder =
+            Some(crate::embedder::Embedder::fake(|text| vec![1.0, text.len() as f32 / 100.0]));
+        let app = router(state);
+
+        let coords = [
+            ("OPEN_URGENT", "alice", serde_json::json!({"status": "open", "title": "outage"}), serde_json::json!({"tags": ["urgent"], "priority": 1})),
+            ("OPEN_LATER", "bob", serde_json::json!({"status": "open", "title": "cleanup"}), serde_json::json!({"priority": 5})),
+            ("CLOSED", "alice", serde_json::json!({"status": "closed"}), serde_json::json!({"tags": ["urgent"]})),
+        ];
+        for (hint, author, state, metadata) in coords {
+            let body = serde_json::json!({"coord_hint": hint, "state": state, "author": author, "metadata": metadata});
+            assert_eq!(call(app.clone(), keyed("POST", "/store", None, Some(body))).await.0, StatusCode::OK);
+        }
+
+        let search = |extra: serde_json::Value| {
+            let app = app.clone();
+            async move {
+                let mut query = serde_json::json!({"query": "status"});
+                query.as_object_mut().unwrap().extend(extra.as_object().unwrap().clone());
+                let (status, body) = call(app, keyed("POST", "/search", None, Some(query))).await;
+                let mut ids: Vec<String> = body["results"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .map(|r| r["coord_id"].as_str().unwrap().to_string())
+                    .collect();
+                ids.sort();
+                (status, ids, body)
+            }
+        };
+
+        // Head state, metadata and author predicates in one expression
+        let open = serde_json::json!({"filter_expr": {"and": [
+            {"eq": {"field": "/status", "value": "open"}},
+            {"or": [{"tag": "urgent"}, {"gt": {"field": "metadata.priority", "value": 3}}]},
+        ]}});
+        assert_eq!(search(open).await.1, ["OPEN_LATER", "OPEN_URGENT"]);
+        let alice = serde_json::json!({"filter_expr": {"and": [
+            {"eq": {"field": "author", "value": "alice"}},
+            {"not": {"exists": "metadata.priority"}},
+        ]}});
+        assert_eq!(search(alice).await.1, ["CLOSED"]);
+
+        let (status, _, body) =
+            search(serde_json::json!({"filter_expr": {"tag": "urgent"}, "author": "alice"})).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body["error"].as_str().unwrap().contains("cannot be combined"));
+        let (status, _, body) =
+            search(serde_json::json!({"filter_expr": {"and": [{"tag": "urgent"}, {"lt": {"field": "/n"}}]}})).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body["error"].as_str().unwrap().contains("$.and[1].lt.value"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn test_ephemerals_are_hidden_until_promoted() {
+        let state = state("ephemeral").await;
+        let app = router(state.clone());
+        let get = |uri: &str| Request::get(uri).body(Body::empty()).unwrap();
+        let post = |uri: &str, body: serde_json::Value| {
+            Request::post(uri)
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+        let listed = |body: serde_json::Value| -> Vec<String> {
+            let mut ids: Vec<String> =
+                body.as_array().unwrap().iter().map(|c| c["id"].as_str().unwrap().to_string()).collect();
+            ids.sort();
+            ids
+        };
+
+        let scratch = serde_json::json!({"coord_hint": "SCRATCH", "state": {"step": 1}, "ephemeral": true});
+        assert_eq!(call(app.clone(), post("/store", scratch)).await.0, StatusCode::OK);
+        let durable = serde_json::json!({"coord_hint": "DURABLE", "state": {"plan": "ship"}});
+        assert_eq!(call(app.clone(), post("/store", durable)).await.0, StatusCode::OK);
+        // Backdated scratch coordinates: one read just now, one untouched
+        for coord in ["READNOW", "FORGOTTEN"] {
+            let params = bms_storage::StoreParams {
+                coord_id: Some(bms_core::CoordId(coord.to_string())),
+                state: serde_json::json!({"draft": coord}),
+                metadata: Some([(bms_core::EPHEMERAL_METADATA_KEY.to_string(), true.into())].into()),
+                created_at: Some(chrono::Utc::now() - chrono::Duration::hours(3)),
+                ..Default::default()
+            };
+            state.facade.store(params).await.unwrap();
+        }
+
+        // Listing leaves them out unless asked; recall works as usual
+        assert_eq!(listed(call(app.clone(), get("/coords")).await.1), ["DURABLE"]);
+        let (_, all) = call(app.clone(), get("/coords?include_ephemeral=true")).await;
+        assert_eq!(listed(all), ["DURABLE", "FORGOTTEN", "READNOW", "SCRATCH"]);
+        let (status, recalled) = call(app.clone(), get("/recall/READNOW")).await;
+        assert_eq!((status, &recalled["state"]), (StatusCode::OK, &serde_json::json!({"draft": "READNOW"})));
+
+        // Collection counts the read that has not been flushed yet
+        collect_ephemeral(&state, Duration::from_secs(3600)).await;
+        let (_, all) = call(app.clone(), get("/coords?include_ephemeral=true")).await;
+        assert_eq!(listed(all), ["DURABLE", "READNOW", "SCRATCH"]);
+
+        // Promotion makes the coordinate listed and searchable, and is idempotent
+        let (status, body) = call(app.clone(), post("/coords/SCRATCH/promote", serde_json::json!({}))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!((body["promoted"].as_bool(), body["indexed"].as_bool()), (Some(true), Some(false)));
+        assert_eq!(listed(call(app.clone(), get("/coords")).await.1), ["DURABLE", "SCRATCH"]);
+        let (_, body) = call(app.clone(), post("/coords/SCRATCH/promote", serde_json::json!({}))).await;
+        assert_eq!(body["promoted"], false);
+        let (status, _) = call(app.clone(), post("/coords/MISSING/promote", serde_json::json!({}))).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        // Promoted and durable coordinates survive any idle window
+        collect_ephemeral(&state, Duration::ZERO).await;
+        let (_, all) = call(app, get("/coords?include_ephemeral=true")).await;
+        assert_eq!(listed(all), ["DURABLE", "SCRATCH"]);
+    }
+
+    #[tokio::test]
+    async fn test_list_coordinates_by_namespace() {
+        let app = router(state("namespaces").await);
+        let get = |uri: &str| Request::get(uri).body(Body::empty()).unwrap();
+        let store = |body: serde_json::Value| {
+            Request::post("/store")
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+        let listed = |body: serde_json::Value| -> Vec<String> {
+            let mut ids: Vec<String> =
+                body.as_array().unwrap().iter().map(|c| c["id"].as_str().unwrap().to_string()).collect();
+            ids.sort();
+            ids
+        };
+
+        let plain = serde_json::json!({"coord_hint": "PLAIN", "state": {"n": 0}});
+        assert_eq!(call(app.clone(), store(plain)).await.0, StatusCode::OK);
+        let planner = serde_json::json!({"coord_hint": "PLANNER", "state": {"n": 1}, "namespace": "agents/planner"});
+        assert_eq!(call(app.clone(), store(planner)).await.0, StatusCode::OK);
+
+        assert_eq!(listed(call(app.clone(), get("/coords")).await.1), ["PLAIN", "PLANNER"]);
+        assert_eq!(listed(call(app.clone(), get("/coords?namespace=default")).await.1), ["PLAIN"]);
+        let (_, body) = call(app.clone(), get("/coords?namespace=agents")).await;
+        assert_eq!(body[0]["namespace"], "agents/planner");
+        assert_eq!(listed(body), ["PLANNER"]);
+        assert_eq!(call(app.clone(), get("/coords?namespace=a//b")).await.0, StatusCode::BAD_REQUEST);
+        let bad = serde_json::json!({"state": {"n": 2}, "namespace": "no spaces"});
+        assert_eq!(call(app, store(bad)).await.0, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_coord_hints_are_validated() {
+        let app = router(state("coord_hints").await);
+        let store = |hint: &str, n: u32| {
+            let body = serde_json::json!({"coord_hint": hint, "state": {"n": n}});
+            Request::post("/store")
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+
+        let (code, body) = call(app.clone(), store("two words", 0)).await;
+        assert_eq!(code, StatusCode::BAD_REQUEST);
+        assert!(body["error"].as_str().unwrap().contains("named coordinate ID"), "{}", body);
+        assert_eq!(call(app.clone(), store(&"n".repeat(129), 1)).await.0, StatusCode::BAD_REQUEST);
+
+        assert_eq!(call(app.clone(), store("notes", 2)).await.0, StatusCode::OK);
+        let (_, body) = call(app, Request::get("/coords").body(Body::empty()).unwrap()).await;
+        assert_eq!(body.as_array().unwrap().len(), 1);
+        assert_eq!((&body[0]["id"], &body[0]["kind"]), (&serde_json::json!("notes"), &serde_json::json!("named")));
+    }
+
+    #[tokio::test]
+    async fn test_large_heads_are_snapshotted() {
+        assert_eq!(snapshot_manager(64, 0).interval(), Some(64));
+        assert_eq!(snapshot_manager(64, 1024).interval(), Some(64));
+
+        let db_path = std::env::temp_dir().join(format!("bms-server-size-policy-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&db_path);
+        let path = db_path.to_str().unwrap();
+        let mut app_state = build_state(path, false).await.unwrap();
+        let facade = BmsFacade::new(BmsRepository::new(path).await.unwrap(), snapshot_manager(100, 256));
+        Arc::get_mut(&mut app_state).unwrap().facade = Arc::new(facade);
+        let app = router(app_state);
+        let store = |state: serde_json::Value| {
+            let body = serde_json::json!({"coord_hint": "GROWING", "state": state});
+            Request::post("/store")
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+
+        let (_, body) = call(app.clone(), store(serde_json::json!({"n": 0}))).await;
+        assert_eq!(body["snapshot"], "skipped");
+        let (_, body) = call(app, store(serde_json::json!({"n": 1, "body": "x".repeat(256)}))).await;
+        assert_eq!(body["snapshot"], "created", "{}", body);
+    }
+
+    #[tokio::test]
+    async fn test_verify_reports_broken_links() {
+        use bms_core::{DeltaEngine, MerkleChain};
+        let app_state = state("verify_links").await;
+        let app = router(app_state.clone());
+        for n in 0..2 {
+            let body = serde_json::json!({"coord_hint": "LINKED", "state": {"n": n}});
+            let request = Request::post("/store")
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap();
+            assert_eq!(call(app.clone(), request).await.0, StatusCode::OK);
+        }
+        let verify = || Request::get("/verify/LINKED").body(Body::empty()).unwrap();
+        let (_, body) = call(app.clone(), verify()).await;
+        assert_eq!((&body["chain_valid"], &body["break_reason"]), (&serde_json::json!(true), &serde_json::Value::Null));
+
+        // Consistent on its own, but not with the delta it names as parent
+        let repo = app_state.facade.repository();
+        let coord = bms_core::types::CoordId("LINKED".to_string());
+        let mut forged = repo.get_deltas(&coord).await.unwrap().pop().unwrap();
+        forged.parent_id = Some(forged.id.clone());
+        forged.ops = serde_json::from_value(serde_json::json!([{"op": "add", "path": "/forged", "value": 1}])).unwrap();
+        forged.id = DeltaEngine::generate_delta_id(&forged.ops).unwrap();
+        forged.delta_hash = DeltaEngine::hash_delta(&forged.ops).unwrap();
+        let fabricated = bms_core::types::Hash("ab".repeat(32));
+        forged.chain_hash = MerkleChain::compute_chain_hash(&fabricated, &forged.delta_hash);
+        forged.parent_hash = Some(fabricated);
+        forged.created_at += chrono::Duration::seconds(1);
+        repo.insert_delta(&forged).await.unwrap();
+
+        let (_, body) = call(app, verify()).await;
+        assert_eq!((&body["chain_valid"], &body["first_break"]), (&serde_json::json!(false), &serde_json::json!(2)));
+        let reason = body["break_reason"].as_str().unwrap();
+        assert!(reason.contains(&format!("parent_hash {} is not the chain_hash", "ab".repeat(32))), "{}", reason);
+    }
+
+    #[tokio::test]
+    async fn test_generated_ids_are_read_in_any_case() {
+        let app = router(state("id_case").await);
+        let coord = bms_core::types::CoordId::from_bytes([3u8; bms_core::COORD_ID_BYTES]);
+        let lower = coord.0.to_lowercase();
+        let store = |hint: &str, n: u32| {
+            let body = serde_json::json!({"coord_hint": hint, "state": {"n": n}});
+            Request::post("/store")
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+        let get = |uri: String| Request::get(uri).body(Body::empty()).unwrap();
+
+        let (code, body) = call(app.clone(), store(&lower, 1)).await;
+        assert_eq!(code, StatusCode::OK, "{}", body);
+        assert_eq!(body["coord_id"], serde_json::json!(coord.0));
+        let grouped = format!("{}-{}", &coord.0[..13], &lower[13..]);
+        assert_eq!(call(app.clone(), store(&grouped, 2)).await.0, StatusCode::OK);
+
+        let (code, body) = call(app.clone(), get(format!("/recall/{}", lower))).await;
+        assert_eq!(code, StatusCode::OK);
+        assert_eq!(body["state"], serde_json::json!({"n": 2}));
+        let (_, body) = call(app.clone(), get(format!("/coords/{}/history", grouped))).await;
+        assert_eq!(body["entries"].as_array().map(Vec::len), Some(2), "{}", body);
+
+        let (code, body) = call(app, get(format!("/coords/{}0/head", &lower[..25]))).await;
+        assert_eq!(code, StatusCode::BAD_REQUEST);
+        assert!(body["error"].as_str().unwrap().contains("'0' at position 25"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn test_derived_children() {
+        let app = router(state("children").await);
+        let store = |body: serde_json::Value| {
+            Request::post("/store")
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+        let children = |coord: &str| Request::get(format!("/coords/{}/children", coord)).body(Body::empty()).unwrap();
+
+        let session = serde_json::json!({"coord_hint": "SESSION", "state": {"turn": 0}});
+        assert_eq!(call(app.clone(), store(session)).await.0, StatusCode::OK);
+        let fork = |n: u32| {
+            serde_json::json!({"parent_coord_id": "SESSION", "child_label": "planner", "state": {"n": n}})
+        };
+        let (code, first) = call(app.clone(), store(fork(1))).await;
+        assert_eq!(code, StatusCode::OK, "{}", first);
+        let session = bms_core::types::CoordId("SESSION".to_string());
+        let child = bms_core::CoordinateGenerator::derive_child(&session, "planner");
+        assert_eq!(first["coord_id"], child.as_str());
+        // The same label reaches the same child again
+        let (_, second) = call(app.clone(), store(fork(2))).await;
+        assert_eq!(second["coord_id"], child.as_str());
+
+        let (code, body) = call(app.clone(), children("SESSION")).await;
+        assert_eq!(code, StatusCode::OK);
+        assert_eq!(body["children"].as_array().unwrap().len(), 1);
+        assert_eq!(body["children"][0]["id"], child.as_str());
+        assert_eq!(body["children"][0]["parent_coord_id"], "SESSION");
+        let (_, body) = call(app.clone(), children(child.as_str())).await;
+        assert!(body["children"].as_array().unwrap().is_empty());
+
+        let orphan = serde_json::json!({"child_label": "planner", "state": {}});
+        assert_eq!(call(app.clone(), store(orphan)).await.0, StatusCode::BAD_REQUEST);
+        let unlabeled = serde_json::json!({"parent_coord_id": "SESSION", "state": {}});
+        assert_eq!(call(app.clone(), store(unlabeled)).await.0, StatusCode::BAD_REQUEST);
+        let unknown = serde_json::json!({"parent_coord_id": "NOSUCH", "child_label": "x", "state": {}});
+        assert_eq!(call(app, store(unknown)).await.0, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_saved_searches_are_scoped_per_key() {
+        let app = router(state("saved").await);
+        let request = |method: &str, uri: &str, token: &str, body: Option<serde_json::Value>| {
+            let builder = Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("authorization", format!("Bearer {}", token))
+                .header("content-type", "application/json");
+            builder
+                .body(body.map(|b| Body::from(b.to_string())).unwrap_or_default())
+                .unwrap()
+        };
+        let status = |request: Request<Body>| {
+            let app = app.clone();
+            async move { app.oneshot(request).await.unwrap().status() }
+        };
+        let saved = serde_json::json!({"name": "nightly-review", "query": "open tasks", "limit": 5});
+
+        assert_eq!(status(request("POST", "/searches", "alpha", Some(saved.clone()))).await, StatusCode::CREATED);
+        assert_eq!(status(request("POST", "/searches", "alpha", Some(saved))).await, StatusCode::OK);
+        let (code, body) = call(app.clone(), request("GET", "/searches/nightly-review", "alpha", None)).await;
+        assert_eq!((code, &body["search"]["query"]), (StatusCode::OK, &serde_json::json!("open tasks")));
+
+        // Another key sees none of it
+        assert_eq!(status(request("GET", "/searches/nightly-review", "beta", None)).await, StatusCode::NOT_FOUND);
+        assert_eq!(status(request("GET", "/searches/nightly-review/run", "beta", None)).await, StatusCode::NOT_FOUND);
+        let (_, listed) = call(app.clone(), request("GET", "/searches", "beta", None)).await;
+        assert_eq!(listed, serde_json::json!([]));
+
+        // Running needs the embedding model, which this server does not load
+        let run = request("GET", "/searches/nightly-review/run", "alpha", None);
+        assert_eq!(status(run).await, StatusCode::NOT_IMPLEMENTED);
+
+        let bad = serde_json::json!({"name": "no spaces", "query": "x"});
+        assert_eq!(status(request("POST", "/searches", "alpha", Some(bad))).await, StatusCode::BAD_REQUEST);
+        assert_eq!(status(request("DELETE", "/searches/nightly-review", "beta", None)).await, StatusCode::NOT_FOUND);
+        assert_eq!(status(request("DELETE", "/searches/nightly-review", "alpha", None)).await, StatusCode::NO_CONTENT);
+    }
+
+    #[cfg(not(feature = "vector"))]
+    #[tokio::test]
+    async fn test_strict_recall_refuses_what_default_repairs() {
+        let db_path = std::env::temp_dir().join(format!("bms-server-strict-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&db_path);
+        let path = db_path.to_str().unwrap();
+        let default = build_state(path, false).await.unwrap();
+        let mut strict = build_state(path, false).await.unwrap();
+        let facade = BmsFacade::new(BmsRepository::new(path).await.unwrap(), SnapshotManager::new(100))
+            .with_integrity_checks(IntegrityChecks::strict());
+        Arc::get_mut(&mut strict).unwrap().facade = Arc::new(facade);
+
+        let (_, health) = call(router(default.clone()), Request::get("/health").body(Body::empty()).unwrap()).await;
+        assert_eq!(health["capabilities"]["strict"], false);
+        assert_eq!(health["capabilities"]["integrity_checks"], serde_json::json!([]));
+        let (_, health) = call(router(strict.clone()), Request::get("/health").body(Body::empty()).unwrap()).await;
+        assert_eq!(health["capabilities"]["strict"], true);
+        assert_eq!(
+            health["capabilities"]["integrity_checks"],
+            serde_json::json!(["verify_snapshots", "verify_deltas", "warnings_are_errors"])
+        );
+
+        // A materialized head behind its chain
+        let coord = bms_core::CoordId("STRICTAPI".to_string());
+        let metadata = std::collections::HashMap::from([("materialize_head".to_string(), serde_json::json!(true))]);
+        let stored = default
+            .facade
+            .store(bms_storage::StoreParams {
+                coord_id: Some(coord.clone()),
+                state: serde_json::json!({"n": 1}),
+                metadata: Some(metadata),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let stale = bms_storage::models::MaterializedHead {
+            coord_id: coord.clone(),
+            head_delta_id: stored.head.delta_id.clone(),
+            chain_hash: bms_core::Hash("stale".to_string()),
+            state: serde_json::json!({"n": 1}),
+            updated_at: chrono::Utc::now(),
+        };
+        let repo = default.facade.repository();
+        assert!(repo.record_head_state(&stale).await.unwrap());
+
+        let recall = || Request::get("/recall/STRICTAPI").body(Body::empty()).unwrap();
+        let (code, body) = call(router(strict.clone()), recall()).await;
+        assert_eq!(code, StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(body["error"].as_str().unwrap().contains("behind its chain"), "{}", body);
+        // The default mode serves a replay and repairs the row
+        let (code, body) = call(router(default.clone()), recall()).await;
+        assert_eq!((code, &body["state"]), (StatusCode::OK, &serde_json::json!({"n": 1})));
+        let (code, _) = call(router(strict), recall()).await;
+        assert_eq!(code, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_minimal_build_never_loads_a_model() {
+        let db_path = std::env::temp_dir().join(format!("bms-server-minimal-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&db_path);
+        let state = build_state(db_path.to_str().unwrap(), true).await.unwrap();
+        assert!(state.embedder.is_none());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_serves_over_unix_socket() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let path = std::env::temp_dir().join(format!("bms-server-{}.sock", std::process::id()));
+        let app = router(state("unix").await);
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let server_path = path.clone();
+        let server = tokio::spawn(async move {
+            serve_unix(&server_path, app, async {
+                let _ = stopped.await;
+            })
+            .await
+        });
+
+        let mut stream = loop {
+            match tokio::net::UnixStream::connect(&path).await {
+                Ok(stream) => break stream,
+                Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        };
+        stream
+            .write_all(b"GET /health HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        assert!(response.contains("\"vector_search\":false"));
+
+        stop.send(()).unwrap();
+        server.await.unwrap().unwrap();
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_if_match_stores() {
+        let app = router(state("if-match").await);
+        let store = |if_match: Option<String>, body: serde_json::Value| {
+            let mut request = Request::post("/store").header("content-type", "application/json");
+            if let Some(tag) = if_match {
+                request = request.header("if-match", tag);
+            }
+            request.body(Body::from(body.to_string())).unwrap()
+        };
+        let state = |v: serde_json::Value| serde_json::json!({"coord_hint": "IFMATCH", "state": {"v": v}});
+        let (_, base) = call(app.clone(), store(None, state(0.into()))).await;
+        let tag = format!("\"{}\"", base["head"]["chain_hash"].as_str().unwrap());
+
+        // Two writers from the same head: one stores, the other gets 412 with the new head's tag
+        let (a, b) = tokio::join!(
+            app.clone().oneshot(store(Some(tag.clone()), state("a".into()))),
+            app.clone().oneshot(store(Some(tag.clone()), state("b".into()))),
+        );
+        let (a, b) = (a.unwrap(), b.unwrap());
+        let statuses = [a.status(), b.status()];
+        assert_eq!(statuses.iter().filter(|s| **s == StatusCode::OK).count(), 1, "{:?}", statuses);
+        let (winner, loser) = if a.status() == StatusCode::OK { (a, b) } else { (b, a) };
+        assert_eq!(loser.status(), StatusCode::PRECONDITION_FAILED);
+        let loser_tag = loser.headers()["etag"].to_str().unwrap().to_string();
+        let body = axum::body::to_bytes(winner.into_body(), usize::MAX).await.unwrap();
+        let won: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let head_tag = format!("\"{}\"", won["head"]["chain_hash"].as_str().unwrap());
+        assert_eq!(loser_tag, head_tag);
+
+        // The header wins over a stale body precondition
+        let mut stale = state(2.into());
+        stale["expected_head_delta_id"] = "stale".into();
+        let (status, _) = call(app.clone(), store(Some(head_tag.clone()), stale)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, body) = call(app, store(Some(format!("W/{}", head_tag)), state(3.into()))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body["error"].as_str().unwrap().contains("Weak entity tags"), "{}", body);
+    }
+}
@@ -1,6 +1,13 @@
-use bms_core::{CoordId, SnapshotManager};
-use bms_storage::BmsRepository;
-use bms_vector::EmbeddingGenerator;
+use crate::config::{ConfigSource, RuntimeConfig};
+use crate::drift::{DriftPolicy, TextSketch};
+use crate::embedder::Embedder;
+use crate::replication::Replicator;
+use crate::search_cache::SearchCache;
+use arc_swap::ArcSwap;
+use bms_core::extract::ExtractionConfig;
+use bms_core::{CoordId, ImportancePolicy};
+use bms_storage::sampler::IntegritySampler;
+use bms_storage::{AccessTracker, BmsFacade, CostModel};
 use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::Mutex;
@@ -12,14 +19,70 @@ pub struct CachedEmbedding {
     pub embedding: Vec<f32>,
     pub author: Option<String>,
     pub created_at: chrono::DateTime<chrono::Utc>,
+    /// Copies of the coordinate metadata keys in `AppState::index_metadata_keys`,
+    /// kept in step with metadata patches without embedding again
+    pub custom: HashMap<String, serde_json::Value>,
+    /// Sketch of the embedded text; `None` when the drift check was off
+    pub sketch: Option<TextSketch>,
+    /// Each facet in `AppState::index_facets` the coordinate had, embedded
+    /// on its own for facet queries
+    pub facets: HashMap<String, FacetEmbedding>,
+}
+
+/// One facet line and its embedding
+#[derive(Clone)]
+pub struct FacetEmbedding {
+    pub text: String,
+    pub embedding: Vec<f32>,
 }
 
 pub struct AppState {
-    pub repository: BmsRepository,
+    pub facade: Arc<BmsFacade>,
     /// In-memory cache of embeddings for coordinate heads (coord_id -> cached embedding)
     /// Design: vectors are search metadata, not canonical storage
     /// Embeddings are computed on-demand during search and cached by head hash
     pub embedding_cache: Arc<Mutex<HashMap<CoordId, CachedEmbedding>>>,
-    pub embedding_generator: Mutex<EmbeddingGenerator>,
-    pub snapshot_manager: SnapshotManager,
+    /// Model for `/search`; `None` when built without the `vector` feature,
+    /// disabled, or the model failed to load
+    pub embedder: Option<Embedder>,
+    /// How far a changed head's text may drift before it is embedded again
+    pub embed_drift: DriftPolicy,
+    /// Recent search results, invalidated by the facade generation
+    pub search_cache: SearchCache,
+    /// Per-coordinate read counters, flushed periodically to `coord_access`
+    pub access_tracker: AccessTracker,
+    /// Bearer token for admin-only operations (`BMS_ADMIN_TOKEN`); unset disables them
+    pub admin_token: Option<String>,
+    /// Background chain sampler; `None` when sampling is disabled
+    pub sampler: Option<Arc<IntegritySampler>>,
+    /// Answer `/health` with 503 rather than 200 when the last sample failed
+    pub degraded_unavailable: bool,
+    /// Decay and per-read bump of coordinate importance
+    pub importance: ImportancePolicy,
+    /// Coordinates below this effective importance get no planned snapshots
+    pub importance_floor: f32,
+    /// Coordinate metadata keys copied into the search index and filterable
+    /// with `metadata` in search requests
+    pub index_metadata_keys: Vec<String>,
+    /// Coordinate metadata keys appended to the embedded text and
+    /// searchable with `facets` in search requests
+    pub index_facets: ExtractionConfig,
+    /// Settings a config reload can change; see `config`
+    pub config: ArcSwap<RuntimeConfig>,
+    /// Where reloads read settings from
+    pub config_source: ConfigSource,
+    /// Follows the primary when this instance is a standby; the facade is
+    /// read-only then
+    pub replicator: Option<Arc<Replicator>>,
+}
+
+impl AppState {
+    /// Planner cost model with the configured importance floor
+    pub fn cost_model(&self) -> CostModel {
+        CostModel {
+            importance_floor: self.importance_floor,
+            importance: self.importance,
+            ..CostModel::default()
+        }
+    }
 }
@@ -0,0 +1,214 @@
+//! Keeps the embedding cache in step with storage mutations
+//!
+//! Search builds its index from the embedding cache, so an entry left behind
+//! after a delete or rename would keep matching a coordinate that no longer
+//! exists under that ID. Entries also carry copies of indexed metadata keys,
+//! which metadata patches update in place; `repair` catches copies that
+//! drifted anyway, e.g. after missed events.
+
+use crate::state::CachedEmbedding;
+use bms_core::{CoordId, Result, EPHEMERAL_METADATA_KEY};
+use bms_storage::{BmsFacade, StorageEvent};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+use tokio::sync::Mutex;
+use tracing::{debug, warn};
+
+type EmbeddingCache = Arc<Mutex<HashMap<CoordId, CachedEmbedding>>>;
+
+/// The `keys` of a coordinate's metadata, as copied into its cache entry
+pub fn index_metadata(keys: &[String], metadata: Option<&HashMap<String, Value>>) -> HashMap<String, Value> {
+    let Some(metadata) = metadata else {
+        return HashMap::new();
+    };
+    keys.iter()
+        .filter_map(|key| Some((key.clone(), metadata.get(key)?.clone())))
+        .collect()
+}
+
+/// Apply storage events to the cache until the facade is dropped
+pub async fn run(mut events: Receiver<StorageEvent>, cache: EmbeddingCache, keys: Vec<String>) {
+    loop {
+        match events.recv().await {
+            Ok(event) => apply(&cache, &keys, &event).await,
+            Err(RecvError::Lagged(skipped)) => {
+                // Entries are recomputed on demand, so dropping them all is a safe repair
+                warn!("Missed {} storage events, clearing embedding cache", skipped);
+                cache.lock().await.clear();
+            }
+            Err(RecvError::Closed) => break,
+        }
+    }
+}
+
+/// Apply one event to the cache
+pub async fn apply(cache: &EmbeddingCache, keys: &[String], event: &StorageEvent) {
+    debug!("Syncing embedding cache: {:?}", event);
+    let mut cache = cache.lock().await;
+
+    match event {
+        StorageEvent::Deleted { coord_id } | StorageEvent::Archived { coord_id } => {
+            cache.remove(coord_id);
+        }
+        StorageEvent::Renamed { old, new } => {
+            if let Some(entry) = cache.remove(old) {
+                cache.insert(new.clone(), entry);
+            }
+        }
+        // Restored coordinates are embedded again on the next search
+        StorageEvent::Restored { .. } => {}
+        // A coordinate made ephemeral leaves the index until it is promoted
+        StorageEvent::MetadataUpdated { coord_id, metadata }
+            if metadata.get(EPHEMERAL_METADATA_KEY) == Some(&Value::Bool(true)) =>
+        {
+            cache.remove(coord_id);
+        }
+        StorageEvent::MetadataUpdated { coord_id, metadata } => {
+            if let Some(entry) = cache.get_mut(coord_id) {
+                entry.custom = index_metadata(keys, Some(metadata));
+            }
+        }
+    }
+}
+
+/// Compare every cache entry with its coordinate, dropping entries of
+/// missing or ephemeral coordinates and fixing drifted metadata copies
+///
+/// Returns the number of entries repaired. The cache is not locked while
+/// coordinates are read, and an entry that changed meanwhile is left for the
+/// next run.
+pub async fn repair(facade: &BmsFacade, cache: &EmbeddingCache, keys: &[String]) -> Result<usize> {
+    let indexed: Vec<(CoordId, HashMap<String, Value>)> = cache
+        .lock()
+        .await
+        .iter()
+        .map(|(coord_id, entry)| (coord_id.clone(), entry.custom.clone()))
+        .collect();
+
+    let mut repairs = Vec::new();
+    for (coord_id, custom) in indexed {
+        let current = facade
+            .repository()
+            .get_coordinate(&coord_id)
+            .await?
+            .filter(|c| !c.is_ephemeral())
+            .map(|c| index_metadata(keys, c.metadata.as_ref()));
+        if current.as_ref() != Some(&custom) {
+            repairs.push((coord_id, custom, current));
+        }
+    }
+
+    let mut cache = cache.lock().await;
+    let mut repaired = 0;
+    for (coord_id, seen, current) in repairs {
+        if cache.get(&coord_id).is_none_or(|entry| entry.custom != seen) {
+            continue;
+        }
+        match current {
+            Some(custom) => cache.get_mut(&coord_id).expect("checked above").custom = custom,
+            None => {
+                cache.remove(&coord_id);
+            }
+        }
+        repaired += 1;
+    }
+    Ok(repaired)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::metadata_matches;
+
+    fn entry() -> CachedEmbedding {
+        CachedEmbedding {
+            head_hash: "h".to_string(),
+            embedding: vec![1.0],
+            author: None,
+            created_at: chrono::Utc::now(),
+            custom: HashMap::new(),
+            sketch: None,
+            facets: HashMap::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_events_update_cache() {
+        let a = CoordId("A".to_string());
+        let b = CoordId("B".to_string());
+        let c = CoordId("C".to_string());
+        let cache: EmbeddingCache = Arc::new(Mutex::new(HashMap::from([
+            (a.clone(), entry()),
+            (b.clone(), entry()),
+        ])));
+
+        apply(&cache, &[], &StorageEvent::Deleted { coord_id: a.clone() }).await;
+        assert!(!cache.lock().await.contains_key(&a));
+
+        apply(&cache, &[], &StorageEvent::Renamed { old: b.clone(), new: c.clone() }).await;
+        assert!(!cache.lock().await.contains_key(&b));
+        assert!(cache.lock().await.contains_key(&c));
+
+        apply(&cache, &[], &StorageEvent::Archived { coord_id: c.clone() }).await;
+        assert!(cache.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_metadata_patches_reach_indexed_copies() {
+        let db_path = std::env::temp_dir().join(format!("bms-index-sync-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&db_path);
+        let app = crate::build_state(db_path.to_str().unwrap(), false).await.unwrap();
+        let facade = &app.facade;
+        let keys = vec!["project".to_string(), "source".to_string()];
+        let metadata = |pairs: &[(&str, Value)]| -> HashMap<String, Value> {
+            pairs.iter().map(|(k, v)| (k.to_string(), v.clone())).collect()
+        };
+
+        let coord = CoordId("INDEXSYNC".to_string());
+        facade
+            .store(bms_storage::StoreParams {
+                coord_id: Some(coord.clone()),
+                state: serde_json::json!({"note": "launch plan"}),
+                metadata: Some(metadata(&[("project", "apollo".into()), ("sensitivity", "high".into())])),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        // Indexed as search would: only the configured keys are copied
+        let stored = facade.repository().get_coordinate(&coord).await.unwrap().unwrap();
+        let indexed = CachedEmbedding {
+            custom: index_metadata(&keys, stored.metadata.as_ref()),
+            ..entry()
+        };
+        assert_eq!(indexed.custom, metadata(&[("project", "apollo".into())]));
+        let cache: EmbeddingCache = Arc::new(Mutex::new(HashMap::from([(coord.clone(), indexed)])));
+
+        // A patch updates the copy in place; the embedding is not recomputed
+        let mut events = facade.subscribe_storage();
+        let patch = metadata(&[("project", "gemini".into()), ("source", "slack".into())]);
+        facade.patch_metadata(&coord, patch).await.unwrap();
+        apply(&cache, &keys, &events.recv().await.unwrap()).await;
+        let updated = cache.lock().await[&coord].clone();
+        assert_eq!(updated.custom, metadata(&[("project", "gemini".into()), ("source", "slack".into())]));
+        assert_eq!((updated.head_hash.as_str(), updated.embedding.as_slice()), ("h", &[1.0][..]));
+        assert!(metadata_matches(&metadata(&[("project", "gemini".into())]), &updated.custom));
+        assert!(!metadata_matches(&metadata(&[("project", "apollo".into())]), &updated.custom));
+
+        // Drift from a missed event, and an entry whose coordinate is gone
+        let gone = CoordId("INDEXGONE".to_string());
+        cache.lock().await.get_mut(&coord).unwrap().custom = metadata(&[("project", "apollo".into())]);
+        cache.lock().await.insert(gone.clone(), entry());
+        assert_eq!(repair(facade, &cache, &keys).await.unwrap(), 2);
+        assert_eq!(cache.lock().await[&coord].custom, updated.custom);
+        assert!(!cache.lock().await.contains_key(&gone));
+        assert_eq!(repair(facade, &cache, &keys).await.unwrap(), 0);
+
+        // Made ephemeral, the coordinate leaves the index
+        let patch = metadata(&[(EPHEMERAL_METADATA_KEY, true.into())]);
+        facade.patch_metadata(&coord, patch).await.unwrap();
+        apply(&cache, &keys, &events.recv().await.unwrap()).await;
+        assert!(cache.lock().await.is_empty());
+    }
+}
@@ -0,0 +1,544 @@
+//! WebSocket session protocol for long-lived agent connections
+//!
+//! Requests are `{id, op, params}` and each gets exactly one
+//! `{id, ok, result|error}` reply. Deltas on subscribed coordinates are pushed
+//! as `{event: "delta", ...}`. A subscription with `pointers` only receives
+//! deltas whose ops touch one of them, and the event lists the ones matched
+//! under `matched`. A session that falls behind the broadcast
+//! channel gets one `{event: "head_moved", ...}` marker per subscription
+//! instead of the missed deltas, so no per-connection queue grows unbounded.
+
+use crate::acl::Caller;
+use crate::handlers::{parse_coord_id, AppError, StoreResponse};
+use crate::state::AppState;
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        State,
+    },
+    http::HeaderMap,
+    response::Response,
+};
+use bms_core::types::{CoordId, DeltaId, Hash};
+use bms_core::{Access, DeltaPolicy, DiffOptions, PointerFilter};
+use bms_storage::facade::{DeltaEvent, StoreParams, StorePrecondition};
+use bms_storage::BmsFacade;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{debug, warn};
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum WsOp {
+    Store,
+    Recall,
+    Subscribe,
+    Unsubscribe,
+}
+
+#[derive(Debug, Deserialize)]
+struct WsRequest {
+    id: Value,
+    op: WsOp,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Debug, Serialize)]
+struct WsResponse {
+    id: Value,
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum WsEvent<'a> {
+    Delta {
+        #[serde(flatten)]
+        delta: &'a DeltaEvent,
+        /// Watched pointers the delta touched
+        #[serde(skip_serializing_if = "Option::is_none")]
+        matched: Option<Vec<String>>,
+    },
+    /// Deltas were dropped for this coordinate; re-read the head
+    HeadMoved {
+        coord_id: &'a CoordId,
+        delta_id: Option<DeltaId>,
+        chain_hash: Option<Hash>,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+struct StoreOpParams {
+    coord_id: Option<String>,
+    state: Value,
+    metadata: Option<HashMap<String, Value>>,
+    author: Option<String>,
+    op_authors: Option<Vec<String>>,
+    /// Expected head chain hash, same semantics as HTTP `If-Match`
+    if_match: Option<String>,
+    diff_options: Option<DiffOptions>,
+    /// Include per-phase timings in the result
+    #[serde(default)]
+    explain: bool,
+    /// Namespace to create the coordinate in
+    namespace: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CoordOpParams {
+    #[serde(default)]
+    coord_id: Option<String>,
+    #[serde(default)]
+    coord_ids: Vec<String>,
+    /// Only notify when a delta touches one of these JSON Pointers (subscribe only)
+    #[serde(default)]
+    pointers: Option<Vec<String>>,
+}
+
+/// Subscribed coordinates, each with an optional watched-path filter
+type Subscriptions = HashMap<CoordId, Option<PointerFilter>>;
+
+impl CoordOpParams {
+    async fn into_coord_ids(self, facade: &BmsFacade) -> Result<Vec<CoordId>, String> {
+        let mut coord_ids = Vec::new();
+        for input in self.coord_id.into_iter().chain(self.coord_ids) {
+            coord_ids.push(coord_param(facade, &input).await?);
+        }
+        Ok(coord_ids)
+    }
+}
+
+/// Upgrade to a WebSocket session
+///
+/// The session acts as the key of the upgrade request's bearer token, and
+/// each op is checked against the coordinate's ACL like the HTTP routes.
+pub async fn ws_handler(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+    ws: WebSocketUpgrade,
+) -> Response {
+    let caller = Caller::from_headers(&app, &headers);
+    // Store messages get the same size limit and delta policy as `/store`
+    // bodies, as configured when the session opened
+    let config = app.config.load();
+    let ws = ws.max_message_size(config.body_limits.store);
+    upgrade(ws, app.facade.clone(), caller, config.delta_policy.clone())
+}
+
+fn upgrade(ws: WebSocketUpgrade, facade: Arc<BmsFacade>, caller: Caller, policy: DeltaPolicy) -> Response {
+    ws.on_upgrade(move |socket| run_session(socket, facade, caller, policy))
+}
+
+/// Drive one connection until the client closes it
+async fn run_session(mut socket: WebSocket, facade: Arc<BmsFacade>, caller: Caller, policy: DeltaPolicy) {
+    let mut events = facade.subscribe();
+    let mut subscriptions = Subscriptions::new();
+
+    loop {
+        tokio::select! {
+            incoming = socket.recv() => {
+                let text = match incoming {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(Message::Close(_))) | None => break,
+                    Some(Ok(_)) => continue,
+                    Some(Err(e)) => {
+                        debug!("WebSocket receive failed: {}", e);
+                        break;
+                    }
+                };
+
+                let response = handle_request(&text, &facade, &caller, &policy, &mut subscriptions).await;
+                if send_json(&mut socket, &response).await.is_err() {
+                    break;
+                }
+            }
+            event = events.recv() => {
+                let sent = match event {
+                    Ok(event) => match subscriptions.get(&event.coord_id) {
+                        Some(None) => {
+                            send_json(&mut socket, &WsEvent::Delta { delta: &event, matched: None }).await
+                        }
+                        Some(Some(filter)) => {
+                            let matched = filter.matches(&event.touched);
+                            if matched.is_empty() {
+                                Ok(())
+                            } else {
+                                let event = WsEvent::Delta { delta: &event, matched: Some(matched) };
+                                send_json(&mut socket, &event).await
+                            }
+                        }
+                        None => Ok(()),
+                    },
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("WebSocket session lagged by {} events, coalescing", skipped);
+                        send_head_moved(&mut socket, &facade, &subscriptions).await
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                if sent.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+async fn handle_request(
+    text: &str,
+    facade: &BmsFacade,
+    caller: &Caller,
+    policy: &DeltaPolicy,
+    subscriptions: &mut Subscriptions,
+) -> WsResponse {
+    let request: WsRequest = match serde_json::from_str(text) {
+        Ok(request) => request,
+        Err(e) => {
+            return WsResponse {
+                id: Value::Null,
+                ok: false,
+                result: None,
+                error: Some(format!("Invalid request: {}", e)),
+            }
+        }
+    };
+
+    let result = match request.op {
+        WsOp::Store => store(facade, caller, policy, request.params).await,
+        WsOp::Recall => recall(facade, caller, request.params).await,
+        WsOp::Subscribe => subscribe(facade, caller, request.params, subscriptions).await,
+        WsOp::Unsubscribe => unsubscribe(facade, request.params, subscriptions).await,
+    };
+
+    match result {
+        Ok(result) => WsResponse {
+            id: request.id,
+            ok: true,
+            result: Some(result),
+            error: None,
+        },
+        Err(e) => WsResponse {
+            id: request.id,
+            ok: false,
+            result: None,
+            error: Some(e),
+        },
+    }
+}
+
+/// Add or replace subscriptions; the pointer filter applies to every listed coordinate
+async fn subscribe(
+    facade: &BmsFacade,
+    caller: &Caller,
+    params: Value,
+    subscriptions: &mut Subscriptions,
+) -> Result<Value, String> {
+    let mut params: CoordOpParams = parse_params(params)?;
+    let pointers = params.pointers.take();
+    let filter = pointers
+        .clone()
+        .map(PointerFilter::new)
+        .transpose()
+        .map_err(|e| e.to_string())?;
+
+    let coord_ids = params.into_coord_ids(facade).await?;
+    for coord_id in &coord_ids {
+        authorize(facade, caller, coord_id, Access::Read).await?;
+    }
+    for coord_id in &coord_ids {
+        subscriptions.insert(coord_id.clone(), filter.clone());
+    }
+    Ok(serde_json::json!({ "subscribed": coord_ids, "pointers": pointers }))
+}
+
+async fn unsubscribe(facade: &BmsFacade, params: Value, subscriptions: &mut Subscriptions) -> Result<Value, String> {
+    let coord_ids = parse_params::<CoordOpParams>(params)?.into_coord_ids(facade).await?;
+    for coord_id in &coord_ids {
+        subscriptions.remove(coord_id);
+    }
+    Ok(serde_json::json!({ "unsubscribed": coord_ids }))
+}
+
+async fn store(facade: &BmsFacade, caller: &Caller, policy: &DeltaPolicy, params: Value) -> Result<Value, String> {
+    let params: StoreOpParams = parse_params(params)?;
+    policy.check_state(&params.state).map_err(|e| e.to_string())?;
+    let coord_id = match &params.coord_id {
+        Some(input) => Some(coord_param(facade, input).await?),
+        None => None,
+    };
+    if let Some(coord_id) = &coord_id {
+        authorize(facade, caller, coord_id, Access::Write).await?;
+    }
+    let outcome = facade
+        .store(StoreParams {
+            coord_id,
+            state: params.state,
+            metadata: params.metadata,
+            author: params.author,
+            precondition: params
+                .if_match
+                .map(|h| StorePrecondition::HeadChainHash(Hash(h))),
+            diff_options: params.diff_options,
+            explain: params.explain,
+            // Sessions carry no admin scope, so they cannot backdate deltas
+            created_at: None,
+            op_authors: params.op_authors,
+            policy: Some(policy.clone()),
+            namespace: params.namespace,
+            hash_algorithm: None,
+            parent_coord_id: None,
+            child_label: None,
+        })
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let chain_hash = outcome.head.chain_hash.clone();
+    let mut result = serde_json::to_value(StoreResponse::from(outcome)).map_err(|e| e.to_string())?;
+    // Deprecated, same as `head.chain_hash`; removed in the next release
+    result["chain_hash"] = serde_json::json!(chain_hash);
+    Ok(result)
+}
+
+async fn recall(facade: &BmsFacade, caller: &Caller, params: Value) -> Result<Value, String> {
+    let params: CoordOpParams = parse_params(params)?;
+    let Some(input) = params.coord_id else {
+        return Err("recall requires coord_id".to_string());
+    };
+    let coord_id = coord_param(facade, &input).await?;
+    authorize(facade, caller, &coord_id, Access::Read).await?;
+
+    let head = facade
+        .head(&coord_id)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("No deltas found for coordinate: {}", coord_id))?;
+    let chain_hash = head.deltas.last().map(|d| d.chain_hash.clone());
+    let rules = facade
+        .redaction_rules(&coord_id)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(serde_json::json!({
+        "coord_id": coord_id,
+        "state": bms_core::redact(&head.state, &rules),
+        "delta_count": head.deltas.len(),
+        "chain_hash": chain_hash,
+    }))
+}
+
+/// Check the coordinate's ACL, with the error as reply text
+async fn authorize(facade: &BmsFacade, caller: &Caller, coord_id: &CoordId, access: Access) -> Result<(), String> {
+    let coordinate = facade
+        .repository()
+        .get_coordinate(coord_id)
+        .await
+        .map_err(|e| e.to_string())?;
+    caller
+        .check(coord_id, coordinate.and_then(|c| c.metadata).as_ref(), access)
+        .map_err(|e| match e {
+            AppError::ForbiddenCode { message, .. } => message,
+            other => format!("{:?}", other),
+        })
+}
+
+fn parse_params<T: serde::de::DeserializeOwned>(params: Value) -> Result<T, String> {
+    serde_json::from_value(params).map_err(|e| format!("Invalid params: {}", e))
+}
+
+async fn coord_param(facade: &BmsFacade, input: &str) -> Result<CoordId, String> {
+    parse_coord_id(facade, input).await.map_err(|e| e.to_string())
+}
+
+/// Tell the client which subscribed heads may have moved while it lagged
+async fn send_head_moved(
+    socket: &mut WebSocket,
+    facade: &BmsFacade,
+    subscriptions: &Subscriptions,
+) -> Result<(), axum::Error> {
+    for coord_id in subscriptions.keys() {
+        let last = match facade.repository().get_deltas(coord_id).await {
+            Ok(deltas) => deltas.last().cloned(),
+            Err(e) => {
+                warn!("Failed to read head of {}: {}", coord_id, e);
+                None
+            }
+        };
+        let event = WsEvent::HeadMoved {
+            coord_id,
+            delta_id: last.as_ref().map(|d| d.id.clone()),
+            chain_hash: last.map(|d| d.chain_hash),
+        };
+        send_json(socket, &event).await?;
+    }
+    Ok(())
+}
+
+async fn send_json<T: Serialize>(socket: &mut WebSocket, value: &T) -> Result<(), axum::Error> {
+    let text = serde_json::to_string(value).expect("WebSocket messages are serializable");
+    socket.send(Message::Text(text)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{routing::get, Router};
+    use bms_core::SnapshotManager;
+    use bms_storage::BmsRepository;
+    use futures_util::{SinkExt, StreamExt};
+    use tokio_tungstenite::tungstenite::Message as ClientMessage;
+
+    #[tokio::test]
+    async fn test_subscribe_store_notification_ordering() {
+        let db_path = std::env::temp_dir().join(format!("bms-ws-test-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&db_path);
+        let repository = BmsRepository::new(&db_path).await.unwrap();
+        let facade = Arc::new(BmsFacade::new(repository, SnapshotManager::new(128)));
+
+        let app = Router::new().route(
+            "/ws",
+            get(move |ws: WebSocketUpgrade| async move {
+                upgrade(ws, facade, Caller::key("anonymous"), DeltaPolicy::default())
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let url = format!("ws://{}/ws", addr);
+        let (mut watcher, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+        let (mut writer, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+
+        async fn request<S>(socket: &mut S, body: Value) -> Value
+        where
+            S: SinkExt<ClientMessage> + StreamExt<Item = tokio_tungstenite::tungstenite::Result<ClientMessage>> + Unpin,
+            <S as futures_util::Sink<ClientMessage>>::Error: std::fmt::Debug,
+        {
+            socket.send(ClientMessage::Text(body.to_string())).await.unwrap();
+            next_json(socket).await
+        }
+
+        async fn next_json<S>(socket: &mut S) -> Value
+        where
+            S: StreamExt<Item = tokio_tungstenite::tungstenite::Result<ClientMessage>> + Unpin,
+        {
+            match socket.next().await.unwrap().unwrap() {
+                ClientMessage::Text(text) => serde_json::from_str(&text).unwrap(),
+                other => panic!("unexpected message: {:?}", other),
+            }
+        }
+
+        let ack = request(
+            &mut watcher,
+            serde_json::json!({"id": 1, "op": "subscribe", "params": {"coord_id": "WSTEST"}}),
+        )
+        .await;
+        assert_eq!(ack["ok"], true);
+
+        let mut chain_hashes = Vec::new();
+        for n in 0..3 {
+            let reply = request(
+                &mut writer,
+                serde_json::json!({
+                    "id": n,
+                    "op": "store",
+                    "params": {"coord_id": "WSTEST", "state": {"n": n}}
+                }),
+            )
+            .await;
+            assert_eq!(reply["ok"], true);
+            chain_hashes.push(reply["result"]["chain_hash"].clone());
+        }
+
+        for chain_hash in &chain_hashes {
+            let event = next_json(&mut watcher).await;
+            assert_eq!(event["event"], "delta");
+            assert_eq!(event["coord_id"], "WSTEST");
+            assert_eq!(&event["chain_hash"], chain_hash);
+        }
+
+        let recall = request(
+            &mut watcher,
+            serde_json::json!({"id": "r", "op": "recall", "params": {"coord_id": "WSTEST"}}),
+        )
+        .await;
+        assert_eq!(recall["id"], "r");
+        assert_eq!(recall["result"]["state"], serde_json::json!({"n": 2}));
+
+        let _ = std::fs::remove_file(&db_path);
+    }
+
+    #[tokio::test]
+    async fn test_pointer_subscription_skips_unwatched_deltas() {
+        let db_path = std::env::temp_dir().join(format!("bms-ws-pointer-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&db_path);
+        let repository = BmsRepository::new(&db_path).await.unwrap();
+        let facade = Arc::new(BmsFacade::new(repository, SnapshotManager::new(128)));
+
+        let app = Router::new().route(
+            "/ws",
+            get(move |ws: WebSocketUpgrade| async move {
+                upgrade(ws, facade, Caller::key("anonymous"), DeltaPolicy::default())
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let url = format!("ws://{}/ws", addr);
+        let (mut watcher, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+        let (mut writer, _) = tokio_tungstenite::connect_async(&url).await.unwrap();
+
+        async fn next_json<S>(socket: &mut S) -> Value
+        where
+            S: StreamExt<Item = tokio_tungstenite::tungstenite::Result<ClientMessage>> + Unpin,
+        {
+            match socket.next().await.unwrap().unwrap() {
+                ClientMessage::Text(text) => serde_json::from_str(&text).unwrap(),
+                other => panic!("unexpected message: {:?}", other),
+            }
+        }
+
+        let subscribe = serde_json::json!({
+            "id": 1,
+            "op": "subscribe",
+            "params": {"coord_id": "WSPOINTER", "pointers": ["/status"]}
+        });
+        watcher.send(ClientMessage::Text(subscribe.to_string())).await.unwrap();
+        assert_eq!(next_json(&mut watcher).await["ok"], true);
+
+        let states = [
+            serde_json::json!({"status": "idle", "log": []}),
+            serde_json::json!({"status": "idle", "log": ["hello"]}),
+            serde_json::json!({"status": "busy", "log": ["hello"]}),
+        ];
+        let mut chain_hashes = Vec::new();
+        for (n, state) in states.iter().enumerate() {
+            let store = serde_json::json!({
+                "id": n,
+                "op": "store",
+                "params": {"coord_id": "WSPOINTER", "state": state}
+            });
+            writer.send(ClientMessage::Text(store.to_string())).await.unwrap();
+            let reply = next_json(&mut writer).await;
+            assert_eq!(reply["ok"], true);
+            chain_hashes.push(reply["result"]["chain_hash"].clone());
+        }
+
+        // The log append is suppressed
+        for chain_hash in [&chain_hashes[0], &chain_hashes[2]] {
+            let event = next_json(&mut watcher).await;
+            assert_eq!(event["event"], "delta");
+            assert_eq!(&event["chain_hash"], chain_hash);
+            assert_eq!(event["matched"], serde_json::json!(["/status"]));
+        }
+
+        let _ = std::fs::remove_file(&db_path);
+    }
+}
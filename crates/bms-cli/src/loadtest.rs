@@ -0,0 +1,489 @@
+//! HTTP load generator for a running BMS API
+//!
+//! Workers loop over a weighted mix of store/recall/search requests until the
+//! deadline, recording per-operation latencies. Every coordinate written uses
+//! a per-run prefix so load-test data is easy to tell apart from real data.
+
+use anyhow::{anyhow, bail, Context, Result};
+use bms_storage::simulate::{self, SimulationProfile};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use reqwest::StatusCode;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Coordinates each worker keeps evolving and recalling
+const COORDS_PER_WORKER: usize = 8;
+
+/// Longest backoff after a 429 without a usable Retry-After header
+const MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Op {
+    Store,
+    Recall,
+    Search,
+}
+
+impl Op {
+    fn name(self) -> &'static str {
+        match self {
+            Op::Store => "store",
+            Op::Recall => "recall",
+            Op::Search => "search",
+        }
+    }
+}
+
+/// Relative weights of each operation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpMix {
+    pub store: u32,
+    pub recall: u32,
+    pub search: u32,
+}
+
+impl OpMix {
+    /// Parse `store=70,recall=20,search=10`; omitted operations get weight 0
+    pub fn parse(input: &str) -> Result<Self> {
+        let mut mix = OpMix { store: 0, recall: 0, search: 0 };
+        for part in input.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (name, weight) = part
+                .split_once('=')
+                .ok_or_else(|| anyhow!("Invalid mix entry '{}' (expected op=weight)", part))?;
+            let weight: u32 = weight
+                .trim()
+                .parse()
+                .with_context(|| format!("Invalid weight in '{}'", part))?;
+            match name.trim() {
+                "store" => mix.store = weight,
+                "recall" => mix.recall = weight,
+                "search" => mix.search = weight,
+                other => bail!("Unknown operation '{}' (expected store, recall, or search)", other),
+            }
+        }
+
+        if mix.store + mix.recall + mix.search == 0 {
+            bail!("Operation mix has no weight");
+        }
+        Ok(mix)
+    }
+
+    fn pick(&self, rng: &mut ChaCha8Rng) -> Op {
+        let roll = rng.gen_range(0..self.store + self.recall + self.search);
+        if roll < self.store {
+            Op::Store
+        } else if roll < self.store + self.recall {
+            Op::Recall
+        } else {
+            Op::Search
+        }
+    }
+}
+
+/// Parse a duration such as `500ms`, `60s`, `2m` (bare numbers are seconds)
+pub fn parse_duration(input: &str) -> Result<Duration> {
+    let input = input.trim();
+    let (digits, unit): (&str, fn(u64) -> Duration) = if let Some(d) = input.strip_suffix("ms") {
+        (d, Duration::from_millis)
+    } else if let Some(d) = input.strip_suffix('s') {
+        (d, Duration::from_secs)
+    } else if let Some(d) = input.strip_suffix('m') {
+        (d, |m| Duration::from_secs(m * 60))
+    } else {
+        (input, Duration::from_secs)
+    };
+
+    digits
+        .parse::<u64>()
+        .map(unit)
+        .with_context(|| format!("Invalid duration '{}'", input))
+}
+
+/// Load test parameters
+#[derive(Debug, Clone)]
+pub struct LoadTestConfig {
+    pub target: String,
+    pub workers: usize,
+    pub duration: Duration,
+    pub mix: OpMix,
+    pub state_size: RangeInclusive<usize>,
+    pub profile: SimulationProfile,
+    pub token: Option<String>,
+    pub seed: u64,
+}
+
+/// Latency summary for one operation
+#[derive(Debug, Clone, Serialize)]
+pub struct OpStats {
+    pub count: u64,
+    pub errors: u64,
+    pub rate_limited: u64,
+    pub ops_per_sec: f64,
+    pub p50_ms: f64,
+    pub p90_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+}
+
+/// Final report
+#[derive(Debug, Clone, Serialize)]
+pub struct LoadTestReport {
+    pub run_id: String,
+    pub workers: usize,
+    pub elapsed_secs: f64,
+    pub operations: BTreeMap<&'static str, OpStats>,
+    /// Coordinates written by this run
+    pub coordinates: Vec<String>,
+}
+
+impl LoadTestReport {
+    pub fn print(&self) {
+        println!("Load test {} ({} workers, {:.1}s):", self.run_id, self.workers, self.elapsed_secs);
+        println!(
+            "  {:<8} {:>8} {:>7} {:>6} {:>9} {:>8} {:>8} {:>8} {:>8}",
+            "OP", "COUNT", "ERRORS", "429S", "OPS/S", "P50 MS", "P90 MS", "P99 MS", "MAX MS"
+        );
+        for (name, s) in &self.operations {
+            println!(
+                "  {:<8} {:>8} {:>7} {:>6} {:>9.1} {:>8.2} {:>8.2} {:>8.2} {:>8.2}",
+                name, s.count, s.errors, s.rate_limited, s.ops_per_sec, s.p50_ms, s.p90_ms, s.p99_ms, s.max_ms
+            );
+        }
+        println!("  Coordinates written: {}", self.coordinates.len());
+    }
+}
+
+#[derive(Default)]
+struct OpSamples {
+    latencies_us: Vec<u64>,
+    errors: u64,
+    rate_limited: u64,
+}
+
+#[derive(Default)]
+struct Shared {
+    samples: Mutex<BTreeMap<Op, OpSamples>>,
+    coordinates: Mutex<Vec<String>>,
+    auth_failure: Mutex<Option<String>>,
+    stop: AtomicBool,
+}
+
+impl Shared {
+    fn record(&self, op: Op, outcome: &Outcome, elapsed: Duration) {
+        let mut samples = self.samples.lock().unwrap_or_else(|e| e.into_inner());
+        let entry = samples.entry(op).or_default();
+        match outcome {
+            Outcome::Ok => entry.latencies_us.push(elapsed.as_micros() as u64),
+            Outcome::RateLimited(_) => entry.rate_limited += 1,
+            Outcome::Failed => entry.errors += 1,
+        }
+    }
+}
+
+enum Outcome {
+    Ok,
+    RateLimited(Option<Duration>),
+    Failed,
+}
+
+struct Worker {
+    client: reqwest::Client,
+    config: Arc<LoadTestConfig>,
+    shared: Arc<Shared>,
+    rng: ChaCha8Rng,
+    prefix: String,
+    /// Current state per coordinate this worker owns
+    coords: Vec<(String, Value, usize)>,
+    backoff: Duration,
+}
+
+/// Run the load test until the configured duration elapses
+pub async fn run(config: LoadTestConfig) -> Result<LoadTestReport> {
+    let run_id = format!("{:08x}", rand::random::<u32>());
+    let target = config.target.trim_end_matches('/').to_string();
+    let config = Arc::new(LoadTestConfig { target, ..config });
+    let shared = Arc::new(Shared::default());
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(30))
+        .build()?;
+
+    let started = Instant::now();
+    let deadline = started + config.duration;
+    let mut handles = Vec::new();
+    for index in 0..config.workers.max(1) {
+        let mut worker = Worker {
+            client: client.clone(),
+            config: config.clone(),
+            shared: shared.clone(),
+            rng: ChaCha8Rng::seed_from_u64(config.seed.wrapping_add(index as u64)),
+            prefix: format!("LOADTEST-{}-W{}", run_id, index),
+            coords: Vec::new(),
+            backoff: Duration::from_millis(50),
+        };
+        handles.push(tokio::spawn(async move { worker.run(deadline).await }));
+    }
+    for handle in handles {
+        handle.await?;
+    }
+    let elapsed = started.elapsed();
+
+    if let Some(message) = shared.auth_failure.lock().unwrap_or_else(|e| e.into_inner()).take() {
+        bail!("Aborted: authentication failed ({})", message);
+    }
+
+    let samples = std::mem::take(&mut *shared.samples.lock().unwrap_or_else(|e| e.into_inner()));
+    let operations = samples
+        .into_iter()
+        .map(|(op, s)| (op.name(), summarize(s, elapsed)))
+        .collect();
+    let coordinates = std::mem::take(&mut *shared.coordinates.lock().unwrap_or_else(|e| e.into_inner()));
+
+    Ok(LoadTestReport {
+        run_id,
+        workers: config.workers.max(1),
+        elapsed_secs: elapsed.as_secs_f64(),
+        operations,
+        coordinates,
+    })
+}
+
+/// Delete the coordinates written by a run
+///
+/// Returns how many were removed. Stops at the first 404/405 on the first
+/// coordinate, since that means the server has no delete endpoint.
+pub async fn cleanup(config: &LoadTestConfig, coordinates: &[String]) -> Result<usize> {
+    let client = reqwest::Client::new();
+    let target = config.target.trim_end_matches('/');
+    let mut deleted = 0;
+
+    for coord_id in coordinates {
+        let mut request = client.delete(format!("{}/coords/{}", target, coord_id));
+        if let Some(token) = &config.token {
+            request = request.bearer_auth(token);
+        }
+        let response = request.send().await?;
+        match response.status() {
+            status if status.is_success() => deleted += 1,
+            StatusCode::NOT_FOUND | StatusCode::METHOD_NOT_ALLOWED if deleted == 0 => {
+                bail!("server does not support deleting coordinates");
+            }
+            status => bail!("failed to delete {}: {}", coord_id, status),
+        }
+    }
+
+    Ok(deleted)
+}
+
+fn summarize(mut samples: OpSamples, elapsed: Duration) -> OpStats {
+    samples.latencies_us.sort_unstable();
+    let percentile = |p: f64| -> f64 {
+        if samples.latencies_us.is_empty() {
+            return 0.0;
+        }
+        let rank = ((samples.latencies_us.len() - 1) as f64 * p).round() as usize;
+        samples.latencies_us[rank] as f64 / 1000.0
+    };
+
+    OpStats {
+        count: samples.latencies_us.len() as u64,
+        errors: samples.errors,
+        rate_limited: samples.rate_limited,
+        ops_per_sec: samples.latencies_us.len() as f64 / elapsed.as_secs_f64().max(f64::EPSILON),
+        p50_ms: percentile(0.50),
+        p90_ms: percentile(0.90),
+        p99_ms: percentile(0.99),
+        max_ms: percentile(1.0),
+    }
+}
+
+impl Worker {
+    async fn run(&mut self, deadline: Instant) {
+        while Instant::now() < deadline && !self.shared.stop.load(Ordering::Relaxed) {
+            let mut op = self.config.mix.pick(&mut self.rng);
+            if op == Op::Recall && self.coords.is_empty() {
+                op = Op::Store;
+            }
+
+            let started = Instant::now();
+            let outcome = match op {
+                Op::Store => self.store().await,
+                Op::Recall => self.recall().await,
+                Op::Search => self.search().await,
+            };
+            self.shared.record(op, &outcome, started.elapsed());
+
+            match outcome {
+                Outcome::RateLimited(retry_after) => {
+                    let wait = retry_after.unwrap_or(self.backoff).min(MAX_BACKOFF);
+                    self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+                    tokio::time::sleep(wait).await;
+                }
+                _ => self.backoff = Duration::from_millis(50),
+            }
+        }
+    }
+
+    async fn store(&mut self) -> Outcome {
+        let (coord_id, state) = if self.coords.len() < COORDS_PER_WORKER {
+            let coord_id = format!("{}-C{}", self.prefix, self.coords.len());
+            let target_size = self.rng.gen_range(self.config.state_size.clone());
+            let state = self.config.profile.initial(&mut self.rng, target_size);
+            self.coords.push((coord_id.clone(), state.clone(), target_size));
+            self.shared
+                .coordinates
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .push(coord_id.clone());
+            (coord_id, state)
+        } else {
+            let index = self.rng.gen_range(0..self.coords.len());
+            let (coord_id, state, target_size) = &mut self.coords[index];
+            self.config.profile.evolve(state, &mut self.rng, *target_size);
+            (coord_id.clone(), state.clone())
+        };
+
+        let body = json!({"coord_hint": coord_id, "state": state, "author": "loadtest"});
+        self.send(self.client.post(format!("{}/store", self.config.target)).json(&body))
+            .await
+    }
+
+    async fn recall(&mut self) -> Outcome {
+        let index = self.rng.gen_range(0..self.coords.len());
+        let url = format!("{}/recall/{}", self.config.target, self.coords[index].0);
+        self.send(self.client.get(url)).await
+    }
+
+    async fn search(&mut self) -> Outcome {
+        let words = self.rng.gen_range(2..6);
+        let body = json!({"query": simulate::sentence(&mut self.rng, words), "limit": 5});
+        self.send(self.client.post(format!("{}/search", self.config.target)).json(&body))
+            .await
+    }
+
+    async fn send(&self, request: reqwest::RequestBuilder) -> Outcome {
+        let request = match &self.config.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        };
+
+        let response = match request.send().await {
+            Ok(response) => response,
+            Err(_) => return Outcome::Failed,
+        };
+
+        match response.status() {
+            // Read the full body so latency covers the whole response
+            status if status.is_success() => match response.bytes().await {
+                Ok(_) => Outcome::Ok,
+                Err(_) => Outcome::Failed,
+            },
+            StatusCode::TOO_MANY_REQUESTS => {
+                let retry_after = response
+                    .headers()
+                    .get(reqwest::header::RETRY_AFTER)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| v.parse::<u64>().ok())
+                    .map(Duration::from_secs);
+                Outcome::RateLimited(retry_after)
+            }
+            status @ (StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN) => {
+                let body = response.text().await.unwrap_or_default();
+                self.shared
+                    .auth_failure
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .get_or_insert_with(|| format!("{}: {}", status, body.trim()));
+                self.shared.stop.store(true, Ordering::Relaxed);
+                Outcome::Failed
+            }
+            _ => Outcome::Failed,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        extract::Path,
+        routing::{get, post},
+        Json, Router,
+    };
+
+    #[test]
+    fn test_parse_mix_and_duration() {
+        let mix = OpMix::parse("store=70,recall=20,search=10").unwrap();
+        assert_eq!(mix, OpMix { store: 70, recall: 20, search: 10 });
+        assert_eq!(OpMix::parse("store=1").unwrap().recall, 0);
+        assert!(OpMix::parse("store=0").is_err());
+        assert!(OpMix::parse("delete=5").is_err());
+
+        assert_eq!(parse_duration("60s").unwrap(), Duration::from_secs(60));
+        assert_eq!(parse_duration("2m").unwrap(), Duration::from_secs(120));
+        assert_eq!(parse_duration("250ms").unwrap(), Duration::from_millis(250));
+        assert!(parse_duration("soon").is_err());
+    }
+
+    async fn spawn_server(app: Router) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{}", addr)
+    }
+
+    fn config(target: String, duration: Duration) -> LoadTestConfig {
+        LoadTestConfig {
+            target,
+            workers: 4,
+            duration,
+            mix: OpMix::parse("store=70,recall=20,search=10").unwrap(),
+            state_size: 512..=2048,
+            profile: SimulationProfile::Chatlog,
+            token: None,
+            seed: 1,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_smoke_against_test_server() {
+        let app = Router::new()
+            .route(
+                "/store",
+                post(|Json(body): Json<Value>| async move {
+                    Json(json!({"coord_id": body["coord_hint"], "delta_id": "d", "snapshot_created": false}))
+                }),
+            )
+            .route(
+                "/recall/:coord_id",
+                get(|Path(coord_id): Path<String>| async move {
+                    Json(json!({"coord_id": coord_id, "state": {}, "delta_count": 1}))
+                }),
+            )
+            .route("/search", post(|| async { Json(json!({"results": []})) }));
+        let target = spawn_server(app).await;
+
+        let report = run(config(target, Duration::from_secs(2))).await.unwrap();
+
+        let store = &report.operations["store"];
+        assert!(store.count > 0);
+        assert_eq!(store.errors, 0);
+        assert!(report.operations.contains_key("recall"));
+        assert!(report.coordinates.iter().all(|c| c.starts_with("LOADTEST-")));
+        assert!(report.elapsed_secs >= 2.0);
+    }
+
+    #[tokio::test]
+    async fn test_aborts_on_auth_failure() {
+        let app = Router::new().fallback(|| async { (axum::http::StatusCode::UNAUTHORIZED, "missing key") });
+        let target = spawn_server(app).await;
+
+        let err = run(config(target, Duration::from_secs(30))).await.unwrap_err();
+        assert!(err.to_string().contains("authentication failed"));
+    }
+}
@@ -1,10 +1,30 @@
 use anyhow::Result;
-use bms_core::{types::*, CoordinateGenerator, DeltaEngine, SnapshotManager};
-use bms_storage::BmsRepository;
-use clap::{Parser, Subcommand};
+use bms_core::humanize::iec_bytes;
+use bms_core::importance::DEFAULT_IMPORTANCE;
+use bms_core::delta::PATCH_RATIO_BOUNDS;
+use bms_core::{
+    types::*, CoordinateGenerator, DeltaEncoding, DeltaEngine, DiffOptions, FilterExpr, ImportancePolicy, PatchRatios,
+    SnapshotManager, DEFAULT_SNAPSHOT_INTERVAL,
+};
+use bms_storage::drill::{self, DrillConfig, DrillFailure};
+use bms_api::{ReplicationConfig, Replicator};
+use bms_storage::oplog;
+use bms_storage::planner::{self, CostModel, PlanAction};
+use bms_storage::simulate::{self, SimulationConfig};
+use bms_storage::{
+    resolve_snapshot_interval, BmsFacade, BmsRepository, IntegrityChecks, StoreParams, StorePrecondition,
+};
+use clap::{Args, Parser, Subcommand};
+use serde::Deserialize;
 use serde_json::Value;
-use tracing::info;
-use bms_vector::{EmbeddingGenerator, InMemoryVectorStore, VectorConfig, VectorMetadata, SearchFilter as VecSearchFilter, VectorStore};
+use std::collections::{HashMap, HashSet};
+use resolve::{resolve_coord, resolve_delta, resolve_hint, IdFormat};
+use std::sync::Arc;
+use tracing::{info, warn};
+
+mod loadtest;
+mod resolve;
+mod sync;
 
 #[derive(Parser)]
 #[command(name = "bms")]
@@ -14,6 +34,37 @@ struct Cli {
     #[arg(short, long, default_value = "./bms.db")]
     db_path: String,
 
+    /// Hours for coordinate importance to halve (0 disables decay)
+    #[arg(long, env = "BMS_IMPORTANCE_HALF_LIFE_HOURS", default_value_t = 168.0)]
+    importance_half_life_hours: f64,
+
+    /// Run every read-path integrity check and fail instead of repairing
+    #[arg(long, env = "BMS_STRICT", value_parser = clap::builder::FalseyValueParser::new())]
+    strict: bool,
+
+    /// Deltas between snapshots; replaces the interval stored in the database
+    /// for this and later runs
+    #[arg(long, env = "BMS_SNAPSHOT_INTERVAL")]
+    snapshot_interval: Option<u32>,
+
+    /// Encoding of newly written delta ops: json, or cbor with the cbor-ops
+    /// feature (default: cbor when built with it, json otherwise)
+    #[arg(long, env = "BMS_DELTA_ENCODING")]
+    delta_encoding: Option<DeltaEncoding>,
+
+    /// Print full coordinate and delta IDs instead of shortened ones
+    #[arg(long, global = true)]
+    full_ids: bool,
+
+    /// Characters of each ID to print when shortening (at least 6)
+    #[arg(long, global = true, env = "BMS_SHORT_ID_LEN", default_value_t = resolve::DEFAULT_SHORT_ID_LEN)]
+    short_id_len: usize,
+
+    /// Namespace that store creates coordinates in and list shows, such as
+    /// agents/planner (default: the default namespace; list shows all)
+    #[arg(long, global = true, env = "BMS_NAMESPACE")]
+    namespace: Option<String>,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -26,28 +77,233 @@ enum Commands {
         #[arg(short, long)]
         state: String,
 
-        /// Optional coordinate hint
+        /// Optional coordinate hint: a generated ID, or a name of letters, digits, -, _, and .
         #[arg(short, long)]
         coord: Option<String>,
+
+        /// Only store if the head chain hash matches (as returned in the API ETag)
+        #[arg(long)]
+        if_match: Option<String>,
+
+        /// Print the store outcome as JSON
+        #[arg(long)]
+        json: bool,
+
+        /// Include per-phase timings in the outcome
+        #[arg(long)]
+        explain: bool,
+
+        /// Historical RFC 3339 timestamp for the delta (for importers)
+        #[arg(long)]
+        created_at: Option<chrono::DateTime<chrono::Utc>>,
+
+        /// Create the coordinate as scratch space, unlisted and deleted once
+        /// idle unless promoted
+        #[arg(long)]
+        ephemeral: bool,
+    },
+
+    /// Store several states atomically from a JSON file
+    StoreGroup {
+        /// File with `{"items": [{"coord_hint", "state", "expected_head_delta_id", ...}]}`;
+        /// expected head delta IDs may be prefixes
+        #[arg(short, long)]
+        file: String,
+
+        /// Print the store outcomes as JSON
+        #[arg(long)]
+        json: bool,
     },
 
     /// Recall a state
     Recall {
-        /// Coordinate ID
+        /// Coordinate ID, alias, or ID prefix
         coord_id: String,
     },
 
     /// List all coordinates
-    List,
+    List {
+        /// Order by created, importance, or size
+        #[arg(long, default_value = "created")]
+        sort: String,
+
+        /// Also list ephemeral coordinates
+        #[arg(long)]
+        include_ephemeral: bool,
+    },
+
+    /// Keep an ephemeral coordinate: list it, search it, and stop deleting it when idle
+    Promote {
+        /// Coordinate ID, alias, or ID prefix
+        coord_id: String,
+    },
+
+    /// Add to a coordinate's importance (negative values demote)
+    Reinforce {
+        /// Coordinate ID, alias, or ID prefix
+        coord_id: String,
+        #[arg(short, long, default_value_t = 0.1, allow_hyphen_values = true)]
+        delta: f32,
+    },
+
+    /// Undo the last deltas of a coordinate by appending their inverse
+    Rollback {
+        /// Coordinate ID, alias, or ID prefix
+        coord_id: String,
+        /// Number of deltas to undo
+        #[arg(short = 'n', long, default_value_t = 1)]
+        steps: usize,
+        /// Author recorded on the new delta
+        #[arg(short, long)]
+        author: Option<String>,
+    },
+
+    /// Squash a coordinate's old deltas into one, anchored by a snapshot
+    Compact {
+        /// Coordinate ID, alias, or ID prefix
+        coord_id: String,
+        /// Number of most recent deltas to keep as they are
+        #[arg(long, default_value_t = 0)]
+        keep_last: usize,
+    },
+
+    /// Rewrite stored delta ops in the configured encoding
+    RecodeDeltas,
 
     /// Verify chain integrity
     Verify {
-        /// Coordinate ID
+        /// Coordinate ID, alias, or ID prefix
+        coord_id: String,
+    },
+
+    /// List a coordinate's deltas with who wrote their ops
+    History {
+        /// Coordinate ID, alias, or ID prefix
         coord_id: String,
+        /// Only deltas with at least one op by this author
+        #[arg(long)]
+        author: Option<String>,
     },
 
     /// Show statistics
-    Stats,
+    Stats {
+        /// Show how well one coordinate's chain compresses instead (ID, alias, or ID prefix)
+        #[arg(conflicts_with_all = ["hot", "recompute_sizes"])]
+        coord_id: Option<String>,
+        /// List the most-read coordinates (recorded by the API server)
+        #[arg(long)]
+        hot: bool,
+        /// Max coordinates to list with --hot
+        #[arg(short, long, default_value_t = 20)]
+        limit: i64,
+        /// Measure every head state and record its size
+        #[arg(long, conflicts_with = "hot")]
+        recompute_sizes: bool,
+        /// Heads reconstructed at once with --recompute-sizes
+        #[arg(long, default_value_t = 4)]
+        jobs: usize,
+    },
+
+    /// Check every coordinate for invalid IDs, broken chains, and drifted
+    /// materialized heads, and list dangling links
+    Fsck {
+        /// Rebuild materialized heads that drifted from their chain
+        #[arg(long)]
+        rebuild_heads: bool,
+    },
+
+    /// Restore a backup into a scratch directory and verify the copy
+    ///
+    /// Exits with 2 if the backup cannot be restored, 3 if it cannot be
+    /// opened with the current schema, and 4 if an integrity check fails.
+    Drill {
+        /// Backup database file
+        #[arg(long)]
+        backup: std::path::PathBuf,
+        /// Coordinates to verify, chosen at random
+        #[arg(long, default_value_t = drill::DEFAULT_DRILL_SAMPLE)]
+        sample: usize,
+        /// Verify every coordinate instead of a sample
+        #[arg(long)]
+        full: bool,
+        /// Replay this `oplog export` file onto the restored copy
+        #[arg(long)]
+        oplog: Option<std::path::PathBuf>,
+        /// Keep the restored copy and print its path
+        #[arg(long)]
+        keep: bool,
+        /// Chains verified at once
+        #[arg(long, default_value_t = 4)]
+        jobs: usize,
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Print the coordinates as a Graphviz DOT graph
+    Graph {
+        /// Draw links between coordinates; missing targets are dashed
+        #[arg(long)]
+        links: bool,
+    },
+
+    /// Export, replay, and trim the mutation log
+    Oplog {
+        #[command(subcommand)]
+        command: OplogCommand,
+    },
+
+    /// Pull a primary server's changes into this database, as its standby
+    ///
+    /// Refuses to start when this database holds data the primary does not.
+    Replicate {
+        /// Base URL of the primary's API
+        #[arg(long, env = "BMS_REPLICATE_FROM")]
+        source: String,
+        /// The primary's admin token
+        #[arg(long, env = "BMS_REPLICATE_TOKEN")]
+        token: Option<String>,
+        /// Keep following the primary instead of stopping once caught up
+        #[arg(long)]
+        follow: bool,
+        /// Seconds between polls while following
+        #[arg(long, default_value_t = 1)]
+        interval_secs: u64,
+    },
+
+    /// Copy deltas between this database and a server, fast-forward only
+    Sync {
+        #[command(subcommand)]
+        command: SyncCommand,
+    },
+
+    /// Recommend snapshot and prune actions ranked by estimated savings
+    Plan {
+        /// Max recommendations to list
+        #[arg(short, long, default_value_t = 20)]
+        limit: usize,
+        /// Print recommendations (or the applied result) as JSON
+        #[arg(long)]
+        json: bool,
+        /// Apply an action instead of listing: snapshot or prune-snapshots
+        #[arg(long, requires = "coord")]
+        apply: Option<String>,
+        /// Coordinate to apply the action to (ID, alias, or ID prefix)
+        #[arg(long)]
+        coord: Option<String>,
+        /// Snapshots to keep with prune-snapshots
+        #[arg(long, default_value_t = 1)]
+        keep: u32,
+        /// Skip snapshots for coordinates below this effective importance
+        #[arg(long, env = "BMS_IMPORTANCE_FLOOR", default_value_t = 0.0)]
+        importance_floor: f32,
+    },
+
+    /// Read or change instance settings stored in the database
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
 
     /// Initialize database
     Init,
@@ -55,142 +311,595 @@ enum Commands {
     /// Semantic search
     Search {
         /// Query text
-        query: String,
+        #[arg(required_unless_present = "saved")]
+        query: Option<String>,
+        /// Run a search saved on the server instead, marking results new since its last run
+        #[arg(long, conflicts_with = "query")]
+        saved: Option<String>,
+        /// Bearer token the saved search belongs to
+        #[arg(long, env = "BMS_API_TOKEN")]
+        token: Option<String>,
         /// Max results
         #[arg(short, long, default_value_t = 10)]
         limit: usize,
         /// Minimum score filter
         #[arg(long)]
         min_score: Option<f32>,
-        /// Author filter
+        #[command(flatten)]
+        filters: SearchFilterArgs,
+        #[command(flatten)]
+        index: LocalIndexArgs,
+    },
+
+    /// Maintain the local vector index
+    Index {
+        #[command(subcommand)]
+        command: IndexCommand,
+    },
+
+    /// Generate deterministic synthetic data through the store pipeline
+    Simulate {
+        /// Number of coordinates to generate
+        #[arg(long, default_value_t = 100)]
+        coords: usize,
+        /// Deltas per coordinate (range, e.g. 50..500)
+        #[arg(long, default_value = "50..500")]
+        deltas: String,
+        /// Target state size (range with k/m suffixes, e.g. 1k..100k)
+        #[arg(long, default_value = "1k..100k")]
+        state_size: String,
+        /// Number of distinct authors
+        #[arg(long, default_value_t = 5)]
+        authors: usize,
+        /// RNG seed
+        #[arg(long, default_value_t = 42)]
+        seed: u64,
+        /// State shape: chatlog, config, or document
+        #[arg(long, default_value = "chatlog")]
+        profile: String,
+    },
+
+    /// Drive a running API with a mix of operations and report latencies
+    Loadtest {
+        /// API base URL
+        #[arg(long, default_value = "http://localhost:3000")]
+        target: String,
+        /// Concurrent workers
+        #[arg(long, default_value_t = 16)]
+        workers: usize,
+        /// Test duration (e.g. 60s, 2m)
+        #[arg(long, default_value = "60s")]
+        duration: String,
+        /// Operation weights
+        #[arg(long, default_value = "store=70,recall=20,search=10")]
+        mix: String,
+        /// State size (single value or range with k/m suffixes)
+        #[arg(long, default_value = "5k")]
+        state_size: String,
+        /// State shape: chatlog, config, or document
+        #[arg(long, default_value = "chatlog")]
+        profile: String,
+        /// Bearer token sent with every request
+        #[arg(long, env = "BMS_API_TOKEN")]
+        token: Option<String>,
+        /// RNG seed for generated states
+        #[arg(long, default_value_t = 42)]
+        seed: u64,
+        /// Keep the coordinates written by the run
         #[arg(long)]
-        author: Option<String>,
-        /// Tags filter (comma-separated)
+        keep: bool,
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Serve the HTTP API from this process
+    Serve {
+        /// Address to listen on
+        #[arg(long, env = "BMS_LISTEN", default_value = "127.0.0.1:3000")]
+        listen: String,
+        /// Listen on a unix socket instead of TCP
+        #[arg(long, conflicts_with = "listen")]
+        socket: Option<std::path::PathBuf>,
+        /// Skip loading the embedding model; `/search` answers 501
+        #[arg(long)]
+        no_vector: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum OplogCommand {
+    /// Write entries after an LSN as NDJSON
+    Export {
+        /// Export entries after this LSN (the backup's last LSN)
+        #[arg(long, default_value_t = 0)]
+        since_lsn: i64,
+        /// Output file (default: stdout)
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// Replay an exported NDJSON file onto this database
+    ///
+    /// The file's trailer is checked first, so a truncated export is
+    /// rejected before anything is applied.
+    Apply {
+        /// NDJSON file produced by `oplog export`
+        file: String,
+        /// Apply a file without a valid trailer, e.g. to salvage the entries
+        /// of a truncated export
+        #[arg(long)]
+        no_verify: bool,
+    },
+    /// Record that a backup of this database completed
+    MarkBackup {
+        #[arg(long)]
+        label: Option<String>,
+    },
+    /// Drop entries that none of the most recent backups need
+    Trim {
+        /// Backups that must stay restorable
+        #[arg(long, default_value_t = 1)]
+        keep_backups: u32,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Print a setting
+    Get {
+        /// Setting name: snapshot_interval
+        key: String,
+    },
+    /// Change a setting; a running server picks it up when restarted
+    Set {
+        /// Setting name: snapshot_interval
+        key: String,
+        value: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum IndexCommand {
+    /// Fit a projection to reduce vectors to fewer dimensions, from a sample
+    /// of stored heads; pass the output to `search --projection`
+    FitProjection {
+        /// Dimensions to keep
         #[arg(long)]
-        tags: Option<String>,
+        reduce_to: usize,
+        /// Heads to embed and fit to, chosen at random
+        #[arg(long, default_value_t = 2000)]
+        sample: usize,
+        /// RNG seed for the sample and the fit
+        #[arg(long, default_value_t = 42)]
+        seed: u64,
+        /// Projection file to write
+        #[arg(short, long)]
+        output: std::path::PathBuf,
     },
 }
 
+/// Which heads `bms search` may return
+#[derive(Args)]
+struct SearchFilterArgs {
+    /// Author filter
+    #[arg(long)]
+    author: Option<String>,
+    /// Tags filter (comma-separated)
+    #[arg(long)]
+    tags: Option<String>,
+    /// Filter expression, e.g. `/status == open and (tag == urgent or metadata.priority < 3)`
+    #[arg(long, conflicts_with_all = ["author", "tags"])]
+    filter: Option<String>,
+}
+
+impl SearchFilterArgs {
+    fn tag_list(&self) -> Option<Vec<String>> {
+        self.tags.as_ref().map(|s| s.split(',').map(|t| t.trim().to_string()).filter(|t| !t.is_empty()).collect())
+    }
+
+    /// Parse `--filter`, resolving relative dates now
+    fn expression(&self) -> Result<Option<FilterExpr>> {
+        Ok(self.filter.as_deref().map(|f| FilterExpr::parse(f, chrono::Utc::now())).transpose()?)
+    }
+}
+
+/// How `bms search` builds its local index
+#[derive(Args)]
+struct LocalIndexArgs {
+    /// States embedded per model call when building the local index (default 32)
+    #[arg(long, env = "BMS_EMBED_BATCH_SIZE")]
+    embed_batch_size: Option<usize>,
+    /// Store vectors reduced to this many dimensions, by a seeded random projection
+    #[arg(long, env = "BMS_VECTOR_REDUCE_TO")]
+    reduce_to: Option<usize>,
+    /// Reduce with a projection written by `index fit-projection` instead
+    #[arg(long, env = "BMS_VECTOR_PROJECTION")]
+    projection: Option<std::path::PathBuf>,
+    /// Comma-separated coordinate metadata keys appended to the embedded text
+    #[arg(long, env = "BMS_INDEX_FACETS")]
+    facets: Option<String>,
+}
+
+#[derive(Subcommand)]
+enum SyncCommand {
+    /// Upload local deltas the server is missing
+    Push(SyncArgs),
+    /// Download server deltas this database is missing
+    Pull(SyncArgs),
+}
+
+#[derive(Args)]
+struct SyncArgs {
+    /// Base URL of the BMS API
+    #[arg(long, env = "BMS_API_URL")]
+    target: String,
+    /// Coordinates to sync (default: all on the sending side); push also
+    /// accepts aliases and ID prefixes, pull needs full IDs
+    #[arg(long)]
+    coord: Vec<String>,
+    /// Admin token of the server
+    #[arg(long, env = "BMS_API_TOKEN")]
+    token: Option<String>,
+}
+
+/// Input file for `bms store-group`, in the same shape as `POST /store/group`
+#[derive(Debug, Deserialize)]
+struct StoreGroupFile {
+    items: Vec<StoreGroupItem>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StoreGroupItem {
+    coord_hint: Option<String>,
+    state: Value,
+    metadata: Option<HashMap<String, Value>>,
+    author: Option<String>,
+    expected_head_delta_id: Option<String>,
+    diff_options: Option<DiffOptions>,
+    created_at_override: Option<chrono::DateTime<chrono::Utc>>,
+    op_authors: Option<Vec<String>>,
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
+    // Settings from BMS_CONFIG_FILE fill in the environment before clap reads it
+    bms_api::ConfigSource::load()?;
     // Initialize tracing
-    tracing_subscriber::fmt()
-        .with_target(false)
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::from_default_env()
-                .add_directive(tracing::Level::INFO.into()),
-        )
-        .init();
+    // Logs go to stderr so piped output (e.g. `oplog export`) stays clean
+    bms_api::init_tracing(std::io::stderr)?;
 
     let cli = Cli::parse();
 
-    let repo = BmsRepository::new(&cli.db_path).await?;
+    // The load test only talks to the API, so it needs no local database
+    if let Commands::Loadtest { target, workers, duration, mix, state_size, profile, token, seed, keep, json } = cli.command {
+        let config = loadtest::LoadTestConfig {
+            target,
+            workers,
+            duration: loadtest::parse_duration(&duration)?,
+            mix: loadtest::OpMix::parse(&mix)?,
+            state_size: simulate::parse_range(&state_size)?,
+            profile: profile.parse()?,
+            token,
+            seed,
+        };
+
+        let report = loadtest::run(config.clone()).await?;
+        if json {
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        } else {
+            report.print();
+        }
+
+        if !keep {
+            match loadtest::cleanup(&config, &report.coordinates).await {
+                Ok(deleted) => info!("Deleted {} load-test coordinates", deleted),
+                Err(e) => warn!(
+                    "Cleanup skipped ({}); load-test coordinates are prefixed LOADTEST-{}",
+                    e, report.run_id
+                ),
+            }
+        }
+        return Ok(());
+    }
+
+    // The drill restores the backup into its own scratch directory
+    if let Commands::Drill { backup, sample, full, oplog, keep, jobs, json } = cli.command {
+        let report = drill::run(&DrillConfig {
+            sample: (!full).then_some(sample),
+            oplog,
+            keep,
+            concurrency: jobs,
+            ..DrillConfig::new(backup)
+        })
+        .await;
+
+        if json {
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        } else {
+            println!("Restore drill of {}:", report.backup.display());
+            for step in &report.steps {
+                let mark = if step.ok { "✓" } else { "✗" };
+                println!("  {} {:<15} {:>9.1} ms  {}", mark, step.name, step.elapsed_ms, step.detail);
+            }
+            for failure in &report.failures {
+                println!("  {}  {}", failure.coord_id, failure.reason);
+            }
+            if let Some(path) = &report.kept {
+                println!("Restored copy kept at {}", path.display());
+            }
+            println!(
+                "Status: {} in {:.1} ms",
+                if report.is_ok() { "✓ Passed" } else { "✗ Failed" },
+                report.elapsed_ms
+            );
+        }
+
+        // Distinct codes so schedulers can tell the failures apart
+        let code = match report.failure {
+            None => return Ok(()),
+            Some(DrillFailure::Restore) => 2,
+            Some(DrillFailure::Migration) => 3,
+            Some(DrillFailure::Integrity) => 4,
+        };
+        std::process::exit(code);
+    }
+
+    // The server opens the database itself, with the same settings as bms-api
+    if let Commands::Serve { listen, socket, no_vector } = cli.command {
+        if let Some(interval) = cli.snapshot_interval {
+            let repository = BmsRepository::new(&cli.db_path).await?;
+            resolve_snapshot_interval(&repository, DEFAULT_SNAPSHOT_INTERVAL, Some(interval)).await?;
+        }
+        let state = bms_api::build_state(&cli.db_path, !no_vector).await?;
+        let listen = match socket {
+            Some(path) => bms_api::Listen::Unix(path),
+            None => bms_api::Listen::Tcp(listen),
+        };
+        return bms_api::serve(state, listen).await;
+    }
+
+    let mut repository = BmsRepository::new(&cli.db_path).await?;
     info!("Connected to database: {}", cli.db_path);
+    if let Some(encoding) = cli.delta_encoding {
+        repository = repository.with_delta_encoding(encoding);
+    }
+    let snapshot_interval =
+        resolve_snapshot_interval(&repository, DEFAULT_SNAPSHOT_INTERVAL, cli.snapshot_interval).await?;
+    let mut facade = BmsFacade::new(repository, SnapshotManager::new(snapshot_interval));
+    if cli.strict {
+        facade = facade.with_integrity_checks(IntegrityChecks::strict());
+    }
+    let facade = Arc::new(facade);
+    let repo = facade.repository();
+    let ids = IdFormat::new(cli.short_id_len, cli.full_ids);
+    let importance = ImportancePolicy {
+        half_life_secs: cli.importance_half_life_hours * 3600.0,
+        ..ImportancePolicy::default()
+    };
 
     match cli.command {
-        Commands::Store { state, coord } => {
+        Commands::Store { state, coord, if_match, json, explain, created_at, ephemeral } => {
             let state_value: Value = serde_json::from_str(&state)?;
 
-            let coord_id = if let Some(hint) = coord {
-                CoordId(hint)
-            } else {
-                CoordinateGenerator::generate_now(&state_value)?
-            };
+            let outcome = facade
+                .store(StoreParams {
+                    coord_id: match coord {
+                        Some(coord) => Some(resolve_hint(repo, &coord).await?),
+                        None => None,
+                    },
+                    state: state_value,
+                    metadata: ephemeral
+                        .then(|| HashMap::from([(EPHEMERAL_METADATA_KEY.to_string(), Value::Bool(true))])),
+                    author: None,
+                    precondition: if_match
+                        .map(|tag| StorePrecondition::HeadChainHash(Hash(tag.trim_matches('"').to_string()))),
+                    diff_options: None,
+                    explain,
+                    created_at,
+                    op_authors: None,
+                    policy: None,
+                    namespace: cli.namespace.clone(),
+                    hash_algorithm: None,
+                    parent_coord_id: None,
+                    child_label: None,
+                })
+                .await?;
 
-            // Create coordinate if new
-            if !repo.coordinate_exists(&coord_id).await? {
-                let coordinate = Coordinate {
-                    id: coord_id.clone(),
-                    rune_alias: None,
-                    created_at: chrono::Utc::now(),
-                    metadata: None,
+            if json {
+                println!("{}", serde_json::to_string_pretty(&outcome)?);
+                return Ok(());
+            }
+            if outcome.coordinate_created {
+                println!("Created coordinate: {}", ids.show(outcome.coord_id.as_str()));
+            }
+            println!("Stored delta: {} (seq {})", ids.show(outcome.head.delta_id.as_str()), outcome.head.seq);
+            println!("Coordinate: {}", ids.show(outcome.coord_id.as_str()));
+            println!("Snapshot: {}", serde_json::to_value(outcome.snapshot)?.as_str().unwrap_or_default());
+            for warning in &outcome.warnings {
+                println!("Warning: {}", warning.code());
+            }
+            if let Some(timings) = outcome.timings {
+                println!(
+                    "Timings: prepare {:.2} ms, write {:.2} ms, snapshot {:.2} ms",
+                    timings.prepare_ms, timings.write_ms, timings.snapshot_ms
+                );
+            }
+        }
+
+        Commands::StoreGroup { file, json } => {
+            let group: StoreGroupFile = serde_json::from_str(&std::fs::read_to_string(&file)?)?;
+            let mut items = Vec::with_capacity(group.items.len());
+            for item in group.items {
+                let coord_id = match item.coord_hint {
+                    Some(hint) => Some(resolve_hint(repo, &hint).await?),
+                    None => None,
+                };
+                let expected_head = match (&coord_id, item.expected_head_delta_id) {
+                    (Some(coord_id), Some(id)) => Some(resolve_delta(repo, coord_id, &id).await?),
+                    (None, id) => id.map(DeltaId),
+                    (_, None) => None,
                 };
-                repo.insert_coordinate(&coordinate).await?;
-                println!("Created coordinate: {}", coord_id);
+                items.push(StoreParams {
+                    coord_id,
+                    state: item.state,
+                    metadata: item.metadata,
+                    author: item.author,
+                    precondition: expected_head.map(StorePrecondition::HeadDeltaId),
+                    diff_options: item.diff_options,
+                    explain: false,
+                    created_at: item.created_at_override,
+                    op_authors: item.op_authors,
+                    policy: None,
+                    namespace: cli.namespace.clone(),
+                    hash_algorithm: None,
+                    parent_coord_id: None,
+                    child_label: None,
+                });
             }
 
-            // Get deltas and compute new delta
-            let deltas = repo.get_deltas(&coord_id).await?;
-            let prev_state = if deltas.is_empty() {
-                serde_json::json!({})
-            } else {
-                let mut state = serde_json::json!({});
-                for delta in &deltas {
-                    DeltaEngine::apply_delta(&mut state, &delta.ops)?;
-                }
-                state
-            };
+            let outcomes = facade.store_group(items).await?;
+            // No background worker here, so write deferred snapshots before exiting
+            facade.flush_pending_snapshots().await;
 
-            let ops = DeltaEngine::compute_delta(&prev_state, &state_value)?;
-            let delta_hash = DeltaEngine::hash_delta(&ops)?;
-            let delta_id = DeltaEngine::generate_delta_id(&ops)?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&outcomes)?);
+                return Ok(());
+            }
+            println!("Stored {} deltas:", outcomes.len());
+            for outcome in outcomes {
+                let warnings: Vec<&str> = outcome.warnings.iter().map(|w| w.code()).collect();
+                let (coord, delta) = (ids.show(outcome.coord_id.as_str()), ids.show(outcome.head.delta_id.as_str()));
+                if warnings.is_empty() {
+                    println!("  {}  {}", coord, delta);
+                } else {
+                    println!("  {}  {}  warnings: {}", coord, delta, warnings.join(", "));
+                }
+            }
+        }
 
-            let (parent_id, parent_hash) = if let Some(last) = deltas.last() {
-                (Some(last.id.clone()), Some(last.chain_hash.clone()))
-            } else {
-                (None, None)
-            };
+        Commands::Recall { coord_id } => {
+            let coord_id = resolve_coord(repo, &coord_id).await?;
 
-            let chain_hash = if let Some(ref ph) = parent_hash {
-                bms_core::MerkleChain::compute_chain_hash(ph, &delta_hash)
-            } else {
-                delta_hash.clone()
+            let Some(head) = facade.head(&coord_id).await? else {
+                println!("No deltas found for coordinate: {}", ids.show(coord_id.as_str()));
+                return Ok(());
             };
 
-            let delta = Delta {
-                id: delta_id.clone(),
-                coord_id: coord_id.clone(),
-                parent_id,
-                parent_hash,
-                delta_hash,
-                chain_hash,
-                ops,
-                created_at: chrono::Utc::now(),
-                tags: None,
-                author: None,
-            };
+            println!("State for {}:", ids.show(coord_id.as_str()));
+            println!("{}", serde_json::to_string_pretty(&head.state)?);
+            println!("\nDelta count: {}", head.deltas.len());
+        }
 
-            repo.insert_delta(&delta).await?;
+        Commands::List { sort, include_ephemeral } => {
+            let mut coords = repo.list_coordinates(cli.namespace.as_deref(), None).await?;
+            if !include_ephemeral {
+                coords.retain(|c| !c.is_ephemeral());
+            }
+            let now = chrono::Utc::now();
+            let current: HashMap<CoordId, f32> = repo
+                .get_importance(None)
+                .await?
+                .into_iter()
+                .map(|i| (i.coord_id.clone(), i.effective(&importance, now)))
+                .collect();
+            let importance_of = |id: &CoordId| current.get(id).copied().unwrap_or(DEFAULT_IMPORTANCE);
 
-            println!("Stored delta: {}", delta_id);
-            println!("Coordinate: {}", coord_id);
-        }
+            let sizes: HashMap<CoordId, u64> = repo
+                .list_state_sizes(None)
+                .await?
+                .into_iter()
+                .map(|s| (s.coord_id, s.state_bytes))
+                .collect();
 
-        Commands::Recall { coord_id } => {
-            let coord_id = CoordId(coord_id);
-            let deltas = repo.get_deltas(&coord_id).await?;
+            match sort.as_str() {
+                "created" => {}
+                "importance" => coords.sort_by(|a, b| importance_of(&b.id).total_cmp(&importance_of(&a.id))),
+                "size" => coords.sort_by_key(|c| std::cmp::Reverse(sizes.get(&c.id).copied())),
+                other => anyhow::bail!("Unknown sort order: {} (expected created, importance, or size)", other),
+            }
 
-            if deltas.is_empty() {
-                println!("No deltas found for coordinate: {}", coord_id);
-                return Ok(());
+            println!("Coordinates ({}):", coords.len());
+            for coord in coords {
+                let size = sizes
+                    .get(&coord.id)
+                    .map(|&bytes| iec_bytes(bytes as i64))
+                    .unwrap_or_else(|| "unmeasured".to_string());
+                println!(
+                    "  {} (namespace: {}, created: {}, importance: {:.3}, size: {})",
+                    ids.show(coord.id.as_str()),
+                    coord.namespace(),
+                    coord.created_at,
+                    importance_of(&coord.id),
+                    size
+                );
             }
+        }
 
-            let mut state = serde_json::json!({});
-            for delta in &deltas {
-                DeltaEngine::apply_delta(&mut state, &delta.ops)?;
+        Commands::Promote { coord_id } => {
+            let coord_id = resolve_coord(repo, &coord_id).await?;
+            match facade.promote(&coord_id).await? {
+                None => anyhow::bail!("Coordinate not found: {}", coord_id),
+                Some(true) => println!("Promoted {}", ids.show(coord_id.as_str())),
+                Some(false) => println!("{} is not ephemeral", ids.show(coord_id.as_str())),
             }
+        }
 
-            println!("State for {}:", coord_id);
-            println!("{}", serde_json::to_string_pretty(&state)?);
-            println!("\nDelta count: {}", deltas.len());
+        Commands::Reinforce { coord_id, delta } => {
+            let coord_id = resolve_coord(repo, &coord_id).await?;
+            let Some(value) = repo
+                .reinforce_importance(&coord_id, delta, &importance, chrono::Utc::now())
+                .await?
+            else {
+                anyhow::bail!("Coordinate not found: {}", coord_id);
+            };
+            println!("Importance of {}: {:.3}", ids.show(coord_id.as_str()), value);
         }
 
-        Commands::List => {
-            let coords = repo.list_coordinates(None).await?;
+        Commands::Rollback { coord_id, steps, author } => {
+            let coord_id = resolve_coord(repo, &coord_id).await?;
+            let outcome = facade.rollback(&coord_id, steps, author).await?;
+            println!(
+                "Rolled back {} deltas of {}: new delta {} (seq {})",
+                steps,
+                ids.show(coord_id.as_str()),
+                ids.show(outcome.head.delta_id.as_str()),
+                outcome.head.seq
+            );
+        }
 
-            println!("Coordinates ({}):", coords.len());
-            for coord in coords {
-                println!("  {} (created: {})", coord.id, coord.created_at);
+        Commands::RecodeDeltas => {
+            let recoded = repo.recode_deltas().await?;
+            println!("Rewrote {} deltas as {}", recoded, repo.delta_encoding());
+        }
+
+        Commands::Compact { coord_id, keep_last } => {
+            let coord_id = resolve_coord(repo, &coord_id).await?;
+            let report = facade.compact(&coord_id, keep_last).await?;
+            if report.squashed == 0 {
+                println!(
+                    "Nothing to compact in {}: {} deltas",
+                    ids.show(coord_id.as_str()),
+                    report.kept
+                );
+            } else {
+                println!(
+                    "Compacted {}: squashed {} deltas into {} ops, kept {}",
+                    ids.show(coord_id.as_str()),
+                    report.squashed,
+                    report.ops,
+                    report.kept
+                );
             }
         }
 
         Commands::Verify { coord_id } => {
-            let coord_id = CoordId(coord_id);
+            let coord_id = resolve_coord(repo, &coord_id).await?;
             let deltas = repo.get_deltas(&coord_id).await?;
+            let algorithm = repo.get_hash_algorithm(&coord_id).await?;
 
-            let (verified, error) = bms_core::MerkleChain::verify_chain_integrity(&deltas);
+            let (verified, error) = bms_core::MerkleChain::verify_linked_chain_integrity_with(&deltas, algorithm);
 
-            println!("Chain verification for {}:", coord_id);
+            println!("Chain verification for {}:", ids.show(coord_id.as_str()));
             println!("  Total deltas: {}", deltas.len());
             println!("  Verified: {}", verified);
 
@@ -201,31 +910,443 @@ async fn main() -> Result<()> {
             }
         }
 
-        Commands::Stats => {
+        Commands::History { coord_id, author } => {
+            let coord_id = resolve_coord(repo, &coord_id).await?;
+            let deltas = repo.get_deltas(&coord_id).await?;
+            if deltas.is_empty() {
+                anyhow::bail!("Coordinate not found: {}", coord_id);
+            }
+
+            println!("History of {}:", ids.show(coord_id.as_str()));
+            let mut ratios = PatchRatios::default();
+            for (i, delta) in deltas.iter().enumerate() {
+                let by_author = delta.ops_by_author();
+                if author.as_ref().is_some_and(|a| !by_author.iter().any(|(b, _)| b == a)) {
+                    continue;
+                }
+                let replaced = if delta.is_full_replace() { "  (full replace)" } else { "" };
+                println!(
+                    "  {:>4}  {}  {}  {}  ({}){}",
+                    i + 1,
+                    ids.show(delta.id.as_str()),
+                    delta.created_at.to_rfc3339(),
+                    delta
+                        .authorship_summary()
+                        .unwrap_or_else(|| format!("{} ops", delta.ops.len())),
+                    DeltaEngine::describe_ops(&delta.ops).text_summary,
+                    replaced
+                );
+                if let Some(ratio) = delta.patch_ratio() {
+                    ratios.record(ratio, delta.is_full_replace());
+                }
+            }
+            if ratios.total() > 0 {
+                print_patch_ratios(&ratios);
+            }
+        }
+
+        Commands::Stats { recompute_sizes: true, jobs, .. } => {
+            let recorded = facade.recompute_state_sizes(jobs).await?;
+            println!("Recorded head sizes of {} coordinates", recorded);
+
+            let largest = repo.list_state_sizes(Some(5)).await?;
+            if !largest.is_empty() {
+                println!("Largest heads:");
+                for size in largest {
+                    println!("  {:<28} {:>10}", ids.show(size.coord_id.as_str()), iec_bytes(size.state_bytes as i64));
+                }
+            }
+        }
+
+        Commands::Stats { coord_id: Some(coord_id), .. } => {
+            let coord_id = resolve_coord(repo, &coord_id).await?;
+            let Some(compression) = facade.compression_stats(&coord_id).await? else {
+                anyhow::bail!("Coordinate not found: {}", coord_id);
+            };
+
+            println!("Compression of {}:", ids.show(coord_id.as_str()));
+            println!("  Deltas: {}", compression.delta_count);
+            println!("  Full-state bytes: {}", compression.original_bytes);
+            println!("  Delta bytes: {}", compression.compressed_bytes);
+            println!("  Compression ratio: {:.2}%", compression.compression_ratio * 100.0);
+            let ops = compression.ops;
+            println!(
+                "  Ops: {} add, {} remove, {} replace, {} move, {} copy, {} test ({} canonical bytes)",
+                ops.add_count,
+                ops.remove_count,
+                ops.replace_count,
+                ops.move_count,
+                ops.copy_count,
+                ops.test_count,
+                ops.total_bytes
+            );
+        }
+
+        Commands::Stats { hot: false, .. } => {
             let stats = repo.get_stats().await?;
 
             println!("BMS Statistics:");
             println!("  Coordinates: {}", stats.coordinate_count);
             println!("  Deltas: {}", stats.delta_count);
             println!("  Snapshots: {}", stats.snapshot_count);
+            println!("  Blobs: {}", stats.blob_count);
+            println!(
+                "  Materialized heads: {} ({} bytes)",
+                stats.head_state_count, stats.head_state_bytes
+            );
+        }
+
+        Commands::Stats { hot: true, limit, .. } => {
+            let hot = repo.get_hot_coordinates(limit).await?;
+            if hot.is_empty() {
+                println!("No read statistics recorded yet");
+                return Ok(());
+            }
+
+            println!("{:<28} {:>8} {:>8}  LAST READ", "COORDINATE", "READS", "DELTAS");
+            for h in hot {
+                println!(
+                    "{:<28} {:>8} {:>8}  {}",
+                    ids.show(h.coord_id.as_str()),
+                    h.read_count,
+                    h.delta_count,
+                    h.last_read_at.format("%Y-%m-%d %H:%M:%S")
+                );
+            }
+        }
+
+        Commands::Fsck { rebuild_heads } => {
+            let coords = repo.list_coordinates(None, Some(i64::MAX)).await?;
+            let mut invalid_ids = 0;
+            let mut broken_chains = 0;
+
+            // Rows that cannot be decoded at all, or that carry ops of an unknown type
+            let corrupt = repo.scan_corrupt_rows().await?;
+            for table in ["deltas", "snapshots"] {
+                for row in corrupt.iter().filter(|row| row.table == table) {
+                    println!("  {} row {} ({})  {}", table, row.id, ids.show(row.coord_id.as_str()), row.reason);
+                }
+            }
+
+            println!("Checking {} coordinates...", coords.len());
+            for coord in &coords {
+                // Named IDs from before hints were checked, and generated IDs
+                // with non-zero padding bits, land here
+                let valid = match coord.kind {
+                    CoordIdKind::Generated => CoordinateGenerator::validate(coord.id.as_str()),
+                    CoordIdKind::Named => CoordinateGenerator::validate_named(coord.id.as_str()),
+                };
+                if let Err(e) = valid {
+                    invalid_ids += 1;
+                    println!("  {}  invalid ID: {}", coord.id, e);
+                }
+
+                // Lenient so one corrupt row does not hide the rest of the chain
+                let deltas = repo.get_deltas_lenient(&coord.id).await?;
+                let algorithm = coord.hash_algorithm;
+                if let (verified, Some(e)) = bms_core::MerkleChain::verify_chain_integrity_with(&deltas, algorithm) {
+                    broken_chains += 1;
+                    println!("  {}  chain broken at delta {}: {}", ids.show(coord.id.as_str()), verified, e);
+                }
+            }
+
+            let mut drifted_heads = 0;
+            for coord_id in repo.list_head_state_coords().await? {
+                let drift = match facade.check_head_state(&coord_id).await {
+                    Ok(Some(drift)) => drift,
+                    Ok(None) => continue,
+                    // Already counted above when a corrupt row is the cause
+                    Err(e) => {
+                        println!("  {}  materialized head not checked: {}", ids.show(coord_id.as_str()), e);
+                        continue;
+                    }
+                };
+                if rebuild_heads && facade.rebuild_head_state(&coord_id).await? {
+                    println!("  {}  {}; rebuilt", ids.show(coord_id.as_str()), drift);
+                } else {
+                    drifted_heads += 1;
+                    println!("  {}  {}", ids.show(coord_id.as_str()), drift);
+                }
+            }
+
+            // Left behind by deletes under the warn policy, so reported but not failed
+            let dangling = repo.list_links(true).await?;
+            for link in &dangling {
+                println!(
+                    "  {}  dangling link {} -> {}",
+                    ids.show(link.from_coord.as_str()),
+                    link.pointer,
+                    ids.show(link.to_coord.as_str())
+                );
+            }
+
+            let corrupt_deltas = corrupt.iter().filter(|row| row.table == "deltas").count();
+            println!(
+                "Corrupt rows: {} ({} deltas, {} snapshots)",
+                corrupt.len(),
+                corrupt_deltas,
+                corrupt.len() - corrupt_deltas
+            );
+            println!("Invalid IDs: {}", invalid_ids);
+            println!("Broken chains: {}", broken_chains);
+            println!("Drifted materialized heads: {}", drifted_heads);
+            println!("Dangling links: {}", dangling.len());
+            let problems = corrupt.len() + invalid_ids + broken_chains + drifted_heads;
+            if problems > 0 {
+                anyhow::bail!("fsck found {} problem(s)", problems);
+            }
+            println!("Status: ✓ Clean");
+        }
+
+        Commands::Graph { links } => {
+            let coords = repo.list_coordinate_ids().await?;
+            println!("digraph bms {{");
+            for id in &coords {
+                println!("  \"{}\";", id);
+            }
+            if links {
+                let mut missing = HashSet::new();
+                for link in repo.list_links(false).await? {
+                    if !coords.contains(&link.to_coord) && missing.insert(link.to_coord.clone()) {
+                        println!("  \"{}\" [style=dashed];", link.to_coord);
+                    }
+                    println!(
+                        "  \"{}\" -> \"{}\" [label={:?}];",
+                        link.from_coord, link.to_coord, link.pointer
+                    );
+                }
+            }
+            println!("}}");
+        }
+
+        Commands::Sync { command } => {
+            let (pushing, args) = match command {
+                SyncCommand::Push(args) => (true, args),
+                SyncCommand::Pull(args) => (false, args),
+            };
+            let client = sync::SyncClient::new(&args.target, args.token);
+            let coords = match (args.coord.is_empty(), pushing) {
+                (false, true) => {
+                    let mut coords = Vec::with_capacity(args.coord.len());
+                    for coord in &args.coord {
+                        coords.push(resolve_coord(repo, coord).await?);
+                    }
+                    coords
+                }
+                // The server only accepts full IDs, and a pulled coordinate may not exist here
+                (false, false) => args.coord.into_iter().map(CoordId).collect(),
+                (true, true) => repo.list_coordinate_ids().await?,
+                (true, false) => client.list_coordinates().await?,
+            };
+
+            println!(
+                "{} {} coordinate(s) {} {}",
+                if pushing { "Pushing" } else { "Pulling" },
+                coords.len(),
+                if pushing { "to" } else { "from" },
+                args.target
+            );
+            let mut diverged = 0;
+            for coord_id in coords {
+                let status = if pushing {
+                    sync::push(&facade, &client, &coord_id).await?
+                } else {
+                    sync::pull(&facade, &client, &coord_id).await?
+                };
+                if matches!(status, sync::SyncStatus::Diverged { .. }) {
+                    diverged += 1;
+                }
+                sync::CoordSync { coord_id, status }.print(ids);
+            }
+            if diverged > 0 {
+                anyhow::bail!(
+                    "{} coordinate(s) diverged; nothing was written for them",
+                    diverged
+                );
+            }
+        }
+
+        Commands::Oplog { command } => match command {
+            OplogCommand::Export { since_lsn, output } => {
+                let written = match output {
+                    // Written under a temp name, so a crash never leaves a partial export at `path`
+                    Some(path) => {
+                        let mut out = bms_core::AtomicFile::create(&path)?;
+                        let written = oplog::export(repo, since_lsn, &mut out).await?;
+                        out.commit()?;
+                        written
+                    }
+                    None => oplog::export(repo, since_lsn, &mut std::io::stdout().lock()).await?,
+                };
+                info!("Exported {} oplog entries after LSN {}", written, since_lsn);
+            }
+            OplogCommand::Apply { file, no_verify } => {
+                if no_verify {
+                    warn!("Applying {} without checking its trailer", file);
+                } else {
+                    oplog::verify_export(std::path::Path::new(&file))?;
+                }
+                let input = std::io::BufReader::new(std::fs::File::open(&file)?);
+                let report = oplog::apply(repo, input).await?;
+
+                println!("Applied: {}", report.applied);
+                println!("Skipped (already present): {}", report.skipped);
+                println!("Without payload (deleted later): {}", report.without_payload);
+                if let Some(lsn) = report.last_lsn {
+                    println!("Last LSN: {}", lsn);
+                }
+            }
+            OplogCommand::MarkBackup { label } => {
+                let marker = repo.record_backup_marker(label.as_deref()).await?;
+                println!("Backup marker {} at LSN {}", marker.id, marker.lsn);
+            }
+            OplogCommand::Trim { keep_backups } => {
+                let trimmed = repo.trim_oplog(keep_backups).await?;
+                println!("Trimmed {} oplog entries", trimmed);
+            }
+        },
+
+        Commands::Replicate { source, token, follow, interval_secs } => {
+            let config = ReplicationConfig {
+                token,
+                poll_interval: std::time::Duration::from_secs(interval_secs.max(1)),
+                ..ReplicationConfig::new(source)
+            };
+            let replicator = Replicator::new(facade.clone(), config);
+            if follow {
+                // Only returns once divergence stopped replication
+                replicator.run().await;
+                anyhow::bail!(replicator.status().last_error.unwrap_or_default());
+            }
+
+            let received = replicator.catch_up().await?;
+            println!(
+                "Received {} entries from {}, now at LSN {}",
+                received,
+                replicator.status().source,
+                replicator.position().await?
+            );
+        }
+
+        Commands::Plan { limit, json, apply: None, importance_floor, .. } => {
+            let model = CostModel { importance_floor, importance, ..CostModel::default() };
+            let mut plan = planner::plan_store(&facade, &model).await?;
+            plan.truncate(limit);
+
+            if json {
+                println!("{}", serde_json::to_string_pretty(&plan)?);
+            } else if plan.is_empty() {
+                println!("Nothing to do");
+            } else {
+                for (i, r) in plan.iter().enumerate() {
+                    println!("{:>3}. {}", i + 1, r.summary);
+                    println!("     {}", r.command);
+                }
+            }
         }
 
+        Commands::Plan { json, apply: Some(action), coord, keep, importance_floor, .. } => {
+            let coord_id = resolve_coord(repo, &coord.expect("clap requires --coord with --apply")).await?;
+            let action = match action.as_str() {
+                "snapshot" => PlanAction::Snapshot { coord_id },
+                "prune-snapshots" => PlanAction::PruneSnapshots { coord_id, keep },
+                other => anyhow::bail!("Unknown plan action: {} (expected snapshot or prune-snapshots)", other),
+            };
+
+            let model = CostModel { importance_floor, importance, ..CostModel::default() };
+            let applied = planner::apply(&facade, &action, &model).await?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&applied)?);
+            } else {
+                println!("Applied {}", action.command());
+                println!(
+                    "  Bytes saved: est {} / actual {}",
+                    applied.estimate.bytes_saved, applied.actual.bytes_saved
+                );
+                println!(
+                    "  Reconstruction: est {:.1}ms → {:.1}ms / measured {:.1}ms → {:.1}ms",
+                    applied.estimate.reconstruction_ms_before,
+                    applied.estimate.reconstruction_ms_after,
+                    applied.actual.reconstruction_ms_before,
+                    applied.actual.reconstruction_ms_after
+                );
+            }
+        }
+
+        Commands::Config { command: ConfigCommand::Get { key } } => match key.as_str() {
+            "snapshot_interval" => match facade.snapshot_manager().interval() {
+                Some(interval) => println!("{}", interval),
+                None => println!("none"),
+            },
+            _ => anyhow::bail!("Unknown setting {:?}; settings: snapshot_interval", key),
+        },
+
+        Commands::Config { command: ConfigCommand::Set { key, value } } => match key.as_str() {
+            "snapshot_interval" => {
+                let interval: u32 = value
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("snapshot_interval must be a positive integer, got {:?}", value))?;
+                let previous = facade.snapshot_manager().interval();
+                repo.set_snapshot_interval(interval).await?;
+                let previous = previous.map_or("none".to_string(), |n| n.to_string());
+                println!("snapshot_interval: {} -> {}", previous, interval);
+            }
+            _ => anyhow::bail!("Unknown setting {:?}; settings: snapshot_interval", key),
+        },
+
         Commands::Init => {
             println!("Database initialized at: {}", cli.db_path);
         }
 
-        Commands::Search { query, limit, min_score, author, tags } => {
+        Commands::Search { saved: Some(name), token, .. } => {
+            let Ok(api_url) = std::env::var("BMS_API_URL") else {
+                anyhow::bail!("Saved searches live on the server; set BMS_API_URL");
+            };
+            let url = format!("{}/searches/{}/run", api_url.trim_end_matches('/'), name);
+            let mut request = reqwest::Client::new().get(url);
+            if let Some(token) = token {
+                request = request.bearer_auth(token);
+            }
+            let resp = request.send().await?;
+            if !resp.status().is_success() {
+                anyhow::bail!("API error: {}", resp.text().await.unwrap_or_default());
+            }
+            let report: Value = resp.json().await?;
+
+            let results = report["results"].as_array().cloned().unwrap_or_default();
+            match report["previous_run_at"].as_str() {
+                Some(at) => println!(
+                    "Saved search {} ({} results, {} new since {}):",
+                    name, results.len(), report["new"], at
+                ),
+                None => println!("Saved search {} ({} results, first run):", name, results.len()),
+            }
+            for result in &results {
+                println!(
+                    "  {:<11} {}  {:.4}",
+                    format!("[{}]", result["change"].as_str().unwrap_or("?")),
+                    ids.show(result["coord_id"].as_str().unwrap_or_default()),
+                    result["score"].as_f64().unwrap_or_default()
+                );
+            }
+            for coord_id in report["dropped"].as_array().into_iter().flatten() {
+                println!("  {:<11} {}", "[dropped]", ids.show(coord_id.as_str().unwrap_or_default()));
+            }
+        }
+
+        Commands::Search { query, limit, min_score, filters, index, .. } => {
+            let query = query.expect("clap requires a query without --saved");
             // If API URL is provided, call API; else local fallback
             if let Ok(api_url) = std::env::var("BMS_API_URL") {
                 let url = format!("{}/search", api_url.trim_end_matches('/'));
                 let client = reqwest::Client::new();
-                let tags_vec = tags.as_ref().map(|s| s.split(',').map(|t| t.trim().to_string()).filter(|t| !t.is_empty()).collect::<Vec<_>>() );
                 let body = serde_json::json!({
                     "query": query,
                     "limit": limit,
                     "min_score": min_score,
-                    "author": author,
-                    "tags": tags_vec,
+                    "author": filters.author,
+                    "tags": filters.tag_list(),
+                    "filter_expr": filters.expression()?.as_ref().map(FilterExpr::to_json),
                 });
                 let resp = client.post(url).json(&body).send().await?;
                 if !resp.status().is_success() {
@@ -237,45 +1358,244 @@ async fn main() -> Result<()> {
             }
 
             // Local fallback: build in-memory index from current heads
-            info!("Building in-memory index from current data (no API URL set)...");
-            let coords = repo.list_coordinates(None).await?;
-            let mut generator = EmbeddingGenerator::new().map_err(|e| anyhow::anyhow!("Embedding init error: {}", e))?;
-            let store = InMemoryVectorStore::new(VectorConfig::default())
-                .map_err(|e| anyhow::anyhow!("Vector store init error: {}", e))?;
-
-            for coord in &coords {
-                // Reconstruct head state
-                let deltas = repo.get_deltas(&coord.id).await?;
-                if deltas.is_empty() { continue; }
-                let state = if let Some(snapshot) = repo.get_latest_snapshot(&coord.id).await? {
-                    SnapshotManager::reconstruct(&snapshot, &deltas[..])?
-                } else {
-                    let mut s = serde_json::json!({});
-                    for d in &deltas { DeltaEngine::apply_delta(&mut s, &d.ops)?; }
-                    s
-                };
-                // Embed and store
-                let embedding = generator.generate_from_state(&state)
-                    .map_err(|e| anyhow::anyhow!("Embedding error: {}", e))?;
-                let metadata = VectorMetadata::new(coord.id.clone())
-                    .with_author("unknown".to_string());
-                store.store_embedding(&coord.id, embedding, metadata).await
-                    .map_err(|e| anyhow::anyhow!("Vector store error: {}", e))?;
-            }
-
-            // Query embedding and search
-            let q_embed = generator.generate(&query)
-                .map_err(|e| anyhow::anyhow!("Embedding error: {}", e))?;
-            let filter = if author.is_some() || tags.is_some() {
-                Some(VecSearchFilter { author, tags: tags.map(|s| s.split(',').map(|t| t.trim().to_string()).filter(|t| !t.is_empty()).collect()), created_after: None, created_before: None })
-            } else { None };
-            let mut results = store.search_by_vector(q_embed, limit, filter).await
-                .map_err(|e| anyhow::anyhow!("Search error: {}", e))?;
-            if let Some(min) = min_score { results.retain(|r| r.score >= min); }
+            let results = local_search(&facade, &query, limit, min_score, filters, index).await?;
             println!("Top {} results:", results.len());
-            for r in results { println!("  {}  (score: {:.4})", r.coord_id, r.score); }
+            for (coord_id, score) in results {
+                println!("  {}  (score: {:.4})", ids.show(coord_id.as_str()), score);
+            }
+        }
+
+        Commands::Index { command: IndexCommand::FitProjection { reduce_to, sample, seed, output } } => {
+            fit_projection(&facade, reduce_to, sample, seed, &output).await?;
+        }
+
+        Commands::Simulate { coords, deltas, state_size, authors, seed, profile } => {
+            let config = SimulationConfig {
+                coords,
+                deltas: simulate::parse_range(&deltas)?,
+                state_size: simulate::parse_range(&state_size)?,
+                authors,
+                seed,
+                profile: profile.parse()?,
+            };
+
+            let started = std::time::Instant::now();
+            let report = simulate::run_simulation(&facade, &config).await?;
+            let compression = report.compression();
+
+            println!("Simulation complete ({} profile, seed {}):", profile, seed);
+            println!("  Coordinates: {}", report.coordinates);
+            println!("  Deltas: {}", report.deltas);
+            println!("  Snapshots: {}", report.snapshots);
+            println!("  Large rewrites: {}", report.large_rewrites);
+            println!("  Full-state bytes: {}", compression.original_bytes);
+            println!("  Delta bytes: {}", compression.compressed_bytes);
+            println!("  Compression ratio: {:.2}%", compression.compression_ratio * 100.0);
+            println!(
+                "  Array diffs: {} element-level, {} replaced",
+                report.diff_stats.lcs_arrays, report.diff_stats.replaced_arrays
+            );
+            print_patch_ratios(&report.patch_ratios);
+            println!("  Elapsed: {:.2?}", started.elapsed());
+        }
+
+        Commands::Loadtest { .. } | Commands::Serve { .. } | Commands::Drill { .. } => {
+            unreachable!("handled before opening the database")
+        }
+    }
+
+    Ok(())
+}
+
+/// Print how large patches were relative to their states, per bucket
+fn print_patch_ratios(ratios: &PatchRatios) {
+    println!("  Patch size / state size ({} deltas, {} stored whole):", ratios.total(), ratios.full_replaces);
+    for (below, count) in ratios.buckets() {
+        let label = match below {
+            Some(bound) => format!("< {:.2}", bound),
+            None => format!(">= {:.2}", PATCH_RATIO_BOUNDS[PATCH_RATIO_BOUNDS.len() - 1]),
+        };
+        println!("    {:>7}  {}", label, count);
+    }
+}
+
+/// Search an in-memory index built from every head in the local database,
+/// returning matches with their scores
+#[cfg(feature = "vector")]
+async fn local_search(
+    facade: &BmsFacade,
+    query: &str,
+    limit: usize,
+    min_score: Option<f32>,
+    filters: SearchFilterArgs,
+    index: LocalIndexArgs,
+) -> Result<Vec<(CoordId, f32)>> {
+    use bms_core::filter::{metadata_tags, FilterRecord};
+    use bms_vector::batch::DEFAULT_BATCH_SIZE;
+    use bms_vector::{
+        embed_in_batches, BatchStats, EmbeddingGenerator, ExtractionConfig, InMemoryVectorStore, Projection,
+        SearchFilter as VecSearchFilter, VectorConfig, VectorMetadata, VectorStore,
+    };
+
+    let filter = filters.expression()?;
+    info!("Building in-memory index from current data (no API URL set)...");
+    let coords = facade.repository().list_coordinates(None, None).await?;
+    let mut generator = EmbeddingGenerator::new().map_err(|e| anyhow::anyhow!("Embedding init error: {}", e))?;
+    let fitted_projection = index
+        .projection
+        .map(|path| Projection::load(&path))
+        .transpose()
+        .map_err(|e| anyhow::anyhow!("Projection error: {}", e))?;
+    let config = VectorConfig {
+        dimension: generator.dimension(),
+        // A fitted projection carries its own output dimension
+        reduce_to: index.reduce_to.or(fitted_projection.as_ref().map(Projection::output_dimension)),
+        fitted_projection,
+        ..VectorConfig::default()
+    };
+    let store = InMemoryVectorStore::new(config)
+        .map_err(|e| anyhow::anyhow!("Vector store init error: {}", e))?;
+
+    // Reconstruct head states, with the configured facets appended; heads the
+    // filter expression rejects are never embedded
+    let extraction = ExtractionConfig::from_list(index.facets.as_deref().unwrap_or_default());
+    let mut heads = Vec::with_capacity(coords.len());
+    for coord in coords.iter().filter(|c| !c.is_ephemeral()) {
+        let Some(head) = facade.head(&coord.id).await? else { continue; };
+        if let Some(filter) = &filter {
+            let tags = metadata_tags(coord.metadata.as_ref());
+            let record = FilterRecord {
+                author: head.deltas.last().and_then(|d| d.author.as_deref()),
+                tags: &tags,
+                created_at: Some(coord.created_at),
+                metadata: coord.metadata.as_ref(),
+                state: Some(&head.state),
+            };
+            if !filter.matches(&record) {
+                continue;
+            }
         }
+        heads.push((coord.id.clone(), extraction.extract(&head.state, coord.metadata.as_ref())));
     }
 
+    // Embed in batches and store everything in one call
+    let texts: Vec<&str> = heads.iter().map(|(_, extracted)| extracted.text.as_str()).collect();
+    let mut stats = BatchStats::default();
+    let batch_size = index.embed_batch_size.unwrap_or(DEFAULT_BATCH_SIZE);
+    let embeddings = embed_in_batches(&texts, batch_size, &mut stats, |chunk| {
+        generator.generate_batch(chunk.to_vec())
+    });
+    let mut items = Vec::with_capacity(heads.len());
+    for ((coord_id, extracted), embedding) in heads.into_iter().zip(embeddings) {
+        match embedding {
+            Ok(embedding) => {
+                let metadata = VectorMetadata::new(coord_id.clone())
+                    .with_author("unknown".to_string())
+                    .with_facets(extracted.facet_keys());
+                items.push((coord_id, embedding, metadata));
+            }
+            Err(e) => warn!("Skipping {}, embedding failed: {}", coord_id, e),
+        }
+    }
+    store.store_embeddings_batch(items).await
+        .map_err(|e| anyhow::anyhow!("Vector store error: {}", e))?;
+    info!(
+        "Embedded {} states in {} batches ({:.1} ms)",
+        stats.items, stats.batches, stats.total_ms
+    );
+
+    // Query embedding and search
+    let q_embed = generator.generate(query)
+        .map_err(|e| anyhow::anyhow!("Embedding error: {}", e))?;
+    let search_filter = if filters.author.is_some() || filters.tags.is_some() {
+        let tags = filters.tag_list();
+        Some(VecSearchFilter { author: filters.author, tags, created_after: None, created_before: None, custom: None, expr: None })
+    } else { None };
+    let mut results = store.search_by_vector(q_embed, limit, search_filter).await
+        .map_err(|e| anyhow::anyhow!("Search error: {}", e))?;
+    if let Some(min) = min_score { results.retain(|r| r.score >= min); }
+    Ok(results.into_iter().map(|r| (r.coord_id, r.score)).collect())
+}
+
+#[cfg(not(feature = "vector"))]
+async fn local_search(
+    _facade: &BmsFacade,
+    _query: &str,
+    _limit: usize,
+    _min_score: Option<f32>,
+    _filters: SearchFilterArgs,
+    _index: LocalIndexArgs,
+) -> Result<Vec<(CoordId, f32)>> {
+    anyhow::bail!("Vector search unavailable: built without the `vector` feature; set BMS_API_URL to search through a server")
+}
+
+/// Embed a random sample of non-ephemeral heads and write the projection
+/// fitted to them
+#[cfg(feature = "vector")]
+async fn fit_projection(
+    facade: &BmsFacade,
+    reduce_to: usize,
+    sample: usize,
+    seed: u64,
+    output: &std::path::Path,
+) -> Result<()> {
+    use bms_vector::batch::DEFAULT_BATCH_SIZE;
+    use bms_vector::{embed_in_batches, BatchStats, EmbeddingGenerator, Projection, ProjectionSource};
+    use rand::seq::SliceRandom;
+    use rand::SeedableRng;
+
+    let mut coords: Vec<CoordId> = facade
+        .repository()
+        .list_coordinates(None, None)
+        .await?
+        .into_iter()
+        .filter(|c| !c.is_ephemeral())
+        .map(|c| c.id)
+        .collect();
+    coords.shuffle(&mut rand_chacha::ChaCha8Rng::seed_from_u64(seed));
+    coords.truncate(sample);
+
+    let mut states = Vec::with_capacity(coords.len());
+    for coord_id in &coords {
+        if let Some(head) = facade.head(coord_id).await? {
+            states.push(head.state);
+        }
+    }
+    info!("Embedding {} sampled heads...", states.len());
+
+    let mut generator = EmbeddingGenerator::new().map_err(|e| anyhow::anyhow!("Embedding init error: {}", e))?;
+    let mut stats = BatchStats::default();
+    let embeddings: Vec<Vec<f32>> = embed_in_batches(&states, DEFAULT_BATCH_SIZE, &mut stats, |chunk| {
+        generator.generate_from_states(chunk)
+    })
+    .into_iter()
+    .filter_map(|embedding| embedding.map_err(|e| warn!("Skipping a sample, embedding failed: {}", e)).ok())
+    .collect();
+
+    let projection = Projection::fit(&embeddings, reduce_to, seed)
+        .and_then(|projection| projection.save(output).map(|()| projection))
+        .map_err(|e| anyhow::anyhow!("Projection error: {}", e))?;
+    println!(
+        "Fitted {} -> {} dimensions to {} samples",
+        projection.input_dimension(),
+        projection.output_dimension(),
+        embeddings.len()
+    );
+    if let ProjectionSource::Fitted { retained_energy, .. } = projection.source() {
+        println!("  Retained energy: {:.2}%", retained_energy * 100.0);
+    }
+    println!("  Fingerprint: {}", projection.fingerprint());
+    println!("  Written to {}", output.display());
     Ok(())
 }
+
+#[cfg(not(feature = "vector"))]
+async fn fit_projection(
+    _facade: &BmsFacade,
+    _reduce_to: usize,
+    _sample: usize,
+    _seed: u64,
+    _output: &std::path::Path,
+) -> Result<()> {
+    anyhow::bail!("Projection fitting unavailable: built without the `vector` feature")
+}
@@ -0,0 +1,250 @@
+//! Coordinate and delta IDs as typed and as printed
+//!
+//! Commands accept a full ID or an unambiguous prefix of at least
+//! `MIN_PREFIX_LEN` characters, the way git accepts abbreviated commit
+//! hashes; coordinates can also be named by their rune alias. An exact ID
+//! wins over an alias, and an alias over a prefix. Generated IDs are found
+//! in any case, with or without hyphens, see `CoordId::parse`; so is a prefix
+//! that matches nothing as typed. Output shortens IDs to a fixed number of characters
+//! unless `--full-ids` is set. The API only accepts full IDs.
+
+use anyhow::{bail, Result};
+use bms_core::types::{CoordId, DeltaId};
+use bms_storage::BmsRepository;
+use std::fmt::Display;
+
+/// Shortest prefix looked up as an abbreviated ID
+pub const MIN_PREFIX_LEN: usize = 6;
+
+/// Characters of an ID printed by default
+pub const DEFAULT_SHORT_ID_LEN: usize = 10;
+
+/// Candidates listed when a prefix is ambiguous
+const MAX_CANDIDATES: usize = 10;
+
+/// Resolve a coordinate ID, rune alias, or ID prefix
+pub async fn resolve_coord(repo: &BmsRepository, input: &str) -> Result<CoordId> {
+    let exact = CoordId(input.to_string());
+    if repo.coordinate_exists(&exact).await? {
+        return Ok(exact);
+    }
+    let parsed = CoordId::parse(input);
+    if let Ok(parsed) = &parsed {
+        if parsed != &exact && repo.coordinate_exists(parsed).await? {
+            return Ok(parsed.clone());
+        }
+    }
+
+    if let Some(aliased) = repo.get_coordinate_by_alias(input).await? {
+        return Ok(aliased.id);
+    }
+
+    check_prefix_len("Coordinate", input)?;
+    let mut matches = repo
+        .find_coordinates_by_prefix(input, MAX_CANDIDATES as i64 + 1)
+        .await?;
+    let generated: String = input.chars().filter(|c| *c != '-').collect::<String>().to_ascii_uppercase();
+    if matches.is_empty() && generated != input {
+        matches = repo
+            .find_coordinates_by_prefix(&generated, MAX_CANDIDATES as i64 + 1)
+            .await?;
+    }
+    if matches.is_empty() {
+        // Say why a mistyped ID cannot exist rather than just not finding it
+        parsed?;
+    }
+    pick("Coordinate", input, matches)
+}
+
+/// Coordinate a write names with `--coord` or a hint, existing or not
+///
+/// An existing ID is taken as typed, so chains stored before IDs were checked
+/// stay writable; anything else goes through `CoordId::parse`.
+pub async fn resolve_hint(repo: &BmsRepository, input: &str) -> Result<CoordId> {
+    let exact = CoordId(input.to_string());
+    if repo.coordinate_exists(&exact).await? {
+        return Ok(exact);
+    }
+    Ok(CoordId::parse(input)?)
+}
+
+/// Resolve a delta ID or ID prefix among the deltas of `coord_id`
+pub async fn resolve_delta(repo: &BmsRepository, coord_id: &CoordId, input: &str) -> Result<DeltaId> {
+    let exact = DeltaId(input.to_string());
+    if repo.get_delta(&exact).await?.is_some_and(|d| &d.coord_id == coord_id) {
+        return Ok(exact);
+    }
+
+    check_prefix_len("Delta", input)?;
+    let matches = repo
+        .find_deltas_by_prefix(coord_id, input, MAX_CANDIDATES as i64 + 1)
+        .await?;
+    pick("Delta", input, matches)
+}
+
+fn check_prefix_len(kind: &str, input: &str) -> Result<()> {
+    if input.chars().count() < MIN_PREFIX_LEN {
+        bail!(
+            "{} not found: {} (an ID prefix needs at least {} characters)",
+            kind,
+            input,
+            MIN_PREFIX_LEN
+        );
+    }
+    Ok(())
+}
+
+fn pick<T: Display>(kind: &str, input: &str, mut matches: Vec<T>) -> Result<T> {
+    match matches.len() {
+        0 => bail!("{} not found: {}", kind, input),
+        1 => Ok(matches.remove(0)),
+        n => {
+            let mut candidates: Vec<String> =
+                matches.iter().take(MAX_CANDIDATES).map(|m| m.to_string()).collect();
+            if n > MAX_CANDIDATES {
+                candidates.push("...".to_string());
+            }
+            bail!("{} {} is ambiguous; candidates:\n  {}", kind, input, candidates.join("\n  "))
+        }
+    }
+}
+
+/// How IDs are printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IdFormat {
+    /// Characters kept, or `None` for full IDs
+    len: Option<usize>,
+}
+
+impl IdFormat {
+    /// Never shorter than `MIN_PREFIX_LEN`, so printed IDs can be typed back
+    pub fn new(len: usize, full: bool) -> Self {
+        Self {
+            len: (!full).then_some(len.max(MIN_PREFIX_LEN)),
+        }
+    }
+
+    pub fn show<'a>(&self, id: &'a str) -> &'a str {
+        match self.len.and_then(|len| id.char_indices().nth(len)) {
+            Some((end, _)) => &id[..end],
+            None => id,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bms_core::types::Coordinate;
+    use bms_core::{SnapshotManager, DEFAULT_SNAPSHOT_INTERVAL};
+    use bms_storage::{BmsFacade, StoreParams};
+    use serde_json::json;
+
+    async fn facade(name: &str) -> BmsFacade {
+        let path = std::env::temp_dir().join(format!("bms-resolve-{}-{}.db", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let repository = BmsRepository::new(path.to_str().unwrap()).await.unwrap();
+        let facade = BmsFacade::new(repository, SnapshotManager::new(DEFAULT_SNAPSHOT_INTERVAL));
+        for (coord, n) in [("ALPHA1XXXX", 1), ("ALPHA1YYYY", 2), ("ALPHA2ZZZZ", 3), ("ALPHA2ZZZZ", 4)] {
+            facade
+                .store(StoreParams {
+                    coord_id: Some(CoordId(coord.to_string())),
+                    state: json!({"n": n}),
+                    ..Default::default()
+                })
+                .await
+                .unwrap();
+        }
+        let aliased = |id: &str, alias: &str| Coordinate {
+            id: CoordId(id.to_string()),
+            rune_alias: Some(alias.to_string()),
+            created_at: chrono::Utc::now(),
+            metadata: None,
+            namespace: None,
+            hash_algorithm: Default::default(),
+            kind: Default::default(),
+            parent_coord_id: None,
+        };
+        let repo = facade.repository();
+        repo.insert_coordinate(&aliased("RUNEDCOORD", "ALPHA2")).await.unwrap();
+        repo.insert_coordinate(&aliased("SHADOWED", "ALPHA1XXXX")).await.unwrap();
+        facade
+    }
+
+    #[tokio::test]
+    async fn test_ids_resolve_by_precedence() {
+        let facade = facade("coords").await;
+        let repo = facade.repository();
+        let resolve = |input: &'static str| async move { resolve_coord(repo, input).await };
+
+        // A unique prefix, an exact ID over an alias, and an alias over a prefix
+        assert_eq!(resolve("ALPHA2Z").await.unwrap().as_str(), "ALPHA2ZZZZ");
+        assert_eq!(resolve("ALPHA1XXXX").await.unwrap().as_str(), "ALPHA1XXXX");
+        assert_eq!(resolve("ALPHA2").await.unwrap().as_str(), "RUNEDCOORD");
+
+        let ambiguous = resolve("ALPHA1").await.unwrap_err().to_string();
+        assert!(ambiguous.contains("ambiguous"), "{}", ambiguous);
+        assert!(ambiguous.contains("ALPHA1XXXX") && ambiguous.contains("ALPHA1YYYY"));
+
+        let short = resolve("ALPH").await.unwrap_err().to_string();
+        assert!(short.contains("at least 6 characters"), "{}", short);
+        assert!(resolve("BRAVO1").await.unwrap_err().to_string().contains("not found"));
+    }
+
+    #[tokio::test]
+    async fn test_generated_ids_resolve_in_any_case() {
+        let facade = facade("case").await;
+        let repo = facade.repository();
+        let generated = CoordId::from_bytes([9u8; bms_core::COORD_ID_BYTES]);
+        facade
+            .store(StoreParams {
+                coord_id: Some(generated.clone()),
+                state: json!({"n": 5}),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        let lower = generated.0.to_lowercase();
+        let grouped = format!("{}-{}", &lower[..13], &lower[13..]);
+        for input in [lower.as_str(), grouped.as_str(), &lower[..8], &grouped[..15]] {
+            assert_eq!(resolve_coord(repo, input).await.unwrap(), generated, "{}", input);
+        }
+        // Uppercased, a named ID is still found as a prefix of itself
+        assert_eq!(resolve_coord(repo, "alpha1xxxx").await.unwrap().as_str(), "ALPHA1XXXX");
+        assert!(resolve_coord(repo, "bravo1xxxx").await.unwrap_err().to_string().contains("not found"));
+
+        let typo = format!("{}0", &generated.0[..25]);
+        let err = resolve_coord(repo, &typo).await.unwrap_err().to_string();
+        assert!(err.contains("'0' at position 25"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_delta_prefixes_resolve_within_the_coordinate() {
+        let facade = facade("deltas").await;
+        let repo = facade.repository();
+        let coord = CoordId("ALPHA2ZZZZ".to_string());
+        let deltas = repo.get_deltas(&coord).await.unwrap();
+        let other = repo.get_deltas(&CoordId("ALPHA1XXXX".to_string())).await.unwrap();
+
+        let id = deltas[1].id.as_str();
+        assert_eq!(resolve_delta(repo, &coord, id).await.unwrap(), deltas[1].id);
+        assert_eq!(resolve_delta(repo, &coord, &id[..8]).await.unwrap(), deltas[1].id);
+        assert!(resolve_delta(repo, &coord, &id[..4]).await.is_err());
+
+        // Another coordinate's delta is not found, even by its full ID
+        let foreign = other[0].id.as_str();
+        assert!(resolve_delta(repo, &coord, foreign).await.is_err());
+        assert!(resolve_delta(repo, &coord, &foreign[..8]).await.is_err());
+    }
+
+    #[test]
+    fn test_ids_print_shortened() {
+        let id = "MFRGGZDFMZTWQ2LKNNWG23TPOA";
+        assert_eq!(IdFormat::new(DEFAULT_SHORT_ID_LEN, false).show(id), "MFRGGZDFMZ");
+        assert_eq!(IdFormat::new(DEFAULT_SHORT_ID_LEN, true).show(id), id);
+        assert_eq!(IdFormat::new(2, false).show(id), "MFRGGZ");
+        assert_eq!(IdFormat::new(DEFAULT_SHORT_ID_LEN, false).show("notes"), "notes");
+        assert_eq!(IdFormat::new(6, false).show("ñññññññ"), "ññññññ");
+    }
+}
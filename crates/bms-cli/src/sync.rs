@@ -0,0 +1,434 @@
+//! Copy delta chains between a local database and a BMS API server
+//!
+//! Chains are compared by chain hash. When one side's chain is a prefix of
+//! the other's, the missing deltas are appended to the side that is behind,
+//! keeping their IDs, hashes, and timestamps. When both sides have advanced
+//! from a shared delta nothing is written; the coordinate is reported as
+//! diverged with that branch point.
+//!
+//! Pushes go through `POST /coords/:id/append-deltas` as NDJSON, which skips
+//! deltas the server already has, so an interrupted sync is resumed by
+//! running it again.
+
+use crate::resolve::IdFormat;
+use anyhow::{bail, Context, Result};
+use bms_core::types::{CoordId, Delta, DeltaId, Hash};
+use bms_storage::BmsFacade;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+/// Deltas per append request
+const CHUNK_SIZE: usize = 256;
+
+/// Last delta two diverged chains share
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BranchPoint {
+    /// Position in the chain, starting at 1
+    pub seq: usize,
+    pub delta_id: DeltaId,
+    pub chain_hash: Hash,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyncStatus {
+    UpToDate,
+    /// Deltas appended to the destination
+    FastForwarded { deltas: usize },
+    /// The destination has deltas the source lacks; sync the other way
+    DestinationAhead { deltas: usize },
+    /// Both sides advanced; `branch_point` is `None` if they share no delta
+    Diverged {
+        branch_point: Option<BranchPoint>,
+        local_only: usize,
+        remote_only: usize,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct CoordSync {
+    pub coord_id: CoordId,
+    pub status: SyncStatus,
+}
+
+impl CoordSync {
+    pub fn print(&self, ids: IdFormat) {
+        let coord = ids.show(self.coord_id.as_str());
+        match &self.status {
+            SyncStatus::UpToDate => println!("  {}  up to date", coord),
+            SyncStatus::FastForwarded { deltas } => {
+                println!("  {}  fast-forwarded {} delta(s)", coord, deltas)
+            }
+            SyncStatus::DestinationAhead { deltas } => println!(
+                "  {}  destination is {} delta(s) ahead; sync the other way",
+                coord, deltas
+            ),
+            SyncStatus::Diverged { branch_point, local_only, remote_only } => {
+                println!(
+                    "  {}  DIVERGED: {} local and {} remote delta(s) since the branch point",
+                    coord, local_only, remote_only
+                );
+                match branch_point {
+                    Some(bp) => println!(
+                        "      branch point: seq {}, delta {}, chain hash {}",
+                        bp.seq, ids.show(bp.delta_id.as_str()), bp.chain_hash.as_str()
+                    ),
+                    None => println!("      no shared history"),
+                }
+            }
+        }
+    }
+}
+
+/// Deltas and metadata of a coordinate as served by `GET /coords/:id/deltas`
+#[derive(Debug, Deserialize)]
+struct RemoteChain {
+    metadata: Option<HashMap<String, Value>>,
+    deltas: Vec<Delta>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RemoteHead {
+    chain_hash: Hash,
+}
+
+/// Client for the sync endpoints of one server
+pub struct SyncClient {
+    http: reqwest::Client,
+    target: String,
+    token: Option<String>,
+}
+
+impl SyncClient {
+    pub fn new(target: &str, token: Option<String>) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            target: target.trim_end_matches('/').to_string(),
+            token,
+        }
+    }
+
+    fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        let request = self.http.request(method, format!("{}{}", self.target, path));
+        match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+
+    async fn check(response: reqwest::Response) -> Result<reqwest::Response> {
+        let status = response.status();
+        if !status.is_success() {
+            bail!("API error ({}): {}", status, response.text().await.unwrap_or_default());
+        }
+        Ok(response)
+    }
+
+    /// IDs of every coordinate on the server
+    pub async fn list_coordinates(&self) -> Result<Vec<CoordId>> {
+        let response = self
+            .request(reqwest::Method::GET, &format!("/coords?limit={}", i64::MAX))
+            .send()
+            .await?;
+        let coords: Vec<Value> = Self::check(response).await?.json().await?;
+        coords
+            .iter()
+            .map(|c| c["id"].as_str().map(|id| CoordId(id.to_string())))
+            .collect::<Option<_>>()
+            .context("coordinate listing without ids")
+    }
+
+    async fn head(&self, coord_id: &CoordId) -> Result<Option<Hash>> {
+        let response = self
+            .request(reqwest::Method::GET, &format!("/coords/{}/head", coord_id))
+            .send()
+            .await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let head: RemoteHead = Self::check(response).await?.json().await?;
+        Ok(Some(head.chain_hash))
+    }
+
+    /// Deltas after `after`, or `None` if `after` is not in the server's chain
+    async fn deltas(&self, coord_id: &CoordId, after: Option<&Hash>) -> Result<Option<RemoteChain>> {
+        let mut path = format!("/coords/{}/deltas", coord_id);
+        if let Some(after) = after {
+            path.push_str(&format!("?after={}", after.as_str()));
+        }
+        let response = self.request(reqwest::Method::GET, &path).send().await?;
+        if response.status() == StatusCode::CONFLICT {
+            return Ok(None);
+        }
+        Ok(Some(Self::check(response).await?.json().await?))
+    }
+
+    async fn append(
+        &self,
+        coord_id: &CoordId,
+        deltas: &[Delta],
+        metadata: Option<&HashMap<String, Value>>,
+    ) -> Result<()> {
+        for chunk in deltas.chunks(CHUNK_SIZE) {
+            // Sent as NDJSON, which the server streams rather than buffering
+            // under the store body limit
+            let mut body = json!({ "metadata": metadata }).to_string();
+            for delta in chunk {
+                body.push('\n');
+                body.push_str(&serde_json::to_string(delta)?);
+            }
+            let response = self
+                .request(reqwest::Method::POST, &format!("/coords/{}/append-deltas", coord_id))
+                .header(reqwest::header::CONTENT_TYPE, "application/x-ndjson")
+                .body(body)
+                .send()
+                .await?;
+            Self::check(response)
+                .await
+                .with_context(|| format!("appending to {}", coord_id))?;
+        }
+        Ok(())
+    }
+}
+
+/// Position of `hash` in `chain`
+fn position(chain: &[Delta], hash: &Hash) -> Option<usize> {
+    chain.iter().position(|d| &d.chain_hash == hash)
+}
+
+/// Status of two chains where neither is a prefix of the other
+fn diverged(local: &[Delta], remote: &[Delta]) -> SyncStatus {
+    let shared = local
+        .iter()
+        .zip(remote)
+        .take_while(|(l, r)| l.chain_hash == r.chain_hash)
+        .count();
+    SyncStatus::Diverged {
+        branch_point: shared.checked_sub(1).map(|i| BranchPoint {
+            seq: shared,
+            delta_id: local[i].id.clone(),
+            chain_hash: local[i].chain_hash.clone(),
+        }),
+        local_only: local.len() - shared,
+        remote_only: remote.len() - shared,
+    }
+}
+
+/// Upload local deltas the server lacks
+pub async fn push(facade: &BmsFacade, client: &SyncClient, coord_id: &CoordId) -> Result<SyncStatus> {
+    let repo = facade.repository();
+    let local = repo.get_deltas(coord_id).await?;
+    if local.is_empty() {
+        bail!("Coordinate not found locally: {}", coord_id);
+    }
+
+    let missing = match client.head(coord_id).await? {
+        None => {
+            let metadata = repo.get_coordinate(coord_id).await?.and_then(|c| c.metadata);
+            client.append(coord_id, &local, metadata.as_ref()).await?;
+            return Ok(SyncStatus::FastForwarded { deltas: local.len() });
+        }
+        Some(head) => match position(&local, &head) {
+            Some(i) => &local[i + 1..],
+            None => {
+                let remote = client
+                    .deltas(coord_id, None)
+                    .await?
+                    .context("server chain changed during sync")?
+                    .deltas;
+                let tip = &local.last().expect("checked non-empty").chain_hash;
+                return Ok(match position(&remote, tip) {
+                    Some(i) => SyncStatus::DestinationAhead { deltas: remote.len() - i - 1 },
+                    None => diverged(&local, &remote),
+                });
+            }
+        },
+    };
+
+    if missing.is_empty() {
+        return Ok(SyncStatus::UpToDate);
+    }
+    client.append(coord_id, missing, None).await?;
+    Ok(SyncStatus::FastForwarded { deltas: missing.len() })
+}
+
+/// Append server deltas the local chain lacks
+pub async fn pull(facade: &BmsFacade, client: &SyncClient, coord_id: &CoordId) -> Result<SyncStatus> {
+    let Some(remote_head) = client.head(coord_id).await? else {
+        bail!("Coordinate not found on the server: {}", coord_id);
+    };
+    let local = facade.repository().get_deltas(coord_id).await?;
+    let local_tip = local.last().map(|d| &d.chain_hash);
+    if local_tip == Some(&remote_head) {
+        return Ok(SyncStatus::UpToDate);
+    }
+    if let Some(i) = position(&local, &remote_head) {
+        return Ok(SyncStatus::DestinationAhead { deltas: local.len() - i - 1 });
+    }
+
+    let chain = match client.deltas(coord_id, local_tip).await? {
+        Some(chain) => chain,
+        None => {
+            let remote = client
+                .deltas(coord_id, None)
+                .await?
+                .context("server chain changed during sync")?
+                .deltas;
+            return Ok(diverged(&local, &remote));
+        }
+    };
+    if chain.deltas.is_empty() {
+        return Ok(SyncStatus::UpToDate);
+    }
+    let outcome = facade
+        .append_deltas(coord_id, chain.deltas, chain.metadata)
+        .await?;
+    Ok(SyncStatus::FastForwarded { deltas: outcome.appended })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bms_core::{SnapshotManager, DEFAULT_SNAPSHOT_INTERVAL};
+    use bms_storage::{BmsRepository, StoreParams};
+    use std::sync::Arc;
+
+    const TOKEN: &str = "sync-secret";
+
+    async fn local(name: &str) -> BmsFacade {
+        let path = std::env::temp_dir().join(format!("bms-sync-{}-{}.db", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let repository = BmsRepository::new(path.to_str().unwrap()).await.unwrap();
+        BmsFacade::new(repository, SnapshotManager::new(DEFAULT_SNAPSHOT_INTERVAL))
+    }
+
+    async fn spawn_server(name: &str) -> (String, Arc<bms_api::AppState>) {
+        let path = std::env::temp_dir().join(format!("bms-sync-{}-{}.db", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut state = bms_api::build_state(path.to_str().unwrap(), false).await.unwrap();
+        Arc::get_mut(&mut state).unwrap().admin_token = Some(TOKEN.to_string());
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = bms_api::router(state.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (format!("http://{}", addr), state)
+    }
+
+    async fn store(facade: &BmsFacade, coord: &CoordId, state: Value) {
+        let metadata = Some([("origin".to_string(), json!("laptop"))].into());
+        facade
+            .store(StoreParams {
+                coord_id: Some(coord.clone()),
+                state,
+                metadata,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    }
+
+    async fn chain_hashes(facade: &BmsFacade, coord: &CoordId) -> Vec<Hash> {
+        let deltas = facade.repository().get_deltas(coord).await.unwrap();
+        deltas.into_iter().map(|d| d.chain_hash).collect()
+    }
+
+    #[tokio::test]
+    async fn test_push_and_pull_fast_forward() {
+        let (target, server) = spawn_server("ff-server").await;
+        let client = SyncClient::new(&target, Some(TOKEN.to_string()));
+        let (laptop, desktop) = (local("ff-laptop").await, local("ff-desktop").await);
+        let coord = CoordId("OFFLINE".to_string());
+        for n in 0..3 {
+            store(&laptop, &coord, json!({"notes": (0..=n).collect::<Vec<_>>()})).await;
+        }
+
+        assert_eq!(push(&laptop, &client, &coord).await.unwrap(), SyncStatus::FastForwarded { deltas: 3 });
+        assert_eq!(push(&laptop, &client, &coord).await.unwrap(), SyncStatus::UpToDate);
+        assert_eq!(chain_hashes(&server.facade, &coord).await, chain_hashes(&laptop, &coord).await);
+        let created = server.facade.repository().get_coordinate(&coord).await.unwrap().unwrap();
+        assert_eq!(created.metadata.unwrap()["origin"], "laptop");
+
+        // Another machine pulls, and later picks up only the new delta
+        assert_eq!(client.list_coordinates().await.unwrap(), std::slice::from_ref(&coord));
+        assert_eq!(pull(&desktop, &client, &coord).await.unwrap(), SyncStatus::FastForwarded { deltas: 3 });
+        store(&laptop, &coord, json!({"notes": [0, 1, 2, 3]})).await;
+        push(&laptop, &client, &coord).await.unwrap();
+        assert_eq!(pull(&desktop, &client, &coord).await.unwrap(), SyncStatus::FastForwarded { deltas: 1 });
+        assert_eq!(pull(&desktop, &client, &coord).await.unwrap(), SyncStatus::UpToDate);
+        assert_eq!(desktop.head(&coord).await.unwrap().unwrap().state, json!({"notes": [0, 1, 2, 3]}));
+
+        // A side that is ahead is left alone in the other direction
+        store(&desktop, &coord, json!({"notes": [4]})).await;
+        assert_eq!(pull(&desktop, &client, &coord).await.unwrap(), SyncStatus::DestinationAhead { deltas: 1 });
+        assert_eq!(push(&laptop, &client, &coord).await.unwrap(), SyncStatus::UpToDate);
+        push(&desktop, &client, &coord).await.unwrap();
+        assert_eq!(push(&laptop, &client, &coord).await.unwrap(), SyncStatus::DestinationAhead { deltas: 1 });
+    }
+
+    #[tokio::test]
+    async fn test_interrupted_push_resumes() {
+        let (target, server) = spawn_server("resume-server").await;
+        let client = SyncClient::new(&target, Some(TOKEN.to_string()));
+        let laptop = local("resume-laptop").await;
+        let coord = CoordId("RESUMED".to_string());
+        for n in 0..4 {
+            store(&laptop, &coord, json!({"n": n})).await;
+        }
+
+        // The first two deltas made it before the connection dropped
+        let deltas = laptop.repository().get_deltas(&coord).await.unwrap();
+        client.append(&coord, &deltas[..2], None).await.unwrap();
+        assert_eq!(push(&laptop, &client, &coord).await.unwrap(), SyncStatus::FastForwarded { deltas: 2 });
+        assert_eq!(chain_hashes(&server.facade, &coord).await, chain_hashes(&laptop, &coord).await);
+
+        // A retried chunk is accepted without duplicating anything
+        client.append(&coord, &deltas, None).await.unwrap();
+        assert_eq!(server.facade.repository().get_deltas(&coord).await.unwrap().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_divergence_reports_the_branch_point() {
+        let (target, server) = spawn_server("diverge-server").await;
+        let client = SyncClient::new(&target, Some(TOKEN.to_string()));
+        let laptop = local("diverge-laptop").await;
+        let coord = CoordId("FORKED".to_string());
+        store(&laptop, &coord, json!({"draft": 1})).await;
+        store(&laptop, &coord, json!({"draft": 2})).await;
+        push(&laptop, &client, &coord).await.unwrap();
+        let shared = laptop.repository().get_deltas(&coord).await.unwrap().pop().unwrap();
+
+        // Both sides write while the laptop is offline
+        store(&laptop, &coord, json!({"draft": 3, "by": "laptop"})).await;
+        store(&laptop, &coord, json!({"draft": 4, "by": "laptop"})).await;
+        store(&server.facade, &coord, json!({"draft": 3, "by": "server"})).await;
+        let server_chain = chain_hashes(&server.facade, &coord).await;
+
+        let expected = SyncStatus::Diverged {
+            branch_point: Some(BranchPoint {
+                seq: 2,
+                delta_id: shared.id,
+                chain_hash: shared.chain_hash,
+            }),
+            local_only: 2,
+            remote_only: 1,
+        };
+        assert_eq!(push(&laptop, &client, &coord).await.unwrap(), expected);
+        assert_eq!(pull(&laptop, &client, &coord).await.unwrap(), expected);
+        assert_eq!(chain_hashes(&server.facade, &coord).await, server_chain);
+        assert_eq!(laptop.repository().get_deltas(&coord).await.unwrap().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_sync_requires_the_admin_token() {
+        let (target, _server) = spawn_server("auth-server").await;
+        let laptop = local("auth-laptop").await;
+        let coord = CoordId("GUARDED".to_string());
+        store(&laptop, &coord, json!({"n": 1})).await;
+
+        let client = SyncClient::new(&target, Some("wrong".to_string()));
+        let err = push(&laptop, &client, &coord).await.unwrap_err();
+        assert!(format!("{:#}", err).contains("403"));
+    }
+}
@@ -0,0 +1,75 @@
+//! Array diff strategies on a 1,000-element array with one change
+//!
+//! Positional diffs shift every element after an insert or remove, so a
+//! single change can rewrite most of the array. Before timing, each case
+//! prints the canonical patch size per strategy next to the new state's size.
+
+use bms_core::{ArrayStrategy, Canonicalizer, DeltaEngine, DiffOptions};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use serde_json::{json, Value};
+
+const ITEMS: usize = 1_000;
+
+fn item(id: usize) -> Value {
+    json!({"id": format!("item-{}", id), "name": format!("Item {}", id), "qty": id % 7})
+}
+
+fn state(items: Vec<Value>) -> Value {
+    json!({"items": items})
+}
+
+/// (case, previous state, current state)
+fn cases() -> Vec<(&'static str, Value, Value)> {
+    let base: Vec<Value> = (0..ITEMS).map(item).collect();
+
+    let mut edited = base.clone();
+    edited[ITEMS / 2]["qty"] = json!(99);
+    let mut inserted = base.clone();
+    inserted.insert(1, item(ITEMS));
+    let mut removed = base.clone();
+    removed.remove(1);
+
+    vec![
+        ("edit_one", state(base.clone()), state(edited)),
+        ("insert_one", state(base.clone()), state(inserted)),
+        ("remove_one", state(base.clone()), state(removed)),
+    ]
+}
+
+fn strategies() -> Vec<(&'static str, DiffOptions)> {
+    let with = |array_strategy| DiffOptions { array_strategy, ..Default::default() };
+    vec![
+        ("replace", with(ArrayStrategy::Replace)),
+        ("lcs", with(ArrayStrategy::Lcs)),
+        ("auto", with(ArrayStrategy::default())),
+        ("by_id", with(ArrayStrategy::ById { key: "id".to_string() })),
+    ]
+}
+
+fn patch_len(ops: &[json_patch::PatchOperation]) -> usize {
+    Canonicalizer::canonical_len(&serde_json::to_value(ops).unwrap()).unwrap()
+}
+
+fn bench_array_diff(c: &mut Criterion) {
+    let mut group = c.benchmark_group("array_diff");
+    group.sample_size(20);
+
+    for (case, prev, current) in cases() {
+        let state_len = Canonicalizer::canonical_len(&current).unwrap();
+        let plain = DeltaEngine::compute_delta(&prev, &current).unwrap();
+        println!("{}: state {} bytes, json_patch::diff {} bytes", case, state_len, patch_len(&plain));
+        for (name, options) in strategies() {
+            let (ops, _) = DeltaEngine::compute_delta_optimized(&prev, &current, &options).unwrap();
+            println!("  {:<8} {:>3} ops {:>7} bytes", name, ops.len(), patch_len(&ops));
+
+            group.bench_with_input(BenchmarkId::new(name, case), &options, |b, options| {
+                b.iter(|| DeltaEngine::compute_delta_optimized(&prev, &current, options).unwrap())
+            });
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_array_diff);
+criterion_main!(benches);
@@ -0,0 +1,133 @@
+//! Per-coordinate access control lists
+//!
+//! Coordinate metadata can restrict which API keys read or write the
+//! coordinate (`"acl": {"read": ["key:…", "*"], "write": ["key:…"]}`). `*`
+//! matches every caller, including anonymous ones. Once an ACL is present a
+//! key missing from a list is denied that access; a coordinate without an ACL
+//! is open to everyone. Write access implies read access.
+
+use crate::error::{BmsError, Result};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Coordinate metadata key holding the ACL document
+pub const ACL_METADATA_KEY: &str = "acl";
+
+/// ACL entry matching every caller
+pub const ANY_KEY: &str = "*";
+
+/// Kind of access a request needs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Access {
+    Read,
+    Write,
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Access::Read => "read",
+            Access::Write => "write",
+        })
+    }
+}
+
+/// Key ids allowed to read and write a coordinate
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Acl {
+    pub read: Vec<String>,
+    pub write: Vec<String>,
+}
+
+impl Acl {
+    /// Read the ACL from coordinate metadata
+    ///
+    /// Returns `None` when the metadata has no `acl` key. The document must be
+    /// an object with only `read` and `write` members, each an array of
+    /// non-empty key ids; an omitted list grants nobody that access.
+    pub fn from_metadata(metadata: &HashMap<String, Value>) -> Result<Option<Self>> {
+        let Some(document) = metadata.get(ACL_METADATA_KEY) else {
+            return Ok(None);
+        };
+        let Some(document) = document.as_object() else {
+            return Err(BmsError::InvalidState(
+                "acl must be an object with read and write lists".to_string(),
+            ));
+        };
+
+        let mut acl = Acl::default();
+        for (field, value) in document {
+            let list = match field.as_str() {
+                "read" => &mut acl.read,
+                "write" => &mut acl.write,
+                other => {
+                    return Err(BmsError::InvalidState(format!("Unknown acl field: {}", other)))
+                }
+            };
+            *list = value
+                .as_array()
+                .and_then(|a| {
+                    a.iter()
+                        .map(|v| v.as_str().filter(|s| !s.is_empty()).map(String::from))
+                        .collect()
+                })
+                .ok_or_else(|| {
+                    BmsError::InvalidState(format!("acl.{} must be an array of key ids", field))
+                })?;
+        }
+        Ok(Some(acl))
+    }
+
+    /// Whether `key_id` has `access`
+    pub fn allows(&self, access: Access, key_id: &str) -> bool {
+        let listed = |list: &[String]| list.iter().any(|k| k == ANY_KEY || k == key_id);
+        match access {
+            Access::Read => listed(&self.read) || listed(&self.write),
+            Access::Write => listed(&self.write),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn acl(document: Value) -> Result<Option<Acl>> {
+        Acl::from_metadata(&HashMap::from([(ACL_METADATA_KEY.to_string(), document)]))
+    }
+
+    #[test]
+    fn test_acl_from_metadata() {
+        assert_eq!(Acl::from_metadata(&HashMap::new()).unwrap(), None);
+
+        let parsed = acl(json!({"read": ["*"], "write": ["key:a"]})).unwrap().unwrap();
+        assert_eq!(parsed.read, vec!["*"]);
+        assert_eq!(parsed.write, vec!["key:a"]);
+        assert_eq!(acl(json!({})).unwrap(), Some(Acl::default()));
+
+        assert!(acl(json!(["key:a"])).is_err());
+        assert!(acl(json!({"read": "key:a"})).is_err());
+        assert!(acl(json!({"read": [""]})).is_err());
+        assert!(acl(json!({"read": [1]})).is_err());
+        assert!(acl(json!({"admin": ["key:a"]})).is_err());
+    }
+
+    #[test]
+    fn test_listed_keys_only() {
+        let acl = acl(json!({"read": ["key:r"], "write": ["key:w"]})).unwrap().unwrap();
+        assert!(acl.allows(Access::Read, "key:r"));
+        assert!(!acl.allows(Access::Write, "key:r"));
+        // Writers can read what they write
+        assert!(acl.allows(Access::Read, "key:w"));
+        assert!(acl.allows(Access::Write, "key:w"));
+        assert!(!acl.allows(Access::Read, "key:other"));
+
+        let open = Acl { read: vec![ANY_KEY.to_string()], write: Vec::new() };
+        assert!(open.allows(Access::Read, "anonymous"));
+        assert!(!open.allows(Access::Write, "anonymous"));
+    }
+}
@@ -0,0 +1,197 @@
+//! Crash-safe file output
+//!
+//! Files are written to `<path>.tmp-<pid>` next to the destination, synced,
+//! and renamed over it, and then the directory is synced so the rename itself
+//! survives a crash. Readers see either the previous file or the complete new
+//! one, never a partial write under the final name. A temp file left behind
+//! by a crashed run is removed the next time that destination is written.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Marker between a destination's file name and the writer's pid
+const TEMP_MARKER: &str = ".tmp-";
+
+/// Output file that only appears under its final name once committed
+///
+/// Dropping it without calling `commit` removes the temp file and leaves
+/// any existing file at the destination untouched.
+pub struct AtomicFile {
+    path: PathBuf,
+    temp: PathBuf,
+    file: Option<BufWriter<File>>,
+}
+
+impl AtomicFile {
+    /// Start writing `path`, removing stale temp files for it first
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        remove_stale_temps(&path)?;
+        let temp = temp_path(&path);
+        let file = File::create(&temp)?;
+        Ok(Self {
+            path,
+            temp,
+            file: Some(BufWriter::new(file)),
+        })
+    }
+
+    /// Sync the written data and move it into place
+    pub fn commit(mut self) -> io::Result<()> {
+        let file = self.file.take().expect("file is present until commit");
+        let file = file.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()?;
+        drop(file);
+        std::fs::rename(&self.temp, &self.path)?;
+        sync_dir(&self.path)
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.as_mut().expect("file is present until commit").write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.as_mut().expect("file is present until commit").flush()
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            let _ = std::fs::remove_file(&self.temp);
+        }
+    }
+}
+
+/// Replace `path` with `contents` atomically
+pub fn atomic_write(path: impl AsRef<Path>, contents: &[u8]) -> io::Result<()> {
+    let mut file = AtomicFile::create(path)?;
+    file.write_all(contents)?;
+    file.commit()
+}
+
+/// Temp file this process writes before renaming to `path`
+pub fn temp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!("{}{}", TEMP_MARKER, std::process::id()));
+    path.with_file_name(name)
+}
+
+/// Remove temp files for `path` left by writers that are no longer running
+///
+/// Returns the removed paths. Where liveness cannot be checked (outside
+/// Linux), every other process's temp file counts as stale.
+pub fn remove_stale_temps(path: &Path) -> io::Result<Vec<PathBuf>> {
+    let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+        return Ok(Vec::new());
+    };
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let prefix = format!("{}{}", name, TEMP_MARKER);
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut removed = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        let Some(pid) = file_name
+            .to_str()
+            .and_then(|n| n.strip_prefix(&prefix))
+            .and_then(|pid| pid.parse::<u32>().ok())
+        else {
+            continue;
+        };
+        if pid != std::process::id() && !process_running(pid) {
+            std::fs::remove_file(entry.path())?;
+            removed.push(entry.path());
+        }
+    }
+    Ok(removed)
+}
+
+#[cfg(target_os = "linux")]
+fn process_running(pid: u32) -> bool {
+    Path::new("/proc").join(pid.to_string()).exists()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn process_running(_pid: u32) -> bool {
+    false
+}
+
+/// Sync the directory holding `path` so a rename into it is durable
+#[cfg(unix)]
+fn sync_dir(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => File::open(dir)?.sync_all(),
+        _ => File::open(".")?.sync_all(),
+    }
+}
+
+/// Directories cannot be opened for syncing on this platform
+#[cfg(not(unix))]
+fn sync_dir(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("bms-atomic-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_partial_write_never_reaches_the_final_name() {
+        let dir = scratch("partial");
+        let path = dir.join("export.ndjson");
+        atomic_write(&path, b"old\n").unwrap();
+
+        let mut file = AtomicFile::create(&path).unwrap();
+        file.write_all(b"new, but interrupted").unwrap();
+        file.flush().unwrap();
+        // Until the commit only the temp file has the new bytes
+        assert_eq!(std::fs::read(&path).unwrap(), b"old\n");
+        assert!(temp_path(&path).exists());
+        drop(file);
+        assert!(!temp_path(&path).exists());
+        assert_eq!(std::fs::read(&path).unwrap(), b"old\n");
+
+        let mut file = AtomicFile::create(&path).unwrap();
+        file.write_all(b"new\n").unwrap();
+        file.commit().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"new\n");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_stale_temps_are_removed_on_the_next_write() {
+        let dir = scratch("stale");
+        let path = dir.join("index.json");
+        // No process has this pid, so its temp file was left by a crash
+        let stale = dir.join(format!("index.json{}{}", TEMP_MARKER, u32::MAX));
+        let other = dir.join(format!("other.json{}{}", TEMP_MARKER, u32::MAX));
+        std::fs::write(&stale, "half an ind").unwrap();
+        std::fs::write(&other, "someone else's").unwrap();
+
+        atomic_write(&path, b"{}").unwrap();
+        assert!(!stale.exists());
+        assert!(other.exists());
+        assert_eq!(remove_stale_temps(&dir.join("other.json")).unwrap(), vec![other]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,22 +1,140 @@
 use crate::error::{BmsError, Result};
+use crate::types::Hash;
+use serde::de::{DeserializeSeed, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::collections::BTreeMap;
+use sha3::{Digest, Sha3_256};
+use std::cell::RefCell;
+use std::borrow::Cow;
+use std::fmt::Write as _;
+use unicode_normalization::{is_nfc, UnicodeNormalization};
+
+/// Version of the canonical encoding
+///
+/// Every coordinate ID, delta ID, and chain hash so far was taken over
+/// `Legacy`, so it stays the default and those hashes keep verifying.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CanonicalForm {
+    /// serde_json's compact output with keys sorted by UTF-8 bytes; numbers
+    /// keep the form they were parsed in, so `1` and `1.0` differ
+    #[default]
+    Legacy,
+    /// RFC 8785 (JCS): keys sorted by UTF-16 code units and numbers in the
+    /// shortest ECMAScript form; like the RFC, strings are not normalized
+    Jcs,
+    /// `Legacy` with every string and object key in Unicode NFC, so composed
+    /// and decomposed text canonicalize alike
+    Nfc,
+}
+
+/// Bounds on the values the canonicalizer accepts
+///
+/// Canonicalizing takes no stack per level of nesting, but serde_json's own
+/// serializing, cloning, and dropping of a value do, so a state nested
+/// thousands deep is refused here with `InvalidState` rather than left to
+/// overflow the stack further on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CanonicalLimits {
+    /// Most arrays and objects nested in one another
+    pub max_depth: usize,
+    /// Longest canonical form in bytes; `None` is unlimited
+    pub max_total_bytes: Option<usize>,
+}
+
+impl CanonicalLimits {
+    pub const DEFAULT_MAX_DEPTH: usize = 512;
+}
+
+impl Default for CanonicalLimits {
+    fn default() -> Self {
+        Self { max_depth: Self::DEFAULT_MAX_DEPTH, max_total_bytes: None }
+    }
+}
 
 /// Canonicalizer for deterministic JSON serialization
 ///
 /// Ensures consistent serialization across platforms:
 /// - Sorted keys
 /// - Compact separators (no spaces)
-/// - UTF-8 NFC normalization
+/// - UTF-8 NFC normalization, in `CanonicalForm::Nfc` only
 /// - Deterministic ordering
 pub struct Canonicalizer;
 
 impl Canonicalizer {
     /// Canonicalize a JSON value to a deterministic byte representation
     pub fn canonicalize(value: &Value) -> Result<Vec<u8>> {
-        let normalized = Self::normalize_value(value)?;
-        let canonical_str = serde_json::to_string(&normalized)?;
-        Ok(canonical_str.into_bytes())
+        Self::canonicalize_as(value, CanonicalForm::Legacy)
+    }
+
+    /// Canonicalize in the given form
+    pub fn canonicalize_as(value: &Value, form: CanonicalForm) -> Result<Vec<u8>> {
+        Self::canonicalize_with(value, form, &CanonicalLimits::default())
+    }
+
+    /// Canonicalize in the given form, failing with `InvalidState` once
+    /// `value` breaks one of `limits`
+    pub fn canonicalize_with(value: &Value, form: CanonicalForm, limits: &CanonicalLimits) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        Self::emit(value, form, limits, &mut |bytes| out.extend_from_slice(bytes))?;
+        Ok(out)
+    }
+
+    /// Canonicalize per RFC 8785
+    ///
+    /// Numbers are IEEE doubles as in JavaScript, so integers beyond 2^53
+    /// lose precision here, as the RFC prescribes.
+    pub fn canonicalize_jcs(value: &Value) -> Result<Vec<u8>> {
+        Self::canonicalize_as(value, CanonicalForm::Jcs)
+    }
+
+    /// ECMAScript `Number.prototype.toString` of `n`, as RFC 8785 formats
+    /// numbers
+    ///
+    /// Integral values print without a fraction, `-0` prints as `0`, and NaN
+    /// and the infinities are errors.
+    pub fn format_jcs_number(n: f64) -> Result<String> {
+        if !n.is_finite() {
+            return Err(BmsError::InvalidState(format!("{} has no JSON form", n)));
+        }
+        if n == 0.0 {
+            return Ok("0".to_string());
+        }
+        // Rust's exponent form has the fewest digits that round-trip, but
+        // of two equally short candidates ECMAScript takes the closer one,
+        // which exact formatting to that many digits yields
+        let shortest = format!("{:e}", n.abs());
+        let precision = shortest.split_once('e').expect("exponent form has an e").0.len().saturating_sub(2);
+        let sci = format!("{:.*e}", precision, n.abs());
+        let (mantissa, exponent) = sci.split_once('e').expect("exponent form has an e");
+        let digits = mantissa.replace('.', "");
+        let k = digits.len() as i32;
+        // Position of the decimal point relative to the digits
+        let point = exponent.parse::<i32>().expect("exponent is an integer") + 1;
+
+        let mut out = String::new();
+        if n < 0.0 {
+            out.push('-');
+        }
+        if k <= point && point <= 21 {
+            out.push_str(&digits);
+            out.push_str(&"0".repeat((point - k) as usize));
+        } else if 0 < point && point <= 21 {
+            let (int, frac) = digits.split_at(point as usize);
+            let _ = write!(out, "{}.{}", int, frac);
+        } else if -6 < point && point <= 0 {
+            out.push_str("0.");
+            out.push_str(&"0".repeat(-point as usize));
+            out.push_str(&digits);
+        } else {
+            let (first, rest) = digits.split_at(1);
+            out.push_str(first);
+            if !rest.is_empty() {
+                let _ = write!(out, ".{}", rest);
+            }
+            let _ = write!(out, "e{}{}", if point > 0 { '+' } else { '-' }, (point - 1).abs());
+        }
+        Ok(out)
     }
 
     /// Canonicalize and return as string
@@ -27,41 +145,262 @@ impl Canonicalizer {
         )
     }
 
-    /// Normalize a JSON value for canonical representation
-    fn normalize_value(value: &Value) -> Result<Value> {
-        match value {
-            Value::Object(map) => {
-                // Sort keys and recursively normalize values
-                let mut sorted = BTreeMap::new();
-                for (k, v) in map.iter() {
-                    sorted.insert(k.clone(), Self::normalize_value(v)?);
+    /// Write the canonical form of `value` to `out` piece by piece and
+    /// return its length
+    ///
+    /// Walks with an explicit stack, so nesting costs heap rather than call
+    /// frames. Under `Nfc`, two keys of one object that only differ in
+    /// normalization are an error, since either value could win.
+    fn emit(value: &Value, form: CanonicalForm, limits: &CanonicalLimits, out: &mut dyn FnMut(&[u8])) -> Result<usize> {
+        enum Frame<'v> {
+            Array(std::slice::Iter<'v, Value>),
+            Object(std::vec::IntoIter<(Cow<'v, str>, &'v Value)>),
+        }
+
+        let mut written = 0;
+        let mut put = |bytes: &[u8]| -> Result<()> {
+            written += bytes.len();
+            if let Some(max) = limits.max_total_bytes.filter(|&max| written > max) {
+                return Err(BmsError::InvalidState(format!("Canonical form is longer than {} bytes", max)));
+            }
+            out(bytes);
+            Ok(())
+        };
+        fn text(s: &str, form: CanonicalForm) -> Cow<'_, str> {
+            if form == CanonicalForm::Nfc && !is_nfc(s) {
+                Cow::Owned(s.nfc().collect())
+            } else {
+                Cow::Borrowed(s)
+            }
+        }
+
+        let mut scratch = Vec::new();
+        // (open container, whether it has written no member yet)
+        let mut stack: Vec<(Frame, bool)> = Vec::new();
+        let mut next = Some(value);
+        loop {
+            if let Some(value) = next.take() {
+                if matches!(value, Value::Array(_) | Value::Object(_)) && stack.len() >= limits.max_depth {
+                    return Err(BmsError::InvalidState(format!(
+                        "Value nests more than {} arrays and objects",
+                        limits.max_depth
+                    )));
+                }
+                scratch.clear();
+                match value {
+                    Value::Array(items) => {
+                        put(b"[")?;
+                        stack.push((Frame::Array(items.iter()), true));
+                    }
+                    Value::Object(map) => {
+                        let mut entries: Vec<(Cow<str>, &Value)> =
+                            map.iter().map(|(k, v)| (text(k, form), v)).collect();
+                        if form == CanonicalForm::Jcs {
+                            entries.sort_by(|(a, _), (b, _)| a.encode_utf16().cmp(b.encode_utf16()));
+                        } else {
+                            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+                        }
+                        if let Some(pair) = entries.windows(2).find(|pair| pair[0].0 == pair[1].0) {
+                            return Err(BmsError::InvalidState(format!(
+                                "Object has two keys that normalize to {:?}",
+                                pair[0].0
+                            )));
+                        }
+                        put(b"{")?;
+                        stack.push((Frame::Object(entries.into_iter()), true));
+                    }
+                    Value::Number(n) if form == CanonicalForm::Jcs => {
+                        let n = n
+                            .as_f64()
+                            .ok_or_else(|| BmsError::InvalidState(format!("Number {} is not a double", n)))?;
+                        put(Self::format_jcs_number(n)?.as_bytes())?;
+                    }
+                    // serde_json escapes strings exactly as JCS does
+                    Value::String(s) => {
+                        serde_json::to_writer(&mut scratch, text(s, form).as_ref())?;
+                        put(&scratch)?;
+                    }
+                    // Other primitive types are already canonical
+                    _ => {
+                        serde_json::to_writer(&mut scratch, value)?;
+                        put(&scratch)?;
+                    }
                 }
-                Ok(Value::Object(sorted.into_iter().collect()))
             }
-            Value::Array(arr) => {
-                // Recursively normalize array elements
-                let normalized: Result<Vec<Value>> = arr
-                    .iter()
-                    .map(|v| Self::normalize_value(v))
-                    .collect();
-                Ok(Value::Array(normalized?))
+
+            let Some((frame, empty)) = stack.last_mut() else {
+                break;
+            };
+            let member = match frame {
+                Frame::Array(items) => items.next().map(|item| (None, item)),
+                Frame::Object(entries) => entries.next().map(|(key, item)| (Some(key), item)),
+            };
+            match member {
+                Some((key, item)) => {
+                    if !std::mem::replace(empty, false) {
+                        put(b",")?;
+                    }
+                    if let Some(key) = key {
+                        scratch.clear();
+                        serde_json::to_writer(&mut scratch, key.as_ref())?;
+                        scratch.push(b':');
+                        put(&scratch)?;
+                    }
+                    next = Some(item);
+                }
+                None => {
+                    put(if matches!(frame, Frame::Array(_)) { b"]" } else { b"}" })?;
+                    stack.pop();
+                }
             }
-            // Primitive types are already canonical
-            _ => Ok(value.clone()),
         }
+        Ok(written)
+    }
+
+    /// Feed the canonical form of `value` to `hasher` piece by piece
+    ///
+    /// Gives the digest of `canonicalize(value)` without building the
+    /// canonical string, which for a large state would take several times
+    /// its size.
+    pub fn hash_canonical(value: &Value, hasher: &mut impl Digest) -> Result<()> {
+        Self::hash_canonical_with(value, &CanonicalLimits::default(), hasher)
+    }
+
+    /// `hash_canonical` under `limits` rather than the default ones
+    pub fn hash_canonical_with(value: &Value, limits: &CanonicalLimits, hasher: &mut impl Digest) -> Result<()> {
+        Self::emit(value, CanonicalForm::Legacy, limits, &mut |bytes| Digest::update(hasher, bytes))?;
+        Ok(())
+    }
+
+    /// SHA3-256 of the canonical form, as `sha3-256:<hex>`
+    ///
+    /// The digest is `DeltaEngine::hash_state`'s; the prefix names the
+    /// algorithm for systems outside BMS.
+    pub fn content_hash(value: &Value) -> Result<Hash> {
+        let mut hasher = Sha3_256::new();
+        Self::hash_canonical(value, &mut hasher)?;
+        Ok(Hash(format!("{}{}", Hash::SHA3_256_PREFIX, hex::encode(hasher.finalize()))))
+    }
+
+    /// Length in bytes of the canonical form, without building it
+    pub fn canonical_len(value: &Value) -> Result<usize> {
+        Self::emit(value, CanonicalForm::Legacy, &CanonicalLimits::default(), &mut |_| {})
     }
 
     /// Parse JSON and canonicalize in one step
+    ///
+    /// Parses with `parse_strict`, so inputs that differ only in a
+    /// duplicated key cannot hash alike.
     pub fn parse_and_canonicalize(json_str: &str) -> Result<Vec<u8>> {
-        let value: Value = serde_json::from_str(json_str)?;
+        let value = Self::parse_strict(json_str)?;
         Self::canonicalize(&value)
     }
+
+    /// Parse JSON, failing with `DuplicateKey` where serde_json would keep
+    /// the last of two equal keys
+    pub fn parse_strict(json_str: &str) -> Result<Value> {
+        let duplicate = RefCell::new(None);
+        let mut deserializer = serde_json::Deserializer::from_str(json_str);
+        let parsed = StrictValue { path: String::new(), duplicate: &duplicate }
+            .deserialize(&mut deserializer)
+            .and_then(|value| deserializer.end().map(|()| value));
+        match (parsed, duplicate.into_inner()) {
+            (_, Some((path, key))) => Err(BmsError::DuplicateKey { path, key }),
+            (parsed, None) => Ok(parsed?),
+        }
+    }
+}
+
+/// Seed for one value of `parse_strict`, at JSON Pointer `path`
+///
+/// A duplicate key is recorded in `duplicate` and aborts the parse with a
+/// placeholder error.
+struct StrictValue<'a> {
+    path: String,
+    duplicate: &'a RefCell<Option<(String, String)>>,
+}
+
+impl<'a> StrictValue<'a> {
+    fn child(&self, segment: &str) -> Self {
+        Self {
+            path: format!("{}/{}", self.path, segment.replace('~', "~0").replace('/', "~1")),
+            duplicate: self.duplicate,
+        }
+    }
+}
+
+impl<'de> DeserializeSeed<'de> for StrictValue<'_> {
+    type Value = Value;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> std::result::Result<Value, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for StrictValue<'_> {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("any JSON value")
+    }
+
+    fn visit_bool<E>(self, v: bool) -> std::result::Result<Value, E> {
+        Ok(Value::Bool(v))
+    }
+
+    fn visit_i64<E>(self, v: i64) -> std::result::Result<Value, E> {
+        Ok(v.into())
+    }
+
+    fn visit_u64<E>(self, v: u64) -> std::result::Result<Value, E> {
+        Ok(v.into())
+    }
+
+    fn visit_f64<E>(self, v: f64) -> std::result::Result<Value, E> {
+        Ok(v.into())
+    }
+
+    fn visit_str<E>(self, v: &str) -> std::result::Result<Value, E> {
+        Ok(Value::String(v.to_string()))
+    }
+
+    fn visit_string<E>(self, v: String) -> std::result::Result<Value, E> {
+        Ok(Value::String(v))
+    }
+
+    fn visit_unit<E>(self) -> std::result::Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> std::result::Result<Value, A::Error> {
+        let mut items = Vec::new();
+        while let Some(item) = seq.next_element_seed(self.child(&items.len().to_string()))? {
+            items.push(item);
+        }
+        Ok(Value::Array(items))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> std::result::Result<Value, A::Error> {
+        let mut object = serde_json::Map::new();
+        while let Some(key) = map.next_key::<String>()? {
+            if object.contains_key(&key) {
+                *self.duplicate.borrow_mut() = Some((self.path.clone(), key));
+                return Err(serde::de::Error::custom("duplicate key"));
+            }
+            let value = map.next_value_seed(self.child(&key))?;
+            object.insert(key, value);
+        }
+        Ok(Value::Object(object))
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
     use serde_json::json;
+    use sha3::Sha3_256;
 
     #[test]
     fn test_canonical_ordering() {
@@ -99,4 +438,221 @@ mod tests {
 
         assert_eq!(canon1, canon2);
     }
+
+    #[test]
+    fn test_jcs_numbers() {
+        // RFC 8785 appendix B
+        let vectors = [
+            (0x0000000000000000, "0"),
+            (0x8000000000000000, "0"),
+            (0x0000000000000001, "5e-324"),
+            (0x8000000000000001, "-5e-324"),
+            (0x7fefffffffffffff, "1.7976931348623157e+308"),
+            (0xffefffffffffffff, "-1.7976931348623157e+308"),
+            (0x4340000000000000, "9007199254740992"),
+            (0xc340000000000000, "-9007199254740992"),
+            (0x4430000000000000, "295147905179352830000"),
+            (0x44b52d02c7e14af5, "9.999999999999997e+22"),
+            (0x44b52d02c7e14af6, "1e+23"),
+            (0x44b52d02c7e14af7, "1.0000000000000001e+23"),
+            (0x444b1ae4d6e2ef4e, "999999999999999700000"),
+            (0x444b1ae4d6e2ef4f, "999999999999999900000"),
+            (0x444b1ae4d6e2ef50, "1e+21"),
+            (0x3eb0c6f7a0b5ed8c, "9.999999999999997e-7"),
+            (0x3eb0c6f7a0b5ed8d, "0.000001"),
+            (0x41b3de4355555553, "333333333.3333332"),
+            (0x41b3de4355555554, "333333333.33333325"),
+            (0x41b3de4355555555, "333333333.3333333"),
+            (0x41b3de4355555556, "333333333.3333334"),
+            (0x41b3de4355555557, "333333333.33333343"),
+            (0xbecbf647612f3696, "-0.0000033333333333333333"),
+            (0x43143ff3c1cb0959, "1424953923781206.2"),
+        ];
+        for (bits, expected) in vectors {
+            let n = f64::from_bits(bits);
+            assert_eq!(Canonicalizer::format_jcs_number(n).unwrap(), expected, "{:#018x}", bits);
+        }
+        for bits in [0x7fffffffffffffff, 0x7ff0000000000000, 0xfff0000000000000] {
+            assert!(Canonicalizer::format_jcs_number(f64::from_bits(bits)).is_err());
+        }
+    }
+
+    #[test]
+    #[allow(clippy::excessive_precision)] // the RFC's input as written
+    fn test_jcs_documents() {
+        // RFC 8785 section 3.2.2
+        // serde_json's default float parsing is not correctly rounded, so
+        // the numbers are Rust literals
+        let string = r#""\u20ac$\u000F\u000aA'\u0042\u0022\u005c\\\"\/""#;
+        let value = json!({
+            "numbers": [333333333.33333329, 1E30, 4.50, 2e-3, 0.000000000000000000000000001],
+            "string": serde_json::from_str::<Value>(string).unwrap(),
+            "literals": [null, true, false]
+        });
+        let jcs = String::from_utf8(Canonicalizer::canonicalize_jcs(&value).unwrap()).unwrap();
+        assert_eq!(
+            jcs,
+            "{\"literals\":[null,true,false],\"numbers\":[333333333.3333333,1e+30,4.5,0.002,1e-27],\
+             \"string\":\"\u{20ac}$\\u000f\\nA'B\\\"\\\\\\\\\\\"/\"}"
+        );
+
+        // Section 3.2.3: UTF-16 order puts the emoji before U+FB33
+        let input = r#"{"\u20ac": 1, "\r": 2, "\ufb33": 3, "1": 4, "\ud83d\ude00": 5, "\u0080": 6, "\u00f6": 7}"#;
+        let value: Value = serde_json::from_str(input).unwrap();
+        let jcs = String::from_utf8(Canonicalizer::canonicalize_jcs(&value).unwrap()).unwrap();
+        assert_eq!(jcs, "{\"\\r\":2,\"1\":4,\"\u{80}\":6,\"\u{f6}\":7,\"\u{20ac}\":1,\"\u{1f600}\":5,\"\u{fb33}\":3}");
+
+        // The legacy form keeps how a number was written; JCS does not
+        let (int, float) = (json!({"n": 1}), json!({"n": 1.0}));
+        assert_ne!(Canonicalizer::canonicalize(&int).unwrap(), Canonicalizer::canonicalize(&float).unwrap());
+        let jcs = |v: &Value| Canonicalizer::canonicalize_as(v, CanonicalForm::Jcs).unwrap();
+        assert_eq!(jcs(&int), jcs(&float));
+        assert_eq!(jcs(&json!(-0.0)), b"0");
+        assert_eq!(
+            Canonicalizer::canonicalize_as(&float, CanonicalForm::default()).unwrap(),
+            Canonicalizer::canonicalize(&float).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_nfc_form() {
+        let (composed, decomposed) = ("caf\u{e9}", "cafe\u{301}");
+        let state = |s: &str| json!({"name": s, "tags": [s], s: 1});
+        let nfc = |v: &Value| Canonicalizer::canonicalize_as(v, CanonicalForm::Nfc).unwrap();
+        assert_eq!(nfc(&state(composed)), nfc(&state(decomposed)));
+        assert_eq!(nfc(&state(composed)), Canonicalizer::canonicalize(&state(composed)).unwrap());
+        assert_ne!(
+            Canonicalizer::canonicalize(&state(composed)).unwrap(),
+            Canonicalizer::canonicalize(&state(decomposed)).unwrap()
+        );
+
+        let both = json!({composed: 1, decomposed: 2});
+        // Legacy keeps both keys; NFC would merge them
+        let legacy = Canonicalizer::canonicalize_str(&both).unwrap();
+        assert!(legacy.contains(composed) && legacy.contains(decomposed));
+        assert!(Canonicalizer::canonicalize_as(&both, CanonicalForm::Nfc).is_err());
+    }
+
+    #[test]
+    fn test_parse_strict_rejects_duplicate_keys() {
+        let duplicate = |json: &str| match Canonicalizer::parse_strict(json) {
+            Err(BmsError::DuplicateKey { path, key }) => (path, key),
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(duplicate(r#"{"a": 1, "a": 2}"#), ("".to_string(), "a".to_string()));
+        assert_eq!(
+            duplicate(r#"{"x/y": [0, {"b": {}, "c": 1, "b": null}]}"#),
+            ("/x~1y/1".to_string(), "b".to_string())
+        );
+        // Escapes are compared decoded
+        assert_eq!(duplicate(r#"{"é": 1, "\u00e9": 2}"#).1, "é");
+        assert!(matches!(
+            Canonicalizer::parse_and_canonicalize(r#"{"a": 1, "a": 2}"#),
+            Err(BmsError::DuplicateKey { .. })
+        ));
+
+        let json = r#"{"a": [1, -2, 3.5, "s", true, null, {}], "b": {"a": 1}, "big": 18446744073709551615}"#;
+        assert_eq!(Canonicalizer::parse_strict(json).unwrap(), serde_json::from_str::<Value>(json).unwrap());
+        for bad in ["{", r#"{"a": 1} x"#, "[1,]", ""] {
+            assert!(matches!(Canonicalizer::parse_strict(bad), Err(BmsError::Serialization(_))), "{}", bad);
+        }
+    }
+
+    fn random_value(rng: &mut ChaCha8Rng, depth: u32) -> Value {
+        let text = |rng: &mut ChaCha8Rng| -> String {
+            let alphabet = ['a', 'Z', '"', '\\', '\n', '\u{1}', '/', 'é', '\u{301}', '€', '\u{1F600}', ' '];
+            (0..rng.gen_range(0..6)).map(|_| alphabet[rng.gen_range(0..alphabet.len())]).collect()
+        };
+        match rng.gen_range(0..if depth == 0 { 6 } else { 8 }) {
+            0 => Value::Null,
+            1 => json!(rng.gen_bool(0.5)),
+            2 => json!(rng.gen::<i64>() >> rng.gen_range(0..64)),
+            3 => json!(rng.gen::<u64>()),
+            4 => json!(rng.gen::<f64>() * 10f64.powi(rng.gen_range(-30..30))),
+            5 => json!(text(rng)),
+            6 => Value::Array((0..rng.gen_range(0..5)).map(|_| random_value(rng, depth - 1)).collect()),
+            _ => Value::Object((0..rng.gen_range(0..5)).map(|_| (text(rng), random_value(rng, depth - 1))).collect()),
+        }
+    }
+
+    #[test]
+    fn test_hash_canonical_matches_canonicalize() {
+        let mut rng = ChaCha8Rng::seed_from_u64(1514);
+        for _ in 0..2000 {
+            let value = random_value(&mut rng, 4);
+            let mut streamed = Sha3_256::new();
+            Canonicalizer::hash_canonical(&value, &mut streamed).unwrap();
+            let whole = Sha3_256::digest(Canonicalizer::canonicalize(&value).unwrap());
+            assert_eq!(streamed.finalize(), whole, "{}", value);
+            // serde_json's map keeps keys sorted, so its compact output is
+            // the legacy form
+            assert_eq!(Canonicalizer::canonicalize(&value).unwrap(), serde_json::to_vec(&value).unwrap());
+        }
+    }
+
+    /// Array `depth` levels deep around `0`
+    fn nested(depth: usize) -> Value {
+        (0..depth).fold(json!(0), |inner, _| Value::Array(vec![inner]))
+    }
+
+    /// Drop a `nested` value one level at a time, as serde_json's own drop
+    /// would recurse
+    fn unnest(mut value: Value) {
+        while let Value::Array(mut items) = value {
+            value = items.pop().unwrap_or(Value::Null);
+        }
+    }
+
+    #[test]
+    fn test_depth_and_size_limits() {
+        let deep = nested(100_000);
+        let too_deep = |result: Result<()>| match result {
+            Err(BmsError::InvalidState(reason)) => assert!(reason.contains("512"), "{}", reason),
+            other => panic!("{:?}", other),
+        };
+        too_deep(Canonicalizer::canonicalize(&deep).map(drop));
+        too_deep(Canonicalizer::canonicalize_jcs(&deep).map(drop));
+        too_deep(Canonicalizer::canonical_len(&deep).map(drop));
+        too_deep(Canonicalizer::hash_canonical(&deep, &mut Sha3_256::new()));
+        // The walk itself needs no stack per level
+        let unbounded = CanonicalLimits { max_depth: usize::MAX, max_total_bytes: None };
+        let canonical = Canonicalizer::canonicalize_with(&deep, CanonicalForm::Nfc, &unbounded).unwrap();
+        assert_eq!(canonical.len(), 200_001);
+        assert_eq!(&canonical[99_998..100_003], b"[[0]]");
+        unnest(deep);
+
+        let limits = CanonicalLimits::default();
+        let value = nested(limits.max_depth);
+        assert_eq!(Canonicalizer::canonical_len(&value).unwrap(), 2 * limits.max_depth + 1);
+        assert!(Canonicalizer::canonicalize(&Value::Array(vec![value])).is_err());
+        assert!(Canonicalizer::canonicalize(&json!({"a": nested(limits.max_depth - 1)})).is_ok());
+        assert!(Canonicalizer::canonicalize(&json!({"a": nested(limits.max_depth)})).is_err());
+
+        let value = json!({"a": "xyz", "b": [1, 2]});
+        let len = Canonicalizer::canonical_len(&value).unwrap();
+        let capped = |max| CanonicalLimits { max_total_bytes: Some(max), ..Default::default() };
+        let canonical = Canonicalizer::canonicalize_with(&value, CanonicalForm::Legacy, &capped(len)).unwrap();
+        assert_eq!(canonical, Canonicalizer::canonicalize(&value).unwrap());
+        match Canonicalizer::hash_canonical_with(&value, &capped(len - 1), &mut Sha3_256::new()) {
+            Err(BmsError::InvalidState(reason)) => assert!(reason.contains("longer than"), "{}", reason),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_content_hash() {
+        let value = json!({"b": null, "a": [1, 2.5, "é"]});
+        let hash = Canonicalizer::content_hash(&value).unwrap();
+        let digest = Sha3_256::digest(r#"{"a":[1,2.5,"é"],"b":null}"#);
+        assert_eq!(hash.0, format!("sha3-256:{}", hex::encode(digest)));
+        assert_eq!(hash.0.parse::<Hash>().unwrap().0, hash.hex());
+        assert_eq!(Canonicalizer::content_hash(&json!({"a": [1, 2.5, "é"], "b": null})).unwrap(), hash);
+    }
+
+    #[test]
+    fn test_canonical_len() {
+        let value = json!({"z": [1, "two", null], "a": {"é": "\u{1F600}", "b": 2.5}, "m": "quote\""});
+        let len = Canonicalizer::canonical_len(&value).unwrap();
+        assert_eq!(len, Canonicalizer::canonicalize(&value).unwrap().len());
+    }
 }
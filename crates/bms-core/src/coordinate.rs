@@ -1,10 +1,42 @@
 use crate::canonical::Canonicalizer;
 use crate::error::{BmsError, Result};
-use crate::types::CoordId;
-use crate::COORD_ID_BYTES;
+use crate::merkle::HashAlgorithm;
+use crate::types::{CoordId, CoordIdKind};
+use crate::{COORD_ID_BYTES, COORD_ID_CHARS, DEFAULT_NAMESPACE, MAX_NAMED_ID_LEN, MAX_NAMESPACE_LEN};
 use chrono::{DateTime, Utc};
 use serde_json::Value;
 use sha3::{Digest, Sha3_256};
+use std::collections::{HashMap, HashSet};
+
+/// Words of generated aliases, one per byte value
+const ALIAS_WORDS: [&str; 256] = [
+    "acorn", "alpine", "amber", "anchor", "apple", "arrow", "aspen", "atlas", "autumn", "badge",
+    "badger", "bamboo", "banjo", "basil", "beacon", "beetle", "berry", "birch", "bison", "blaze",
+    "blossom", "bonnet", "bramble", "breeze", "brick", "bridge", "brook", "bubble", "cabin",
+    "cactus", "camel", "candle", "canoe", "canyon", "carbon", "cargo", "cascade", "cedar", "cello",
+    "chalk", "cherry", "chestnut", "cider", "cinder", "citrus", "clover", "cobalt", "cobble",
+    "comet", "condor", "copper", "coral", "cosmos", "cotton", "crane", "cricket", "crystal",
+    "cypress", "daisy", "dawn", "delta", "denim", "desert", "dingo", "dolphin", "drift", "dune",
+    "eagle", "echo", "elm", "ember", "emerald", "fable", "falcon", "feather", "fennel", "fern",
+    "fiddle", "fig", "finch", "fjord", "flare", "flint", "forest", "fossil", "fox", "frost",
+    "galaxy", "garden", "garnet", "gazelle", "geyser", "ginger", "glacier", "glade", "glow",
+    "granite", "gravel", "gull", "harbor", "harp", "hawk", "hazel", "heath", "heron", "hickory",
+    "hollow", "honey", "horizon", "igloo", "indigo", "iris", "island", "ivory", "jade", "jasmine",
+    "jasper", "jetty", "juniper", "kayak", "kelp", "kestrel", "kettle", "kiwi", "koala", "lagoon",
+    "lantern", "larch", "lark", "lava", "ledge", "lemon", "lichen", "lilac", "lily", "linen",
+    "lotus", "lunar", "lynx", "magnet", "mango", "maple", "marble", "marsh", "meadow", "melon",
+    "mesa", "meteor", "mint", "mist", "mosaic", "moss", "nebula", "nectar", "nettle", "nickel",
+    "nimbus", "north", "nova", "nutmeg", "oak", "oasis", "ocean", "olive", "onyx", "opal", "orbit",
+    "orchid", "oriole", "osprey", "otter", "oyster", "paddle", "panda", "pansy", "papaya", "parrot",
+    "pearl", "pebble", "pepper", "pilot", "pine", "pixel", "planet", "plover", "plum", "polar",
+    "pond", "poppy", "prairie", "prism", "puffin", "quail", "quartz", "quill", "rabbit", "radish",
+    "rain", "raven", "reef", "ridge", "ripple", "river", "robin", "rocket", "rose", "ruby", "sable",
+    "saddle", "saffron", "sage", "salmon", "sapphire", "satin", "scarlet", "seal", "sequoia",
+    "shadow", "shell", "shore", "sierra", "silver", "slate", "snow", "solar", "spark", "sparrow",
+    "spring", "spruce", "squash", "starling", "stone", "storm", "summit", "sunset", "swan", "tango",
+    "thistle", "thunder", "tiger", "timber", "topaz", "tulip", "tundra", "turtle", "valley",
+    "velvet", "violet", "walnut", "willow", "window", "winter", "wren", "yarrow", "zephyr", "zinc",
+];
 
 /// Coordinate generator for telic addressing
 ///
@@ -18,21 +50,74 @@ impl CoordinateGenerator {
     /// Algorithm:
     /// 1. Canonicalize state to deterministic bytes
     /// 2. Concatenate with ISO-8601 UTC timestamp
-    /// 3. Hash with SHA3-256
+    /// 3. Hash with `algo` (SHA3-256 by default)
     /// 4. Take first 16 bytes (128-bit)
     /// 5. Encode as base32 (no padding)
-    pub fn generate(state: &Value, timestamp: &DateTime<Utc>) -> Result<CoordId> {
-        let canonical_state = Canonicalizer::canonicalize(state)?;
-        let timestamp_str = timestamp.to_rfc3339();
-        
-        // Concatenate: canonical_state + "|" + timestamp
-        let mut input = canonical_state;
-        input.push(b'|');
-        input.extend_from_slice(timestamp_str.as_bytes());
+    pub fn generate(state: &Value, timestamp: &DateTime<Utc>, algo: HashAlgorithm) -> Result<CoordId> {
+        match algo {
+            HashAlgorithm::Sha3_256 => Self::derive::<Sha3_256>(None, state, timestamp, None),
+            HashAlgorithm::Blake3 => Self::derive::<blake3::Hasher>(None, state, timestamp, None),
+        }
+    }
 
-        // Hash with SHA3-256
-        let mut hasher = Sha3_256::new();
-        hasher.update(&input);
+    /// Generate a coordinate ID that is distinct per namespace
+    ///
+    /// The namespace is hashed ahead of the state, so two agents storing the
+    /// same state at the same moment get different IDs. The default
+    /// namespace adds nothing, which keeps it equal to `generate`.
+    pub fn generate_in_namespace(
+        namespace: &str,
+        state: &Value,
+        timestamp: &DateTime<Utc>,
+        algo: HashAlgorithm,
+    ) -> Result<CoordId> {
+        Self::validate_namespace(namespace)?;
+        let namespace = Some(namespace).filter(|ns| *ns != DEFAULT_NAMESPACE);
+        match algo {
+            HashAlgorithm::Sha3_256 => Self::derive::<Sha3_256>(namespace, state, timestamp, None),
+            HashAlgorithm::Blake3 => Self::derive::<blake3::Hasher>(namespace, state, timestamp, None),
+        }
+    }
+
+    /// `generate_with_nonce` in a namespace (`None` for the default one) and
+    /// with `algo`, for retrying any generated ID after a collision
+    pub fn generate_nonced(
+        namespace: Option<&str>,
+        state: &Value,
+        timestamp: &DateTime<Utc>,
+        algo: HashAlgorithm,
+        nonce: u32,
+    ) -> Result<CoordId> {
+        if let Some(namespace) = namespace {
+            Self::validate_namespace(namespace)?;
+        }
+        let namespace = namespace.filter(|ns| *ns != DEFAULT_NAMESPACE);
+        match algo {
+            HashAlgorithm::Sha3_256 => Self::derive::<Sha3_256>(namespace, state, timestamp, Some(nonce)),
+            HashAlgorithm::Blake3 => Self::derive::<blake3::Hasher>(namespace, state, timestamp, Some(nonce)),
+        }
+    }
+
+    /// Hash `namespace|` (if any), the canonical state, `|`, the timestamp,
+    /// and `|nonce` (if any), and encode the first 16 bytes
+    fn derive<D: Digest>(
+        namespace: Option<&str>,
+        state: &Value,
+        timestamp: &DateTime<Utc>,
+        nonce: Option<u32>,
+    ) -> Result<CoordId> {
+        let mut hasher = D::new();
+        if let Some(namespace) = namespace {
+            hasher.update(namespace.as_bytes());
+            hasher.update(b"|");
+        }
+        Canonicalizer::hash_canonical(state, &mut hasher)?;
+        hasher.update(b"|");
+        hasher.update(timestamp.to_rfc3339().as_bytes());
+        if let Some(nonce) = nonce {
+            hasher.update(b"|");
+            hasher.update(nonce.to_le_bytes());
+        }
         let hash = hasher.finalize();
 
         // Take first 16 bytes (128-bit)
@@ -44,9 +129,79 @@ impl CoordinateGenerator {
         Ok(CoordId(coord_id))
     }
 
+    /// Check a namespace: `/`-separated segments of ASCII letters, digits,
+    /// `-`, `_`, and `.`, such as `agents/planner`
+    pub fn validate_namespace(namespace: &str) -> Result<()> {
+        if namespace.len() > MAX_NAMESPACE_LEN {
+            return Err(BmsError::InvalidCoordinate(format!(
+                "Namespace is {} bytes, more than {}",
+                namespace.len(),
+                MAX_NAMESPACE_LEN
+            )));
+        }
+        let valid_segment = |segment: &str| {
+            !segment.is_empty() && segment.bytes().all(|b| b.is_ascii_alphanumeric() || b"-_.".contains(&b))
+        };
+        if !namespace.split('/').all(valid_segment) {
+            return Err(BmsError::InvalidCoordinate(format!(
+                "Invalid namespace {:?}: use /-separated segments of letters, digits, -, _, and .",
+                namespace
+            )));
+        }
+        Ok(())
+    }
+
+    /// Check a coordinate hint and tell which kind of ID it makes
+    ///
+    /// A canonical base32 ID is `Generated`, as if `generate` had produced
+    /// it. Anything else must pass `validate_named`.
+    pub fn validate_hint(hint: &str) -> Result<CoordIdKind> {
+        if Self::validate(hint).is_ok() {
+            return Ok(CoordIdKind::Generated);
+        }
+        Self::validate_named(hint)?;
+        Ok(CoordIdKind::Named)
+    }
+
+    /// Validate a user-defined coordinate ID
+    ///
+    /// Named IDs are 1 to `MAX_NAMED_ID_LEN` ASCII letters, digits, `-`,
+    /// `_`, and `.`, so they fit in a URL path segment unescaped. 26 base32
+    /// characters are left to generated IDs: ones with non-zero padding bits
+    /// would alias a generated ID, and `bms fsck` lists them. So are names
+    /// of 26 letters and digits besides hyphens, which `CoordId::parse`
+    /// reads as a generated ID in another case or grouping.
+    pub fn validate_named(coord_id: &str) -> Result<()> {
+        let base32 = |b: u8| b.is_ascii_uppercase() || (b'2'..=b'7').contains(&b);
+        if coord_id.len() == COORD_ID_CHARS && coord_id.bytes().all(base32) {
+            return Self::validate(coord_id);
+        }
+        let compact: Vec<u8> = coord_id.bytes().filter(|b| *b != b'-').collect();
+        if compact.len() == COORD_ID_CHARS && compact.iter().all(u8::is_ascii_alphanumeric) {
+            return Err(BmsError::InvalidCoordinate(format!(
+                "Named coordinate ID {:?} reads as a generated ID: add a _ or a .",
+                coord_id
+            )));
+        }
+        if coord_id.is_empty() || coord_id.len() > MAX_NAMED_ID_LEN {
+            return Err(BmsError::InvalidCoordinate(format!(
+                "Named coordinate ID is {} bytes, expected 1 to {}",
+                coord_id.len(),
+                MAX_NAMED_ID_LEN
+            )));
+        }
+        if !coord_id.bytes().all(|b| b.is_ascii_alphanumeric() || b"-_.".contains(&b)) {
+            return Err(BmsError::InvalidCoordinate(format!(
+                "Invalid named coordinate ID {:?}: use letters, digits, -, _, and .",
+                coord_id
+            )));
+        }
+        Ok(())
+    }
+
     /// Generate with current UTC timestamp
     pub fn generate_now(state: &Value) -> Result<CoordId> {
-        Self::generate(state, &Utc::now())
+        Self::generate(state, &Utc::now(), HashAlgorithm::Sha3_256)
     }
 
     /// Validate coordinate ID format
@@ -66,32 +221,82 @@ impl CoordinateGenerator {
             ));
         }
 
+        // The last character carries 2 padding bits that must be zero,
+        // otherwise several strings would decode to the same 16 bytes.
+        // IDs stored before this check may fail here; `bms fsck` lists them.
+        CoordId(coord_id.to_string()).to_bytes()?;
+
         Ok(())
     }
 
+    /// ID of the coordinate branched from `parent` under `label`
+    ///
+    /// SHA3-256 of the parent's 16 bytes (its ASCII for a named parent), a
+    /// zero byte, and the label, cut to 128 bits. The same parent and label
+    /// always give the same child, so a fork can be found again by name.
+    pub fn derive_child(parent: &CoordId, label: &str) -> CoordId {
+        let mut hasher = Sha3_256::new();
+        match parent.to_bytes() {
+            Ok(bytes) => hasher.update(bytes),
+            Err(_) => hasher.update(parent.0.as_bytes()),
+        }
+        hasher.update([0]);
+        hasher.update(label.as_bytes());
+        let hash = hasher.finalize();
+        let mut seed = [0u8; COORD_ID_BYTES];
+        seed.copy_from_slice(&hash[..COORD_ID_BYTES]);
+        CoordId::from_bytes(seed)
+    }
+
+    /// Pronounceable `word-word-word` alias of a coordinate, from the first
+    /// three bytes of its ID
+    ///
+    /// IDs that are not base32 are hashed for their bytes. Three bytes give
+    /// 16.7 million aliases, so two coordinates can draw the same one; the
+    /// repository then stores the later one with a numeric suffix.
+    pub fn generate_alias(coord_id: &CoordId) -> String {
+        let bytes = match coord_id.to_bytes() {
+            Ok(bytes) => [bytes[0], bytes[1], bytes[2]],
+            Err(_) => {
+                let hash = Sha3_256::digest(coord_id.0.as_bytes());
+                [hash[0], hash[1], hash[2]]
+            }
+        };
+        bytes.map(|b| ALIAS_WORDS[b as usize]).join("-")
+    }
+
     /// Generate with explicit nonce for collision resolution
     pub fn generate_with_nonce(
         state: &Value,
         timestamp: &DateTime<Utc>,
         nonce: u32,
     ) -> Result<CoordId> {
-        let canonical_state = Canonicalizer::canonicalize(state)?;
-        let timestamp_str = timestamp.to_rfc3339();
-        
-        let mut input = canonical_state;
-        input.push(b'|');
-        input.extend_from_slice(timestamp_str.as_bytes());
-        input.push(b'|');
-        input.extend_from_slice(&nonce.to_le_bytes());
-
-        let mut hasher = Sha3_256::new();
-        hasher.update(&input);
-        let hash = hasher.finalize();
-
-        let seed = &hash[..COORD_ID_BYTES];
-        let coord_id = base32::encode(base32::Alphabet::Rfc4648 { padding: false }, seed);
+        Self::derive::<Sha3_256>(None, state, timestamp, Some(nonce))
+    }
 
-        Ok(CoordId(coord_id))
+    /// Generate IDs for many states at one timestamp, for bulk ingest
+    ///
+    /// IDs are in batch order. An ID already given to an earlier state (an
+    /// equal state gets the same one) is generated again with the next nonce
+    /// for that ID, 1, 2, ..., until it is unique in the batch, so the same
+    /// batch always maps to the same IDs.
+    pub fn generate_batch(states: &[Value], timestamp: &DateTime<Utc>) -> Result<Vec<CoordId>> {
+        let mut ids = Vec::with_capacity(states.len());
+        let mut taken = HashSet::with_capacity(states.len());
+        let mut nonces: HashMap<CoordId, u32> = HashMap::new();
+        for state in states {
+            let mut id = Self::generate(state, timestamp, HashAlgorithm::Sha3_256)?;
+            if taken.contains(&id) {
+                let nonce = nonces.entry(id.clone()).or_insert(0);
+                while taken.contains(&id) {
+                    *nonce += 1;
+                    id = Self::generate_with_nonce(state, timestamp, *nonce)?;
+                }
+            }
+            taken.insert(id.clone());
+            ids.push(id);
+        }
+        Ok(ids)
     }
 }
 
@@ -106,13 +311,19 @@ mod tests {
         let state = json!({"key": "value", "number": 42});
         let timestamp = Utc.with_ymd_and_hms(2025, 10, 28, 12, 0, 0).unwrap();
 
-        let coord = CoordinateGenerator::generate(&state, &timestamp).unwrap();
+        let coord = CoordinateGenerator::generate(&state, &timestamp, HashAlgorithm::Sha3_256).unwrap();
         
         // Should be 26 characters (128 bits in base32)
         assert_eq!(coord.0.len(), 26);
         
         // Should be valid base32
         assert!(CoordinateGenerator::validate(&coord.0).is_ok());
+
+        // SHA3-256 of the canonical state, "|", and the timestamp, computed
+        // independently; a change here orphans every stored coordinate
+        assert_eq!(coord.0, "UOTJRLJSBHPKFHZFWQ4HRMSSQE");
+        let nonced = CoordinateGenerator::generate_with_nonce(&state, &timestamp, 7).unwrap();
+        assert_eq!(nonced.0, "OIUKQ7FS6TENMM6QUXWEZ4W5HA");
     }
 
     #[test]
@@ -120,8 +331,8 @@ mod tests {
         let state = json!({"a": 1, "b": 2});
         let timestamp = Utc.with_ymd_and_hms(2025, 10, 28, 12, 0, 0).unwrap();
 
-        let coord1 = CoordinateGenerator::generate(&state, &timestamp).unwrap();
-        let coord2 = CoordinateGenerator::generate(&state, &timestamp).unwrap();
+        let coord1 = CoordinateGenerator::generate(&state, &timestamp, HashAlgorithm::Sha3_256).unwrap();
+        let coord2 = CoordinateGenerator::generate(&state, &timestamp, HashAlgorithm::Sha3_256).unwrap();
 
         assert_eq!(coord1, coord2);
     }
@@ -132,8 +343,8 @@ mod tests {
         let state2 = json!({"key": "value2"});
         let timestamp = Utc.with_ymd_and_hms(2025, 10, 28, 12, 0, 0).unwrap();
 
-        let coord1 = CoordinateGenerator::generate(&state1, &timestamp).unwrap();
-        let coord2 = CoordinateGenerator::generate(&state2, &timestamp).unwrap();
+        let coord1 = CoordinateGenerator::generate(&state1, &timestamp, HashAlgorithm::Sha3_256).unwrap();
+        let coord2 = CoordinateGenerator::generate(&state2, &timestamp, HashAlgorithm::Sha3_256).unwrap();
 
         assert_ne!(coord1, coord2);
     }
@@ -143,7 +354,7 @@ mod tests {
         let state = json!({"key": "value"});
         let timestamp = Utc.with_ymd_and_hms(2025, 10, 28, 12, 0, 0).unwrap();
 
-        let coord0 = CoordinateGenerator::generate(&state, &timestamp).unwrap();
+        let coord0 = CoordinateGenerator::generate(&state, &timestamp, HashAlgorithm::Sha3_256).unwrap();
         let coord1 = CoordinateGenerator::generate_with_nonce(&state, &timestamp, 1).unwrap();
         let coord2 = CoordinateGenerator::generate_with_nonce(&state, &timestamp, 2).unwrap();
 
@@ -151,6 +362,13 @@ mod tests {
         assert_ne!(coord0, coord1);
         assert_ne!(coord1, coord2);
         assert_ne!(coord0, coord2);
+
+        // The general form matches, and keeps namespaces and algorithms apart
+        let nonced = |ns, algo| CoordinateGenerator::generate_nonced(ns, &state, &timestamp, algo, 1).unwrap();
+        assert_eq!(nonced(None, HashAlgorithm::Sha3_256), coord1);
+        assert_eq!(nonced(Some(DEFAULT_NAMESPACE), HashAlgorithm::Sha3_256), coord1);
+        assert_ne!(nonced(Some("agents"), HashAlgorithm::Sha3_256), coord1);
+        assert_ne!(nonced(None, HashAlgorithm::Blake3), coord1);
     }
 
     #[test]
@@ -159,9 +377,132 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_validate_rejects_nonzero_padding_bits() {
+        let state = json!({"key": "value"});
+        let timestamp = Utc.with_ymd_and_hms(2025, 10, 28, 12, 0, 0).unwrap();
+        let coord = CoordinateGenerator::generate(&state, &timestamp, HashAlgorithm::Sha3_256).unwrap();
+
+        let mut aliased = coord.0[..25].to_string();
+        let last = coord.0.as_bytes()[25];
+        // Flip to the neighbouring character, which differs only in padding bits
+        aliased.push(if last == b'Z' { 'Y' } else { (last + 1) as char });
+
+        assert!(CoordinateGenerator::validate(&aliased).is_err());
+    }
+
     #[test]
     fn test_validate_invalid_chars() {
         let result = CoordinateGenerator::validate("ABCDEFGH12345678901234!!!!");
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_validate_hint() {
+        let timestamp = Utc.with_ymd_and_hms(2025, 10, 28, 12, 0, 0).unwrap();
+        let coord =
+            CoordinateGenerator::generate(&json!({"key": "value"}), &timestamp, HashAlgorithm::Sha3_256).unwrap();
+        assert_eq!(CoordinateGenerator::validate_hint(coord.as_str()).unwrap(), CoordIdKind::Generated);
+        assert_eq!(CoordIdKind::of(&coord), CoordIdKind::Generated);
+
+        for named in ["notes", "agent-7.scratch_pad", "TOOSHORT"] {
+            assert_eq!(CoordinateGenerator::validate_hint(named).unwrap(), CoordIdKind::Named);
+            assert_eq!(CoordIdKind::of(&CoordId(named.to_string())), CoordIdKind::Named);
+        }
+        let too_long = "n".repeat(MAX_NAMED_ID_LEN + 1);
+        // 'Z' has a padding bit set, so this aliases some generated ID
+        let aliased = format!("{}Z", &coord.0[..25]);
+        let lowercase = coord.0.to_lowercase();
+        let invalid = ["", "two words", "a/b", "ABCDEFGH12345678901234!!!!", &too_long, &aliased, &lowercase];
+        for invalid in invalid {
+            let err = CoordinateGenerator::validate_hint(invalid).unwrap_err();
+            assert!(matches!(err, BmsError::InvalidCoordinate(_)), "{:?}", invalid);
+        }
+        assert_eq!("named".parse::<CoordIdKind>().unwrap(), CoordIdKind::Named);
+        assert_eq!(CoordIdKind::Generated.to_string(), "generated");
+    }
+
+    #[test]
+    fn test_generate_alias() {
+        let coord = CoordId::from_bytes([0, 1, 255, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9]);
+        let alias = CoordinateGenerator::generate_alias(&coord);
+        assert_eq!(alias, format!("{}-{}-{}", ALIAS_WORDS[0], ALIAS_WORDS[1], ALIAS_WORDS[255]));
+        assert_eq!(CoordinateGenerator::generate_alias(&coord), alias);
+
+        let named = CoordinateGenerator::generate_alias(&CoordId("notes".to_string()));
+        assert_eq!(named.split('-').count(), 3);
+        assert_ne!(named, CoordinateGenerator::generate_alias(&CoordId("Notes".to_string())));
+
+        // Words neither repeat nor contain the separator
+        let mut words = ALIAS_WORDS.to_vec();
+        words.sort_unstable();
+        words.dedup();
+        assert_eq!(words.len(), 256);
+        assert!(words.iter().all(|w| !w.is_empty() && w.bytes().all(|b| b.is_ascii_lowercase())));
+    }
+
+    #[test]
+    fn test_generate_batch() {
+        let timestamp = Utc.with_ymd_and_hms(2025, 10, 28, 12, 0, 0).unwrap();
+        let states = [json!({"n": 1}), json!({"n": 2}), json!({"n": 1}), json!({"n": 1})];
+        let ids = CoordinateGenerator::generate_batch(&states, &timestamp).unwrap();
+
+        assert_eq!(ids[0], CoordinateGenerator::generate(&states[0], &timestamp, HashAlgorithm::Sha3_256).unwrap());
+        assert_eq!(ids[1], CoordinateGenerator::generate(&states[1], &timestamp, HashAlgorithm::Sha3_256).unwrap());
+        assert_eq!(ids[2], CoordinateGenerator::generate_with_nonce(&states[2], &timestamp, 1).unwrap());
+        assert_eq!(ids[3], CoordinateGenerator::generate_with_nonce(&states[3], &timestamp, 2).unwrap());
+        assert_eq!(ids, CoordinateGenerator::generate_batch(&states, &timestamp).unwrap());
+        assert!(CoordinateGenerator::generate_batch(&[], &timestamp).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_derive_child() {
+        let timestamp = Utc.with_ymd_and_hms(2025, 10, 28, 12, 0, 0).unwrap();
+        let parent =
+            CoordinateGenerator::generate(&json!({"key": "value"}), &timestamp, HashAlgorithm::Sha3_256).unwrap();
+        let child = CoordinateGenerator::derive_child(&parent, "planner");
+
+        CoordinateGenerator::validate(child.as_str()).unwrap();
+        assert_eq!(child, CoordinateGenerator::derive_child(&parent, "planner"));
+        assert_ne!(child, CoordinateGenerator::derive_child(&parent, "critic"));
+        assert_ne!(child, CoordinateGenerator::derive_child(&child, "planner"));
+        let named = CoordinateGenerator::derive_child(&CoordId("session".to_string()), "planner");
+        CoordinateGenerator::validate(named.as_str()).unwrap();
+        assert_ne!(named, child);
+    }
+
+    #[test]
+    fn test_generate_in_namespace() {
+        let state = json!({"key": "value"});
+        let timestamp = Utc.with_ymd_and_hms(2025, 10, 28, 12, 0, 0).unwrap();
+        let plain = CoordinateGenerator::generate(&state, &timestamp, HashAlgorithm::Sha3_256).unwrap();
+        let algo = HashAlgorithm::default();
+        let in_ns = |ns: &str| CoordinateGenerator::generate_in_namespace(ns, &state, &timestamp, algo);
+
+        assert_eq!(in_ns(DEFAULT_NAMESPACE).unwrap(), plain);
+        let planner = in_ns("agents/planner").unwrap();
+        assert_ne!(planner, plain);
+        assert_ne!(planner, in_ns("agents/critic").unwrap());
+        assert_eq!(planner, in_ns("agents/planner").unwrap());
+        CoordinateGenerator::validate(planner.as_str()).unwrap();
+
+        for bad in ["", "/agents", "agents/", "a//b", "agents planner", "caf\u{e9}", &"a".repeat(129)] {
+            assert!(matches!(in_ns(bad), Err(BmsError::InvalidCoordinate(_))), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn test_generate_with_blake3() {
+        let state = json!({"key": "value"});
+        let timestamp = Utc.with_ymd_and_hms(2025, 10, 28, 12, 0, 0).unwrap();
+        let sha3 = CoordinateGenerator::generate(&state, &timestamp, HashAlgorithm::Sha3_256).unwrap();
+        assert_eq!(sha3, CoordinateGenerator::generate(&state, &timestamp, HashAlgorithm::default()).unwrap());
+
+        let blake3 = CoordinateGenerator::generate(&state, &timestamp, HashAlgorithm::Blake3).unwrap();
+        assert_ne!(blake3, sha3);
+        CoordinateGenerator::validate(blake3.as_str()).unwrap();
+        let in_default =
+            CoordinateGenerator::generate_in_namespace(DEFAULT_NAMESPACE, &state, &timestamp, HashAlgorithm::Blake3);
+        assert_eq!(in_default.unwrap(), blake3);
+    }
 }
@@ -1,105 +1,2221 @@
-use crate::canonical::Canonicalizer;
+use crate::canonical::{CanonicalForm, Canonicalizer};
 use crate::error::{BmsError, Result};
-use crate::types::{DeltaId, Hash};
-use serde_json::Value;
+use crate::policy::OpKind;
+use crate::types::{Delta, DeltaId, DeltaSignature, Hash};
+use crate::watch::{shifting, touched_paths};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 use sha3::{Digest, Sha3_256};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// Coordinate metadata key holding per-coordinate `DiffOptions`
+pub const DIFF_METADATA_KEY: &str = "diff";
+
+/// Coordinate metadata key holding `DiffOptions::array_keys`
+pub const ARRAY_KEYS_METADATA_KEY: &str = "array_keys";
+
+/// Patch size relative to the new state above which the state is stored
+/// whole, unless `DiffOptions::full_replace_ratio` says otherwise
+pub const DEFAULT_FULL_REPLACE_RATIO: f64 = 0.9;
+
+/// Canonical size below which a state is never stored whole: the bytes saved
+/// are not worth losing which paths the delta touched
+pub const FULL_REPLACE_MIN_STATE_BYTES: usize = 256;
+
+/// Delta tag set on deltas stored as a single root replace
+pub const FULL_REPLACE_TAG: &str = "full_replace";
+
+/// Delta tag holding the computed patch's canonical size relative to the
+/// new state's, whether or not the patch was kept
+pub const PATCH_RATIO_TAG: &str = "patch_ratio";
+
+/// Delta tag holding how many deltas a compaction squashed into this one
+pub const SQUASHED_TAG: &str = "squashed";
+
+/// Pointer tested by the guard op of `DeltaEngine::compute_delta_with_guard`
+///
+/// It names no member of the state; `apply_guarded` checks it against the
+/// current head chain hash instead.
+pub const HEAD_HASH_POINTER: &str = "/_bms_head_hash";
+
+/// Upper bounds of the `PatchRatios` buckets; a last bucket takes the rest
+pub const PATCH_RATIO_BOUNDS: [f64; 6] = [0.1, 0.25, 0.5, 0.75, 0.9, 1.0];
+
+/// First byte of stored ops that are framed: uncompressed JSON follows
+///
+/// Unframed ops start with `[` (JSON) or `0x80`–`0x9f` (a CBOR array), so
+/// the flag bytes cannot be mistaken for either.
+pub const OPS_FLAG_JSON: u8 = 0;
+/// First byte of stored ops that are zstd-compressed JSON
+pub const OPS_FLAG_ZSTD_JSON: u8 = 1;
+/// First byte of stored ops that are zstd-compressed CBOR
+pub const OPS_FLAG_ZSTD_CBOR: u8 = 2;
+
+/// Encoded ops shorter than this are stored uncompressed by the
+/// `compressed-ops` feature, where a zstd frame would save little or nothing
+#[cfg(feature = "compressed-ops")]
+const COMPRESS_MIN_BYTES: usize = 256;
+
+/// Largest LCS table (old × new array length) computed before falling back to replace
+const MAX_LCS_CELLS: usize = 1 << 20;
+
+/// How arrays are diffed by `compute_delta_optimized`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "array_strategy", rename_all = "snake_case")]
+pub enum ArrayStrategy {
+    /// Element-level ops from a longest-common-subsequence alignment
+    Lcs,
+    /// Diff elements by position, as `compute_delta` does; an insert or
+    /// remove rewrites every element after it
+    ElementWise,
+    /// Replace any changed array as a whole
+    Replace,
+    /// Use the LCS ops unless they exceed either limit, then replace
+    Auto {
+        /// Maximum ops the LCS patch may use for one array
+        #[serde(default = "default_max_ops_per_array")]
+        max_ops_per_array: usize,
+        /// Maximum LCS patch size relative to the replace op's size
+        #[serde(default = "default_max_bytes_ratio")]
+        max_bytes_ratio: f64,
+    },
+    /// Match elements on their `key` field, as an `array_keys` hint does,
+    /// in every array without a hint; arrays where an element lacks the
+    /// key or repeats one use `Lcs`
+    ById { key: String },
+}
+
+fn default_max_ops_per_array() -> usize {
+    32
+}
+
+fn default_max_bytes_ratio() -> f64 {
+    1.0
+}
+
+impl Default for ArrayStrategy {
+    fn default() -> Self {
+        ArrayStrategy::Auto {
+            max_ops_per_array: default_max_ops_per_array(),
+            max_bytes_ratio: default_max_bytes_ratio(),
+        }
+    }
+}
+
+/// Options for `compute_delta_optimized`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DiffOptions {
+    #[serde(flatten)]
+    pub array_strategy: ArrayStrategy,
+    /// Arrays matched by element instead of by position, keyed by JSON
+    /// Pointer (a `*` segment matches any member or element)
+    ///
+    /// A field name diffs the array as a keyed collection: elements are
+    /// matched on that field, edited in place, removed, or appended at the
+    /// end. `None` diffs it as a set: a reorder is no change, and any other
+    /// change writes the elements in canonical order.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub array_keys: BTreeMap<String, Option<String>>,
+    /// Patch size relative to the new state's canonical size above which
+    /// the state is stored whole, as one root replace (default 0.9)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub full_replace_ratio: Option<f64>,
+}
+
+impl DiffOptions {
+    /// Read options from coordinate metadata
+    ///
+    /// The `array_keys` key, if present, replaces `array_keys` from the
+    /// `diff` key. Returns `None` when the metadata has neither.
+    pub fn from_metadata(metadata: &HashMap<String, Value>) -> Result<Option<Self>> {
+        let options: Option<Self> = metadata
+            .get(DIFF_METADATA_KEY)
+            .map(|v| {
+                serde_json::from_value(v.clone())
+                    .map_err(|e| BmsError::InvalidState(format!("Invalid diff options: {}", e)))
+            })
+            .transpose()?;
+        let array_keys: Option<BTreeMap<String, Option<String>>> = metadata
+            .get(ARRAY_KEYS_METADATA_KEY)
+            .map(|v| {
+                serde_json::from_value(v.clone()).map_err(|e| {
+                    BmsError::InvalidState(format!(
+                        "array_keys must map JSON Pointers to a key field or null: {}",
+                        e
+                    ))
+                })
+            })
+            .transpose()?;
+        if options.is_none() && array_keys.is_none() {
+            return Ok(None);
+        }
+
+        let mut options = options.unwrap_or_default();
+        if let Some(array_keys) = array_keys {
+            options.array_keys = array_keys;
+        }
+        options.full_replace_ratio()?;
+        for (pointer, key) in &options.array_keys {
+            if !pointer.starts_with('/') || key.as_deref() == Some("") {
+                return Err(BmsError::InvalidState(format!(
+                    "Array key hint needs a non-empty JSON Pointer and key field: {:?} => {:?}",
+                    pointer, key
+                )));
+            }
+        }
+        Ok(Some(options))
+    }
+
+    /// `full_replace_ratio`, or the default; fails if it is negative
+    pub fn full_replace_ratio(&self) -> Result<f64> {
+        match self.full_replace_ratio {
+            None => Ok(DEFAULT_FULL_REPLACE_RATIO),
+            Some(ratio) if ratio >= 0.0 => Ok(ratio),
+            Some(ratio) => Err(BmsError::InvalidState(format!(
+                "full_replace_ratio must not be negative, got {}",
+                ratio
+            ))),
+        }
+    }
+}
+
+/// How delta ops are written to storage; rows in either encoding are read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeltaEncoding {
+    /// JSON text
+    Json,
+    /// CBOR, about a third smaller; needs the `cbor-ops` feature
+    Cbor,
+}
+
+impl Default for DeltaEncoding {
+    /// CBOR with the `cbor-ops` feature, JSON otherwise
+    fn default() -> Self {
+        if cfg!(feature = "cbor-ops") {
+            DeltaEncoding::Cbor
+        } else {
+            DeltaEncoding::Json
+        }
+    }
+}
+
+impl std::str::FromStr for DeltaEncoding {
+    type Err = BmsError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "json" => Ok(DeltaEncoding::Json),
+            "cbor" if cfg!(feature = "cbor-ops") => Ok(DeltaEncoding::Cbor),
+            "cbor" => Err(BmsError::InvalidState("The cbor delta encoding needs the cbor-ops feature".to_string())),
+            other => Err(BmsError::InvalidState(format!("Unknown delta encoding: {}", other))),
+        }
+    }
+}
+
+impl std::fmt::Display for DeltaEncoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            DeltaEncoding::Json => "json",
+            DeltaEncoding::Cbor => "cbor",
+        })
+    }
+}
+
+/// What a patch changes, by path, for people reading a history
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct DiffSummary {
+    /// JSON Pointers of added members and inserted elements (`-` for appends)
+    pub added_paths: Vec<String>,
+    pub removed_paths: Vec<String>,
+    pub modified_paths: Vec<String>,
+    /// e.g. `3 fields added, 1 removed, 2 modified`
+    pub text_summary: String,
+}
+
+/// How the ops of two concurrent patches clash at a path
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictKind {
+    /// Both sides wrote the path, or a value containing it
+    BothModified,
+    /// One side removed what the other wrote to
+    OneRemovedOtherModified,
+    /// Both sides added at the same path, or inserted into the same array
+    AddedAtSamePath,
+}
+
+/// A path both of two concurrent patches write
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConflictReport {
+    /// The deeper of the two clashing paths; inserts and removals at array
+    /// positions count as writing the array
+    pub path: jsonptr::Pointer,
+    pub kind: ConflictKind,
+}
+
+/// How `DeltaEngine::merge_with_conflict_resolution` settles values both
+/// sides changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MergeStrategy {
+    /// The second patch's value wins
+    LastWriteWins,
+    /// Fail with `MergeConflict` if `detect_conflicts` reports anything
+    FailOnConflict,
+    /// As `LastWriteWins`, except that an array both sides changed keeps the
+    /// first side's elements, less those the second removed, plus those it added
+    UnionArrays,
+}
+
+/// A path both sides of a `DeltaEngine::merge` changed to different values
+#[derive(Debug, Clone, PartialEq)]
+pub struct MergeConflict {
+    pub path: jsonptr::Pointer,
+    /// Value on each side; `None` if that side removed it
+    pub ours: Option<Value>,
+    pub theirs: Option<Value>,
+}
+
+/// Result of a `DeltaEngine::merge`
+#[derive(Debug, Clone, PartialEq)]
+pub enum MergeOutcome {
+    /// Patch taking the base to the merged state
+    Merged(Vec<json_patch::PatchOperation>),
+    /// Every clash, in the order met walking the merged value
+    Conflicts(Vec<MergeConflict>),
+}
+
+/// How one op fared in a `DeltaEngine::try_apply` dry run
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct OpOutcome {
+    /// Position of the op in the patch
+    pub index: usize,
+    /// Pointer the op failed at (`from` for a missing move or copy source),
+    /// or its target path if it applied
+    pub path: String,
+    /// Why the op failed; `None` if it applied
+    pub error: Option<String>,
+}
+
+impl OpOutcome {
+    pub fn applied(&self) -> bool {
+        self.error.is_none()
+    }
+
+    /// The failure as `BmsError::PatchRejected`, if the op failed
+    pub fn to_error(&self) -> Option<BmsError> {
+        self.error.as_ref().map(|reason| BmsError::PatchRejected {
+            index: self.index,
+            path: self.path.clone(),
+            reason: reason.clone(),
+        })
+    }
+}
+
+/// Which strategy was applied to the changed arrays of one delta
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct DiffStats {
+    pub lcs_arrays: u32,
+    pub replaced_arrays: u32,
+    /// Arrays matched by key field; changed sets count as LCS or replaced
+    pub keyed_arrays: u32,
+    /// Arrays diffed by position under `ArrayStrategy::ElementWise`
+    pub positional_arrays: u32,
+}
+
+impl DiffStats {
+    fn merge(&mut self, other: DiffStats) {
+        self.lcs_arrays += other.lcs_arrays;
+        self.replaced_arrays += other.replaced_arrays;
+        self.keyed_arrays += other.keyed_arrays;
+        self.positional_arrays += other.positional_arrays;
+    }
+}
+
+/// Ops of a patch by kind, and the patch's size
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OpsStats {
+    pub add_count: usize,
+    pub remove_count: usize,
+    pub replace_count: usize,
+    pub test_count: usize,
+    pub move_count: usize,
+    pub copy_count: usize,
+    /// Canonical JSON length of the ops array
+    pub total_bytes: usize,
+}
+
+impl OpsStats {
+    pub fn op_count(&self) -> usize {
+        self.add_count + self.remove_count + self.replace_count + self.test_count + self.move_count + self.copy_count
+    }
+
+    /// Add the counts and bytes of another patch
+    pub fn merge(&mut self, other: &OpsStats) {
+        self.add_count += other.add_count;
+        self.remove_count += other.remove_count;
+        self.replace_count += other.replace_count;
+        self.test_count += other.test_count;
+        self.move_count += other.move_count;
+        self.copy_count += other.copy_count;
+        self.total_bytes += other.total_bytes;
+    }
+}
+
+/// How large computed patches were relative to the states they produce
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PatchRatios {
+    /// Deltas per bucket of `PATCH_RATIO_BOUNDS`, then above the last bound
+    pub counts: [u64; PATCH_RATIO_BOUNDS.len() + 1],
+    /// Deltas whose patch was replaced by the whole state
+    pub full_replaces: u64,
+}
+
+impl PatchRatios {
+    pub fn record(&mut self, ratio: f64, full_replace: bool) {
+        let bucket = PATCH_RATIO_BOUNDS
+            .iter()
+            .position(|bound| ratio < *bound)
+            .unwrap_or(PATCH_RATIO_BOUNDS.len());
+        self.counts[bucket] += 1;
+        self.full_replaces += u64::from(full_replace);
+    }
+
+    pub fn merge(&mut self, other: &PatchRatios) {
+        self.counts.iter_mut().zip(other.counts).for_each(|(a, b)| *a += b);
+        self.full_replaces += other.full_replaces;
+    }
+
+    /// Deltas recorded
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Upper bound of each bucket with its count; `None` for the last
+    pub fn buckets(&self) -> impl Iterator<Item = (Option<f64>, u64)> + '_ {
+        PATCH_RATIO_BOUNDS.iter().map(|b| Some(*b)).chain([None]).zip(self.counts)
+    }
+}
+
+impl Serialize for PatchRatios {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let buckets: Vec<Value> = self
+            .buckets()
+            .map(|(below, count)| json!({"below": below, "count": count}))
+            .collect();
+        json!({"buckets": buckets, "full_replaces": self.full_replaces}).serialize(serializer)
+    }
+}
 
 /// Delta engine for RFC 6902 JSON Patch compression
 pub struct DeltaEngine;
 
-impl DeltaEngine {
-    /// Compute delta from previous state to current state
-    ///
-    /// Returns JSON Patch operations (RFC 6902)
-    pub fn compute_delta(
-        prev_state: &Value,
-        current_state: &Value,
-    ) -> Result<Vec<json_patch::PatchOperation>> {
-        let patch = json_patch::diff(prev_state, current_state);
-        Ok(patch.0)
+impl DeltaEngine {
+    /// Compute delta from previous state to current state
+    ///
+    /// Returns JSON Patch operations (RFC 6902)
+    pub fn compute_delta(
+        prev_state: &Value,
+        current_state: &Value,
+    ) -> Result<Vec<json_patch::PatchOperation>> {
+        let patch = json_patch::diff(prev_state, current_state);
+        Ok(patch.0)
+    }
+
+    /// Compute delta led by a `test` op on `HEAD_HASH_POINTER` holding
+    /// `prev_hash`, the chain hash of the head `prev_state` was read from
+    ///
+    /// Applied with `apply_guarded`, the patch fails if the head moved since,
+    /// instead of overwriting the other writer's change.
+    pub fn compute_delta_with_guard(
+        prev_state: &Value,
+        current_state: &Value,
+        prev_hash: &Hash,
+    ) -> Result<Vec<json_patch::PatchOperation>> {
+        let mut ops: Vec<json_patch::PatchOperation> =
+            serde_json::from_value(json!([{"op": "test", "path": HEAD_HASH_POINTER, "value": prev_hash.0}]))?;
+        ops.extend(Self::compute_delta(prev_state, current_state)?);
+        Ok(ops)
+    }
+
+    /// Compute delta led by `test` ops asserting the previous value of every
+    /// path it modifies
+    ///
+    /// Replaced and removed paths, existing elements an add inserts before,
+    /// and the sources of moves and copies are each tested once, in the
+    /// order the ops first touch them. Adds of new members have no previous
+    /// value to test. Applied to a state that differs from `prev_state` at
+    /// any of those paths, the patch fails before it writes anything.
+    pub fn compute_delta_with_tests(
+        prev_state: &Value,
+        current_state: &Value,
+    ) -> Result<Vec<json_patch::PatchOperation>> {
+        use json_patch::PatchOperation;
+
+        let ops = Self::compute_delta(prev_state, current_state)?;
+        let mut tested = HashSet::new();
+        let mut tests = Vec::new();
+        for op in &ops {
+            let from = match op {
+                PatchOperation::Move(mv) => Some(&mv.from),
+                PatchOperation::Copy(copy) => Some(&copy.from),
+                _ => None,
+            };
+            for pointer in from.into_iter().chain([op.path()]) {
+                let path = pointer.to_string();
+                if let Some(value) = prev_state.pointer(&path) {
+                    if tested.insert(path.clone()) {
+                        tests.push(json!({"op": "test", "path": path, "value": value}));
+                    }
+                }
+            }
+        }
+        let mut guarded: Vec<PatchOperation> = serde_json::from_value(Value::Array(tests))?;
+        guarded.extend(ops);
+        Ok(guarded)
+    }
+
+    /// Check the `test` ops of a patch against `state` without applying it
+    ///
+    /// The first failing test is returned as `PatchRejected` with its index
+    /// in `ops`. Other ops are skipped, as are leading guard ops on
+    /// `HEAD_HASH_POINTER`, which only `apply_guarded` can check.
+    pub fn verify_patch_preconditions(state: &Value, ops: &[json_patch::PatchOperation]) -> Result<()> {
+        let guards = ops
+            .iter()
+            .take_while(|op| matches!(op, json_patch::PatchOperation::Test(t) if t.path == HEAD_HASH_POINTER))
+            .count();
+        for (index, op) in ops.iter().enumerate().skip(guards) {
+            let json_patch::PatchOperation::Test(test) = op else {
+                continue;
+            };
+            let reason = match state.pointer(test.path.as_str()) {
+                Some(value) if *value == test.value => continue,
+                Some(_) => json_patch::PatchErrorKind::TestFailed,
+                None => json_patch::PatchErrorKind::InvalidPointer,
+            };
+            return Err(BmsError::PatchRejected {
+                index,
+                path: test.path.to_string(),
+                reason: reason.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Apply a patch whose leading guard ops must name `head_hash`
+    ///
+    /// Guard ops (`test` on `HEAD_HASH_POINTER`) are checked against
+    /// `head_hash`, not the state, and the rest applied as `apply_delta`
+    /// does. A guard for another head fails with `PreconditionFailed` and
+    /// leaves `state` as it was. Ops without a guard apply unconditionally.
+    pub fn apply_guarded(
+        state: &mut Value,
+        ops: &[json_patch::PatchOperation],
+        head_hash: &Hash,
+    ) -> Result<()> {
+        let guards = ops
+            .iter()
+            .take_while(|op| matches!(op, json_patch::PatchOperation::Test(t) if t.path == HEAD_HASH_POINTER))
+            .count();
+        for op in &ops[..guards] {
+            if let json_patch::PatchOperation::Test(test) = op {
+                if test.value.as_str() != Some(head_hash.0.as_str()) {
+                    return Err(BmsError::PreconditionFailed {
+                        expected: test.value.as_str().map_or_else(|| test.value.to_string(), str::to_string),
+                        actual: head_hash.0.clone(),
+                        head_chain_hash: Some(head_hash.0.clone()),
+                    });
+                }
+            }
+        }
+        Self::apply_delta(state, &ops[guards..])
+    }
+
+    /// Compute delta with a configurable array diff
+    ///
+    /// Objects are diffed key by key and arrays according to
+    /// `options.array_strategy`, applied independently to every changed
+    /// array. Arrays named in `options.array_keys`, and every keyed array
+    /// under `ArrayStrategy::ById`, are matched by element instead, so
+    /// applying the ops can yield a different order than `current_state`.
+    /// Also returns which strategy each array ended up using.
+    ///
+    /// Fails with `InvalidArrayKey` if an element of a keyed array in
+    /// `current_state` lacks the key field or repeats a key.
+    pub fn compute_delta_optimized(
+        prev_state: &Value,
+        current_state: &Value,
+        options: &DiffOptions,
+    ) -> Result<(Vec<json_patch::PatchOperation>, DiffStats)> {
+        let mut differ = Differ::new(&options.array_strategy, &options.array_keys);
+        differ.diff(prev_state, current_state, "")?;
+        let ops = serde_json::from_value(Value::Array(differ.ops))?;
+        Ok((ops, differ.stats))
+    }
+
+    /// Canonical size of `ops` relative to the canonical size of `state`
+    pub fn patch_ratio(ops: &[json_patch::PatchOperation], state: &Value) -> Result<f64> {
+        let ops_len = Canonicalizer::canonical_len(&serde_json::to_value(ops)?)?;
+        let state_len = Canonicalizer::canonical_len(state)?;
+        Ok(ops_len as f64 / state_len.max(1) as f64)
+    }
+
+    /// A patch that replaces the whole state with `state`
+    pub fn root_replace(state: &Value) -> Result<Vec<json_patch::PatchOperation>> {
+        Ok(serde_json::from_value(json!([{"op": "replace", "path": "", "value": state}]))?)
+    }
+
+    /// Apply delta to a state
+    pub fn apply_delta(
+        state: &mut Value,
+        ops: &[json_patch::PatchOperation],
+    ) -> Result<()> {
+        let patch = json_patch::Patch(ops.to_vec());
+        json_patch::patch(state, &patch)?;
+        Ok(())
+    }
+
+    /// State after applying `ops` to a copy of `state`
+    pub fn dry_run(state: &Value, ops: &[json_patch::PatchOperation]) -> Result<Value> {
+        let mut patched = state.clone();
+        Self::apply_delta(&mut patched, ops)?;
+        Ok(patched)
+    }
+
+    /// Apply `ops` to a copy of `state`, reporting how each op fared
+    ///
+    /// Ops are applied in order until one fails, so the outcomes end at the
+    /// first failure. The returned state is the patched copy if every op
+    /// applied and `state` unchanged otherwise, as `apply_delta` leaves it.
+    pub fn try_apply(
+        state: &Value,
+        ops: &[json_patch::PatchOperation],
+    ) -> Result<(Value, Vec<OpOutcome>)> {
+        let mut patched = state.clone();
+        let mut outcomes = Vec::with_capacity(ops.len());
+        for (index, op) in ops.iter().enumerate() {
+            let outcome = match json_patch::patch(&mut patched, std::slice::from_ref(op)) {
+                Ok(()) => OpOutcome {
+                    index,
+                    path: op.path().to_string(),
+                    error: None,
+                },
+                Err(e) => {
+                    let path = match (&e.kind, op) {
+                        (
+                            json_patch::PatchErrorKind::InvalidFromPointer,
+                            json_patch::PatchOperation::Move(json_patch::MoveOperation { from, .. })
+                            | json_patch::PatchOperation::Copy(json_patch::CopyOperation { from, .. }),
+                        ) => from.to_string(),
+                        _ => e.path.to_string(),
+                    };
+                    OpOutcome {
+                        index,
+                        path,
+                        error: Some(e.kind.to_string()),
+                    }
+                }
+            };
+            let failed = !outcome.applied();
+            outcomes.push(outcome);
+            if failed {
+                return Ok((state.clone(), outcomes));
+            }
+        }
+        Ok((patched, outcomes))
+    }
+
+    /// Apply only the part of `ops` that affects the value at `prefix`
+    ///
+    /// Ops outside `prefix` are skipped. Writes to an ancestor of `prefix`
+    /// are cut down to the branch leading to it, so replacing `/config` costs
+    /// no more than the `/config/network` it holds. Afterwards the value at
+    /// `prefix` is what `apply_delta` would leave there; the rest of `state`
+    /// is unspecified. Fails with `DeltaCompression` for ops that cannot be
+    /// scoped this way: moving or copying a value in from outside `prefix`,
+    /// and inserting into or removing from an array on its path before the
+    /// indexed element, which shifts it.
+    pub fn apply_partial(
+        state: &mut Value,
+        ops: &[json_patch::PatchOperation],
+        prefix: &jsonptr::Pointer,
+    ) -> Result<()> {
+        let scope: Vec<String> = prefix.tokens().map(|t| t.decoded().to_string()).collect();
+        for (index, op) in ops.iter().enumerate() {
+            let scoped = scope_op(op, &scope, state).map_err(|reason| {
+                let prefix = prefix.to_string();
+                BmsError::DeltaCompression(format!("Op {} cannot be applied under {:?}: {}", index, prefix, reason))
+            })?;
+            if let Some(scoped) = scoped {
+                let scoped: json_patch::PatchOperation = serde_json::from_value(scoped)?;
+                Self::apply_delta(state, std::slice::from_ref(&scoped))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The value at `prefix` after applying `ops` to a copy of `state`
+    ///
+    /// The whole patch is applied, so unlike `apply_partial` any op may come
+    /// from outside `prefix`. Fails with `InvalidState` if nothing is left at
+    /// `prefix`.
+    pub fn dry_run_partial(
+        state: &Value,
+        ops: &[json_patch::PatchOperation],
+        prefix: &jsonptr::Pointer,
+    ) -> Result<Value> {
+        let mut patched = Self::dry_run(state, ops)?;
+        patched
+            .pointer_mut(prefix.as_str())
+            .map(Value::take)
+            .ok_or_else(|| BmsError::InvalidState(format!("Nothing at {:?} after the patch", prefix.as_str())))
+    }
+
+    /// Deltas from the start of the chain up to and including `target`, in
+    /// chain order
+    ///
+    /// Order is taken from `parent_id` links, not from the slice. Fails with
+    /// `DeltaNotFound` naming `target`, or the first missing ancestor, when
+    /// the slice does not hold the whole chain.
+    pub fn chain_to<'a>(deltas: &'a [Delta], target: &DeltaId) -> Result<Vec<&'a Delta>> {
+        let by_id: HashMap<&DeltaId, &Delta> = deltas.iter().map(|d| (&d.id, d)).collect();
+        let mut chain = Vec::new();
+        let mut next = Some(target);
+        while let Some(id) = next {
+            let delta = by_id.get(id).ok_or_else(|| BmsError::DeltaNotFound(id.0.clone()))?;
+            // More steps than deltas means the parent links loop
+            if chain.len() == deltas.len() {
+                return Err(BmsError::InvalidState(format!("Parent links of {} form a cycle", target)));
+            }
+            chain.push(*delta);
+            next = delta.parent_id.as_ref();
+        }
+        chain.reverse();
+        Ok(chain)
+    }
+
+    /// State after applying the chain up to and including `target`
+    ///
+    /// Replays from the empty state every chain starts from; see `chain_to`
+    /// for ordering and errors.
+    pub fn replay_to(deltas: &[Delta], target: &DeltaId) -> Result<Value> {
+        let mut state = json!({});
+        for delta in Self::chain_to(deltas, target)? {
+            Self::apply_delta(&mut state, &delta.ops)?;
+        }
+        Ok(state)
+    }
+
+    /// Squash a contiguous run of deltas into one equivalent patch
+    ///
+    /// The ops are concatenated and then folded wherever the result does not
+    /// depend on the state they apply to: two writes to the same path keep
+    /// only the last value (`remove` then `add` becomes `replace`), writes
+    /// under a path that is later replaced or removed are dropped, and writes
+    /// into a value added earlier in the run are folded into that value. An
+    /// op in between that touches the same region blocks a fold; inserts and
+    /// removals at array positions count as touching the whole array, since
+    /// they shift the indices after them.
+    ///
+    /// Applying the result to a state the chain applies to gives the chain's
+    /// final state. Fails if a delta is not the child of the one before it.
+    pub fn merge_deltas(deltas: &[Delta]) -> Result<Vec<json_patch::PatchOperation>> {
+        for pair in deltas.windows(2) {
+            if pair[1].coord_id != pair[0].coord_id || pair[1].parent_id.as_ref() != Some(&pair[0].id) {
+                return Err(BmsError::InvalidState(format!(
+                    "Delta {} does not follow {}; only a contiguous chain can be merged",
+                    pair[1].id, pair[0].id
+                )));
+            }
+        }
+
+        let mut merged: Vec<MergeOp> = Vec::new();
+        for op in deltas.iter().flat_map(|d| &d.ops) {
+            if let Some(op) = merge_op(&mut merged, MergeOp::new(op.clone())?)? {
+                merged.push(op);
+            }
+        }
+        Ok(merged.into_iter().map(|m| m.op).collect())
+    }
+
+    /// Compose a run of deltas into one patch, for history compaction
+    ///
+    /// The same as `merge_deltas`; the run must be contiguous and the patch
+    /// applies to the state before its first delta.
+    pub fn squash(deltas: &[Delta]) -> Result<Vec<json_patch::PatchOperation>> {
+        Self::merge_deltas(deltas)
+    }
+
+    /// Patch that undoes `ops`, given the state they were applied to
+    ///
+    /// An `add` becomes a `remove` (or a `replace` with the old value when it
+    /// overwrote an object member), a `remove` becomes an `add` of the
+    /// removed value, and a `replace` puts the old value back. `move` and
+    /// `copy` are undone the same way; `test` needs no undo. Applying the
+    /// result to the state after `ops` gives `state_before`.
+    ///
+    /// Fails with `DeltaCompression` if a `remove` or `replace` (or the
+    /// source of a `move` or `copy`) names a path the state does not have.
+    pub fn invert_delta(
+        ops: &[json_patch::PatchOperation],
+        state_before: &Value,
+    ) -> Result<Vec<json_patch::PatchOperation>> {
+        let missing = |path: &str| BmsError::DeltaCompression(format!("Cannot invert: {:?} is not in the state", path));
+        let mut state = state_before.clone();
+        // Undo ops per op, applied in reverse at the end
+        let mut undo: Vec<Vec<Value>> = Vec::with_capacity(ops.len());
+        for op in ops {
+            let path = op.path().to_string();
+            let inverse = match op {
+                json_patch::PatchOperation::Add(_) => undo_write(&state, &path),
+                json_patch::PatchOperation::Copy(copy) => {
+                    let from = copy.from.to_string();
+                    state.pointer(&from).ok_or_else(|| missing(&from))?;
+                    undo_write(&state, &path)
+                }
+                json_patch::PatchOperation::Remove(_) => {
+                    let old = state.pointer(&path).ok_or_else(|| missing(&path))?;
+                    vec![json!({"op": "add", "path": path, "value": old})]
+                }
+                json_patch::PatchOperation::Replace(_) => {
+                    let old = state.pointer(&path).ok_or_else(|| missing(&path))?;
+                    vec![json!({"op": "replace", "path": path, "value": old})]
+                }
+                json_patch::PatchOperation::Move(mv) => {
+                    let from = mv.from.to_string();
+                    state.pointer(&from).ok_or_else(|| missing(&from))?;
+                    // The target is resolved after the source is taken out,
+                    // which matters for appends within the same array
+                    let mut taken = state.clone();
+                    let take: Vec<json_patch::PatchOperation> =
+                        serde_json::from_value(json!([{"op": "remove", "path": from}]))?;
+                    Self::apply_delta(&mut taken, &take)?;
+                    // Moving back restores the source; a member the move
+                    // overwrote is added back after that
+                    let mut inverse = undo_write(&taken, &path);
+                    let target = match inverse.first() {
+                        Some(first) => first["path"].as_str().unwrap_or(&path).to_string(),
+                        None => path.clone(),
+                    };
+                    inverse.retain(|u| u["op"] == "replace");
+                    let mut steps = vec![json!({"op": "move", "from": target, "path": from})];
+                    steps.extend(inverse.into_iter().map(|mut u| {
+                        u["op"] = json!("add");
+                        u
+                    }));
+                    steps
+                }
+                json_patch::PatchOperation::Test(_) => Vec::new(),
+            };
+            Self::apply_delta(&mut state, std::slice::from_ref(op))?;
+            undo.push(inverse);
+        }
+        let inverse: Vec<Value> = undo.into_iter().rev().flatten().collect();
+        Ok(serde_json::from_value(Value::Array(inverse))?)
+    }
+
+    /// Sort the paths a patch writes into added, removed, and modified
+    ///
+    /// An `add` counts as added (without the state, an add that overwrites
+    /// a member cannot be told apart), `replace` as modified, and `remove` as
+    /// removed. A `move` removes its source and adds its target, a `copy`
+    /// adds its target, and `test` changes nothing. Each list keeps the op
+    /// order without repeats.
+    pub fn describe_ops(ops: &[json_patch::PatchOperation]) -> DiffSummary {
+        use json_patch::PatchOperation;
+
+        let mut summary = DiffSummary::default();
+        let push = |paths: &mut Vec<String>, pointer: &jsonptr::Pointer| {
+            let path = pointer.to_string();
+            if !paths.contains(&path) {
+                paths.push(path);
+            }
+        };
+        for op in ops {
+            match op {
+                PatchOperation::Add(add) => push(&mut summary.added_paths, &add.path),
+                PatchOperation::Remove(remove) => push(&mut summary.removed_paths, &remove.path),
+                PatchOperation::Replace(replace) => push(&mut summary.modified_paths, &replace.path),
+                PatchOperation::Move(mv) => {
+                    push(&mut summary.removed_paths, &mv.from);
+                    push(&mut summary.added_paths, &mv.path);
+                }
+                PatchOperation::Copy(copy) => push(&mut summary.added_paths, &copy.path),
+                PatchOperation::Test(_) => {}
+            }
+        }
+        let (added, removed, modified) =
+            (summary.added_paths.len(), summary.removed_paths.len(), summary.modified_paths.len());
+        summary.text_summary = if added + removed + modified == 0 {
+            "no changes".to_string()
+        } else {
+            let noun = if added == 1 { "field" } else { "fields" };
+            format!("{} {} added, {} removed, {} modified", added, noun, removed, modified)
+        };
+        summary
+    }
+
+    /// Paths written by both of two patches computed from the same base
+    ///
+    /// Works from the op paths alone. Paths clash when one is a token-wise
+    /// prefix of the other, and inserts and removals at array positions count
+    /// as writing the whole array, since they shift the elements after them.
+    /// The same op on both sides does not clash, unless it appends (twice).
+    /// `test` ops write nothing, and the source of a `copy` is only read.
+    /// One report per path, in path order.
+    pub fn detect_conflicts(
+        a: &[json_patch::PatchOperation],
+        b: &[json_patch::PatchOperation],
+    ) -> Vec<ConflictReport> {
+        let mut found: BTreeMap<Vec<String>, ConflictKind> = BTreeMap::new();
+        for op_a in a {
+            for op_b in b {
+                let appends = op_a.path().tokens().last().is_some_and(|t| t.decoded() == "-");
+                if op_a == op_b && !appends {
+                    continue;
+                }
+                for (path_a, write_a) in op_writes(op_a) {
+                    for (path_b, write_b) in op_writes(op_b) {
+                        let shared = path_a.len().min(path_b.len());
+                        if path_a[..shared] != path_b[..shared] {
+                            continue;
+                        }
+                        let kind = match (write_a, write_b) {
+                            (Write::Removed, Write::Removed) => ConflictKind::BothModified,
+                            (Write::Removed, _) | (_, Write::Removed) => ConflictKind::OneRemovedOtherModified,
+                            (Write::Added, Write::Added) if path_a == path_b => ConflictKind::AddedAtSamePath,
+                            _ => ConflictKind::BothModified,
+                        };
+                        let deeper = if path_a.len() >= path_b.len() { &path_a } else { &path_b };
+                        found.entry(deeper.clone()).or_insert(kind);
+                    }
+                }
+            }
+        }
+        found
+            .into_iter()
+            .map(|(path, kind)| ConflictReport {
+                path: jsonptr::Pointer::new(&path),
+                kind,
+            })
+            .collect()
+    }
+
+    /// Three-way merge of two patches computed from `base`
+    ///
+    /// Both patches are applied to `base` and the results merged value by
+    /// value: what only one side changed is kept, objects merge member by
+    /// member, and arrays of unchanged length element by element. A value
+    /// both sides changed differently is settled by `strategy`; with
+    /// `FailOnConflict`, any clash `detect_conflicts` finds is an error
+    /// instead. Fails with `DeltaCompression` if either patch does not apply.
+    pub fn merge_with_conflict_resolution(
+        base: &Value,
+        a: &[json_patch::PatchOperation],
+        b: &[json_patch::PatchOperation],
+        strategy: MergeStrategy,
+    ) -> Result<Value> {
+        if strategy == MergeStrategy::FailOnConflict {
+            let conflicts = Self::detect_conflicts(a, b);
+            if !conflicts.is_empty() {
+                return Err(BmsError::MergeConflict {
+                    paths: conflicts.iter().map(|c| c.path.to_string()).collect(),
+                });
+            }
+        }
+        let mut ours = base.clone();
+        Self::apply_delta(&mut ours, a)?;
+        let mut theirs = base.clone();
+        Self::apply_delta(&mut theirs, b)?;
+        let mut settle = Settle::Theirs {
+            union: strategy == MergeStrategy::UnionArrays,
+        };
+        Ok(merge3(Some(base), Some(&ours), Some(&theirs), &mut settle).unwrap_or(Value::Null))
+    }
+
+    /// Three-way merge of two patches computed from `base`, reporting
+    /// rather than settling what both changed
+    ///
+    /// Merges like `merge_with_conflict_resolution`: changes to different
+    /// members or to different elements of an array whose length neither
+    /// side changed combine, and the same change on both sides is taken
+    /// once. Anything else both sides changed is a conflict, reported at the
+    /// deepest path where the two differ; an array whose length both sides
+    /// changed differently conflicts as a whole. Fails with
+    /// `DeltaCompression` if either patch does not apply to `base`.
+    pub fn merge(
+        base: &Value,
+        ours: &[json_patch::PatchOperation],
+        theirs: &[json_patch::PatchOperation],
+    ) -> Result<MergeOutcome> {
+        let mut ours_state = base.clone();
+        Self::apply_delta(&mut ours_state, ours)?;
+        let mut theirs_state = base.clone();
+        Self::apply_delta(&mut theirs_state, theirs)?;
+        let mut settle = Settle::Report {
+            at: Vec::new(),
+            conflicts: Vec::new(),
+        };
+        let merged = merge3(Some(base), Some(&ours_state), Some(&theirs_state), &mut settle).unwrap_or(Value::Null);
+        match settle {
+            Settle::Report { conflicts, .. } if !conflicts.is_empty() => Ok(MergeOutcome::Conflicts(conflicts)),
+            _ => Ok(MergeOutcome::Merged(Self::compute_delta(base, &merged)?)),
+        }
+    }
+
+    /// Encode ops for storage in the default `DeltaEncoding`
+    ///
+    /// Only the stored form changes; `hash_delta` always hashes canonical
+    /// JSON, so delta IDs and chain hashes do not depend on the encoding.
+    pub fn encode_ops(ops: &[json_patch::PatchOperation]) -> Result<Vec<u8>> {
+        Self::encode_ops_as(ops, DeltaEncoding::default())
+    }
+
+    /// Encode ops for storage in `encoding`
+    ///
+    /// With the `compressed-ops` feature, ops of `COMPRESS_MIN_BYTES` or more
+    /// are stored as by `compress_ops`.
+    pub fn encode_ops_as(ops: &[json_patch::PatchOperation], encoding: DeltaEncoding) -> Result<Vec<u8>> {
+        let encoded = Self::serialize_ops(ops, encoding)?;
+        #[cfg(feature = "compressed-ops")]
+        let encoded = if encoded.len() >= COMPRESS_MIN_BYTES {
+            Self::compress_encoded(&encoded, encoding)?
+        } else {
+            encoded
+        };
+        Ok(encoded)
+    }
+
+    fn serialize_ops(ops: &[json_patch::PatchOperation], encoding: DeltaEncoding) -> Result<Vec<u8>> {
+        match encoding {
+            DeltaEncoding::Json => Ok(serde_json::to_vec(ops)?),
+            #[cfg(feature = "cbor-ops")]
+            DeltaEncoding::Cbor => Self::ops_to_cbor(ops),
+            #[cfg(not(feature = "cbor-ops"))]
+            DeltaEncoding::Cbor => Err(BmsError::DeltaCompression(
+                "CBOR ops need the cbor-ops feature".to_string(),
+            )),
+        }
+    }
+
+    /// Compress ops with zstd in the default `DeltaEncoding`, led by the
+    /// matching flag byte (`OPS_FLAG_ZSTD_JSON` or `OPS_FLAG_ZSTD_CBOR`)
+    #[cfg(feature = "compressed-ops")]
+    pub fn compress_ops(ops: &[json_patch::PatchOperation]) -> Result<Vec<u8>> {
+        let encoding = DeltaEncoding::default();
+        Self::compress_encoded(&Self::serialize_ops(ops, encoding)?, encoding)
+    }
+
+    #[cfg(feature = "compressed-ops")]
+    fn compress_encoded(encoded: &[u8], encoding: DeltaEncoding) -> Result<Vec<u8>> {
+        let flag = match encoding {
+            DeltaEncoding::Json => OPS_FLAG_ZSTD_JSON,
+            DeltaEncoding::Cbor => OPS_FLAG_ZSTD_CBOR,
+        };
+        let mut framed = vec![flag];
+        zstd::stream::copy_encode(encoded, &mut framed, 0)
+            .map_err(|e| BmsError::DeltaCompression(format!("Cannot compress ops: {}", e)))?;
+        Ok(framed)
+    }
+
+    /// Decode ops led by a flag byte, as written by `compress_ops`
+    pub fn decompress_ops(bytes: &[u8]) -> Result<Vec<json_patch::PatchOperation>> {
+        match bytes.first() {
+            Some(&flag) if flag <= OPS_FLAG_ZSTD_CBOR => Self::decode_ops(bytes),
+            _ => Err(BmsError::DeltaCompression("Ops have no compression flag".to_string())),
+        }
+    }
+
+    /// The payload behind a flag byte, decompressed
+    fn unframe(flag: u8, payload: &[u8]) -> Result<Vec<u8>> {
+        if flag == OPS_FLAG_JSON {
+            return Ok(payload.to_vec());
+        }
+        #[cfg(feature = "compressed-ops")]
+        {
+            zstd::stream::decode_all(payload)
+                .map_err(|e| BmsError::DeltaCompression(format!("Ops are not valid zstd: {}", e)))
+        }
+        #[cfg(not(feature = "compressed-ops"))]
+        {
+            Err(BmsError::DeltaCompression(
+                "Ops are zstd-compressed; reading them needs the compressed-ops feature".to_string(),
+            ))
+        }
+    }
+
+    /// Encoding of stored ops, judged by the first byte as `decode_op_values`
+    /// does
+    pub fn stored_encoding(bytes: &[u8]) -> DeltaEncoding {
+        match bytes.first() {
+            Some(&OPS_FLAG_ZSTD_CBOR) => DeltaEncoding::Cbor,
+            Some(&flag) if flag <= OPS_FLAG_ZSTD_JSON => DeltaEncoding::Json,
+            _ if bytes.trim_ascii_start().first() == Some(&b'[') => DeltaEncoding::Json,
+            _ => DeltaEncoding::Cbor,
+        }
+    }
+
+    /// Decode stored ops in either encoding
+    pub fn decode_ops(bytes: &[u8]) -> Result<Vec<json_patch::PatchOperation>> {
+        Ok(serde_json::from_value(Value::Array(Self::decode_op_values(bytes)?))?)
+    }
+
+    /// Decode stored ops in either encoding, as JSON values so each op can
+    /// be checked on its own
+    ///
+    /// A JSON array starts with `[` (after any whitespace), which no CBOR
+    /// array does. CBOR needs the `cbor-ops` feature, and ops led by a zstd
+    /// flag byte the `compressed-ops` feature.
+    pub fn decode_op_values(bytes: &[u8]) -> Result<Vec<Value>> {
+        if let Some(&flag) = bytes.first().filter(|flag| **flag <= OPS_FLAG_ZSTD_CBOR) {
+            let payload = Self::unframe(flag, &bytes[1..])?;
+            if payload.first().is_some_and(|b| *b <= OPS_FLAG_ZSTD_CBOR) {
+                return Err(BmsError::DeltaCompression("Ops are framed twice".to_string()));
+            }
+            return Self::decode_op_values(&payload);
+        }
+        if Self::stored_encoding(bytes) == DeltaEncoding::Json {
+            return Ok(serde_json::from_slice(bytes)?);
+        }
+        #[cfg(feature = "cbor-ops")]
+        {
+            ciborium::from_reader(bytes)
+                .map_err(|e| BmsError::DeltaCompression(format!("Ops are not valid CBOR: {}", e)))
+        }
+        #[cfg(not(feature = "cbor-ops"))]
+        {
+            Err(BmsError::DeltaCompression(
+                "Ops are not a JSON array; CBOR ops need the cbor-ops feature".to_string(),
+            ))
+        }
+    }
+
+    /// Serialize ops as CBOR
+    #[cfg(feature = "cbor-ops")]
+    pub fn ops_to_cbor(ops: &[json_patch::PatchOperation]) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        ciborium::into_writer(ops, &mut bytes)
+            .map_err(|e| BmsError::DeltaCompression(format!("Cannot encode ops as CBOR: {}", e)))?;
+        Ok(bytes)
+    }
+
+    /// Parse CBOR ops, e.g. to re-encode stored rows as JSON
+    #[cfg(feature = "cbor-ops")]
+    pub fn ops_from_cbor(bytes: &[u8]) -> Result<Vec<json_patch::PatchOperation>> {
+        ciborium::from_reader(bytes).map_err(|e| BmsError::DeltaCompression(format!("Ops are not valid CBOR: {}", e)))
+    }
+
+    /// Compute hash of delta operations
+    pub fn hash_delta(ops: &[json_patch::PatchOperation]) -> Result<Hash> {
+        Ok(Hash(hex::encode(Self::delta_digest(ops)?)))
+    }
+
+    /// SHA3-256 of the canonical ops, the bytes `hash_delta` hex-encodes
+    fn delta_digest(ops: &[json_patch::PatchOperation]) -> Result<[u8; 32]> {
+        let delta_value = serde_json::to_value(ops)?;
+        let mut hasher = Sha3_256::new();
+        Canonicalizer::hash_canonical(&delta_value, &mut hasher)?;
+        Ok(hasher.finalize().into())
+    }
+
+    /// Sign the delta hash of `ops` with `key`
+    pub fn sign_delta(
+        ops: &[json_patch::PatchOperation],
+        key: &ed25519_dalek::SigningKey,
+    ) -> Result<DeltaSignature> {
+        use ed25519_dalek::Signer;
+
+        Ok(DeltaSignature {
+            public_key: key.verifying_key().to_bytes(),
+            signature_bytes: key.sign(&Self::delta_digest(ops)?).to_bytes(),
+        })
+    }
+
+    /// Check that `signature` is its key's signature over the delta hash of
+    /// `ops`
+    ///
+    /// Only proves the ops were signed by whoever holds that key; which keys
+    /// to trust is up to the caller.
+    pub fn verify_signature(ops: &[json_patch::PatchOperation], signature: &DeltaSignature) -> Result<()> {
+        let key = ed25519_dalek::VerifyingKey::from_bytes(&signature.public_key)
+            .map_err(|e| BmsError::InvalidSignature(format!("bad public key: {}", e)))?;
+        let sig = ed25519_dalek::Signature::from_bytes(&signature.signature_bytes);
+        key.verify_strict(&Self::delta_digest(ops)?, &sig)
+            .map_err(|_| BmsError::InvalidSignature(format!("not signed by {}", hex::encode(signature.public_key))))
+    }
+
+    /// Generate delta ID from hash (first 16 bytes)
+    pub fn generate_delta_id(ops: &[json_patch::PatchOperation]) -> Result<DeltaId> {
+        let hash = Self::delta_digest(ops)?;
+
+        // First 16 bytes as hex
+        let id = hex::encode(&hash[..16]);
+        Ok(DeltaId(id))
+    }
+
+    /// Compute hash of a state
+    pub fn hash_state(state: &Value) -> Result<Hash> {
+        Self::hash_state_as(state, CanonicalForm::Legacy)
+    }
+
+    /// Hash of a state over the given canonical form
+    pub fn hash_state_as(state: &Value, form: CanonicalForm) -> Result<Hash> {
+        let mut hasher = Sha3_256::new();
+        match form {
+            CanonicalForm::Legacy => Canonicalizer::hash_canonical(state, &mut hasher)?,
+            form => hasher.update(Canonicalizer::canonicalize_as(state, form)?),
+        }
+        let hash = hasher.finalize();
+        
+        Ok(Hash(hex::encode(hash)))
+    }
+
+    /// Verify a state hashes to `expected`, given bare or prefixed
+    pub fn verify_state_hash(state: &Value, expected: &Hash) -> Result<()> {
+        let actual = Self::hash_state(state)?;
+        if !actual.matches(expected) {
+            return Err(BmsError::HashMismatch {
+                expected: expected.0.clone(),
+                actual: actual.0,
+            });
+        }
+        Ok(())
+    }
+
+    /// Verify delta hash matches expected
+    pub fn verify_delta_hash(
+        ops: &[json_patch::PatchOperation],
+        expected_hash: &Hash,
+    ) -> Result<()> {
+        let actual_hash = Self::hash_delta(ops)?;
+        
+        if actual_hash.0 != expected_hash.0 {
+            return Err(BmsError::HashMismatch {
+                expected: expected_hash.0.clone(),
+                actual: actual_hash.0,
+            });
+        }
+        
+        Ok(())
+    }
+
+    /// Count `ops` by kind and measure their canonical size
+    pub fn ops_statistics(ops: &[json_patch::PatchOperation]) -> OpsStats {
+        let mut stats = OpsStats {
+            // Patch ops always serialize; a failure would only undercount
+            total_bytes: serde_json::to_value(ops)
+                .ok()
+                .and_then(|ops| Canonicalizer::canonical_len(&ops).ok())
+                .unwrap_or(0),
+            ..OpsStats::default()
+        };
+        for op in ops {
+            let count = match OpKind::of(op) {
+                OpKind::Add => &mut stats.add_count,
+                OpKind::Remove => &mut stats.remove_count,
+                OpKind::Replace => &mut stats.replace_count,
+                OpKind::Test => &mut stats.test_count,
+                OpKind::Move => &mut stats.move_count,
+                OpKind::Copy => &mut stats.copy_count,
+            };
+            *count += 1;
+        }
+        stats
+    }
+
+    /// Share of the canonical size of `original` saved by storing
+    /// `delta_ops` instead
+    pub fn compression_ratio(original: &Value, delta_ops: &[json_patch::PatchOperation]) -> f64 {
+        let original_size = Canonicalizer::canonical_len(original).unwrap_or(0);
+        if original_size == 0 {
+            return 0.0;
+        }
+        let delta_size = Self::ops_statistics(delta_ops).total_bytes;
+        1.0 - (delta_size as f64 / original_size as f64)
+    }
+}
+
+/// Recursive patch builder behind `compute_delta_optimized`
+struct Differ<'a> {
+    strategy: &'a ArrayStrategy,
+    array_keys: &'a BTreeMap<String, Option<String>>,
+    ops: Vec<Value>,
+    stats: DiffStats,
+}
+
+enum Edit<'a> {
+    Keep,
+    Delete,
+    Insert(&'a Value),
+    Modify(&'a Value, &'a Value),
+}
+
+impl<'a> Differ<'a> {
+    fn new(strategy: &'a ArrayStrategy, array_keys: &'a BTreeMap<String, Option<String>>) -> Self {
+        Self {
+            strategy,
+            array_keys,
+            ops: Vec::new(),
+            stats: DiffStats::default(),
+        }
+    }
+
+    fn diff(&mut self, prev: &Value, current: &Value, path: &str) -> Result<()> {
+        if prev == current {
+            return Ok(());
+        }
+
+        match (prev, current) {
+            (Value::Object(a), Value::Object(b)) => {
+                for (key, old) in a {
+                    let child = child_path(path, key);
+                    match b.get(key) {
+                        Some(new) => self.diff(old, new, &child)?,
+                        None => self.ops.push(json!({"op": "remove", "path": child})),
+                    }
+                }
+                for (key, new) in b {
+                    if !a.contains_key(key) {
+                        self.ops.push(json!({"op": "add", "path": child_path(path, key), "value": new}));
+                    }
+                }
+            }
+            (Value::Array(a), Value::Array(b)) => match self.array_hint(path) {
+                Some(Some(key)) => self.diff_keyed(a, b, key, path)?,
+                Some(None) => self.diff_set(a, b, path)?,
+                None => match self.strategy {
+                    ArrayStrategy::ById { key }
+                        if element_keys(a, key, path).is_ok() && element_keys(b, key, path).is_ok() =>
+                    {
+                        self.diff_keyed(a, b, key, path)?
+                    }
+                    _ => self.diff_array(a, b, path)?,
+                },
+            },
+            _ => self.ops.push(json!({"op": "replace", "path": path, "value": current})),
+        }
+        Ok(())
+    }
+
+    /// Key field of the array at `path` (`None` for a set), if it has a hint
+    fn array_hint(&self, path: &str) -> Option<Option<&'a str>> {
+        let segments: Vec<&str> = path.split('/').collect();
+        self.array_keys.iter().find_map(|(pointer, key)| {
+            let pattern: Vec<&str> = pointer.split('/').collect();
+            let matches = pattern.len() == segments.len()
+                && pattern.iter().zip(&segments).all(|(p, s)| *p == "*" || p == s);
+            matches.then_some(key.as_deref())
+        })
+    }
+
+    fn diff_array(&mut self, a: &[Value], b: &[Value], path: &str) -> Result<()> {
+        let replace = json!({"op": "replace", "path": path, "value": b});
+
+        let candidate = match *self.strategy {
+            ArrayStrategy::Replace => None,
+            ArrayStrategy::ElementWise => return self.diff_positional(a, b, path),
+            ArrayStrategy::Lcs | ArrayStrategy::ById { .. } => self.lcs_diff(a, b, path)?,
+            ArrayStrategy::Auto { max_ops_per_array, max_bytes_ratio } => {
+                self.lcs_diff(a, b, path)?.filter(|lcs| {
+                    let lcs_bytes = serialized_len(&lcs.ops);
+                    let replace_bytes = serialized_len(&replace);
+                    lcs.ops.len() <= max_ops_per_array
+                        && lcs_bytes as f64 <= replace_bytes as f64 * max_bytes_ratio
+                })
+            }
+        };
+
+        match candidate {
+            Some(lcs) => {
+                self.ops.extend(lcs.ops);
+                self.stats.merge(lcs.stats);
+                self.stats.lcs_arrays += 1;
+            }
+            None => {
+                self.ops.push(replace);
+                self.stats.replaced_arrays += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Diff elements at equal indices, then add the extra elements of `b`
+    /// or remove those of `a` from the last index down
+    fn diff_positional(&mut self, a: &[Value], b: &[Value], path: &str) -> Result<()> {
+        for (i, (old, new)) in a.iter().zip(b).enumerate() {
+            self.diff(old, new, &format!("{}/{}", path, i))?;
+        }
+        for (i, new) in b.iter().enumerate().skip(a.len()) {
+            self.ops.push(json!({"op": "add", "path": format!("{}/{}", path, i), "value": new}));
+        }
+        for i in (b.len()..a.len()).rev() {
+            self.ops.push(json!({"op": "remove", "path": format!("{}/{}", path, i)}));
+        }
+        self.stats.positional_arrays += 1;
+        Ok(())
+    }
+
+    /// Diff an array whose order does not matter
+    ///
+    /// Equal multisets produce no ops. Otherwise `b` is sorted by canonical
+    /// form and diffed positionally, so the stored order is canonical.
+    fn diff_set(&mut self, a: &[Value], b: &[Value], path: &str) -> Result<()> {
+        let canonical = |items: &[Value]| -> Result<Vec<(String, Value)>> {
+            let mut sorted = items
+                .iter()
+                .map(|v| Ok((Canonicalizer::canonicalize_str(v)?, v.clone())))
+                .collect::<Result<Vec<_>>>()?;
+            sorted.sort_by(|x, y| x.0.cmp(&y.0));
+            Ok(sorted)
+        };
+        let (old, new) = (canonical(a)?, canonical(b)?);
+        if old.iter().map(|e| &e.0).eq(new.iter().map(|e| &e.0)) {
+            return Ok(());
+        }
+
+        let sorted: Vec<Value> = new.into_iter().map(|e| e.1).collect();
+        self.diff_array(a, &sorted, path)
+    }
+
+    /// Diff an array of objects identified by their `key` field
+    ///
+    /// Removals come first, from the last index down, then edits to the
+    /// remaining elements in their old order, then additions appended in
+    /// their order in `b`. Reordering alone produces no ops. If `a` itself
+    /// is not a valid keyed array, it is diffed positionally instead.
+    fn diff_keyed(&mut self, a: &[Value], b: &[Value], key: &str, path: &str) -> Result<()> {
+        let new_keys = element_keys(b, key, path)?;
+        let Ok(old_keys) = element_keys(a, key, path) else {
+            return self.diff_array(a, b, path);
+        };
+
+        let new_by_key: HashMap<&str, &Value> =
+            new_keys.iter().map(String::as_str).zip(b).collect();
+        for (i, old_key) in old_keys.iter().enumerate().rev() {
+            if !new_by_key.contains_key(old_key.as_str()) {
+                self.ops.push(json!({"op": "remove", "path": format!("{}/{}", path, i)}));
+            }
+        }
+
+        let mut index = 0;
+        for (old, old_key) in a.iter().zip(&old_keys) {
+            if let Some(new) = new_by_key.get(old_key.as_str()) {
+                self.diff(old, new, &format!("{}/{}", path, index))?;
+                index += 1;
+            }
+        }
+
+        let old_keys: HashSet<&str> = old_keys.iter().map(String::as_str).collect();
+        for (new, new_key) in b.iter().zip(&new_keys) {
+            if !old_keys.contains(new_key.as_str()) {
+                self.ops.push(json!({"op": "add", "path": format!("{}/{}", path, index), "value": new}));
+                index += 1;
+            }
+        }
+
+        self.stats.keyed_arrays += 1;
+        Ok(())
+    }
+
+    /// Element-level ops for one array, or `None` if it is too large to align
+    fn lcs_diff(&self, a: &[Value], b: &[Value], path: &str) -> Result<Option<Differ<'a>>> {
+        if a.len().saturating_mul(b.len()) > MAX_LCS_CELLS {
+            return Ok(None);
+        }
+
+        // lcs[i][j] = length of the LCS of a[i..] and b[j..]
+        let width = b.len() + 1;
+        let mut lcs = vec![0u32; (a.len() + 1) * width];
+        for i in (0..a.len()).rev() {
+            for j in (0..b.len()).rev() {
+                lcs[i * width + j] = if a[i] == b[j] {
+                    lcs[(i + 1) * width + j + 1] + 1
+                } else {
+                    lcs[(i + 1) * width + j].max(lcs[i * width + j + 1])
+                };
+            }
+        }
+
+        let mut edits = Vec::with_capacity(a.len().max(b.len()));
+        let (mut i, mut j) = (0, 0);
+        while i < a.len() || j < b.len() {
+            if i < a.len() && j < b.len() && a[i] == b[j] {
+                edits.push(Edit::Keep);
+                i += 1;
+                j += 1;
+            } else if j == b.len() || (i < a.len() && lcs[(i + 1) * width + j] >= lcs[i * width + j + 1]) {
+                // A deletion directly followed by an insertion is an in-place change
+                if j < b.len() && lcs[(i + 1) * width + j] == lcs[(i + 1) * width + j + 1] {
+                    edits.push(Edit::Modify(&a[i], &b[j]));
+                    j += 1;
+                } else {
+                    edits.push(Edit::Delete);
+                }
+                i += 1;
+            } else {
+                edits.push(Edit::Insert(&b[j]));
+                j += 1;
+            }
+        }
+
+        let mut child = Differ::new(self.strategy, self.array_keys);
+        let mut index = 0;
+        for edit in edits {
+            let element = format!("{}/{}", path, index);
+            match edit {
+                Edit::Keep => index += 1,
+                Edit::Delete => child.ops.push(json!({"op": "remove", "path": element})),
+                Edit::Insert(value) => {
+                    child.ops.push(json!({"op": "add", "path": element, "value": value}));
+                    index += 1;
+                }
+                Edit::Modify(old, new) => {
+                    child.diff(old, new, &element)?;
+                    index += 1;
+                }
+            }
+        }
+
+        Ok(Some(child))
+    }
+}
+
+/// Canonical key of every element of a keyed array
+fn element_keys(items: &[Value], key: &str, path: &str) -> Result<Vec<String>> {
+    let mut seen = HashSet::new();
+    items
+        .iter()
+        .enumerate()
+        .map(|(i, item)| {
+            let pointer = format!("{}/{}", path, i);
+            let value = item.get(key).filter(|v| !v.is_null()).ok_or_else(|| {
+                BmsError::InvalidArrayKey {
+                    pointer: pointer.clone(),
+                    reason: format!("element has no {:?} field", key),
+                }
+            })?;
+            let canonical = Canonicalizer::canonicalize_str(value)?;
+            if !seen.insert(canonical.clone()) {
+                return Err(BmsError::InvalidArrayKey {
+                    pointer,
+                    reason: format!("duplicate {} {}", key, canonical),
+                });
+            }
+            Ok(canonical)
+        })
+        .collect()
+}
+
+/// Undo for writing a value at `path` in `state`: the old value back if it
+/// overwrote one, otherwise a removal of the new location
+///
+/// An array append (`-`) is removed at the index it landed on.
+fn undo_write(state: &Value, path: &str) -> Vec<Value> {
+    if path.is_empty() {
+        return vec![json!({"op": "replace", "path": "", "value": state})];
+    }
+    let (parent, last) = path.rsplit_once('/').unwrap_or(("", path));
+    let inserted = match state.pointer(parent) {
+        Some(Value::Array(items)) if last == "-" => format!("{}/{}", parent, items.len()),
+        Some(Value::Array(_)) => path.to_string(),
+        _ => match state.pointer(path) {
+            Some(old) => return vec![json!({"op": "replace", "path": path, "value": old})],
+            None => path.to_string(),
+        },
+    };
+    vec![json!({"op": "remove", "path": inserted})]
+}
+
+/// What an op does at a path it writes, for `detect_conflicts`
+#[derive(Clone, Copy)]
+enum Write {
+    Added,
+    Replaced,
+    Removed,
+}
+
+/// Decoded paths an op writes; array inserts and removals widen to the array
+fn op_writes(op: &json_patch::PatchOperation) -> Vec<(Vec<String>, Write)> {
+    use json_patch::PatchOperation;
+
+    match op {
+        PatchOperation::Add(add) => vec![(shifting(decoded(&add.path)), Write::Added)],
+        PatchOperation::Replace(replace) => vec![(decoded(&replace.path), Write::Replaced)],
+        PatchOperation::Remove(remove) => vec![(shifting(decoded(&remove.path)), Write::Removed)],
+        PatchOperation::Move(mv) => vec![
+            (shifting(decoded(&mv.from)), Write::Removed),
+            (shifting(decoded(&mv.path)), Write::Added),
+        ],
+        PatchOperation::Copy(copy) => vec![(shifting(decoded(&copy.path)), Write::Added)],
+        PatchOperation::Test(_) => Vec::new(),
+    }
+}
+
+/// How `merge3` settles a value both sides changed differently
+enum Settle {
+    /// The second side wins; with `union`, arrays combine as in
+    /// `MergeStrategy::UnionArrays`
+    Theirs { union: bool },
+    /// The first side stands in and the clash is recorded; `at` is the path
+    /// being merged
+    Report { at: Vec<String>, conflicts: Vec<MergeConflict> },
+}
+
+impl Settle {
+    fn enter(&mut self, token: String) {
+        if let Settle::Report { at, .. } = self {
+            at.push(token);
+        }
+    }
+
+    fn leave(&mut self) {
+        if let Settle::Report { at, .. } = self {
+            at.pop();
+        }
+    }
+}
+
+/// Merge two changed versions of a value; `None` is an absent member
+fn merge3(base: Option<&Value>, ours: Option<&Value>, theirs: Option<&Value>, settle: &mut Settle) -> Option<Value> {
+    if ours == theirs || theirs == base {
+        return ours.cloned();
+    }
+    if ours == base {
+        return theirs.cloned();
+    }
+    match (base, ours, theirs, &mut *settle) {
+        (base, Some(Value::Object(ours)), Some(Value::Object(theirs)), _)
+            if base.is_none_or(Value::is_object) =>
+        {
+            let base = base.and_then(Value::as_object);
+            let mut merged = serde_json::Map::new();
+            for key in ours.keys().chain(theirs.keys().filter(|k| !ours.contains_key(*k))) {
+                let base = base.and_then(|b| b.get(key));
+                settle.enter(key.clone());
+                let value = merge3(base, ours.get(key), theirs.get(key), settle);
+                settle.leave();
+                if let Some(value) = value {
+                    merged.insert(key.clone(), value);
+                }
+            }
+            Some(Value::Object(merged))
+        }
+        (Some(Value::Array(base)), Some(Value::Array(ours)), Some(Value::Array(theirs)), _)
+            if base.len() == ours.len() && base.len() == theirs.len() =>
+        {
+            let mut merged = Vec::with_capacity(base.len());
+            for i in 0..base.len() {
+                settle.enter(i.to_string());
+                merged.push(merge3(Some(&base[i]), Some(&ours[i]), Some(&theirs[i]), settle).unwrap_or(Value::Null));
+                settle.leave();
+            }
+            Some(Value::Array(merged))
+        }
+        (base, Some(Value::Array(ours)), Some(Value::Array(theirs)), Settle::Theirs { union: true }) => {
+            let base = match base {
+                Some(Value::Array(base)) => base.as_slice(),
+                _ => &[],
+            };
+            // Drop what the second side removed, then append what it added
+            let mut merged: Vec<Value> =
+                ours.iter().filter(|v| !base.contains(v) || theirs.contains(v)).cloned().collect();
+            for value in theirs {
+                if !base.contains(value) && !merged.contains(value) {
+                    merged.push(value.clone());
+                }
+            }
+            Some(Value::Array(merged))
+        }
+        (_, ours, theirs, Settle::Report { at, conflicts }) => {
+            conflicts.push(MergeConflict {
+                path: jsonptr::Pointer::new(&*at),
+                ours: ours.cloned(),
+                theirs: theirs.cloned(),
+            });
+            ours.cloned()
+        }
+        (_, _, theirs, Settle::Theirs { .. }) => theirs.cloned(),
+    }
+}
+
+/// Where an op path lies relative to the `apply_partial` prefix
+enum Reach<'a> {
+    /// At or under the prefix
+    Inside,
+    /// Above the prefix; holds the tokens from the path down to it
+    Above(&'a [String]),
+    Apart,
+}
+
+fn reach<'a>(path: &[String], scope: &'a [String]) -> Reach<'a> {
+    if path.len() >= scope.len() && path[..scope.len()] == *scope {
+        Reach::Inside
+    } else if path.len() < scope.len() && scope[..path.len()] == *path {
+        Reach::Above(&scope[path.len()..])
+    } else {
+        Reach::Apart
+    }
+}
+
+fn decoded(pointer: &jsonptr::Pointer) -> Vec<String> {
+    pointer.tokens().map(|t| t.decoded().to_string()).collect()
+}
+
+fn encoded(tokens: &[String]) -> String {
+    tokens.iter().fold(String::new(), |p, t| child_path(&p, t))
+}
+
+/// `path` with a trailing `-` replaced by the index it appends at, given
+/// that `removed` elements leave the array first
+fn resolve_append(mut path: Vec<String>, state: &Value, removed: usize) -> Vec<String> {
+    if path.last().is_some_and(|t| t == "-") {
+        if let Some(Value::Array(items)) = state.pointer(&encoded(&path[..path.len() - 1])) {
+            let at = items.len().saturating_sub(removed);
+            *path.last_mut().unwrap() = at.to_string();
+        }
+    }
+    path
+}
+
+/// The part of `op` that `apply_partial` applies, if any
+fn scope_op(
+    op: &json_patch::PatchOperation,
+    scope: &[String],
+    state: &Value,
+) -> std::result::Result<Option<Value>, String> {
+    use json_patch::PatchOperation;
+
+    let as_value = || serde_json::to_value(op).map_err(|e| e.to_string());
+    Ok(match op {
+        PatchOperation::Add(add) => {
+            let path = resolve_append(decoded(&add.path), state, 0);
+            match reach(&path, scope) {
+                Reach::Inside => Some(as_value()?),
+                Reach::Above(rest) => Some(json!({
+                    "op": "add", "path": encoded(&path), "value": prune(&add.value, rest)
+                })),
+                Reach::Apart => {
+                    check_shift(&path, scope, state, true)?;
+                    None
+                }
+            }
+        }
+        PatchOperation::Replace(replace) => match reach(&decoded(&replace.path), scope) {
+            Reach::Inside => Some(as_value()?),
+            Reach::Above(rest) => Some(json!({
+                "op": "replace", "path": replace.path.to_string(), "value": prune(&replace.value, rest)
+            })),
+            Reach::Apart => None,
+        },
+        PatchOperation::Remove(remove) => scope_remove(&decoded(&remove.path), scope, state)?,
+        PatchOperation::Test(test) => match reach(&decoded(&test.path), scope) {
+            Reach::Inside => Some(as_value()?),
+            _ => None,
+        },
+        PatchOperation::Move(mv) => {
+            let from = decoded(&mv.from);
+            // Moving within one array takes the element out before appending
+            let same_array = from.split_last().map(|(_, p)| p) == decoded(&mv.path).split_last().map(|(_, p)| p);
+            let path = resolve_append(decoded(&mv.path), state, usize::from(same_array));
+            match (reach(&from, scope), reach(&path, scope)) {
+                (Reach::Inside, Reach::Inside) => Some(as_value()?),
+                (_, Reach::Inside | Reach::Above(_)) => return Err("moves a value in from outside".to_string()),
+                // Only the removal reaches the prefix
+                (_, Reach::Apart) => {
+                    check_shift(&path, scope, state, true)?;
+                    scope_remove(&from, scope, state)?
+                }
+            }
+        }
+        PatchOperation::Copy(copy) => {
+            let (from, path) = (decoded(&copy.from), resolve_append(decoded(&copy.path), state, 0));
+            match (reach(&from, scope), reach(&path, scope)) {
+                (Reach::Inside, Reach::Inside) => Some(as_value()?),
+                (_, Reach::Inside | Reach::Above(_)) => return Err("copies a value in from outside".to_string()),
+                (_, Reach::Apart) => {
+                    check_shift(&path, scope, state, true)?;
+                    None
+                }
+            }
+        }
+    })
+}
+
+fn scope_remove(path: &[String], scope: &[String], state: &Value) -> std::result::Result<Option<Value>, String> {
+    let pointer = encoded(path);
+    match reach(path, scope) {
+        Reach::Inside if path.len() > scope.len() => Ok(Some(json!({"op": "remove", "path": pointer}))),
+        Reach::Inside | Reach::Above(_) => {
+            // The element shifted into place lies outside the prefix
+            if let Some((parent, _)) = pointer.rsplit_once('/') {
+                if matches!(state.pointer(parent), Some(Value::Array(_))) {
+                    return Err("removes an array element on its path".to_string());
+                }
+            }
+            Ok(Some(json!({"op": "remove", "path": pointer})))
+        }
+        Reach::Apart => {
+            check_shift(path, scope, state, false)?;
+            Ok(None)
+        }
+    }
+}
+
+/// Fail if inserting or removing at `path` shifts the array element that
+/// `scope` passes through
+fn check_shift(path: &[String], scope: &[String], state: &Value, insert: bool) -> std::result::Result<(), String> {
+    let Some((last, parent)) = path.split_last() else {
+        return Ok(());
+    };
+    if parent.len() >= scope.len() || scope[..parent.len()] != *parent {
+        return Ok(());
+    }
+    let pointer = encoded(parent);
+    if !matches!(state.pointer(&pointer), Some(Value::Array(_))) {
+        return Ok(());
+    }
+    let (Ok(at), Ok(element)) = (last.parse::<usize>(), scope[parent.len()].parse::<usize>()) else {
+        return Ok(());
+    };
+    if at < element || (insert && at == element) {
+        let verb = if insert { "inserts into" } else { "removes from" };
+        return Err(format!("{} the array at {:?} before the element on its path", verb, pointer));
+    }
+    Ok(())
+}
+
+/// `value` cut down to the branch along `rest`
+///
+/// Object members off the branch are dropped; array elements off it become
+/// `null`, which keeps the indices.
+fn prune(value: &Value, rest: &[String]) -> Value {
+    let Some((token, rest)) = rest.split_first() else {
+        return value.clone();
+    };
+    match value {
+        Value::Object(map) => match map.get(token) {
+            Some(child) => json!({ token: prune(child, rest) }),
+            None => json!({}),
+        },
+        Value::Array(items) => {
+            let at = token.parse::<usize>().ok();
+            let items = items
+                .iter()
+                .enumerate()
+                .map(|(i, item)| if Some(i) == at { prune(item, rest) } else { Value::Null })
+                .collect();
+            Value::Array(items)
+        }
+        other => other.clone(),
+    }
+}
+
+/// An op being merged, with its path decoded
+struct MergeOp {
+    op: json_patch::PatchOperation,
+    path: Vec<String>,
+    /// Paths the op reads or writes, widened to the array for shifting ops
+    touched: Vec<Vec<String>>,
+}
+
+impl MergeOp {
+    fn new(op: json_patch::PatchOperation) -> Result<Self> {
+        let path = op.path().tokens().map(|t| t.decoded().to_string()).collect();
+        let mut touched = touched_paths(std::slice::from_ref(&op));
+        if let json_patch::PatchOperation::Test(test) = &op {
+            touched.push(test.path.tokens().map(|t| t.decoded().to_string()).collect());
+        }
+        Ok(Self { op, path, touched })
+    }
+
+    fn build(kind: &str, path: &[String], value: Option<Value>) -> Result<Self> {
+        let pointer: String = path.iter().fold(String::new(), |p, t| child_path(&p, t));
+        let mut op = json!({"op": kind, "path": pointer});
+        if let Some(value) = value {
+            op["value"] = value;
+        }
+        Self::new(serde_json::from_value(op)?)
+    }
+
+    fn kind(&self) -> &'static str {
+        match &self.op {
+            json_patch::PatchOperation::Add(_) => "add",
+            json_patch::PatchOperation::Remove(_) => "remove",
+            json_patch::PatchOperation::Replace(_) => "replace",
+            json_patch::PatchOperation::Move(_) => "move",
+            json_patch::PatchOperation::Copy(_) => "copy",
+            json_patch::PatchOperation::Test(_) => "test",
+        }
+    }
+
+    /// Value written by an `add` or `replace`
+    fn value(&self) -> Option<&Value> {
+        match &self.op {
+            json_patch::PatchOperation::Add(op) => Some(&op.value),
+            json_patch::PatchOperation::Replace(op) => Some(&op.value),
+            _ => None,
+        }
+    }
+
+    fn value_mut(&mut self) -> Option<&mut Value> {
+        match &mut self.op {
+            json_patch::PatchOperation::Add(op) => Some(&mut op.value),
+            json_patch::PatchOperation::Replace(op) => Some(&mut op.value),
+            _ => None,
+        }
+    }
+
+    fn interferes(&self, other: &MergeOp) -> bool {
+        self.touched
+            .iter()
+            .any(|a| other.touched.iter().any(|b| a.iter().zip(b).all(|(x, y)| x == y)))
+    }
+}
+
+/// Fold `next` into the ops merged so far, scanning back from the newest
+///
+/// Removes earlier ops `next` makes dead, and returns what is left of `next`
+/// to append, if anything.
+fn merge_op(merged: &mut Vec<MergeOp>, mut next: MergeOp) -> Result<Option<MergeOp>> {
+    let writes = |kind: &str| matches!(kind, "add" | "replace" | "remove");
+    let mut dead: Vec<usize> = Vec::new();
+    let mut folded = false;
+    for i in (0..merged.len()).rev() {
+        let earlier = &merged[i];
+        let (was, now) = (earlier.kind(), next.kind());
+
+        // `next` overwrites or removes the whole region `earlier` wrote
+        if matches!(now, "replace" | "remove")
+            && writes(was)
+            && earlier.path.len() > next.path.len()
+            && earlier.path.starts_with(&next.path)
+        {
+            dead.push(i);
+            continue;
+        }
+
+        if earlier.path == next.path && writes(was) {
+            // Moving the write to `next`'s position must not reorder it
+            // around ops that shift or read the same array
+            let crossed = (i + 1..merged.len()).any(|j| !dead.contains(&j) && merged[j].interferes(earlier));
+            if crossed {
+                break;
+            }
+            match (was, now) {
+                ("add" | "replace", "replace") => {
+                    dead.push(i);
+                    next = MergeOp::build(was, &next.path, next.value().cloned())?;
+                }
+                ("replace", "remove") => {
+                    dead.push(i);
+                    continue;
+                }
+                ("remove", "add") => {
+                    dead.push(i);
+                    next = MergeOp::build("replace", &next.path, next.value().cloned())?;
+                }
+                _ => {}
+            }
+            break;
+        }
+
+        // `next` lands inside a value `earlier` wrote: apply it to that value
+        if matches!(was, "add" | "replace")
+            && writes(now)
+            && next.path.len() > earlier.path.len()
+            && next.path.starts_with(&earlier.path)
+        {
+            let relative = MergeOp::build(now, &next.path[earlier.path.len()..], next.value().cloned())?;
+            let mut value = earlier.value().cloned().unwrap_or(Value::Null);
+            if json_patch::patch(&mut value, &json_patch::Patch(vec![relative.op])).is_ok() {
+                *merged[i].value_mut().expect("add and replace carry a value") = value;
+                folded = true;
+            }
+            break;
+        }
+
+        if earlier.interferes(&next) {
+            break;
+        }
+    }
+    // `dead` is in descending order
+    for i in dead {
+        merged.remove(i);
+    }
+    Ok((!folded).then_some(next))
+}
+
+fn child_path(path: &str, key: &str) -> String {
+    format!("{}/{}", path, key.replace('~', "~0").replace('/', "~1"))
+}
+
+fn serialized_len<T: Serialize>(value: &T) -> usize {
+    serde_json::to_string(value).map(|s| s.len()).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merkle::MerkleChain;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+    use serde_json::json;
+
+    fn optimized(prev: &Value, current: &Value, strategy: ArrayStrategy) -> (Vec<json_patch::PatchOperation>, DiffStats) {
+        let options = DiffOptions { array_strategy: strategy, ..Default::default() };
+        let (ops, stats) = DeltaEngine::compute_delta_optimized(prev, current, &options).unwrap();
+
+        let mut applied = prev.clone();
+        DeltaEngine::apply_delta(&mut applied, &ops).unwrap();
+        assert_eq!(&applied, current);
+
+        (ops, stats)
+    }
+
+    #[test]
+    fn test_optimized_round_trips() {
+        let cases = [
+            (json!({"a": [1, 2, 3], "b": {"c": "x"}}), json!({"a": [0, 1, 3, 4], "b": {"d": "y"}})),
+            (json!([{"id": 1, "v": "a"}, {"id": 2, "v": "b"}]), json!([{"id": 2, "v": "b"}, {"id": 1, "v": "c"}])),
+            (json!({"k/ey": {"t~": [1]}}), json!({"k/ey": {"t~": [1, 2]}})),
+            (json!({"a": [1, 2, 3]}), json!({"a": []})),
+            (json!(1), json!({"a": 1})),
+        ];
+        let strategies = [
+            ArrayStrategy::Lcs,
+            ArrayStrategy::Replace,
+            ArrayStrategy::ElementWise,
+            ArrayStrategy::default(),
+        ];
+
+        for (prev, current) in &cases {
+            for strategy in &strategies {
+                optimized(prev, current, strategy.clone());
+            }
+        }
+    }
+
+    #[test]
+    fn test_auto_prefers_lcs_for_small_edits() {
+        let prev = json!({"log": (0..50).map(|i| format!("entry number {}", i)).collect::<Vec<_>>()});
+        let mut current = prev.clone();
+        current["log"].as_array_mut().unwrap().insert(0, json!("new first entry"));
+
+        let (ops, stats) = optimized(&prev, &current, ArrayStrategy::default());
+        assert_eq!(ops.len(), 1);
+        assert_eq!(stats, DiffStats { lcs_arrays: 1, ..Default::default() });
+    }
+
+    #[test]
+    fn test_auto_prefers_replace_for_rewrites() {
+        let prev = json!({"items": (0..40).collect::<Vec<_>>()});
+        let current = json!({"items": (100..140).rev().collect::<Vec<_>>()});
+
+        let (lcs_ops, _) = optimized(&prev, &current, ArrayStrategy::Lcs);
+        assert_eq!(lcs_ops.len(), 40);
+
+        let (ops, stats) = optimized(&prev, &current, ArrayStrategy::default());
+        assert_eq!(ops.len(), 1);
+        assert_eq!(stats, DiffStats { replaced_arrays: 1, ..Default::default() });
+    }
+
+    #[test]
+    fn test_element_wise_matches_positional_diff() {
+        let prev = json!({"items": (0..10_000).collect::<Vec<_>>(), "tail": [1, 2, 3]});
+        let mut current = prev.clone();
+        current["items"][5000] = json!("changed");
+        current["tail"] = json!([1]);
+
+        let (ops, stats) = optimized(&prev, &current, ArrayStrategy::ElementWise);
+        assert_eq!(ops.len(), 3);
+        assert_eq!(stats, DiffStats { positional_arrays: 2, ..Default::default() });
+
+        // An insert at the front shifts every element
+        current["items"].as_array_mut().unwrap().insert(0, json!(-1));
+        let (ops, _) = optimized(&prev, &current, ArrayStrategy::ElementWise);
+        assert_eq!(ops.len(), 10_001 + 2);
+        let (ops, stats) = optimized(&prev, &current, ArrayStrategy::Replace);
+        assert_eq!((ops.len(), stats.replaced_arrays), (2, 2));
+
+        let options: DiffOptions = serde_json::from_value(json!({"array_strategy": "element_wise"})).unwrap();
+        assert_eq!(options.array_strategy, ArrayStrategy::ElementWise);
+        let options: DiffOptions = serde_json::from_value(json!({"array_strategy": "by_id", "key": "id"})).unwrap();
+        assert_eq!(options.array_strategy, ArrayStrategy::ById { key: "id".to_string() });
     }
 
-    /// Apply delta to a state
-    pub fn apply_delta(
-        state: &mut Value,
-        ops: &[json_patch::PatchOperation],
-    ) -> Result<()> {
-        let patch = json_patch::Patch(ops.to_vec());
-        json_patch::patch(state, &patch)?;
-        Ok(())
+    /// Ops for `prev` → `current` with array hints, and the state they produce
+    fn hinted(prev: &Value, current: &Value, hints: &[(&str, Option<&str>)]) -> (Vec<json_patch::PatchOperation>, Value) {
+        let options = DiffOptions {
+            array_keys: hints.iter().map(|(p, k)| (p.to_string(), k.map(String::from))).collect(),
+            ..Default::default()
+        };
+        let (ops, _) = DeltaEngine::compute_delta_optimized(prev, current, &options).unwrap();
+        let mut applied = prev.clone();
+        DeltaEngine::apply_delta(&mut applied, &ops).unwrap();
+        (ops, applied)
     }
 
-    /// Compute hash of delta operations
-    pub fn hash_delta(ops: &[json_patch::PatchOperation]) -> Result<Hash> {
-        let delta_value = serde_json::to_value(ops)?;
-        let canonical = Canonicalizer::canonicalize(&delta_value)?;
-        
-        let mut hasher = Sha3_256::new();
-        hasher.update(&canonical);
-        let hash = hasher.finalize();
-        
-        Ok(Hash(hex::encode(hash)))
+    #[test]
+    fn test_set_arrays_ignore_order() {
+        let prev = json!({"tags": ["b", "a", "c"], "n": 1});
+        let (ops, _) = hinted(&prev, &json!({"tags": ["c", "b", "a"], "n": 1}), &[("/tags", None)]);
+        assert!(ops.is_empty());
+
+        // A real change writes the set in canonical order
+        let (ops, applied) = hinted(&prev, &json!({"tags": ["d", "c", "a"], "n": 1}), &[("/tags", None)]);
+        assert_eq!(applied, json!({"tags": ["a", "c", "d"], "n": 1}));
+        assert!(!ops.is_empty());
+        let (ops, _) = hinted(&applied, &json!({"tags": ["c", "d", "a"], "n": 1}), &[("/tags", None)]);
+        assert!(ops.is_empty());
     }
 
-    /// Generate delta ID from hash (first 16 bytes)
-    pub fn generate_delta_id(ops: &[json_patch::PatchOperation]) -> Result<DeltaId> {
-        let delta_value = serde_json::to_value(ops)?;
-        let canonical = Canonicalizer::canonicalize(&delta_value)?;
-        
-        let mut hasher = Sha3_256::new();
-        hasher.update(&canonical);
-        let hash = hasher.finalize();
-        
-        // First 16 bytes as hex
-        let id = hex::encode(&hash[..16]);
-        Ok(DeltaId(id))
+    #[test]
+    fn test_keyed_arrays_match_elements() {
+        let hints = [("/users", Some("id")), ("/teams/*/members", Some("id"))];
+        let prev = json!({
+            "users": [{"id": "a", "name": "Ann"}, {"id": "b", "name": "Bo"}, {"id": "c", "name": "Cy"}],
+            "teams": {"core": {"members": [{"id": 1}, {"id": 2}]}}
+        });
+
+        let mut reordered = prev.clone();
+        reordered["users"].as_array_mut().unwrap().reverse();
+        reordered["teams"]["core"]["members"].as_array_mut().unwrap().reverse();
+        assert!(hinted(&prev, &reordered, &hints).0.is_empty());
+
+        // An edit to a moved element is one op at its stored position
+        reordered["users"][0]["name"] = json!("Cyrus");
+        let (ops, applied) = hinted(&prev, &reordered, &hints);
+        assert_eq!(serde_json::to_value(&ops).unwrap(), json!([{"op": "replace", "path": "/users/2/name", "value": "Cyrus"}]));
+        assert_eq!(applied["users"][2], json!({"id": "c", "name": "Cyrus"}));
+
+        // Removals keep the survivors' order; additions are appended in order
+        let current = json!({
+            "users": [{"id": "e"}, {"id": "c", "name": "Cy"}, {"id": "d"}, {"id": "a", "name": "Ann"}],
+            "teams": {"core": {"members": [{"id": 3}, {"id": 1}]}}
+        });
+        let (ops, applied) = hinted(&prev, &current, &hints);
+        assert_eq!(ops.len(), 5);
+        let ids: Vec<&Value> = applied["users"].as_array().unwrap().iter().map(|u| &u["id"]).collect();
+        assert_eq!(ids, [&json!("a"), &json!("c"), &json!("e"), &json!("d")]);
+        assert_eq!(applied["teams"]["core"]["members"], json!([{"id": 1}, {"id": 3}]));
     }
 
-    /// Compute hash of a state
-    pub fn hash_state(state: &Value) -> Result<Hash> {
-        let canonical = Canonicalizer::canonicalize(state)?;
-        
-        let mut hasher = Sha3_256::new();
-        hasher.update(&canonical);
-        let hash = hasher.finalize();
-        
-        Ok(Hash(hex::encode(hash)))
+    #[test]
+    fn test_keyed_array_errors_name_the_element() {
+        let options = DiffOptions {
+            array_keys: BTreeMap::from([("/users".to_string(), Some("id".to_string()))]),
+            ..Default::default()
+        };
+        let prev = json!({"users": [{"id": "a"}]});
+        let error = |current: Value| {
+            match DeltaEngine::compute_delta_optimized(&prev, &current, &options) {
+                Err(BmsError::InvalidArrayKey { pointer, reason }) => (pointer, reason),
+                other => panic!("expected a key error, got {:?}", other),
+            }
+        };
+
+        assert_eq!(
+            error(json!({"users": [{"id": "a"}, {"id": "b"}, {"id": "a"}]})),
+            ("/users/2".to_string(), "duplicate id \"a\"".to_string())
+        );
+        assert_eq!(error(json!({"users": [{"id": "a"}, {"name": "b"}]})).0, "/users/1");
+        assert_eq!(error(json!({"users": ["a"]})).0, "/users/0");
+
+        // A stored array that predates the hint is diffed by position
+        let (_, applied) = hinted(&json!({"users": [{"id": 1}, {"id": 1}]}), &json!({"users": [{"id": 2}, {"id": 1}]}), &[("/users", Some("id"))]);
+        assert_eq!(applied, json!({"users": [{"id": 2}, {"id": 1}]}));
     }
 
-    /// Verify delta hash matches expected
-    pub fn verify_delta_hash(
-        ops: &[json_patch::PatchOperation],
-        expected_hash: &Hash,
-    ) -> Result<()> {
-        let actual_hash = Self::hash_delta(ops)?;
-        
-        if actual_hash.0 != expected_hash.0 {
-            return Err(BmsError::HashMismatch {
-                expected: expected_hash.0.clone(),
-                actual: actual_hash.0,
-            });
-        }
-        
-        Ok(())
+    #[test]
+    fn test_by_id_matches_every_keyed_array() {
+        let by_id = ArrayStrategy::ById { key: "id".to_string() };
+        let options = DiffOptions { array_strategy: by_id, ..Default::default() };
+        let diff = |prev: &Value, current: &Value| {
+            let (ops, stats) = DeltaEngine::compute_delta_optimized(prev, current, &options).unwrap();
+            let mut applied = prev.clone();
+            DeltaEngine::apply_delta(&mut applied, &ops).unwrap();
+            (serde_json::to_value(&ops).unwrap(), stats, applied)
+        };
+        let prev = json!({
+            "users": [{"id": "a", "n": 1}, {"id": "b", "n": 2}, {"id": "c", "n": 3}],
+            "tags": ["x", "y"]
+        });
+
+        // An insert is one add, appended after the stored elements
+        let mut current = prev.clone();
+        current["users"].as_array_mut().unwrap().insert(1, json!({"id": "d", "n": 4}));
+        let (ops, stats, applied) = diff(&prev, &current);
+        assert_eq!(ops, json!([{"op": "add", "path": "/users/3", "value": {"id": "d", "n": 4}}]));
+        assert_eq!(stats, DiffStats { keyed_arrays: 1, ..Default::default() });
+        assert_eq!(applied["users"][3]["id"], "d");
+
+        let mut current = prev.clone();
+        current["users"].as_array_mut().unwrap().remove(1);
+        let (ops, _, applied) = diff(&prev, &current);
+        assert_eq!(ops, json!([{"op": "remove", "path": "/users/1"}]));
+        assert_eq!(applied, current);
+
+        let mut current = prev.clone();
+        current["users"].as_array_mut().unwrap().rotate_left(1);
+        current["users"][0]["n"] = json!(20);
+        let (ops, _, _) = diff(&prev, &current);
+        assert_eq!(ops, json!([{"op": "replace", "path": "/users/1/n", "value": 20}]));
+
+        // Arrays without the key fall back to LCS
+        let mut current = prev.clone();
+        current["tags"] = json!(["w", "x", "y"]);
+        let (ops, stats, _) = diff(&prev, &current);
+        assert_eq!(ops, json!([{"op": "add", "path": "/tags/0", "value": "w"}]));
+        assert_eq!(stats, DiffStats { lcs_arrays: 1, ..Default::default() });
     }
 
-    /// Calculate compression ratio
-    pub fn compression_ratio(original: &Value, delta_ops: &[json_patch::PatchOperation]) -> f64 {
-        let original_size = serde_json::to_string(original).unwrap_or_default().len();
-        let delta_size = serde_json::to_string(delta_ops).unwrap_or_default().len();
-        
-        if original_size == 0 {
-            return 0.0;
-        }
-        
-        1.0 - (delta_size as f64 / original_size as f64)
+    #[test]
+    fn test_one_insert_into_a_large_keyed_array_is_one_add() {
+        let item = |i: usize| json!({"id": format!("item-{}", i), "name": format!("Item {}", i), "qty": i % 7});
+        let prev = json!({"items": (0..1_000).map(item).collect::<Vec<_>>()});
+        let mut current = prev.clone();
+        current["items"].as_array_mut().unwrap().insert(1, item(1_000));
+
+        let (ops, _) = optimized(&prev, &current, ArrayStrategy::Lcs);
+        let added = json!([{"op": "add", "path": "/items/1", "value": item(1_000)}]);
+        assert_eq!(serde_json::to_value(&ops).unwrap(), added);
+
+        // Matched by id, the new element is appended instead
+        let by_id = ArrayStrategy::ById { key: "id".to_string() };
+        let options = DiffOptions { array_strategy: by_id, ..Default::default() };
+        let (ops, _) = DeltaEngine::compute_delta_optimized(&prev, &current, &options).unwrap();
+        let appended = json!([{"op": "add", "path": "/items/1000", "value": item(1_000)}]);
+        assert_eq!(serde_json::to_value(&ops).unwrap(), appended);
+
+        let (ops, _) = optimized(&prev, &current, ArrayStrategy::ElementWise);
+        assert_eq!(ops.len(), 999 * 3 + 1);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use serde_json::json;
+    #[test]
+    fn test_diff_options_from_metadata() {
+        let mut metadata = HashMap::new();
+        assert_eq!(DiffOptions::from_metadata(&metadata).unwrap(), None);
+
+        metadata.insert("diff".to_string(), json!({"array_strategy": "auto", "max_ops_per_array": 4}));
+        let options = DiffOptions::from_metadata(&metadata).unwrap().unwrap();
+        assert_eq!(
+            options.array_strategy,
+            ArrayStrategy::Auto { max_ops_per_array: 4, max_bytes_ratio: 1.0 }
+        );
+
+        metadata.insert("array_keys".to_string(), json!({"/users": "id", "/tags": null}));
+        let options = DiffOptions::from_metadata(&metadata).unwrap().unwrap();
+        assert_eq!(options.array_strategy, ArrayStrategy::Auto { max_ops_per_array: 4, max_bytes_ratio: 1.0 });
+        assert_eq!(options.array_keys["/users"].as_deref(), Some("id"));
+        assert_eq!(options.array_keys["/tags"], None);
+
+        metadata.insert("array_keys".to_string(), json!({"users": "id"}));
+        assert!(DiffOptions::from_metadata(&metadata).is_err());
+        metadata.remove("array_keys");
+        metadata.insert("diff".to_string(), json!({"array_strategy": "fastest"}));
+        assert!(DiffOptions::from_metadata(&metadata).is_err());
+    }
 
     #[test]
     fn test_compute_delta() {
@@ -176,4 +2292,944 @@ mod tests {
         // Delta should be significantly smaller than full object
         assert!(ratio > 0.5);
     }
+
+    #[test]
+    fn test_state_hash_forms() {
+        let pinned = DeltaEngine::hash_state(&json!({"b": null, "a": [1, 2.5, "é"]})).unwrap();
+        assert_eq!(pinned.0, "a7d226d17c60c1ee6f2fa78009d5e80bd3d1223218381a506b94d021d28b22e9");
+        let (int, float) = (json!({"n": 1}), json!({"n": 1.0}));
+        let legacy = DeltaEngine::hash_state_as(&int, CanonicalForm::Legacy).unwrap();
+        assert_eq!(DeltaEngine::hash_state(&int).unwrap(), legacy);
+        assert_ne!(DeltaEngine::hash_state(&int).unwrap(), DeltaEngine::hash_state(&float).unwrap());
+        assert_eq!(
+            DeltaEngine::hash_state_as(&int, CanonicalForm::Jcs).unwrap(),
+            DeltaEngine::hash_state_as(&float, CanonicalForm::Jcs).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_ops_statistics() {
+        let ops: Vec<json_patch::PatchOperation> = serde_json::from_value(json!([
+            {"op": "add", "path": "/a", "value": 1},
+            {"op": "add", "path": "/b", "value": [1, 2]},
+            {"op": "remove", "path": "/c"},
+            {"op": "replace", "path": "/d", "value": "x"},
+            {"op": "test", "path": "/d", "value": "x"},
+            {"op": "move", "from": "/a", "path": "/e"},
+            {"op": "copy", "from": "/b", "path": "/f"},
+        ]))
+        .unwrap();
+        let stats = DeltaEngine::ops_statistics(&ops);
+        let canonical = Canonicalizer::canonical_len(&serde_json::to_value(&ops).unwrap()).unwrap();
+        assert_eq!(
+            stats,
+            OpsStats {
+                add_count: 2,
+                remove_count: 1,
+                replace_count: 1,
+                test_count: 1,
+                move_count: 1,
+                copy_count: 1,
+                total_bytes: canonical,
+            }
+        );
+        assert_eq!(stats.op_count(), ops.len());
+
+        let mut sum = DeltaEngine::ops_statistics(&[]);
+        assert_eq!(sum, OpsStats { total_bytes: 2, ..Default::default() });
+        sum.merge(&stats);
+        sum.merge(&stats);
+        assert_eq!((sum.add_count, sum.op_count(), sum.total_bytes), (4, 14, 2 + 2 * canonical));
+    }
+
+    #[test]
+    fn test_patch_ratio_and_root_replace() {
+        let prev = json!({"doc": "first draft of the text", "v": 1});
+        let next = json!({"doc": "a wholly regenerated text", "v": 2});
+        let ops = DeltaEngine::compute_delta(&prev, &next).unwrap();
+        assert!(DeltaEngine::patch_ratio(&ops, &next).unwrap() > 1.0);
+
+        let replace = DeltaEngine::root_replace(&next).unwrap();
+        let mut state = prev.clone();
+        DeltaEngine::apply_delta(&mut state, &replace).unwrap();
+        assert_eq!(state, next);
+
+        let mut ratios = PatchRatios::default();
+        for (ratio, replaced) in [(0.05, false), (0.5, false), (0.95, true), (3.0, true)] {
+            ratios.record(ratio, replaced);
+        }
+        assert_eq!(ratios.counts, [1, 0, 0, 1, 0, 1, 1]);
+        assert_eq!((ratios.total(), ratios.full_replaces), (4, 2));
+
+        let negative = DiffOptions { full_replace_ratio: Some(-1.0), ..DiffOptions::default() };
+        assert!(negative.full_replace_ratio().is_err());
+        assert_eq!(DiffOptions::default().full_replace_ratio().unwrap(), DEFAULT_FULL_REPLACE_RATIO);
+    }
+
+    /// Deltas carrying `ops`, each the child of the one before
+    fn chain(ops: Vec<Vec<json_patch::PatchOperation>>) -> Vec<Delta> {
+        let mut parent: Option<DeltaId> = None;
+        ops.into_iter()
+            .enumerate()
+            .map(|(i, ops)| {
+                let id = DeltaId(format!("d{}", i));
+                Delta {
+                    id: id.clone(),
+                    coord_id: crate::types::CoordId("C".to_string()),
+                    parent_id: parent.replace(id),
+                    parent_hash: None,
+                    delta_hash: Hash(String::new()),
+                    chain_hash: Hash(String::new()),
+                    ops,
+                    created_at: chrono::Utc::now(),
+                    tags: None,
+                    author: None,
+                    op_authors: None,
+                    signature: None,
+                }
+            })
+            .collect()
+    }
+
+    fn patch(ops: Value) -> Vec<json_patch::PatchOperation> {
+        serde_json::from_value(ops).unwrap()
+    }
+
+    /// Merge `deltas`, check the result against replaying them on `initial`,
+    /// and return the merged ops as JSON
+    fn merged(initial: &Value, deltas: &[Delta]) -> Value {
+        let ops = DeltaEngine::merge_deltas(deltas).unwrap();
+        let mut replayed = initial.clone();
+        for delta in deltas {
+            DeltaEngine::apply_delta(&mut replayed, &delta.ops).unwrap();
+        }
+        let mut applied = initial.clone();
+        DeltaEngine::apply_delta(&mut applied, &ops).unwrap();
+        assert_eq!(applied, replayed);
+        serde_json::to_value(ops).unwrap()
+    }
+
+    #[test]
+    fn test_merge_collapses_writes_to_one_path() {
+        let initial = json!({"status": "new", "tags": ["a", "b"], "n": 1});
+        let deltas = chain(vec![
+            patch(json!([{"op": "replace", "path": "/status", "value": "open"}])),
+            patch(json!([{"op": "remove", "path": "/n"}, {"op": "replace", "path": "/status", "value": "closed"}])),
+            patch(json!([{"op": "add", "path": "/n", "value": 2}])),
+        ]);
+        assert_eq!(
+            merged(&initial, &deltas),
+            json!([
+                {"op": "replace", "path": "/status", "value": "closed"},
+                {"op": "replace", "path": "/n", "value": 2},
+            ])
+        );
+
+        // The same holds for an element position, with nothing shifting it
+        let deltas = chain(vec![
+            patch(json!([{"op": "remove", "path": "/tags/1"}])),
+            patch(json!([{"op": "add", "path": "/tags/1", "value": "c"}])),
+        ]);
+        assert_eq!(merged(&initial, &deltas), json!([{"op": "replace", "path": "/tags/1", "value": "c"}]));
+
+        // Replacing then removing leaves only the removal
+        let deltas = chain(vec![
+            patch(json!([{"op": "replace", "path": "/n", "value": 5}])),
+            patch(json!([{"op": "remove", "path": "/n"}])),
+        ]);
+        assert_eq!(merged(&initial, &deltas), json!([{"op": "remove", "path": "/n"}]));
+    }
+
+    #[test]
+    fn test_merge_drops_and_folds_nested_writes() {
+        let initial = json!({"profile": {"name": "a", "langs": ["rust"]}});
+        let deltas = chain(vec![
+            patch(json!([{"op": "replace", "path": "/profile/name", "value": "b"}])),
+            patch(json!([{"op": "add", "path": "/profile/langs/-", "value": "go"}])),
+            patch(json!([{"op": "replace", "path": "/profile", "value": {"name": "c"}}])),
+            patch(json!([{"op": "add", "path": "/profile/langs", "value": ["zig"]}])),
+            patch(json!([{"op": "add", "path": "/profile/langs/0", "value": "c"}])),
+        ]);
+        assert_eq!(
+            merged(&initial, &deltas),
+            json!([{"op": "replace", "path": "/profile", "value": {"name": "c", "langs": ["c", "zig"]}}])
+        );
+    }
+
+    #[test]
+    fn test_merge_keeps_ops_across_shifts() {
+        let initial = json!({"items": [1, 2, 3]});
+        // Removing /items/0 shifts the element the replaces address
+        let deltas = chain(vec![
+            patch(json!([{"op": "replace", "path": "/items/1", "value": 20}])),
+            patch(json!([{"op": "remove", "path": "/items/0"}])),
+            patch(json!([{"op": "replace", "path": "/items/1", "value": 30}])),
+        ]);
+        assert_eq!(merged(&initial, &deltas).as_array().unwrap().len(), 3);
+
+        // A copy reads the value before it is overwritten
+        let deltas = chain(vec![
+            patch(json!([{"op": "replace", "path": "/items/0", "value": 9}])),
+            patch(json!([{"op": "copy", "from": "/items/0", "path": "/first"}])),
+            patch(json!([{"op": "replace", "path": "/items/0", "value": 7}])),
+        ]);
+        assert_eq!(merged(&initial, &deltas).as_array().unwrap().len(), 3);
+    }
+
+    fn random_value(rng: &mut ChaCha8Rng, depth: u32) -> Value {
+        match rng.gen_range(0..if depth == 0 { 2 } else { 4 }) {
+            0 => json!(rng.gen_range(0..5)),
+            1 => json!(["x", "y", "z"][rng.gen_range(0..3)]),
+            2 => Value::Array((0..rng.gen_range(0..5)).map(|_| random_value(rng, depth - 1)).collect()),
+            _ => Value::Object(
+                (0..rng.gen_range(0..4))
+                    .map(|_| (format!("k{}", rng.gen_range(0..4)), random_value(rng, depth - 1)))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Change a few values somewhere in `value`
+    fn mutate(rng: &mut ChaCha8Rng, value: &mut Value, depth: u32) {
+        match value {
+            Value::Object(map) if rng.gen_bool(0.8) => {
+                let key = format!("k{}", rng.gen_range(0..4));
+                match (map.get_mut(&key), rng.gen_range(0..3)) {
+                    (Some(child), 0) => mutate(rng, child, depth + 1),
+                    (Some(_), 1) => {
+                        map.remove(&key);
+                    }
+                    _ => {
+                        map.insert(key, random_value(rng, 2));
+                    }
+                }
+            }
+            Value::Array(items) if rng.gen_bool(0.8) => match rng.gen_range(0..3) {
+                0 if !items.is_empty() => {
+                    let i = rng.gen_range(0..items.len());
+                    mutate(rng, &mut items[i], depth + 1);
+                }
+                1 if !items.is_empty() => {
+                    items.remove(rng.gen_range(0..items.len()));
+                }
+                _ => items.insert(rng.gen_range(0..=items.len()), random_value(rng, 1)),
+            },
+            _ => *value = random_value(rng, 3u32.saturating_sub(depth)),
+        }
+    }
+
+    #[test]
+    fn test_merge_round_trips_random_chains() {
+        let mut rng = ChaCha8Rng::seed_from_u64(1501);
+        let lcs = DiffOptions { array_strategy: ArrayStrategy::Lcs, ..Default::default() };
+        let mut total_ops = 0;
+        let mut merged_ops = 0;
+        for _ in 0..300 {
+            let initial = json!({"k0": random_value(&mut rng, 3), "k1": [1, 2, 3, 4]});
+            let mut state = initial.clone();
+            let mut ops = Vec::new();
+            for _ in 0..rng.gen_range(1..8) {
+                let mut next = state.clone();
+                for _ in 0..rng.gen_range(1..4) {
+                    mutate(&mut rng, &mut next, 0);
+                }
+                let delta = if rng.gen_bool(0.5) {
+                    DeltaEngine::compute_delta_optimized(&state, &next, &lcs).unwrap().0
+                } else {
+                    DeltaEngine::compute_delta(&state, &next).unwrap()
+                };
+                total_ops += delta.len();
+                ops.push(delta);
+                state = next;
+            }
+            merged_ops += merged(&initial, &chain(ops)).as_array().unwrap().len();
+        }
+        assert!(merged_ops < total_ops, "{} merged ops from {}", merged_ops, total_ops);
+    }
+
+    #[test]
+    fn test_merge_needs_a_contiguous_chain() {
+        let mut deltas = chain(vec![patch(json!([])), patch(json!([])), patch(json!([]))]);
+        assert!(DeltaEngine::merge_deltas(&deltas).unwrap().is_empty());
+        assert!(DeltaEngine::merge_deltas(&[]).unwrap().is_empty());
+        deltas.remove(1);
+        assert!(matches!(DeltaEngine::merge_deltas(&deltas), Err(BmsError::InvalidState(_))));
+    }
+
+    #[test]
+    fn test_replay_to_follows_parent_links() {
+        let mut deltas = chain(vec![
+            patch(json!([{"op": "add", "path": "/v", "value": 1}])),
+            patch(json!([{"op": "replace", "path": "/v", "value": 2}])),
+            patch(json!([{"op": "add", "path": "/w", "value": true}])),
+        ]);
+        deltas.reverse();
+        let at = |i: usize| DeltaEngine::replay_to(&deltas, &DeltaId(format!("d{}", i))).unwrap();
+        assert_eq!(at(0), json!({"v": 1}));
+        assert_eq!(at(1), json!({"v": 2}));
+        assert_eq!(at(2), json!({"v": 2, "w": true}));
+
+        let missing = DeltaId("nope".to_string());
+        assert!(matches!(DeltaEngine::replay_to(&deltas, &missing), Err(BmsError::DeltaNotFound(id)) if id == "nope"));
+        // Without the middle delta the chain to d2 is broken at its parent
+        deltas.remove(1);
+        assert!(matches!(
+            DeltaEngine::replay_to(&deltas, &DeltaId("d2".to_string())),
+            Err(BmsError::DeltaNotFound(id)) if id == "d1"
+        ));
+    }
+
+    #[test]
+    fn test_invert_delta_undoes_every_op() {
+        let before = json!({"name": "a", "tags": ["x", "y"], "meta": {"n": 1}, "old": true});
+        let ops = patch(json!([
+            {"op": "replace", "path": "/name", "value": "b"},
+            {"op": "add", "path": "/tags/-", "value": "z"},
+            {"op": "add", "path": "/tags/0", "value": "w"},
+            {"op": "remove", "path": "/old"},
+            {"op": "add", "path": "/meta/n", "value": 2},
+            {"op": "add", "path": "/meta/m", "value": {"deep": [1]}},
+            {"op": "copy", "from": "/meta", "path": "/copied"},
+            {"op": "move", "from": "/tags/1", "path": "/meta/n"},
+            {"op": "move", "from": "/tags/0", "path": "/tags/-"},
+            {"op": "test", "path": "/name", "value": "b"},
+        ]));
+        let mut after = before.clone();
+        DeltaEngine::apply_delta(&mut after, &ops).unwrap();
+
+        let inverse = DeltaEngine::invert_delta(&ops, &before).unwrap();
+        let mut undone = after.clone();
+        DeltaEngine::apply_delta(&mut undone, &inverse).unwrap();
+        assert_eq!(undone, before);
+
+        // Inverting the inverse redoes the change
+        let redo = DeltaEngine::invert_delta(&inverse, &after).unwrap();
+        DeltaEngine::apply_delta(&mut undone, &redo).unwrap();
+        assert_eq!(undone, after);
+
+        let root = patch(json!([{"op": "replace", "path": "", "value": [1]}]));
+        assert_eq!(
+            serde_json::to_value(DeltaEngine::invert_delta(&root, &before).unwrap()).unwrap(),
+            json!([{"op": "replace", "path": "", "value": before}])
+        );
+    }
+
+    #[test]
+    fn test_invert_delta_needs_the_paths_it_removes() {
+        let before = json!({"a": 1});
+        for ops in [
+            json!([{"op": "remove", "path": "/b"}]),
+            json!([{"op": "replace", "path": "/a/x", "value": 2}]),
+            json!([{"op": "move", "from": "/b", "path": "/c"}]),
+        ] {
+            assert!(matches!(
+                DeltaEngine::invert_delta(&patch(ops), &before),
+                Err(BmsError::DeltaCompression(_))
+            ));
+        }
+
+        // Computed diffs invert back to where they started
+        let pairs = [
+            (json!({"a": [1, 2, 3], "b": {"c": "x"}}), json!({"a": [0, 1, 3, 4], "b": {"d": "y"}})),
+            (json!([{"id": 1}, {"id": 2}]), json!([{"id": 2}, {"id": 1, "v": 3}])),
+            (json!({"k/ey": {"t~": [1]}}), json!({"k/ey": {"t~": [1, 2]}})),
+        ];
+        let lcs = DiffOptions { array_strategy: ArrayStrategy::Lcs, ..Default::default() };
+        for (before, after) in pairs {
+            let (ops, _) = DeltaEngine::compute_delta_optimized(&before, &after, &lcs).unwrap();
+            let mut undone = after.clone();
+            DeltaEngine::apply_delta(&mut undone, &DeltaEngine::invert_delta(&ops, &before).unwrap()).unwrap();
+            assert_eq!(undone, before);
+        }
+    }
+
+    fn partial(state: &Value, ops: Value, prefix: &str) -> Result<Value> {
+        let mut state = state.clone();
+        let prefix = jsonptr::Pointer::parse(prefix).unwrap();
+        DeltaEngine::apply_partial(&mut state, &patch(ops), &prefix)?;
+        Ok(state)
+    }
+
+    #[test]
+    fn test_stored_ops_round_trip() {
+        let ops: Vec<json_patch::PatchOperation> = serde_json::from_value(json!([
+            {"op": "add", "path": "/a~1b", "value": {"n": 1.5, "list": [true, null, "x"]}},
+            {"op": "move", "from": "/c", "path": "/d/-"},
+            {"op": "test", "path": "", "value": -7},
+        ]))
+        .unwrap();
+        let hash = DeltaEngine::hash_delta(&ops).unwrap();
+        let encoded = DeltaEngine::encode_ops(&ops).unwrap();
+        let values = DeltaEngine::decode_op_values(&encoded).unwrap();
+        assert_eq!(serde_json::to_value(&ops).unwrap(), Value::Array(values));
+
+        // Rows written as JSON still decode, whatever the build writes
+        let json_row = serde_json::to_vec(&ops).unwrap();
+        assert_eq!(DeltaEngine::decode_op_values(&json_row).unwrap().len(), 3);
+        assert!(DeltaEngine::decode_op_values(b"{}").is_err());
+        assert_eq!(DeltaEngine::stored_encoding(&json_row), DeltaEncoding::Json);
+        assert_eq!(DeltaEngine::stored_encoding(&encoded), DeltaEncoding::default());
+        assert_eq!(DeltaEngine::decode_ops(&json_row).unwrap(), ops);
+        assert_eq!("json".parse::<DeltaEncoding>().unwrap(), DeltaEncoding::Json);
+        assert!("msgpack".parse::<DeltaEncoding>().is_err());
+
+        #[cfg(feature = "cbor-ops")]
+        {
+            let cbor = DeltaEngine::encode_ops_as(&ops, "cbor".parse().unwrap()).unwrap();
+            assert_eq!(cbor, DeltaEngine::ops_to_cbor(&ops).unwrap());
+            assert!(cbor.len() < json_row.len());
+            let decoded = DeltaEngine::decode_ops(&cbor).unwrap();
+            assert_eq!(DeltaEngine::hash_delta(&decoded).unwrap(), hash);
+        }
+        #[cfg(not(feature = "cbor-ops"))]
+        assert!("cbor".parse::<DeltaEncoding>().is_err());
+
+        // A flag byte frames JSON whether or not this build compresses
+        let framed = [&[OPS_FLAG_JSON][..], &json_row].concat();
+        assert_eq!(DeltaEngine::decompress_ops(&framed).unwrap(), ops);
+        assert!(DeltaEngine::decompress_ops(&json_row).is_err());
+        assert!(DeltaEngine::decode_op_values(&[OPS_FLAG_JSON, OPS_FLAG_JSON, b'[', b']']).is_err());
+        #[cfg(not(feature = "compressed-ops"))]
+        assert!(DeltaEngine::decode_ops(&[OPS_FLAG_ZSTD_JSON, 0x28, 0xb5]).is_err());
+        assert_eq!(DeltaEngine::hash_delta(&ops).unwrap(), hash);
+    }
+
+    #[test]
+    fn test_delta_with_tests_asserts_previous_values() {
+        let prev = json!({"name": "ada", "tags": ["a", "b", "c"], "old": 1, "n": {"x": 1}});
+        let current = json!({"name": "grace", "tags": ["a", "c"], "new": 2, "n": {"x": 1}});
+        let ops = DeltaEngine::compute_delta_with_tests(&prev, &current).unwrap();
+        let tests: Vec<String> = ops
+            .iter()
+            .filter(|op| matches!(op, json_patch::PatchOperation::Test(_)))
+            .map(|op| op.path().to_string())
+            .collect();
+        let plain = DeltaEngine::compute_delta(&prev, &current).unwrap();
+        assert_eq!(ops.len(), tests.len() + plain.len());
+        assert!(tests.contains(&"/name".to_string()) && tests.contains(&"/old".to_string()));
+        assert!(!tests.contains(&"/new".to_string()) && !tests.contains(&"/n".to_string()));
+
+        let mut applied = prev.clone();
+        DeltaEngine::apply_delta(&mut applied, &ops).unwrap();
+        assert_eq!(applied, current);
+        DeltaEngine::verify_patch_preconditions(&prev, &ops).unwrap();
+
+        // Another writer renamed in between: the patch is refused, untouched
+        let mut moved = prev.clone();
+        moved["name"] = json!("ada l.");
+        let index = tests.iter().position(|p| p == "/name").unwrap();
+        match DeltaEngine::verify_patch_preconditions(&moved, &ops) {
+            Err(BmsError::PatchRejected { index: i, path, .. }) => assert_eq!((i, path.as_str()), (index, "/name")),
+            other => panic!("expected PatchRejected, got {:?}", other),
+        }
+        let before = moved.clone();
+        assert!(DeltaEngine::apply_delta(&mut moved, &ops).is_err());
+        assert_eq!(moved, before);
+
+        // Guards are left to apply_guarded
+        let hash = Hash("0".repeat(64));
+        let guarded = DeltaEngine::compute_delta_with_guard(&prev, &current, &hash).unwrap();
+        DeltaEngine::verify_patch_preconditions(&prev, &guarded).unwrap();
+    }
+
+    #[cfg(feature = "compressed-ops")]
+    #[test]
+    fn test_compressed_ops_round_trip() {
+        let rows: Vec<Value> =
+            (0..64).map(|i| json!({"id": i, "name": format!("row {}", i), "tags": ["a", "b"]})).collect();
+        let large = DeltaEngine::compute_delta(&json!({"rows": []}), &json!({"rows": rows})).unwrap();
+        let hash = DeltaEngine::hash_delta(&large).unwrap();
+        let plain = serde_json::to_vec(&large).unwrap();
+
+        let compressed = DeltaEngine::compress_ops(&large).unwrap();
+        assert!(compressed.len() < plain.len() / 4);
+        assert_eq!(compressed, DeltaEngine::encode_ops(&large).unwrap());
+        let decoded = DeltaEngine::decompress_ops(&compressed).unwrap();
+        assert_eq!(DeltaEngine::hash_delta(&decoded).unwrap(), hash);
+        assert_eq!(DeltaEngine::stored_encoding(&compressed), DeltaEncoding::default());
+
+        let as_json = DeltaEngine::encode_ops_as(&large, DeltaEncoding::Json).unwrap();
+        assert_eq!(as_json[0], OPS_FLAG_ZSTD_JSON);
+        assert_eq!(DeltaEngine::decode_ops(&as_json).unwrap(), large);
+
+        // Small patches are not worth a zstd frame
+        let small = DeltaEngine::compute_delta(&json!({"n": 1}), &json!({"n": 2})).unwrap();
+        let stored = DeltaEngine::encode_ops_as(&small, DeltaEncoding::Json).unwrap();
+        assert_eq!(stored, serde_json::to_vec(&small).unwrap());
+        assert!(DeltaEngine::decode_ops(&[OPS_FLAG_ZSTD_JSON, 0x28, 0xb5]).is_err());
+    }
+
+    #[test]
+    fn test_guarded_delta_rejects_an_interleaved_writer() {
+        let base = json!({"count": 1, "owner": "ada"});
+        let base_ops = DeltaEngine::compute_delta(&json!({}), &base).unwrap();
+        let mut head = (base.clone(), DeltaEngine::hash_delta(&base_ops).unwrap());
+
+        // Both writers read the same head and diff against it
+        let read = head.clone();
+        let guarded =
+            |head: &(Value, Hash), next: Value| DeltaEngine::compute_delta_with_guard(&head.0, &next, &head.1);
+        let first = guarded(&read, json!({"count": 2, "owner": "ada"})).unwrap();
+        let second = guarded(&read, json!({"count": 1, "owner": "bob"})).unwrap();
+        assert!(matches!(&first[0], json_patch::PatchOperation::Test(t) if t.path == HEAD_HASH_POINTER));
+
+        let commit = |head: &mut (Value, Hash), ops: &[json_patch::PatchOperation]| -> Result<()> {
+            DeltaEngine::apply_guarded(&mut head.0, ops, &head.1)?;
+            head.1 = MerkleChain::compute_chain_hash(&head.1, &DeltaEngine::hash_delta(&ops[1..])?);
+            Ok(())
+        };
+        commit(&mut head, &first).unwrap();
+        let stale = commit(&mut head, &second).unwrap_err();
+        assert!(matches!(&stale, BmsError::PreconditionFailed { expected, .. } if *expected == read.1.0), "{}", stale);
+        assert_eq!(head.0, json!({"count": 2, "owner": "ada"}));
+
+        // Re-read and retry: both changes survive
+        let retry = guarded(&head, json!({"count": 2, "owner": "bob"})).unwrap();
+        commit(&mut head, &retry).unwrap();
+        assert_eq!(head.0, json!({"count": 2, "owner": "bob"}));
+
+        // Unguarded ops, and states that happen to have the member, are not special
+        let mut state = json!({"_bms_head_hash": "x"});
+        DeltaEngine::apply_guarded(&mut state, &base_ops, &read.1).unwrap();
+        assert_eq!(state["owner"], "ada");
+    }
+
+    #[test]
+    fn test_describe_ops() {
+        let ops: Vec<json_patch::PatchOperation> = serde_json::from_value(json!([
+            {"op": "add", "path": "/a~1b", "value": 1},
+            {"op": "add", "path": "/items/-", "value": 2},
+            {"op": "replace", "path": "/title", "value": "t"},
+            {"op": "replace", "path": "/title", "value": "u"},
+            {"op": "remove", "path": "/old"},
+            {"op": "move", "from": "/tmp", "path": "/kept"},
+            {"op": "test", "path": "/n", "value": 1},
+            {"op": "copy", "from": "/kept", "path": "/again"},
+        ]))
+        .unwrap();
+        let summary = DeltaEngine::describe_ops(&ops);
+        assert_eq!(summary.added_paths, ["/a~1b", "/items/-", "/kept", "/again"]);
+        assert_eq!(summary.removed_paths, ["/old", "/tmp"]);
+        assert_eq!(summary.modified_paths, ["/title"]);
+        assert_eq!(summary.text_summary, "4 fields added, 2 removed, 1 modified");
+
+        assert_eq!(DeltaEngine::describe_ops(&ops[..1]).text_summary, "1 field added, 0 removed, 0 modified");
+        assert_eq!(DeltaEngine::describe_ops(&ops[6..7]).text_summary, "no changes");
+        let root = DeltaEngine::root_replace(&json!({"a": 1})).unwrap();
+        assert_eq!(DeltaEngine::describe_ops(&root).modified_paths, [""]);
+    }
+
+    #[test]
+    fn test_detect_conflicts() {
+        let ops = |v: Value| -> Vec<json_patch::PatchOperation> { serde_json::from_value(v).unwrap() };
+        let a = ops(json!([
+            {"op": "replace", "path": "/title", "value": "A"},
+            {"op": "remove", "path": "/tags/0"},
+            {"op": "add", "path": "/meta/x", "value": 1},
+            {"op": "add", "path": "/items/-", "value": 1},
+            {"op": "replace", "path": "/cfg", "value": {}},
+            {"op": "remove", "path": "/gone"},
+            {"op": "test", "path": "/n", "value": 1},
+            {"op": "copy", "from": "/src", "path": "/dst"},
+        ]));
+        let b = ops(json!([
+            {"op": "replace", "path": "/title", "value": "B"},
+            {"op": "replace", "path": "/tags/1", "value": "t"},
+            {"op": "add", "path": "/meta/x", "value": 2},
+            {"op": "add", "path": "/items/-", "value": 1},
+            {"op": "replace", "path": "/cfg/net", "value": "lan"},
+            {"op": "remove", "path": "/gone"},
+            {"op": "replace", "path": "/n", "value": 2},
+            {"op": "replace", "path": "/src", "value": 0},
+        ]));
+        let found: Vec<(String, ConflictKind)> = DeltaEngine::detect_conflicts(&a, &b)
+            .into_iter()
+            .map(|c| (c.path.to_string(), c.kind))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("/cfg/net".to_string(), ConflictKind::BothModified),
+                ("/items".to_string(), ConflictKind::AddedAtSamePath),
+                ("/meta/x".to_string(), ConflictKind::AddedAtSamePath),
+                ("/tags/1".to_string(), ConflictKind::OneRemovedOtherModified),
+                ("/title".to_string(), ConflictKind::BothModified),
+            ]
+        );
+        assert!(DeltaEngine::detect_conflicts(&a[..1], &b[1..]).is_empty());
+    }
+
+    #[test]
+    fn test_merge_with_conflict_resolution() {
+        let ops = |v: Value| -> Vec<json_patch::PatchOperation> { serde_json::from_value(v).unwrap() };
+        let base = json!({"title": "t", "tags": ["a", "b"], "cfg": {"x": 1, "y": 1}, "n": 1});
+        let a = ops(json!([
+            {"op": "replace", "path": "/title", "value": "A"},
+            {"op": "add", "path": "/tags/-", "value": "c"},
+            {"op": "replace", "path": "/cfg/x", "value": 2},
+        ]));
+        let b = ops(json!([
+            {"op": "replace", "path": "/title", "value": "B"},
+            {"op": "remove", "path": "/tags/0"},
+            {"op": "replace", "path": "/cfg/y", "value": 2},
+            {"op": "add", "path": "/extra", "value": true},
+        ]));
+        let merge = |strategy| DeltaEngine::merge_with_conflict_resolution(&base, &a, &b, strategy);
+
+        let expected = json!({"title": "B", "tags": ["b"], "cfg": {"x": 2, "y": 2}, "n": 1, "extra": true});
+        assert_eq!(merge(MergeStrategy::LastWriteWins).unwrap(), expected);
+        let mut union = expected.clone();
+        union["tags"] = json!(["b", "c"]);
+        assert_eq!(merge(MergeStrategy::UnionArrays).unwrap(), union);
+        match merge(MergeStrategy::FailOnConflict) {
+            Err(BmsError::MergeConflict { paths }) => assert_eq!(paths, ["/tags", "/title"]),
+            other => panic!("{:?}", other),
+        }
+        assert!(DeltaEngine::merge_with_conflict_resolution(&json!({}), &a, &b, MergeStrategy::LastWriteWins).is_err());
+    }
+
+    #[test]
+    fn test_merge_without_conflicts_is_sequential_application() {
+        let mut rng = ChaCha8Rng::seed_from_u64(1505);
+        let lcs = DiffOptions { array_strategy: ArrayStrategy::Lcs, ..Default::default() };
+        let mut clean = 0;
+        for _ in 0..500 {
+            let base = json!({"k0": random_value(&mut rng, 3), "k1": random_value(&mut rng, 3), "k2": [1, 2, 3]});
+            let mut sides = Vec::new();
+            for _ in 0..2 {
+                let mut next = base.clone();
+                for _ in 0..rng.gen_range(1..3) {
+                    mutate(&mut rng, &mut next, 0);
+                }
+                sides.push(DeltaEngine::compute_delta_optimized(&base, &next, &lcs).unwrap().0);
+            }
+            let (a, b) = (&sides[0], &sides[1]);
+            let merged = DeltaEngine::merge_with_conflict_resolution(&base, a, b, MergeStrategy::FailOnConflict);
+            if DeltaEngine::detect_conflicts(a, b).is_empty() {
+                clean += 1;
+                // An op both sides made happens once
+                let rest: Vec<_> = b.iter().filter(|op| !a.contains(op)).cloned().collect();
+                let mut sequential = base.clone();
+                DeltaEngine::apply_delta(&mut sequential, a).unwrap();
+                DeltaEngine::apply_delta(&mut sequential, &rest).unwrap();
+                assert_eq!(merged.unwrap(), sequential, "{:?} then {:?}", a, b);
+            } else {
+                assert!(matches!(merged, Err(BmsError::MergeConflict { .. })));
+            }
+            for strategy in [MergeStrategy::LastWriteWins, MergeStrategy::UnionArrays] {
+                DeltaEngine::merge_with_conflict_resolution(&base, a, b, strategy).unwrap();
+            }
+        }
+        assert!(clean > 100, "{} of 500 merges had no conflicts", clean);
+    }
+
+    #[test]
+    fn test_merge_combines_nested_changes() {
+        let ops = |v: Value| -> Vec<json_patch::PatchOperation> { serde_json::from_value(v).unwrap() };
+        let base = json!({
+            "doc": {"title": "t", "meta": {"a": 1, "b": 1}},
+            "list": [{"n": 1}, {"n": 2}],
+            "tags": ["x"],
+        });
+        let ours = ops(json!([
+            {"op": "replace", "path": "/doc/meta/a", "value": 2},
+            {"op": "replace", "path": "/list/0/n", "value": 10},
+            {"op": "add", "path": "/tags/-", "value": "y"},
+            {"op": "add", "path": "/doc/new", "value": {"k": 1}},
+        ]));
+        let theirs = ops(json!([
+            {"op": "replace", "path": "/doc/meta/b", "value": 3},
+            {"op": "replace", "path": "/list/1/n", "value": 20},
+            {"op": "add", "path": "/tags/-", "value": "y"},
+            {"op": "add", "path": "/doc/new", "value": {"k": 1}},
+            {"op": "remove", "path": "/doc/title"},
+        ]));
+
+        let MergeOutcome::Merged(patch) = DeltaEngine::merge(&base, &ours, &theirs).unwrap() else {
+            panic!("expected a clean merge");
+        };
+        let mut merged = base.clone();
+        DeltaEngine::apply_delta(&mut merged, &patch).unwrap();
+        assert_eq!(
+            merged,
+            json!({
+                "doc": {"meta": {"a": 2, "b": 3}, "new": {"k": 1}},
+                "list": [{"n": 10}, {"n": 20}],
+                "tags": ["x", "y"],
+            })
+        );
+        // The same change on both sides is taken once
+        let twice = DeltaEngine::merge(&base, &theirs, &theirs).unwrap();
+        assert!(matches!(twice, MergeOutcome::Merged(p) if p.len() == 5));
+        assert!(DeltaEngine::merge(&json!({}), &ours, &theirs).is_err());
+    }
+
+    #[test]
+    fn test_merge_reports_conflicts() {
+        let ops = |v: Value| -> Vec<json_patch::PatchOperation> { serde_json::from_value(v).unwrap() };
+        let base = json!({
+            "doc": {"title": "t", "meta": {"a": 1, "b": 1}},
+            "list": [{"n": 1}, {"n": 2}],
+            "tags": ["x"],
+        });
+        let ours = ops(json!([
+            {"op": "replace", "path": "/doc/meta/a", "value": 2},
+            {"op": "remove", "path": "/doc/title"},
+            {"op": "replace", "path": "/list/1/n", "value": 5},
+            {"op": "add", "path": "/tags/-", "value": "y"},
+            {"op": "add", "path": "/added", "value": [1]},
+        ]));
+        let theirs = ops(json!([
+            {"op": "replace", "path": "/doc/meta/a", "value": 3},
+            {"op": "replace", "path": "/doc/title", "value": "u"},
+            {"op": "replace", "path": "/list/1/n", "value": 6},
+            {"op": "add", "path": "/tags/0", "value": "z"},
+            {"op": "add", "path": "/added", "value": {"n": 1}},
+            {"op": "replace", "path": "/doc/meta/b", "value": 4},
+        ]));
+
+        let MergeOutcome::Conflicts(conflicts) = DeltaEngine::merge(&base, &ours, &theirs).unwrap() else {
+            panic!("expected conflicts");
+        };
+        let found: Vec<(String, Option<Value>, Option<Value>)> =
+            conflicts.into_iter().map(|c| (c.path.to_string(), c.ours, c.theirs)).collect();
+        assert_eq!(
+            found,
+            vec![
+                ("/added".to_string(), Some(json!([1])), Some(json!({"n": 1}))),
+                ("/doc/meta/a".to_string(), Some(json!(2)), Some(json!(3))),
+                ("/doc/title".to_string(), None, Some(json!("u"))),
+                ("/list/1/n".to_string(), Some(json!(5)), Some(json!(6))),
+                // Both changed the length, so the whole array clashes
+                ("/tags".to_string(), Some(json!(["x", "y"])), Some(json!(["z", "x"]))),
+            ]
+        );
+
+        // A conflict at the root has the empty path
+        let a = ops(json!([{"op": "replace", "path": "", "value": 1}]));
+        let b = ops(json!([{"op": "replace", "path": "", "value": 2}]));
+        let MergeOutcome::Conflicts(root) = DeltaEngine::merge(&base, &a, &b).unwrap() else {
+            panic!("expected a conflict");
+        };
+        assert_eq!((root[0].path.to_string(), root.len()), (String::new(), 1));
+    }
+
+    #[test]
+    fn test_merge_agrees_with_last_write_wins() {
+        let mut rng = ChaCha8Rng::seed_from_u64(1509);
+        let mut clean = 0;
+        for _ in 0..500 {
+            let base = json!({"k0": random_value(&mut rng, 3), "k1": random_value(&mut rng, 3), "k2": [1, 2, 3]});
+            let mut sides = Vec::new();
+            for _ in 0..2 {
+                let mut next = base.clone();
+                for _ in 0..rng.gen_range(1..3) {
+                    mutate(&mut rng, &mut next, 0);
+                }
+                sides.push(DeltaEngine::compute_delta(&base, &next).unwrap());
+            }
+            let (a, b) = (&sides[0], &sides[1]);
+            let last_write =
+                DeltaEngine::merge_with_conflict_resolution(&base, a, b, MergeStrategy::LastWriteWins).unwrap();
+            match DeltaEngine::merge(&base, a, b).unwrap() {
+                MergeOutcome::Merged(patch) => {
+                    clean += 1;
+                    let mut merged = base.clone();
+                    DeltaEngine::apply_delta(&mut merged, &patch).unwrap();
+                    assert_eq!(merged, last_write);
+                }
+                MergeOutcome::Conflicts(conflicts) => {
+                    assert!(!DeltaEngine::detect_conflicts(a, b).is_empty(), "{:?} and {:?}", a, b);
+                    // Their side of every clash is what last write wins keeps
+                    for conflict in conflicts {
+                        let pointer = conflict.path.to_string();
+                        assert_eq!(last_write.pointer(&pointer), conflict.theirs.as_ref(), "at {}", pointer);
+                    }
+                }
+            }
+        }
+        assert!(clean > 100, "{} of 500 merges were clean", clean);
+    }
+
+    #[test]
+    fn test_try_apply_reports_the_failing_op() {
+        let state = json!({"a": 1, "items": [1, 2]});
+        let ops: Vec<json_patch::PatchOperation> = serde_json::from_value(json!([
+            {"op": "add", "path": "/b", "value": 2},
+            {"op": "remove", "path": "/items/0"},
+        ]))
+        .unwrap();
+        let (patched, outcomes) = DeltaEngine::try_apply(&state, &ops).unwrap();
+        assert_eq!(patched, json!({"a": 1, "b": 2, "items": [2]}));
+        assert!(outcomes.iter().all(OpOutcome::applied));
+        assert_eq!(outcomes[1].path, "/items/0");
+
+        let failing = [
+            (json!({"op": "remove", "path": "/items/5"}), "/items/5"),
+            (json!({"op": "move", "from": "/gone", "path": "/c"}), "/gone"),
+            (json!({"op": "test", "path": "/a", "value": 2}), "/a"),
+        ];
+        for (op, path) in failing {
+            let mut ops = ops.clone();
+            ops.insert(1, serde_json::from_value(op).unwrap());
+            let (unchanged, outcomes) = DeltaEngine::try_apply(&state, &ops).unwrap();
+            assert_eq!(unchanged, state);
+            assert_eq!(outcomes.len(), 2);
+            assert!(outcomes[0].applied());
+            assert_eq!((outcomes[1].index, outcomes[1].path.as_str()), (1, path));
+            let err = outcomes[1].to_error().unwrap();
+            assert!(matches!(err, BmsError::PatchRejected { index: 1, .. }), "{}", err);
+            assert!(DeltaEngine::apply_delta(&mut state.clone(), &ops).is_err());
+        }
+    }
+
+    #[test]
+    fn test_dry_run_leaves_state_untouched() {
+        let state = json!({"config": {"network": {"port": 80}, "name": "a"}, "log": [1]});
+        let ops: Vec<json_patch::PatchOperation> = serde_json::from_value(json!([
+            {"op": "replace", "path": "/config/network/port", "value": 8080},
+            {"op": "move", "from": "/log", "path": "/config/network/log"},
+        ]))
+        .unwrap();
+        let mut applied = state.clone();
+        DeltaEngine::apply_delta(&mut applied, &ops).unwrap();
+
+        assert_eq!(DeltaEngine::dry_run(&state, &ops).unwrap(), applied);
+        let network = jsonptr::Pointer::parse("/config/network").unwrap();
+        assert_eq!(
+            DeltaEngine::dry_run_partial(&state, &ops, &network).unwrap(),
+            json!({"port": 8080, "log": [1]})
+        );
+        assert_eq!(state["config"]["network"], json!({"port": 80}));
+
+        let failing: Vec<json_patch::PatchOperation> =
+            serde_json::from_value(json!([{"op": "remove", "path": "/missing"}])).unwrap();
+        assert!(matches!(DeltaEngine::dry_run(&state, &failing), Err(BmsError::DeltaCompression(_))));
+        assert!(matches!(
+            DeltaEngine::dry_run_partial(&state, &failing, &network),
+            Err(BmsError::DeltaCompression(_))
+        ));
+        let gone = jsonptr::Pointer::parse("/log").unwrap();
+        assert!(matches!(DeltaEngine::dry_run_partial(&state, &ops, &gone), Err(BmsError::InvalidState(_))));
+    }
+
+    #[test]
+    fn test_apply_partial_scopes_ops() {
+        let state = json!({"config": {"network": {"port": 80}, "disk": 1}, "items": [{"n": 0}, {"n": 1}]});
+
+        // Ops elsewhere are skipped, and ancestor writes keep only the branch
+        let ops = json!([
+            {"op": "replace", "path": "/config/disk", "value": {"huge": [1, 2, 3]}},
+            {"op": "add", "path": "/config/network/host", "value": "a"},
+            {"op": "replace", "path": "/config", "value": {"network": {"port": 81}, "other": 2}},
+            {"op": "test", "path": "/config/disk", "value": "not checked"},
+        ]);
+        let scoped = partial(&state, ops, "/config/network").unwrap();
+        assert_eq!(scoped["config"], json!({"network": {"port": 81}}));
+        assert_eq!(scoped["items"], state["items"]);
+
+        let gone = json!([{"op": "replace", "path": "/config", "value": {"disk": 2}}]);
+        assert_eq!(partial(&state, gone, "/config/network").unwrap()["config"], json!({}));
+        let appended = json!([{"op": "add", "path": "/items/-", "value": {"n": 2, "m": 3}}]);
+        assert_eq!(partial(&state, appended, "/items/2/n").unwrap().pointer("/items/2/n"), Some(&json!(2)));
+        let moved_out = json!([{"op": "move", "from": "/config/network", "path": "/net"}]);
+        assert_eq!(partial(&state, moved_out, "/config/network").unwrap().pointer("/config/network"), None);
+        let within = json!([{"op": "copy", "from": "/config/network/port", "path": "/config/network/old"}]);
+        assert_eq!(partial(&state, within, "/config/network").unwrap()["config"]["network"]["old"], 80);
+
+        // Later elements may shift; earlier ones must not
+        let later = json!([{"op": "remove", "path": "/items/1"}, {"op": "add", "path": "/items/1", "value": 5}]);
+        assert_eq!(partial(&state, later, "/items/0").unwrap()["items"][0], json!({"n": 0}));
+        for ops in [
+            json!([{"op": "add", "path": "/items/0", "value": 5}]),
+            json!([{"op": "remove", "path": "/items/0"}]),
+            json!([{"op": "remove", "path": "/items/1"}]),
+            json!([{"op": "move", "from": "/items/0", "path": "/first"}]),
+            json!([{"op": "copy", "from": "/config/disk", "path": "/items/1/n"}]),
+            json!([{"op": "move", "from": "/config/disk", "path": "/items"}]),
+        ] {
+            assert!(matches!(partial(&state, ops, "/items/1/n"), Err(BmsError::DeltaCompression(_))));
+        }
+    }
+
+    #[test]
+    fn test_apply_partial_agrees_with_full_replay() {
+        fn paths(value: &Value, at: String, out: &mut Vec<String>) {
+            match value {
+                Value::Object(map) => map.iter().for_each(|(k, v)| paths(v, child_path(&at, k), out)),
+                Value::Array(items) => {
+                    items.iter().enumerate().for_each(|(i, v)| paths(v, format!("{}/{}", at, i), out))
+                }
+                _ => {}
+            }
+            out.push(at);
+        }
+
+        let mut rng = ChaCha8Rng::seed_from_u64(1503);
+        let lcs = DiffOptions { array_strategy: ArrayStrategy::Lcs, ..Default::default() };
+        let mut scoped = 0;
+        for _ in 0..400 {
+            let initial = json!({"k0": random_value(&mut rng, 3), "k1": [1, {"k2": [2, 3]}, 4]});
+            let mut candidates = Vec::new();
+            paths(&initial, String::new(), &mut candidates);
+            let prefix = candidates[rng.gen_range(0..candidates.len())].clone();
+            let pointer = jsonptr::Pointer::parse(&prefix).unwrap();
+            let scope: Vec<String> = pointer.tokens().map(|t| t.decoded().to_string()).collect();
+
+            let mut full = initial.clone();
+            // Out-of-scope parts of the starting state are not needed
+            let mut states = [initial.clone(), prune(&initial, &scope)];
+            let mut failed = false;
+            for _ in 0..rng.gen_range(1..6) {
+                let mut next = full.clone();
+                for _ in 0..rng.gen_range(1..4) {
+                    mutate(&mut rng, &mut next, 0);
+                }
+                let ops = if rng.gen_bool(0.5) {
+                    DeltaEngine::compute_delta_optimized(&full, &next, &lcs).unwrap().0
+                } else {
+                    DeltaEngine::compute_delta(&full, &next).unwrap()
+                };
+                DeltaEngine::apply_delta(&mut full, &ops).unwrap();
+                for state in &mut states {
+                    failed |= DeltaEngine::apply_partial(state, &ops, &pointer).is_err();
+                }
+                if failed {
+                    break;
+                }
+            }
+            if !failed {
+                scoped += 1;
+                for state in &states {
+                    assert_eq!(state.pointer(&prefix), full.pointer(&prefix), "under {:?}", prefix);
+                }
+            }
+        }
+        assert!(scoped > 300, "only {} of 400 chains could be scoped", scoped);
+    }
+
+    #[test]
+    fn test_sign_and_verify_delta() {
+        let key = ed25519_dalek::SigningKey::from_bytes(&[7u8; 32]);
+        let ops = DeltaEngine::compute_delta(&json!({"a": 1}), &json!({"a": 2, "b": [1]})).unwrap();
+        let signature = DeltaEngine::sign_delta(&ops, &key).unwrap();
+        DeltaEngine::verify_signature(&ops, &signature).unwrap();
+
+        // The signed bytes are the delta hash
+        let digest = hex::decode(DeltaEngine::hash_delta(&ops).unwrap().0).unwrap();
+        let sig = ed25519_dalek::Signature::from_bytes(&signature.signature_bytes);
+        key.verifying_key().verify_strict(&digest, &sig).unwrap();
+
+        let other = DeltaEngine::compute_delta(&json!({"a": 1}), &json!({"a": 3})).unwrap();
+        assert!(matches!(DeltaEngine::verify_signature(&other, &signature), Err(BmsError::InvalidSignature(_))));
+        let mut forged = signature.clone();
+        forged.public_key = ed25519_dalek::SigningKey::from_bytes(&[8u8; 32]).verifying_key().to_bytes();
+        assert!(matches!(DeltaEngine::verify_signature(&ops, &forged), Err(BmsError::InvalidSignature(_))));
+
+        let encoded = serde_json::to_value(&signature).unwrap();
+        assert_eq!(encoded["public_key"], json!(hex::encode(signature.public_key)));
+        assert_eq!(serde_json::from_value::<DeltaSignature>(encoded).unwrap(), signature);
+        let short = json!({"public_key": "abcd", "signature_bytes": hex::encode(signature.signature_bytes)});
+        assert!(serde_json::from_value::<DeltaSignature>(short).is_err());
+    }
 }
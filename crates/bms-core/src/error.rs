@@ -19,6 +19,11 @@ pub enum BmsError {
     #[error("Merkle chain broken at delta {delta_id}")]
     MerkleChainBroken { delta_id: String },
 
+    /// A delta that does not follow from its own ops or from the delta
+    /// before it, with the link that fails
+    #[error("Chain link broken at delta {delta_id}: {link}")]
+    ChainLinkBroken { delta_id: String, link: String },
+
     #[error("Snapshot not found: {0}")]
     SnapshotNotFound(String),
 
@@ -34,6 +39,82 @@ pub enum BmsError {
     #[error("Collision detected for coordinate: {0}")]
     CoordinateCollision(String),
 
+    #[error("Precondition failed: expected head {expected}, found {actual}")]
+    PreconditionFailed {
+        expected: String,
+        actual: String,
+        /// Chain hash of the current head, if the coordinate has one
+        head_chain_hash: Option<String>,
+    },
+
+    #[error("Store group item {index} ({coord_id}) failed its precondition: expected head {expected}, found {actual}")]
+    GroupConflict {
+        /// Position of the conflicting item in the group
+        index: usize,
+        coord_id: String,
+        expected: String,
+        actual: String,
+    },
+
+    #[error("Coordinate {coord_id} is referenced by {referrers} other coordinate(s)")]
+    CoordinateReferenced { coord_id: String, referrers: usize },
+
+    #[error("Delta has {ops} ops but {op_authors} op authors")]
+    OpAuthorsMismatch { ops: usize, op_authors: usize },
+
+    #[error("Invalid delta signature: {0}")]
+    InvalidSignature(String),
+
+    #[error("Invalid keyed array element at {pointer}: {reason}")]
+    InvalidArrayKey { pointer: String, reason: String },
+
+    /// A patch op that does not apply to the state it was computed against
+    #[error("Op {index} cannot be applied at {path}: {reason}")]
+    PatchRejected { index: usize, path: String, reason: String },
+
+    /// Concurrent patches that edit the same paths, merged with
+    /// `MergeStrategy::FailOnConflict`
+    #[error("Conflicting edits at {}", paths.join(", "))]
+    MergeConflict { paths: Vec<String> },
+
+    #[error("Invalid timestamp override: {0}")]
+    InvalidTimestamp(String),
+
+    /// A key that occurs twice in one object of strictly parsed JSON, with
+    /// the JSON Pointer of that object
+    #[error("Duplicate key {key:?} in the object at {path:?}")]
+    DuplicateKey { path: String, key: String },
+
+    /// A coordinate ID prefix that more than one coordinate starts with,
+    /// with the first few of them
+    #[error("Prefix {prefix} is ambiguous; candidates: {}", candidates.join(", "))]
+    AmbiguousPrefix { prefix: String, candidates: Vec<String> },
+
+    #[error("Read-only: {0}")]
+    ReadOnly(String),
+
+    /// A delta or state outside the limits of a `DeltaPolicy`
+    #[error("Policy violation: {0}")]
+    PolicyViolation(String),
+
+    /// An integrity check failed where non-strict mode would have repaired
+    /// or fallen back
+    #[error("Integrity check failed: {0}")]
+    IntegrityViolation(String),
+
+    /// A stored row that does not decode, with the table, row, and coordinate
+    #[error("Corrupt {table} row {id} of coordinate {coord_id}: {source}")]
+    CorruptRow {
+        table: &'static str,
+        id: String,
+        coord_id: String,
+        source: Box<BmsError>,
+    },
+
+    /// A file that ends before its trailer, or whose checksum does not match
+    #[error("{path} is truncated or partial: {reason}")]
+    PartialFile { path: String, reason: String },
+
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
 
@@ -0,0 +1,154 @@
+//! Text handed to the embedding model
+//!
+//! A head is embedded as its JSON. With facets configured, the values of
+//! those coordinate metadata keys are appended after `FACET_MARKER`, one
+//! `key: value` line each, so a coordinate can be found by its topic or
+//! labels even when the body never mentions them. Object values are tag
+//! maps and become `key: name=value, ...`; arrays become comma lists.
+//!
+//! Each facet line is also embedded on its own, for searches that match a
+//! query against one facet only.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// Line between the head JSON and the appended facets
+pub const FACET_MARKER: &str = "\n--- facets ---\n";
+
+/// Which metadata keys are appended to the embedded text
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ExtractionConfig {
+    pub facets: Vec<String>,
+}
+
+/// One facet of an extracted text
+#[derive(Debug, Clone, PartialEq)]
+pub struct Facet {
+    pub key: String,
+    /// The `key: value` line, as embedded
+    pub text: String,
+}
+
+/// Text to embed for one head
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExtractedText {
+    pub text: String,
+    /// Facets present on the coordinate, in configured order
+    pub facets: Vec<Facet>,
+}
+
+impl ExtractedText {
+    /// Keys of the facets appended to the text
+    pub fn facet_keys(&self) -> Vec<String> {
+        self.facets.iter().map(|f| f.key.clone()).collect()
+    }
+}
+
+impl ExtractionConfig {
+    /// Facets from a comma-separated list of metadata keys
+    pub fn from_list(list: &str) -> Self {
+        Self {
+            facets: list
+                .split(',')
+                .map(str::trim)
+                .filter(|key| !key.is_empty())
+                .map(str::to_string)
+                .collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.facets.is_empty()
+    }
+
+    /// The head JSON, followed by the configured facets the metadata has
+    pub fn extract(&self, state: &Value, metadata: Option<&HashMap<String, Value>>) -> ExtractedText {
+        let facets: Vec<Facet> = self
+            .facets
+            .iter()
+            .filter_map(|key| {
+                let text = facet_text(key, metadata?.get(key)?)?;
+                Some(Facet { key: key.clone(), text })
+            })
+            .collect();
+
+        let mut text = serde_json::to_string(state).unwrap_or_default();
+        if !facets.is_empty() {
+            text.push_str(FACET_MARKER);
+            text.push_str(&facets.iter().map(|f| f.text.as_str()).collect::<Vec<_>>().join("\n"));
+        }
+        ExtractedText { text, facets }
+    }
+}
+
+/// `key: value` line for one facet; `None` for null or empty values
+pub fn facet_text(key: &str, value: &Value) -> Option<String> {
+    let value = match value {
+        Value::Null => return None,
+        Value::String(s) => s.clone(),
+        Value::Array(items) => items.iter().map(scalar).collect::<Vec<_>>().join(", "),
+        Value::Object(tags) => {
+            let mut pairs: Vec<String> = tags.iter().map(|(k, v)| format!("{}={}", k, scalar(v))).collect();
+            pairs.sort();
+            pairs.join(", ")
+        }
+        other => other.to_string(),
+    };
+    (!value.trim().is_empty()).then(|| format!("{}: {}", key, value))
+}
+
+fn scalar(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Body and facet section of an embedded text, for showing where the
+/// appended facets start
+pub fn split_facets(text: &str) -> (&str, Option<&str>) {
+    match text.split_once(FACET_MARKER) {
+        Some((body, facets)) => (body, Some(facets)),
+        None => (text, None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_facets_follow_the_marker() {
+        let config = ExtractionConfig::from_list("topic, labels,missing,");
+        assert_eq!(config.facets, vec!["topic", "labels", "missing"]);
+
+        let metadata: HashMap<String, Value> = serde_json::from_value(json!({
+            "topic": "database migrations",
+            "labels": {"team": "storage", "env": "prod"},
+            "owner": "ana",
+        }))
+        .unwrap();
+        let state = json!({"note": "bread recipe"});
+        let extracted = config.extract(&state, Some(&metadata));
+        assert_eq!(extracted.facet_keys(), vec!["topic", "labels"]);
+
+        let (body, facets) = split_facets(&extracted.text);
+        assert_eq!(body, r#"{"note":"bread recipe"}"#);
+        assert_eq!(facets, Some("topic: database migrations\nlabels: env=prod, team=storage"));
+
+        // Without matching metadata the text is the bare head
+        let bare = config.extract(&state, None);
+        assert!(bare.facets.is_empty());
+        assert_eq!(split_facets(&bare.text), (r#"{"note":"bread recipe"}"#, None));
+    }
+
+    #[test]
+    fn test_facet_values() {
+        assert_eq!(facet_text("tags", &json!(["a", 1])), Some("tags: a, 1".to_string()));
+        assert_eq!(facet_text("n", &json!(3)), Some("n: 3".to_string()));
+        assert_eq!(facet_text("empty", &json!("  ")), None);
+        assert_eq!(facet_text("none", &Value::Null), None);
+    }
+}
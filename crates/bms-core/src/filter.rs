@@ -0,0 +1,911 @@
+//! Filter expressions combining structural predicates
+//!
+//! A filter is a tree of `and`, `or` and `not` over leaf predicates. Its JSON
+//! form has one operator key per node:
+//!
+//! ```json
+//! {"and": [
+//!   {"eq": {"field": "/status", "value": "open"}},
+//!   {"in": {"field": "author", "values": ["alice", "bob"]}},
+//!   {"not": {"tag": "archived"}},
+//!   {"lt": {"field": "metadata.priority", "value": 3}},
+//!   {"created": {"within_days": 30}},
+//!   {"exists": "metadata.project"}
+//! ]}
+//! ```
+//!
+//! Fields are `author` (author of the head delta), `metadata.<key>` (a
+//! coordinate metadata value) or a JSON Pointer into the head state. `tag`
+//! tests the coordinate's `tags` metadata array and `created` its creation
+//! time (`after` inclusive, `before` exclusive).
+//!
+//! The CLI form of the same filter is
+//! `/status == open and author in (alice, bob) and not tag == archived and
+//! metadata.priority < 3 and created >= -30d and exists metadata.project`.
+//! `and` binds tighter than `or`, and `!=` is `not` around `==`.
+//!
+//! A missing field and a JSON `null` are the same: no comparison matches
+//! them, so `not` around a comparison matches them. Equality is only defined
+//! for strings, numbers and booleans, and numbers compare by value (`1` equals
+//! `1.0`). Relative dates are resolved once, when the filter is compiled.
+
+use crate::error::{BmsError, Result};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{json, Map, Value};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Coordinate metadata key holding the tags `tag` predicates test
+pub const TAGS_METADATA_KEY: &str = "tags";
+
+/// Deepest nesting of `and`, `or` and `not` a filter may use
+pub const MAX_DEPTH: usize = 32;
+
+/// Value a predicate reads from a record
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Field {
+    /// Author of the coordinate's head delta
+    Author,
+    /// Coordinate metadata value
+    Metadata(String),
+    /// JSON Pointer into the head state
+    State(String),
+}
+
+impl Field {
+    fn parse(text: &str) -> std::result::Result<Self, String> {
+        if text == "author" {
+            return Ok(Field::Author);
+        }
+        if let Some(key) = text.strip_prefix("metadata.") {
+            if key.is_empty() {
+                return Err("metadata field needs a key, e.g. metadata.project".to_string());
+            }
+            return Ok(Field::Metadata(key.to_string()));
+        }
+        if text.starts_with('/') {
+            return Ok(Field::State(text.to_string()));
+        }
+        Err(format!(
+            "unknown field {:?}; use author, metadata.<key> or a JSON Pointer like /status",
+            text
+        ))
+    }
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Field::Author => f.write_str("author"),
+            Field::Metadata(key) => write!(f, "metadata.{}", key),
+            Field::State(pointer) => f.write_str(pointer),
+        }
+    }
+}
+
+/// Numeric comparison
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CmpOp {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl CmpOp {
+    fn name(self) -> &'static str {
+        match self {
+            CmpOp::Lt => "lt",
+            CmpOp::Le => "le",
+            CmpOp::Gt => "gt",
+            CmpOp::Ge => "ge",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "lt" => Some(CmpOp::Lt),
+            "le" => Some(CmpOp::Le),
+            "gt" => Some(CmpOp::Gt),
+            "ge" => Some(CmpOp::Ge),
+            _ => None,
+        }
+    }
+
+    pub fn holds(self, left: f64, right: f64) -> bool {
+        match self {
+            CmpOp::Lt => left < right,
+            CmpOp::Le => left <= right,
+            CmpOp::Gt => left > right,
+            CmpOp::Ge => left >= right,
+        }
+    }
+}
+
+/// Compiled filter expression
+#[derive(Debug, Clone, PartialEq)]
+pub enum FilterExpr {
+    And(Vec<FilterExpr>),
+    Or(Vec<FilterExpr>),
+    Not(Box<FilterExpr>),
+    /// Field equals a string, number or boolean
+    Eq { field: Field, value: Value },
+    /// Field equals any of the values
+    In { field: Field, values: Vec<Value> },
+    /// Field is a number and compares to `value`
+    Cmp { field: Field, op: CmpOp, value: f64 },
+    /// Field is present and not null
+    Exists(Field),
+    /// The `tags` metadata array contains the tag
+    Tag(String),
+    /// Created at or after `after` and before `before`
+    Created {
+        after: Option<DateTime<Utc>>,
+        before: Option<DateTime<Utc>>,
+    },
+}
+
+/// Everything a filter can read about one coordinate
+///
+/// Fields a source cannot provide stay `None` (or empty), and predicates on
+/// them match nothing.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FilterRecord<'a> {
+    pub author: Option<&'a str>,
+    pub tags: &'a [String],
+    pub created_at: Option<DateTime<Utc>>,
+    pub metadata: Option<&'a HashMap<String, Value>>,
+    pub state: Option<&'a Value>,
+}
+
+/// Strings in the `tags` metadata array
+pub fn metadata_tags(metadata: Option<&HashMap<String, Value>>) -> Vec<String> {
+    metadata
+        .and_then(|m| m.get(TAGS_METADATA_KEY))
+        .and_then(Value::as_array)
+        .map(|tags| tags.iter().filter_map(Value::as_str).map(String::from).collect())
+        .unwrap_or_default()
+}
+
+impl FilterExpr {
+    /// Validate and compile the JSON form, resolving `within_days` against `now`
+    ///
+    /// Errors name the position of the offending node, e.g.
+    /// `$.and[1].eq.value`.
+    pub fn from_json(value: &Value, now: DateTime<Utc>) -> Result<Self> {
+        compile(value, "$", 0, now)
+            .map_err(|(path, message)| BmsError::InvalidState(format!("Filter at {}: {}", path, message)))
+    }
+
+    /// Parse the compact string form, resolving relative dates against `now`
+    ///
+    /// Errors give the 1-based column of the offending token.
+    pub fn parse(text: &str, now: DateTime<Utc>) -> Result<Self> {
+        let tokens = tokenize(text).map_err(|(column, message)| column_error(column, &message))?;
+        let mut parser = Parser { tokens, pos: 0, end: text.chars().count() + 1, now };
+        let expr = parser.expr(0).map_err(|(column, message)| column_error(column, &message))?;
+        if let Some(token) = parser.tokens.get(parser.pos) {
+            return Err(column_error(token.column, &format!("unexpected {}", token.kind)));
+        }
+        Ok(expr)
+    }
+
+    /// JSON form, with relative dates already resolved
+    pub fn to_json(&self) -> Value {
+        let list = |items: &[FilterExpr]| Value::Array(items.iter().map(FilterExpr::to_json).collect());
+        match self {
+            FilterExpr::And(items) => json!({ "and": list(items) }),
+            FilterExpr::Or(items) => json!({ "or": list(items) }),
+            FilterExpr::Not(inner) => json!({ "not": inner.to_json() }),
+            FilterExpr::Eq { field, value } => json!({ "eq": { "field": field.to_string(), "value": value } }),
+            FilterExpr::In { field, values } => {
+                json!({ "in": { "field": field.to_string(), "values": values } })
+            }
+            FilterExpr::Cmp { field, op, value } => {
+                json!({ op.name(): { "field": field.to_string(), "value": value } })
+            }
+            FilterExpr::Exists(field) => json!({ "exists": field.to_string() }),
+            FilterExpr::Tag(tag) => json!({ "tag": tag }),
+            FilterExpr::Created { after, before } => {
+                let mut range = Map::new();
+                if let Some(after) = after {
+                    range.insert("after".to_string(), json!(after.to_rfc3339()));
+                }
+                if let Some(before) = before {
+                    range.insert("before".to_string(), json!(before.to_rfc3339()));
+                }
+                json!({ "created": range })
+            }
+        }
+    }
+
+    /// Evaluate against one record
+    pub fn matches(&self, record: &FilterRecord) -> bool {
+        match self {
+            FilterExpr::And(items) => items.iter().all(|e| e.matches(record)),
+            FilterExpr::Or(items) => items.iter().any(|e| e.matches(record)),
+            FilterExpr::Not(inner) => !inner.matches(record),
+            FilterExpr::Eq { field, value } => lookup(field, record).is_some_and(|v| scalar_eq(&v, value)),
+            FilterExpr::In { field, values } => {
+                lookup(field, record).is_some_and(|v| values.iter().any(|value| scalar_eq(&v, value)))
+            }
+            FilterExpr::Cmp { field, op, value } => lookup(field, record)
+                .and_then(|v| v.as_f64())
+                .is_some_and(|v| op.holds(v, *value)),
+            FilterExpr::Exists(field) => lookup(field, record).is_some(),
+            FilterExpr::Tag(tag) => record.tags.iter().any(|t| t == tag),
+            FilterExpr::Created { after, before } => record.created_at.is_some_and(|created| {
+                after.is_none_or(|after| created >= after) && before.is_none_or(|before| created < before)
+            }),
+        }
+    }
+
+    /// Whether the expression reads the head delta's author
+    pub fn uses_author(&self) -> bool {
+        self.any_field(&|field| matches!(field, Field::Author))
+    }
+
+    /// Whether the expression reads the head state
+    pub fn uses_state(&self) -> bool {
+        self.any_field(&|field| matches!(field, Field::State(_)))
+    }
+
+    fn any_field(&self, test: &dyn Fn(&Field) -> bool) -> bool {
+        match self {
+            FilterExpr::And(items) | FilterExpr::Or(items) => items.iter().any(|e| e.any_field(test)),
+            FilterExpr::Not(inner) => inner.any_field(test),
+            FilterExpr::Eq { field, .. }
+            | FilterExpr::In { field, .. }
+            | FilterExpr::Cmp { field, .. }
+            | FilterExpr::Exists(field) => test(field),
+            FilterExpr::Tag(_) | FilterExpr::Created { .. } => false,
+        }
+    }
+
+    /// Part of the expression a store holding only coordinate rows can evaluate
+    ///
+    /// Author and head-state predicates need the deltas. When the whole
+    /// expression cannot be pushed down, the pushable conjuncts of a
+    /// top-level `and` are returned; rows they keep are a superset of the
+    /// matches, so callers still evaluate the full expression afterwards.
+    pub fn pushdown(&self) -> Option<FilterExpr> {
+        if !self.uses_author() && !self.uses_state() {
+            return Some(self.clone());
+        }
+        let FilterExpr::And(items) = self else {
+            return None;
+        };
+        let mut pushed: Vec<FilterExpr> = items.iter().filter_map(FilterExpr::pushdown).collect();
+        match pushed.len() {
+            0 => None,
+            1 => pushed.pop(),
+            _ => Some(FilterExpr::And(pushed)),
+        }
+    }
+}
+
+impl Serialize for FilterExpr {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        self.to_json().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for FilterExpr {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let value = Value::deserialize(deserializer)?;
+        FilterExpr::from_json(&value, Utc::now()).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Field value, with JSON `null` treated as missing
+fn lookup<'a>(field: &Field, record: &FilterRecord<'a>) -> Option<Cow<'a, Value>> {
+    let value = match field {
+        Field::Author => return record.author.map(|a| Cow::Owned(Value::String(a.to_string()))),
+        Field::Metadata(key) => record.metadata?.get(key)?,
+        Field::State(pointer) => record.state?.pointer(pointer)?,
+    };
+    (!value.is_null()).then_some(Cow::Borrowed(value))
+}
+
+fn scalar_eq(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::String(a), Value::String(b)) => a == b,
+        (Value::Bool(a), Value::Bool(b)) => a == b,
+        (Value::Number(a), Value::Number(b)) => a.as_f64() == b.as_f64(),
+        _ => false,
+    }
+}
+
+fn is_scalar(value: &Value) -> bool {
+    matches!(value, Value::String(_) | Value::Number(_) | Value::Bool(_))
+}
+
+type CompileError = (String, String);
+
+fn compile(value: &Value, path: &str, depth: usize, now: DateTime<Utc>) -> std::result::Result<FilterExpr, CompileError> {
+    let err = |path: &str, message: &str| (path.to_string(), message.to_string());
+    if depth > MAX_DEPTH {
+        return Err(err(path, &format!("nested deeper than {} levels", MAX_DEPTH)));
+    }
+    let node = value.as_object().filter(|o| o.len() == 1).ok_or_else(|| {
+        err(path, "expected an object with exactly one operator, e.g. {\"eq\": {...}}")
+    })?;
+    let (op, body) = node.iter().next().expect("one entry");
+    let here = format!("{}.{}", path, op);
+
+    let field_of = |body: &Value| -> std::result::Result<Field, CompileError> {
+        let field = body
+            .get("field")
+            .and_then(Value::as_str)
+            .ok_or_else(|| err(&format!("{}.field", here), "expected a field name"))?;
+        Field::parse(field).map_err(|m| err(&format!("{}.field", here), &m))
+    };
+    let members = |body: &Value, allowed: &[&str]| -> std::result::Result<(), CompileError> {
+        let object = body.as_object().ok_or_else(|| err(&here, "expected an object"))?;
+        match object.keys().find(|k| !allowed.contains(&k.as_str())) {
+            Some(key) => Err(err(&format!("{}.{}", here, key), "unknown member")),
+            None => Ok(()),
+        }
+    };
+
+    match op.as_str() {
+        "and" | "or" => {
+            let items = body
+                .as_array()
+                .filter(|a| !a.is_empty())
+                .ok_or_else(|| err(&here, "expected a non-empty array of expressions"))?;
+            let items = items
+                .iter()
+                .enumerate()
+                .map(|(i, item)| compile(item, &format!("{}[{}]", here, i), depth + 1, now))
+                .collect::<std::result::Result<Vec<_>, _>>()?;
+            Ok(if op == "and" { FilterExpr::And(items) } else { FilterExpr::Or(items) })
+        }
+        "not" => Ok(FilterExpr::Not(Box::new(compile(body, &here, depth + 1, now)?))),
+        "eq" => {
+            members(body, &["field", "value"])?;
+            let field = field_of(body)?;
+            let value = body.get("value").filter(|v| is_scalar(v)).ok_or_else(|| {
+                err(&format!("{}.value", here), "expected a string, number or boolean (use exists for null checks)")
+            })?;
+            Ok(FilterExpr::Eq { field, value: value.clone() })
+        }
+        "in" => {
+            members(body, &["field", "values"])?;
+            let field = field_of(body)?;
+            let values = body
+                .get("values")
+                .and_then(Value::as_array)
+                .filter(|a| !a.is_empty())
+                .ok_or_else(|| err(&format!("{}.values", here), "expected a non-empty array"))?;
+            if let Some(i) = values.iter().position(|v| !is_scalar(v)) {
+                return Err(err(&format!("{}.values[{}]", here, i), "expected a string, number or boolean"));
+            }
+            Ok(FilterExpr::In { field, values: values.clone() })
+        }
+        "lt" | "le" | "gt" | "ge" => {
+            members(body, &["field", "value"])?;
+            let field = field_of(body)?;
+            let value = body
+                .get("value")
+                .and_then(Value::as_f64)
+                .ok_or_else(|| err(&format!("{}.value", here), "expected a number"))?;
+            let op = CmpOp::from_name(op).expect("comparison name");
+            Ok(FilterExpr::Cmp { field, op, value })
+        }
+        "exists" => {
+            let field = body.as_str().ok_or_else(|| err(&here, "expected a field name"))?;
+            Ok(FilterExpr::Exists(Field::parse(field).map_err(|m| err(&here, &m))?))
+        }
+        "tag" => {
+            let tag = body
+                .as_str()
+                .filter(|t| !t.is_empty())
+                .ok_or_else(|| err(&here, "expected a non-empty tag"))?;
+            Ok(FilterExpr::Tag(tag.to_string()))
+        }
+        "created" => {
+            members(body, &["after", "before", "within_days"])?;
+            let time = |key: &str| -> std::result::Result<Option<DateTime<Utc>>, CompileError> {
+                body.get(key)
+                    .map(|v| {
+                        v.as_str()
+                            .and_then(|s| DateTime::parse_from_rfc3339(s).ok())
+                            .map(|t| t.with_timezone(&Utc))
+                            .ok_or_else(|| err(&format!("{}.{}", here, key), "expected an RFC 3339 timestamp"))
+                    })
+                    .transpose()
+            };
+            let mut after = time("after")?;
+            let before = time("before")?;
+            if let Some(days) = body.get("within_days") {
+                let path = format!("{}.within_days", here);
+                if after.is_some() {
+                    return Err(err(&path, "give either after or within_days, not both"));
+                }
+                let days = days
+                    .as_u64()
+                    .filter(|d| *d <= 36_500)
+                    .ok_or_else(|| err(&path, "expected a whole number of days up to 36500"))?;
+                after = Some(now - Duration::days(days as i64));
+            }
+            if after.is_none() && before.is_none() {
+                return Err(err(&here, "expected after, before or within_days"));
+            }
+            Ok(FilterExpr::Created { after, before })
+        }
+        other => Err(err(
+            path,
+            &format!("unknown operator {:?}; expected and, or, not, eq, in, lt, le, gt, ge, exists, tag or created", other),
+        )),
+    }
+}
+
+fn column_error(column: usize, message: &str) -> BmsError {
+    BmsError::InvalidState(format!("Filter at column {}: {}", column, message))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    Open,
+    Close,
+    Comma,
+    /// `==`, `!=`, `<`, `<=`, `>` or `>=`
+    Op(&'static str),
+    /// Unquoted run of characters: keywords, fields, numbers, dates and bare strings
+    Word(String),
+    Quoted(String),
+}
+
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenKind::Open => f.write_str("'('"),
+            TokenKind::Close => f.write_str("')'"),
+            TokenKind::Comma => f.write_str("','"),
+            TokenKind::Op(op) => write!(f, "'{}'", op),
+            TokenKind::Word(word) => write!(f, "'{}'", word),
+            TokenKind::Quoted(text) => write!(f, "{:?}", text),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: TokenKind,
+    column: usize,
+}
+
+type ParseError = (usize, String);
+
+fn tokenize(text: &str) -> std::result::Result<Vec<Token>, ParseError> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let column = i + 1;
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        let kind = match c {
+            c if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '(' => TokenKind::Open,
+            ')' => TokenKind::Close,
+            ',' => TokenKind::Comma,
+            '=' if next == Some('=') => TokenKind::Op("=="),
+            '!' if next == Some('=') => TokenKind::Op("!="),
+            '<' if next == Some('=') => TokenKind::Op("<="),
+            '>' if next == Some('=') => TokenKind::Op(">="),
+            '<' => TokenKind::Op("<"),
+            '>' => TokenKind::Op(">"),
+            '=' | '!' => return Err((column, format!("unexpected '{}'; comparisons are ==, !=, <, <=, > and >=", c))),
+            '"' | '\'' => {
+                let mut value = String::new();
+                let mut j = i + 1;
+                loop {
+                    match chars.get(j) {
+                        None => return Err((column, "unterminated string".to_string())),
+                        Some(&q) if q == c => break,
+                        Some('\\') if chars.get(j + 1).is_some() => {
+                            value.push(chars[j + 1]);
+                            j += 2;
+                        }
+                        Some(&ch) => {
+                            value.push(ch);
+                            j += 1;
+                        }
+                    }
+                }
+                i = j + 1;
+                tokens.push(Token { kind: TokenKind::Quoted(value), column });
+                continue;
+            }
+            _ => {
+                let start = i;
+                while i < chars.len() && !chars[i].is_whitespace() && !"(),=!<>\"'".contains(chars[i]) {
+                    i += 1;
+                }
+                tokens.push(Token { kind: TokenKind::Word(chars[start..i].iter().collect()), column });
+                continue;
+            }
+        };
+        i += match kind {
+            TokenKind::Op(op) => op.len(),
+            _ => 1,
+        };
+        tokens.push(Token { kind, column });
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    /// Column reported for errors at the end of the input
+    end: usize,
+    now: DateTime<Utc>,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&TokenKind> {
+        self.tokens.get(self.pos).map(|t| &t.kind)
+    }
+
+    fn column(&self) -> usize {
+        self.tokens.get(self.pos).map_or(self.end, |t| t.column)
+    }
+
+    fn next(&mut self, expected: &str) -> std::result::Result<Token, ParseError> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or_else(|| (self.end, format!("expected {}, found end of filter", expected)))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn keyword(&mut self, word: &str) -> bool {
+        if matches!(self.peek(), Some(TokenKind::Word(w)) if w == word) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    fn expect(&mut self, kind: TokenKind) -> std::result::Result<(), ParseError> {
+        let token = self.next(&kind.to_string())?;
+        if token.kind != kind {
+            return Err((token.column, format!("expected {}, found {}", kind, token.kind)));
+        }
+        Ok(())
+    }
+
+    fn expr(&mut self, depth: usize) -> std::result::Result<FilterExpr, ParseError> {
+        let mut items = vec![self.conjunction(depth)?];
+        while self.keyword("or") {
+            items.push(self.conjunction(depth)?);
+        }
+        Ok(if items.len() == 1 { items.pop().expect("one item") } else { FilterExpr::Or(items) })
+    }
+
+    fn conjunction(&mut self, depth: usize) -> std::result::Result<FilterExpr, ParseError> {
+        let mut items = vec![self.unary(depth)?];
+        while self.keyword("and") {
+            items.push(self.unary(depth)?);
+        }
+        Ok(if items.len() == 1 { items.pop().expect("one item") } else { FilterExpr::And(items) })
+    }
+
+    fn unary(&mut self, depth: usize) -> std::result::Result<FilterExpr, ParseError> {
+        if depth > MAX_DEPTH {
+            return Err((self.column(), format!("nested deeper than {} levels", MAX_DEPTH)));
+        }
+        if self.keyword("not") {
+            return Ok(FilterExpr::Not(Box::new(self.unary(depth + 1)?)));
+        }
+        if self.peek() == Some(&TokenKind::Open) {
+            self.pos += 1;
+            let inner = self.expr(depth + 1)?;
+            self.expect(TokenKind::Close)?;
+            return Ok(inner);
+        }
+        self.predicate()
+    }
+
+    fn predicate(&mut self) -> std::result::Result<FilterExpr, ParseError> {
+        let token = self.next("a predicate")?;
+        let TokenKind::Word(word) = token.kind else {
+            return Err((token.column, format!("expected a field, tag, created or exists, found {}", token.kind)));
+        };
+        match word.as_str() {
+            "exists" => {
+                let field = self.next("a field")?;
+                return Ok(FilterExpr::Exists(self.field(field)?));
+            }
+            "created" => return self.created(),
+            "tag" => {
+                let values = self.equality_values()?;
+                let tags = values
+                    .into_iter()
+                    .map(|(column, value)| match value {
+                        Value::String(tag) if !tag.is_empty() => Ok(FilterExpr::Tag(tag)),
+                        _ => Err((column, "tags are non-empty strings".to_string())),
+                    })
+                    .collect::<std::result::Result<Vec<_>, _>>()?;
+                return Ok(if tags.len() == 1 { tags.into_iter().next().expect("one tag") } else { FilterExpr::Or(tags) });
+            }
+            _ => {}
+        }
+        let field = self.field(Token { kind: TokenKind::Word(word), column: token.column })?;
+        let op_column = self.column();
+        match self.peek().cloned() {
+            Some(TokenKind::Op(op @ ("<" | "<=" | ">" | ">="))) => {
+                self.pos += 1;
+                let value = self.next("a number")?;
+                let number = match &value.kind {
+                    TokenKind::Word(w) => w.parse::<f64>().ok().filter(|n| n.is_finite()),
+                    _ => None,
+                }
+                .ok_or_else(|| (value.column, format!("expected a number, found {}", value.kind)))?;
+                let op = match op {
+                    "<" => CmpOp::Lt,
+                    "<=" => CmpOp::Le,
+                    ">" => CmpOp::Gt,
+                    _ => CmpOp::Ge,
+                };
+                Ok(FilterExpr::Cmp { field, op, value: number })
+            }
+            Some(TokenKind::Op("!=")) => {
+                self.pos += 1;
+                let value = self.value()?;
+                Ok(FilterExpr::Not(Box::new(FilterExpr::Eq { field, value })))
+            }
+            Some(TokenKind::Op("==")) | Some(TokenKind::Word(_)) => {
+                let mut values: Vec<Value> = self.equality_values()?.into_iter().map(|(_, v)| v).collect();
+                Ok(if values.len() == 1 {
+                    FilterExpr::Eq { field, value: values.pop().expect("one value") }
+                } else {
+                    FilterExpr::In { field, values }
+                })
+            }
+            Some(other) => Err((op_column, format!("expected a comparison after {}, found {}", field, other))),
+            None => Err((op_column, format!("expected a comparison after {}, found end of filter", field))),
+        }
+    }
+
+    /// `== value` or `in (value, ...)`
+    fn equality_values(&mut self) -> std::result::Result<Vec<(usize, Value)>, ParseError> {
+        let column = self.column();
+        if self.peek() == Some(&TokenKind::Op("==")) {
+            self.pos += 1;
+            let column = self.column();
+            return Ok(vec![(column, self.value()?)]);
+        }
+        if !self.keyword("in") {
+            let found = self.peek().map_or("end of filter".to_string(), |k| k.to_string());
+            return Err((column, format!("expected == or in, found {}", found)));
+        }
+        self.expect(TokenKind::Open)?;
+        let mut values = Vec::new();
+        loop {
+            let column = self.column();
+            values.push((column, self.value()?));
+            let token = self.next("',' or ')'")?;
+            match token.kind {
+                TokenKind::Comma => continue,
+                TokenKind::Close => return Ok(values),
+                other => return Err((token.column, format!("expected ',' or ')', found {}", other))),
+            }
+        }
+    }
+
+    fn value(&mut self) -> std::result::Result<Value, ParseError> {
+        let token = self.next("a value")?;
+        match token.kind {
+            TokenKind::Quoted(text) => Ok(Value::String(text)),
+            TokenKind::Word(word) if !["and", "or", "not", "in"].contains(&word.as_str()) => Ok(match word.as_str() {
+                "true" => Value::Bool(true),
+                "false" => Value::Bool(false),
+                _ => match serde_json::from_str::<serde_json::Number>(&word) {
+                    Ok(number) => Value::Number(number),
+                    Err(_) => Value::String(word),
+                },
+            }),
+            other => Err((token.column, format!("expected a value, found {}", other))),
+        }
+    }
+
+    fn field(&self, token: Token) -> std::result::Result<Field, ParseError> {
+        match token.kind {
+            TokenKind::Word(word) => Field::parse(&word).map_err(|m| (token.column, m)),
+            other => Err((token.column, format!("expected a field, found {}", other))),
+        }
+    }
+
+    /// `created >= DATE` or `created < DATE`
+    fn created(&mut self) -> std::result::Result<FilterExpr, ParseError> {
+        let op = self.next("'>=' or '<'")?;
+        let after = match op.kind {
+            TokenKind::Op(">=") => true,
+            TokenKind::Op("<") => false,
+            other => return Err((op.column, format!("created takes >= or <, found {}", other))),
+        };
+        let date = self.next("a date")?;
+        let text = match &date.kind {
+            TokenKind::Word(w) | TokenKind::Quoted(w) => w.as_str(),
+            other => return Err((date.column, format!("expected a date, found {}", other))),
+        };
+        let time = self
+            .date(text)
+            .ok_or_else(|| (date.column, format!("expected an RFC 3339 timestamp or -<n>d/-<n>h, found {:?}", text)))?;
+        Ok(if after {
+            FilterExpr::Created { after: Some(time), before: None }
+        } else {
+            FilterExpr::Created { after: None, before: Some(time) }
+        })
+    }
+
+    /// RFC 3339 timestamp, or a relative offset into the past such as `-30d`
+    fn date(&self, text: &str) -> Option<DateTime<Utc>> {
+        if let Some(offset) = text.strip_prefix('-') {
+            let (amount, unit) = offset.split_at(offset.len().checked_sub(1)?);
+            let amount: i64 = amount.parse().ok().filter(|n| (0..=876_000).contains(n))?;
+            let span = match unit {
+                "d" => Duration::days(amount),
+                "h" => Duration::hours(amount),
+                _ => return None,
+            };
+            return Some(self.now - span);
+        }
+        DateTime::parse_from_rfc3339(text).ok().map(|t| t.with_timezone(&Utc))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2026-10-01T00:00:00Z").unwrap().with_timezone(&Utc)
+    }
+
+    fn parse(text: &str) -> FilterExpr {
+        FilterExpr::parse(text, now()).unwrap()
+    }
+
+    fn parse_err(text: &str) -> String {
+        FilterExpr::parse(text, now()).unwrap_err().to_string()
+    }
+
+    fn json_err(value: Value) -> String {
+        FilterExpr::from_json(&value, now()).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn test_precedence() {
+        // and binds tighter than or; not applies to the next operand only
+        assert_eq!(
+            parse("tag == a or tag == b and not tag == c"),
+            FilterExpr::Or(vec![
+                FilterExpr::Tag("a".into()),
+                FilterExpr::And(vec![
+                    FilterExpr::Tag("b".into()),
+                    FilterExpr::Not(Box::new(FilterExpr::Tag("c".into()))),
+                ]),
+            ])
+        );
+        assert_eq!(
+            parse("(tag == a or tag == b) and tag == c"),
+            FilterExpr::And(vec![
+                FilterExpr::Or(vec![FilterExpr::Tag("a".into()), FilterExpr::Tag("b".into())]),
+                FilterExpr::Tag("c".into()),
+            ])
+        );
+
+        let tags = |tags: &[&str]| tags.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+        let expr = parse("tag == a or tag == b and tag == c");
+        let only_a = tags(&["a"]);
+        let only_b = tags(&["b"]);
+        assert!(expr.matches(&FilterRecord { tags: &only_a, ..Default::default() }));
+        assert!(!expr.matches(&FilterRecord { tags: &only_b, ..Default::default() }));
+    }
+
+    #[test]
+    fn test_string_and_json_forms_agree() {
+        let text = "/status == open and author in (alice, 'bob') and metadata.priority < 3 \
+                    and created >= -30d and exists metadata.project and metadata.done != true";
+        let json = json!({"and": [
+            {"eq": {"field": "/status", "value": "open"}},
+            {"in": {"field": "author", "values": ["alice", "bob"]}},
+            {"lt": {"field": "metadata.priority", "value": 3}},
+            {"created": {"within_days": 30}},
+            {"exists": "metadata.project"},
+            {"not": {"eq": {"field": "metadata.done", "value": true}}},
+        ]});
+        let compiled = FilterExpr::from_json(&json, now()).unwrap();
+        assert_eq!(parse(text), compiled);
+        // The JSON form round-trips with the relative date resolved
+        let resolved = compiled.to_json();
+        assert_eq!(resolved["and"][3], json!({"created": {"after": "2026-09-01T00:00:00+00:00"}}));
+        assert_eq!(FilterExpr::from_json(&resolved, now()).unwrap(), compiled);
+    }
+
+    #[test]
+    fn test_nulls_and_missing_fields() {
+        let metadata = HashMap::from([
+            ("owner".to_string(), Value::Null),
+            ("priority".to_string(), json!(2)),
+            ("label".to_string(), json!("2")),
+        ]);
+        let state = json!({"status": null, "count": 1.0});
+        let record = FilterRecord { metadata: Some(&metadata), state: Some(&state), ..Default::default() };
+
+        for missing in ["metadata.owner", "metadata.absent", "/status", "/absent/deeper", "author"] {
+            assert!(!parse(&format!("exists {}", missing)).matches(&record), "{}", missing);
+            assert!(!parse(&format!("{} == x", missing)).matches(&record), "{}", missing);
+            assert!(!parse(&format!("{} > 0", missing)).matches(&record), "{}", missing);
+            // Negation matches what the comparison does not
+            assert!(parse(&format!("{} != x", missing)).matches(&record), "{}", missing);
+            assert!(parse(&format!("not {} < 0", missing)).matches(&record), "{}", missing);
+        }
+        assert!(!parse("created >= -1d").matches(&record));
+        assert!(parse("not created >= -1d").matches(&record));
+
+        // Numbers compare by value, never against strings
+        assert!(parse("/count == 1").matches(&record));
+        assert!(parse("metadata.priority == 2.0").matches(&record));
+        assert!(!parse("metadata.priority == '2'").matches(&record));
+        assert!(parse("metadata.label == '2'").matches(&record));
+        assert!(!parse("metadata.label == 2").matches(&record));
+        assert!(!parse("metadata.label < 3").matches(&record));
+    }
+
+    #[test]
+    fn test_json_errors_name_the_node() {
+        assert_eq!(
+            json_err(json!({"and": [{"tag": "x"}, {"eq": {"field": "/s", "value": null}}]})),
+            "Invalid state: Filter at $.and[1].eq.value: expected a string, number or boolean (use exists for null checks)"
+        );
+        assert!(json_err(json!({"or": []})).contains("$.or: expected a non-empty array"));
+        assert!(json_err(json!({"not": {"tag": "a", "eq": {}}})).contains("$.not: expected an object with exactly one operator"));
+        assert!(json_err(json!({"in": {"field": "state", "values": [1]}})).contains("$.in.field: unknown field \"state\""));
+        assert!(json_err(json!({"in": {"field": "author", "values": [1, [2]]}})).contains("$.in.values[1]"));
+        assert!(json_err(json!({"gt": {"field": "author", "value": "3"}})).contains("$.gt.value: expected a number"));
+        assert!(json_err(json!({"created": {"after": "yesterday"}})).contains("$.created.after"));
+        assert!(json_err(json!({"eq": {"field": "author", "value": "a", "extra": 1}})).contains("$.eq.extra: unknown member"));
+        assert!(json_err(json!({"like": "x"})).contains("$: unknown operator \"like\""));
+
+        let mut deep = json!({"tag": "x"});
+        for _ in 0..=MAX_DEPTH {
+            deep = json!({ "not": deep });
+        }
+        assert!(json_err(deep).contains("nested deeper"));
+    }
+
+    #[test]
+    fn test_string_errors_give_columns() {
+        assert_eq!(parse_err("tag == a and (tag == b"), "Invalid state: Filter at column 23: expected ')', found end of filter");
+        assert_eq!(parse_err("author = x"), "Invalid state: Filter at column 8: unexpected '='; comparisons are ==, !=, <, <=, > and >=");
+        assert_eq!(parse_err("tag == a tag == b"), "Invalid state: Filter at column 10: unexpected 'tag'");
+        assert!(parse_err("metadata.priority < high").starts_with("Invalid state: Filter at column 21: expected a number"));
+        assert!(parse_err("status == open").starts_with("Invalid state: Filter at column 1: unknown field \"status\""));
+        assert!(parse_err("created > -3d").starts_with("Invalid state: Filter at column 9: created takes >= or <"));
+        assert!(parse_err("author == 'open").starts_with("Invalid state: Filter at column 11: unterminated string"));
+        assert!(parse_err("author in (a, b").contains("column 16"));
+        assert!(parse_err("").contains("column 1: expected a predicate"));
+    }
+
+    #[test]
+    fn test_pushdown_keeps_coordinate_predicates() {
+        let expr = parse("metadata.team == core and author == alice and (tag == a or /x == 1)");
+        assert_eq!(expr.pushdown(), Some(parse("metadata.team == core")));
+        assert!(parse("author == alice or tag == a").pushdown().is_none());
+        let pushable = parse("not (tag == a or created < 2026-01-01T00:00:00Z)");
+        assert_eq!(pushable.pushdown(), Some(pushable));
+    }
+}
@@ -0,0 +1,158 @@
+//! Human-readable companions for timestamps and byte counts
+//!
+//! `decorate` walks a serialized response and, next to every `*_at` RFC 3339
+//! timestamp and every `*_bytes` or `bytes_*` integer, adds a `*_human` field
+//! with a relative time ("3 minutes ago") or an IEC size ("1.2 MiB"). Raw
+//! fields are never changed. The reference time is passed in so output is
+//! reproducible.
+
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+
+const UNITS: [&str; 6] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB"];
+
+/// Add `*_human` fields throughout `value`, relative to `now`
+///
+/// Existing `*_human` fields are left alone.
+pub fn decorate(value: &mut Value, now: DateTime<Utc>) {
+    match value {
+        Value::Object(map) => {
+            for child in map.values_mut() {
+                decorate(child, now);
+            }
+            let companions: Vec<(String, String)> = map
+                .iter()
+                .filter_map(|(key, value)| Some((format!("{}_human", key), companion(key, value, now)?)))
+                .filter(|(key, _)| !map.contains_key(key))
+                .collect();
+            for (key, text) in companions {
+                map.insert(key, Value::String(text));
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                decorate(item, now);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn companion(key: &str, value: &Value, now: DateTime<Utc>) -> Option<String> {
+    if key.ends_with("_at") {
+        let at = DateTime::parse_from_rfc3339(value.as_str()?).ok()?;
+        return Some(relative_time(at.with_timezone(&Utc), now));
+    }
+    if key.ends_with("_bytes") || key.starts_with("bytes_") {
+        return value.as_i64().map(iec_bytes);
+    }
+    None
+}
+
+/// English relative time such as "5 minutes ago" or "in 2 days"
+pub fn relative_time(at: DateTime<Utc>, now: DateTime<Utc>) -> String {
+    let secs = (now - at).num_seconds();
+    let magnitude = secs.unsigned_abs();
+    if magnitude < 10 {
+        return "just now".to_string();
+    }
+
+    let (count, unit) = match magnitude {
+        s if s < 60 => (s, "second"),
+        s if s < 3_600 => (s / 60, "minute"),
+        s if s < 86_400 => (s / 3_600, "hour"),
+        s if s < 30 * 86_400 => (s / 86_400, "day"),
+        s if s < 365 * 86_400 => (s / (30 * 86_400), "month"),
+        s => (s / (365 * 86_400), "year"),
+    };
+    let plural = if count == 1 { "" } else { "s" };
+
+    if secs > 0 {
+        format!("{} {}{} ago", count, unit, plural)
+    } else {
+        format!("in {} {}{}", count, unit, plural)
+    }
+}
+
+/// IEC size such as "512 B" or "1.2 MiB"; negative counts keep their sign
+pub fn iec_bytes(bytes: i64) -> String {
+    let sign = if bytes < 0 { "-" } else { "" };
+    let mut size = bytes.unsigned_abs() as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{}{} B", sign, bytes.unsigned_abs())
+    } else {
+        format!("{}{:.1} {}", sign, size, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+    use serde_json::json;
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 6, 1, 12, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn test_relative_time() {
+        let now = now();
+        assert_eq!(relative_time(now - Duration::seconds(3), now), "just now");
+        assert_eq!(relative_time(now - Duration::seconds(42), now), "42 seconds ago");
+        assert_eq!(relative_time(now - Duration::seconds(61), now), "1 minute ago");
+        assert_eq!(relative_time(now - Duration::minutes(190), now), "3 hours ago");
+        assert_eq!(relative_time(now - Duration::days(1), now), "1 day ago");
+        assert_eq!(relative_time(now - Duration::days(75), now), "2 months ago");
+        assert_eq!(relative_time(now - Duration::days(800), now), "2 years ago");
+        assert_eq!(relative_time(now + Duration::days(2), now), "in 2 days");
+    }
+
+    #[test]
+    fn test_iec_bytes() {
+        assert_eq!(iec_bytes(0), "0 B");
+        assert_eq!(iec_bytes(1023), "1023 B");
+        assert_eq!(iec_bytes(1536), "1.5 KiB");
+        assert_eq!(iec_bytes(1_258_291), "1.2 MiB");
+        assert_eq!(iec_bytes(-3 * 1024 * 1024 * 1024), "-3.0 GiB");
+    }
+
+    #[test]
+    fn test_decorate_adds_companions_only() {
+        let mut value = json!({
+            "coordinates": 3,
+            "items": [{
+                "created_at": "2024-06-01T11:55:00Z",
+                "ops_bytes": 2048,
+                "latest_snapshot_at": null,
+            }],
+            "estimate": {"bytes_saved": 10},
+            "updated_at": "2024-06-01T11:00:00Z",
+            "updated_at_human": "kept",
+        });
+        decorate(&mut value, now());
+
+        assert_eq!(
+            value,
+            json!({
+                "coordinates": 3,
+                "items": [{
+                    "created_at": "2024-06-01T11:55:00Z",
+                    "created_at_human": "5 minutes ago",
+                    "ops_bytes": 2048,
+                    "ops_bytes_human": "2.0 KiB",
+                    "latest_snapshot_at": null,
+                }],
+                "estimate": {"bytes_saved": 10, "bytes_saved_human": "10 B"},
+                "updated_at": "2024-06-01T11:00:00Z",
+                "updated_at_human": "kept",
+            })
+        );
+    }
+}
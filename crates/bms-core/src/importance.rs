@@ -0,0 +1,108 @@
+//! Per-coordinate importance with exponential decay
+//!
+//! Importance is stored as a value and the time it was last set. The
+//! effective value halves every `half_life_secs` and is only computed when
+//! read, so nothing has to sweep the store to age it.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Importance of a coordinate nobody has reinforced yet
+pub const DEFAULT_IMPORTANCE: f32 = 0.5;
+
+/// Upper bound of an importance value; the lower bound is 0
+pub const MAX_IMPORTANCE: f32 = 1.0;
+
+/// Decay and reinforcement settings
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ImportancePolicy {
+    /// Time for importance to halve; 0 disables decay
+    pub half_life_secs: f64,
+    /// Added per recorded read
+    pub access_bump: f32,
+}
+
+impl Default for ImportancePolicy {
+    fn default() -> Self {
+        Self {
+            half_life_secs: 7.0 * 24.0 * 3600.0,
+            access_bump: 0.01,
+        }
+    }
+}
+
+impl ImportancePolicy {
+    /// Importance at `now` of a value stored at `updated_at`
+    pub fn effective(&self, importance: f32, updated_at: DateTime<Utc>, now: DateTime<Utc>) -> f32 {
+        let elapsed = (now - updated_at).num_milliseconds() as f64 / 1000.0;
+        if self.half_life_secs <= 0.0 || elapsed <= 0.0 {
+            return importance;
+        }
+        (f64::from(importance) * 0.5f64.powf(elapsed / self.half_life_secs)) as f32
+    }
+
+    /// Value to store at `now` after adding `delta` to the decayed importance
+    ///
+    /// `delta` may be negative; the result is clamped to `0..=MAX_IMPORTANCE`.
+    pub fn reinforce(
+        &self,
+        importance: f32,
+        updated_at: DateTime<Utc>,
+        delta: f32,
+        now: DateTime<Utc>,
+    ) -> f32 {
+        (self.effective(importance, updated_at, now) + delta).clamp(0.0, MAX_IMPORTANCE)
+    }
+}
+
+/// Blend a similarity score with importance; `weight` is clamped to `0..=1`
+pub fn blend(score: f32, importance: f32, weight: f32) -> f32 {
+    let weight = weight.clamp(0.0, 1.0);
+    score * (1.0 - weight) + importance * weight
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn policy(half_life_secs: f64) -> ImportancePolicy {
+        ImportancePolicy {
+            half_life_secs,
+            access_bump: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_decay_halves_per_half_life() {
+        let policy = policy(3600.0);
+        let at = Utc::now();
+
+        assert_eq!(policy.effective(0.8, at, at), 0.8);
+        assert!((policy.effective(0.8, at, at + Duration::hours(1)) - 0.4).abs() < 1e-6);
+        assert!((policy.effective(0.8, at, at + Duration::hours(3)) - 0.1).abs() < 1e-6);
+        // Clock skew never raises importance
+        assert_eq!(policy.effective(0.8, at, at - Duration::hours(1)), 0.8);
+        // Zero half-life disables decay
+        assert_eq!(self::policy(0.0).effective(0.8, at, at + Duration::days(365)), 0.8);
+    }
+
+    #[test]
+    fn test_reinforce_decays_first_and_clamps() {
+        let policy = policy(3600.0);
+        let at = Utc::now();
+        let later = at + Duration::hours(1);
+
+        assert!((policy.reinforce(0.8, at, 0.2, later) - 0.6).abs() < 1e-6);
+        assert_eq!(policy.reinforce(0.9, at, 0.5, at), MAX_IMPORTANCE);
+        assert_eq!(policy.reinforce(0.1, at, -0.5, at), 0.0);
+    }
+
+    #[test]
+    fn test_blend() {
+        assert_eq!(blend(0.6, 0.2, 0.0), 0.6);
+        assert_eq!(blend(0.6, 0.2, 1.0), 0.2);
+        assert!((blend(0.6, 0.2, 0.25) - 0.5).abs() < 1e-6);
+        assert_eq!(blend(0.6, 0.2, 7.0), 0.2);
+    }
+}
@@ -6,22 +6,54 @@
 //! - Delta compression (RFC 6902 JSON Patch)
 //! - Merkle chain verification
 //! - Snapshot management
+//! - Read-time field redaction
+//! - Importance decay
+//! - Watched-path matching for change feeds
+//! - Human-readable response fields
+//! - Search text extraction with metadata facets
+//! - Per-coordinate access control lists
+//! - Crash-safe file output
+//! - Filter expressions for search
+//! - Limits on stored deltas
 
+pub mod acl;
+pub mod atomic;
 pub mod canonical;
 pub mod coordinate;
 pub mod delta;
 pub mod error;
+pub mod extract;
+pub mod filter;
+pub mod humanize;
+pub mod importance;
+pub mod links;
 pub mod merkle;
+pub mod policy;
+pub mod redact;
 pub mod snapshot;
 pub mod types;
+pub mod watch;
 
-pub use canonical::Canonicalizer;
+pub use acl::{Access, Acl};
+pub use atomic::{atomic_write, AtomicFile};
+pub use canonical::{CanonicalForm, CanonicalLimits, Canonicalizer};
 pub use coordinate::CoordinateGenerator;
-pub use delta::DeltaEngine;
+pub use delta::{
+    ArrayStrategy, ConflictKind, ConflictReport, DeltaEncoding, DeltaEngine, DiffOptions, DiffStats, DiffSummary,
+    MergeConflict, MergeOutcome, MergeStrategy, OpOutcome, OpsStats, PatchRatios,
+};
 pub use error::{BmsError, Result};
-pub use merkle::MerkleChain;
-pub use snapshot::SnapshotManager;
+pub use filter::{FilterExpr, FilterRecord};
+pub use importance::ImportancePolicy;
+pub use links::{extract_links, Link, LinkRules};
+pub use merkle::{AuditLogFormat, ForkInfo, HashAlgorithm, MerkleChain, MerkleProof};
+pub use policy::DeltaPolicy;
+pub use redact::{redact, RedactMode, RedactionRules};
+pub use snapshot::{
+    CompositePolicy, IntervalPolicy, SizePolicy, SnapshotContext, SnapshotManager, SnapshotPolicy, TimePolicy,
+};
 pub use types::*;
+pub use watch::PointerFilter;
 
 /// BMS version
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -32,5 +64,18 @@ pub const DEFAULT_SNAPSHOT_INTERVAL: u32 = 128;
 /// Coordinate ID length in bytes (128-bit)
 pub const COORD_ID_BYTES: usize = 16;
 
+/// Coordinate ID length in base32 characters (ceiling of 128 / 5)
+pub const COORD_ID_CHARS: usize = 26;
+
+/// Namespace of coordinates stored without one, including every
+/// coordinate from before namespaces
+pub const DEFAULT_NAMESPACE: &str = "default";
+
+/// Longest coordinate namespace, in bytes
+pub const MAX_NAMESPACE_LEN: usize = 128;
+
+/// Longest named coordinate ID, in bytes
+pub const MAX_NAMED_ID_LEN: usize = 128;
+
 /// Hash output length (SHA3-256)
 pub const HASH_BYTES: usize = 32;
@@ -0,0 +1,160 @@
+//! References from one coordinate's state to other coordinates
+//!
+//! Coordinate metadata can list JSON Pointers whose string values name other
+//! coordinates (`"links": ["/related/*", "/parent"]`). Only strings that pass
+//! `CoordinateGenerator::validate` count as references; anything else found at
+//! a link pointer is ignored, so free text never becomes a link.
+
+use crate::coordinate::CoordinateGenerator;
+use crate::error::{BmsError, Result};
+use crate::redact::{collect_paths, pointer_tokens, Segment};
+use crate::types::CoordId;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// Coordinate metadata key holding the link pointers
+pub const LINKS_METADATA_KEY: &str = "links";
+
+/// Set of JSON Pointers whose values are coordinate references
+///
+/// A `*` segment matches every member of an object or element of an array.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LinkRules {
+    pub pointers: Vec<String>,
+}
+
+/// Reference found in a state
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Link {
+    /// Concrete pointer of the referencing value, e.g. `/related/0`
+    pub pointer: String,
+    pub to_coord: CoordId,
+}
+
+impl LinkRules {
+    pub fn new(pointers: Vec<String>) -> Result<Self> {
+        for pointer in &pointers {
+            if !pointer.starts_with('/') {
+                return Err(BmsError::InvalidState(format!(
+                    "Link rule must be a non-empty JSON Pointer: {:?}",
+                    pointer
+                )));
+            }
+        }
+        Ok(Self { pointers })
+    }
+
+    /// Read rules from coordinate metadata
+    ///
+    /// Returns `None` when the metadata has no `links` key.
+    pub fn from_metadata(metadata: &HashMap<String, Value>) -> Result<Option<Self>> {
+        let Some(rules) = metadata.get(LINKS_METADATA_KEY) else {
+            return Ok(None);
+        };
+
+        let pointers = rules
+            .as_array()
+            .and_then(|a| a.iter().map(|v| v.as_str().map(String::from)).collect())
+            .ok_or_else(|| {
+                BmsError::InvalidState("links must be an array of JSON Pointers".to_string())
+            })?;
+
+        Self::new(pointers).map(Some)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pointers.is_empty()
+    }
+}
+
+/// Every coordinate reference in `state` matched by `rules`, ordered by pointer
+pub fn extract_links(state: &Value, rules: &LinkRules) -> Vec<Link> {
+    let mut paths = Vec::new();
+    for pointer in &rules.pointers {
+        collect_paths(state, &pointer_tokens(pointer), &mut Vec::new(), &mut paths);
+    }
+
+    let mut links: Vec<Link> = paths
+        .iter()
+        .filter_map(|path| match lookup(state, path)? {
+            Value::String(id) if CoordinateGenerator::validate(id).is_ok() => Some(Link {
+                pointer: to_pointer(path),
+                to_coord: CoordId(id.clone()),
+            }),
+            _ => None,
+        })
+        .collect();
+    // Overlapping rules resolve to the same pointer
+    links.sort_by(|a, b| a.pointer.cmp(&b.pointer));
+    links.dedup();
+    links
+}
+
+fn lookup<'a>(value: &'a Value, path: &[Segment]) -> Option<&'a Value> {
+    path.iter().try_fold(value, |current, segment| match (current, segment) {
+        (Value::Object(map), Segment::Key(key)) => map.get(key),
+        (Value::Array(items), Segment::Index(i)) => items.get(*i),
+        _ => None,
+    })
+}
+
+fn to_pointer(path: &[Segment]) -> String {
+    path.iter()
+        .map(|segment| match segment {
+            Segment::Key(key) => format!("/{}", key.replace('~', "~0").replace('/', "~1")),
+            Segment::Index(i) => format!("/{}", i),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const A: &str = "MFRGGZDFMZTWQ2LKNNWG23TPOA";
+    const B: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY";
+
+    fn rules(pointers: &[&str]) -> LinkRules {
+        LinkRules::new(pointers.iter().map(|p| p.to_string()).collect()).unwrap()
+    }
+
+    #[test]
+    fn test_extract_wildcards_and_escapes() {
+        let state = json!({
+            "related": [A, "not a coordinate", B],
+            "a/b": {"parent": A},
+            "n": 1
+        });
+
+        let links = extract_links(&state, &rules(&["/related/*", "/a~1b/parent", "/n", "/missing"]));
+        let found: Vec<(&str, &str)> =
+            links.iter().map(|l| (l.pointer.as_str(), l.to_coord.as_str())).collect();
+        assert_eq!(found, [("/a~1b/parent", A), ("/related/0", A), ("/related/2", B)]);
+
+        // Overlapping rules yield each reference once
+        assert_eq!(extract_links(&state, &rules(&["/related/*", "/related/0"])).len(), 2);
+    }
+
+    #[test]
+    fn test_invalid_ids_are_ignored() {
+        // Lowercase, wrong length, and non-zero padding bits all fail validation
+        let state = json!({"refs": [A.to_lowercase(), "ABC", "MFRGGZDFMZTWQ2LKNNWG23TPOB", {"id": A}]});
+        assert!(extract_links(&state, &rules(&["/refs/*"])).is_empty());
+    }
+
+    #[test]
+    fn test_rules_from_metadata() {
+        let mut metadata = HashMap::new();
+        assert_eq!(LinkRules::from_metadata(&metadata).unwrap(), None);
+
+        metadata.insert("links".to_string(), json!(["/related/*"]));
+        assert_eq!(LinkRules::from_metadata(&metadata).unwrap(), Some(rules(&["/related/*"])));
+
+        metadata.insert("links".to_string(), json!(["related"]));
+        assert!(LinkRules::from_metadata(&metadata).is_err());
+        metadata.insert("links".to_string(), json!("/related"));
+        assert!(LinkRules::from_metadata(&metadata).is_err());
+    }
+}
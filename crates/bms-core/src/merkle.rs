@@ -1,6 +1,106 @@
+use crate::delta::DeltaEngine;
 use crate::error::{BmsError, Result};
-use crate::types::{Delta, Hash};
+use crate::types::{CoordId, Delta, DeltaId, Hash};
+use chrono::{DateTime, SecondsFormat, Utc};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 use sha3::{Digest, Sha3_256};
+use std::collections::HashSet;
+
+/// Proof that one delta is part of a chain ending at a known chain hash
+///
+/// Carries the delta's own hashes and the delta hashes of every later delta,
+/// but none of their ops.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MerkleProof {
+    pub delta_id: DeltaId,
+    /// Hash of the delta's ops, which its ID is the first 16 bytes of
+    pub delta_hash: Hash,
+    /// Chain hash of the delta's parent; `None` for the first delta
+    pub parent_hash: Option<Hash>,
+    pub chain_hash_at_position: Hash,
+    /// Delta hashes of the later deltas, oldest first
+    pub sibling_hashes: Vec<Hash>,
+}
+
+/// Where two delta sequences of one coordinate stop agreeing
+///
+/// If `common_prefix_len` equals the shorter sequence's length, one is an
+/// extension of the other and there is no fork.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ForkInfo {
+    /// Leading deltas the two sequences share
+    pub common_prefix_len: usize,
+    /// Chain hash of the last shared delta; `None` if they share none
+    pub fork_point_hash: Option<Hash>,
+}
+
+/// Hash function of a coordinate's chain hashes and generated ID
+///
+/// Delta hashes, delta IDs, and snapshot hashes are SHA3-256 under either.
+/// Proofs, `rebase`, and `repair` only handle SHA3-256 chains.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HashAlgorithm {
+    #[default]
+    #[serde(rename = "sha3-256")]
+    Sha3_256,
+    /// Several times faster than SHA3-256 on long inputs
+    #[serde(rename = "blake3")]
+    Blake3,
+}
+
+impl std::str::FromStr for HashAlgorithm {
+    type Err = BmsError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "sha3-256" => Ok(HashAlgorithm::Sha3_256),
+            "blake3" => Ok(HashAlgorithm::Blake3),
+            other => Err(BmsError::InvalidState(format!("Unknown hash algorithm: {}", other))),
+        }
+    }
+}
+
+impl std::fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            HashAlgorithm::Sha3_256 => "sha3-256",
+            HashAlgorithm::Blake3 => "blake3",
+        })
+    }
+}
+
+/// Layout of `MerkleChain::export_audit_log`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditLogFormat {
+    /// One JSON object per delta and a last `{"summary": ...}` object
+    #[default]
+    JsonLines,
+    /// RFC 4180 with a header row; the last row starts with `summary` and
+    /// has the root hash in the `chain_hash` column
+    Csv,
+}
+
+/// One delta of an audit log
+#[derive(Serialize)]
+struct AuditEntry<'a> {
+    delta_id: &'a DeltaId,
+    coord_id: &'a CoordId,
+    author: Option<&'a str>,
+    created_at: &'a DateTime<Utc>,
+    delta_hash: &'a Hash,
+    chain_hash: &'a Hash,
+    parent_hash: Option<&'a Hash>,
+}
+
+/// Last line of an audit log
+#[derive(Serialize)]
+struct AuditSummary<'a> {
+    entries: usize,
+    /// Chain hash of the last delta; `None` for an empty log
+    root_hash: Option<&'a Hash>,
+}
 
 /// Merkle chain for tamper-evident delta linking
 pub struct MerkleChain;
@@ -8,18 +108,32 @@ pub struct MerkleChain;
 impl MerkleChain {
     /// Compute chain hash: SHA3-256(parent_hash + current_delta_hash)
     pub fn compute_chain_hash(parent_hash: &Hash, delta_hash: &Hash) -> Hash {
-        let mut hasher = Sha3_256::new();
-        
-        // Hash concatenation of parent and current
-        hasher.update(parent_hash.0.as_bytes());
-        hasher.update(delta_hash.0.as_bytes());
-        
-        let hash = hasher.finalize();
-        Hash(hex::encode(hash))
+        Self::compute_chain_hash_with(parent_hash, delta_hash, HashAlgorithm::Sha3_256)
+    }
+
+    /// `compute_chain_hash` with `algo` in place of SHA3-256
+    pub fn compute_chain_hash_with(parent_hash: &Hash, delta_hash: &Hash, algo: HashAlgorithm) -> Hash {
+        fn chain_hash<D: Digest>(parent_hash: &Hash, delta_hash: &Hash) -> Hash {
+            let mut hasher = D::new();
+            // Hash concatenation of parent and current
+            hasher.update(parent_hash.0.as_bytes());
+            hasher.update(delta_hash.0.as_bytes());
+            Hash(hex::encode(hasher.finalize()))
+        }
+
+        match algo {
+            HashAlgorithm::Sha3_256 => chain_hash::<Sha3_256>(parent_hash, delta_hash),
+            HashAlgorithm::Blake3 => chain_hash::<blake3::Hasher>(parent_hash, delta_hash),
+        }
     }
 
     /// Verify a single delta's Merkle link
     pub fn verify_delta(delta: &Delta) -> Result<()> {
+        Self::verify_delta_with(delta, HashAlgorithm::Sha3_256)
+    }
+
+    /// `verify_delta` for a chain hashed with `algo`
+    pub fn verify_delta_with(delta: &Delta, algo: HashAlgorithm) -> Result<()> {
         // If this is the first delta (no parent), verify only delta hash
         if delta.parent_id.is_none() {
             return Ok(());
@@ -32,7 +146,7 @@ impl MerkleChain {
         })?;
 
         // Compute expected chain hash
-        let expected_chain_hash = Self::compute_chain_hash(parent_hash, &delta.delta_hash);
+        let expected_chain_hash = Self::compute_chain_hash_with(parent_hash, &delta.delta_hash, algo);
 
         // Verify it matches
         if expected_chain_hash.0 != delta.chain_hash.0 {
@@ -53,6 +167,120 @@ impl MerkleChain {
         Ok(())
     }
 
+    /// Verify a chain in two phases: every delta hash against its ops, in
+    /// parallel, then the chain hashes and parent links in order
+    ///
+    /// Checks more than `verify_chain`, which trusts the stored delta hashes
+    /// and only checks each chain hash; that stays the serial fallback. Each
+    /// delta after the first must name the one before it as its parent. The
+    /// error is that of the earliest delta failing either phase.
+    pub fn verify_chain_parallel(deltas: &[Delta]) -> Result<()> {
+        let bad_hash = deltas
+            .par_iter()
+            .enumerate()
+            .map(|(i, delta)| (i, DeltaEngine::verify_delta_hash(&delta.ops, &delta.delta_hash)))
+            .find_first(|(_, result)| result.is_err());
+        let checked = bad_hash.as_ref().map_or(deltas.len(), |(i, _)| *i);
+
+        for (i, delta) in deltas[..checked].iter().enumerate() {
+            Self::verify_delta(delta)?;
+            let Some(parent) = i.checked_sub(1).map(|p| &deltas[p]) else {
+                continue;
+            };
+            if delta.parent_id.as_ref() != Some(&parent.id) || delta.parent_hash.as_ref() != Some(&parent.chain_hash) {
+                return Err(BmsError::MerkleChainBroken {
+                    delta_id: delta.id.0.clone(),
+                });
+            }
+        }
+        bad_hash.map_or(Ok(()), |(_, result)| result)
+    }
+
+    /// Re-root a linked run of deltas at `new_parent_hash`
+    ///
+    /// The first delta gets `new_parent_hash` as its parent hash and every
+    /// chain hash is recomputed from there; each later parent hash follows
+    /// the new chain hash before it. Delta hashes are kept, and so is the
+    /// first delta's `parent_id`, which the caller points at the new parent.
+    /// If any later delta is not linked to the one before it, nothing is
+    /// changed.
+    pub fn rebase(deltas: &mut [Delta], new_parent_hash: &Hash) -> Result<()> {
+        for pair in deltas.windows(2) {
+            if pair[1].parent_hash.is_none() || pair[1].parent_id.as_ref() != Some(&pair[0].id) {
+                return Err(BmsError::MerkleChainBroken {
+                    delta_id: pair[1].id.0.clone(),
+                });
+            }
+        }
+
+        let mut parent = new_parent_hash.clone();
+        for delta in deltas.iter_mut() {
+            delta.chain_hash = Self::compute_chain_hash(&parent, &delta.delta_hash);
+            delta.parent_hash = Some(std::mem::replace(&mut parent, delta.chain_hash.clone()));
+        }
+        Ok(())
+    }
+
+    /// Recompute every chain hash of `deltas` in order from their delta
+    /// hashes, returning how many deltas had a wrong chain or parent hash
+    ///
+    /// For chains whose delta hashes are sound but whose chain hashes are
+    /// not, e.g. written by an older schema. The first delta keeps its
+    /// parent hash, if any. Every delta hash is first checked against its
+    /// ops, and if one does not match, the error is `HashMismatch` and
+    /// nothing is changed. With `dry_run`, nothing is changed either way and
+    /// the count says what a real run would correct.
+    pub fn repair(deltas: &mut [Delta], dry_run: bool) -> Result<usize> {
+        for delta in deltas.iter() {
+            DeltaEngine::verify_delta_hash(&delta.ops, &delta.delta_hash)?;
+        }
+
+        let mut corrected = 0;
+        let mut parent = deltas.first().and_then(|d| d.parent_hash.clone());
+        for delta in deltas.iter_mut() {
+            let chain_hash = match &parent {
+                Some(parent) => Self::compute_chain_hash(parent, &delta.delta_hash),
+                None => delta.delta_hash.clone(),
+            };
+            if delta.chain_hash != chain_hash || delta.parent_hash != parent {
+                corrected += 1;
+                if !dry_run {
+                    delta.chain_hash = chain_hash.clone();
+                    delta.parent_hash = parent;
+                }
+            }
+            parent = Some(chain_hash);
+        }
+        Ok(corrected)
+    }
+
+    /// Compare two delta sequences from the same root
+    ///
+    /// A chain hash covers its delta and everything before it, so deltas
+    /// are compared by chain hash alone and the first unequal pair is where
+    /// the sequences fork.
+    pub fn detect_fork(chain_a: &[Delta], chain_b: &[Delta]) -> ForkInfo {
+        let common_prefix_len = chain_a
+            .iter()
+            .zip(chain_b)
+            .take_while(|(a, b)| a.chain_hash == b.chain_hash)
+            .count();
+        ForkInfo {
+            common_prefix_len,
+            fork_point_hash: common_prefix_len.checked_sub(1).map(|last| chain_a[last].chain_hash.clone()),
+        }
+    }
+
+    /// Newest delta of `a` that `b` also has
+    ///
+    /// Deltas are matched by chain hash, which covers all history before
+    /// them, so this is the last delta before the two diverged; `None` if
+    /// they share no history.
+    pub fn find_common_ancestor<'a>(a: &'a [Delta], b: &'a [Delta]) -> Option<&'a Delta> {
+        let in_b: HashSet<&Hash> = b.iter().map(|d| &d.chain_hash).collect();
+        a.iter().rev().find(|d| in_b.contains(&d.chain_hash))
+    }
+
     /// Find the break point in a chain (for healing)
     ///
     /// Returns the index of the first broken delta, or None if chain is valid
@@ -65,10 +293,208 @@ impl MerkleChain {
         None
     }
 
+    /// Proof that `target_delta_id` is part of `deltas`, whose last chain
+    /// hash is the root
+    ///
+    /// The target and every later delta must link correctly, or the proof
+    /// could not verify.
+    pub fn generate_proof(deltas: &[Delta], target_delta_id: &DeltaId) -> Result<MerkleProof> {
+        let position = deltas
+            .iter()
+            .position(|d| &d.id == target_delta_id)
+            .ok_or_else(|| BmsError::DeltaNotFound(target_delta_id.0.clone()))?;
+        for (i, delta) in deltas.iter().enumerate().skip(position) {
+            Self::verify_delta(delta)?;
+            if i > position && delta.parent_hash.as_ref() != Some(&deltas[i - 1].chain_hash) {
+                return Err(BmsError::MerkleChainBroken {
+                    delta_id: delta.id.0.clone(),
+                });
+            }
+        }
+
+        let target = &deltas[position];
+        Ok(MerkleProof {
+            delta_id: target.id.clone(),
+            delta_hash: target.delta_hash.clone(),
+            parent_hash: target.parent_hash.clone(),
+            chain_hash_at_position: target.chain_hash.clone(),
+            sibling_hashes: deltas[position + 1..].iter().map(|d| d.delta_hash.clone()).collect(),
+        })
+    }
+
+    /// Check `proof` against the chain hash of a chain's last delta
+    ///
+    /// Fails with `MerkleChainBroken` if the proof does not hold together
+    /// and `HashMismatch` if it leads to another root.
+    pub fn verify_proof(proof: &MerkleProof, chain_root: &Hash) -> Result<()> {
+        let broken = || BmsError::MerkleChainBroken {
+            delta_id: proof.delta_id.0.clone(),
+        };
+        if proof.delta_id.0.len() != 32 || !proof.delta_hash.0.starts_with(&proof.delta_id.0) {
+            return Err(broken());
+        }
+        let at_position = match &proof.parent_hash {
+            Some(parent) => Self::compute_chain_hash(parent, &proof.delta_hash),
+            None => proof.delta_hash.clone(),
+        };
+        if at_position != proof.chain_hash_at_position {
+            return Err(broken());
+        }
+
+        let root = proof
+            .sibling_hashes
+            .iter()
+            .fold(at_position, |parent, delta_hash| Self::compute_chain_hash(&parent, delta_hash));
+        if &root != chain_root {
+            return Err(BmsError::HashMismatch {
+                expected: chain_root.0.clone(),
+                actual: root.0,
+            });
+        }
+        Ok(())
+    }
+
+    /// Write one line per delta with its hashes, then a summary line with
+    /// the root hash, to `writer`
+    ///
+    /// The log carries no ops. A verifier folds the delta hashes into chain
+    /// hashes and compares the last with the root, one comparison for the
+    /// whole log. Deltas are written as given, so verify the chain first to
+    /// log only a sound one.
+    pub fn export_audit_log(deltas: &[Delta], writer: &mut impl std::io::Write, format: AuditLogFormat) -> Result<()> {
+        let summary = AuditSummary {
+            entries: deltas.len(),
+            root_hash: deltas.last().map(|d| &d.chain_hash),
+        };
+        match format {
+            AuditLogFormat::JsonLines => {
+                for delta in deltas {
+                    let entry = AuditEntry {
+                        delta_id: &delta.id,
+                        coord_id: &delta.coord_id,
+                        author: delta.author.as_deref(),
+                        created_at: &delta.created_at,
+                        delta_hash: &delta.delta_hash,
+                        chain_hash: &delta.chain_hash,
+                        parent_hash: delta.parent_hash.as_ref(),
+                    };
+                    serde_json::to_writer(&mut *writer, &entry)?;
+                    writer.write_all(b"\n")?;
+                }
+                serde_json::to_writer(&mut *writer, &serde_json::json!({ "summary": summary }))?;
+                writer.write_all(b"\n")?;
+            }
+            AuditLogFormat::Csv => {
+                writer.write_all(b"delta_id,coord_id,author,created_at,delta_hash,chain_hash,parent_hash\r\n")?;
+                for delta in deltas {
+                    let created_at = delta.created_at.to_rfc3339_opts(SecondsFormat::AutoSi, true);
+                    let fields = [
+                        delta.id.as_str(),
+                        delta.coord_id.as_str(),
+                        delta.author.as_deref().unwrap_or(""),
+                        &created_at,
+                        delta.delta_hash.as_str(),
+                        delta.chain_hash.as_str(),
+                        delta.parent_hash.as_ref().map_or("", |h| h.as_str()),
+                    ];
+                    write_csv_row(writer, &fields)?;
+                }
+                let root = summary.root_hash.map_or("", |h| h.as_str());
+                write_csv_row(writer, &["summary", "", "", "", "", root, ""])?;
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Verify that each delta follows from its ops and from the one before it
+    ///
+    /// `verify_delta` only checks that a chain hash matches the parent hash
+    /// stored next to it, so a delta swapped in with a made-up parent hash
+    /// still passes. Here every delta hash is recomputed from the ops, and
+    /// each delta after the first must name the previous delta as its
+    /// parent, by ID and by chain hash. The first delta is checked on its
+    /// own, so `deltas` may start mid-chain. Fails with `ChainLinkBroken`
+    /// naming the link.
+    pub fn verify_linked_chain(deltas: &[Delta]) -> Result<()> {
+        Self::verify_linked_chain_with(deltas, HashAlgorithm::Sha3_256)
+    }
+
+    /// `verify_linked_chain` for a chain hashed with `algo`
+    pub fn verify_linked_chain_with(deltas: &[Delta], algo: HashAlgorithm) -> Result<()> {
+        match Self::verify_linked_chain_integrity_with(deltas, algo) {
+            (_, Some(e)) => Err(e),
+            (_, None) => Ok(()),
+        }
+    }
+
+    /// `verify_linked_chain` returning the number of deltas that check out
+    /// before the first one that does not, as `verify_chain_integrity` does
+    pub fn verify_linked_chain_integrity_with(deltas: &[Delta], algo: HashAlgorithm) -> (usize, Option<BmsError>) {
+        let mut previous: Option<&Delta> = None;
+        for (idx, delta) in deltas.iter().enumerate() {
+            if let Some(link) = Self::broken_link(previous, delta, algo) {
+                return (
+                    idx,
+                    Some(BmsError::ChainLinkBroken {
+                        delta_id: delta.id.0.clone(),
+                        link,
+                    }),
+                );
+            }
+            previous = Some(delta);
+        }
+        (deltas.len(), None)
+    }
+
+    /// What keeps `delta` from following `previous`, if anything
+    fn broken_link(previous: Option<&Delta>, delta: &Delta, algo: HashAlgorithm) -> Option<String> {
+        match DeltaEngine::hash_delta(&delta.ops) {
+            Ok(hash) if hash == delta.delta_hash => {}
+            Ok(hash) => {
+                return Some(format!("delta_hash {} does not match its ops, which hash to {}", delta.delta_hash, hash))
+            }
+            Err(e) => return Some(format!("ops do not hash: {}", e)),
+        }
+
+        if let Some(previous) = previous {
+            match &delta.parent_id {
+                Some(parent_id) if parent_id == &previous.id => {}
+                Some(parent_id) => {
+                    return Some(format!("parent_id {} is not the previous delta {}", parent_id, previous.id))
+                }
+                None => return Some(format!("no parent_id, but follows delta {}", previous.id)),
+            }
+            match &delta.parent_hash {
+                Some(parent_hash) if parent_hash == &previous.chain_hash => {}
+                Some(parent_hash) => {
+                    return Some(format!(
+                        "parent_hash {} is not the chain_hash {} of delta {}",
+                        parent_hash, previous.chain_hash, previous.id
+                    ))
+                }
+                None => return Some(format!("no parent_hash, but follows delta {}", previous.id)),
+            }
+        }
+
+        let expected = match &delta.parent_hash {
+            Some(parent_hash) => Self::compute_chain_hash_with(parent_hash, &delta.delta_hash, algo),
+            None => delta.delta_hash.clone(),
+        };
+        (expected != delta.chain_hash).then(|| {
+            format!("chain_hash {} is not {}, hashed from its parent_hash and delta_hash", delta.chain_hash, expected)
+        })
+    }
+
     /// Verify chain integrity and return verified length
     pub fn verify_chain_integrity(deltas: &[Delta]) -> (usize, Option<BmsError>) {
+        Self::verify_chain_integrity_with(deltas, HashAlgorithm::Sha3_256)
+    }
+
+    /// `verify_chain_integrity` for a chain hashed with `algo`
+    pub fn verify_chain_integrity_with(deltas: &[Delta], algo: HashAlgorithm) -> (usize, Option<BmsError>) {
         for (idx, delta) in deltas.iter().enumerate() {
-            if let Err(e) = Self::verify_delta(delta) {
+            if let Err(e) = Self::verify_delta_with(delta, algo) {
                 return (idx, Some(e));
             }
         }
@@ -76,10 +502,25 @@ impl MerkleChain {
     }
 }
 
+/// Write `fields` as one CSV row, quoting those that need it
+fn write_csv_row(writer: &mut impl std::io::Write, fields: &[&str]) -> std::io::Result<()> {
+    for (i, field) in fields.iter().enumerate() {
+        if i > 0 {
+            writer.write_all(b",")?;
+        }
+        if field.contains([',', '"', '\r', '\n']) {
+            write!(writer, "\"{}\"", field.replace('"', "\"\""))?;
+        } else {
+            writer.write_all(field.as_bytes())?;
+        }
+    }
+    writer.write_all(b"\r\n")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::types::{CoordId, DeltaId};
+    use crate::types::CoordId;
     use chrono::Utc;
 
     fn mock_delta(
@@ -107,6 +548,8 @@ mod tests {
             created_at: Utc::now(),
             tags: None,
             author: None,
+            op_authors: None,
+            signature: None,
         }
     }
 
@@ -121,6 +564,37 @@ mod tests {
         assert_eq!(chain_hash.0.len(), 64); // SHA3-256 hex = 64 chars
     }
 
+    #[test]
+    fn test_chain_hash_algorithms() {
+        let parent = Hash("abc123".to_string());
+        let current = Hash("def456".to_string());
+        assert_eq!(
+            MerkleChain::compute_chain_hash_with(&parent, &current, HashAlgorithm::Sha3_256),
+            MerkleChain::compute_chain_hash(&parent, &current)
+        );
+        let blake3 = MerkleChain::compute_chain_hash_with(&parent, &current, HashAlgorithm::Blake3);
+        assert_eq!(blake3.0, blake3::hash(b"abc123def456").to_hex().as_str());
+
+        // A chain verifies under its own algorithm only
+        let mut deltas = real_chain(3);
+        for i in 1..deltas.len() {
+            let parent_hash = deltas[i - 1].chain_hash.clone();
+            deltas[i].chain_hash =
+                MerkleChain::compute_chain_hash_with(&parent_hash, &deltas[i].delta_hash, HashAlgorithm::Blake3);
+            deltas[i].parent_hash = Some(parent_hash);
+        }
+        let (verified, error) = MerkleChain::verify_chain_integrity_with(&deltas, HashAlgorithm::Blake3);
+        assert_eq!(verified, 3);
+        assert!(error.is_none());
+        assert_eq!(MerkleChain::verify_chain_integrity(&deltas).0, 1);
+
+        for algo in [HashAlgorithm::Sha3_256, HashAlgorithm::Blake3] {
+            assert_eq!(algo.to_string().parse::<HashAlgorithm>().unwrap(), algo);
+            assert_eq!(serde_json::to_value(algo).unwrap(), algo.to_string());
+        }
+        assert!("md5".parse::<HashAlgorithm>().is_err());
+    }
+
     #[test]
     fn test_verify_first_delta() {
         let delta = mock_delta("d1", "c1", None, None, "hash1");
@@ -168,4 +642,296 @@ mod tests {
         let break_point = MerkleChain::find_break_point(&deltas);
         assert_eq!(break_point, Some(1)); // Second delta is broken
     }
+
+    /// Chain of `n` deltas with real delta hashes and IDs
+    fn real_chain(n: usize) -> Vec<Delta> {
+        let mut deltas: Vec<Delta> = Vec::new();
+        for i in 0..n {
+            let ops: Vec<json_patch::PatchOperation> =
+                serde_json::from_value(serde_json::json!([{"op": "add", "path": "/n", "value": i}])).unwrap();
+            let delta_hash = DeltaEngine::hash_delta(&ops).unwrap();
+            let mut delta = mock_delta("", "c1", None, None, &delta_hash.0);
+            if let Some(parent) = deltas.last() {
+                delta.parent_id = Some(parent.id.clone());
+                delta.parent_hash = Some(parent.chain_hash.clone());
+                delta.chain_hash = MerkleChain::compute_chain_hash(&parent.chain_hash, &delta_hash);
+            }
+            delta.id = DeltaEngine::generate_delta_id(&ops).unwrap();
+            delta.ops = ops;
+            deltas.push(delta);
+        }
+        deltas
+    }
+
+    #[test]
+    fn test_membership_proofs() {
+        let deltas = real_chain(4);
+        let root = &deltas[3].chain_hash;
+        for delta in &deltas {
+            let proof = MerkleChain::generate_proof(&deltas, &delta.id).unwrap();
+            let json = serde_json::to_value(&proof).unwrap();
+            let proof: MerkleProof = serde_json::from_value(json).unwrap();
+            MerkleChain::verify_proof(&proof, root).unwrap();
+        }
+
+        let proof = MerkleChain::generate_proof(&deltas, &deltas[1].id).unwrap();
+        assert_eq!((proof.sibling_hashes.len(), proof.parent_hash.as_ref()), (2, Some(&deltas[0].chain_hash)));
+        // Against an older root, or with a sibling dropped or swapped
+        assert!(matches!(
+            MerkleChain::verify_proof(&proof, &deltas[2].chain_hash),
+            Err(BmsError::HashMismatch { .. })
+        ));
+        let mut short = proof.clone();
+        short.sibling_hashes.pop();
+        assert!(MerkleChain::verify_proof(&short, root).is_err());
+        let mut swapped = proof.clone();
+        swapped.sibling_hashes.swap(0, 1);
+        assert!(MerkleChain::verify_proof(&swapped, root).is_err());
+        // The ID must belong to the hashes the proof carries
+        let mut renamed = proof.clone();
+        renamed.delta_id = deltas[2].id.clone();
+        assert!(matches!(
+            MerkleChain::verify_proof(&renamed, root),
+            Err(BmsError::MerkleChainBroken { .. })
+        ));
+        let mut moved = proof;
+        moved.chain_hash_at_position = deltas[2].chain_hash.clone();
+        assert!(MerkleChain::verify_proof(&moved, root).is_err());
+
+        assert!(matches!(
+            MerkleChain::generate_proof(&deltas, &DeltaId("missing".to_string())),
+            Err(BmsError::DeltaNotFound(_))
+        ));
+        // Only the target and later deltas need to link
+        let mut broken = deltas.clone();
+        broken[2].chain_hash = Hash("corrupted".to_string());
+        assert!(MerkleChain::generate_proof(&broken, &deltas[1].id).is_err());
+        assert!(MerkleChain::generate_proof(&broken, &deltas[3].id).is_ok());
+    }
+
+    #[test]
+    fn test_verify_chain_parallel() {
+        let deltas = real_chain(1000);
+        MerkleChain::verify_chain_parallel(&deltas).unwrap();
+        MerkleChain::verify_chain_parallel(&deltas[500..]).unwrap();
+        MerkleChain::verify_chain_parallel(&[]).unwrap();
+
+        // Ops that no longer match their hash pass the serial check only
+        let mut tampered = deltas.clone();
+        tampered[700].ops = real_chain(1)[0].ops.clone();
+        MerkleChain::verify_chain(&tampered).unwrap();
+        assert!(matches!(
+            MerkleChain::verify_chain_parallel(&tampered),
+            Err(BmsError::HashMismatch { .. })
+        ));
+
+        // The earlier of a bad link and a bad hash is reported
+        tampered.remove(300);
+        match MerkleChain::verify_chain_parallel(&tampered) {
+            Err(BmsError::MerkleChainBroken { delta_id }) => assert_eq!(delta_id, deltas[301].id.0),
+            other => panic!("{:?}", other),
+        }
+        let mut relinked = deltas.clone();
+        relinked[10].chain_hash = Hash("corrupted".to_string());
+        assert!(matches!(
+            MerkleChain::verify_chain_parallel(&relinked),
+            Err(BmsError::HashMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_verify_linked_chain() {
+        let deltas = real_chain(6);
+        MerkleChain::verify_linked_chain(&deltas).unwrap();
+        MerkleChain::verify_linked_chain(&deltas[3..]).unwrap();
+        MerkleChain::verify_linked_chain(&[]).unwrap();
+        let link = |chain: &[Delta]| {
+            match MerkleChain::verify_linked_chain_integrity_with(chain, HashAlgorithm::Sha3_256) {
+                (idx, Some(BmsError::ChainLinkBroken { delta_id, link })) => {
+                    assert_eq!(delta_id, chain[idx].id.0);
+                    (idx, link)
+                }
+                other => panic!("{:?}", other),
+            }
+        };
+
+        // A middle delta swapped for one with a made-up parent hash is
+        // consistent on its own, but the next delta does not follow it
+        let mut forged = deltas.clone();
+        let ops: Vec<json_patch::PatchOperation> =
+            serde_json::from_value(serde_json::json!([{"op": "add", "path": "/forged", "value": true}])).unwrap();
+        forged[2].delta_hash = DeltaEngine::hash_delta(&ops).unwrap();
+        forged[2].ops = ops;
+        forged[2].parent_hash = Some(Hash("fabricated".to_string()));
+        forged[2].chain_hash = MerkleChain::compute_chain_hash(&Hash("fabricated".to_string()), &forged[2].delta_hash);
+        MerkleChain::verify_chain(&forged).unwrap();
+        let (idx, reason) = link(&forged);
+        assert_eq!(idx, 2);
+        assert!(reason.starts_with("parent_hash fabricated is not the chain_hash"), "{}", reason);
+
+        let mut skipped = deltas.clone();
+        skipped.remove(3);
+        let (idx, reason) = link(&skipped);
+        assert_eq!(idx, 3);
+        assert!(reason.starts_with("parent_id"), "{}", reason);
+
+        let mut tampered = deltas.clone();
+        tampered[4].ops = deltas[0].ops.clone();
+        let (idx, reason) = link(&tampered);
+        assert_eq!(idx, 4);
+        assert!(reason.contains("does not match its ops"), "{}", reason);
+
+        let mut rehashed = deltas.clone();
+        rehashed[0].chain_hash = Hash("corrupted".to_string());
+        let (idx, reason) = link(&rehashed);
+        assert_eq!(idx, 0);
+        assert!(reason.starts_with("chain_hash corrupted"), "{}", reason);
+        // The per-delta check skips the root
+        assert!(MerkleChain::verify_chain_integrity(&rehashed).1.is_none());
+    }
+
+    #[test]
+    fn test_rebase_reroots_a_segment() {
+        let base = real_chain(3);
+        let mut branch = real_chain(6);
+        let delta_hashes: Vec<Hash> = branch.iter().map(|d| d.delta_hash.clone()).collect();
+
+        let mut tail = branch.split_off(2);
+        tail[0].parent_id = Some(base[2].id.clone());
+        MerkleChain::rebase(&mut tail, &base[2].chain_hash).unwrap();
+        assert_eq!(tail[0].parent_hash.as_ref(), Some(&base[2].chain_hash));
+        assert!(tail.iter().map(|d| &d.delta_hash).eq(&delta_hashes[2..]));
+        let joined: Vec<Delta> = base.iter().chain(&tail).cloned().collect();
+        MerkleChain::verify_chain_parallel(&joined).unwrap();
+        MerkleChain::rebase(&mut [], &base[2].chain_hash).unwrap();
+
+        // An unlinked delta mid-segment leaves the slice as it was
+        let mut unlinked = tail.clone();
+        unlinked[2].parent_hash = None;
+        let before = unlinked.clone();
+        assert!(matches!(
+            MerkleChain::rebase(&mut unlinked, &base[0].chain_hash),
+            Err(BmsError::MerkleChainBroken { delta_id }) if delta_id == tail[2].id.0
+        ));
+        let unchanged = |(a, b): (&Delta, &Delta)| a.chain_hash == b.chain_hash && a.parent_hash == b.parent_hash;
+        assert!(unlinked.iter().zip(&before).all(unchanged));
+    }
+
+    #[test]
+    fn test_repair_recomputes_chain_hashes() {
+        let good = real_chain(5);
+        let mut deltas = good.clone();
+        MerkleChain::repair(&mut deltas, false).unwrap();
+        assert_eq!(MerkleChain::repair(&mut deltas, true).unwrap(), 0);
+
+        // A wrong chain hash that the next delta copied as its parent hash
+        deltas[1].chain_hash = Hash("old".to_string());
+        deltas[2].parent_hash = Some(Hash("old".to_string()));
+        deltas[3].chain_hash = Hash("old".to_string());
+        let before = deltas.clone();
+        let same = |a: &[Delta], b: &[Delta]| {
+            a.iter().zip(b).all(|(a, b)| a.chain_hash == b.chain_hash && a.parent_hash == b.parent_hash)
+        };
+        assert_eq!(MerkleChain::repair(&mut deltas, true).unwrap(), 3);
+        assert!(same(&deltas, &before));
+        assert_eq!(MerkleChain::repair(&mut deltas, false).unwrap(), 3);
+        assert!(same(&deltas, &good));
+        MerkleChain::verify_chain_parallel(&deltas).unwrap();
+
+        // A segment keeps the parent hash it starts from
+        let mut tail = deltas[2..].to_vec();
+        tail[1].chain_hash = Hash("old".to_string());
+        assert_eq!(MerkleChain::repair(&mut tail, false).unwrap(), 1);
+        assert_eq!(tail[2].chain_hash, good[4].chain_hash);
+
+        for delta_hash in ["", "not hex", &good[0].delta_hash.0] {
+            let mut broken = good.clone();
+            broken[2].delta_hash = Hash(delta_hash.to_string());
+            broken[1].chain_hash = Hash("old".to_string());
+            assert!(matches!(MerkleChain::repair(&mut broken, false), Err(BmsError::HashMismatch { .. })));
+            assert_eq!(broken[1].chain_hash.0, "old");
+        }
+    }
+
+    #[test]
+    fn test_export_audit_log() {
+        let mut deltas = real_chain(3);
+        deltas[1].author = Some("Smith, \"J\"".to_string());
+        let root = deltas[2].chain_hash.clone();
+
+        let mut out = Vec::new();
+        MerkleChain::export_audit_log(&deltas, &mut out, AuditLogFormat::JsonLines).unwrap();
+        let lines: Vec<serde_json::Value> =
+            String::from_utf8(out).unwrap().lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0]["delta_id"], deltas[0].id.0);
+        assert_eq!(lines[0]["parent_hash"], serde_json::Value::Null);
+        assert_eq!(lines[1]["author"], "Smith, \"J\"");
+        assert_eq!(lines[2]["parent_hash"], deltas[1].chain_hash.0);
+        assert_eq!(lines[3], serde_json::json!({"summary": {"entries": 3, "root_hash": root.0}}));
+
+        // Folding the logged delta hashes reaches the root
+        let folded = lines[1..3].iter().fold(Hash(lines[0]["chain_hash"].as_str().unwrap().to_string()), |parent, l| {
+            MerkleChain::compute_chain_hash(&parent, &Hash(l["delta_hash"].as_str().unwrap().to_string()))
+        });
+        assert_eq!(folded, root);
+
+        let mut out = Vec::new();
+        MerkleChain::export_audit_log(&deltas, &mut out, AuditLogFormat::Csv).unwrap();
+        let csv = String::from_utf8(out).unwrap();
+        let rows: Vec<&str> = csv.split_terminator("\r\n").collect();
+        assert_eq!(rows.len(), 5);
+        assert_eq!(rows[0], "delta_id,coord_id,author,created_at,delta_hash,chain_hash,parent_hash");
+        assert!(rows[2].contains(",\"Smith, \"\"J\"\"\","), "{}", rows[2]);
+        assert!(rows[1].ends_with(&format!("{},", deltas[0].chain_hash.0)));
+        assert_eq!(rows[4], format!("summary,,,,,{},", root.0));
+
+        let mut out = Vec::new();
+        MerkleChain::export_audit_log(&[], &mut out, AuditLogFormat::JsonLines).unwrap();
+        assert_eq!(out, b"{\"summary\":{\"entries\":0,\"root_hash\":null}}\n");
+    }
+
+    #[test]
+    fn test_detect_fork() {
+        let chain = real_chain(5);
+        let extension = MerkleChain::detect_fork(&chain, &chain[..3]);
+        assert_eq!(extension.common_prefix_len, 3);
+        assert_eq!(extension.fork_point_hash.as_ref(), Some(&chain[2].chain_hash));
+        assert_eq!(MerkleChain::detect_fork(&chain[..3], &chain), extension);
+        let same = MerkleChain::detect_fork(&chain, &chain);
+        assert_eq!((same.common_prefix_len, same.fork_point_hash), (5, Some(chain[4].chain_hash.clone())));
+
+        // A concurrent writer appended something else after the third delta
+        let mut other = chain[..3].to_vec();
+        let mut concurrent = real_chain(7)[5..].to_vec();
+        concurrent[0].parent_id = Some(chain[2].id.clone());
+        MerkleChain::rebase(&mut concurrent, &chain[2].chain_hash).unwrap();
+        other.extend(concurrent);
+        let fork = MerkleChain::detect_fork(&chain, &other);
+        assert_eq!(fork, extension);
+        assert!(fork.common_prefix_len < chain.len().min(other.len()));
+
+        let unrelated = MerkleChain::detect_fork(&chain, &real_chain(3)[1..]);
+        assert_eq!(unrelated, ForkInfo { common_prefix_len: 0, fork_point_hash: None });
+        assert_eq!(MerkleChain::detect_fork(&[], &chain).common_prefix_len, 0);
+    }
+
+    #[test]
+    fn test_find_common_ancestor() {
+        let chain = real_chain(6);
+        let mut branch = chain[..4].to_vec();
+        let mut concurrent = real_chain(9)[6..].to_vec();
+        concurrent[0].parent_id = Some(chain[3].id.clone());
+        MerkleChain::rebase(&mut concurrent, &chain[3].chain_hash).unwrap();
+        branch.extend(concurrent);
+
+        let ancestor = |a: &[Delta], b: &[Delta]| MerkleChain::find_common_ancestor(a, b).map(|d| d.id.clone());
+        assert_eq!(ancestor(&chain, &branch), Some(chain[3].id.clone()));
+        assert_eq!(ancestor(&branch, &chain), Some(chain[3].id.clone()));
+        assert_eq!(ancestor(&chain, &chain[..2]), Some(chain[1].id.clone()));
+        // Only the later part of one history was fetched
+        assert_eq!(ancestor(&chain[3..], &branch), Some(chain[3].id.clone()));
+        assert_eq!(ancestor(&chain[4..], &branch), None);
+        assert_eq!(ancestor(&chain, &[]), None);
+    }
 }
@@ -0,0 +1,219 @@
+//! Limits on the deltas a writer may store
+//!
+//! A `DeltaPolicy` bounds how many ops a delta has, which kinds they are,
+//! and how large the values they carry and the state they produce are, all
+//! by canonical size. The default limits nothing.
+
+use crate::canonical::Canonicalizer;
+use crate::error::{BmsError, Result};
+use json_patch::PatchOperation;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+
+/// Kind of a JSON Patch op
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OpKind {
+    Add,
+    Remove,
+    Replace,
+    Move,
+    Copy,
+    Test,
+}
+
+impl OpKind {
+    pub const ALL: [OpKind; 6] = [
+        OpKind::Add,
+        OpKind::Remove,
+        OpKind::Replace,
+        OpKind::Move,
+        OpKind::Copy,
+        OpKind::Test,
+    ];
+
+    pub fn of(op: &PatchOperation) -> Self {
+        match op {
+            PatchOperation::Add(_) => OpKind::Add,
+            PatchOperation::Remove(_) => OpKind::Remove,
+            PatchOperation::Replace(_) => OpKind::Replace,
+            PatchOperation::Move(_) => OpKind::Move,
+            PatchOperation::Copy(_) => OpKind::Copy,
+            PatchOperation::Test(_) => OpKind::Test,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            OpKind::Add => "add",
+            OpKind::Remove => "remove",
+            OpKind::Replace => "replace",
+            OpKind::Move => "move",
+            OpKind::Copy => "copy",
+            OpKind::Test => "test",
+        }
+    }
+}
+
+impl fmt::Display for OpKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for OpKind {
+    type Err = BmsError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::ALL.into_iter().find(|kind| kind.as_str() == s).ok_or_else(|| {
+            BmsError::InvalidState(format!(
+                "Unknown op kind {:?}; expected add, remove, replace, move, copy, or test",
+                s
+            ))
+        })
+    }
+}
+
+/// What a delta may contain; `None` limits are off
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeltaPolicy {
+    /// Most ops in one delta
+    pub max_ops: Option<usize>,
+    /// Largest state a delta may produce, in canonical bytes
+    pub max_state_bytes: Option<u64>,
+    /// Largest value one op may carry, in canonical bytes
+    pub max_value_bytes: Option<u64>,
+    pub allowed_ops: HashSet<OpKind>,
+}
+
+impl Default for DeltaPolicy {
+    fn default() -> Self {
+        Self {
+            max_ops: None,
+            max_state_bytes: None,
+            max_value_bytes: None,
+            allowed_ops: OpKind::ALL.into_iter().collect(),
+        }
+    }
+}
+
+impl DeltaPolicy {
+    /// Check `state` against `max_state_bytes`
+    ///
+    /// Needs no ops, so a writer can refuse an oversized state before
+    /// diffing it.
+    pub fn check_state(&self, state: &Value) -> Result<()> {
+        let Some(max) = self.max_state_bytes else {
+            return Ok(());
+        };
+        let bytes = Canonicalizer::canonical_len(state)? as u64;
+        if bytes > max {
+            return Err(BmsError::PolicyViolation(format!(
+                "state is {} bytes, more than the {} allowed",
+                bytes, max
+            )));
+        }
+        Ok(())
+    }
+
+    /// Check `ops` and the `state` they produce; fails with
+    /// `PolicyViolation` naming the first limit broken
+    pub fn validate(&self, ops: &[PatchOperation], state: &Value) -> Result<()> {
+        if let Some(max) = self.max_ops.filter(|&max| ops.len() > max) {
+            return Err(BmsError::PolicyViolation(format!(
+                "delta has {} ops, more than the {} allowed",
+                ops.len(),
+                max
+            )));
+        }
+        for (index, op) in ops.iter().enumerate() {
+            let kind = OpKind::of(op);
+            if !self.allowed_ops.contains(&kind) {
+                return Err(BmsError::PolicyViolation(format!(
+                    "op {} at {} is a {} op, which is not allowed",
+                    index,
+                    op.path(),
+                    kind
+                )));
+            }
+            let value = match op {
+                PatchOperation::Add(op) => Some(&op.value),
+                PatchOperation::Replace(op) => Some(&op.value),
+                PatchOperation::Test(op) => Some(&op.value),
+                _ => None,
+            };
+            if let (Some(max), Some(value)) = (self.max_value_bytes, value) {
+                let bytes = Canonicalizer::canonical_len(value)? as u64;
+                if bytes > max {
+                    return Err(BmsError::PolicyViolation(format!(
+                        "op {} at {} carries {} bytes, more than the {} allowed",
+                        index,
+                        op.path(),
+                        bytes,
+                        max
+                    )));
+                }
+            }
+        }
+        self.check_state(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn ops(v: Value) -> Vec<PatchOperation> {
+        serde_json::from_value(v).unwrap()
+    }
+
+    #[test]
+    fn test_policy_limits() {
+        let patch = ops(json!([
+            {"op": "add", "path": "/a", "value": "x".repeat(20)},
+            {"op": "move", "from": "/a", "path": "/b"},
+            {"op": "remove", "path": "/c"},
+        ]));
+        let state = json!({"b": "x".repeat(20)});
+        DeltaPolicy::default().validate(&patch, &state).unwrap();
+
+        let violation = |policy: DeltaPolicy| match policy.validate(&patch, &state) {
+            Err(BmsError::PolicyViolation(reason)) => reason,
+            other => panic!("{:?}", other),
+        };
+        let reason = violation(DeltaPolicy { max_ops: Some(2), ..Default::default() });
+        assert_eq!(reason, "delta has 3 ops, more than the 2 allowed");
+        let no_moves = DeltaPolicy {
+            allowed_ops: [OpKind::Add, OpKind::Remove].into(),
+            ..Default::default()
+        };
+        assert_eq!(violation(no_moves), "op 1 at /b is a move op, which is not allowed");
+        // 20 characters and their quotes
+        let reason = violation(DeltaPolicy { max_value_bytes: Some(21), ..Default::default() });
+        assert_eq!(reason, "op 0 at /a carries 22 bytes, more than the 21 allowed");
+        let reason = violation(DeltaPolicy { max_state_bytes: Some(10), ..Default::default() });
+        assert!(reason.starts_with("state is 28 bytes"), "{}", reason);
+
+        let exact = DeltaPolicy {
+            max_ops: Some(3),
+            max_value_bytes: Some(22),
+            max_state_bytes: Some(28),
+            ..Default::default()
+        };
+        exact.validate(&patch, &state).unwrap();
+    }
+
+    #[test]
+    fn test_op_kind_names() {
+        for kind in OpKind::ALL {
+            assert_eq!(kind.to_string().parse::<OpKind>().unwrap(), kind);
+            assert_eq!(serde_json::to_value(kind).unwrap(), json!(kind.as_str()));
+        }
+        assert!("splice".parse::<OpKind>().is_err());
+        assert!("Add".parse::<OpKind>().is_err());
+    }
+}
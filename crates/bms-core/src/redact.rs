@@ -0,0 +1,323 @@
+use crate::error::{BmsError, Result};
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+/// Replacement value for masked fields
+pub const REDACTED: &str = "[redacted]";
+
+/// Coordinate metadata key holding the redaction pointers
+pub const REDACT_METADATA_KEY: &str = "redact";
+
+/// Coordinate metadata key selecting the redaction mode (`"mask"` or `"remove"`)
+pub const REDACT_MODE_METADATA_KEY: &str = "redact_mode";
+
+/// How redacted fields are presented
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RedactMode {
+    /// Replace the value with `"[redacted]"`
+    #[default]
+    Mask,
+    /// Remove the field or array element
+    Remove,
+}
+
+/// Set of JSON Pointers to hide from readers
+///
+/// A `*` segment matches every member of an object or element of an array.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RedactionRules {
+    pub pointers: Vec<String>,
+    pub mode: RedactMode,
+}
+
+/// Concrete step of a resolved JSON Pointer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+impl Segment {
+    fn cmp_desc(a: &[Segment], b: &[Segment]) -> Ordering {
+        for (x, y) in a.iter().zip(b) {
+            let ord = match (x, y) {
+                (Segment::Index(x), Segment::Index(y)) => y.cmp(x),
+                (Segment::Key(x), Segment::Key(y)) => y.cmp(x),
+                (Segment::Index(_), Segment::Key(_)) => Ordering::Greater,
+                (Segment::Key(_), Segment::Index(_)) => Ordering::Less,
+            };
+            if ord != Ordering::Equal {
+                return ord;
+            }
+        }
+        b.len().cmp(&a.len())
+    }
+}
+
+impl RedactionRules {
+    pub fn new(pointers: Vec<String>, mode: RedactMode) -> Result<Self> {
+        for pointer in &pointers {
+            if !pointer.starts_with('/') {
+                return Err(BmsError::InvalidState(format!(
+                    "Redaction rule must be a non-empty JSON Pointer: {:?}",
+                    pointer
+                )));
+            }
+        }
+        Ok(Self { pointers, mode })
+    }
+
+    /// Read rules from coordinate metadata
+    ///
+    /// Returns `None` when the metadata has no `redact` key.
+    pub fn from_metadata(metadata: &HashMap<String, Value>) -> Result<Option<Self>> {
+        let Some(rules) = metadata.get(REDACT_METADATA_KEY) else {
+            return Ok(None);
+        };
+
+        let pointers = rules
+            .as_array()
+            .and_then(|a| a.iter().map(|v| v.as_str().map(String::from)).collect())
+            .ok_or_else(|| {
+                BmsError::InvalidState("redact must be an array of JSON Pointers".to_string())
+            })?;
+
+        let mode = match metadata.get(REDACT_MODE_METADATA_KEY).and_then(Value::as_str) {
+            None | Some("mask") => RedactMode::Mask,
+            Some("remove") => RedactMode::Remove,
+            Some(other) => {
+                return Err(BmsError::InvalidState(format!("Unknown redact_mode: {}", other)))
+            }
+        };
+
+        Self::new(pointers, mode).map(Some)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pointers.is_empty()
+    }
+
+    /// Whether `pointer` names a redacted field or something inside one
+    pub fn covers(&self, pointer: &str) -> bool {
+        if pointer.is_empty() {
+            return false;
+        }
+        let path = pointer_tokens(pointer);
+        self.pointers.iter().any(|rule| {
+            let rule = pointer_tokens(rule);
+            rule.len() <= path.len() && rule.iter().zip(&path).all(|(r, p)| r == "*" || r == p)
+        })
+    }
+
+    /// Whether a patch op reads or writes a redacted field
+    pub fn touches(&self, op: &json_patch::PatchOperation) -> bool {
+        let from = match op {
+            json_patch::PatchOperation::Move(mv) => Some(&mv.from),
+            json_patch::PatchOperation::Copy(copy) => Some(&copy.from),
+            _ => None,
+        };
+        self.covers(op.path().as_str()) || from.is_some_and(|from| self.covers(from.as_str()))
+    }
+}
+
+/// Return a copy of `state` with every field matched by `rules` redacted
+///
+/// The stored state is never modified, so hashes are unaffected. Pointers
+/// that match nothing are ignored.
+pub fn redact(state: &Value, rules: &RedactionRules) -> Value {
+    let mut redacted = state.clone();
+    if rules.is_empty() {
+        return redacted;
+    }
+
+    // Resolve every rule against the original document first so overlapping
+    // rules and array removals don't shift each other's targets
+    let mut paths = Vec::new();
+    for pointer in &rules.pointers {
+        collect_paths(state, &pointer_tokens(pointer), &mut Vec::new(), &mut paths);
+    }
+    paths.sort_by(|a, b| Segment::cmp_desc(a, b));
+    paths.dedup();
+
+    for path in &paths {
+        let Some((last, parent_path)) = path.split_last() else {
+            continue;
+        };
+        let Some(parent) = lookup_mut(&mut redacted, parent_path) else {
+            continue; // An enclosing field was already redacted
+        };
+
+        match (rules.mode, parent, last) {
+            (RedactMode::Mask, Value::Object(map), Segment::Key(key)) => {
+                if let Some(v) = map.get_mut(key) {
+                    *v = Value::String(REDACTED.to_string());
+                }
+            }
+            (RedactMode::Mask, Value::Array(items), Segment::Index(i)) => {
+                if let Some(v) = items.get_mut(*i) {
+                    *v = Value::String(REDACTED.to_string());
+                }
+            }
+            (RedactMode::Remove, Value::Object(map), Segment::Key(key)) => {
+                map.remove(key);
+            }
+            (RedactMode::Remove, Value::Array(items), Segment::Index(i)) if *i < items.len() => {
+                items.remove(*i);
+            }
+            _ => {}
+        }
+    }
+
+    redacted
+}
+
+/// Decoded tokens of a pointer that starts with `/`
+pub(crate) fn pointer_tokens(pointer: &str) -> Vec<String> {
+    pointer[1..]
+        .split('/')
+        .map(|t| t.replace("~1", "/").replace("~0", "~"))
+        .collect()
+}
+
+/// Every concrete path in `value` matching `tokens`, where `*` matches any
+/// object member or array element
+pub(crate) fn collect_paths(
+    value: &Value,
+    tokens: &[String],
+    prefix: &mut Vec<Segment>,
+    out: &mut Vec<Vec<Segment>>,
+) {
+    let Some((token, rest)) = tokens.split_first() else {
+        out.push(prefix.clone());
+        return;
+    };
+
+    let children: Vec<(Segment, &Value)> = match value {
+        Value::Object(map) if token == "*" => map
+            .iter()
+            .map(|(k, v)| (Segment::Key(k.clone()), v))
+            .collect(),
+        Value::Object(map) => map
+            .get(token)
+            .map(|v| vec![(Segment::Key(token.clone()), v)])
+            .unwrap_or_default(),
+        Value::Array(items) if token == "*" => items
+            .iter()
+            .enumerate()
+            .map(|(i, v)| (Segment::Index(i), v))
+            .collect(),
+        Value::Array(items) => token
+            .parse::<usize>()
+            .ok()
+            .and_then(|i| items.get(i).map(|v| vec![(Segment::Index(i), v)]))
+            .unwrap_or_default(),
+        _ => Vec::new(),
+    };
+
+    for (segment, child) in children {
+        prefix.push(segment);
+        collect_paths(child, rest, prefix, out);
+        prefix.pop();
+    }
+}
+
+fn lookup_mut<'a>(value: &'a mut Value, path: &[Segment]) -> Option<&'a mut Value> {
+    path.iter().try_fold(value, |current, segment| match (current, segment) {
+        (Value::Object(map), Segment::Key(key)) => map.get_mut(key),
+        (Value::Array(items), Segment::Index(i)) => items.get_mut(*i),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn rules(pointers: &[&str], mode: RedactMode) -> RedactionRules {
+        RedactionRules::new(pointers.iter().map(|p| p.to_string()).collect(), mode).unwrap()
+    }
+
+    #[test]
+    fn test_covers_fields_under_a_rule() {
+        let rules = rules(&["/secrets", "/users/*/token"], RedactMode::Mask);
+        for (pointer, covered) in [
+            ("/secrets", true),
+            ("/secrets/aws/key", true),
+            ("/secret", false),
+            ("/users/3/token", true),
+            ("/users/-/token/x", true),
+            ("/users/3/name", false),
+            ("/users", false),
+            ("", false),
+        ] {
+            assert_eq!(rules.covers(pointer), covered, "{}", pointer);
+        }
+
+        let ops: Vec<json_patch::PatchOperation> = serde_json::from_value(json!([
+            {"op": "copy", "from": "/secrets/key", "path": "/leak"},
+            {"op": "add", "path": "/users/0/token", "value": "t"},
+            {"op": "move", "from": "/a", "path": "/b"},
+        ]))
+        .unwrap();
+        let touched: Vec<bool> = ops.iter().map(|op| rules.touches(op)).collect();
+        assert_eq!(touched, [true, true, false]);
+    }
+
+    #[test]
+    fn test_redact_nested_paths() {
+        let state = json!({"user": {"name": "ada", "email": "ada@example.com"}, "n": 1});
+
+        let masked = redact(&state, &rules(&["/user/email", "/missing/path"], RedactMode::Mask));
+        assert_eq!(masked, json!({"user": {"name": "ada", "email": "[redacted]"}, "n": 1}));
+
+        let removed = redact(&state, &rules(&["/user/email"], RedactMode::Remove));
+        assert_eq!(removed, json!({"user": {"name": "ada"}, "n": 1}));
+
+        // Source state is untouched
+        assert_eq!(state["user"]["email"], "ada@example.com");
+    }
+
+    #[test]
+    fn test_redact_array_wildcards() {
+        let state = json!({"tokens": ["a", "b", "c"], "keys": [{"id": 1, "secret": "x"}, {"id": 2, "secret": "y"}]});
+
+        let masked = redact(&state, &rules(&["/tokens/*", "/keys/*/secret"], RedactMode::Mask));
+        assert_eq!(masked["tokens"], json!(["[redacted]", "[redacted]", "[redacted]"]));
+        assert_eq!(masked["keys"], json!([{"id": 1, "secret": "[redacted]"}, {"id": 2, "secret": "[redacted]"}]));
+
+        let removed = redact(&state, &rules(&["/tokens/0", "/tokens/2"], RedactMode::Remove));
+        assert_eq!(removed["tokens"], json!(["b"]));
+    }
+
+    #[test]
+    fn test_redact_overlapping_rules() {
+        let state = json!({"secrets": {"api_key": "k", "nested": {"pw": "p"}}, "ok": true});
+
+        for mode in [RedactMode::Mask, RedactMode::Remove] {
+            let a = redact(&state, &rules(&["/secrets", "/secrets/nested/pw", "/secrets/*"], mode));
+            let b = redact(&state, &rules(&["/secrets/*", "/secrets/nested/pw", "/secrets"], mode));
+            assert_eq!(a, b);
+            match mode {
+                RedactMode::Mask => assert_eq!(a, json!({"secrets": "[redacted]", "ok": true})),
+                RedactMode::Remove => assert_eq!(a, json!({"ok": true})),
+            }
+        }
+    }
+
+    #[test]
+    fn test_rules_from_metadata() {
+        let mut metadata = HashMap::new();
+        assert_eq!(RedactionRules::from_metadata(&metadata).unwrap(), None);
+
+        metadata.insert("redact".to_string(), json!(["/secrets", "/a~1b"]));
+        metadata.insert("redact_mode".to_string(), json!("remove"));
+        let parsed = RedactionRules::from_metadata(&metadata).unwrap().unwrap();
+        assert_eq!(parsed.mode, RedactMode::Remove);
+        assert_eq!(redact(&json!({"a/b": 1, "c": 2}), &parsed), json!({"c": 2}));
+
+        metadata.insert("redact".to_string(), json!(["secrets"]));
+        assert!(RedactionRules::from_metadata(&metadata).is_err());
+    }
+}
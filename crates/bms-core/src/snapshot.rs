@@ -1,21 +1,194 @@
-use crate::delta::DeltaEngine;
+use crate::delta::{DeltaEngine, DiffSummary};
 use crate::error::{BmsError, Result};
-use crate::types::{CoordId, Delta, Snapshot, SnapshotId};
+use crate::types::{CoordId, Delta, DeltaId, Snapshot, SnapshotId};
+use chrono::{DateTime, Utc};
 use serde_json::Value;
+use sha3::{Digest, Sha3_256};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Coordinate metadata key listing JSON Pointers whose values snapshots store
+/// as shared, content-addressed blobs instead of inline
+pub const SNAPSHOT_EXTERNALIZE_METADATA_KEY: &str = "snapshot_externalize";
+
+/// Pointers listed under `snapshot_externalize`
+///
+/// Returns `None` when the metadata has no such key. Pointers must be exact;
+/// `*` segments are not expanded.
+pub fn externalize_pointers(metadata: &HashMap<String, Value>) -> Result<Option<Vec<String>>> {
+    let Some(rules) = metadata.get(SNAPSHOT_EXTERNALIZE_METADATA_KEY) else {
+        return Ok(None);
+    };
+
+    let invalid = || {
+        BmsError::InvalidState(
+            "snapshot_externalize must be an array of non-empty JSON Pointers".to_string(),
+        )
+    };
+    let pointers: Vec<String> = rules
+        .as_array()
+        .and_then(|a| a.iter().map(|v| v.as_str().map(String::from)).collect())
+        .ok_or_else(invalid)?;
+    if pointers.iter().any(|p| !p.starts_with('/')) {
+        return Err(invalid());
+    }
+    Ok(Some(pointers))
+}
+
+/// Take the values at `pointers` out of `state`, leaving `null` in their place
+///
+/// Pointers missing from the state are skipped, as are pointers nested in a
+/// value already taken.
+pub fn externalize(state: &mut Value, pointers: &[String]) -> Vec<(String, Value)> {
+    // Parents are shorter than their children, so they are taken first
+    let mut ordered: Vec<&String> = pointers.iter().collect();
+    ordered.sort_by_key(|p| p.len());
+
+    let mut taken: Vec<(String, Value)> = Vec::new();
+    for pointer in ordered {
+        if taken.iter().any(|(p, _)| pointer.starts_with(&format!("{}/", p)) || p == pointer) {
+            continue;
+        }
+        if let Some(value) = state.pointer_mut(pointer) {
+            taken.push((pointer.clone(), value.take()));
+        }
+    }
+    taken
+}
+
+/// Put a value taken by `externalize` back into `state`
+pub fn inline(state: &mut Value, pointer: &str, value: Value) -> Result<()> {
+    let slot = state.pointer_mut(pointer).ok_or_else(|| {
+        BmsError::ReconstructionFailed(format!("Externalized field {} is missing from the snapshot", pointer))
+    })?;
+    *slot = value;
+    Ok(())
+}
+
+impl Snapshot {
+    /// Snapshot of `state` as of `head_delta_id`, taken now
+    pub fn capture(coord_id: CoordId, head_delta_id: DeltaId, state: Value) -> Result<Self> {
+        let state_hash = DeltaEngine::hash_state(&state)?;
+
+        // The ID also covers where the state sits, since a chain can return
+        // to an earlier state (a rollback does) and snapshot it again
+        let mut hasher = Sha3_256::new();
+        for part in [&coord_id.0, &head_delta_id.0, &state_hash.0] {
+            hasher.update(part.as_bytes());
+            hasher.update([0]);
+        }
+        let id = SnapshotId(hex::encode(&hasher.finalize()[..16]));
+
+        Ok(Snapshot {
+            id,
+            coord_id,
+            head_delta_id,
+            state_hash,
+            state,
+            created_at: chrono::Utc::now(),
+        })
+    }
+}
+
+/// What a `SnapshotPolicy` knows about a coordinate when a delta lands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SnapshotContext {
+    /// Deltas in the chain, the new one included
+    pub delta_count: u32,
+    /// When the latest snapshot was taken; `None` if there is none
+    pub last_snapshot_at: Option<DateTime<Utc>>,
+    /// Canonical size of the new head state
+    pub state_size_bytes: usize,
+    /// Time since the latest snapshot, or since the chain began without one
+    pub elapsed_since_last: Duration,
+}
+
+/// Decides which deltas are followed by a snapshot
+pub trait SnapshotPolicy: Send + Sync {
+    fn should_snapshot(&self, ctx: &SnapshotContext) -> bool;
+
+    /// Deltas between snapshots, for policies that count them
+    fn interval(&self) -> Option<u32> {
+        None
+    }
+}
+
+/// Snapshot every `n`th delta
+#[derive(Debug, Clone, Copy)]
+pub struct IntervalPolicy(pub u32);
+
+impl SnapshotPolicy for IntervalPolicy {
+    fn should_snapshot(&self, ctx: &SnapshotContext) -> bool {
+        ctx.delta_count.is_multiple_of(self.0)
+    }
+
+    fn interval(&self) -> Option<u32> {
+        Some(self.0)
+    }
+}
+
+/// Snapshot once the latest snapshot is at least this old
+#[derive(Debug, Clone, Copy)]
+pub struct TimePolicy(pub Duration);
+
+impl SnapshotPolicy for TimePolicy {
+    fn should_snapshot(&self, ctx: &SnapshotContext) -> bool {
+        ctx.elapsed_since_last >= self.0
+    }
+}
+
+/// Snapshot every delta whose head state is at least `max_reconstruction_bytes`
+///
+/// Replaying deltas onto a state of several megabytes costs more than
+/// loading it, however few the deltas, so such heads are kept whole.
+#[derive(Debug, Clone, Copy)]
+pub struct SizePolicy {
+    pub max_reconstruction_bytes: usize,
+}
+
+impl SnapshotPolicy for SizePolicy {
+    fn should_snapshot(&self, ctx: &SnapshotContext) -> bool {
+        ctx.state_size_bytes >= self.max_reconstruction_bytes
+    }
+}
+
+/// Snapshot when any of the policies would
+pub struct CompositePolicy(pub Vec<Box<dyn SnapshotPolicy>>);
+
+impl SnapshotPolicy for CompositePolicy {
+    fn should_snapshot(&self, ctx: &SnapshotContext) -> bool {
+        self.0.iter().any(|policy| policy.should_snapshot(ctx))
+    }
+
+    /// The shortest interval among the policies
+    fn interval(&self) -> Option<u32> {
+        self.0.iter().filter_map(|policy| policy.interval()).min()
+    }
+}
 
 /// Snapshot manager for efficient state reconstruction
 pub struct SnapshotManager {
-    snapshot_interval: u32,
+    policy: Box<dyn SnapshotPolicy>,
 }
 
 impl SnapshotManager {
+    pub fn new_with_policy(policy: Box<dyn SnapshotPolicy>) -> Self {
+        Self { policy }
+    }
+
+    /// Snapshot every `snapshot_interval`th delta, see `IntervalPolicy`
     pub fn new(snapshot_interval: u32) -> Self {
-        Self { snapshot_interval }
+        Self::new_with_policy(Box::new(IntervalPolicy(snapshot_interval)))
+    }
+
+    /// Deltas between snapshots, if the policy counts them
+    pub fn interval(&self) -> Option<u32> {
+        self.policy.interval()
     }
 
-    /// Check if a snapshot should be created based on delta count
-    pub fn should_snapshot(&self, delta_count: u32) -> bool {
-        delta_count % self.snapshot_interval == 0
+    /// Check if a snapshot should follow the delta described by `ctx`
+    pub fn should_snapshot(&self, ctx: &SnapshotContext) -> bool {
+        self.policy.should_snapshot(ctx)
     }
 
     /// Create a snapshot from current state
@@ -25,19 +198,7 @@ impl SnapshotManager {
         head_delta_id: crate::types::DeltaId,
         state: Value,
     ) -> Result<Snapshot> {
-        let state_hash = DeltaEngine::hash_state(&state)?;
-        
-        // Generate snapshot ID from state hash
-        let snapshot_id = SnapshotId(state_hash.0[..32].to_string());
-
-        Ok(Snapshot {
-            id: snapshot_id,
-            coord_id,
-            head_delta_id,
-            state_hash,
-            state,
-            created_at: chrono::Utc::now(),
-        })
+        Snapshot::capture(coord_id, head_delta_id, state)
     }
 
     /// Reconstruct state from snapshot and forward deltas
@@ -55,18 +216,144 @@ impl SnapshotManager {
         Ok(state)
     }
 
-    /// Verify snapshot integrity
-    pub fn verify_snapshot(&self, snapshot: &Snapshot) -> Result<()> {
-        let computed_hash = DeltaEngine::hash_state(&snapshot.state)?;
+    /// Reconstruct head state from a full delta chain
+    ///
+    /// When a snapshot is given, only the deltas after its head delta are
+    /// replayed on top of it; otherwise the whole chain is replayed from `{}`.
+    pub fn reconstruct_head(snapshot: Option<&Snapshot>, deltas: &[Delta]) -> Result<Value> {
+        let covered = Self::covered_deltas(snapshot, deltas)?;
+        let Some(snapshot) = snapshot else {
+            let mut state = serde_json::json!({});
+            for delta in deltas {
+                DeltaEngine::apply_delta(&mut state, &delta.ops)?;
+            }
+            return Ok(state);
+        };
 
-        if computed_hash.0 != snapshot.state_hash.0 {
-            return Err(BmsError::HashMismatch {
-                expected: snapshot.state_hash.0.clone(),
-                actual: computed_hash.0,
-            });
+        Self::reconstruct(snapshot, &deltas[covered..])
+    }
+
+    /// Number of leading deltas in `deltas` that `snapshot` already includes
+    pub fn covered_deltas(snapshot: Option<&Snapshot>, deltas: &[Delta]) -> Result<usize> {
+        let Some(snapshot) = snapshot else {
+            return Ok(0);
+        };
+
+        let position = deltas
+            .iter()
+            .position(|d| d.id == snapshot.head_delta_id)
+            .ok_or_else(|| {
+                BmsError::ReconstructionFailed(format!(
+                    "Snapshot head delta {} not found in chain",
+                    snapshot.head_delta_id
+                ))
+            })?;
+        Ok(position + 1)
+    }
+
+    /// `reconstruct` stopped after the first `index` deltas
+    ///
+    /// `deltas` follow `snapshot`, or start the chain when there is none, and
+    /// `index` counts from the start of the slice: 0 is the snapshot's own
+    /// state and `deltas.len()` the head.
+    pub fn reconstruct_at_index(snapshot: Option<&Snapshot>, deltas: &[Delta], index: usize) -> Result<Value> {
+        let Some(applied) = deltas.get(..index) else {
+            return Err(BmsError::DeltaNotFound(format!(
+                "Index {} is past the {} deltas given",
+                index,
+                deltas.len()
+            )));
+        };
+        match snapshot {
+            Some(snapshot) => Self::reconstruct(snapshot, applied),
+            None => Self::reconstruct_head(None, applied),
         }
+    }
 
-        Ok(())
+    /// State of a chain as of `target`
+    ///
+    /// Applies every delta created at or before `target`, starting from
+    /// `snapshot` when it was taken by then and from `{}` otherwise. Deltas
+    /// are in chain order, which never goes back in time.
+    pub fn reconstruct_at_time(
+        snapshot: Option<&Snapshot>,
+        deltas: &[Delta],
+        target: DateTime<Utc>,
+    ) -> Result<Value> {
+        let Some(first) = deltas.first() else {
+            return Err(BmsError::InvalidState("Cannot reconstruct a chain without deltas".to_string()));
+        };
+        if target < first.created_at {
+            return Err(BmsError::InvalidState(format!(
+                "{} is before the first delta, created at {}",
+                target.to_rfc3339(),
+                first.created_at.to_rfc3339()
+            )));
+        }
+
+        let end = deltas.iter().take_while(|d| d.created_at <= target).count();
+        if let Some(snapshot) = snapshot.filter(|s| s.created_at <= target) {
+            let covered = Self::covered_deltas(Some(snapshot), deltas)?;
+            // Deltas stamped after the snapshot was taken could only come from
+            // a skewed clock; replaying from `{}` does not depend on them
+            if covered <= end {
+                return Self::reconstruct(snapshot, &deltas[covered..end]);
+            }
+        }
+        Self::reconstruct_head(None, &deltas[..end])
+    }
+
+    /// Ops that turn the state of `from` into the state of `to`
+    ///
+    /// Both snapshots must be of the same coordinate.
+    pub fn diff_snapshots(from: &Snapshot, to: &Snapshot) -> Result<Vec<json_patch::PatchOperation>> {
+        if from.coord_id != to.coord_id {
+            return Err(BmsError::InvalidState(format!(
+                "Cannot diff snapshot {} of {} against snapshot {} of {}",
+                from.id, from.coord_id, to.id, to.coord_id
+            )));
+        }
+        DeltaEngine::compute_delta(&from.state, &to.state)
+    }
+
+    /// `diff_snapshots` as added, removed, and modified paths
+    pub fn summarize_diff(from: &Snapshot, to: &Snapshot) -> Result<DiffSummary> {
+        Ok(DeltaEngine::describe_ops(&Self::diff_snapshots(from, to)?))
+    }
+
+    /// Snapshots to delete so each coordinate keeps its `keep` most recent
+    ///
+    /// Recency is by `created_at`, ties going to the later one in
+    /// `snapshots`. A coordinate always keeps its latest snapshot, even with
+    /// `keep` 0, so pruning never leaves a chain to replay from `{}`. IDs come
+    /// back in the order given.
+    pub fn prune_old_snapshots(snapshots: &[Snapshot], keep: usize) -> Vec<SnapshotId> {
+        let mut by_coord: HashMap<&CoordId, Vec<usize>> = HashMap::new();
+        for (idx, snapshot) in snapshots.iter().enumerate() {
+            by_coord.entry(&snapshot.coord_id).or_default().push(idx);
+        }
+
+        let mut pruned = vec![false; snapshots.len()];
+        for mut indices in by_coord.into_values() {
+            // Stable, so among equal timestamps the later index sorts last
+            indices.sort_by_key(|&idx| snapshots[idx].created_at);
+            let stale = indices.len().saturating_sub(keep.max(1));
+            for &idx in &indices[..stale] {
+                pruned[idx] = true;
+            }
+        }
+
+        snapshots
+            .iter()
+            .zip(pruned)
+            .filter(|(_, pruned)| *pruned)
+            .map(|(snapshot, _)| snapshot.id.clone())
+            .collect()
+    }
+
+    /// Verify snapshot integrity
+    pub fn verify_snapshot(&self, snapshot: &Snapshot) -> Result<()> {
+        DeltaEngine::verify_state_hash(&snapshot.state, &snapshot.state_hash)
     }
 
     /// Find nearest snapshot before or at target delta
@@ -83,17 +370,74 @@ impl SnapshotManager {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::types::{CoordId, DeltaId};
+    use crate::types::{CoordId, DeltaId, Hash};
     use serde_json::json;
 
+    #[test]
+    fn test_externalized_fields_round_trip() {
+        let metadata: HashMap<String, Value> =
+            [(SNAPSHOT_EXTERNALIZE_METADATA_KEY.to_string(), json!(["/transcript", "/a/b", "/a", "/gone"]))].into();
+        let pointers = externalize_pointers(&metadata).unwrap().unwrap();
+        let original = json!({"transcript": "long text", "a": {"b": [1, 2]}, "small": 1});
+
+        let mut stored = original.clone();
+        let taken = externalize(&mut stored, &pointers);
+        assert_eq!(stored, json!({"transcript": null, "a": null, "small": 1}));
+        let taken_pointers: Vec<&str> = taken.iter().map(|(p, _)| p.as_str()).collect();
+        assert_eq!(taken_pointers, ["/a", "/transcript"]);
+
+        for (pointer, value) in taken {
+            inline(&mut stored, &pointer, value).unwrap();
+        }
+        assert_eq!(stored, original);
+
+        let bad: HashMap<String, Value> = [(SNAPSHOT_EXTERNALIZE_METADATA_KEY.to_string(), json!([""]))].into();
+        assert!(externalize_pointers(&bad).is_err());
+        assert_eq!(externalize_pointers(&HashMap::new()).unwrap(), None);
+    }
+
+    fn context(delta_count: u32) -> SnapshotContext {
+        SnapshotContext {
+            delta_count,
+            last_snapshot_at: None,
+            state_size_bytes: 0,
+            elapsed_since_last: Duration::ZERO,
+        }
+    }
+
     #[test]
     fn test_should_snapshot() {
         let manager = SnapshotManager::new(10);
 
-        assert!(!manager.should_snapshot(5));
-        assert!(manager.should_snapshot(10));
-        assert!(!manager.should_snapshot(11));
-        assert!(manager.should_snapshot(20));
+        assert!(!manager.should_snapshot(&context(5)));
+        assert!(manager.should_snapshot(&context(10)));
+        assert!(!manager.should_snapshot(&context(11)));
+        assert!(manager.should_snapshot(&context(20)));
+        assert_eq!(manager.interval(), Some(10));
+    }
+
+    #[test]
+    fn test_snapshot_policies() {
+        let hour = Duration::from_secs(3600);
+        let stale = SnapshotContext { elapsed_since_last: hour, ..context(3) };
+        let large = SnapshotContext { state_size_bytes: 1 << 20, ..context(3) };
+
+        assert!(TimePolicy(hour).should_snapshot(&stale));
+        assert!(!TimePolicy(hour).should_snapshot(&context(3)));
+        let sized = SizePolicy { max_reconstruction_bytes: 1 << 20 };
+        assert!(sized.should_snapshot(&large));
+        assert!(!sized.should_snapshot(&stale));
+
+        let manager = SnapshotManager::new_with_policy(Box::new(CompositePolicy(vec![
+            Box::new(IntervalPolicy(50)),
+            Box::new(TimePolicy(hour)),
+            Box::new(IntervalPolicy(20)),
+        ])));
+        assert!(manager.should_snapshot(&context(20)));
+        assert!(manager.should_snapshot(&stale));
+        assert!(!manager.should_snapshot(&large));
+        assert_eq!(manager.interval(), Some(20));
+        assert_eq!(SnapshotManager::new_with_policy(Box::new(TimePolicy(hour))).interval(), None);
     }
 
     #[test]
@@ -113,6 +457,30 @@ mod tests {
         assert_eq!(snapshot.state, state);
     }
 
+    #[test]
+    fn test_diff_snapshots() {
+        let capture = |coord: &str, state: Value| {
+            Snapshot::capture(CoordId(coord.to_string()), DeltaId("head".to_string()), state).unwrap()
+        };
+        let from = capture("c1", json!({"keep": 1, "change": "a", "drop": true}));
+        let to = capture("c1", json!({"keep": 1, "change": "b", "new": [1]}));
+
+        let ops = SnapshotManager::diff_snapshots(&from, &to).unwrap();
+        let mut state = from.state.clone();
+        DeltaEngine::apply_delta(&mut state, &ops).unwrap();
+        assert_eq!(state, to.state);
+        assert!(SnapshotManager::diff_snapshots(&from, &from).unwrap().is_empty());
+
+        let summary = SnapshotManager::summarize_diff(&from, &to).unwrap();
+        assert_eq!(summary.added_paths, ["/new"]);
+        assert_eq!(summary.removed_paths, ["/drop"]);
+        assert_eq!(summary.modified_paths, ["/change"]);
+
+        let other = capture("c2", to.state.clone());
+        assert!(matches!(SnapshotManager::diff_snapshots(&from, &other), Err(BmsError::InvalidState(_))));
+        assert!(matches!(SnapshotManager::summarize_diff(&from, &other), Err(BmsError::InvalidState(_))));
+    }
+
     #[test]
     fn test_verify_snapshot() {
         let manager = SnapshotManager::new(10);
@@ -127,6 +495,13 @@ mod tests {
             .unwrap();
 
         assert!(manager.verify_snapshot(&snapshot).is_ok());
+
+        // An exported snapshot may carry the prefixed form
+        let mut exported = snapshot.clone();
+        exported.state_hash = Hash(snapshot.state_hash.prefixed());
+        manager.verify_snapshot(&exported).unwrap();
+        exported.state = json!({"key": "other"});
+        assert!(matches!(manager.verify_snapshot(&exported), Err(BmsError::HashMismatch { .. })));
     }
 
     #[test]
@@ -158,10 +533,141 @@ mod tests {
             created_at: chrono::Utc::now(),
             tags: None,
             author: None,
+            op_authors: None,
+            signature: None,
         };
 
         let reconstructed = SnapshotManager::reconstruct(&snapshot, &[delta]).unwrap();
 
         assert_eq!(reconstructed, new_state);
     }
+
+    /// Deltas d0, d1, ... of coordinate "test" from `{}` through `states`
+    fn chain(states: &[Value]) -> Vec<Delta> {
+        chain_at(states, chrono::Utc::now(), chrono::Duration::zero())
+    }
+
+    /// `chain`, with delta i created at `start + i * step`
+    fn chain_at(states: &[Value], start: DateTime<Utc>, step: chrono::Duration) -> Vec<Delta> {
+        let mut deltas = Vec::new();
+        let mut prev = json!({});
+        for (i, state) in states.iter().enumerate() {
+            let ops = DeltaEngine::compute_delta(&prev, state).unwrap();
+            let delta_hash = DeltaEngine::hash_delta(&ops).unwrap();
+            deltas.push(Delta {
+                id: DeltaId(format!("d{}", i)),
+                coord_id: CoordId("test".to_string()),
+                parent_id: None,
+                parent_hash: None,
+                delta_hash: delta_hash.clone(),
+                chain_hash: delta_hash,
+                ops,
+                created_at: start + step * i as i32,
+                tags: None,
+                author: None,
+                op_authors: None,
+                signature: None,
+            });
+            prev = state.clone();
+        }
+        deltas
+    }
+
+    #[test]
+    fn test_reconstruct_head_skips_covered_deltas() {
+        let manager = SnapshotManager::new(10);
+        let states = [json!({"a": 1}), json!({"a": 2}), json!({"a": 2, "b": 3})];
+        let deltas = chain(&states);
+
+        let snapshot = manager
+            .create_snapshot(
+                CoordId("test".to_string()),
+                DeltaId("d1".to_string()),
+                states[1].clone(),
+            )
+            .unwrap();
+
+        let from_snapshot = SnapshotManager::reconstruct_head(Some(&snapshot), &deltas).unwrap();
+        let from_genesis = SnapshotManager::reconstruct_head(None, &deltas).unwrap();
+
+        assert_eq!(from_snapshot, states[2]);
+        assert_eq!(from_genesis, states[2]);
+    }
+
+    #[test]
+    fn test_reconstruct_at_index() {
+        let states = [json!({"a": 1}), json!({"a": 2}), json!({"a": 2, "b": 3})];
+        let deltas = chain(&states);
+
+        let at = |index| SnapshotManager::reconstruct_at_index(None, &deltas, index).unwrap();
+        assert_eq!(at(0), json!({}));
+        assert_eq!(at(1), states[0]);
+        assert_eq!(at(3), states[2]);
+
+        // Relative to the deltas after the snapshot
+        let snapshot =
+            Snapshot::capture(CoordId("test".to_string()), DeltaId("d0".to_string()), states[0].clone()).unwrap();
+        let forward = &deltas[1..];
+        let after = |index| SnapshotManager::reconstruct_at_index(Some(&snapshot), forward, index).unwrap();
+        assert_eq!(after(0), states[0]);
+        assert_eq!(after(1), states[1]);
+        assert_eq!(after(2), states[2]);
+
+        let past = SnapshotManager::reconstruct_at_index(Some(&snapshot), forward, 3);
+        assert!(matches!(past, Err(BmsError::DeltaNotFound(_))));
+    }
+
+    #[test]
+    fn test_prune_old_snapshots() {
+        let start = chrono::Utc::now();
+        let snapshot = |coord: &str, n: i64, minutes: i64| {
+            let mut snapshot =
+                Snapshot::capture(CoordId(coord.to_string()), DeltaId(format!("d{}", n)), json!({"n": n})).unwrap();
+            snapshot.created_at = start + chrono::Duration::minutes(minutes);
+            snapshot
+        };
+        // Out of order, interleaved across coordinates, with a tie at minute 2
+        let snapshots = vec![
+            snapshot("A", 3, 3),
+            snapshot("A", 1, 1),
+            snapshot("B", 1, 5),
+            snapshot("A", 2, 2),
+            snapshot("A", 4, 2),
+            snapshot("B", 2, 6),
+        ];
+        let ids = |picks: &[usize]| picks.iter().map(|&i| snapshots[i].id.clone()).collect::<Vec<_>>();
+
+        assert_eq!(SnapshotManager::prune_old_snapshots(&snapshots, 2), ids(&[1, 3]));
+        assert_eq!(SnapshotManager::prune_old_snapshots(&snapshots, 3), ids(&[1]));
+        assert!(SnapshotManager::prune_old_snapshots(&snapshots, 4).is_empty());
+
+        // The latest of each coordinate survives even keep 0
+        let all_but_latest = ids(&[1, 2, 3, 4]);
+        assert_eq!(SnapshotManager::prune_old_snapshots(&snapshots, 0), all_but_latest);
+        assert_eq!(SnapshotManager::prune_old_snapshots(&snapshots, 1), all_but_latest);
+        assert!(SnapshotManager::prune_old_snapshots(&[], 0).is_empty());
+    }
+
+    #[test]
+    fn test_reconstruct_at_time() {
+        let t0 = chrono::Utc::now() - chrono::Duration::hours(3);
+        let hours = |h: i64| t0 + chrono::Duration::hours(h);
+        let states = [json!({"a": 1}), json!({"a": 2}), json!({"a": 2, "b": 3})];
+        let deltas = chain_at(&states, t0, chrono::Duration::hours(1));
+        // Marked so results show whether replay started from it
+        let mut snapshot =
+            Snapshot::capture(CoordId("test".to_string()), DeltaId("d1".to_string()), json!({"a": 2, "s": 1})).unwrap();
+        snapshot.created_at = t0 + chrono::Duration::minutes(90);
+
+        let at = |target| SnapshotManager::reconstruct_at_time(Some(&snapshot), &deltas, target).unwrap();
+        assert_eq!(at(hours(0)), states[0]);
+        assert_eq!(at(hours(1)), states[1]);
+        assert_eq!(at(t0 + chrono::Duration::minutes(100)), json!({"a": 2, "s": 1}));
+        assert_eq!(at(hours(2)), json!({"a": 2, "b": 3, "s": 1}));
+        assert_eq!(SnapshotManager::reconstruct_at_time(None, &deltas, hours(5)).unwrap(), states[2]);
+
+        let early = SnapshotManager::reconstruct_at_time(None, &deltas, hours(-1));
+        assert!(matches!(early, Err(BmsError::InvalidState(_))));
+        assert!(matches!(SnapshotManager::reconstruct_at_time(None, &[], hours(1)), Err(BmsError::InvalidState(_))));
+    }
 }
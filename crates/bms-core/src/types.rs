@@ -1,3 +1,6 @@
+use crate::delta::OpsStats;
+use crate::error::{BmsError, Result};
+use crate::{COORD_ID_BYTES, COORD_ID_CHARS};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -10,6 +13,123 @@ impl CoordId {
     pub fn as_str(&self) -> &str {
         &self.0
     }
+
+    /// Encode 16 raw bytes as a coordinate ID (RFC 4648 base32, no padding)
+    pub fn from_bytes(bytes: [u8; COORD_ID_BYTES]) -> Self {
+        CoordId(base32::encode(
+            base32::Alphabet::Rfc4648 { padding: false },
+            &bytes,
+        ))
+    }
+
+    /// Decode the coordinate ID back into its 16 raw bytes
+    ///
+    /// Only the canonical form is accepted: uppercase ASCII `A-Z`/`2-7`,
+    /// exactly 26 characters, and zero padding bits in the last character.
+    /// Decoding is byte-based and therefore independent of locale.
+    pub fn to_bytes(&self) -> Result<[u8; COORD_ID_BYTES]> {
+        let input = self.0.as_bytes();
+        if input.len() != COORD_ID_CHARS {
+            return Err(BmsError::InvalidCoordinate(format!(
+                "Expected {} characters, got {}",
+                COORD_ID_CHARS,
+                input.len()
+            )));
+        }
+
+        let mut bytes = [0u8; COORD_ID_BYTES];
+        let mut buffer: u16 = 0;
+        let mut bits = 0;
+        let mut written = 0;
+
+        for (idx, &c) in input.iter().enumerate() {
+            let value = base32_value(c).ok_or_else(|| {
+                BmsError::InvalidCoordinate(format!(
+                    "Invalid base32 character {:?} at position {}",
+                    c as char, idx
+                ))
+            })?;
+
+            buffer = (buffer << 5) | value as u16;
+            bits += 5;
+            if bits >= 8 {
+                bits -= 8;
+                bytes[written] = (buffer >> bits) as u8;
+                written += 1;
+                buffer &= (1 << bits) - 1;
+            }
+        }
+
+        // 26 characters carry 130 bits; the 2 leftover bits must be zero
+        if buffer != 0 {
+            return Err(BmsError::InvalidCoordinate(
+                "Non-canonical trailing bits in last character".to_string(),
+            ));
+        }
+
+        Ok(bytes)
+    }
+
+    /// Assign the coordinate to one of `shards` buckets using its leading bytes
+    pub fn shard_of(&self, shards: u32) -> Result<u32> {
+        if shards == 0 {
+            return Err(BmsError::Other("Shard count must be positive".to_string()));
+        }
+
+        let bytes = self.to_bytes()?;
+        let mut prefix = [0u8; 8];
+        prefix.copy_from_slice(&bytes[..8]);
+
+        Ok((u64::from_be_bytes(prefix) % shards as u64) as u32)
+    }
+
+    /// Read a coordinate ID as a person typed or pasted it
+    ///
+    /// Surrounding whitespace is trimmed. If what is left reads as 26
+    /// letters and digits once hyphens and inner whitespace are dropped, it
+    /// is taken for a generated ID in any case, so `abcd-efgh ...` finds
+    /// `ABCDEFGH...`; a digit outside `2-7` is reported by position, with
+    /// the letter it was likely mistaken for. Anything else must be an exact
+    /// named ID, see `CoordinateGenerator::validate_named`.
+    pub fn parse(input: &str) -> Result<CoordId> {
+        let trimmed = input.trim();
+        let compact: String = trimmed
+            .chars()
+            .filter(|c| *c != '-' && !c.is_whitespace())
+            .map(|c| c.to_ascii_uppercase())
+            .collect();
+
+        if compact.len() != COORD_ID_CHARS || !compact.bytes().all(|b| b.is_ascii_alphanumeric()) {
+            crate::coordinate::CoordinateGenerator::validate_named(trimmed)?;
+            return Ok(CoordId(trimmed.to_string()));
+        }
+
+        if let Some((idx, c)) = compact.char_indices().find(|(_, c)| base32_value(*c as u8).is_none()) {
+            let hint = match c {
+                '0' => " (did you mean 'O'?)",
+                '1' => " (did you mean 'I' or 'L'?)",
+                '8' => " (did you mean 'B'?)",
+                _ => "",
+            };
+            return Err(BmsError::InvalidCoordinate(format!(
+                "Invalid character {:?} at position {} of coordinate ID {:?}: IDs use A-Z and 2-7{}",
+                c, idx, trimmed, hint
+            )));
+        }
+
+        let coord_id = CoordId(compact);
+        coord_id.to_bytes()?;
+        Ok(coord_id)
+    }
+}
+
+/// Map an RFC 4648 base32 character to its 5-bit value
+fn base32_value(c: u8) -> Option<u8> {
+    match c {
+        b'A'..=b'Z' => Some(c - b'A'),
+        b'2'..=b'7' => Some(c - b'2' + 26),
+        _ => None,
+    }
 }
 
 impl From<String> for CoordId {
@@ -24,6 +144,49 @@ impl std::fmt::Display for CoordId {
     }
 }
 
+/// How a coordinate got its ID
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CoordIdKind {
+    /// 26 canonical base32 characters, as `CoordinateGenerator` derives them
+    #[default]
+    Generated,
+    /// Chosen by the caller as a coordinate hint, see
+    /// `CoordinateGenerator::validate_named`
+    Named,
+}
+
+impl CoordIdKind {
+    /// `Generated` if the ID is canonical base32, whoever chose it
+    pub fn of(coord_id: &CoordId) -> Self {
+        match coord_id.to_bytes() {
+            Ok(_) => CoordIdKind::Generated,
+            Err(_) => CoordIdKind::Named,
+        }
+    }
+}
+
+impl std::str::FromStr for CoordIdKind {
+    type Err = BmsError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "generated" => Ok(CoordIdKind::Generated),
+            "named" => Ok(CoordIdKind::Named),
+            other => Err(BmsError::InvalidState(format!("Unknown coordinate ID kind: {}", other))),
+        }
+    }
+}
+
+impl std::fmt::Display for CoordIdKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            CoordIdKind::Generated => "generated",
+            CoordIdKind::Named => "named",
+        })
+    }
+}
+
 /// Delta ID (SHA3-256 hash of delta, first 16 bytes hex)
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct DeltaId(pub String);
@@ -63,13 +226,58 @@ impl std::fmt::Display for SnapshotId {
 }
 
 /// Hash value (SHA3-256, 32 bytes)
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+///
+/// Stored hashes are bare hex. `prefixed` gives the self-describing
+/// `sha3-256:<hex>` form for other systems; parsing and `matches` take
+/// either form.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Hash(pub String);
 
 impl Hash {
+    /// Algorithm tag of the prefixed form
+    pub const SHA3_256_PREFIX: &'static str = "sha3-256:";
+
     pub fn as_str(&self) -> &str {
         &self.0
     }
+
+    /// Hex digest, without the algorithm prefix if there is one
+    pub fn hex(&self) -> &str {
+        self.0.strip_prefix(Self::SHA3_256_PREFIX).unwrap_or(&self.0)
+    }
+
+    /// `sha3-256:<hex>`
+    pub fn prefixed(&self) -> String {
+        format!("{}{}", Self::SHA3_256_PREFIX, self.hex())
+    }
+
+    /// Whether both name the same digest, each bare or prefixed
+    pub fn matches(&self, other: &Hash) -> bool {
+        self.hex().eq_ignore_ascii_case(other.hex())
+    }
+}
+
+impl std::fmt::Display for Hash {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::str::FromStr for Hash {
+    type Err = BmsError;
+
+    /// Parse 64 hex digits, bare or prefixed, into lowercase bare hex
+    fn from_str(s: &str) -> Result<Self> {
+        let hex = s.strip_prefix(Self::SHA3_256_PREFIX).unwrap_or(s);
+        if hex.len() != 64 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(BmsError::InvalidState(format!(
+                "{:?} is not a SHA3-256 hash in hex, bare or after {}",
+                s,
+                Self::SHA3_256_PREFIX
+            )));
+        }
+        Ok(Hash(hex.to_ascii_lowercase()))
+    }
 }
 
 /// Coordinate metadata
@@ -81,6 +289,63 @@ pub struct Coordinate {
     pub created_at: DateTime<Utc>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub metadata: Option<HashMap<String, serde_json::Value>>,
+    /// Namespace the coordinate was created in; `None` is the default one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+    /// Hash function of the coordinate's chain hashes
+    #[serde(default)]
+    pub hash_algorithm: crate::merkle::HashAlgorithm,
+    /// Whether the ID was generated or named by a hint
+    #[serde(default)]
+    pub kind: CoordIdKind,
+    /// Coordinate this one branched from, see `CoordinateGenerator::derive_child`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_coord_id: Option<CoordId>,
+}
+
+/// Metadata flag of scratch coordinates: left out of default listings and
+/// search, and deleted once idle until promoted
+pub const EPHEMERAL_METADATA_KEY: &str = "ephemeral";
+
+impl Coordinate {
+    /// Namespace of the coordinate, `DEFAULT_NAMESPACE` if it has none
+    pub fn namespace(&self) -> &str {
+        self.namespace.as_deref().unwrap_or(crate::DEFAULT_NAMESPACE)
+    }
+
+    /// Whether the coordinate carries `ephemeral: true`
+    pub fn is_ephemeral(&self) -> bool {
+        self.metadata
+            .as_ref()
+            .and_then(|metadata| metadata.get(EPHEMERAL_METADATA_KEY))
+            .is_some_and(|flag| flag == &serde_json::Value::Bool(true))
+    }
+}
+
+/// Ed25519 signature over a delta's hash, see `DeltaEngine::sign_delta`
+///
+/// Serialized as hex.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeltaSignature {
+    #[serde(with = "hex_array")]
+    pub public_key: [u8; 32],
+    #[serde(with = "hex_array")]
+    pub signature_bytes: [u8; 64],
+}
+
+mod hex_array {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer, const N: usize>(bytes: &[u8; N], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, const N: usize>(deserializer: D) -> Result<[u8; N], D::Error> {
+        let text = String::deserialize(deserializer)?;
+        let mut bytes = [0u8; N];
+        hex::decode_to_slice(&text, &mut bytes).map_err(D::Error::custom)?;
+        Ok(bytes)
+    }
 }
 
 /// Delta (JSON Patch with Merkle linking)
@@ -100,6 +365,78 @@ pub struct Delta {
     pub tags: Option<HashMap<String, serde_json::Value>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub author: Option<String>,
+    /// Author of each op, parallel to `ops`, when several wrote one delta
+    ///
+    /// Attribution is metadata: neither the delta hash nor the chain hash
+    /// covers it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub op_authors: Option<Vec<String>>,
+    /// Signature of the author's key over `delta_hash`
+    ///
+    /// Like op authors, it is not covered by either hash, so a chain can be
+    /// signed after the fact; it vouches for the ops, not their position.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<DeltaSignature>,
+}
+
+impl Delta {
+    /// Reject op authors that are not parallel to the ops
+    pub fn check_op_authors(&self) -> Result<()> {
+        match &self.op_authors {
+            Some(authors) if authors.len() != self.ops.len() => Err(BmsError::OpAuthorsMismatch {
+                ops: self.ops.len(),
+                op_authors: authors.len(),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Op counts per author, most ops first
+    ///
+    /// Without op authors every op counts for the delta's author; a delta
+    /// with neither gives an empty list.
+    pub fn ops_by_author(&self) -> Vec<(&str, usize)> {
+        let mut counts: Vec<(&str, usize)> = Vec::new();
+        let authors: Vec<&str> = match (&self.op_authors, &self.author) {
+            (Some(authors), _) => authors.iter().map(String::as_str).collect(),
+            (None, Some(author)) => vec![author.as_str(); self.ops.len()],
+            (None, None) => Vec::new(),
+        };
+        for author in authors {
+            match counts.iter_mut().find(|(a, _)| *a == author) {
+                Some((_, n)) => *n += 1,
+                None => counts.push((author, 1)),
+            }
+        }
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        counts
+    }
+
+    /// Attribution as text, e.g. `3 ops by planner, 1 by executor`
+    pub fn authorship_summary(&self) -> Option<String> {
+        let counts = self.ops_by_author();
+        let (first, rest) = counts.split_first()?;
+        let noun = if first.1 == 1 { "op" } else { "ops" };
+        let mut summary = format!("{} {} by {}", first.1, noun, first.0);
+        for (author, n) in rest {
+            summary.push_str(&format!(", {} by {}", n, author));
+        }
+        Some(summary)
+    }
+
+    /// Patch size relative to the state, as tagged when the delta was stored
+    pub fn patch_ratio(&self) -> Option<f64> {
+        self.tags.as_ref()?.get(crate::delta::PATCH_RATIO_TAG)?.as_f64()
+    }
+
+    /// Whether the delta stores the whole state because its patch was too large
+    pub fn is_full_replace(&self) -> bool {
+        self.tags
+            .as_ref()
+            .and_then(|tags| tags.get(crate::delta::FULL_REPLACE_TAG))
+            .and_then(serde_json::Value::as_bool)
+            .unwrap_or(false)
+    }
 }
 
 /// Snapshot (full state at a point in the delta chain)
@@ -120,6 +457,9 @@ pub struct CompressionStats {
     pub compressed_bytes: usize,
     pub compression_ratio: f64,
     pub delta_count: u32,
+    /// Ops of the whole chain by kind
+    #[serde(default)]
+    pub ops: OpsStats,
 }
 
 impl CompressionStats {
@@ -135,6 +475,191 @@ impl CompressionStats {
             compressed_bytes,
             compression_ratio,
             delta_count,
+            ops: OpsStats::default(),
+        }
+    }
+
+    pub fn with_ops(mut self, ops: OpsStats) -> Self {
+        self.ops = ops;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+
+    fn delta(ops: usize, author: Option<&str>, op_authors: Option<&[&str]>) -> Delta {
+        let op: json_patch::PatchOperation =
+            serde_json::from_value(serde_json::json!({"op": "add", "path": "/n", "value": 1})).unwrap();
+        Delta {
+            id: DeltaId("d".to_string()),
+            coord_id: CoordId("C".to_string()),
+            parent_id: None,
+            parent_hash: None,
+            delta_hash: Hash("h".to_string()),
+            chain_hash: Hash("h".to_string()),
+            ops: vec![op; ops],
+            created_at: Utc::now(),
+            tags: None,
+            author: author.map(String::from),
+            op_authors: op_authors.map(|a| a.iter().map(|s| s.to_string()).collect()),
+            signature: None,
+        }
+    }
+
+    #[test]
+    fn test_op_authorship() {
+        let mixed = delta(4, Some("planner"), Some(&["executor", "planner", "planner", "planner"]));
+        assert!(mixed.check_op_authors().is_ok());
+        assert_eq!(mixed.ops_by_author(), [("planner", 3), ("executor", 1)]);
+        assert_eq!(mixed.authorship_summary().unwrap(), "3 ops by planner, 1 by executor");
+
+        // Without op authors the delta author wrote everything
+        assert_eq!(delta(1, Some("cli"), None).authorship_summary().unwrap(), "1 op by cli");
+        assert_eq!(delta(2, None, None).authorship_summary(), None);
+
+        let short = delta(2, None, Some(&["planner"]));
+        assert!(matches!(
+            short.check_op_authors(),
+            Err(BmsError::OpAuthorsMismatch { ops: 2, op_authors: 1 })
+        ));
+    }
+
+    #[test]
+    fn test_hash_forms() {
+        let hex = "a7".repeat(32);
+        let bare: Hash = hex.parse().unwrap();
+        assert_eq!(bare, Hash(hex.clone()));
+        let prefixed: Hash = format!("sha3-256:{}", hex.to_uppercase()).parse().unwrap();
+        assert_eq!(prefixed, bare);
+        assert_eq!(bare.prefixed(), format!("sha3-256:{}", hex));
+        assert_eq!(Hash(bare.prefixed()).hex(), hex);
+        assert!(Hash(bare.prefixed()).matches(&bare));
+        assert!(!Hash("b7".repeat(32)).matches(&bare));
+        for bad in [&hex[2..], "sha3-256:", "sha2-256:00", &format!("blake3:{}", hex), &"zz".repeat(32)] {
+            assert!(bad.parse::<Hash>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_bytes_round_trip() {
+        let bytes = [0xABu8; COORD_ID_BYTES];
+        let coord = CoordId::from_bytes(bytes);
+
+        assert_eq!(coord.0.len(), COORD_ID_CHARS);
+        assert_eq!(coord.to_bytes().unwrap(), bytes);
+    }
+
+    #[test]
+    fn test_rejects_trailing_bits() {
+        let coord = CoordId::from_bytes([0u8; COORD_ID_BYTES]);
+        assert!(coord.0.ends_with('A'));
+
+        // 'B' sets a padding bit and would decode to the same bytes as 'A'
+        let mut aliased = coord.0.clone();
+        aliased.pop();
+        aliased.push('B');
+        assert!(CoordId(aliased).to_bytes().is_err());
+    }
+
+    #[test]
+    fn test_rejects_lowercase_and_invalid_chars() {
+        let coord = CoordId::from_bytes([7u8; COORD_ID_BYTES]);
+        assert!(CoordId(coord.0.to_lowercase()).to_bytes().is_err());
+        assert!(CoordId("ABCDEFGH12345678901234!!!!".to_string()).to_bytes().is_err());
+        assert!(CoordId("SHORT".to_string()).to_bytes().is_err());
+    }
+
+    #[test]
+    fn test_shard_of() {
+        let coord = CoordId::from_bytes([0xFFu8; COORD_ID_BYTES]);
+        assert_eq!(coord.shard_of(1).unwrap(), 0);
+        assert!(coord.shard_of(16).unwrap() < 16);
+        assert!(coord.shard_of(0).is_err());
+    }
+
+    #[test]
+    fn test_fuzz_round_trip() {
+        let mut rng = ChaCha8Rng::seed_from_u64(1452);
+
+        for _ in 0..10_000 {
+            let bytes: [u8; COORD_ID_BYTES] = rng.gen();
+            let coord = CoordId::from_bytes(bytes);
+            assert_eq!(coord.to_bytes().unwrap(), bytes);
+        }
+
+        // Random strings over the alphabet decode iff their padding bits are zero,
+        // and every accepted string re-encodes to itself
+        const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+        for _ in 0..10_000 {
+            let text: String = (0..COORD_ID_CHARS)
+                .map(|_| ALPHABET[rng.gen_range(0..ALPHABET.len())] as char)
+                .collect();
+            let last = base32_value(*text.as_bytes().last().unwrap()).unwrap();
+
+            match CoordId(text.clone()).to_bytes() {
+                Ok(bytes) => {
+                    assert_eq!(last & 0b11, 0);
+                    assert_eq!(CoordId::from_bytes(bytes).0, text);
+                }
+                Err(_) => assert_ne!(last & 0b11, 0),
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let coord = CoordId::from_bytes([42u8; COORD_ID_BYTES]);
+        let grouped: Vec<&str> = (0..COORD_ID_CHARS).step_by(4).map(|i| &coord.0[i..(i + 4).min(26)]).collect();
+        for typed in [
+            coord.0.clone(),
+            coord.0.to_lowercase(),
+            format!("  {}\n", coord.0),
+            grouped.join("-").to_lowercase(),
+            grouped.join(" "),
+        ] {
+            assert_eq!(CoordId::parse(&typed).unwrap(), coord, "{:?}", typed);
+        }
+
+        assert_eq!(CoordId::parse(" notes.v2 ").unwrap().0, "notes.v2");
+        assert_eq!(CoordId::parse("Mixed-Case_name").unwrap().0, "Mixed-Case_name");
+
+        let confused = format!("{}0", &coord.0[..25]);
+        let message = CoordId::parse(&confused).unwrap_err().to_string();
+        assert!(message.contains("'0' at position 25") && message.contains("'O'"), "{}", message);
+        let message = CoordId::parse(&format!("1{}", &coord.0[1..])).unwrap_err().to_string();
+        assert!(message.contains("'1' at position 0") && message.contains("'L'"), "{}", message);
+    }
+
+    #[test]
+    fn test_fuzz_parse_garbage() {
+        let mut rng = ChaCha8Rng::seed_from_u64(1524);
+        const NOISE: &[char] = &['a', 'Z', '0', '1', '7', '9', '-', '_', '.', ' ', '\t', '/', '%', '\0', 'é', '𝔘', '"'];
+
+        for _ in 0..10_000 {
+            let len = rng.gen_range(0..40);
+            let text: String = (0..len).map(|_| NOISE[rng.gen_range(0..NOISE.len())]).collect();
+            // Never panics, and whatever parses is stable and stored as-is
+            if let Ok(coord) = CoordId::parse(&text) {
+                assert_eq!(CoordId::parse(coord.as_str()).unwrap(), coord);
+                assert!(coord.0.bytes().all(|b| b.is_ascii_alphanumeric() || b"-_.".contains(&b)), "{:?}", coord);
+            }
+        }
+
+        for _ in 0..10_000 {
+            let bytes: [u8; COORD_ID_BYTES] = rng.gen();
+            let coord = CoordId::from_bytes(bytes);
+            let mut mangled = String::new();
+            for c in coord.0.chars() {
+                if rng.gen_ratio(1, 8) {
+                    mangled.push('-');
+                }
+                mangled.push(if rng.gen_bool(0.5) { c.to_ascii_lowercase() } else { c });
+            }
+            assert_eq!(CoordId::parse(&mangled).unwrap(), coord, "{:?}", mangled);
         }
     }
 }
@@ -0,0 +1,165 @@
+//! Matching delta ops against watched JSON Pointer regions
+//!
+//! Subscribers can ask to hear only about changes under some pointers. The
+//! decision is made from the op paths alone, so the head never has to be
+//! reconstructed to filter an event.
+//!
+//! A watched pointer and an op path overlap when one is a token-wise prefix
+//! of the other: a write to `/status/code` touches `/status`, and replacing
+//! `/status` touches `/status/code`. A `*` token in a watched pointer matches
+//! any single token. Because inserting into or removing from an array shifts
+//! the elements after it, an `add` or `remove` whose last token is an array
+//! index (or `-`) counts as touching the whole array.
+
+use crate::error::{BmsError, Result};
+use json_patch::PatchOperation;
+
+/// Decoded tokens of every location an op list modifies
+///
+/// `move` and `copy` touch both their source and target.
+pub fn touched_paths(ops: &[PatchOperation]) -> Vec<Vec<String>> {
+    let mut paths = Vec::new();
+    for op in ops {
+        match op {
+            PatchOperation::Add(op) => paths.push(shifting(tokens(&op.path))),
+            PatchOperation::Remove(op) => paths.push(shifting(tokens(&op.path))),
+            PatchOperation::Replace(op) => paths.push(tokens(&op.path)),
+            PatchOperation::Move(op) => {
+                paths.push(shifting(tokens(&op.from)));
+                paths.push(shifting(tokens(&op.path)));
+            }
+            PatchOperation::Copy(op) => {
+                paths.push(tokens(&op.from));
+                paths.push(shifting(tokens(&op.path)));
+            }
+            PatchOperation::Test(_) => {}
+        }
+    }
+    paths
+}
+
+fn tokens(pointer: &jsonptr::Pointer) -> Vec<String> {
+    pointer.tokens().map(|t| t.decoded().to_string()).collect()
+}
+
+/// Widen an insert or removal at an array position to the array itself
+pub(crate) fn shifting(mut path: Vec<String>) -> Vec<String> {
+    if path.last().is_some_and(|t| is_array_position(t)) {
+        path.pop();
+    }
+    path
+}
+
+fn is_array_position(token: &str) -> bool {
+    token == "-" || (!token.is_empty() && token.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// Set of watched JSON Pointers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PointerFilter {
+    pointers: Vec<(String, Vec<String>)>,
+}
+
+impl PointerFilter {
+    /// `""` watches the whole document; any other pointer must start with `/`
+    pub fn new(pointers: Vec<String>) -> Result<Self> {
+        let pointers = pointers
+            .into_iter()
+            .map(|pointer| {
+                if !pointer.is_empty() && !pointer.starts_with('/') {
+                    return Err(BmsError::InvalidState(format!(
+                        "Watched path must be a JSON Pointer: {:?}",
+                        pointer
+                    )));
+                }
+                let tokens = pointer
+                    .split('/')
+                    .skip(1)
+                    .map(|t| t.replace("~1", "/").replace("~0", "~"))
+                    .collect();
+                Ok((pointer, tokens))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { pointers })
+    }
+
+    /// Watched pointers overlapping any of `touched`, in subscription order
+    pub fn matches(&self, touched: &[Vec<String>]) -> Vec<String> {
+        self.pointers
+            .iter()
+            .filter(|(_, watched)| touched.iter().any(|path| overlaps(watched, path)))
+            .map(|(pointer, _)| pointer.clone())
+            .collect()
+    }
+}
+
+fn overlaps(watched: &[String], path: &[String]) -> bool {
+    watched.iter().zip(path).all(|(w, p)| w == "*" || w == p)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn ops(value: serde_json::Value) -> Vec<PatchOperation> {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn filter(pointers: &[&str]) -> PointerFilter {
+        PointerFilter::new(pointers.iter().map(|p| p.to_string()).collect()).unwrap()
+    }
+
+    #[test]
+    fn test_nested_prefixes() {
+        let watch = filter(&["/status", "/profile/name"]);
+
+        let deeper = touched_paths(&ops(json!([{"op": "replace", "path": "/status/code", "value": 1}])));
+        assert_eq!(watch.matches(&deeper), ["/status"]);
+
+        let parent = touched_paths(&ops(json!([{"op": "replace", "path": "/profile", "value": {}}])));
+        assert_eq!(watch.matches(&parent), ["/profile/name"]);
+
+        // Token comparison, not string prefix
+        let sibling = touched_paths(&ops(json!([{"op": "replace", "path": "/statusline", "value": 1}])));
+        assert!(watch.matches(&sibling).is_empty());
+
+        let root = touched_paths(&ops(json!([{"op": "replace", "path": "", "value": {}}])));
+        assert_eq!(watch.matches(&root), ["/status", "/profile/name"]);
+    }
+
+    #[test]
+    fn test_array_wildcards_and_shifts() {
+        let watch = filter(&["/messages/*/edited"]);
+
+        let edit = touched_paths(&ops(json!([{"op": "replace", "path": "/messages/3/edited", "value": true}])));
+        assert_eq!(watch.matches(&edit), ["/messages/*/edited"]);
+        let body = touched_paths(&ops(json!([{"op": "replace", "path": "/messages/3/body", "value": "x"}])));
+        assert!(watch.matches(&body).is_empty());
+
+        // Appending or removing an element touches the whole array
+        let append = touched_paths(&ops(json!([{"op": "add", "path": "/messages/-", "value": {}}])));
+        assert_eq!(append, [vec!["messages".to_string()]]);
+        assert!(filter(&["/status"]).matches(&append).is_empty());
+        assert_eq!(filter(&["/messages/0"]).matches(&append), ["/messages/0"]);
+
+        let moved = touched_paths(&ops(json!([{"op": "move", "from": "/status", "path": "/archive/status"}])));
+        assert_eq!(filter(&["/status"]).matches(&moved), ["/status"]);
+        assert_eq!(filter(&["/archive"]).matches(&moved), ["/archive"]);
+        let copied = touched_paths(&ops(json!([{"op": "copy", "from": "/status", "path": "/backup"}])));
+        assert_eq!(filter(&["/status"]).matches(&copied), ["/status"]);
+    }
+
+    #[test]
+    fn test_escaped_tokens() {
+        let watch = filter(&["/a~1b/c~0d"]);
+
+        let hit = touched_paths(&ops(json!([{"op": "add", "path": "/a~1b/c~0d/e", "value": 1}])));
+        assert_eq!(watch.matches(&hit), ["/a~1b/c~0d"]);
+        // "/a/b" is two tokens, not the single token "a/b"
+        let miss = touched_paths(&ops(json!([{"op": "add", "path": "/a/b/c~0d", "value": 1}])));
+        assert!(watch.matches(&miss).is_empty());
+
+        assert!(PointerFilter::new(vec!["status".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,118 @@
+//! Bulk ingestion of new coordinates with and without the coordinate filter
+//!
+//! Every store to an unseen coordinate that the filter rules out skips three
+//! queries (chain, latest snapshot, coordinate row); the filtered run prints
+//! how many lookups were avoided. `create_batch` times 10k coordinates from
+//! `generate_batch` going in through one `create_coordinates` call.
+
+use bms_core::types::{Coordinate, CoordId};
+use bms_core::{CoordinateGenerator, SnapshotManager};
+use bms_storage::{BmsFacade, BmsRepository, StoreParams};
+use criterion::{criterion_group, criterion_main, Criterion};
+use serde_json::json;
+use std::time::{Duration, Instant};
+use tokio::runtime::Runtime;
+
+async fn facade(name: &str, filtered: bool) -> BmsFacade {
+    let path = std::env::temp_dir().join(format!("bms-bench-{}-{}.db", name, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let repository = BmsRepository::new(&path).await.unwrap();
+    let facade = BmsFacade::new(repository, SnapshotManager::new(128));
+    if filtered {
+        facade.with_coord_filter(0.01).await.unwrap()
+    } else {
+        facade
+    }
+}
+
+async fn ingest(facade: &BmsFacade, run: u64, count: u64) -> Duration {
+    let started = Instant::now();
+    for i in 0..count {
+        facade
+            .store(StoreParams {
+                coord_id: Some(CoordId(format!("BENCH-{}-{}", run, i))),
+                state: json!({"run": run, "item": i}),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    }
+    started.elapsed()
+}
+
+fn bench_ingest(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("ingest_new_coordinates");
+    group.sample_size(10);
+
+    for filtered in [false, true] {
+        let name = if filtered { "with_filter" } else { "without_filter" };
+        let facade = rt.block_on(facade(name, filtered));
+        let mut run = 0;
+        group.bench_function(name, |b| {
+            b.iter_custom(|iters| {
+                run += 1;
+                rt.block_on(ingest(&facade, run, iters))
+            })
+        });
+
+        if let Some(stats) = facade.coord_filter_stats() {
+            println!(
+                "{}: {} lookups avoided ({} queries), {} false positives, {} rebuilds",
+                name,
+                stats.lookups_avoided,
+                stats.lookups_avoided * 3,
+                stats.false_positives,
+                stats.rebuilds
+            );
+        }
+    }
+
+    group.finish();
+}
+
+async fn create_batch(facade: &BmsFacade, run: u64, count: u64) -> Duration {
+    let started = Instant::now();
+    let states: Vec<_> = (0..count).map(|i| json!({"run": run, "item": i})).collect();
+    let now = chrono::Utc::now();
+    let coords: Vec<Coordinate> = CoordinateGenerator::generate_batch(&states, &now)
+        .unwrap()
+        .into_iter()
+        .map(|id| Coordinate {
+            rune_alias: Some(CoordinateGenerator::generate_alias(&id)),
+            id,
+            created_at: now,
+            metadata: None,
+            namespace: None,
+            hash_algorithm: Default::default(),
+            kind: Default::default(),
+            parent_coord_id: None,
+        })
+        .collect();
+    assert_eq!(facade.create_coordinates(&coords).await.unwrap(), count);
+    started.elapsed()
+}
+
+fn bench_batch(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("create_batch");
+    group.sample_size(10);
+
+    let facade = rt.block_on(facade("batch", false));
+    let mut run = 0;
+    group.bench_function("10k_coordinates", |b| {
+        b.iter_custom(|iters| {
+            (0..iters)
+                .map(|_| {
+                    run += 1;
+                    rt.block_on(create_batch(&facade, run, 10_000))
+                })
+                .sum()
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_ingest, bench_batch);
+criterion_main!(benches);
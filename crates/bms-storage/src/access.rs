@@ -0,0 +1,205 @@
+//! Per-coordinate read statistics
+//!
+//! Reads are counted in memory and written to the `coord_access` table in
+//! batches, so recording a read never touches the database.
+
+use crate::models::AccessRecord;
+use crate::repository::BmsRepository;
+use bms_core::types::CoordId;
+use bms_core::{ImportancePolicy, Result};
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+#[derive(Debug, Clone, Copy)]
+struct PendingAccess {
+    read_count: u64,
+    last_read_at: DateTime<Utc>,
+}
+
+/// In-memory read counter flushed periodically to storage
+pub struct AccessTracker {
+    enabled: bool,
+    /// Bumps importance per flushed read when set
+    importance: Option<ImportancePolicy>,
+    pending: Mutex<HashMap<CoordId, PendingAccess>>,
+}
+
+impl AccessTracker {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            importance: None,
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Add the policy's `access_bump` to a coordinate's importance for every read
+    pub fn with_importance(mut self, policy: ImportancePolicy) -> Self {
+        self.importance = Some(policy);
+        self
+    }
+
+    /// Whether reads are being tracked
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Count a read of a coordinate
+    pub fn record_read(&self, coord_id: &CoordId) {
+        if !self.enabled {
+            return;
+        }
+
+        let now = Utc::now();
+        let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+        pending
+            .entry(coord_id.clone())
+            .and_modify(|p| {
+                p.read_count += 1;
+                p.last_read_at = now;
+            })
+            .or_insert(PendingAccess {
+                read_count: 1,
+                last_read_at: now,
+            });
+    }
+
+    /// Number of coordinates with unflushed reads
+    pub fn pending_len(&self) -> usize {
+        self.pending.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    /// Write all pending counts to the repository in one batch
+    ///
+    /// Returns the number of coordinates flushed. On failure the counts are
+    /// merged back so the next flush retries them.
+    pub async fn flush(&self, repository: &BmsRepository) -> Result<usize> {
+        let batch = std::mem::take(&mut *self.pending.lock().unwrap_or_else(|e| e.into_inner()));
+        if batch.is_empty() {
+            return Ok(0);
+        }
+
+        let records: Vec<AccessRecord> = batch
+            .iter()
+            .map(|(coord_id, p)| AccessRecord {
+                coord_id: coord_id.clone(),
+                read_count: p.read_count,
+                last_read_at: p.last_read_at,
+            })
+            .collect();
+
+        if let Err(e) = repository.record_access_batch(&records, self.importance.as_ref()).await {
+            let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+            for (coord_id, p) in batch {
+                pending
+                    .entry(coord_id)
+                    .and_modify(|cur| {
+                        cur.read_count += p.read_count;
+                        cur.last_read_at = cur.last_read_at.max(p.last_read_at);
+                    })
+                    .or_insert(p);
+            }
+            return Err(e);
+        }
+
+        Ok(records.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDb;
+    use crate::StoreParams;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_flush_accumulates_counts() {
+        let db = TempDb::new("access-flush");
+        let facade = db.facade(128).await;
+        let hot = CoordId("HOTCOORD".to_string());
+        let cold = CoordId("COLDCOORD".to_string());
+        for coord in [&hot, &cold] {
+            facade
+                .store(StoreParams {
+                    coord_id: Some(coord.clone()),
+                    state: json!({"coord": coord.0}),
+                    ..Default::default()
+                })
+                .await
+                .unwrap();
+        }
+
+        let tracker = AccessTracker::new(true);
+        for _ in 0..3 {
+            tracker.record_read(&hot);
+        }
+        tracker.record_read(&cold);
+        tracker.record_read(&CoordId("UNKNOWN".to_string()));
+        assert_eq!(tracker.flush(facade.repository()).await.unwrap(), 3);
+        assert_eq!(tracker.pending_len(), 0);
+
+        tracker.record_read(&hot);
+        tracker.flush(facade.repository()).await.unwrap();
+
+        let stats = facade.repository().get_hot_coordinates(10).await.unwrap();
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[0].coord_id, hot);
+        assert_eq!(stats[0].read_count, 4);
+        assert_eq!(stats[0].delta_count, 1);
+        assert_eq!(stats[1].read_count, 1);
+    }
+
+    #[tokio::test]
+    async fn test_reads_and_reinforcement_raise_importance() {
+        let db = TempDb::new("access-importance");
+        let facade = db.facade(128).await;
+        let hot = CoordId("HOTCOORD".to_string());
+        let cold = CoordId("COLDCOORD".to_string());
+        for coord in [&hot, &cold] {
+            facade
+                .store(StoreParams {
+                    coord_id: Some(coord.clone()),
+                    state: json!({"coord": coord.0}),
+                    ..Default::default()
+                })
+                .await
+                .unwrap();
+        }
+
+        // No decay, so the values are exact
+        let policy = ImportancePolicy {
+            half_life_secs: 0.0,
+            access_bump: 0.05,
+        };
+        let tracker = AccessTracker::new(true).with_importance(policy);
+        for _ in 0..3 {
+            tracker.record_read(&hot);
+        }
+        tracker.flush(facade.repository()).await.unwrap();
+
+        let repo = facade.repository();
+        let reinforced = repo.reinforce_importance(&cold, -0.2, &policy, Utc::now()).await.unwrap();
+        assert!((reinforced.unwrap() - 0.3).abs() < 1e-6);
+        let missing = CoordId("UNKNOWN".to_string());
+        assert_eq!(repo.reinforce_importance(&missing, 0.2, &policy, Utc::now()).await.unwrap(), None);
+
+        let mut importance = repo.get_importance(None).await.unwrap();
+        importance.sort_by(|a, b| a.coord_id.0.cmp(&b.coord_id.0));
+        assert_eq!(importance[0].coord_id, cold);
+        assert!((importance[0].importance - 0.3).abs() < 1e-6);
+        assert_eq!(importance[1].coord_id, hot);
+        assert!((importance[1].importance - 0.65).abs() < 1e-6);
+
+        facade.delete_coordinate(&hot).await.unwrap();
+        assert!(repo.get_importance(Some(&hot)).await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_disabled_tracker_ignores_reads() {
+        let tracker = AccessTracker::new(false);
+        tracker.record_read(&CoordId("HOTCOORD".to_string()));
+        assert_eq!(tracker.pending_len(), 0);
+    }
+}
@@ -0,0 +1,196 @@
+//! Bloom filter over coordinate IDs
+//!
+//! Lets the store path skip the coordinate lookup for IDs that have never been
+//! seen, which is the common case during bulk ingestion. A miss is definite; a
+//! hit only means the authoritative query still has to run.
+
+use bms_core::types::CoordId;
+use serde::Serialize;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// Smallest capacity the filter is sized for
+const MIN_CAPACITY: usize = 1024;
+
+/// Counters reported in `/stats`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CoordFilterStats {
+    /// Filter size in bits
+    pub size_bits: usize,
+    pub hash_count: u32,
+    /// IDs the filter was sized for
+    pub capacity: usize,
+    /// IDs inserted since the last rebuild
+    pub items: usize,
+    /// Share of bits set
+    pub fill: f64,
+    pub false_positive_rate: f64,
+    /// Lookups answered without a query
+    pub lookups_avoided: u64,
+    /// Possible hits the query showed to be absent
+    pub false_positives: u64,
+    pub rebuilds: u64,
+}
+
+/// Coordinate ID membership filter
+#[derive(Debug)]
+pub struct CoordFilter {
+    bits: Vec<u64>,
+    size_bits: usize,
+    hash_count: u32,
+    capacity: usize,
+    items: usize,
+    /// Set when deleted IDs are still in the filter; cleared by a rebuild
+    stale: bool,
+    false_positive_rate: f64,
+    lookups_avoided: u64,
+    false_positives: u64,
+    rebuilds: u64,
+}
+
+impl CoordFilter {
+    /// Filter sized for twice `expected` IDs at the given false-positive rate
+    pub fn with_rate(expected: usize, false_positive_rate: f64) -> Self {
+        let rate = false_positive_rate.clamp(1e-9, 0.5);
+        let capacity = (expected * 2).max(MIN_CAPACITY);
+        let ln2 = std::f64::consts::LN_2;
+        let size_bits = ((-(capacity as f64) * rate.ln()) / (ln2 * ln2)).ceil() as usize;
+        let size_bits = size_bits.next_multiple_of(64);
+        let hash_count = ((size_bits as f64 / capacity as f64) * ln2).round().max(1.0) as u32;
+
+        Self {
+            bits: vec![0; size_bits / 64],
+            size_bits,
+            hash_count,
+            capacity,
+            items: 0,
+            stale: false,
+            false_positive_rate: rate,
+            lookups_avoided: 0,
+            false_positives: 0,
+            rebuilds: 0,
+        }
+    }
+
+    /// Filter holding `ids`, keeping the counters of `self`
+    pub fn rebuilt<'a, I>(&self, ids: I, count: usize) -> Self
+    where
+        I: IntoIterator<Item = &'a CoordId>,
+    {
+        let mut filter = Self::with_rate(count, self.false_positive_rate);
+        for id in ids {
+            filter.insert(id);
+        }
+        filter.lookups_avoided = self.lookups_avoided;
+        filter.false_positives = self.false_positives;
+        filter.rebuilds = self.rebuilds + 1;
+        filter
+    }
+
+    pub fn insert(&mut self, id: &CoordId) {
+        for index in self.indexes(id) {
+            self.bits[index / 64] |= 1 << (index % 64);
+        }
+        self.items += 1;
+    }
+
+    /// False means the ID was definitely never inserted
+    pub fn may_contain(&self, id: &CoordId) -> bool {
+        self.indexes(id)
+            .all(|index| self.bits[index / 64] & (1 << (index % 64)) != 0)
+    }
+
+    /// Whether the filter should be rebuilt before its next use
+    ///
+    /// Past capacity the false-positive rate climbs above the configured one;
+    /// after deletes the removed IDs keep answering as possible hits.
+    pub fn needs_rebuild(&self) -> bool {
+        self.stale || self.items > self.capacity
+    }
+
+    pub fn mark_stale(&mut self) {
+        self.stale = true;
+    }
+
+    pub fn record_avoided(&mut self) {
+        self.lookups_avoided += 1;
+    }
+
+    pub fn record_false_positive(&mut self) {
+        self.false_positives += 1;
+    }
+
+    pub fn stats(&self) -> CoordFilterStats {
+        let set: u32 = self.bits.iter().map(|w| w.count_ones()).sum();
+        CoordFilterStats {
+            size_bits: self.size_bits,
+            hash_count: self.hash_count,
+            capacity: self.capacity,
+            items: self.items,
+            fill: set as f64 / self.size_bits as f64,
+            false_positive_rate: self.false_positive_rate,
+            lookups_avoided: self.lookups_avoided,
+            false_positives: self.false_positives,
+            rebuilds: self.rebuilds,
+        }
+    }
+
+    /// Bit positions for an ID, by double hashing
+    fn indexes(&self, id: &CoordId) -> impl Iterator<Item = usize> {
+        let h1 = hash_with(id, 0);
+        let h2 = hash_with(id, 0x9e37_79b9_7f4a_7c15) | 1;
+        let size = self.size_bits as u64;
+        (0..u64::from(self.hash_count))
+            .map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % size) as usize)
+    }
+}
+
+fn hash_with(id: &CoordId, seed: u64) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    seed.hash(&mut hasher);
+    id.0.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(n: usize) -> CoordId {
+        CoordId(format!("COORD{}", n))
+    }
+
+    #[test]
+    fn test_no_false_negatives_and_bounded_false_positives() {
+        let mut filter = CoordFilter::with_rate(5000, 0.01);
+        for n in 0..5000 {
+            filter.insert(&id(n));
+        }
+        assert!((0..5000).all(|n| filter.may_contain(&id(n))));
+
+        let false_positives = (5000..55_000).filter(|&n| filter.may_contain(&id(n))).count();
+        // Half full, so well under the configured rate
+        assert!(false_positives < 500, "{} false positives", false_positives);
+        assert!(!filter.needs_rebuild());
+    }
+
+    #[test]
+    fn test_rebuild_when_saturated_or_stale() {
+        let mut filter = CoordFilter::with_rate(0, 0.01);
+        let ids: Vec<CoordId> = (0..MIN_CAPACITY + 1).map(id).collect();
+        for id in &ids {
+            filter.insert(id);
+        }
+        filter.record_avoided();
+        assert!(filter.needs_rebuild());
+
+        let mut rebuilt = filter.rebuilt(&ids, ids.len());
+        assert!(!rebuilt.needs_rebuild());
+        assert_eq!(rebuilt.stats().capacity, ids.len() * 2);
+        assert_eq!(rebuilt.stats().lookups_avoided, 1);
+        assert_eq!(rebuilt.stats().rebuilds, 1);
+
+        rebuilt.mark_stale();
+        assert!(rebuilt.needs_rebuild());
+    }
+}
@@ -0,0 +1,424 @@
+//! Restore drills
+//!
+//! A backup nobody has restored is not known to be one. `run` copies a
+//! backup into a scratch directory, opens the copy (which applies the current
+//! schema, as any upgrade would), and checks it: SQLite's quick check, chain
+//! and snapshot verification of a random sample or every coordinate, a
+//! recall of the largest head, and optionally an oplog replay on top. The
+//! copy is deleted afterwards unless it is kept for inspection.
+
+use crate::facade::BmsFacade;
+use crate::oplog;
+use crate::repository::BmsRepository;
+use crate::sampler::{verify_coordinates, SampleFailure};
+use bms_core::{BmsError, Canonicalizer, SnapshotManager, DEFAULT_SNAPSHOT_INTERVAL};
+use chrono::Utc;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use serde::Serialize;
+use std::future::Future;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Coordinates verified by a drill that does not check them all
+pub const DEFAULT_DRILL_SAMPLE: usize = 50;
+
+const SQLITE_HEADER: &[u8; 16] = b"SQLite format 3\0";
+
+/// Drill settings
+#[derive(Debug, Clone)]
+pub struct DrillConfig {
+    /// Backup database file
+    pub backup: PathBuf,
+    /// Coordinates to verify, or `None` for all of them
+    pub sample: Option<usize>,
+    /// NDJSON from `oplog export` replayed onto the restored copy
+    pub oplog: Option<PathBuf>,
+    /// Where the scratch directory is created
+    pub work_dir: PathBuf,
+    /// Leave the restored copy in place
+    pub keep: bool,
+    /// Maximum chains verified at once
+    pub concurrency: usize,
+}
+
+impl DrillConfig {
+    pub fn new(backup: impl Into<PathBuf>) -> Self {
+        Self {
+            backup: backup.into(),
+            sample: Some(DEFAULT_DRILL_SAMPLE),
+            oplog: None,
+            work_dir: std::env::temp_dir(),
+            keep: false,
+            concurrency: 4,
+        }
+    }
+}
+
+/// Stage at which a drill failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DrillFailure {
+    /// The backup could not be copied or is not a SQLite database
+    Restore,
+    /// The restored copy could not be opened with the current schema
+    Migration,
+    /// The restored data failed a check
+    Integrity,
+}
+
+/// One check of a drill
+#[derive(Debug, Clone, Serialize)]
+pub struct DrillStep {
+    pub name: &'static str,
+    pub ok: bool,
+    pub elapsed_ms: f64,
+    pub detail: String,
+}
+
+/// Outcome of a drill
+#[derive(Debug, Clone, Serialize)]
+pub struct DrillReport {
+    pub backup: PathBuf,
+    /// The restored copy, if it was kept
+    pub kept: Option<PathBuf>,
+    pub steps: Vec<DrillStep>,
+    /// Coordinates whose chain or snapshot failed verification
+    pub failures: Vec<SampleFailure>,
+    pub failure: Option<DrillFailure>,
+    pub elapsed_ms: f64,
+}
+
+impl DrillReport {
+    pub fn is_ok(&self) -> bool {
+        self.failure.is_none()
+    }
+}
+
+/// Restore `config.backup` into a scratch directory and check the copy
+///
+/// Problems are reported, not returned as errors. Restore and migration
+/// failures end the drill; integrity checks all run, so one report lists
+/// every failed check.
+pub async fn run(config: &DrillConfig) -> DrillReport {
+    let started = Instant::now();
+    let dir = config.work_dir.join(format!(
+        "bms-drill-{}-{}",
+        std::process::id(),
+        Utc::now().timestamp_nanos_opt().unwrap_or_default()
+    ));
+    let restored = dir.join("restored.db");
+
+    let mut drill = Drill::default();
+    let failure = drill.run(config, &dir, &restored).await;
+
+    let kept = if config.keep && dir.exists() {
+        Some(restored)
+    } else {
+        let _ = std::fs::remove_dir_all(&dir);
+        None
+    };
+    DrillReport {
+        backup: config.backup.clone(),
+        kept,
+        steps: drill.steps,
+        failures: drill.failures,
+        failure,
+        elapsed_ms: started.elapsed().as_secs_f64() * 1000.0,
+    }
+}
+
+#[derive(Default)]
+struct Drill {
+    steps: Vec<DrillStep>,
+    failures: Vec<SampleFailure>,
+}
+
+impl Drill {
+    async fn run(&mut self, config: &DrillConfig, dir: &Path, restored: &Path) -> Option<DrillFailure> {
+        let restore = async {
+            let bytes = restore(&config.backup, dir, restored).map_err(|e| e.to_string())?;
+            Ok(format!("{} bytes copied to {}", bytes, restored.display()))
+        };
+        if !self.check("restore", restore).await {
+            return Some(DrillFailure::Restore);
+        }
+
+        let mut repository = None;
+        let migrate = async {
+            let repo = BmsRepository::new(restored).await.map_err(|e| e.to_string())?;
+            let stats = repo.get_stats().await.map_err(|e| e.to_string())?;
+            repository = Some(repo);
+            Ok(format!(
+                "{} coordinates, {} deltas, {} snapshots",
+                stats.coordinate_count, stats.delta_count, stats.snapshot_count
+            ))
+        };
+        if !self.check("migrate", migrate).await {
+            return Some(DrillFailure::Migration);
+        }
+        let repository = repository.expect("set by a successful migrate step");
+        let facade = Arc::new(BmsFacade::new(repository, SnapshotManager::new(DEFAULT_SNAPSHOT_INTERVAL)));
+        let repo = facade.repository();
+
+        let quick_check = async {
+            let problems = repo.quick_check().await.map_err(|e| e.to_string())?;
+            match problems.is_empty() {
+                true => Ok("ok".to_string()),
+                false => Err(problems.join("; ")),
+            }
+        };
+        let mut ok = self.check("quick_check", quick_check).await;
+
+        let mut failures = Vec::new();
+        let verify = async {
+            let mut coords = repo.list_coordinate_ids().await.map_err(|e| e.to_string())?;
+            let total = coords.len();
+            if let Some(sample) = config.sample.filter(|&sample| sample < total) {
+                coords.shuffle(&mut StdRng::from_entropy());
+                coords.truncate(sample);
+            }
+            let checked = coords.len();
+            failures = verify_coordinates(&facade, coords, config.concurrency)
+                .await
+                .map_err(|e| e.to_string())?;
+            match failures.len() {
+                0 => Ok(format!("{} of {} chains verified", checked, total)),
+                failed => Err(format!("{} of {} checked chains failed", failed, checked)),
+            }
+        };
+        ok &= self.check("verify", verify).await;
+        self.failures = failures;
+
+        let recall = async {
+            let sizes = repo.list_state_sizes(Some(1)).await.map_err(|e| e.to_string())?;
+            let Some(largest) = sizes.into_iter().next() else {
+                return Ok("skipped: no head sizes recorded".to_string());
+            };
+            let head = facade
+                .head(&largest.coord_id)
+                .await
+                .map_err(|e| e.to_string())?
+                .ok_or_else(|| format!("{} has no deltas", largest.coord_id))?;
+            let bytes = Canonicalizer::canonical_len(&head.state).map_err(|e| e.to_string())? as u64;
+            let at_recorded_head = head.deltas.last().is_some_and(|d| d.id == largest.head_delta_id);
+            if at_recorded_head && bytes != largest.state_bytes {
+                return Err(format!(
+                    "{} recalled as {} bytes, recorded as {}",
+                    largest.coord_id, bytes, largest.state_bytes
+                ));
+            }
+            Ok(format!("{} ({} bytes, {} deltas)", largest.coord_id, bytes, head.deltas.len()))
+        };
+        ok &= self.check("recall_largest", recall).await;
+
+        if let Some(path) = &config.oplog {
+            let replay = async {
+                oplog::verify_export(path).map_err(|e| e.to_string())?;
+                let input = std::fs::File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+                let report = oplog::apply(repo, std::io::BufReader::new(input))
+                    .await
+                    .map_err(|e| e.to_string())?;
+                Ok(format!("{} applied, {} already present", report.applied, report.skipped))
+            };
+            ok &= self.check("replay_oplog", replay).await;
+        }
+
+        (!ok).then_some(DrillFailure::Integrity)
+    }
+
+    /// Time one check and record its outcome
+    async fn check<F>(&mut self, name: &'static str, check: F) -> bool
+    where
+        F: Future<Output = std::result::Result<String, String>>,
+    {
+        let started = Instant::now();
+        let outcome = check.await;
+        let ok = outcome.is_ok();
+        self.steps.push(DrillStep {
+            name,
+            ok,
+            elapsed_ms: started.elapsed().as_secs_f64() * 1000.0,
+            detail: outcome.unwrap_or_else(|e| e),
+        });
+        ok
+    }
+}
+
+/// Copy a backup and its write-ahead log, if it has one, to `restored`
+fn restore(backup: &Path, dir: &Path, restored: &Path) -> std::io::Result<u64> {
+    let mut header = [0u8; 100];
+    let mut file = std::fs::File::open(backup)?;
+    file.read_exact(&mut header[..16])
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidData, "not a SQLite database"))?;
+    if &header[..16] != SQLITE_HEADER {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "not a SQLite database"));
+    }
+    let len = file.metadata()?.len();
+    let complete = file
+        .read_exact(&mut header[16..])
+        .map_err(|e| e.to_string())
+        .and_then(|_| check_size(&header, len));
+    if let Err(reason) = complete {
+        let partial = BmsError::PartialFile {
+            path: backup.display().to_string(),
+            reason,
+        };
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, partial));
+    }
+
+    std::fs::create_dir_all(dir)?;
+    let bytes = std::fs::copy(backup, restored)?;
+    // A copy taken while the database was open keeps recent pages in the WAL
+    let wal = PathBuf::from(format!("{}-wal", backup.display()));
+    if wal.exists() {
+        std::fs::copy(&wal, format!("{}-wal", restored.display()))?;
+    }
+    Ok(bytes)
+}
+
+/// Check a database file's length against the page size and count in its
+/// header
+///
+/// The page count is only trusted when the header marks it valid, as SQLite
+/// does; a file that ends mid-page is always partial.
+fn check_size(header: &[u8; 100], len: u64) -> std::result::Result<(), String> {
+    let page_size = match u16::from_be_bytes([header[16], header[17]]) {
+        1 => 65536,
+        size => u64::from(size),
+    };
+    if page_size < 512 || !page_size.is_power_of_two() {
+        return Err(format!("invalid page size {}", page_size));
+    }
+    if !len.is_multiple_of(page_size) {
+        return Err(format!("{} bytes is not a whole number of {} byte pages", len, page_size));
+    }
+    let pages = u64::from(u32::from_be_bytes([header[28], header[29], header[30], header[31]]));
+    let count_valid = header[92..96] == header[24..28];
+    if count_valid && len < pages * page_size {
+        return Err(format!("header lists {} pages, file has {}", pages, len / page_size));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDb;
+    use crate::StoreParams;
+    use bms_core::types::CoordId;
+    use serde_json::json;
+
+    async fn fixture(name: &str) -> TempDb {
+        let db = TempDb::new(name);
+        let facade = db.facade(2).await;
+        for coord in ["DRILLA", "DRILLB", "BROKEN"] {
+            for n in 0..3 {
+                facade
+                    .store(StoreParams {
+                        coord_id: Some(CoordId(coord.to_string())),
+                        state: json!({"items": (0..=n).map(|i| format!("{}-{}", coord, i)).collect::<Vec<_>>()}),
+                        ..Default::default()
+                    })
+                    .await
+                    .unwrap();
+            }
+        }
+        db
+    }
+
+    fn config(db: &TempDb) -> DrillConfig {
+        DrillConfig {
+            sample: None,
+            ..DrillConfig::new(&db.path)
+        }
+    }
+
+    fn step<'a>(report: &'a DrillReport, name: &str) -> &'a DrillStep {
+        report.steps.iter().find(|s| s.name == name).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_clean_backup_passes_and_is_cleaned_up() {
+        let db = fixture("drill-clean").await;
+        let report = run(&config(&db)).await;
+
+        assert!(report.is_ok(), "{:?}", report.steps);
+        assert!(step(&report, "verify").detail.starts_with("3 of 3"));
+        assert!(step(&report, "recall_largest").detail.contains("3 deltas"));
+        assert!(report.kept.is_none());
+        let restored = step(&report, "restore").detail.rsplit(' ').next().unwrap().to_string();
+        assert!(!Path::new(&restored).exists());
+
+        // A kept copy survives, and an oplog replays onto it as a no-op
+        let export = db.path.with_extension("ndjson");
+        let mut out = std::fs::File::create(&export).unwrap();
+        oplog::export(&db.repository().await, 0, &mut out).await.unwrap();
+        let kept = run(&DrillConfig {
+            keep: true,
+            oplog: Some(export.clone()),
+            sample: Some(2),
+            ..DrillConfig::new(&db.path)
+        })
+        .await;
+        assert!(kept.is_ok(), "{:?}", kept.steps);
+        assert!(step(&kept, "verify").detail.starts_with("2 of 3"));
+        assert!(step(&kept, "replay_oplog").detail.starts_with("0 applied"));
+        let restored = kept.kept.unwrap();
+        assert!(restored.exists());
+        std::fs::remove_dir_all(restored.parent().unwrap()).unwrap();
+        std::fs::remove_file(export).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_corrupted_chain_fails_integrity() {
+        let db = fixture("drill-corrupt").await;
+        db.execute("UPDATE deltas SET chain_hash = lower(hex(randomblob(32))) WHERE coord_id = 'BROKEN' AND parent_id IS NOT NULL")
+            .await;
+
+        let report = run(&config(&db)).await;
+        assert_eq!(report.failure, Some(DrillFailure::Integrity));
+        assert!(!step(&report, "verify").ok);
+        assert!(step(&report, "quick_check").ok);
+        let failed: Vec<&str> = report.failures.iter().map(|f| f.coord_id.as_str()).collect();
+        assert_eq!(failed, ["BROKEN"]);
+    }
+
+    #[tokio::test]
+    async fn test_restore_and_migration_failures_are_distinguished() {
+        let db = TempDb::new("drill-not-sqlite");
+        std::fs::write(&db.path, "definitely not a database").unwrap();
+        let report = run(&config(&db)).await;
+        assert_eq!(report.failure, Some(DrillFailure::Restore));
+        assert_eq!(report.steps.len(), 1);
+
+        let missing = run(&DrillConfig::new("/nonexistent/backup.db")).await;
+        assert_eq!(missing.failure, Some(DrillFailure::Restore));
+
+        // A copy interrupted part-way through a page
+        let db = fixture("drill-truncated").await;
+        db.execute("PRAGMA wal_checkpoint(TRUNCATE)").await;
+        let file = std::fs::OpenOptions::new().write(true).open(&db.path).unwrap();
+        file.set_len(file.metadata().unwrap().len() - 100).unwrap();
+        let report = run(&config(&db)).await;
+        assert_eq!(report.failure, Some(DrillFailure::Restore));
+        let detail = &step(&report, "restore").detail;
+        assert!(detail.starts_with(&format!("{} is truncated or partial", db.path.display())), "{}", detail);
+
+        // A table the current schema cannot index
+        let db = TempDb::new("drill-old-schema");
+        let options = sqlx::sqlite::SqliteConnectOptions::new()
+            .filename(&db.path)
+            .create_if_missing(true);
+        let pool = sqlx::SqlitePool::connect_with(options).await.unwrap();
+        sqlx::query("CREATE TABLE coordinates (id TEXT)").execute(&pool).await.unwrap();
+        pool.close().await;
+        let report = run(&config(&db)).await;
+        assert_eq!(report.failure, Some(DrillFailure::Migration), "{:?}", report.steps);
+        assert!(step(&report, "restore").ok);
+        assert!(report.kept.is_none());
+    }
+}
@@ -0,0 +1,3319 @@
+//! Store/recall pipeline shared by the API server and the CLI
+//!
+//! Every mutation goes through `BmsFacade` so that delta hashing, Merkle
+//! linking, and snapshot policy are applied identically regardless of the
+//! entry point.
+
+use crate::bloom::{CoordFilter, CoordFilterStats};
+use crate::models::{CompactReport, HeadRows, MaterializedHead, ReconstructionCheckpoint};
+use crate::oplog::{self, ApplyReport, OpKind, OplogEntry};
+use crate::repository::BmsRepository;
+use bms_core::error::BmsError;
+use bms_core::links::LINKS_METADATA_KEY;
+use bms_core::types::{
+    CompressionStats, Coordinate, CoordId, CoordIdKind, Delta, DeltaId, Hash, Snapshot, EPHEMERAL_METADATA_KEY,
+};
+use bms_core::delta::{OpOutcome, FULL_REPLACE_MIN_STATE_BYTES, FULL_REPLACE_TAG, PATCH_RATIO_TAG};
+use bms_core::snapshot::externalize_pointers;
+use bms_core::{
+    extract_links, watch, Acl, Canonicalizer, CoordinateGenerator, DeltaEngine, DeltaPolicy, DiffOptions,
+    DiffStats, HashAlgorithm, LinkRules, MerkleChain, OpsStats, PatchRatios, RedactionRules, Result, SnapshotContext,
+    SnapshotManager,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, Mutex, Notify};
+use tracing::{info, warn};
+
+/// Expected head of a coordinate, checked before a store is applied
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StorePrecondition {
+    /// Head delta must have this chain hash (HTTP `If-Match`)
+    HeadChainHash(Hash),
+    /// Head delta must have this ID
+    HeadDeltaId(DeltaId),
+}
+
+impl StorePrecondition {
+    /// Fails with `PreconditionFailed` unless `head`, the head delta's ID and
+    /// chain hash, is the expected one
+    pub(crate) fn check(&self, head: Option<(&str, &str)>) -> Result<()> {
+        let (expected, actual) = match self {
+            StorePrecondition::HeadChainHash(expected) => (&expected.0, head.map(|(_, hash)| hash)),
+            StorePrecondition::HeadDeltaId(expected) => (&expected.0, head.map(|(id, _)| id)),
+        };
+        if actual != Some(expected.as_str()) {
+            return Err(BmsError::PreconditionFailed {
+                expected: expected.clone(),
+                actual: actual.unwrap_or("none").to_string(),
+                head_chain_hash: head.map(|(_, hash)| hash.to_string()),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// What deleting a coordinate that other coordinates link to does
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LinkDeletePolicy {
+    /// Delete and log a warning; the links become dangling
+    #[default]
+    Warn,
+    /// Refuse with `BmsError::CoordinateReferenced`
+    Block,
+}
+
+impl std::str::FromStr for LinkDeletePolicy {
+    type Err = BmsError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "warn" => Ok(Self::Warn),
+            "block" => Ok(Self::Block),
+            other => Err(BmsError::InvalidState(format!("Unknown link delete policy: {}", other))),
+        }
+    }
+}
+
+/// Integrity checks on the read path, beyond the chain checks every append
+/// gets
+///
+/// Each check is off by default; `strict` turns them all on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct IntegrityChecks {
+    /// Recompute a snapshot's state hash before replaying from it
+    pub verify_snapshots: bool,
+    /// Recompute every loaded delta's hash and chain link before a head is
+    /// served
+    pub verify_deltas: bool,
+    /// Fail where reads would otherwise repair and warn: a snapshot that
+    /// fails verification, a checkpoint that no longer matches its chain, a
+    /// materialized head behind its chain
+    pub warnings_are_errors: bool,
+}
+
+impl IntegrityChecks {
+    /// Every check on
+    pub fn strict() -> Self {
+        Self {
+            verify_snapshots: true,
+            verify_deltas: true,
+            warnings_are_errors: true,
+        }
+    }
+
+    pub fn is_strict(&self) -> bool {
+        *self == Self::strict()
+    }
+
+    /// Names of the checks that are on, for startup logs and `/health`
+    pub fn enabled(&self) -> Vec<&'static str> {
+        [
+            (self.verify_snapshots, "verify_snapshots"),
+            (self.verify_deltas, "verify_deltas"),
+            (self.warnings_are_errors, "warnings_are_errors"),
+        ]
+        .into_iter()
+        .filter_map(|(on, name)| on.then_some(name))
+        .collect()
+    }
+}
+
+/// Parameters for storing a new state
+#[derive(Debug, Clone, Default)]
+pub struct StoreParams {
+    /// Target coordinate; generated from the state when absent
+    pub coord_id: Option<CoordId>,
+    pub state: Value,
+    /// Coordinate metadata, only used when the coordinate is created
+    pub metadata: Option<HashMap<String, Value>>,
+    pub author: Option<String>,
+    /// Optimistic concurrency check against the current head
+    pub precondition: Option<StorePrecondition>,
+    /// Diff options for this store; falls back to the coordinate's `diff` metadata
+    pub diff_options: Option<DiffOptions>,
+    /// Measure the phases of the store into `StoreOutcome::timings`
+    pub explain: bool,
+    /// Trusted timestamp for the delta instead of the server clock, for
+    /// importers replaying history. Must not precede the head delta or lie in
+    /// the future; every use is written to the audit log.
+    pub created_at: Option<DateTime<Utc>>,
+    /// Author of each computed op, for deltas written by several authors;
+    /// must have one entry per op
+    pub op_authors: Option<Vec<String>>,
+    /// Limits the delta and new head must keep to, checked before anything
+    /// is written
+    pub policy: Option<DeltaPolicy>,
+    /// Namespace a generated coordinate ID is mixed with and a new
+    /// coordinate is created in; `None` is the default namespace
+    pub namespace: Option<String>,
+    /// Hash function of a new coordinate's chain and generated ID; ignored
+    /// for an existing coordinate, which keeps its own (default SHA3-256)
+    pub hash_algorithm: Option<HashAlgorithm>,
+    /// Existing coordinate a new coordinate branches from, recorded as its
+    /// parent; ignored for an existing coordinate
+    pub parent_coord_id: Option<CoordId>,
+    /// With `parent_coord_id` and no `coord_id`, the target is
+    /// `CoordinateGenerator::derive_child(parent, label)`
+    pub child_label: Option<String>,
+}
+
+/// What the snapshot policy did for a store
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SnapshotStatus {
+    /// Written in the same call
+    Created,
+    /// Due, and queued for the snapshot worker
+    Scheduled,
+    /// Not due at this delta
+    Skipped,
+}
+
+/// Whether the new head was handed to the search index
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IndexStatus {
+    Queued,
+    Skipped,
+    /// No store-time indexing; search embeds heads on demand
+    Disabled,
+}
+
+/// Something worth telling the writer about a store that still succeeded
+///
+/// Codes are stable: a code is never renamed or reused for another meaning,
+/// and new codes may be added at any time, so clients must ignore codes they
+/// do not know.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StoreWarning {
+    /// Metadata was given for a coordinate that already exists and was ignored
+    MetadataIgnored,
+    /// The state equals the head; an empty delta was appended
+    EmptyDelta,
+    /// The new head is larger than the configured size warning
+    LargeState,
+}
+
+impl StoreWarning {
+    pub fn code(self) -> &'static str {
+        match self {
+            StoreWarning::MetadataIgnored => "metadata_ignored",
+            StoreWarning::EmptyDelta => "empty_delta",
+            StoreWarning::LargeState => "large_state",
+        }
+    }
+}
+
+/// Head of the chain after a store
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoreHead {
+    pub delta_id: DeltaId,
+    pub chain_hash: Hash,
+    /// Position of the delta in the chain, starting at 1
+    pub seq: u64,
+}
+
+/// Time spent in each phase of a store, in milliseconds
+///
+/// In a group store `write_ms` is the shared transaction.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct StoreTimings {
+    pub prepare_ms: f64,
+    pub write_ms: f64,
+    pub snapshot_ms: f64,
+    /// How the previous head was reconstructed
+    #[serde(default)]
+    pub replay: ReplayStats,
+}
+
+/// How a head was reconstructed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplayStats {
+    /// Deltas covered by the snapshot replay started from
+    pub snapshot_seq: Option<u64>,
+    /// Deltas covered by the checkpoint replay resumed from, when newer
+    /// than the snapshot
+    pub checkpoint_seq: Option<u64>,
+    /// Deltas applied
+    pub replayed: usize,
+    pub checkpoints_written: usize,
+    /// Read from the materialized head state without replaying
+    pub materialized: bool,
+}
+
+/// Result of a store operation
+#[derive(Debug, Clone, Serialize)]
+pub struct StoreOutcome {
+    pub coord_id: CoordId,
+    pub head: StoreHead,
+    pub coordinate_created: bool,
+    pub snapshot: SnapshotStatus,
+    pub indexed: IndexStatus,
+    /// Whether the store matched an existing head and wrote nothing
+    pub deduplicated: bool,
+    pub warnings: Vec<StoreWarning>,
+    /// Serialized size of the delta ops in bytes
+    pub ops_bytes: usize,
+    /// Canonical size of the computed patch relative to the new state;
+    /// `None` for a coordinate's first delta and for empty deltas
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub patch_ratio: Option<f64>,
+    /// Whether the state was stored whole because the patch was larger
+    /// than `DiffOptions::full_replace_ratio` allows
+    pub full_replace: bool,
+    /// Array diff strategies used for this delta
+    pub diff_stats: DiffStats,
+    /// Only measured when `StoreParams::explain` is set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timings: Option<StoreTimings>,
+}
+
+/// Result of appending deltas computed by another instance
+#[derive(Debug, Clone, Serialize)]
+pub struct AppendOutcome {
+    pub coord_id: CoordId,
+    /// Deltas written by this call
+    pub appended: usize,
+    /// Leading deltas the chain already had, e.g. from an interrupted attempt
+    pub already_present: usize,
+    pub coordinate_created: bool,
+    pub head: StoreHead,
+}
+
+/// Store computed by the facade but not yet written
+#[derive(Debug, Clone)]
+pub struct PreparedStore {
+    /// Coordinate to create along with the delta
+    pub coordinate: Option<Coordinate>,
+    pub delta: Delta,
+    /// New head state
+    pub state: Value,
+    /// Whether the snapshot policy asks for a snapshot at this delta
+    pub snapshot_due: bool,
+    /// Chain position of the delta
+    pub seq: u64,
+    pub ops_bytes: usize,
+    pub patch_ratio: Option<f64>,
+    pub full_replace: bool,
+    pub diff_stats: DiffStats,
+    pub warnings: Vec<StoreWarning>,
+    /// Present when the store was explained; filled in as phases finish
+    pub timings: Option<StoreTimings>,
+    /// Whether `delta.created_at` came from `StoreParams::created_at`
+    pub created_at_overridden: bool,
+    /// Links and state size of the new head, written with the delta. Links
+    /// are `None` when the coordinate has no link rules.
+    pub head: HeadRows,
+    /// Expected head, checked again in the transaction that writes the delta
+    pub precondition: Option<StorePrecondition>,
+}
+
+impl PreparedStore {
+    fn outcome(self, snapshot: SnapshotStatus) -> StoreOutcome {
+        StoreOutcome {
+            coord_id: self.delta.coord_id,
+            head: StoreHead {
+                delta_id: self.delta.id,
+                chain_hash: self.delta.chain_hash,
+                seq: self.seq,
+            },
+            coordinate_created: self.coordinate.is_some(),
+            snapshot,
+            indexed: IndexStatus::Disabled,
+            deduplicated: false,
+            warnings: self.warnings,
+            ops_bytes: self.ops_bytes,
+            patch_ratio: self.patch_ratio,
+            full_replace: self.full_replace,
+            diff_stats: self.diff_stats,
+            timings: self.timings,
+        }
+    }
+}
+
+fn elapsed_ms(since: Instant) -> f64 {
+    since.elapsed().as_secs_f64() * 1000.0
+}
+
+/// Reconstructed head of a coordinate
+#[derive(Debug, Clone)]
+pub struct Head {
+    pub state: Value,
+    pub deltas: Vec<Delta>,
+    pub replay: ReplayStats,
+}
+
+/// Notification published after a delta is appended
+#[derive(Debug, Clone, Serialize)]
+pub struct DeltaEvent {
+    pub coord_id: CoordId,
+    pub delta_id: DeltaId,
+    pub parent_id: Option<DeltaId>,
+    pub chain_hash: Hash,
+    pub author: Option<String>,
+    pub created_at: DateTime<Utc>,
+    /// Decoded paths the delta's ops modify, for watched-path filtering
+    #[serde(skip)]
+    pub touched: Arc<Vec<Vec<String>>>,
+}
+
+/// Change to a coordinate's existence, identity, or metadata, published
+/// after the storage transaction commits
+///
+/// Derived indexes (embeddings, caches) subscribe to these to drop, re-key,
+/// or update entries. `Renamed`, `Archived`, and `Restored` have no producer
+/// yet; they are reserved for the rename and archive operations.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum StorageEvent {
+    Deleted { coord_id: CoordId },
+    Renamed { old: CoordId, new: CoordId },
+    Archived { coord_id: CoordId },
+    Restored { coord_id: CoordId },
+    /// The coordinate's metadata after the update
+    MetadataUpdated {
+        coord_id: CoordId,
+        metadata: HashMap<String, Value>,
+    },
+}
+
+/// Buffered events per subscriber before it is reported as lagging
+pub const EVENT_CHANNEL_CAPACITY: usize = 1024;
+
+/// Deltas replayed between reconstruction checkpoints
+pub const DEFAULT_CHECKPOINT_INTERVAL: usize = 10_000;
+
+/// Age after which reconstruction checkpoints are dropped
+pub const DEFAULT_CHECKPOINT_TTL: Duration = Duration::from_secs(24 * 3600);
+
+/// Nonces tried after a generated coordinate ID collides, before a store
+/// fails with `CoordinateCollision`
+pub const MAX_COLLISION_RETRIES: u32 = 8;
+
+/// Metadata flag that keeps a coordinate's full head state in storage
+pub const MATERIALIZE_HEAD_METADATA_KEY: &str = "materialize_head";
+
+/// Snapshot interval this instance runs with
+///
+/// The interval stored in the database wins over `default`, so a binary with
+/// a different `DEFAULT_SNAPSHOT_INTERVAL` keeps snapshotting as before. A
+/// `configured` interval (from the environment or a flag) replaces the stored
+/// one, with a notice, and is kept for later startups.
+pub async fn resolve_snapshot_interval(
+    repository: &BmsRepository,
+    default: u32,
+    configured: Option<u32>,
+) -> Result<u32> {
+    let stored = repository.snapshot_interval().await?;
+    match (stored, configured) {
+        (Some(stored), Some(configured)) if stored == configured => Ok(stored),
+        (stored, Some(configured)) => {
+            repository.set_snapshot_interval(configured).await?;
+            if let Some(stored) = stored {
+                warn!("Snapshot interval changed from {} to {} by configuration", stored, configured);
+            }
+            Ok(configured)
+        }
+        (Some(stored), None) => {
+            if stored != default {
+                info!(
+                    "Using the stored snapshot interval {} instead of this build's default {}; \
+                     change it with `bms config set snapshot_interval`",
+                    stored, default
+                );
+            }
+            Ok(stored)
+        }
+        (None, None) => {
+            repository.set_snapshot_interval(default).await?;
+            Ok(default)
+        }
+    }
+}
+
+/// A boolean metadata flag; absent is false
+fn metadata_flag(metadata: &HashMap<String, Value>, key: &str) -> Result<bool> {
+    match metadata.get(key) {
+        None => Ok(false),
+        Some(Value::Bool(flag)) => Ok(*flag),
+        Some(_) => Err(BmsError::InvalidState(format!("{} must be a boolean", key))),
+    }
+}
+
+/// Whether coordinate metadata asks for a materialized head state
+fn materializes_head(metadata: &HashMap<String, Value>) -> Result<bool> {
+    metadata_flag(metadata, MATERIALIZE_HEAD_METADATA_KEY)
+}
+
+/// High-level BMS operations on top of the repository
+pub struct BmsFacade {
+    repository: BmsRepository,
+    snapshot_manager: SnapshotManager,
+    /// Serializes stores so head reads, precondition checks, and inserts are atomic
+    /// within this process
+    write_lock: Mutex<()>,
+    events: broadcast::Sender<DeltaEvent>,
+    storage_events: broadcast::Sender<StorageEvent>,
+    /// Coordinates whose snapshot was deferred by a group store
+    pending_snapshots: StdMutex<HashSet<CoordId>>,
+    snapshot_wakeup: Notify,
+    /// Skips coordinate lookups for IDs never seen; `None` unless enabled
+    coord_filter: Option<StdMutex<CoordFilter>>,
+    /// Bumped after every mutation that can change a coordinate head
+    generation: AtomicU64,
+    link_policy: LinkDeletePolicy,
+    /// Head size in canonical bytes above which stores warn
+    state_size_warning: Option<u64>,
+    /// Deltas between reconstruction checkpoints; `None` disables them
+    checkpoint_interval: Option<usize>,
+    checkpoint_ttl: Duration,
+    /// Time after which a long replay stops at its next checkpoint
+    replay_budget: Option<Duration>,
+    /// Why writes are refused, on a standby that replicates from elsewhere
+    read_only: Option<String>,
+    integrity: IntegrityChecks,
+    /// Patch ratios of the deltas stored since startup
+    patch_ratios: StdMutex<PatchRatios>,
+}
+
+impl BmsFacade {
+    pub fn new(repository: BmsRepository, snapshot_manager: SnapshotManager) -> Self {
+        let (events, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+        let (storage_events, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+        Self {
+            repository,
+            snapshot_manager,
+            write_lock: Mutex::new(()),
+            events,
+            storage_events,
+            pending_snapshots: StdMutex::new(HashSet::new()),
+            snapshot_wakeup: Notify::new(),
+            coord_filter: None,
+            generation: AtomicU64::new(0),
+            link_policy: LinkDeletePolicy::default(),
+            state_size_warning: None,
+            checkpoint_interval: Some(DEFAULT_CHECKPOINT_INTERVAL),
+            checkpoint_ttl: DEFAULT_CHECKPOINT_TTL,
+            replay_budget: None,
+            read_only: None,
+            integrity: IntegrityChecks::default(),
+            patch_ratios: StdMutex::new(PatchRatios::default()),
+        }
+    }
+
+    /// Choose what deleting a linked-to coordinate does
+    pub fn with_link_policy(mut self, policy: LinkDeletePolicy) -> Self {
+        self.link_policy = policy;
+        self
+    }
+
+    /// Warn with `StoreWarning::LargeState` on stores whose head state is
+    /// larger than `bytes` in canonical form
+    pub fn with_state_size_warning(mut self, bytes: u64) -> Self {
+        self.state_size_warning = Some(bytes);
+        self
+    }
+
+    /// Head state size above which stores warn, if any
+    pub fn state_size_warning(&self) -> Option<u64> {
+        self.state_size_warning
+    }
+
+    /// Checkpoint replays every `interval` deltas, keeping checkpoints for
+    /// `ttl`; an interval of 0 disables checkpoints
+    pub fn with_reconstruction_checkpoints(mut self, interval: usize, ttl: Duration) -> Self {
+        self.checkpoint_interval = (interval > 0).then_some(interval);
+        self.checkpoint_ttl = ttl;
+        self
+    }
+
+    /// Fail replays still running after `budget` at their next checkpoint
+    ///
+    /// The checkpoint is kept, so retrying continues from it.
+    pub fn with_replay_budget(mut self, budget: Duration) -> Self {
+        self.replay_budget = Some(budget);
+        self
+    }
+
+    /// Refuse stores, deletes, snapshots, and metadata patches with
+    /// `BmsError::ReadOnly`; only `apply_replicated` writes
+    pub fn with_read_only(mut self, reason: impl Into<String>) -> Self {
+        self.read_only = Some(reason.into());
+        self
+    }
+
+    /// Choose the read-path integrity checks
+    pub fn with_integrity_checks(mut self, checks: IntegrityChecks) -> Self {
+        self.integrity = checks;
+        self
+    }
+
+    /// Read-path integrity checks in effect
+    pub fn integrity_checks(&self) -> IntegrityChecks {
+        self.integrity
+    }
+
+    /// Warn about an integrity problem a read can repair, or fail with it
+    /// when warnings are errors
+    fn integrity_warning(&self, message: String) -> Result<()> {
+        if self.integrity.warnings_are_errors {
+            return Err(BmsError::IntegrityViolation(message));
+        }
+        warn!("{}", message);
+        Ok(())
+    }
+
+    /// Why writes are refused, if they are
+    pub fn read_only(&self) -> Option<&str> {
+        self.read_only.as_deref()
+    }
+
+    fn check_writable(&self) -> Result<()> {
+        match &self.read_only {
+            Some(reason) => Err(BmsError::ReadOnly(reason.clone())),
+            None => Ok(()),
+        }
+    }
+
+    /// Consult a Bloom filter of coordinate IDs before looking coordinates up
+    ///
+    /// The filter is sized from the current coordinate count and rebuilt on
+    /// the next store once it saturates or after a delete.
+    pub async fn with_coord_filter(mut self, false_positive_rate: f64) -> Result<Self> {
+        let ids = self.repository.list_coordinate_ids().await?;
+        let mut filter = CoordFilter::with_rate(ids.len(), false_positive_rate);
+        for id in &ids {
+            filter.insert(id);
+        }
+        info!("Coordinate filter loaded with {} IDs", ids.len());
+
+        self.coord_filter = Some(StdMutex::new(filter));
+        Ok(self)
+    }
+
+    /// Coordinate filter counters, if the filter is enabled
+    /// How large stored deltas' patches were relative to their states,
+    /// since startup
+    pub fn patch_ratios(&self) -> PatchRatios {
+        *self.patch_ratios.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn coord_filter_stats(&self) -> Option<CoordFilterStats> {
+        self.coord_filter
+            .as_ref()
+            .map(|f| f.lock().unwrap_or_else(|e| e.into_inner()).stats())
+    }
+
+    /// Subscribe to deltas appended through this facade
+    ///
+    /// Events are published in chain order; a receiver that falls more than
+    /// `EVENT_CHANNEL_CAPACITY` events behind gets `RecvError::Lagged`.
+    pub fn subscribe(&self) -> broadcast::Receiver<DeltaEvent> {
+        self.events.subscribe()
+    }
+
+    /// Access the underlying repository
+    pub fn repository(&self) -> &BmsRepository {
+        &self.repository
+    }
+
+    /// Access the snapshot manager
+    pub fn snapshot_manager(&self) -> &SnapshotManager {
+        &self.snapshot_manager
+    }
+
+    /// Reconstruct the head state of a coordinate
+    ///
+    /// A materialized head state is used as it is while its chain hash is
+    /// the head's. One that fell behind is rebuilt by replaying. Returns
+    /// `None` when the coordinate has no deltas.
+    pub async fn head(&self, coord_id: &CoordId) -> Result<Option<Head>> {
+        let deltas = self.repository.get_deltas(coord_id).await?;
+        let Some(tip) = deltas.last() else {
+            return Ok(None);
+        };
+        if self.integrity.verify_deltas {
+            let algorithm = self.repository.get_hash_algorithm(coord_id).await?;
+            let mut head = None;
+            for delta in &deltas {
+                oplog::verify_append(head, delta, algorithm)?;
+                head = Some((delta.id.clone(), delta.chain_hash.clone()));
+            }
+        }
+
+        let materialized = self.repository.get_head_state(coord_id).await?;
+        if let Some(materialized) = materialized.as_ref().filter(|m| m.chain_hash == tip.chain_hash) {
+            let replay = ReplayStats {
+                materialized: true,
+                ..ReplayStats::default()
+            };
+            return Ok(Some(Head { state: materialized.state.clone(), deltas, replay }));
+        }
+
+        if materialized.is_some() {
+            self.integrity_warning(format!("Materialized head of {} is behind its chain, rebuilding it", coord_id))?;
+        }
+        let (state, replay) = self.reconstruct(coord_id, &deltas).await?;
+        if materialized.is_some() {
+            self.record_head_state(tip, &state).await;
+        }
+
+        Ok(Some(Head { state, deltas, replay }))
+    }
+
+    /// Replay a chain from its latest snapshot, ignoring any materialized head
+    async fn reconstruct(&self, coord_id: &CoordId, deltas: &[Delta]) -> Result<(Value, ReplayStats)> {
+        let snapshot = self.repository.get_latest_snapshot(coord_id).await?;
+        self.replay(coord_id, snapshot, deltas).await
+    }
+
+    /// Materialize `state` as the head at `tip`; failures only cost the next
+    /// recall a replay
+    async fn record_head_state(&self, tip: &Delta, state: &Value) {
+        let head = MaterializedHead {
+            coord_id: tip.coord_id.clone(),
+            head_delta_id: tip.id.clone(),
+            chain_hash: tip.chain_hash.clone(),
+            state: state.clone(),
+            updated_at: Utc::now(),
+        };
+        if let Err(e) = self.repository.record_head_state(&head).await {
+            warn!("Materializing the head of {} failed: {}", tip.coord_id, e);
+        }
+    }
+
+    /// Compare a materialized head state with a reconstruction of its chain
+    ///
+    /// Returns what is wrong with it, or `None` when it is current or the
+    /// coordinate has none. A head that is only behind is not drift, since
+    /// the next recall rebuilds it.
+    pub async fn check_head_state(&self, coord_id: &CoordId) -> Result<Option<String>> {
+        let Some(materialized) = self.repository.get_head_state(coord_id).await? else {
+            return Ok(None);
+        };
+        let deltas = self.repository.get_deltas(coord_id).await?;
+        let Some(tip) = deltas.last() else {
+            return Ok(Some("materialized head of a coordinate without deltas".to_string()));
+        };
+        if materialized.chain_hash != tip.chain_hash {
+            return Ok(None);
+        }
+
+        let (state, _) = self.reconstruct(coord_id, &deltas).await?;
+        let expected = DeltaEngine::hash_state(&state)?;
+        let actual = DeltaEngine::hash_state(&materialized.state)?;
+        Ok((expected != actual).then(|| {
+            format!("materialized head hashes to {} but its chain to {}", actual.0, expected.0)
+        }))
+    }
+
+    /// Replace a coordinate's materialized head state with a reconstruction
+    ///
+    /// Returns whether one was written; coordinates without `materialize_head`
+    /// or without deltas get none.
+    pub async fn rebuild_head_state(&self, coord_id: &CoordId) -> Result<bool> {
+        let _guard = self.write_lock.lock().await;
+
+        let metadata = self.repository.get_coordinate(coord_id).await?.and_then(|c| c.metadata);
+        if !metadata.as_ref().map(materializes_head).transpose()?.unwrap_or(false) {
+            return Ok(false);
+        }
+        let deltas = self.repository.get_deltas(coord_id).await?;
+        let Some(tip) = deltas.last() else {
+            return Ok(false);
+        };
+        let (state, _) = self.reconstruct(coord_id, &deltas).await?;
+        let head = MaterializedHead {
+            coord_id: coord_id.clone(),
+            head_delta_id: tip.id.clone(),
+            chain_hash: tip.chain_hash.clone(),
+            state,
+            updated_at: Utc::now(),
+        };
+        self.repository.record_head_state(&head).await
+    }
+
+    /// Replay `deltas` from the snapshot or the newest usable checkpoint
+    ///
+    /// Replays of at least `checkpoint_interval` deltas save a checkpoint
+    /// every interval and yield, so a caller's timeout can cancel between
+    /// checkpoints without losing the progress.
+    async fn replay(
+        &self,
+        coord_id: &CoordId,
+        snapshot: Option<Snapshot>,
+        deltas: &[Delta],
+    ) -> Result<(Value, ReplayStats)> {
+        let snapshot = match snapshot {
+            Some(snapshot) if self.integrity.verify_snapshots => self.verified_snapshot(coord_id, snapshot)?,
+            snapshot => snapshot,
+        };
+        let mut start = SnapshotManager::covered_deltas(snapshot.as_ref(), deltas)?;
+        let mut replay = ReplayStats {
+            snapshot_seq: snapshot.as_ref().map(|_| start as u64),
+            ..ReplayStats::default()
+        };
+        let mut state = snapshot.map_or_else(|| serde_json::json!({}), |s| s.state);
+
+        let interval = self.checkpoint_interval.filter(|&n| deltas.len() - start >= n);
+        if interval.is_some() {
+            if let Some(checkpoint) = self.resume_point(coord_id, deltas, start).await? {
+                start = checkpoint.seq as usize;
+                replay.checkpoint_seq = Some(checkpoint.seq);
+                state = checkpoint.state;
+            }
+        }
+
+        let started = Instant::now();
+        for (seq, delta) in deltas.iter().enumerate().skip(start).map(|(i, d)| (i + 1, d)) {
+            DeltaEngine::apply_delta(&mut state, &delta.ops)?;
+            replay.replayed += 1;
+
+            let Some(interval) = interval else { continue };
+            if !replay.replayed.is_multiple_of(interval) || seq == deltas.len() {
+                continue;
+            }
+            self.save_checkpoint(coord_id, seq as u64, &delta.id, &state).await;
+            replay.checkpoints_written += 1;
+            if self.replay_budget.is_some_and(|budget| started.elapsed() >= budget) {
+                return Err(BmsError::ReconstructionFailed(format!(
+                    "Replay of {} stopped at delta {} of {} after its time budget; retrying resumes there",
+                    coord_id,
+                    seq,
+                    deltas.len()
+                )));
+            }
+            tokio::task::yield_now().await;
+        }
+
+        Ok((state, replay))
+    }
+
+    /// `snapshot` if its state still hashes to its recorded hash
+    ///
+    /// A snapshot that does not is skipped, so the chain is replayed from
+    /// the start, unless warnings are errors.
+    fn verified_snapshot(&self, coord_id: &CoordId, snapshot: Snapshot) -> Result<Option<Snapshot>> {
+        match self.snapshot_manager.verify_snapshot(&snapshot) {
+            Ok(()) => Ok(Some(snapshot)),
+            Err(e) => {
+                self.integrity_warning(format!(
+                    "Snapshot {} of {} fails verification ({}), replaying the whole chain",
+                    snapshot.id, coord_id, e
+                ))?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Newest checkpoint past the first `covered` deltas that matches the chain
+    ///
+    /// Checkpoints a snapshot has caught up with, and ones that no longer
+    /// match the chain, are deleted.
+    async fn resume_point(
+        &self,
+        coord_id: &CoordId,
+        deltas: &[Delta],
+        covered: usize,
+    ) -> Result<Option<ReconstructionCheckpoint>> {
+        let Some(checkpoint) = self.repository.get_checkpoint(coord_id, deltas.len() as u64).await? else {
+            return Ok(None);
+        };
+
+        let seq = checkpoint.seq as usize;
+        if seq <= covered {
+            self.repository.delete_checkpoints(coord_id, Some(covered as u64)).await?;
+            return Ok(None);
+        }
+        let matches_chain = deltas[seq - 1].id == checkpoint.delta_id;
+        if !matches_chain || !DeltaEngine::hash_state(&checkpoint.state)?.matches(&checkpoint.state_hash) {
+            self.integrity_warning(format!(
+                "Discarding reconstruction checkpoint {} of {} that no longer matches",
+                seq, coord_id
+            ))?;
+            self.repository.delete_checkpoints(coord_id, None).await?;
+            return Ok(None);
+        }
+        Ok(Some(checkpoint))
+    }
+
+    /// Save a checkpoint and drop expired ones; failures only cost the resume
+    async fn save_checkpoint(&self, coord_id: &CoordId, seq: u64, delta_id: &DeltaId, state: &Value) {
+        let saved = async {
+            let checkpoint = ReconstructionCheckpoint {
+                coord_id: coord_id.clone(),
+                seq,
+                delta_id: delta_id.clone(),
+                state_hash: DeltaEngine::hash_state(state)?,
+                state: state.clone(),
+                created_at: Utc::now(),
+            };
+            self.repository.put_checkpoint(&checkpoint).await?;
+            let cutoff = chrono::Duration::from_std(self.checkpoint_ttl)
+                .ok()
+                .and_then(|ttl| checkpoint.created_at.checked_sub_signed(ttl));
+            if let Some(cutoff) = cutoff {
+                self.repository.prune_checkpoints(cutoff).await?;
+            }
+            Ok::<_, BmsError>(())
+        };
+        if let Err(e) = saved.await {
+            warn!("Saving reconstruction checkpoint {} of {} failed: {}", seq, coord_id, e);
+        }
+    }
+
+    /// Compression of a coordinate's chain, see
+    /// `BmsRepository::get_compression_stats`
+    ///
+    /// A head whose size was never recorded, e.g. one written by an import,
+    /// is reconstructed and measured first. Ops are counted by kind over the
+    /// chain as loaded. `None` if the coordinate has no deltas.
+    pub async fn compression_stats(&self, coord_id: &CoordId) -> Result<Option<CompressionStats>> {
+        let deltas = self.repository.get_deltas(coord_id).await?;
+        let Some(tip) = deltas.last() else {
+            return Ok(None);
+        };
+        let mut ops = OpsStats::default();
+        for delta in &deltas {
+            ops.merge(&DeltaEngine::ops_statistics(&delta.ops));
+        }
+        if let Some(stats) = self.repository.get_compression_stats(coord_id).await? {
+            return Ok(Some(stats.with_ops(ops)));
+        }
+        let (state, _) = self.reconstruct(coord_id, &deltas).await?;
+        let state_bytes = Canonicalizer::canonical_len(&state)? as u64;
+        self.repository.record_state_size(coord_id, &tip.id, state_bytes).await?;
+        // A store in between records its own size, which is just as current
+        Ok(self.repository.get_compression_stats(coord_id).await?.map(|stats| stats.with_ops(ops)))
+    }
+
+    /// Record the head state size of every coordinate
+    ///
+    /// Reconstructs up to `concurrency` heads at once. A head that moves
+    /// while it is measured keeps the size its store recorded. Returns the
+    /// number of sizes written.
+    pub async fn recompute_state_sizes(self: &Arc<Self>, concurrency: usize) -> Result<usize> {
+        let semaphore = Arc::new(tokio::sync::Semaphore::new(concurrency.max(1)));
+        let mut tasks = tokio::task::JoinSet::new();
+        for coord_id in self.repository.list_coordinate_ids().await? {
+            let facade = self.clone();
+            let semaphore = semaphore.clone();
+            tasks.spawn(async move {
+                let _permit = semaphore.acquire_owned().await.expect("semaphore is never closed");
+                let Some(head) = facade.head(&coord_id).await? else {
+                    return Ok(false);
+                };
+                let bytes = Canonicalizer::canonical_len(&head.state)? as u64;
+                let head_delta_id = &head.deltas.last().expect("head has deltas").id;
+                facade.repository.record_state_size(&coord_id, head_delta_id, bytes).await
+            });
+        }
+
+        let mut recorded = 0;
+        while let Some(joined) = tasks.join_next().await {
+            let written: Result<bool> =
+                joined.map_err(|e| BmsError::Other(format!("Size task failed: {}", e)))?;
+            if written? {
+                recorded += 1;
+            }
+        }
+        info!("Recorded state sizes of {} coordinates", recorded);
+        Ok(recorded)
+    }
+
+    /// Counter that changes whenever a coordinate head may have changed
+    ///
+    /// Results derived from heads (search results, caches) can record it and
+    /// treat themselves as stale once it moves.
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    /// Subscribe to coordinate deletions, renames, archival, and metadata
+    /// updates
+    pub fn subscribe_storage(&self) -> broadcast::Receiver<StorageEvent> {
+        self.storage_events.subscribe()
+    }
+
+    /// Create many coordinates without states, for bulk ingest
+    ///
+    /// See `BmsRepository::insert_coordinates_batch`; IDs from
+    /// `CoordinateGenerator::generate_batch` are unique within the batch.
+    /// Returns how many were created.
+    pub async fn create_coordinates(&self, coords: &[Coordinate]) -> Result<u64> {
+        self.check_writable()?;
+        let _guard = self.write_lock.lock().await;
+        let created = self.repository.insert_coordinates_batch(coords).await?;
+        self.with_filter(|f| coords.iter().for_each(|c| f.insert(&c.id)));
+        info!("Created {} of {} coordinates in a batch", created, coords.len());
+        Ok(created)
+    }
+
+    /// Delete a coordinate and its whole history
+    ///
+    /// Links to it from other coordinates are handled by the link policy.
+    /// Returns false if the coordinate did not exist.
+    pub async fn delete_coordinate(&self, coord_id: &CoordId) -> Result<bool> {
+        self.check_writable()?;
+        let _guard = self.write_lock.lock().await;
+        self.delete_locked(coord_id).await
+    }
+
+    /// `delete_coordinate` for callers holding the write lock
+    async fn delete_locked(&self, coord_id: &CoordId) -> Result<bool> {
+        let backlinks = self.repository.get_backlinks(coord_id).await?;
+        if !backlinks.is_empty() && self.repository.coordinate_exists(coord_id).await? {
+            let referrers = backlinks
+                .iter()
+                .map(|l| &l.from_coord)
+                .collect::<HashSet<_>>()
+                .len();
+            match self.link_policy {
+                LinkDeletePolicy::Block => {
+                    return Err(BmsError::CoordinateReferenced {
+                        coord_id: coord_id.0.clone(),
+                        referrers,
+                    });
+                }
+                LinkDeletePolicy::Warn => warn!(
+                    "Deleting {} leaves {} dangling link(s) from {} coordinate(s)",
+                    coord_id,
+                    backlinks.len(),
+                    referrers
+                ),
+            }
+        }
+
+        let deleted = self.repository.delete_coordinate(coord_id).await?;
+        if deleted {
+            self.generation.fetch_add(1, Ordering::AcqRel);
+            self.with_filter(CoordFilter::mark_stale);
+            info!("Deleted coordinate: {}", coord_id);
+            let _ = self.storage_events.send(StorageEvent::Deleted {
+                coord_id: coord_id.clone(),
+            });
+        }
+        Ok(deleted)
+    }
+
+    /// Delete ephemeral coordinates neither written nor read for `idle` before `now`
+    ///
+    /// Runs under the write lock, so a store or promotion cannot land between
+    /// the check and the delete. A coordinate the link policy refuses to
+    /// delete is logged and kept. Returns the deleted IDs.
+    pub async fn collect_ephemeral(&self, idle: Duration, now: DateTime<Utc>) -> Result<Vec<CoordId>> {
+        self.check_writable()?;
+        let _guard = self.write_lock.lock().await;
+
+        let cutoff = now - chrono::Duration::from_std(idle).unwrap_or(chrono::Duration::MAX);
+        let mut deleted = Vec::new();
+        for coord in self.repository.list_ephemeral().await? {
+            if coord.last_touched_at >= cutoff {
+                continue;
+            }
+            match self.delete_locked(&coord.coord_id).await {
+                Ok(true) => deleted.push(coord.coord_id),
+                Ok(false) => {}
+                Err(BmsError::CoordinateReferenced { .. }) => {
+                    warn!("Keeping idle ephemeral coordinate {}: other coordinates link to it", coord.coord_id);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(deleted)
+    }
+
+    /// Clear a coordinate's ephemeral flag, so it is listed, searched, and kept
+    ///
+    /// Returns whether the flag was set, or `None` if the coordinate does not
+    /// exist.
+    pub async fn promote(&self, coord_id: &CoordId) -> Result<Option<bool>> {
+        let Some(coordinate) = self.repository.get_coordinate(coord_id).await? else {
+            return Ok(None);
+        };
+        if !coordinate.is_ephemeral() {
+            return Ok(Some(false));
+        }
+        let patch = HashMap::from([(EPHEMERAL_METADATA_KEY.to_string(), Value::Null)]);
+        Ok(self.patch_metadata(coord_id, patch).await?.map(|_| true))
+    }
+
+    /// Snapshot the current head of a coordinate
+    ///
+    /// Returns the existing snapshot if the latest one already covers the head,
+    /// and `None` when the coordinate has no deltas.
+    pub async fn create_snapshot(&self, coord_id: &CoordId) -> Result<Option<Snapshot>> {
+        self.check_writable()?;
+        let _guard = self.write_lock.lock().await;
+
+        let Some(head) = self.head(coord_id).await? else {
+            return Ok(None);
+        };
+        let head_delta_id = head.deltas.last().expect("head has deltas").id.clone();
+        if let Some(latest) = self.repository.get_latest_snapshot(coord_id).await? {
+            if latest.head_delta_id == head_delta_id {
+                return Ok(Some(latest));
+            }
+        }
+        let snapshot =
+            self.snapshot_manager
+                .create_snapshot(coord_id.clone(), head_delta_id, head.state)?;
+        self.repository.insert_snapshot(&snapshot).await?;
+
+        Ok(Some(snapshot))
+    }
+
+    /// Drop all but the `keep` most recent snapshots of a coordinate
+    pub async fn prune_snapshots(&self, coord_id: &CoordId, keep: u32) -> Result<u64> {
+        self.check_writable()?;
+        let _guard = self.write_lock.lock().await;
+        self.repository.prune_snapshots(coord_id, keep.max(1)).await
+    }
+
+    /// Merge `patch` into a coordinate's metadata
+    ///
+    /// Keys set to `null` are removed. The result is validated like the
+    /// metadata of a new coordinate, and the head's links are extracted again
+    /// when the link rules change. Returns the new metadata, or `None` if the
+    /// coordinate does not exist.
+    pub async fn patch_metadata(
+        &self,
+        coord_id: &CoordId,
+        patch: HashMap<String, Value>,
+    ) -> Result<Option<HashMap<String, Value>>> {
+        self.check_writable()?;
+        let _guard = self.write_lock.lock().await;
+
+        let Some(coordinate) = self.repository.get_coordinate(coord_id).await? else {
+            return Ok(None);
+        };
+        let previous = coordinate.metadata.unwrap_or_default();
+        let mut metadata = previous.clone();
+        for (key, value) in patch {
+            if value.is_null() {
+                metadata.remove(&key);
+            } else {
+                metadata.insert(key, value);
+            }
+        }
+        RedactionRules::from_metadata(&metadata)?;
+        DiffOptions::from_metadata(&metadata)?;
+        Acl::from_metadata(&metadata)?;
+        externalize_pointers(&metadata)?;
+        let materialize = materializes_head(&metadata)?;
+        metadata_flag(&metadata, EPHEMERAL_METADATA_KEY)?;
+        let rules = LinkRules::from_metadata(&metadata)?;
+
+        let links = if metadata.get(LINKS_METADATA_KEY) == previous.get(LINKS_METADATA_KEY) {
+            None
+        } else {
+            let rules = rules.unwrap_or_default();
+            match self.head(coord_id).await? {
+                Some(head) if !rules.is_empty() => Some(extract_links(&head.state, &rules)),
+                _ => Some(Vec::new()),
+            }
+        };
+        if !self
+            .repository
+            .update_coordinate_metadata(coord_id, &metadata, links.as_deref())
+            .await?
+        {
+            return Ok(None);
+        }
+        if materialize != materializes_head(&previous).unwrap_or(false) {
+            if materialize {
+                if let Some(head) = self.head(coord_id).await? {
+                    self.record_head_state(head.deltas.last().expect("head has deltas"), &head.state)
+                        .await;
+                }
+            } else {
+                self.repository.delete_head_state(coord_id).await?;
+            }
+        }
+
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        let _ = self.storage_events.send(StorageEvent::MetadataUpdated {
+            coord_id: coord_id.clone(),
+            metadata: metadata.clone(),
+        });
+        Ok(Some(metadata))
+    }
+
+    /// Redaction rules from the coordinate's metadata
+    ///
+    /// Malformed rules are an error rather than ignored, so recall fails closed.
+    pub async fn redaction_rules(&self, coord_id: &CoordId) -> Result<RedactionRules> {
+        let metadata = self
+            .repository
+            .get_coordinate(coord_id)
+            .await?
+            .and_then(|c| c.metadata);
+
+        match metadata {
+            Some(metadata) => Ok(RedactionRules::from_metadata(&metadata)?.unwrap_or_default()),
+            None => Ok(RedactionRules::default()),
+        }
+    }
+
+    /// Store a new state, appending a delta to the coordinate's chain
+    pub async fn store(&self, params: StoreParams) -> Result<StoreOutcome> {
+        self.check_writable()?;
+        let _guard = self.write_lock.lock().await;
+
+        let prepared = self.prepare(params, None).await?;
+        self.write_prepared(prepared).await
+    }
+
+    /// Undo the last `steps` deltas of a coordinate by appending one delta
+    ///
+    /// The new delta holds the inverted ops of the undone deltas, newest
+    /// first, so the head goes back to the state before them while the chain
+    /// stays append-only. `steps` must be between 1 and the chain length.
+    pub async fn rollback(
+        &self,
+        coord_id: &CoordId,
+        steps: usize,
+        author: Option<String>,
+    ) -> Result<StoreOutcome> {
+        self.check_writable()?;
+        let _guard = self.write_lock.lock().await;
+
+        let Some(head) = self.head(coord_id).await? else {
+            return Err(BmsError::InvalidState(format!(
+                "Coordinate {} has no deltas to roll back",
+                coord_id
+            )));
+        };
+        if steps == 0 || steps > head.deltas.len() {
+            return Err(BmsError::InvalidState(format!(
+                "Cannot roll back {} of the {} deltas of {}",
+                steps,
+                head.deltas.len(),
+                coord_id
+            )));
+        }
+        let keep = head.deltas.len() - steps;
+        let mut state = match keep.checked_sub(1) {
+            Some(last) => DeltaEngine::replay_to(&head.deltas, &head.deltas[last].id)?,
+            None => serde_json::json!({}),
+        };
+        let mut undo = Vec::with_capacity(steps);
+        for delta in &head.deltas[keep..] {
+            undo.push(DeltaEngine::invert_delta(&delta.ops, &state)?);
+            DeltaEngine::apply_delta(&mut state, &delta.ops)?;
+        }
+        let ops = undo.into_iter().rev().flatten().collect();
+
+        let params = StoreParams {
+            coord_id: Some(coord_id.clone()),
+            state: head.state,
+            author,
+            // Checked again because the head was read above
+            precondition: head.deltas.last().map(|d| StorePrecondition::HeadDeltaId(d.id.clone())),
+            ..StoreParams::default()
+        };
+        let prepared = self.prepare(params, Some(ops)).await?;
+        info!("Rolled back {} deltas of {}", steps, coord_id);
+        self.write_prepared(prepared).await
+    }
+
+    /// Squash all but the last `keep_last` deltas of a coordinate into one
+    ///
+    /// See `BmsRepository::compact_coordinate`. The head state is unchanged,
+    /// but delta IDs before the kept suffix are gone.
+    pub async fn compact(&self, coord_id: &CoordId, keep_last: usize) -> Result<CompactReport> {
+        self.check_writable()?;
+        let _guard = self.write_lock.lock().await;
+
+        let report = self.repository.compact_coordinate(coord_id, keep_last).await?;
+        if report.squashed > 0 {
+            self.generation.fetch_add(1, Ordering::AcqRel);
+        }
+        Ok(report)
+    }
+
+    /// Write a prepared store; the caller holds the write lock
+    async fn write_prepared(&self, mut prepared: PreparedStore) -> Result<StoreOutcome> {
+        let started = Instant::now();
+        if let Some(coordinate) = &prepared.coordinate {
+            if self.repository.insert_coordinate_if_absent(coordinate).await? {
+                info!("Created new coordinate: {}", coordinate.id);
+            }
+            self.with_filter(|f| f.insert(&coordinate.id));
+        }
+        self.repository
+            .insert_head(&prepared.delta, &prepared.head, prepared.precondition.as_ref())
+            .await?;
+        Self::audit_created_at(&prepared);
+        self.record_patch_ratio(&prepared);
+        // Published under the write lock so subscribers see chain order
+        self.publish(&prepared.delta);
+        if let Some(timings) = &mut prepared.timings {
+            timings.write_ms = elapsed_ms(started);
+        }
+
+        // Check if snapshot needed
+        let mut snapshot = SnapshotStatus::Skipped;
+        if prepared.snapshot_due {
+            let started = Instant::now();
+            let created = self.snapshot_manager.create_snapshot(
+                prepared.delta.coord_id.clone(),
+                prepared.delta.id.clone(),
+                prepared.state.clone(),
+            )?;
+            self.repository.insert_snapshot(&created).await?;
+            snapshot = SnapshotStatus::Created;
+            info!("Created snapshot for coordinate: {}", prepared.delta.coord_id);
+            if let Some(timings) = &mut prepared.timings {
+                timings.snapshot_ms = elapsed_ms(started);
+            }
+        }
+
+        Ok(prepared.outcome(snapshot))
+    }
+
+    /// Store several states atomically
+    ///
+    /// Every delta is computed and every precondition checked before anything
+    /// is written, and all rows are inserted in one transaction, so a failing
+    /// item leaves the store untouched. A precondition failure is reported as
+    /// `GroupConflict` with the item's position. Each coordinate may appear at
+    /// most once. Snapshots that come due are queued for the snapshot worker
+    /// instead of being written inline.
+    pub async fn store_group(&self, items: Vec<StoreParams>) -> Result<Vec<StoreOutcome>> {
+        self.check_writable()?;
+        let _guard = self.write_lock.lock().await;
+
+        let mut prepared: Vec<PreparedStore> = Vec::with_capacity(items.len());
+        for (index, mut params) in items.into_iter().enumerate() {
+            let coord_id = self.resolve_coord_id(&params).await?;
+            params.coord_id = Some(coord_id.clone());
+
+            let item = match self.prepare(params, None).await {
+                Ok(item) => item,
+                Err(BmsError::PreconditionFailed { expected, actual, .. }) => {
+                    return Err(BmsError::GroupConflict {
+                        index,
+                        coord_id: coord_id.0,
+                        expected,
+                        actual,
+                    });
+                }
+                Err(e) => return Err(e),
+            };
+            if prepared.iter().any(|p| p.delta.coord_id == item.delta.coord_id) {
+                return Err(BmsError::InvalidState(format!(
+                    "Store group item {} repeats coordinate {}",
+                    index, item.delta.coord_id
+                )));
+            }
+            prepared.push(item);
+        }
+
+        let coordinates: Vec<Coordinate> =
+            prepared.iter().filter_map(|p| p.coordinate.clone()).collect();
+        let deltas: Vec<Delta> = prepared.iter().map(|p| p.delta.clone()).collect();
+        let heads: Vec<(CoordId, HeadRows)> = prepared
+            .iter()
+            .map(|p| (p.delta.coord_id.clone(), p.head.clone()))
+            .collect();
+        let started = Instant::now();
+        self.repository.insert_group(&coordinates, &deltas, &heads).await?;
+        let write_ms = elapsed_ms(started);
+        for coordinate in &coordinates {
+            self.with_filter(|f| f.insert(&coordinate.id));
+        }
+        info!("Stored group of {} deltas", deltas.len());
+
+        let mut queued = false;
+        for item in &prepared {
+            Self::audit_created_at(item);
+            self.record_patch_ratio(item);
+            self.publish(&item.delta);
+            if item.snapshot_due {
+                queued |= self.queue_snapshot(item.delta.coord_id.clone());
+            }
+        }
+        if queued {
+            self.snapshot_wakeup.notify_one();
+        }
+
+        Ok(prepared
+            .into_iter()
+            .map(|mut p| {
+                if let Some(timings) = &mut p.timings {
+                    timings.write_ms = write_ms;
+                }
+                let snapshot = if p.snapshot_due {
+                    SnapshotStatus::Scheduled
+                } else {
+                    SnapshotStatus::Skipped
+                };
+                p.outcome(snapshot)
+            })
+            .collect())
+    }
+
+    /// Append deltas computed elsewhere, such as by an offline CLI
+    ///
+    /// Deltas keep their IDs, hashes, and timestamps. Leading deltas that the
+    /// chain already has are skipped, so an interrupted upload can be retried.
+    /// The rest must extend the current head: one whose parent is not the head
+    /// fails with `PreconditionFailed`, and one whose hashes or ops do not
+    /// check out with `MerkleChainBroken`. All deltas are written in one
+    /// transaction. `metadata` is only used when the coordinate is created.
+    pub async fn append_deltas(
+        &self,
+        coord_id: &CoordId,
+        deltas: Vec<Delta>,
+        metadata: Option<HashMap<String, Value>>,
+    ) -> Result<AppendOutcome> {
+        self.check_writable()?;
+        let _guard = self.write_lock.lock().await;
+
+        if let Some(other) = deltas.iter().find(|d| &d.coord_id != coord_id) {
+            return Err(BmsError::InvalidState(format!(
+                "Delta {} belongs to {}, not {}",
+                other.id, other.coord_id, coord_id
+            )));
+        }
+        let (mut state, existing) = match self.head(coord_id).await? {
+            Some(head) => (head.state, head.deltas),
+            None => (serde_json::json!({}), Vec::new()),
+        };
+        let known: HashSet<&str> = existing.iter().map(|d| d.chain_hash.as_str()).collect();
+        let already_present = deltas
+            .iter()
+            .take_while(|d| known.contains(d.chain_hash.as_str()))
+            .count();
+        let new = &deltas[already_present..];
+        let algorithm = self.repository.get_hash_algorithm(coord_id).await?;
+
+        let mut head = existing.last().map(|d| (d.id.clone(), d.chain_hash.clone()));
+        for delta in new {
+            let parent = delta.parent_hash.as_ref().map(|h| &h.0);
+            if parent != head.as_ref().map(|(_, hash)| &hash.0) {
+                return Err(BmsError::PreconditionFailed {
+                    expected: parent.cloned().unwrap_or_else(|| "none".to_string()),
+                    actual: head.as_ref().map_or_else(|| "none".to_string(), |(_, h)| h.0.clone()),
+                    head_chain_hash: head.as_ref().map(|(_, h)| h.0.clone()),
+                });
+            }
+            oplog::verify_append(head.clone(), delta, algorithm)?;
+            delta.check_op_authors()?;
+            if DeltaEngine::generate_delta_id(&delta.ops)? != delta.id {
+                return Err(BmsError::MerkleChainBroken {
+                    delta_id: delta.id.0.clone(),
+                });
+            }
+            DeltaEngine::apply_delta(&mut state, &delta.ops)?;
+            head = Some((delta.id.clone(), delta.chain_hash.clone()));
+        }
+
+        let existing_coord = self.repository.get_coordinate(coord_id).await?;
+        let coordinate = match (&existing_coord, new.is_empty()) {
+            (None, false) => Some(Coordinate {
+                id: coord_id.clone(),
+                rune_alias: Some(CoordinateGenerator::generate_alias(coord_id)),
+                created_at: Utc::now(),
+                metadata,
+                namespace: None,
+                hash_algorithm: algorithm,
+                kind: CoordIdKind::of(coord_id),
+                parent_coord_id: None,
+            }),
+            _ => None,
+        };
+        let metadata = existing_coord
+            .as_ref()
+            .and_then(|c| c.metadata.as_ref())
+            .or(coordinate.as_ref().and_then(|c| c.metadata.as_ref()));
+        let rules = metadata.map(LinkRules::from_metadata).transpose()?.flatten();
+        let materialize = metadata.map(materializes_head).transpose()?.unwrap_or(false);
+
+        if !new.is_empty() {
+            let state_bytes = Canonicalizer::canonical_len(&state)? as u64;
+            let head = HeadRows {
+                links: rules.map(|rules| extract_links(&state, &rules)),
+                state_bytes: Some(state_bytes),
+                materialized: materialize.then(|| state.clone()),
+            };
+            self.repository
+                .insert_group(coordinate.as_slice(), new, &[(coord_id.clone(), head)])
+                .await?;
+            self.with_filter(|f| f.insert(coord_id));
+            for delta in new {
+                self.publish(delta);
+            }
+            info!("Appended {} synced deltas to {}", new.len(), coord_id);
+
+            let counts = existing.len() as u32 + 1..=(existing.len() + new.len()) as u32;
+            let began_at = existing.first().or(new.first()).map(|d| d.created_at);
+            let due = self.snapshot_due(coord_id, counts, state_bytes, began_at).await?;
+            if due && self.queue_snapshot(coord_id.clone()) {
+                self.snapshot_wakeup.notify_one();
+            }
+        }
+
+        let (delta_id, chain_hash) = head.ok_or_else(|| {
+            BmsError::InvalidState(format!("No deltas to append to {}", coord_id))
+        })?;
+        Ok(AppendOutcome {
+            coord_id: coord_id.clone(),
+            appended: new.len(),
+            already_present,
+            coordinate_created: coordinate.is_some(),
+            head: StoreHead {
+                delta_id,
+                chain_hash,
+                seq: (existing.len() + new.len()) as u64,
+            },
+        })
+    }
+
+    /// Apply oplog entries exported by the instance this one replicates from
+    ///
+    /// Entries are applied in order under the write lock, each verified and
+    /// logged under its source LSN like `oplog::apply` does, and applied
+    /// deltas and storage events are published. The derived head rows
+    /// (links, sizes, materialized heads) of coordinates whose head moved are
+    /// refreshed once at the end. Works on a read-only facade.
+    pub async fn apply_replicated(&self, entries: &[OplogEntry]) -> Result<ApplyReport> {
+        let _guard = self.write_lock.lock().await;
+
+        let mut report = ApplyReport::default();
+        let mut moved = HashSet::new();
+        for entry in entries {
+            report.last_lsn = Some(entry.lsn);
+            if !self.repository.apply_oplog_entry(entry).await? {
+                report.skipped += 1;
+                continue;
+            }
+            report.applied += 1;
+            let Some(payload) = &entry.payload else {
+                if oplog::needs_payload(entry.op) {
+                    report.without_payload += 1;
+                }
+                if entry.op == OpKind::CoordinateDeleted {
+                    self.with_filter(CoordFilter::mark_stale);
+                    moved.remove(&entry.coord_id);
+                    let _ = self.storage_events.send(StorageEvent::Deleted {
+                        coord_id: entry.coord_id.clone(),
+                    });
+                } else if entry.op == OpKind::MetadataUpdated {
+                    moved.insert(entry.coord_id.clone());
+                    let _ = self.storage_events.send(StorageEvent::MetadataUpdated {
+                        coord_id: entry.coord_id.clone(),
+                        metadata: entry.metadata()?,
+                    });
+                }
+                continue;
+            };
+            match entry.op {
+                OpKind::CoordinateCreated => self.with_filter(|f| f.insert(&entry.coord_id)),
+                OpKind::DeltaAppended => {
+                    self.publish(&serde_json::from_value(payload.clone())?);
+                    moved.insert(entry.coord_id.clone());
+                }
+                OpKind::CoordinateCompacted => {
+                    moved.insert(entry.coord_id.clone());
+                }
+                _ => {}
+            }
+        }
+        if report.applied > 0 {
+            self.generation.fetch_add(1, Ordering::AcqRel);
+        }
+
+        for coord_id in &moved {
+            self.refresh_head_rows(coord_id).await?;
+        }
+        Ok(report)
+    }
+
+    /// Rewrite the rows a store derives from the head, for a head that moved
+    /// without one
+    async fn refresh_head_rows(&self, coord_id: &CoordId) -> Result<()> {
+        let deltas = self.repository.get_deltas(coord_id).await?;
+        let Some(tip) = deltas.last() else {
+            return Ok(());
+        };
+        let (state, _) = self.reconstruct(coord_id, &deltas).await?;
+        let metadata = self.repository.get_coordinate(coord_id).await?.and_then(|c| c.metadata);
+
+        let links = match metadata.as_ref().map(LinkRules::from_metadata).transpose()?.flatten() {
+            Some(rules) => extract_links(&state, &rules),
+            None => Vec::new(),
+        };
+        self.repository.replace_links(coord_id, &links).await?;
+        let state_bytes = Canonicalizer::canonical_len(&state)? as u64;
+        self.repository.record_state_size(coord_id, &tip.id, state_bytes).await?;
+        if metadata.as_ref().map(materializes_head).transpose()?.unwrap_or(false) {
+            self.record_head_state(tip, &state).await;
+        } else {
+            self.repository.delete_head_state(coord_id).await?;
+        }
+        Ok(())
+    }
+
+    /// Validate a timestamp override against the head and the server clock
+    ///
+    /// Overrides may repeat the head's timestamp but never go back before it,
+    /// so chain order and time order always agree.
+    fn check_created_at(
+        claimed: DateTime<Utc>,
+        head: Option<DateTime<Utc>>,
+        now: DateTime<Utc>,
+    ) -> Result<()> {
+        if claimed > now {
+            return Err(BmsError::InvalidTimestamp(format!(
+                "{} is after the server time {}",
+                claimed.to_rfc3339(),
+                now.to_rfc3339()
+            )));
+        }
+        if let Some(head) = head.filter(|head| claimed < *head) {
+            return Err(BmsError::InvalidTimestamp(format!(
+                "{} is before the head delta's {}",
+                claimed.to_rfc3339(),
+                head.to_rfc3339()
+            )));
+        }
+        Ok(())
+    }
+
+    /// Record a written delta whose timestamp came from the caller
+    fn audit_created_at(prepared: &PreparedStore) {
+        if prepared.created_at_overridden {
+            warn!(
+                target: "bms::audit",
+                coord_id = %prepared.delta.coord_id,
+                delta_id = %prepared.delta.id,
+                claimed = %prepared.delta.created_at.to_rfc3339(),
+                server = %Utc::now().to_rfc3339(),
+                "created_at override"
+            );
+        }
+    }
+
+    /// Target coordinate of a store, generated from the state when absent
+    ///
+    /// A generated ID is from the state and `created_at`, or now. If a
+    /// coordinate with that ID began from another state, the ID is generated
+    /// again with nonces 1, 2, ... up to `MAX_COLLISION_RETRIES`, so the
+    /// store never lands on an unrelated chain. One that began from the same
+    /// state is the same store repeated and is kept. A child of
+    /// `parent_coord_id` is derived from the parent and `child_label`
+    /// without retries, so the same label always reaches the same child.
+    async fn resolve_coord_id(&self, params: &StoreParams) -> Result<CoordId> {
+        if let Some(namespace) = &params.namespace {
+            CoordinateGenerator::validate_namespace(namespace)?;
+        }
+        if let Some(parent) = &params.parent_coord_id {
+            if self.repository.get_coordinate(parent).await?.is_none() {
+                return Err(BmsError::InvalidCoordinate(format!("Unknown parent coordinate: {}", parent)));
+            }
+        }
+        if let Some(coord_id) = &params.coord_id {
+            return Ok(coord_id.clone());
+        }
+        match (&params.parent_coord_id, &params.child_label) {
+            (Some(parent), Some(label)) => return Ok(CoordinateGenerator::derive_child(parent, label)),
+            (Some(_), None) => {
+                return Err(BmsError::InvalidState(
+                    "A parent coordinate needs a child label or a coordinate ID".to_string(),
+                ))
+            }
+            (None, Some(_)) => {
+                return Err(BmsError::InvalidState("A child label needs a parent coordinate".to_string()))
+            }
+            (None, None) => {}
+        }
+
+        let algorithm = params.hash_algorithm.unwrap_or_default();
+        let timestamp = params.created_at.unwrap_or_else(Utc::now);
+        let namespace = params.namespace.as_deref();
+        let state_hash = DeltaEngine::hash_state(&params.state)?;
+        let mut coord_id = match namespace {
+            Some(namespace) => {
+                CoordinateGenerator::generate_in_namespace(namespace, &params.state, &timestamp, algorithm)?
+            }
+            None => CoordinateGenerator::generate(&params.state, &timestamp, algorithm)?,
+        };
+        for nonce in 1..=MAX_COLLISION_RETRIES {
+            if !self.collides(&coord_id, &state_hash).await? {
+                return Ok(coord_id);
+            }
+            warn!("Generated coordinate {} belongs to another state, retrying with nonce {}", coord_id, nonce);
+            coord_id = CoordinateGenerator::generate_nonced(namespace, &params.state, &timestamp, algorithm, nonce)?;
+        }
+        if self.collides(&coord_id, &state_hash).await? {
+            return Err(BmsError::CoordinateCollision(coord_id.0));
+        }
+        Ok(coord_id)
+    }
+
+    /// Whether the snapshot policy wants a snapshot once the chain is any of
+    /// the lengths in `counts`, with a head of `state_bytes`
+    ///
+    /// Time is counted from the latest snapshot, or from `began_at`, the
+    /// chain's first delta, while there is none. A new chain has neither.
+    async fn snapshot_due(
+        &self,
+        coord_id: &CoordId,
+        counts: RangeInclusive<u32>,
+        state_bytes: u64,
+        began_at: Option<DateTime<Utc>>,
+    ) -> Result<bool> {
+        let last_snapshot_at = match began_at {
+            Some(_) => self.repository.latest_snapshot_at(coord_id).await?,
+            None => None,
+        };
+        let now = Utc::now();
+        let since = last_snapshot_at.or(began_at).unwrap_or(now);
+        let elapsed_since_last = (now - since).to_std().unwrap_or_default();
+        Ok(counts.into_iter().any(|delta_count| {
+            self.snapshot_manager.should_snapshot(&SnapshotContext {
+                delta_count,
+                last_snapshot_at,
+                state_size_bytes: state_bytes as usize,
+                elapsed_since_last,
+            })
+        }))
+    }
+
+    /// Whether `coord_id` has a chain that began from a state other than the
+    /// one hashing to `state_hash`
+    async fn collides(&self, coord_id: &CoordId, state_hash: &Hash) -> Result<bool> {
+        if !self.may_exist(coord_id).await? {
+            return Ok(false);
+        }
+        let initial = self.repository.coordinate_initial_state_hash(coord_id).await?;
+        Ok(initial.is_some_and(|initial| &initial != state_hash))
+    }
+
+    /// Compute the delta for a store without writing anything
+    ///
+    /// Callers must hold the write lock until the result is inserted.
+    ///
+    /// `ops`, when given, are used as the delta instead of a diff against
+    /// `params.state`, and the head is what they produce.
+    async fn prepare(
+        &self,
+        params: StoreParams,
+        ops: Option<Vec<json_patch::PatchOperation>>,
+    ) -> Result<PreparedStore> {
+        let started = Instant::now();
+        let coord_id = self.resolve_coord_id(&params).await?;
+        if let Some(metadata) = &params.metadata {
+            RedactionRules::from_metadata(metadata)?;
+            DiffOptions::from_metadata(metadata)?;
+            Acl::from_metadata(metadata)?;
+            externalize_pointers(metadata)?;
+            materializes_head(metadata)?;
+            metadata_flag(metadata, EPHEMERAL_METADATA_KEY)?;
+            LinkRules::from_metadata(metadata)?;
+        }
+
+        // A definite miss in the filter means there is no chain and no row to read
+        let known_absent = !self.may_exist(&coord_id).await?;
+        let head = if known_absent { None } else { self.head(&coord_id).await? };
+        let replay = head.as_ref().map(|h| h.replay).unwrap_or_default();
+        // Fails fast under the write lock; another process writing the same
+        // database is caught when the delta is inserted
+        if let Some(precondition) = &params.precondition {
+            let last = head.as_ref().and_then(|h| h.deltas.last());
+            precondition.check(last.map(|d| (d.id.0.as_str(), d.chain_hash.0.as_str())))?;
+        }
+
+        let existing = if known_absent {
+            None
+        } else {
+            let existing = self.repository.get_coordinate(&coord_id).await?;
+            if existing.is_none() {
+                self.with_filter(CoordFilter::record_false_positive);
+            }
+            existing
+        };
+
+        let mut warnings = Vec::new();
+        // Check if coordinate exists, if not it is created with the delta
+        let (coordinate, metadata, hash_algorithm) = match existing {
+            Some(coordinate) => {
+                if params.metadata.is_some() {
+                    warnings.push(StoreWarning::MetadataIgnored);
+                }
+                (None, coordinate.metadata, coordinate.hash_algorithm)
+            }
+            None => {
+                // Hints are checked when they create a coordinate, so chains
+                // under free-form IDs from before the check stay writable
+                let kind = CoordinateGenerator::validate_hint(coord_id.as_str())?;
+                let coordinate = Coordinate {
+                    id: coord_id.clone(),
+                    rune_alias: Some(CoordinateGenerator::generate_alias(&coord_id)),
+                    created_at: chrono::Utc::now(),
+                    metadata: params.metadata,
+                    namespace: params.namespace,
+                    hash_algorithm: params.hash_algorithm.unwrap_or_default(),
+                    kind,
+                    parent_coord_id: params.parent_coord_id,
+                };
+                let metadata = coordinate.metadata.clone();
+                let hash_algorithm = coordinate.hash_algorithm;
+                (Some(coordinate), metadata, hash_algorithm)
+            }
+        };
+
+        // Array keys describe the coordinate's data, so per-request options
+        // only replace them when they bring their own
+        let stored_options = match &metadata {
+            Some(metadata) => DiffOptions::from_metadata(metadata)?,
+            None => None,
+        };
+        let diff_options = match (params.diff_options, stored_options) {
+            (Some(mut options), Some(stored)) => {
+                if options.array_keys.is_empty() {
+                    options.array_keys = stored.array_keys;
+                }
+                options
+            }
+            (Some(options), None) => options,
+            (None, stored) => stored.unwrap_or_default(),
+        };
+
+        // Get previous state for delta computation
+        let (prev_state, deltas) = match head {
+            Some(head) => (head.state, head.deltas),
+            None => (serde_json::json!({}), Vec::new()),
+        };
+        let delta_count = deltas.len() as u32;
+        let now = Utc::now();
+        let created_at = match params.created_at {
+            Some(claimed) => {
+                Self::check_created_at(claimed, deltas.last().map(|d| d.created_at), now)?;
+                claimed
+            }
+            None => now,
+        };
+
+        // Compute delta
+        let full_replace_ratio = diff_options.full_replace_ratio()?;
+        let given = ops.is_some();
+        let (mut ops, diff_stats) = match ops {
+            Some(ops) => (ops, DiffStats::default()),
+            None => DeltaEngine::compute_delta_optimized(&prev_state, &params.state, &diff_options)?,
+        };
+        if ops.is_empty() {
+            warnings.push(StoreWarning::EmptyDelta);
+        }
+
+        // Matching arrays by element keeps the stored order, so the head is
+        // what the ops produce rather than the submitted state. An op that
+        // does not apply to the head is reported by index, not stored.
+        let by_element = !diff_options.array_keys.is_empty()
+            || matches!(diff_options.array_strategy, bms_core::ArrayStrategy::ById { .. });
+        let state = if !by_element && !given {
+            params.state
+        } else {
+            let (state, outcomes) = DeltaEngine::try_apply(&prev_state, &ops)?;
+            if let Some(err) = outcomes.last().and_then(OpOutcome::to_error) {
+                return Err(err);
+            }
+            state
+        };
+        if let Some(policy) = &params.policy {
+            policy.validate(&ops, &state)?;
+        }
+
+        // A patch about as large as the state it produces costs space and
+        // replay time for nothing, so the state is stored whole instead. The
+        // first delta is skipped: its patch already is the whole state. So
+        // are deltas with op authors, which a single op could not keep, and
+        // small states.
+        let state_bytes = Canonicalizer::canonical_len(&state)? as u64;
+        let mut patch_ratio = None;
+        let mut full_replace = false;
+        let mut tags = None;
+        if !deltas.is_empty() && !ops.is_empty() {
+            let ratio = DeltaEngine::patch_ratio(&ops, &state)?;
+            let mut delta_tags = HashMap::from([(
+                PATCH_RATIO_TAG.to_string(),
+                Value::from((ratio * 1000.0).round() / 1000.0),
+            )]);
+            if ratio > full_replace_ratio
+                && state_bytes >= FULL_REPLACE_MIN_STATE_BYTES as u64
+                && params.op_authors.is_none()
+            {
+                ops = DeltaEngine::root_replace(&state)?;
+                full_replace = true;
+                delta_tags.insert(FULL_REPLACE_TAG.to_string(), Value::Bool(true));
+            }
+            patch_ratio = Some(ratio);
+            tags = Some(delta_tags);
+        }
+        let delta_hash = DeltaEngine::hash_delta(&ops)?;
+        let delta_id = DeltaEngine::generate_delta_id(&ops)?;
+        let ops_bytes = serde_json::to_string(&ops)?.len();
+
+        // Links are re-extracted from every head, so a removed reference drops its row
+        let links = match &metadata {
+            Some(metadata) => LinkRules::from_metadata(metadata)?
+                .map(|rules| extract_links(&state, &rules)),
+            None => None,
+        };
+        let materialized = match &metadata {
+            Some(metadata) if materializes_head(metadata)? => Some(state.clone()),
+            _ => None,
+        };
+        if self.state_size_warning.is_some_and(|limit| state_bytes > limit) {
+            warn!("Head of {} is {} bytes, over the size warning", coord_id, state_bytes);
+            warnings.push(StoreWarning::LargeState);
+        }
+
+        let began_at = deltas.first().map(|d| d.created_at);
+        let snapshot_due = self
+            .snapshot_due(&coord_id, delta_count + 1..=delta_count + 1, state_bytes, began_at)
+            .await?;
+
+        // Get parent info
+        let (parent_id, parent_hash) = match deltas.last() {
+            Some(last_delta) => (Some(last_delta.id.clone()), Some(last_delta.chain_hash.clone())),
+            None => (None, None),
+        };
+
+        // Compute chain hash
+        let chain_hash = match parent_hash {
+            Some(ref ph) => MerkleChain::compute_chain_hash_with(ph, &delta_hash, hash_algorithm),
+            None => delta_hash.clone(),
+        };
+
+        let delta = Delta {
+            id: delta_id,
+            coord_id,
+            parent_id,
+            parent_hash,
+            delta_hash,
+            chain_hash,
+            ops,
+            created_at,
+            tags,
+            author: params.author,
+            op_authors: params.op_authors,
+            signature: None,
+        };
+        delta.check_op_authors()?;
+
+        Ok(PreparedStore {
+            coordinate,
+            delta,
+            state,
+            snapshot_due,
+            seq: u64::from(delta_count) + 1,
+            ops_bytes,
+            patch_ratio,
+            full_replace,
+            diff_stats,
+            warnings,
+            timings: params.explain.then(|| StoreTimings {
+                prepare_ms: elapsed_ms(started),
+                replay,
+                ..StoreTimings::default()
+            }),
+            created_at_overridden: params.created_at.is_some(),
+            head: HeadRows {
+                links,
+                state_bytes: Some(state_bytes),
+                materialized,
+            },
+            precondition: params.precondition,
+        })
+    }
+
+    fn record_patch_ratio(&self, prepared: &PreparedStore) {
+        if let Some(ratio) = prepared.patch_ratio {
+            self.patch_ratios
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .record(ratio, prepared.full_replace);
+        }
+    }
+
+    /// False only when the filter rules the coordinate out
+    async fn may_exist(&self, coord_id: &CoordId) -> Result<bool> {
+        let Some(filter) = &self.coord_filter else {
+            return Ok(true);
+        };
+
+        let needs_rebuild = filter.lock().unwrap_or_else(|e| e.into_inner()).needs_rebuild();
+        if needs_rebuild {
+            let ids = self.repository.list_coordinate_ids().await?;
+            let mut filter = filter.lock().unwrap_or_else(|e| e.into_inner());
+            *filter = filter.rebuilt(&ids, ids.len());
+            info!("Rebuilt coordinate filter with {} IDs", ids.len());
+        }
+
+        let mut filter = filter.lock().unwrap_or_else(|e| e.into_inner());
+        if filter.may_contain(coord_id) {
+            return Ok(true);
+        }
+        filter.record_avoided();
+        Ok(false)
+    }
+
+    fn with_filter(&self, update: impl FnOnce(&mut CoordFilter)) {
+        if let Some(filter) = &self.coord_filter {
+            update(&mut filter.lock().unwrap_or_else(|e| e.into_inner()));
+        }
+    }
+
+    fn publish(&self, delta: &Delta) {
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        let _ = self.events.send(DeltaEvent {
+            coord_id: delta.coord_id.clone(),
+            delta_id: delta.id.clone(),
+            parent_id: delta.parent_id.clone(),
+            chain_hash: delta.chain_hash.clone(),
+            author: delta.author.clone(),
+            created_at: delta.created_at,
+            touched: Arc::new(watch::touched_paths(&delta.ops)),
+        });
+    }
+
+    /// Returns false if the coordinate was already queued
+    fn queue_snapshot(&self, coord_id: CoordId) -> bool {
+        self.pending_snapshots
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(coord_id)
+    }
+
+    /// Coordinates waiting for the snapshot worker
+    pub fn pending_snapshots(&self) -> usize {
+        self.pending_snapshots.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    /// Create every queued snapshot, returning how many were written
+    ///
+    /// Failures are logged and the coordinate is dropped from the queue; its
+    /// next due store queues it again.
+    pub async fn flush_pending_snapshots(&self) -> usize {
+        let pending: Vec<CoordId> = self
+            .pending_snapshots
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .drain()
+            .collect();
+
+        let mut created = 0;
+        for coord_id in pending {
+            match self.create_snapshot(&coord_id).await {
+                Ok(Some(_)) => {
+                    created += 1;
+                    info!("Created snapshot for coordinate: {}", coord_id);
+                }
+                Ok(None) => {}
+                Err(e) => warn!("Deferred snapshot for {} failed: {}", coord_id, e),
+            }
+        }
+        created
+    }
+
+    /// Write queued snapshots as they come in, until the task is dropped
+    pub async fn run_snapshot_worker(&self) {
+        loop {
+            self.snapshot_wakeup.notified().await;
+            self.flush_pending_snapshots().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::AccessRecord;
+    use crate::test_support::TempDb;
+    use bms_core::delta::SQUASHED_TAG;
+    use serde_json::json;
+
+    fn params(coord: &CoordId, state: Value) -> StoreParams {
+        StoreParams {
+            coord_id: Some(coord.clone()),
+            state,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_store_and_head() {
+        let db = TempDb::new("facade-store");
+        let facade = db.facade(2).await;
+        let coord = CoordId("FACADETEST".to_string());
+
+        for i in 0..5 {
+            facade.store(params(&coord, json!({"n": i}))).await.unwrap();
+        }
+
+        let head = facade.head(&coord).await.unwrap().unwrap();
+        assert_eq!(head.state, json!({"n": 4}));
+        assert_eq!(head.deltas.len(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_store_outcome_reports_side_effects() {
+        let db = TempDb::new("facade-outcome");
+        let facade = db.facade(2).await;
+        let coord = CoordId("OUTCOME".to_string());
+        let metadata: HashMap<String, Value> = [("label".to_string(), json!("x"))].into();
+
+        let first = facade
+            .store(StoreParams {
+                metadata: Some(metadata.clone()),
+                ..params(&coord, json!({"n": 1}))
+            })
+            .await
+            .unwrap();
+        assert!(first.coordinate_created);
+        assert_eq!(first.head.seq, 1);
+        assert_eq!(first.snapshot, SnapshotStatus::Skipped);
+        assert_eq!(first.indexed, IndexStatus::Disabled);
+        assert!(!first.deduplicated);
+        assert!(first.warnings.is_empty());
+        assert!(first.timings.is_none());
+
+        let second = facade
+            .store(StoreParams {
+                metadata: Some(metadata),
+                explain: true,
+                ..params(&coord, json!({"n": 1}))
+            })
+            .await
+            .unwrap();
+        assert_eq!(second.head.seq, 2);
+        assert_eq!(second.snapshot, SnapshotStatus::Created);
+        assert_eq!(second.warnings, [StoreWarning::MetadataIgnored, StoreWarning::EmptyDelta]);
+        assert!(second.timings.is_some_and(|t| t.prepare_ms >= 0.0 && t.snapshot_ms > 0.0));
+
+        let value = serde_json::to_value(&second).unwrap();
+        assert_eq!(value["snapshot"], "created");
+        assert_eq!(value["indexed"], "disabled");
+        assert_eq!(value["warnings"], json!(["metadata_ignored", "empty_delta"]));
+        assert_eq!(value["head"]["seq"], 2);
+    }
+
+    /// Stores `state` under a coordinate whose metadata links `/related/*`
+    async fn store_linked(facade: &BmsFacade, coord: &CoordId, state: Value) {
+        facade
+            .store(StoreParams {
+                metadata: Some([("links".to_string(), json!(["/related/*"]))].into()),
+                ..params(coord, state)
+            })
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_links_follow_the_head() {
+        let db = TempDb::new("facade-links");
+        let facade = db.facade(16).await;
+        let repo = facade.repository();
+        let source = CoordId("LINKSOURCE".to_string());
+        let a = CoordinateGenerator::generate_now(&json!({"target": "a"})).unwrap();
+        let b = CoordinateGenerator::generate_now(&json!({"target": "b"})).unwrap();
+
+        store_linked(&facade, &source, json!({"related": [a.0, b.0, "free text"]})).await;
+        let targets = |links: Vec<crate::models::CoordLink>| -> Vec<CoordId> {
+            links.into_iter().map(|l| l.to_coord).collect()
+        };
+        assert_eq!(targets(repo.get_links(&source).await.unwrap()), [a.clone(), b.clone()]);
+        assert_eq!(repo.get_backlinks(&b).await.unwrap()[0].pointer, "/related/1");
+
+        // A later delta that drops a reference drops its link
+        store_linked(&facade, &source, json!({"related": [b.0]})).await;
+        let links = repo.get_links(&source).await.unwrap();
+        assert_eq!((links[0].pointer.as_str(), &links[0].to_coord), ("/related/0", &b));
+        assert_eq!(links.len(), 1);
+        assert!(repo.get_backlinks(&a).await.unwrap().is_empty());
+
+        store_linked(&facade, &source, json!({"related": []})).await;
+        assert!(repo.get_links(&source).await.unwrap().is_empty());
+
+        // Coordinates without link rules never get rows
+        facade.store(params(&a, json!({"related": [b.0]}))).await.unwrap();
+        assert!(repo.get_links(&a).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_delete_of_linked_coordinate() {
+        let db = TempDb::new("facade-link-delete");
+        let target = CoordinateGenerator::generate_now(&json!({"target": true})).unwrap();
+        let source = CoordId("LINKREFERRER".to_string());
+        let blocking = db.facade(16).await.with_link_policy(LinkDeletePolicy::Block);
+        blocking.store(params(&target, json!({"n": 1}))).await.unwrap();
+        store_linked(&blocking, &source, json!({"related": [target.0]})).await;
+
+        let err = blocking.delete_coordinate(&target).await.unwrap_err();
+        assert!(matches!(err, BmsError::CoordinateReferenced { referrers: 1, .. }));
+        assert!(blocking.repository().coordinate_exists(&target).await.unwrap());
+
+        // The default policy deletes and leaves the link dangling
+        let warning = db.facade(16).await;
+        assert!(warning.delete_coordinate(&target).await.unwrap());
+        let dangling = warning.repository().list_links(true).await.unwrap();
+        assert_eq!((&dangling[0].from_coord, &dangling[0].to_coord), (&source, &target));
+
+        // Deleting the referrer removes its links
+        assert!(warning.delete_coordinate(&source).await.unwrap());
+        assert!(warning.repository().list_links(false).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_append_deltas_fast_forwards_and_resumes() {
+        let (source_db, target_db) = (TempDb::new("append-source"), TempDb::new("append-target"));
+        let (source, target) = (source_db.facade(16).await, target_db.facade(16).await);
+        let coord = CoordId("APPENDED".to_string());
+        let metadata: HashMap<String, Value> = [("label".to_string(), json!("laptop"))].into();
+        for n in 0..3 {
+            source.store(params(&coord, json!({"n": n, "log": [n]}))).await.unwrap();
+        }
+        let chain = source.repository().get_deltas(&coord).await.unwrap();
+
+        let first = target
+            .append_deltas(&coord, chain[..2].to_vec(), Some(metadata.clone()))
+            .await
+            .unwrap();
+        assert_eq!((first.appended, first.already_present, first.coordinate_created), (2, 0, true));
+
+        // Resending a partly applied upload skips what is already there
+        let resumed = target.append_deltas(&coord, chain.clone(), None).await.unwrap();
+        assert_eq!((resumed.appended, resumed.already_present, resumed.head.seq), (1, 2, 3));
+        let again = target.append_deltas(&coord, chain.clone(), None).await.unwrap();
+        assert_eq!((again.appended, again.already_present), (0, 3));
+
+        let copied = target.head(&coord).await.unwrap().unwrap();
+        assert_eq!(copied.state, json!({"n": 2, "log": [2]}));
+        assert_eq!(copied.deltas.last().unwrap().chain_hash, chain[2].chain_hash);
+        assert_eq!(copied.deltas[0].created_at, chain[0].created_at);
+        let stored = target.repository().get_coordinate(&coord).await.unwrap().unwrap();
+        assert_eq!(stored.metadata, Some(metadata));
+
+        // The copy keeps working as a normal chain
+        target.store(params(&coord, json!({"n": 3}))).await.unwrap();
+        let (verified, error) = MerkleChain::verify_chain_integrity(&target.repository().get_deltas(&coord).await.unwrap());
+        assert_eq!((verified, error.is_none()), (4, true));
+    }
+
+    #[tokio::test]
+    async fn test_append_deltas_rejects_divergence_and_tampering() {
+        let (source_db, target_db) = (TempDb::new("append-diverge-source"), TempDb::new("append-diverge-target"));
+        let (source, target) = (source_db.facade(16).await, target_db.facade(16).await);
+        let coord = CoordId("DIVERGED".to_string());
+        source.store(params(&coord, json!({"n": 0}))).await.unwrap();
+        target
+            .append_deltas(&coord, source.repository().get_deltas(&coord).await.unwrap(), None)
+            .await
+            .unwrap();
+
+        // Both sides advance from the same head
+        source.store(params(&coord, json!({"n": 1}))).await.unwrap();
+        target.store(params(&coord, json!({"n": -1}))).await.unwrap();
+        let chain = source.repository().get_deltas(&coord).await.unwrap();
+        let err = target.append_deltas(&coord, chain.clone(), None).await.unwrap_err();
+        assert!(matches!(err, BmsError::PreconditionFailed { .. }));
+
+        let fresh = CoordId("TAMPERED".to_string());
+        source.store(params(&fresh, json!({"secret": "a"}))).await.unwrap();
+        let mut tampered = source.repository().get_deltas(&fresh).await.unwrap();
+        tampered[0].ops = serde_json::from_value(json!([{"op": "add", "path": "/secret", "value": "b"}])).unwrap();
+        let err = target.append_deltas(&fresh, tampered, None).await.unwrap_err();
+        assert!(matches!(err, BmsError::MerkleChainBroken { .. }));
+
+        let err = target.append_deltas(&fresh, chain, None).await.unwrap_err();
+        assert!(matches!(err, BmsError::InvalidState(_)));
+        assert!(!target.repository().coordinate_exists(&fresh).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_op_authors_are_not_hashed() {
+        let (annotated_db, plain_db) = (TempDb::new("op-authors"), TempDb::new("op-authors-plain"));
+        let (annotated, plain) = (annotated_db.facade(16).await, plain_db.facade(16).await);
+        let coord = CoordId("COWRITTEN".to_string());
+        let state = json!({"plan": ["draft"], "result": "ok"});
+
+        let err = annotated
+            .store(StoreParams {
+                op_authors: Some(vec!["planner".to_string()]),
+                ..params(&coord, state.clone())
+            })
+            .await
+            .unwrap_err();
+        assert!(matches!(err, BmsError::OpAuthorsMismatch { ops: 2, op_authors: 1 }));
+        assert!(annotated.head(&coord).await.unwrap().is_none());
+
+        let authors = vec!["planner".to_string(), "executor".to_string()];
+        annotated
+            .store(StoreParams {
+                op_authors: Some(authors.clone()),
+                ..params(&coord, state.clone())
+            })
+            .await
+            .unwrap();
+        plain.store(params(&coord, state)).await.unwrap();
+
+        let with = annotated.repository().get_deltas(&coord).await.unwrap().remove(0);
+        let without = plain.repository().get_deltas(&coord).await.unwrap().remove(0);
+        assert_eq!(with.op_authors, Some(authors));
+        assert_eq!(without.op_authors, None);
+        assert_eq!(
+            (&with.id, &with.delta_hash, &with.chain_hash),
+            (&without.id, &without.delta_hash, &without.chain_hash)
+        );
+
+        // Attribution travels with synced deltas and goes with the coordinate
+        let copy_db = TempDb::new("op-authors-copy");
+        let copy = copy_db.facade(16).await;
+        copy.append_deltas(&coord, vec![with.clone()], None).await.unwrap();
+        assert_eq!(copy.repository().get_delta(&with.id).await.unwrap().unwrap().op_authors, with.op_authors);
+        annotated.delete_coordinate(&coord).await.unwrap();
+        annotated.store(params(&coord, json!({"plan": ["draft"], "result": "ok"}))).await.unwrap();
+        assert_eq!(annotated.repository().get_deltas(&coord).await.unwrap()[0].op_authors, None);
+    }
+
+    #[tokio::test]
+    async fn test_signatures_travel_with_appended_deltas() {
+        let (source_db, target_db) = (TempDb::new("signed-source"), TempDb::new("signed-target"));
+        let (source, target) = (source_db.facade(16).await, target_db.facade(16).await);
+        let coord = CoordId("SIGNED".to_string());
+        for n in 0..2 {
+            source.store(params(&coord, json!({"n": n}))).await.unwrap();
+        }
+        let key = ed25519_dalek::SigningKey::from_bytes(&[3u8; 32]);
+        let mut chain = source.repository().get_deltas(&coord).await.unwrap();
+        for delta in &mut chain {
+            delta.signature = Some(DeltaEngine::sign_delta(&delta.ops, &key).unwrap());
+        }
+
+        // A signature over other ops is rejected like a broken hash
+        let mut forged = chain.clone();
+        forged[1].signature = chain[0].signature.clone();
+        let err = target.append_deltas(&coord, forged, None).await.unwrap_err();
+        assert!(matches!(err, BmsError::InvalidSignature(_)), "{:?}", err);
+
+        // Signatures are not hashed, so signed and unsigned copies share a chain
+        target.append_deltas(&coord, chain.clone(), None).await.unwrap();
+        let stored = target.repository().get_deltas(&coord).await.unwrap();
+        assert_eq!(stored[1].chain_hash, chain[1].chain_hash);
+        for (stored, signed) in stored.iter().zip(&chain) {
+            assert_eq!(stored.signature, signed.signature);
+            DeltaEngine::verify_signature(&stored.ops, stored.signature.as_ref().unwrap()).unwrap();
+        }
+        assert_eq!(target.repository().get_delta(&chain[0].id).await.unwrap().unwrap().signature, chain[0].signature);
+
+        // Databases from before signatures gain the columns when opened
+        source_db.execute("ALTER TABLE deltas DROP COLUMN signature_bytes").await;
+        source_db.execute("ALTER TABLE deltas DROP COLUMN signature_pubkey").await;
+        let reopened = source_db.repository().await;
+        assert!(reopened.get_deltas(&coord).await.unwrap().iter().all(|d| d.signature.is_none()));
+        assert!(reopened.scan_corrupt_rows().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_created_at_override_must_not_regress() {
+        let db = TempDb::new("facade-created-at");
+        let facade = db.facade(16).await;
+        let coord = CoordId("IMPORTED".to_string());
+        let at = |secs: i64| DateTime::from_timestamp(1_600_000_000 + secs, 0).unwrap();
+        let store = |n: i64, created_at: Option<DateTime<Utc>>| {
+            let facade = &facade;
+            let params = StoreParams {
+                created_at,
+                ..params(&coord, json!({"n": n}))
+            };
+            async move { facade.store(params).await }
+        };
+
+        store(0, Some(at(0))).await.unwrap();
+        // Equal timestamps are allowed, earlier ones are not
+        store(1, Some(at(0))).await.unwrap();
+        store(2, Some(at(60))).await.unwrap();
+        assert!(matches!(store(3, Some(at(30))).await, Err(BmsError::InvalidTimestamp(_))));
+        let future = Utc::now() + chrono::Duration::hours(1);
+        assert!(matches!(store(3, Some(future)).await, Err(BmsError::InvalidTimestamp(_))));
+
+        // Without an override the server clock is used
+        store(3, None).await.unwrap();
+        let deltas = facade.repository().get_deltas(&coord).await.unwrap();
+        let times: Vec<_> = deltas.iter().map(|d| d.created_at).collect();
+        assert_eq!(times[..3], [at(0), at(0), at(60)]);
+        assert!(times[3] > at(60));
+    }
+
+    #[tokio::test]
+    async fn test_precondition_mismatch_rejected() {
+        let db = TempDb::new("facade-precondition");
+        let facade = db.facade(128).await;
+        let coord = CoordId("FACADETEST".to_string());
+
+        let first = facade.store(params(&coord, json!({"v": 1}))).await.unwrap();
+
+        let mut stale = params(&coord, json!({"v": 2}));
+        stale.precondition = Some(StorePrecondition::HeadDeltaId(DeltaId("stale".to_string())));
+        let err = facade.store(stale).await.unwrap_err();
+        match err {
+            BmsError::PreconditionFailed { actual, head_chain_hash, .. } => {
+                assert_eq!(actual, first.head.delta_id.0);
+                assert_eq!(head_chain_hash, Some(first.head.chain_hash.0.clone()));
+            }
+            other => panic!("unexpected error: {}", other),
+        }
+
+        let mut fresh = params(&coord, json!({"v": 3}));
+        fresh.precondition = Some(StorePrecondition::HeadChainHash(first.head.chain_hash));
+        assert!(facade.store(fresh).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_racing_conditional_writers() {
+        let db = TempDb::new("facade-race");
+        let facade = db.facade(128).await;
+        let coord = CoordId("FACADETEST".to_string());
+
+        let base = facade.store(params(&coord, json!({"v": 0}))).await.unwrap();
+        let precondition = Some(StorePrecondition::HeadChainHash(base.head.chain_hash));
+
+        let mut writer_a = params(&coord, json!({"v": "a"}));
+        writer_a.precondition = precondition.clone();
+        let mut writer_b = params(&coord, json!({"v": "b"}));
+        writer_b.precondition = precondition;
+
+        let (a, b) = tokio::join!(facade.store(writer_a), facade.store(writer_b));
+        let results = [a, b];
+
+        let succeeded = results.iter().filter(|r| r.is_ok()).count();
+        let rejected = results
+            .iter()
+            .filter(|r| matches!(r, Err(BmsError::PreconditionFailed { .. })))
+            .count();
+        assert_eq!(succeeded, 1);
+        assert_eq!(rejected, 1);
+        assert_eq!(facade.head(&coord).await.unwrap().unwrap().deltas.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_rollback_appends_the_inverse() {
+        let db = TempDb::new("facade-rollback");
+        let facade = db.facade(2).await;
+        let coord = CoordId("ROLLBACK".to_string());
+        let states = [
+            json!({"title": "a", "items": [1, 2, 3]}),
+            json!({"title": "b", "items": [2, 3, 4], "extra": {"k": true}}),
+            json!({"title": "b", "items": [4], "moved": 1}),
+            json!({"items": []}),
+        ];
+        for state in &states {
+            facade.store(params(&coord, state.clone())).await.unwrap();
+        }
+
+        let outcome = facade.rollback(&coord, 2, Some("alice".to_string())).await.unwrap();
+        assert_eq!(outcome.head.seq, 5);
+        let head = facade.head(&coord).await.unwrap().unwrap();
+        assert_eq!(head.state, states[1]);
+        let last = head.deltas.last().unwrap();
+        assert_eq!(last.parent_id.as_ref(), Some(&head.deltas[3].id));
+        assert_eq!(last.author.as_deref(), Some("alice"));
+
+        // Rolling back the rollback redoes it
+        facade.rollback(&coord, 1, None).await.unwrap();
+        assert_eq!(facade.head(&coord).await.unwrap().unwrap().state, states[3]);
+        facade.rollback(&coord, 6, None).await.unwrap();
+        assert_eq!(facade.head(&coord).await.unwrap().unwrap().state, json!({}));
+
+        for steps in [0, 8] {
+            assert!(matches!(
+                facade.rollback(&coord, steps, None).await,
+                Err(BmsError::InvalidState(_))
+            ));
+        }
+        assert!(facade.rollback(&CoordId("NONE".to_string()), 1, None).await.is_err());
+    }
+
+    #[cfg(feature = "cbor-ops")]
+    #[tokio::test]
+    async fn test_recode_deltas_between_encodings() {
+        let db = TempDb::new("facade-recode");
+        let open = |encoding| {
+            let db = &db;
+            async move {
+                let repository = db.repository().await.with_delta_encoding(encoding);
+                BmsFacade::new(repository, SnapshotManager::new(100))
+            }
+        };
+        let coord = CoordId("RECODE".to_string());
+        let json_facade = open(bms_core::DeltaEncoding::Json).await;
+        for n in 0..3 {
+            json_facade.store(params(&coord, json!({"n": n, "list": [n]}))).await.unwrap();
+        }
+        let chain = |deltas: &[Delta]| deltas.iter().map(|d| (d.id.clone(), d.chain_hash.clone())).collect::<Vec<_>>();
+        let before = json_facade.head(&coord).await.unwrap().unwrap();
+
+        // Rows in both encodings read back side by side
+        let cbor_facade = open(bms_core::DeltaEncoding::Cbor).await;
+        cbor_facade.store(params(&coord, json!({"n": 3}))).await.unwrap();
+        let mixed = cbor_facade.head(&coord).await.unwrap().unwrap();
+        assert_eq!(chain(&mixed.deltas[..3]), chain(&before.deltas));
+        assert_eq!(mixed.state, json!({"n": 3}));
+
+        assert_eq!(cbor_facade.repository().recode_deltas().await.unwrap(), 3);
+        assert_eq!(cbor_facade.repository().recode_deltas().await.unwrap(), 0);
+        assert_eq!(json_facade.repository().recode_deltas().await.unwrap(), 4);
+        let after = json_facade.head(&coord).await.unwrap().unwrap();
+        assert_eq!(chain(&after.deltas), chain(&mixed.deltas));
+        assert_eq!(after.state, mixed.state);
+        assert!(json_facade.repository().scan_corrupt_rows().await.unwrap().is_empty());
+    }
+
+    #[cfg(feature = "compressed-ops")]
+    #[tokio::test]
+    async fn test_recode_compresses_plain_rows() {
+        let db = TempDb::new("facade-compressed");
+        let facade = db.facade(100).await;
+        let coord = CoordId("SQUEEZE".to_string());
+        let rows: Vec<Value> = (0..50).map(|i| json!({"id": i, "note": "the same words again"})).collect();
+        facade.store(params(&coord, json!({"rows": rows}))).await.unwrap();
+        let head = facade.head(&coord).await.unwrap().unwrap();
+
+        // A row written before the feature was on: plain JSON text
+        let plain = serde_json::to_string(&head.deltas[0].ops).unwrap().replace('\'', "''");
+        db.execute(&format!("UPDATE deltas SET ops = '{}' WHERE coord_id = 'SQUEEZE'", plain)).await;
+        assert_eq!(facade.head(&coord).await.unwrap().unwrap().state, head.state);
+
+        assert_eq!(facade.repository().recode_deltas().await.unwrap(), 1);
+        assert_eq!(facade.repository().recode_deltas().await.unwrap(), 0);
+        let after = facade.head(&coord).await.unwrap().unwrap();
+        assert_eq!(after.state, head.state);
+        assert_eq!(after.deltas[0].chain_hash, head.deltas[0].chain_hash);
+        assert!(facade.repository().scan_corrupt_rows().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_compact_squashes_old_deltas() {
+        let db = TempDb::new("facade-compact");
+        let facade = db.facade(2).await;
+        let coord = CoordId("COMPACT".to_string());
+        for n in 0..6 {
+            facade.store(params(&coord, json!({"n": n, "seen": (0..n).collect::<Vec<_>>()}))).await.unwrap();
+        }
+        let before = facade.head(&coord).await.unwrap().unwrap();
+
+        let report = facade.compact(&coord, 2).await.unwrap();
+        assert_eq!((report.squashed, report.kept), (4, 2));
+        let after = facade.head(&coord).await.unwrap().unwrap();
+        assert_eq!(after.state, before.state);
+        assert_eq!(after.deltas.len(), 3);
+        assert_eq!(after.deltas[0].tags.as_ref().unwrap()[SQUASHED_TAG], json!(4));
+        // Kept deltas keep their IDs but are re-hashed onto the squashed one
+        assert_eq!(after.deltas[1].id, before.deltas[4].id);
+        assert_ne!(after.deltas[1].chain_hash, before.deltas[4].chain_hash);
+        assert!(MerkleChain::verify_chain_integrity(&after.deltas).1.is_none());
+        let snapshot = facade.repository().get_latest_snapshot(&coord).await.unwrap().unwrap();
+        assert_eq!(Some(snapshot.id), report.snapshot_id);
+        assert_eq!(snapshot.head_delta_id, after.deltas[0].id);
+
+        // The chain keeps growing from the rewritten tip
+        facade.store(params(&coord, json!({"n": 6}))).await.unwrap();
+        let head = facade.head(&coord).await.unwrap().unwrap();
+        assert_eq!(head.state, json!({"n": 6}));
+        assert!(MerkleChain::verify_chain_integrity(&head.deltas).1.is_none());
+        assert_eq!(facade.compact(&coord, 3).await.unwrap().squashed, 0);
+
+        // A broken chain is refused before anything is written
+        let zeros = "0".repeat(64);
+        db.execute(&format!(
+            "UPDATE deltas SET chain_hash = '{}' WHERE coord_id = 'COMPACT' AND parent_id IS NULL",
+            zeros
+        ))
+        .await;
+        assert!(matches!(
+            facade.compact(&coord, 0).await,
+            Err(BmsError::MerkleChainBroken { .. })
+        ));
+        assert_eq!(facade.repository().get_deltas(&coord).await.unwrap().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_redaction_rules_from_metadata() {
+        let db = TempDb::new("facade-redact");
+        let facade = db.facade(128).await;
+        let coord = CoordId("FACADETEST".to_string());
+
+        let mut bad = params(&coord, json!({"v": 1}));
+        bad.metadata = Some(HashMap::from([("redact".to_string(), json!("/secrets"))]));
+        assert!(facade.store(bad).await.is_err());
+
+        let mut good = params(&coord, json!({"secrets": "s", "v": 1}));
+        good.metadata = Some(HashMap::from([("redact".to_string(), json!(["/secrets"]))]));
+        facade.store(good).await.unwrap();
+
+        let rules = facade.redaction_rules(&coord).await.unwrap();
+        let head = facade.head(&coord).await.unwrap().unwrap();
+        assert_eq!(bms_core::redact(&head.state, &rules), json!({"secrets": "[redacted]", "v": 1}));
+        assert_eq!(head.state["secrets"], "s");
+    }
+
+    #[tokio::test]
+    async fn test_diff_options_precedence() {
+        let db = TempDb::new("facade-diff");
+        let facade = db.facade(128).await;
+        let coord = CoordId("FACADETEST".to_string());
+
+        let mut first = params(&coord, json!({"items": [1, 2, 3]}));
+        first.metadata = Some(HashMap::from([(
+            "diff".to_string(),
+            json!({"array_strategy": "replace"}),
+        )]));
+        facade.store(first).await.unwrap();
+
+        let from_metadata = facade.store(params(&coord, json!({"items": [0, 1, 2, 3]}))).await.unwrap();
+        assert_eq!(from_metadata.diff_stats.replaced_arrays, 1);
+
+        let mut per_request = params(&coord, json!({"items": [0, 1, 2, 3, 4]}));
+        per_request.diff_options = Some(DiffOptions {
+            array_strategy: bms_core::ArrayStrategy::Lcs,
+            ..Default::default()
+        });
+        let outcome = facade.store(per_request).await.unwrap();
+        assert_eq!(outcome.diff_stats.lcs_arrays, 1);
+        assert_eq!(outcome.diff_stats.replaced_arrays, 0);
+
+        let head = facade.head(&coord).await.unwrap().unwrap();
+        assert_eq!(head.state, json!({"items": [0, 1, 2, 3, 4]}));
+    }
+
+    #[tokio::test]
+    async fn test_array_keys_keep_the_stored_order() {
+        let db = TempDb::new("facade-array-keys");
+        let facade = db.facade(2).await;
+        let coord = CoordId("ROSTER".to_string());
+
+        let mut first = params(&coord, json!({"users": [{"id": "a", "n": 1}, {"id": "b", "n": 2}], "tags": ["y", "x"]}));
+        first.metadata = Some(HashMap::from([(
+            "array_keys".to_string(),
+            json!({"/users": "id", "/tags": null}),
+        )]));
+        facade.store(first).await.unwrap();
+
+        let reordered = facade
+            .store(params(&coord, json!({"users": [{"id": "b", "n": 2}, {"id": "a", "n": 1}], "tags": ["x", "y"]})))
+            .await
+            .unwrap();
+        assert_eq!(reordered.warnings, [StoreWarning::EmptyDelta]);
+
+        // Per-request options keep the coordinate's array keys
+        let mut edited = params(&coord, json!({"users": [{"id": "c"}, {"id": "b", "n": 3}, {"id": "a", "n": 1}], "tags": ["x", "y"]}));
+        edited.diff_options = Some(DiffOptions {
+            array_strategy: bms_core::ArrayStrategy::Replace,
+            ..Default::default()
+        });
+        let outcome = facade.store(edited).await.unwrap();
+        assert_eq!(outcome.diff_stats.keyed_arrays, 1);
+
+        // Heads and snapshots hold the stored order, not the submitted one
+        let expected = json!({"users": [{"id": "a", "n": 1}, {"id": "b", "n": 3}, {"id": "c"}], "tags": ["y", "x"]});
+        assert_eq!(facade.head(&coord).await.unwrap().unwrap().state, expected);
+        let snapshot = facade.repository().get_latest_snapshot(&coord).await.unwrap().unwrap();
+        assert_eq!(snapshot.state, json!({"users": [{"id": "a", "n": 1}, {"id": "b", "n": 2}], "tags": ["y", "x"]}));
+
+        let collision = facade
+            .store(params(&coord, json!({"users": [{"id": "a"}, {"id": "a"}], "tags": []})))
+            .await;
+        assert!(matches!(collision, Err(BmsError::InvalidArrayKey { pointer, .. }) if pointer == "/users/1"));
+    }
+
+    #[tokio::test]
+    async fn test_interrupted_replay_resumes_from_checkpoint() {
+        let db = TempDb::new("facade-checkpoints");
+        let writer = db.facade(1000).await;
+        let coord = CoordId("LONGCHAIN".to_string());
+        for n in 0..35 {
+            writer.store(params(&coord, json!({"n": n, "seen": (0..n).collect::<Vec<_>>()}))).await.unwrap();
+        }
+        let expected = writer.head(&coord).await.unwrap().unwrap().state;
+
+        // A zero budget cancels every replay at its first checkpoint
+        let cancelled = db
+            .facade(1000)
+            .await
+            .with_reconstruction_checkpoints(10, DEFAULT_CHECKPOINT_TTL)
+            .with_replay_budget(Duration::ZERO);
+        for reached in [10, 20, 30] {
+            let err = cancelled.head(&coord).await.unwrap_err();
+            assert!(err.to_string().contains(&format!("stopped at delta {} of 35", reached)), "{}", err);
+        }
+        let head = cancelled.head(&coord).await.unwrap().unwrap();
+        assert_eq!(head.state, expected);
+        let resumed = ReplayStats {
+            snapshot_seq: None,
+            checkpoint_seq: Some(30),
+            replayed: 5,
+            checkpoints_written: 0,
+            materialized: false,
+        };
+        assert_eq!(head.replay, resumed);
+
+        // A tampered checkpoint is discarded and the replay starts over
+        db.execute("UPDATE reconstruction_checkpoints SET state_hash = 'bad' WHERE seq = 30").await;
+        let facade = db.facade(1000).await.with_reconstruction_checkpoints(10, DEFAULT_CHECKPOINT_TTL);
+        let head = facade.head(&coord).await.unwrap().unwrap();
+        assert_eq!((head.replay.checkpoint_seq, head.replay.replayed), (None, 35));
+        assert_eq!(head.state, expected);
+
+        // Explain shows the hit; a snapshot then supersedes the checkpoints
+        let mut explained = params(&coord, json!({"n": 35}));
+        explained.explain = true;
+        let outcome = facade.store(explained).await.unwrap();
+        assert_eq!(outcome.timings.unwrap().replay.checkpoint_seq, Some(30));
+        facade.create_snapshot(&coord).await.unwrap();
+        assert!(facade.repository().get_checkpoint(&coord, 36).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_delete_emits_storage_event() {
+        let db = TempDb::new("facade-delete");
+        let facade = db.facade(1).await;
+        let coord = CoordId("FACADETEST".to_string());
+        let mut events = facade.subscribe_storage();
+
+        facade.store(params(&coord, json!({"v": 1}))).await.unwrap();
+        assert_eq!(facade.generation(), 1);
+        assert!(facade.delete_coordinate(&coord).await.unwrap());
+        assert!(!facade.delete_coordinate(&coord).await.unwrap());
+        assert_eq!(facade.generation(), 2);
+
+        assert_eq!(events.recv().await.unwrap(), StorageEvent::Deleted { coord_id: coord.clone() });
+        assert!(events.try_recv().is_err());
+        assert!(facade.head(&coord).await.unwrap().is_none());
+        assert!(!facade.repository().coordinate_exists(&coord).await.unwrap());
+        assert!(facade.repository().get_latest_snapshot(&coord).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_idle_ephemerals_are_collected() {
+        let db = TempDb::new("facade-ephemeral");
+        let facade = db.facade(16).await;
+        let now = Utc::now();
+        let hours_ago = |hours: i64| Some(now - chrono::Duration::hours(hours));
+        let ephemeral = || Some(HashMap::from([(EPHEMERAL_METADATA_KEY.to_string(), json!(true))]));
+        let store = |coord: &str, metadata, created_at| {
+            let params = StoreParams {
+                metadata,
+                created_at,
+                ..params(&CoordId(coord.to_string()), json!({"scratch": coord}))
+            };
+            let facade = &facade;
+            async move { facade.store(params).await.unwrap() }
+        };
+
+        store("IDLE", ephemeral(), hours_ago(3)).await;
+        store("READ", ephemeral(), hours_ago(3)).await;
+        store("WRITTEN", ephemeral(), hours_ago(3)).await;
+        store("WRITTEN", None, None).await;
+        store("DURABLE", None, hours_ago(3)).await;
+        store("PROMOTED", ephemeral(), hours_ago(3)).await;
+        let read = AccessRecord {
+            coord_id: CoordId("READ".to_string()),
+            read_count: 1,
+            last_read_at: now,
+        };
+        facade.repository().record_access_batch(&[read], None).await.unwrap();
+
+        let promoted = CoordId("PROMOTED".to_string());
+        assert_eq!(facade.promote(&promoted).await.unwrap(), Some(true));
+        assert_eq!(facade.promote(&promoted).await.unwrap(), Some(false));
+        assert_eq!(facade.promote(&CoordId("MISSING".to_string())).await.unwrap(), None);
+        let metadata = facade.repository().get_coordinate(&promoted).await.unwrap().unwrap().metadata;
+        assert_eq!(metadata, Some(HashMap::new()));
+
+        // Only the coordinate untouched for the idle window goes
+        let idle = Duration::from_secs(3600);
+        assert_eq!(facade.collect_ephemeral(idle, now).await.unwrap(), [CoordId("IDLE".to_string())]);
+        assert!(facade.head(&CoordId("IDLE".to_string())).await.unwrap().is_none());
+        let left: HashSet<CoordId> = facade.repository().list_coordinate_ids().await.unwrap().into_iter().collect();
+        assert_eq!(left.len(), 4);
+
+        // Later on, the remaining ephemerals go too
+        let later = now + chrono::Duration::hours(2);
+        assert_eq!(facade.collect_ephemeral(idle, later).await.unwrap().len(), 2);
+        assert_eq!(facade.repository().list_ephemeral().await.unwrap(), []);
+
+        // The flag must be a boolean
+        let bad = HashMap::from([(EPHEMERAL_METADATA_KEY.to_string(), json!("yes"))]);
+        assert!(matches!(facade.patch_metadata(&promoted, bad).await, Err(BmsError::InvalidState(_))));
+    }
+
+    #[tokio::test]
+    async fn test_metadata_patches_merge_and_relink() {
+        let db = TempDb::new("facade-metadata");
+        let facade = db.facade(16).await;
+        let repo = facade.repository();
+        let coord = CoordId("PATCHED".to_string());
+        let target = CoordinateGenerator::generate_now(&json!({"target": 1})).unwrap();
+        let patch = |pairs: &[(&str, Value)]| -> HashMap<String, Value> {
+            pairs.iter().map(|(k, v)| (k.to_string(), v.clone())).collect()
+        };
+
+        store_linked(&facade, &coord, json!({"related": [target.0], "parent": target.0})).await;
+        assert_eq!(repo.get_links(&coord).await.unwrap().len(), 1);
+        let mut events = facade.subscribe_storage();
+        let generation = facade.generation();
+
+        // Merge a key and drop one with null; link rules change, so links follow
+        let metadata = facade
+            .patch_metadata(&coord, patch(&[("project", json!("apollo")), ("links", json!(["/parent"]))]))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(metadata["project"], json!("apollo"));
+        let links = repo.get_links(&coord).await.unwrap();
+        assert_eq!(links.iter().map(|l| l.pointer.as_str()).collect::<Vec<_>>(), ["/parent"]);
+        let metadata = facade.patch_metadata(&coord, patch(&[("links", Value::Null)])).await.unwrap().unwrap();
+        assert_eq!(metadata, patch(&[("project", json!("apollo"))]));
+        assert!(repo.get_links(&coord).await.unwrap().is_empty());
+        assert_eq!(facade.generation(), generation + 2);
+
+        let event = events.recv().await.unwrap();
+        assert!(matches!(event, StorageEvent::MetadataUpdated { ref coord_id, .. } if coord_id == &coord));
+        assert_eq!(
+            events.recv().await.unwrap(),
+            StorageEvent::MetadataUpdated { coord_id: coord.clone(), metadata: metadata.clone() }
+        );
+
+        // Invalid rules are rejected and change nothing; a missing coordinate is None
+        let invalid = facade.patch_metadata(&coord, patch(&[("redact", json!("/secrets"))])).await;
+        assert!(invalid.is_err());
+        assert_eq!(repo.get_coordinate(&coord).await.unwrap().unwrap().metadata, Some(metadata));
+        let missing = CoordId("MISSING".to_string());
+        assert!(facade.patch_metadata(&missing, patch(&[])).await.unwrap().is_none());
+        assert!(events.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_subscribers_see_chain_order() {
+        let db = TempDb::new("facade-events");
+        let facade = db.facade(128).await;
+        let coord = CoordId("FACADETEST".to_string());
+        let mut events = facade.subscribe();
+
+        let mut outcomes = Vec::new();
+        for i in 0..3 {
+            outcomes.push(facade.store(params(&coord, json!({"n": i}))).await.unwrap());
+        }
+
+        for (i, outcome) in outcomes.iter().enumerate() {
+            let event = events.recv().await.unwrap();
+            assert_eq!(event.delta_id, outcome.head.delta_id);
+            assert_eq!(event.chain_hash, outcome.head.chain_hash);
+            let expected_parent = (i > 0).then(|| outcomes[i - 1].head.delta_id.clone());
+            assert_eq!(event.parent_id, expected_parent);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_group_conflict_writes_nothing() {
+        let db = TempDb::new("facade-group-conflict");
+        let facade = db.facade(128).await;
+        let profile = CoordId("PROFILE".to_string());
+        let log = CoordId("LOG".to_string());
+        let first = facade.store(params(&log, json!({"log": 0}))).await.unwrap();
+        let before = facade.repository().get_stats().await.unwrap();
+
+        let result = facade
+            .store_group(vec![
+                params(&profile, json!({"name": "ada"})),
+                StoreParams {
+                    precondition: Some(StorePrecondition::HeadDeltaId(DeltaId("stale".to_string()))),
+                    ..params(&log, json!({"log": 1}))
+                },
+                params(&CoordId("TASK".to_string()), json!({"task": 1})),
+            ])
+            .await;
+
+        match result {
+            Err(BmsError::GroupConflict { index, coord_id, actual, .. }) => {
+                assert_eq!(index, 1);
+                assert_eq!(coord_id, "LOG");
+                assert_eq!(actual, first.head.delta_id.0);
+            }
+            other => panic!("expected a group conflict, got {:?}", other),
+        }
+
+        let after = facade.repository().get_stats().await.unwrap();
+        assert_eq!(after.coordinate_count, before.coordinate_count);
+        assert_eq!(after.delta_count, before.delta_count);
+        assert!(facade.head(&profile).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_group_commits_and_defers_snapshots() {
+        let db = TempDb::new("facade-group");
+        let facade = db.facade(2).await;
+        let profile = CoordId("PROFILE".to_string());
+        let task = CoordId("TASK".to_string());
+        let first = facade.store(params(&profile, json!({"name": "ada"}))).await.unwrap();
+        let mut events = facade.subscribe();
+
+        let outcomes = facade
+            .store_group(vec![
+                StoreParams {
+                    precondition: Some(StorePrecondition::HeadDeltaId(first.head.delta_id.clone())),
+                    ..params(&profile, json!({"name": "grace"}))
+                },
+                params(&task, json!({"task": "write"})),
+            ])
+            .await
+            .unwrap();
+
+        assert_eq!(outcomes.len(), 2);
+        assert!(outcomes[1].coordinate_created);
+        assert_eq!(outcomes[0].snapshot, SnapshotStatus::Scheduled);
+        assert_eq!(outcomes[1].snapshot, SnapshotStatus::Skipped);
+        assert_eq!(events.recv().await.unwrap().delta_id, outcomes[0].head.delta_id);
+        assert_eq!(facade.head(&task).await.unwrap().unwrap().state, json!({"task": "write"}));
+
+        // Second delta on PROFILE is due a snapshot under interval 2
+        assert!(facade.repository().get_latest_snapshot(&profile).await.unwrap().is_none());
+        assert_eq!(facade.pending_snapshots(), 1);
+        assert_eq!(facade.flush_pending_snapshots().await, 1);
+        let snapshot = facade.repository().get_latest_snapshot(&profile).await.unwrap().unwrap();
+        assert_eq!(snapshot.head_delta_id, outcomes[0].head.delta_id);
+
+        let repeated = facade
+            .store_group(vec![
+                params(&task, json!({"task": "a"})),
+                params(&task, json!({"task": "b"})),
+            ])
+            .await;
+        assert!(matches!(repeated, Err(BmsError::InvalidState(_))));
+    }
+
+    #[tokio::test]
+    async fn test_patches_larger_than_the_state_store_it_whole() {
+        let db = TempDb::new("facade-full-replace");
+        let facade = db.facade(128).await;
+        let coord = CoordId("REGENERATED".to_string());
+        let paragraphs = |seed: u32| -> Vec<String> {
+            (0..12).map(|i| format!("paragraph {} of draft {}, regenerated in full", i, seed)).collect()
+        };
+        let first = facade.store(params(&coord, json!({"doc": paragraphs(0), "title": "notes"}))).await.unwrap();
+        assert_eq!((first.patch_ratio, first.full_replace), (None, false));
+
+        // A one-field edit keeps its small patch
+        let edited = facade.store(params(&coord, json!({"doc": paragraphs(0), "title": "Notes"}))).await.unwrap();
+        assert!(edited.patch_ratio.unwrap() < 0.1);
+        assert!(!edited.full_replace);
+
+        // Regenerating every paragraph patches more bytes than the state has
+        let state = json!({"doc": paragraphs(1), "title": "Notes"});
+        let regenerated = facade.store(params(&coord, state.clone())).await.unwrap();
+        assert!(regenerated.patch_ratio.unwrap() > bms_core::delta::DEFAULT_FULL_REPLACE_RATIO);
+        assert!(regenerated.full_replace);
+        let replace_bytes = serde_json::to_string(&DeltaEngine::root_replace(&state).unwrap()).unwrap().len();
+        assert_eq!(regenerated.ops_bytes, replace_bytes);
+
+        let head = facade.head(&coord).await.unwrap().unwrap();
+        assert_eq!(head.state, state);
+        let stored = head.deltas.last().unwrap();
+        assert!(stored.is_full_replace());
+        assert_eq!(stored.ops.len(), 1);
+        assert!(!head.deltas[1].is_full_replace());
+        assert_eq!(facade.repository().get_coord_stats(Some(&coord)).await.unwrap()[0].full_replace_deltas, 1);
+        let ratios = facade.patch_ratios();
+        assert_eq!((ratios.total(), ratios.full_replaces), (2, 1));
+
+        // A higher threshold keeps the patch
+        let tolerant = StoreParams {
+            diff_options: Some(DiffOptions { full_replace_ratio: Some(10.0), ..DiffOptions::default() }),
+            ..params(&coord, json!({"doc": paragraphs(2), "title": "Notes"}))
+        };
+        let kept = facade.store(tolerant).await.unwrap();
+        assert!(!kept.full_replace);
+        assert!(kept.patch_ratio.unwrap() > bms_core::delta::DEFAULT_FULL_REPLACE_RATIO);
+        assert_eq!(facade.head(&coord).await.unwrap().unwrap().state["doc"], json!(paragraphs(2)));
+
+        // Small states keep their patch however large it is relative to them
+        let small = CoordId("SMALL".to_string());
+        facade.store(params(&small, json!({"log": []}))).await.unwrap();
+        let appended = facade.store(params(&small, json!({"log": ["hello"]}))).await.unwrap();
+        assert!(appended.patch_ratio.unwrap() > 1.0);
+        assert!(!appended.full_replace);
+    }
+
+    #[tokio::test]
+    async fn test_stored_snapshot_interval_survives_a_changed_default() {
+        let db = TempDb::new("facade-snapshot-interval");
+        let coord = CoordId("UPGRADED".to_string());
+        let repository = db.repository().await;
+        assert_eq!(repository.snapshot_interval().await.unwrap(), Some(bms_core::DEFAULT_SNAPSHOT_INTERVAL));
+
+        // A later build lowers the default to 4; the stored interval still applies
+        let interval = resolve_snapshot_interval(&repository, 4, None).await.unwrap();
+        assert_eq!(interval, bms_core::DEFAULT_SNAPSHOT_INTERVAL);
+        let facade = BmsFacade::new(repository, SnapshotManager::new(interval));
+        for n in 0..8 {
+            facade.store(params(&coord, json!({"n": n}))).await.unwrap();
+        }
+        assert!(facade.repository().get_latest_snapshot(&coord).await.unwrap().is_none());
+
+        // Until the operator opts in, which sticks across later startups
+        let repository = db.repository().await;
+        assert_eq!(resolve_snapshot_interval(&repository, 4, Some(4)).await.unwrap(), 4);
+        let interval = resolve_snapshot_interval(&repository, bms_core::DEFAULT_SNAPSHOT_INTERVAL, None).await.unwrap();
+        assert_eq!(interval, 4);
+        let facade = BmsFacade::new(repository, SnapshotManager::new(interval));
+        for n in 8..12 {
+            facade.store(params(&coord, json!({"n": n}))).await.unwrap();
+        }
+        assert!(facade.repository().get_latest_snapshot(&coord).await.unwrap().is_some());
+        assert!(matches!(
+            facade.repository().set_snapshot_interval(0).await,
+            Err(BmsError::InvalidState(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_policy_sees_size_and_age() {
+        let db = TempDb::new("facade-snapshot-policy");
+        let policy = bms_core::CompositePolicy(vec![
+            Box::new(bms_core::SizePolicy { max_reconstruction_bytes: 256 }),
+            Box::new(bms_core::TimePolicy(Duration::from_secs(3600))),
+        ]);
+        let facade = BmsFacade::new(db.repository().await, SnapshotManager::new_with_policy(Box::new(policy)));
+        assert_eq!(facade.snapshot_manager().interval(), None);
+
+        let sized = CoordId("SIZED".to_string());
+        let small = facade.store(params(&sized, json!({"n": 0}))).await.unwrap();
+        assert_eq!(small.snapshot, SnapshotStatus::Skipped);
+        let large = facade.store(params(&sized, json!({"n": 1, "body": "x".repeat(256)}))).await.unwrap();
+        assert_eq!(large.snapshot, SnapshotStatus::Created);
+
+        // A chain begun two hours ago is due on its next delta, and then not
+        // again until the new snapshot is an hour old
+        let aged = CoordId("AGED".to_string());
+        let began = StoreParams {
+            created_at: Some(Utc::now() - chrono::Duration::hours(2)),
+            ..params(&aged, json!({}))
+        };
+        assert_eq!(facade.store(began).await.unwrap().snapshot, SnapshotStatus::Skipped);
+        let due = facade.store(params(&aged, json!({"n": 1}))).await.unwrap();
+        assert_eq!(due.snapshot, SnapshotStatus::Created);
+        let fresh = facade.store(params(&aged, json!({"n": 2}))).await.unwrap();
+        assert_eq!(fresh.snapshot, SnapshotStatus::Skipped);
+    }
+
+    #[tokio::test]
+    async fn test_coord_filter_skips_lookups_for_new_coordinates() {
+        let db = TempDb::new("facade-filter");
+        let seeded = db.facade(128).await;
+        let existing = CoordId("EXISTING".to_string());
+        seeded.store(params(&existing, json!({"seed": 0}))).await.unwrap();
+
+        let facade = db.facade(128).await.with_coord_filter(0.01).await.unwrap();
+        for i in 0..20 {
+            let coord = CoordId(format!("NEW{}", i));
+            let outcome = facade.store(params(&coord, json!({"new": i}))).await.unwrap();
+            assert!(outcome.coordinate_created);
+        }
+        let outcome = facade.store(params(&existing, json!({"seed": 1}))).await.unwrap();
+        assert!(!outcome.coordinate_created);
+        assert_eq!(facade.head(&existing).await.unwrap().unwrap().deltas.len(), 2);
+
+        let stats = facade.coord_filter_stats().unwrap();
+        assert!(stats.lookups_avoided >= 19, "{:?}", stats);
+        assert_eq!(stats.items, 21);
+
+        // A deleted coordinate is dropped from the filter by the next rebuild
+        facade.delete_coordinate(&existing).await.unwrap();
+        let outcome = facade.store(params(&existing, json!({"seed": 2}))).await.unwrap();
+        assert!(outcome.coordinate_created);
+        assert_eq!(facade.coord_filter_stats().unwrap().rebuilds, 1);
+    }
+    #[tokio::test]
+    async fn test_state_sizes_are_tracked_and_backfilled() {
+        let db = TempDb::new("facade-sizes");
+        let facade = Arc::new(db.facade(2).await.with_state_size_warning(32));
+        let (small, large) = (CoordId("SMALL".to_string()), CoordId("LARGE".to_string()));
+        let sizes = |facade: Arc<BmsFacade>| async move {
+            let sizes = facade.repository().list_state_sizes(None).await.unwrap();
+            sizes.into_iter().map(|s| (s.coord_id.0, s.state_bytes)).collect::<Vec<_>>()
+        };
+
+        let outcome = facade.store(params(&small, json!({"a": 1}))).await.unwrap();
+        assert!(outcome.warnings.is_empty());
+        let outcome = facade.store(params(&large, json!({"text": "x".repeat(40)}))).await.unwrap();
+        assert_eq!(outcome.warnings, [StoreWarning::LargeState]);
+        facade.store(params(&small, json!({"a": 1, "b": [2, 3]}))).await.unwrap();
+        assert_eq!(
+            sizes(facade.clone()).await,
+            [("LARGE".to_string(), 51), ("SMALL".to_string(), 17)]
+        );
+
+        // Databases written before sizes were tracked are filled in by a pass
+        db.execute("DELETE FROM coord_size").await;
+        assert!(sizes(facade.clone()).await.is_empty());
+        assert_eq!(facade.recompute_state_sizes(2).await.unwrap(), 2);
+        assert_eq!(
+            sizes(facade.clone()).await,
+            [("LARGE".to_string(), 51), ("SMALL".to_string(), 17)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_compression_stats_of_a_chain() {
+        let db = TempDb::new("facade-compression");
+        let facade = db.facade(100).await;
+        let coord = CoordId("SQUEEZED".to_string());
+        let mut state = json!({"log": [], "title": "t".repeat(200)});
+        for n in 0..4 {
+            state["log"].as_array_mut().unwrap().push(json!(n));
+            facade.store(params(&coord, state.clone())).await.unwrap();
+        }
+
+        let stats = facade.compression_stats(&coord).await.unwrap().unwrap();
+        let head_bytes = Canonicalizer::canonical_len(&state).unwrap();
+        let ops_bytes: usize = facade
+            .repository()
+            .get_deltas(&coord)
+            .await
+            .unwrap()
+            .iter()
+            .map(|d| DeltaEngine::encode_ops(&d.ops).unwrap().len())
+            .sum();
+        assert_eq!((stats.delta_count, stats.original_bytes), (4, 4 * head_bytes));
+        assert_eq!(stats.compressed_bytes, ops_bytes);
+        assert!(stats.compression_ratio > 0.5);
+        // The first store adds the whole state, later ones one element each
+        assert_eq!((stats.ops.add_count, stats.ops.op_count()), (5, 5));
+
+        // Without a recorded head size the head is measured
+        db.execute("DELETE FROM coord_size").await;
+        assert!(facade.repository().get_compression_stats(&coord).await.unwrap().is_none());
+        let measured = facade.compression_stats(&coord).await.unwrap().unwrap();
+        assert_eq!(measured.original_bytes, stats.original_bytes);
+        assert_eq!(measured.ops, stats.ops);
+        assert!(facade.compression_stats(&CoordId("MISSING".to_string())).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_externalized_fields_are_shared_between_snapshots() {
+        let db = TempDb::new("facade-blobs");
+        let facade = Arc::new(db.facade(2).await);
+        let coord = CoordId("TRANSCRIPT".to_string());
+        let blobs = |facade: Arc<BmsFacade>| async move { facade.repository().get_stats().await.unwrap().blob_count };
+
+        let transcript = json!(["hello", "how are you", "fine"]);
+        facade
+            .store(StoreParams {
+                metadata: Some(HashMap::from([(
+                    "snapshot_externalize".to_string(),
+                    json!(["/transcript", "/absent"]),
+                )])),
+                ..params(&coord, json!({"turn": 1, "transcript": transcript}))
+            })
+            .await
+            .unwrap();
+        facade.create_snapshot(&coord).await.unwrap().unwrap();
+        facade.store(params(&coord, json!({"turn": 2, "transcript": transcript}))).await.unwrap();
+        let snapshot = facade.create_snapshot(&coord).await.unwrap().unwrap();
+
+        // The unchanged transcript is stored once and read back in place
+        assert_eq!(blobs(facade.clone()).await, 1);
+        let stored = facade.repository().get_latest_snapshot(&coord).await.unwrap().unwrap();
+        assert_eq!(stored.state, json!({"turn": 2, "transcript": transcript}));
+        assert_eq!(stored.state_hash, snapshot.state_hash);
+        assert_eq!(crate::sampler::verify_coordinate(&facade, &coord).await.unwrap(), None);
+
+        // Pruning keeps a blob a retained snapshot still uses
+        facade.prune_snapshots(&coord, 1).await.unwrap();
+        assert_eq!(blobs(facade.clone()).await, 1);
+        facade.store(params(&coord, json!({"turn": 3, "transcript": ["bye"]}))).await.unwrap();
+        facade.create_snapshot(&coord).await.unwrap().unwrap();
+        assert_eq!(blobs(facade.clone()).await, 2);
+        facade.prune_snapshots(&coord, 1).await.unwrap();
+        assert_eq!(blobs(facade.clone()).await, 1);
+        let head = facade.head(&coord).await.unwrap().unwrap();
+        assert_eq!(head.state, json!({"turn": 3, "transcript": ["bye"]}));
+
+        let invalid = StoreParams {
+            metadata: Some(HashMap::from([("snapshot_externalize".to_string(), json!("transcript"))])),
+            ..params(&coord, json!({"turn": 4}))
+        };
+        assert!(facade.store(invalid).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_materialized_head_stays_consistent() {
+        let db = TempDb::new("facade-materialized");
+        let facade = db.facade(100).await;
+        let coord = CoordId("MATERIALIZED".to_string());
+        let metadata = HashMap::from([(MATERIALIZE_HEAD_METADATA_KEY.to_string(), json!(true))]);
+        facade
+            .store(StoreParams {
+                metadata: Some(metadata),
+                ..params(&coord, json!({"n": 1}))
+            })
+            .await
+            .unwrap();
+        let head = facade.head(&coord).await.unwrap().unwrap();
+        assert_eq!((head.state, head.replay.materialized), (json!({"n": 1}), true));
+
+        // A failing upsert rolls the whole store back
+        db.execute(
+            "CREATE TRIGGER break_head_states BEFORE INSERT ON head_states \
+             BEGIN SELECT RAISE(ABORT, 'simulated crash'); END",
+        )
+        .await;
+        assert!(facade.store(params(&coord, json!({"n": 2}))).await.is_err());
+        db.execute("DROP TRIGGER break_head_states").await;
+        let head = facade.head(&coord).await.unwrap().unwrap();
+        assert_eq!((head.state, head.deltas.len(), head.replay.materialized), (json!({"n": 1}), 1, true));
+
+        // A row that fell behind its chain is replayed past and repaired
+        facade.store(params(&coord, json!({"n": 2}))).await.unwrap();
+        db.execute("UPDATE head_states SET chain_hash = 'stale'").await;
+        let head = facade.head(&coord).await.unwrap().unwrap();
+        assert_eq!((head.state, head.replay.materialized), (json!({"n": 2}), false));
+        assert_eq!(facade.check_head_state(&coord).await.unwrap(), None);
+        let head = facade.head(&coord).await.unwrap().unwrap();
+        assert_eq!((head.state, head.replay.materialized), (json!({"n": 2}), true));
+
+        // A row that matches the chain hash but not the chain is drift
+        let tip = head.deltas.last().unwrap();
+        let drifted = MaterializedHead {
+            coord_id: coord.clone(),
+            head_delta_id: tip.id.clone(),
+            chain_hash: tip.chain_hash.clone(),
+            state: json!({"n": 3}),
+            updated_at: Utc::now(),
+        };
+        assert!(facade.repository().record_head_state(&drifted).await.unwrap());
+        assert!(facade.check_head_state(&coord).await.unwrap().is_some());
+        assert!(facade.rebuild_head_state(&coord).await.unwrap());
+        assert_eq!(facade.check_head_state(&coord).await.unwrap(), None);
+        let stats = facade.repository().get_stats().await.unwrap();
+        assert_eq!(stats.head_state_count, 1);
+        assert!(stats.head_state_bytes > 0);
+
+        // Turning the flag off drops the row
+        let off = HashMap::from([(MATERIALIZE_HEAD_METADATA_KEY.to_string(), json!(false))]);
+        facade.patch_metadata(&coord, off).await.unwrap().unwrap();
+        assert!(facade.repository().get_head_state(&coord).await.unwrap().is_none());
+        assert!(!facade.head(&coord).await.unwrap().unwrap().replay.materialized);
+        let invalid = HashMap::from([(MATERIALIZE_HEAD_METADATA_KEY.to_string(), json!("yes"))]);
+        assert!(facade.patch_metadata(&coord, invalid).await.is_err());
+    }
+
+    /// Stores, snapshots, materialized heads, checkpointed replays, patches,
+    /// and deletes on a healthy database, returning every head it read
+    async fn run_flows(facade: &BmsFacade) -> Vec<(Value, ReplayStats)> {
+        let mut heads = Vec::new();
+        let plain = CoordId("FLOWPLAIN".to_string());
+        let materialized = CoordId("FLOWMATERIAL".to_string());
+        let metadata = HashMap::from([(MATERIALIZE_HEAD_METADATA_KEY.to_string(), json!(true))]);
+        for n in 0..25 {
+            facade.store(params(&plain, json!({"n": n, "seen": (0..n).collect::<Vec<_>>()}))).await.unwrap();
+            facade
+                .store(StoreParams {
+                    metadata: Some(metadata.clone()),
+                    ..params(&materialized, json!({"m": n}))
+                })
+                .await
+                .unwrap();
+        }
+        // The first replay writes checkpoints and the second resumes from one
+        for _ in 0..2 {
+            let head = facade.head(&plain).await.unwrap().unwrap();
+            heads.push((head.state, head.replay));
+        }
+        facade.create_snapshot(&plain).await.unwrap();
+        facade.store(params(&plain, json!({"n": 25}))).await.unwrap();
+        for coord in [&plain, &materialized] {
+            let head = facade.head(coord).await.unwrap().unwrap();
+            heads.push((head.state, head.replay));
+        }
+        let patch = HashMap::from([(MATERIALIZE_HEAD_METADATA_KEY.to_string(), json!(false))]);
+        facade.patch_metadata(&materialized, patch).await.unwrap().unwrap();
+        let head = facade.head(&materialized).await.unwrap().unwrap();
+        heads.push((head.state, head.replay));
+        facade.delete_coordinate(&materialized).await.unwrap();
+        assert!(facade.head(&materialized).await.unwrap().is_none());
+        heads
+    }
+
+    #[tokio::test]
+    async fn test_flows_agree_in_both_integrity_modes() {
+        let default_db = TempDb::new("facade-flows-default");
+        let strict_db = TempDb::new("facade-flows-strict");
+        let checkpoints = |facade: BmsFacade| facade.with_reconstruction_checkpoints(10, DEFAULT_CHECKPOINT_TTL);
+        let default = checkpoints(default_db.facade(100).await);
+        let strict = checkpoints(strict_db.facade(100).await).with_integrity_checks(IntegrityChecks::strict());
+        assert!(strict.integrity_checks().is_strict());
+        assert_eq!(default.integrity_checks().enabled(), Vec::<&str>::new());
+
+        let heads = run_flows(&default).await;
+        assert_eq!(heads[1].1.checkpoint_seq, Some(20));
+        assert_eq!(heads, run_flows(&strict).await);
+    }
+
+    #[tokio::test]
+    async fn test_strict_mode_fails_where_default_repairs() {
+        let db = TempDb::new("facade-strict");
+        let writer = db.facade(5).await;
+        let coord = CoordId("STRICTREAD".to_string());
+        for n in 0..7 {
+            writer.store(params(&coord, json!({"n": n}))).await.unwrap();
+        }
+        let verify_only = IntegrityChecks {
+            verify_snapshots: true,
+            verify_deltas: true,
+            ..IntegrityChecks::default()
+        };
+        let unchecked = db.facade(5).await;
+        let verifying = db.facade(5).await.with_integrity_checks(verify_only);
+        let strict = db.facade(5).await.with_integrity_checks(IntegrityChecks::strict());
+
+        // A snapshot that fails verification: used unchecked, skipped when
+        // verified, and refused in strict mode
+        db.execute("UPDATE snapshots SET state_hash = lower(hex(randomblob(32)))").await;
+        let head = unchecked.head(&coord).await.unwrap().unwrap();
+        assert_eq!((head.state, head.replay.snapshot_seq), (json!({"n": 6}), Some(5)));
+        let head = verifying.head(&coord).await.unwrap().unwrap();
+        assert_eq!((head.state, head.replay.snapshot_seq, head.replay.replayed), (json!({"n": 6}), None, 7));
+        let err = strict.head(&coord).await.unwrap_err();
+        assert!(matches!(err, BmsError::IntegrityViolation(ref m) if m.contains("fails verification")), "{}", err);
+
+        // A materialized head behind its chain: rebuilt, or refused
+        writer.create_snapshot(&coord).await.unwrap();
+        let flag = HashMap::from([(MATERIALIZE_HEAD_METADATA_KEY.to_string(), json!(true))]);
+        writer.patch_metadata(&coord, flag).await.unwrap().unwrap();
+        db.execute("UPDATE head_states SET chain_hash = 'stale'").await;
+        let err = strict.head(&coord).await.unwrap_err();
+        assert!(matches!(err, BmsError::IntegrityViolation(ref m) if m.contains("behind its chain")), "{}", err);
+        assert_eq!(verifying.head(&coord).await.unwrap().unwrap().state, json!({"n": 6}));
+        assert!(strict.head(&coord).await.unwrap().unwrap().replay.materialized);
+
+        // A delta whose ops no longer match its hash is only noticed when verified
+        db.execute("UPDATE deltas SET delta_hash = lower(hex(randomblob(32))) WHERE parent_id IS NULL").await;
+        assert!(unchecked.head(&coord).await.is_ok());
+        for facade in [&verifying, &strict] {
+            let err = facade.head(&coord).await.unwrap_err();
+            assert!(matches!(err, BmsError::MerkleChainBroken { .. }), "{}", err);
+        }
+    }
+}
@@ -0,0 +1,193 @@
+//! Translating filter expressions to SQL over the coordinates table
+//!
+//! Only the coordinate-row part of a filter can be pushed down (see
+//! `FilterExpr::pushdown`). Metadata values are read with `json_each`, so keys
+//! need no quoting in a JSON path and every predicate is a plain `EXISTS` or
+//! column comparison. None of them can be SQL `NULL`, which keeps `NOT`
+//! two-valued and the results the same as `FilterExpr::matches`.
+
+use bms_core::filter::{CmpOp, Field, FilterExpr, TAGS_METADATA_KEY};
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+
+/// Value bound to a placeholder in the generated clause
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum SqlArg {
+    Text(String),
+    Real(f64),
+    Time(DateTime<Utc>),
+}
+
+/// `WHERE` clause body and its arguments, or `None` if the expression reads
+/// the author or head state
+pub(crate) fn where_clause(expr: &FilterExpr) -> Option<(String, Vec<SqlArg>)> {
+    let mut args = Vec::new();
+    let sql = translate(expr, &mut args)?;
+    Some((sql, args))
+}
+
+fn translate(expr: &FilterExpr, args: &mut Vec<SqlArg>) -> Option<String> {
+    let join = |items: &[FilterExpr], op: &str, args: &mut Vec<SqlArg>| -> Option<String> {
+        let parts = items.iter().map(|e| translate(e, args)).collect::<Option<Vec<_>>>()?;
+        Some(format!("({})", parts.join(op)))
+    };
+    Some(match expr {
+        FilterExpr::And(items) => join(items, " AND ", args)?,
+        FilterExpr::Or(items) => join(items, " OR ", args)?,
+        FilterExpr::Not(inner) => format!("(NOT {})", translate(inner, args)?),
+        FilterExpr::Eq { field, value } => metadata_test(field, args, |args| equals(value, args))?,
+        FilterExpr::In { field, values } => metadata_test(field, args, |args| {
+            let parts: Vec<String> = values.iter().map(|v| equals(v, args)).collect();
+            format!("({})", parts.join(" OR "))
+        })?,
+        FilterExpr::Cmp { field, op, value } => metadata_test(field, args, |args| {
+            args.push(SqlArg::Real(*value));
+            let op = match op {
+                CmpOp::Lt => "<",
+                CmpOp::Le => "<=",
+                CmpOp::Gt => ">",
+                CmpOp::Ge => ">=",
+            };
+            format!("(type IN ('integer', 'real') AND value {} ?)", op)
+        })?,
+        FilterExpr::Exists(field) => metadata_test(field, args, |_| "type != 'null'".to_string())?,
+        FilterExpr::Tag(tag) => {
+            args.push(SqlArg::Text(TAGS_METADATA_KEY.to_string()));
+            args.push(SqlArg::Text(tag.clone()));
+            "EXISTS (SELECT 1 FROM json_each(coordinates.metadata) AS m, json_each(m.value) AS t \
+             WHERE m.key = ? AND m.type = 'array' AND t.type = 'text' AND t.value = ?)"
+                .to_string()
+        }
+        FilterExpr::Created { after, before } => {
+            let mut parts = Vec::new();
+            if let Some(after) = after {
+                args.push(SqlArg::Time(*after));
+                parts.push("created_at >= ?");
+            }
+            if let Some(before) = before {
+                args.push(SqlArg::Time(*before));
+                parts.push("created_at < ?");
+            }
+            format!("({})", parts.join(" AND "))
+        }
+    })
+}
+
+/// `EXISTS` over the metadata member named by `field`, with `test` applied to
+/// its `type` and `value`
+fn metadata_test(field: &Field, args: &mut Vec<SqlArg>, test: impl FnOnce(&mut Vec<SqlArg>) -> String) -> Option<String> {
+    let Field::Metadata(key) = field else {
+        return None;
+    };
+    args.push(SqlArg::Text(key.clone()));
+    let test = test(args);
+    Some(format!(
+        "EXISTS (SELECT 1 FROM json_each(coordinates.metadata) WHERE key = ? AND {})",
+        test
+    ))
+}
+
+fn equals(value: &Value, args: &mut Vec<SqlArg>) -> String {
+    match value {
+        Value::String(s) => {
+            args.push(SqlArg::Text(s.clone()));
+            "(type = 'text' AND value = ?)".to_string()
+        }
+        Value::Number(n) => {
+            args.push(SqlArg::Real(n.as_f64().unwrap_or(f64::NAN)));
+            "(type IN ('integer', 'real') AND value = ?)".to_string()
+        }
+        Value::Bool(true) => "type = 'true'".to_string(),
+        Value::Bool(false) => "type = 'false'".to_string(),
+        // Compiled filters only compare scalars
+        _ => "0".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDb;
+    use bms_core::filter::{metadata_tags, FilterRecord};
+    use bms_core::types::{CoordId, Coordinate};
+    use chrono::{Duration, TimeZone};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_pushdown_matches_brute_force() {
+        let db = TempDb::new("filter-sql");
+        let repo = db.repository().await;
+        let base = Utc.with_ymd_and_hms(2026, 9, 1, 12, 0, 0).unwrap();
+        let rows = [
+            json!({"team": "core", "priority": 1, "tags": ["db", "urgent"], "done": true}),
+            json!({"team": "core", "priority": 2.5, "tags": ["ui"], "done": false}),
+            json!({"team": "edge", "priority": "3", "tags": "urgent", "owner": null}),
+            json!({"team": null, "priority": 3, "tags": [], "odd key \"x\"": "y"}),
+            json!({"priority": 0, "tags": ["urgent", 7]}),
+            Value::Null,
+        ];
+        for (i, metadata) in rows.iter().enumerate() {
+            let coord = Coordinate {
+                id: CoordId(format!("c{}", i)),
+                rune_alias: None,
+                // Sub-second offsets exercise the stored timestamp format
+                created_at: base + Duration::days(i as i64) + Duration::microseconds(i as i64 * 1500),
+                metadata: serde_json::from_value(metadata.clone()).unwrap(),
+                namespace: None,
+                hash_algorithm: Default::default(),
+                kind: Default::default(),
+                parent_coord_id: None,
+            };
+            repo.insert_coordinate_if_absent(&coord).await.unwrap();
+        }
+        let all = repo.list_coordinates(None, None).await.unwrap();
+
+        let filters = [
+            "metadata.team == core",
+            "metadata.team != core",
+            "metadata.team in (core, edge) and not metadata.done == true",
+            "metadata.priority == 3",
+            "metadata.priority == '3'",
+            "metadata.priority >= 2.5 or tag == db",
+            "not (metadata.priority < 2)",
+            "exists metadata.team",
+            "not exists metadata.owner",
+            "metadata.done == false or metadata.done == true",
+            "tag == urgent",
+            "not tag in (urgent, ui)",
+            "created >= 2026-09-03T12:00:00.003Z",
+            "created < 2026-09-03T12:00:00.003Z and not created < 2026-09-02T00:00:00Z",
+            "created >= -100000d and (metadata.team == edge or not exists metadata.team)",
+        ];
+        let mut exprs: Vec<FilterExpr> = filters.iter().map(|f| FilterExpr::parse(f, Utc::now()).unwrap()).collect();
+        // Keys are bound as values, so no JSON path quoting is involved
+        exprs.push(FilterExpr::from_json(&json!({"eq": {"field": "metadata.odd key \"x\"", "value": "y"}}), Utc::now()).unwrap());
+        for expr in exprs {
+            let pushed: Vec<String> = repo
+                .list_coordinates_where(&expr, None)
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|c| c.id.0)
+                .collect();
+            let brute: Vec<String> = all
+                .iter()
+                .filter(|c| {
+                    let tags = metadata_tags(c.metadata.as_ref());
+                    expr.matches(&FilterRecord {
+                        tags: &tags,
+                        created_at: Some(c.created_at),
+                        metadata: c.metadata.as_ref(),
+                        ..Default::default()
+                    })
+                })
+                .map(|c| c.id.0.clone())
+                .collect();
+            assert_eq!(pushed, brute, "{}", expr.to_json());
+        }
+
+        let author = FilterExpr::parse("author == alice", Utc::now()).unwrap();
+        assert!(where_clause(&author).is_none());
+        assert!(repo.list_coordinates_where(&author, None).await.is_err());
+    }
+}
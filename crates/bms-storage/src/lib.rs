@@ -1,7 +1,28 @@
 //! BMS Storage - SQLite-based persistent storage for coordinates, deltas, and snapshots
 
+pub mod access;
+pub mod bloom;
+pub mod drill;
+pub mod facade;
+mod filter_sql;
 pub mod models;
+pub mod oplog;
+pub mod planner;
 pub mod repository;
+pub mod sampler;
 pub mod schema;
+pub mod simulate;
 
+#[cfg(test)]
+mod query_tests;
+#[cfg(test)]
+mod test_support;
+
+pub use access::AccessTracker;
+pub use facade::{
+    resolve_snapshot_interval, AppendOutcome, BmsFacade, DeltaEvent, IndexStatus, IntegrityChecks,
+    LinkDeletePolicy, PreparedStore, ReplayStats, SnapshotStatus, StorageEvent, StoreHead, StoreOutcome,
+    StoreParams, StorePrecondition, StoreTimings, StoreWarning,
+};
+pub use planner::{CostModel, PlanAction, Recommendation};
 pub use repository::BmsRepository;
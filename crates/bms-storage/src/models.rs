@@ -1,7 +1,14 @@
-use bms_core::types::{Coordinate, CoordId, Delta, DeltaId, Snapshot, SnapshotId};
+use crate::oplog::{BackupMarker, OplogEntry};
+use bms_core::types::{Coordinate, CoordId, Delta, DeltaId, DeltaSignature, Hash, Snapshot, SnapshotId};
+use bms_core::{BmsError, DeltaEngine, ImportancePolicy, Link};
 use chrono::{DateTime, Utc};
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use serde::Serialize;
 use serde_json::Value;
 use sqlx::FromRow;
+use std::io::Read;
 
 /// Database model for coordinates
 #[derive(Debug, Clone, FromRow)]
@@ -10,6 +17,10 @@ pub struct CoordRow {
     pub rune_alias: Option<String>,
     pub created_at: DateTime<Utc>,
     pub metadata: Option<String>, // JSON string
+    pub namespace: Option<String>,
+    pub hash_algorithm: String,
+    pub id_kind: String,
+    pub parent_coord_id: Option<String>,
 }
 
 impl From<CoordRow> for Coordinate {
@@ -23,11 +34,19 @@ impl From<CoordRow> for Coordinate {
             rune_alias: row.rune_alias,
             created_at: row.created_at,
             metadata,
+            namespace: row.namespace,
+            hash_algorithm: row.hash_algorithm.parse().unwrap_or_default(),
+            kind: row.id_kind.parse().unwrap_or_default(),
+            parent_coord_id: row.parent_coord_id.map(CoordId),
         }
     }
 }
 
 /// Database model for deltas
+///
+/// Columns are read as text and validated by the conversion, so a corrupt
+/// row fails with `BmsError::CorruptRow` naming it rather than a bare decode
+/// error.
 #[derive(Debug, Clone, FromRow)]
 pub struct DeltaRow {
     pub id: String,
@@ -36,31 +55,83 @@ pub struct DeltaRow {
     pub parent_hash: Option<String>,
     pub delta_hash: String,
     pub chain_hash: String,
-    pub ops: String, // JSON string
-    pub created_at: DateTime<Utc>,
+    /// JSON or CBOR array, see `DeltaEngine::encode_ops`
+    pub ops: Vec<u8>,
+    pub created_at: String,
     pub tags: Option<String>,
     pub author: Option<String>,
+    /// JSON array from `delta_op_authors`
+    pub op_authors: Option<String>,
+    /// 32-byte ed25519 public key, set together with `signature_bytes`
+    pub signature_pubkey: Option<Vec<u8>>,
+    pub signature_bytes: Option<Vec<u8>>,
+}
+
+impl DeltaRow {
+    /// Convert to a delta; with `lenient`, ops that do not decode (e.g. of
+    /// an unknown type) are skipped instead of failing the row
+    ///
+    /// Returns the delta and a description of each skipped op. The op
+    /// authors of skipped ops are dropped with them.
+    pub fn decode(self, lenient: bool) -> Result<(Delta, Vec<String>), BmsError> {
+        let corrupt = |e: BmsError| corrupt_row("deltas", &self.id, &self.coord_id, e);
+        let ops = DeltaEngine::decode_op_values(&self.ops).map_err(corrupt)?;
+        let mut op_authors: Option<Vec<String>> = self
+            .op_authors
+            .as_deref()
+            .map(serde_json::from_str)
+            .transpose()
+            .map_err(|e| corrupt(e.into()))?;
+        let mut decoded = Vec::with_capacity(ops.len());
+        let mut skipped = Vec::new();
+        for (index, op) in ops.into_iter().enumerate() {
+            match serde_json::from_value::<json_patch::PatchOperation>(op) {
+                Ok(op) => decoded.push(op),
+                Err(e) if lenient => {
+                    tracing::warn!("Skipping op {} of delta {}: {}", index, self.id, e);
+                    if let Some(authors) = op_authors.as_mut().filter(|a| index - skipped.len() < a.len()) {
+                        authors.remove(index - skipped.len());
+                    }
+                    skipped.push(format!("op {}: {}", index, e));
+                }
+                Err(e) => return Err(corrupt(BmsError::InvalidState(format!("op {}: {}", index, e)))),
+            }
+        }
+        let parent_hash = self.parent_hash.as_deref().map(|h| parse_hash("parent_hash", h)).transpose();
+        let signature = match (&self.signature_pubkey, &self.signature_bytes) {
+            (None, None) => None,
+            (Some(key), Some(sig)) => match (key.as_slice().try_into(), sig.as_slice().try_into()) {
+                (Ok(public_key), Ok(signature_bytes)) => Some(DeltaSignature { public_key, signature_bytes }),
+                _ => {
+                    let lengths = format!("signature of {} and {} bytes", key.len(), sig.len());
+                    return Err(corrupt(BmsError::InvalidSignature(lengths)));
+                }
+            },
+            _ => return Err(corrupt(BmsError::InvalidSignature("half a signature".to_string()))),
+        };
+        let delta = Delta {
+            parent_hash: parent_hash.map_err(corrupt)?,
+            delta_hash: parse_hash("delta_hash", &self.delta_hash).map_err(corrupt)?,
+            chain_hash: parse_hash("chain_hash", &self.chain_hash).map_err(corrupt)?,
+            created_at: parse_timestamp(&self.created_at).map_err(corrupt)?,
+            ops: decoded,
+            tags: self.tags.as_deref().and_then(|s| serde_json::from_str(s).ok()),
+            op_authors,
+            signature,
+            id: DeltaId(self.id),
+            coord_id: CoordId(self.coord_id),
+            parent_id: self.parent_id.map(DeltaId),
+            author: self.author,
+        };
+        Ok((delta, skipped))
+    }
 }
 
 impl TryFrom<DeltaRow> for Delta {
     type Error = bms_core::error::BmsError;
 
     fn try_from(row: DeltaRow) -> Result<Self, Self::Error> {
-        let ops: Vec<json_patch::PatchOperation> = serde_json::from_str(&row.ops)?;
-        let tags = row.tags.and_then(|s| serde_json::from_str(&s).ok());
-
-        Ok(Delta {
-            id: DeltaId(row.id),
-            coord_id: CoordId(row.coord_id),
-            parent_id: row.parent_id.map(DeltaId),
-            parent_hash: row.parent_hash.map(bms_core::types::Hash),
-            delta_hash: bms_core::types::Hash(row.delta_hash),
-            chain_hash: bms_core::types::Hash(row.chain_hash),
-            ops,
-            created_at: row.created_at,
-            tags,
-            author: row.author,
-        })
+        row.decode(false).map(|(delta, _)| delta)
     }
 }
 
@@ -72,22 +143,644 @@ pub struct SnapshotRow {
     pub head_delta_id: String,
     pub state_hash: String,
     pub state: String, // JSON string
-    pub created_at: DateTime<Utc>,
+    pub created_at: String,
 }
 
 impl TryFrom<SnapshotRow> for Snapshot {
     type Error = bms_core::error::BmsError;
 
     fn try_from(row: SnapshotRow) -> Result<Self, Self::Error> {
-        let state: Value = serde_json::from_str(&row.state)?;
+        let corrupt = |e: BmsError| corrupt_row("snapshots", &row.id, &row.coord_id, e);
+        let state: Value = serde_json::from_str(&row.state).map_err(|e| corrupt(e.into()))?;
+        let state_hash = parse_hash("state_hash", &row.state_hash).map_err(corrupt)?;
+        let created_at = parse_timestamp(&row.created_at).map_err(corrupt)?;
 
         Ok(Snapshot {
             id: SnapshotId(row.id),
             coord_id: CoordId(row.coord_id),
             head_delta_id: DeltaId(row.head_delta_id),
-            state_hash: bms_core::types::Hash(row.state_hash),
+            state_hash,
             state,
+            created_at,
+        })
+    }
+}
+
+/// A delta or snapshot row that did not decode, or decoded only by
+/// skipping ops, as `bms fsck` reports it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CorruptRow {
+    pub table: &'static str,
+    pub id: String,
+    pub coord_id: String,
+    pub reason: String,
+}
+
+fn corrupt_row(table: &'static str, id: &str, coord_id: &str, source: BmsError) -> BmsError {
+    BmsError::CorruptRow {
+        table,
+        id: id.to_string(),
+        coord_id: coord_id.to_string(),
+        source: Box::new(source),
+    }
+}
+
+/// A SHA3-256 digest as lowercase hex
+fn parse_hash(column: &str, hash: &str) -> Result<Hash, BmsError> {
+    if hash.len() != 64 || !hash.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f')) {
+        return Err(BmsError::InvalidState(format!("{} is not a 64-digit hex hash: {:?}", column, hash)));
+    }
+    Ok(Hash(hash.to_string()))
+}
+
+/// Timestamps as sqlx writes them (RFC 3339) or as `CURRENT_TIMESTAMP` does
+fn parse_timestamp(text: &str) -> Result<DateTime<Utc>, BmsError> {
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(text) {
+        return Ok(timestamp.with_timezone(&Utc));
+    }
+    ["%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M:%S%.f"]
+        .iter()
+        .find_map(|format| chrono::NaiveDateTime::parse_from_str(text, format).ok())
+        .map(|naive| naive.and_utc())
+        .ok_or_else(|| BmsError::InvalidTimestamp(format!("created_at {:?}", text)))
+}
+
+/// Database model for reconstruction checkpoints
+#[derive(Debug, Clone, FromRow)]
+pub struct CheckpointRow {
+    pub coord_id: String,
+    pub seq: i64,
+    pub delta_id: String,
+    pub state_hash: String,
+    pub state: Vec<u8>, // Deflated JSON
+    pub created_at: DateTime<Utc>,
+}
+
+/// State reached partway through replaying a chain
+///
+/// Not part of the verified data: readers must check `delta_id` against the
+/// chain and `state_hash` against `state` before resuming from it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReconstructionCheckpoint {
+    pub coord_id: CoordId,
+    /// Deltas applied to reach `state`, counting from the first
+    pub seq: u64,
+    /// Last delta applied
+    pub delta_id: DeltaId,
+    pub state_hash: Hash,
+    pub state: Value,
+    pub created_at: DateTime<Utc>,
+}
+
+impl ReconstructionCheckpoint {
+    /// `state` as deflated JSON
+    pub fn compressed_state(&self) -> bms_core::Result<Vec<u8>> {
+        deflate_json(&self.state)
+    }
+}
+
+/// Serialize a value as deflated JSON, the form of checkpoint states and blobs
+pub(crate) fn deflate_json(value: &Value) -> bms_core::Result<Vec<u8>> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::fast());
+    serde_json::to_writer(&mut encoder, value)?;
+    Ok(encoder.finish()?)
+}
+
+pub(crate) fn inflate_json(bytes: &[u8]) -> bms_core::Result<Value> {
+    let mut json = Vec::new();
+    DeflateDecoder::new(bytes).read_to_end(&mut json)?;
+    Ok(serde_json::from_slice(&json)?)
+}
+
+impl TryFrom<CheckpointRow> for ReconstructionCheckpoint {
+    type Error = bms_core::error::BmsError;
+
+    fn try_from(row: CheckpointRow) -> Result<Self, Self::Error> {
+        Ok(ReconstructionCheckpoint {
+            coord_id: CoordId(row.coord_id),
+            seq: row.seq as u64,
+            delta_id: DeltaId(row.delta_id),
+            state_hash: Hash(row.state_hash),
+            state: inflate_json(&row.state)?,
             created_at: row.created_at,
         })
     }
 }
+
+/// Read statistics to add for one coordinate
+#[derive(Debug, Clone)]
+pub struct AccessRecord {
+    pub coord_id: CoordId,
+    pub read_count: u64,
+    pub last_read_at: DateTime<Utc>,
+}
+
+/// Database model for access statistics joined with chain length
+#[derive(Debug, Clone, FromRow)]
+pub struct HotCoordRow {
+    pub coord_id: String,
+    pub read_count: i64,
+    pub last_read_at: DateTime<Utc>,
+    pub delta_count: i64,
+}
+
+/// Frequently read coordinate
+#[derive(Debug, Clone, Serialize)]
+pub struct HotCoordinate {
+    pub coord_id: CoordId,
+    pub read_count: u64,
+    pub last_read_at: DateTime<Utc>,
+    pub delta_count: u64,
+}
+
+impl From<HotCoordRow> for HotCoordinate {
+    fn from(row: HotCoordRow) -> Self {
+        HotCoordinate {
+            coord_id: CoordId(row.coord_id),
+            read_count: row.read_count as u64,
+            last_read_at: row.last_read_at,
+            delta_count: row.delta_count as u64,
+        }
+    }
+}
+
+/// Database model for a coordinate reference
+#[derive(Debug, Clone, FromRow)]
+pub struct LinkRow {
+    pub from_coord: String,
+    pub pointer: String,
+    pub to_coord: String,
+}
+
+/// Reference from a pointer in one coordinate's head to another coordinate
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CoordLink {
+    pub from_coord: CoordId,
+    pub pointer: String,
+    pub to_coord: CoordId,
+}
+
+impl From<LinkRow> for CoordLink {
+    fn from(row: LinkRow) -> Self {
+        CoordLink {
+            from_coord: CoordId(row.from_coord),
+            pointer: row.pointer,
+            to_coord: CoordId(row.to_coord),
+        }
+    }
+}
+
+/// Rows describing a coordinate's head, replaced along with its new delta
+#[derive(Debug, Clone, Default)]
+pub struct HeadRows {
+    /// Outgoing links; left as they are when `None`
+    pub links: Option<Vec<Link>>,
+    /// Canonical byte size of the head state; left as it is when `None`
+    pub state_bytes: Option<u64>,
+    /// Head state of a coordinate that materializes it; left as it is when `None`
+    pub materialized: Option<Value>,
+}
+
+/// Database model for a materialized head state
+#[derive(Debug, Clone, FromRow)]
+pub struct HeadStateRow {
+    pub coord_id: String,
+    pub head_delta_id: String,
+    pub chain_hash: String,
+    pub state: Vec<u8>, // Deflated JSON
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Head state kept next to the chain so recalls skip reconstruction
+///
+/// Like a checkpoint it is not verified data: it is only used while
+/// `chain_hash` is the chain hash of the coordinate's head delta.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MaterializedHead {
+    pub coord_id: CoordId,
+    pub head_delta_id: DeltaId,
+    pub chain_hash: Hash,
+    pub state: Value,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl TryFrom<HeadStateRow> for MaterializedHead {
+    type Error = bms_core::error::BmsError;
+
+    fn try_from(row: HeadStateRow) -> Result<Self, Self::Error> {
+        Ok(MaterializedHead {
+            coord_id: CoordId(row.coord_id),
+            head_delta_id: DeltaId(row.head_delta_id),
+            chain_hash: Hash(row.chain_hash),
+            state: inflate_json(&row.state)?,
+            updated_at: row.updated_at,
+        })
+    }
+}
+
+/// Database model for a head state size
+#[derive(Debug, Clone, FromRow)]
+pub struct StateSizeRow {
+    pub coord_id: String,
+    pub head_delta_id: String,
+    pub state_bytes: i64,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Canonical byte size of a coordinate's head state
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StateSize {
+    pub coord_id: CoordId,
+    /// Head the size was measured at
+    pub head_delta_id: DeltaId,
+    pub state_bytes: u64,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl From<StateSizeRow> for StateSize {
+    fn from(row: StateSizeRow) -> Self {
+        StateSize {
+            coord_id: CoordId(row.coord_id),
+            head_delta_id: DeltaId(row.head_delta_id),
+            state_bytes: row.state_bytes as u64,
+            updated_at: row.updated_at,
+        }
+    }
+}
+
+/// Outcome of compacting a coordinate's history
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CompactReport {
+    pub coord_id: CoordId,
+    /// Deltas replaced by the squashed one; 0 when the chain was too short
+    pub squashed: usize,
+    pub kept: usize,
+    /// Ops in the squashed delta
+    pub ops: usize,
+    /// Snapshot of the state the squashed delta produces
+    pub snapshot_id: Option<SnapshotId>,
+}
+
+/// Database model for a saved search
+#[derive(Debug, Clone, FromRow)]
+pub struct SavedSearchRow {
+    pub owner: String,
+    pub name: String,
+    pub request: String,
+    pub webhook_url: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_run_at: Option<DateTime<Utc>>,
+}
+
+/// Search definition saved under a name
+///
+/// `request` is opaque to storage; the API stores its search request body.
+#[derive(Debug, Clone, Serialize)]
+pub struct SavedSearch {
+    /// Scope of the key that saved it
+    pub owner: String,
+    pub name: String,
+    pub request: Value,
+    /// Notified when a periodic run finds new results
+    pub webhook_url: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_run_at: Option<DateTime<Utc>>,
+}
+
+impl From<SavedSearchRow> for SavedSearch {
+    fn from(row: SavedSearchRow) -> Self {
+        SavedSearch {
+            owner: row.owner,
+            name: row.name,
+            request: serde_json::from_str(&row.request).unwrap_or(Value::Null),
+            webhook_url: row.webhook_url,
+            created_at: row.created_at,
+            last_run_at: row.last_run_at,
+        }
+    }
+}
+
+/// Coordinate in a saved search's result set, with the head it had
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SavedResult {
+    pub coord_id: CoordId,
+    pub head_hash: Hash,
+}
+
+/// Database model for per-coordinate storage aggregates
+#[derive(Debug, Clone, FromRow)]
+pub struct CoordStatsRow {
+    pub coord_id: String,
+    pub delta_count: i64,
+    pub ops_bytes: i64,
+    pub replay_deltas: i64,
+    pub replay_ops_bytes: i64,
+    pub full_replace_deltas: i64,
+    pub snapshot_count: i64,
+    pub snapshot_bytes: i64,
+    pub latest_snapshot_bytes: i64,
+    pub latest_snapshot_at: Option<DateTime<Utc>>,
+    pub read_count: i64,
+    pub last_read_at: Option<DateTime<Utc>>,
+    pub importance: f32,
+    pub importance_updated_at: DateTime<Utc>,
+}
+
+/// Storage aggregates for one coordinate, as used by the compaction planner
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CoordStats {
+    pub coord_id: CoordId,
+    /// Deltas in the chain
+    pub delta_count: u64,
+    /// Serialized size of every delta's ops
+    pub ops_bytes: u64,
+    /// Deltas replayed on top of the latest snapshot when reconstructing the head
+    pub replay_deltas: u64,
+    /// Serialized size of the replayed ops
+    pub replay_ops_bytes: u64,
+    /// Deltas that store the whole state because their patch was larger
+    pub full_replace_deltas: u64,
+    pub snapshot_count: u64,
+    /// Serialized size of every stored snapshot state
+    pub snapshot_bytes: u64,
+    pub latest_snapshot_bytes: u64,
+    pub latest_snapshot_at: Option<DateTime<Utc>>,
+    pub read_count: u64,
+    pub last_read_at: Option<DateTime<Utc>>,
+    /// Importance as last set, before decay
+    pub importance: f32,
+    pub importance_updated_at: DateTime<Utc>,
+}
+
+impl From<CoordStatsRow> for CoordStats {
+    fn from(row: CoordStatsRow) -> Self {
+        CoordStats {
+            coord_id: CoordId(row.coord_id),
+            delta_count: row.delta_count as u64,
+            ops_bytes: row.ops_bytes as u64,
+            replay_deltas: row.replay_deltas as u64,
+            replay_ops_bytes: row.replay_ops_bytes as u64,
+            full_replace_deltas: row.full_replace_deltas as u64,
+            snapshot_count: row.snapshot_count as u64,
+            snapshot_bytes: row.snapshot_bytes as u64,
+            latest_snapshot_bytes: row.latest_snapshot_bytes as u64,
+            latest_snapshot_at: row.latest_snapshot_at,
+            read_count: row.read_count as u64,
+            last_read_at: row.last_read_at,
+            importance: row.importance,
+            importance_updated_at: row.importance_updated_at,
+        }
+    }
+}
+
+/// Database model for stored importance
+#[derive(Debug, Clone, FromRow)]
+pub struct ImportanceRow {
+    pub coord_id: String,
+    pub importance: f32,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Importance of a coordinate as last set
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CoordImportance {
+    pub coord_id: CoordId,
+    pub importance: f32,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl CoordImportance {
+    /// Importance at `now` after decay
+    pub fn effective(&self, policy: &ImportancePolicy, now: DateTime<Utc>) -> f32 {
+        policy.effective(self.importance, self.updated_at, now)
+    }
+}
+
+impl From<ImportanceRow> for CoordImportance {
+    fn from(row: ImportanceRow) -> Self {
+        CoordImportance {
+            coord_id: CoordId(row.coord_id),
+            importance: row.importance,
+            updated_at: row.updated_at,
+        }
+    }
+}
+
+/// Database model for the last activity on an ephemeral coordinate
+#[derive(Debug, Clone, FromRow)]
+pub struct EphemeralRow {
+    pub coord_id: String,
+    pub created_at: DateTime<Utc>,
+    pub last_write_at: Option<DateTime<Utc>>,
+    pub last_read_at: Option<DateTime<Utc>>,
+}
+
+/// An ephemeral coordinate and when it was last written or read
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EphemeralCoord {
+    pub coord_id: CoordId,
+    pub last_touched_at: DateTime<Utc>,
+}
+
+impl From<EphemeralRow> for EphemeralCoord {
+    fn from(row: EphemeralRow) -> Self {
+        let written = row.last_write_at.unwrap_or(row.created_at);
+        EphemeralCoord {
+            coord_id: CoordId(row.coord_id),
+            last_touched_at: row.last_read_at.map_or(written, |read| read.max(written)),
+        }
+    }
+}
+
+/// Database model for oplog entries
+#[derive(Debug, Clone, FromRow)]
+pub struct OplogRow {
+    pub lsn: i64,
+    pub op: String,
+    pub coord_id: String,
+    pub ref_id: Option<String>,
+    pub args: Option<String>, // JSON string
+    pub created_at: DateTime<Utc>,
+}
+
+impl TryFrom<OplogRow> for OplogEntry {
+    type Error = bms_core::error::BmsError;
+
+    fn try_from(row: OplogRow) -> Result<Self, Self::Error> {
+        Ok(OplogEntry {
+            lsn: row.lsn,
+            op: row.op.parse()?,
+            coord_id: CoordId(row.coord_id),
+            ref_id: row.ref_id,
+            args: row.args.map(|s| serde_json::from_str(&s)).transpose()?,
+            created_at: row.created_at,
+            payload: None,
+        })
+    }
+}
+
+/// Database model for backup markers
+#[derive(Debug, Clone, FromRow)]
+pub struct BackupMarkerRow {
+    pub id: i64,
+    pub lsn: i64,
+    pub label: Option<String>,
+    pub completed_at: DateTime<Utc>,
+}
+
+impl From<BackupMarkerRow> for BackupMarker {
+    fn from(row: BackupMarkerRow) -> Self {
+        BackupMarker {
+            id: row.id,
+            lsn: row.lsn,
+            label: row.label,
+            completed_at: row.completed_at,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+    use serde_json::json;
+
+    fn delta_row() -> DeltaRow {
+        let ops = json!([
+            {"op": "add", "path": "/a", "value": 1},
+            {"op": "replace", "path": "/b", "value": "x"},
+        ]);
+        let patch: Vec<json_patch::PatchOperation> = serde_json::from_value(ops.clone()).unwrap();
+        let hash = DeltaEngine::hash_delta(&patch).unwrap().0;
+        DeltaRow {
+            id: "d1".to_string(),
+            coord_id: "ROWCOORD".to_string(),
+            parent_id: None,
+            parent_hash: None,
+            delta_hash: hash.clone(),
+            chain_hash: hash,
+            ops: ops.to_string().into_bytes(),
+            created_at: "2026-10-16T20:55:29.247668309+00:00".to_string(),
+            tags: Some(r#"{"k": "v"}"#.to_string()),
+            author: Some("alice".to_string()),
+            op_authors: Some(r#"["alice", "bob"]"#.to_string()),
+            signature_pubkey: None,
+            signature_bytes: None,
+        }
+    }
+
+    fn snapshot_row() -> SnapshotRow {
+        let state = json!({"a": 1, "b": "x"});
+        SnapshotRow {
+            id: "s1".to_string(),
+            coord_id: "ROWCOORD".to_string(),
+            head_delta_id: "d1".to_string(),
+            state_hash: DeltaEngine::hash_state(&state).unwrap().0,
+            state: state.to_string(),
+            created_at: "2026-10-16 20:55:29".to_string(),
+        }
+    }
+
+    /// The table and row an error names, if it is a `CorruptRow`
+    fn named_row(error: &BmsError) -> Option<(&'static str, &str, &str)> {
+        match error {
+            BmsError::CorruptRow { table, id, coord_id, .. } => Some((table, id.as_str(), coord_id.as_str())),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_corrupt_rows_name_their_row() {
+        assert_eq!(Delta::try_from(delta_row()).unwrap().ops.len(), 2);
+        let snapshot = Snapshot::try_from(snapshot_row()).unwrap();
+        assert_eq!(snapshot.created_at.to_rfc3339(), "2026-10-16T20:55:29+00:00");
+
+        let cases = [
+            DeltaRow { ops: br#"[{"op": "add", "path": "/a""#.to_vec(), ..delta_row() },
+            DeltaRow { ops: b"\xa1\x61".to_vec(), ..delta_row() },
+            DeltaRow { delta_hash: delta_row().delta_hash[..40].to_string(), ..delta_row() },
+            DeltaRow { parent_hash: Some("XYZ".to_string()), ..delta_row() },
+            DeltaRow { created_at: "yesterday".to_string(), ..delta_row() },
+            DeltaRow { op_authors: Some("[1, 2]".to_string()), ..delta_row() },
+            DeltaRow { signature_pubkey: Some(vec![0; 32]), ..delta_row() },
+            DeltaRow { signature_pubkey: Some(vec![0; 31]), signature_bytes: Some(vec![0; 64]), ..delta_row() },
+        ];
+        for row in cases {
+            let error = Delta::try_from(row).unwrap_err();
+            assert_eq!(named_row(&error), Some(("deltas", "d1", "ROWCOORD")), "{}", error);
+        }
+        let error = Snapshot::try_from(SnapshotRow { state: "{".to_string(), ..snapshot_row() }).unwrap_err();
+        assert_eq!(named_row(&error), Some(("snapshots", "s1", "ROWCOORD")));
+        assert!(error.to_string().contains("snapshots row s1 of coordinate ROWCOORD"), "{}", error);
+
+        // An op of an unknown type fails the row, or is skipped with its author
+        let unknown = DeltaRow {
+            ops: json!([{"op": "splice", "path": "/a"}, {"op": "remove", "path": "/b"}]).to_string().into_bytes(),
+            ..delta_row()
+        };
+        assert!(named_row(&Delta::try_from(unknown.clone()).unwrap_err()).is_some());
+        let (delta, skipped) = unknown.decode(true).unwrap();
+        assert_eq!((delta.ops.len(), skipped.len()), (1, 1));
+        assert!(skipped[0].starts_with("op 0:"), "{}", skipped[0]);
+        assert_eq!(delta.op_authors, Some(vec!["bob".to_string()]));
+    }
+
+    /// Replace, cut, or garble one character-level piece of `text`
+    fn mangle(rng: &mut ChaCha8Rng, text: &str) -> String {
+        let chars: Vec<char> = text.chars().collect();
+        let at = rng.gen_range(0..=chars.len());
+        let garbage = ["", "\0", "\u{fffd}", "\"", "}", "]", "null", "-1", "9999-99-99", "é", "\\u"];
+        match rng.gen_range(0..5) {
+            0 => chars[..at].iter().collect(),
+            1 => chars[at..].iter().collect(),
+            2 => {
+                let mut out: String = chars[..at].iter().collect();
+                out.push_str(garbage[rng.gen_range(0..garbage.len())]);
+                out.extend(&chars[at..]);
+                out
+            }
+            3 if at < chars.len() => {
+                let mut out = chars.clone();
+                out[at] = char::from(rng.gen_range(0x20u8..0x7f));
+                out.into_iter().collect()
+            }
+            _ => garbage[rng.gen_range(0..garbage.len())].to_string(),
+        }
+    }
+
+    #[test]
+    fn test_mangled_rows_never_panic() {
+        let mut rng = ChaCha8Rng::seed_from_u64(1489);
+        for _ in 0..5000 {
+            let mut row = delta_row();
+            let mut ops = String::from_utf8(row.ops.clone()).unwrap();
+            let field = match rng.gen_range(0..6) {
+                0 => &mut ops,
+                1 => &mut row.delta_hash,
+                2 => &mut row.chain_hash,
+                3 => &mut row.created_at,
+                4 => row.op_authors.as_mut().unwrap(),
+                _ => row.tags.as_mut().unwrap(),
+            };
+            *field = mangle(&mut rng, field);
+            row.ops = ops.into_bytes();
+            for lenient in [false, true] {
+                if let Err(error) = row.clone().decode(lenient) {
+                    assert_eq!(named_row(&error), Some(("deltas", "d1", "ROWCOORD")), "{}", error);
+                }
+            }
+
+            let mut row = snapshot_row();
+            let field = match rng.gen_range(0..3) {
+                0 => &mut row.state,
+                1 => &mut row.state_hash,
+                _ => &mut row.created_at,
+            };
+            *field = mangle(&mut rng, field);
+            if let Err(error) = Snapshot::try_from(row) {
+                assert_eq!(named_row(&error), Some(("snapshots", "s1", "ROWCOORD")), "{}", error);
+            }
+        }
+    }
+}
@@ -0,0 +1,629 @@
+//! Operation log for point-in-time recovery
+//!
+//! Every committed mutation appends an entry to the `oplog` table inside the
+//! mutation's own transaction, so restoring a backup and replaying the entries
+//! after its last LSN reproduces the store. Entries only reference the row
+//! they touched; export resolves the reference into a payload. A row deleted
+//! later exports without a payload, which is harmless because the delete that
+//! removed it is replayed too. Compaction rewrites a whole chain, so its
+//! entry carries the rewritten chain, and the deltas logged before it export
+//! without a payload.
+//!
+//! An export ends with a trailer line holding the entry count and a SHA3-256
+//! of everything before it. `verify_export` checks the trailer, so a file cut
+//! short by a crash or a full disk is rejected before any entry is replayed.
+
+use crate::repository::BmsRepository;
+use bms_core::types::{CoordId, Delta, DeltaId, Hash, Snapshot};
+use bms_core::{BmsError, DeltaEngine, HashAlgorithm, MerkleChain, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha3::{Digest, Sha3_256};
+use std::collections::HashMap;
+use std::io::{BufRead, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+/// Entries fetched per query while exporting
+const EXPORT_PAGE_SIZE: i64 = 1000;
+
+/// Start of the trailer line; entries start with `{"lsn"`
+const TRAILER_PREFIX: &[u8] = b"{\"trailer\"";
+
+/// Kind of mutation recorded in the log
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OpKind {
+    CoordinateCreated,
+    DeltaAppended,
+    SnapshotCreated,
+    /// `args.keep` holds the number of snapshots kept
+    SnapshotsPruned,
+    CoordinateDeleted,
+    /// `args.metadata` holds the coordinate's metadata after the update
+    MetadataUpdated,
+    /// `ref_id` is the head delta after compaction; the payload is the
+    /// rewritten chain up to it
+    CoordinateCompacted,
+}
+
+impl OpKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OpKind::CoordinateCreated => "coordinate_created",
+            OpKind::DeltaAppended => "delta_appended",
+            OpKind::SnapshotCreated => "snapshot_created",
+            OpKind::SnapshotsPruned => "snapshots_pruned",
+            OpKind::CoordinateDeleted => "coordinate_deleted",
+            OpKind::MetadataUpdated => "metadata_updated",
+            OpKind::CoordinateCompacted => "coordinate_compacted",
+        }
+    }
+}
+
+impl FromStr for OpKind {
+    type Err = BmsError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "coordinate_created" => Ok(OpKind::CoordinateCreated),
+            "delta_appended" => Ok(OpKind::DeltaAppended),
+            "snapshot_created" => Ok(OpKind::SnapshotCreated),
+            "snapshots_pruned" => Ok(OpKind::SnapshotsPruned),
+            "coordinate_deleted" => Ok(OpKind::CoordinateDeleted),
+            "metadata_updated" => Ok(OpKind::MetadataUpdated),
+            "coordinate_compacted" => Ok(OpKind::CoordinateCompacted),
+            other => Err(BmsError::InvalidState(format!("Unknown oplog op: {}", other))),
+        }
+    }
+}
+
+/// Entry about to be appended; the LSN is assigned by the database
+#[derive(Debug, Clone)]
+pub(crate) struct OplogRecord {
+    pub op: OpKind,
+    pub coord_id: CoordId,
+    /// Delta or snapshot ID
+    pub ref_id: Option<String>,
+    pub args: Option<Value>,
+}
+
+impl OplogRecord {
+    pub fn new(op: OpKind, coord_id: &CoordId) -> Self {
+        Self {
+            op,
+            coord_id: coord_id.clone(),
+            ref_id: None,
+            args: None,
+        }
+    }
+
+    pub fn delta(delta: &Delta) -> Self {
+        Self {
+            ref_id: Some(delta.id.0.clone()),
+            ..Self::new(OpKind::DeltaAppended, &delta.coord_id)
+        }
+    }
+
+    pub fn snapshot(snapshot: &Snapshot) -> Self {
+        Self {
+            ref_id: Some(snapshot.id.0.clone()),
+            ..Self::new(OpKind::SnapshotCreated, &snapshot.coord_id)
+        }
+    }
+
+    pub fn prune(coord_id: &CoordId, keep: u32) -> Self {
+        Self {
+            args: Some(serde_json::json!({ "keep": keep })),
+            ..Self::new(OpKind::SnapshotsPruned, coord_id)
+        }
+    }
+
+    pub fn metadata(coord_id: &CoordId, metadata: &HashMap<String, Value>) -> Self {
+        Self {
+            args: Some(serde_json::json!({ "metadata": metadata })),
+            ..Self::new(OpKind::MetadataUpdated, coord_id)
+        }
+    }
+
+    pub fn compacted(head: &Delta, keep_last: usize) -> Self {
+        Self {
+            ref_id: Some(head.id.0.clone()),
+            args: Some(serde_json::json!({ "keep_last": keep_last })),
+            ..Self::new(OpKind::CoordinateCompacted, &head.coord_id)
+        }
+    }
+}
+
+/// One logged mutation, as exported
+///
+/// `payload` is the created coordinate, delta, or snapshot; it is absent in
+/// the table itself and for rows that no longer exist.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OplogEntry {
+    pub lsn: i64,
+    pub op: OpKind,
+    pub coord_id: CoordId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ref_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub args: Option<Value>,
+    pub created_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload: Option<Value>,
+}
+
+impl OplogEntry {
+    /// `keep` argument of a `SnapshotsPruned` entry
+    pub fn keep(&self) -> Result<u32> {
+        self.args
+            .as_ref()
+            .and_then(|a| a.get("keep"))
+            .and_then(Value::as_u64)
+            .map(|k| k as u32)
+            .ok_or_else(|| {
+                BmsError::InvalidState(format!("Oplog entry {} has no keep argument", self.lsn))
+            })
+    }
+
+    /// `metadata` argument of a `MetadataUpdated` entry
+    pub fn metadata(&self) -> Result<HashMap<String, Value>> {
+        let metadata = self.args.as_ref().and_then(|a| a.get("metadata")).ok_or_else(|| {
+            BmsError::InvalidState(format!("Oplog entry {} has no metadata argument", self.lsn))
+        })?;
+        Ok(serde_json::from_value(metadata.clone())?)
+    }
+}
+
+/// Completed backup and the last LSN it contains
+#[derive(Debug, Clone, Serialize)]
+pub struct BackupMarker {
+    pub id: i64,
+    pub lsn: i64,
+    pub label: Option<String>,
+    pub completed_at: DateTime<Utc>,
+}
+
+/// Last line of an export
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportTrailer {
+    pub entries: u64,
+    /// SHA3-256 of every byte before the trailer line
+    pub sha3: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TrailerLine {
+    trailer: ExportTrailer,
+}
+
+/// Outcome of replaying an oplog stream
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ApplyReport {
+    pub applied: u64,
+    /// Entries whose LSN was already present
+    pub skipped: u64,
+    /// Entries applied without a payload because the row was deleted later
+    pub without_payload: u64,
+    pub last_lsn: Option<i64>,
+}
+
+/// Write entries after `since_lsn` as NDJSON, then the trailer, returning
+/// how many entries were written
+pub async fn export<W: Write>(repository: &BmsRepository, since_lsn: i64, out: &mut W) -> Result<u64> {
+    let mut written = 0;
+    let mut hasher = Sha3_256::new();
+    let mut after = since_lsn;
+    loop {
+        let entries = repository.get_oplog(after, EXPORT_PAGE_SIZE).await?;
+        let Some(last) = entries.last() else {
+            break;
+        };
+        after = last.lsn;
+
+        for mut entry in entries {
+            entry.payload = repository.oplog_payload(&entry).await?;
+            let mut line = serde_json::to_vec(&entry)?;
+            line.push(b'\n');
+            hasher.update(&line);
+            out.write_all(&line)?;
+            written += 1;
+        }
+    }
+
+    let trailer = TrailerLine {
+        trailer: ExportTrailer {
+            entries: written,
+            sha3: format!("{:x}", hasher.finalize()),
+        },
+    };
+    serde_json::to_writer(&mut *out, &trailer)?;
+    out.write_all(b"\n")?;
+    out.flush()?;
+    Ok(written)
+}
+
+/// Check that an export at `path` is complete without applying it
+///
+/// Fails with `BmsError::PartialFile` when the trailer is missing, does not
+/// match the entries before it, or is followed by more data.
+pub fn verify_export(path: &Path) -> Result<ExportTrailer> {
+    let partial = |reason: String| BmsError::PartialFile {
+        path: path.display().to_string(),
+        reason,
+    };
+    let mut input = std::io::BufReader::new(std::fs::File::open(path)?);
+    let mut hasher = Sha3_256::new();
+    let mut entries = 0u64;
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        if input.read_until(b'\n', &mut line)? == 0 {
+            return Err(partial(format!("no trailer after {} entries; the export did not finish", entries)));
+        }
+        if line.starts_with(TRAILER_PREFIX) {
+            let TrailerLine { trailer } = serde_json::from_slice(&line)
+                .map_err(|e| partial(format!("unreadable trailer: {}", e)))?;
+            let mut rest = Vec::new();
+            input.read_to_end(&mut rest)?;
+            if !rest.iter().all(u8::is_ascii_whitespace) {
+                return Err(partial("data after the trailer".to_string()));
+            }
+            let sha3 = format!("{:x}", hasher.finalize());
+            if trailer.entries != entries || trailer.sha3 != sha3 {
+                return Err(partial(format!(
+                    "trailer lists {} entries with checksum {}, found {} with {}",
+                    trailer.entries, trailer.sha3, entries, sha3
+                )));
+            }
+            return Ok(trailer);
+        }
+        hasher.update(&line);
+        if !line.trim_ascii().is_empty() {
+            entries += 1;
+        }
+    }
+}
+
+/// Replay an NDJSON stream produced by `export`
+///
+/// Safe to run repeatedly: entries whose LSN is already in the log are
+/// skipped, and each applied entry is logged under its original LSN. The
+/// trailer ends the stream but is not checked; run `verify_export` first.
+pub async fn apply<R: BufRead>(repository: &BmsRepository, input: R) -> Result<ApplyReport> {
+    let mut report = ApplyReport::default();
+    for line in input.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if line.as_bytes().starts_with(TRAILER_PREFIX) {
+            break;
+        }
+        let entry: OplogEntry = serde_json::from_str(&line)?;
+
+        if repository.apply_oplog_entry(&entry).await? {
+            report.applied += 1;
+            if entry.payload.is_none() && needs_payload(entry.op) {
+                report.without_payload += 1;
+            }
+        } else {
+            report.skipped += 1;
+        }
+        report.last_lsn = Some(entry.lsn);
+    }
+    Ok(report)
+}
+
+/// Head chain hashes of up to `count` coordinates, spread over the ID order
+///
+/// A standby sends these to its source before replicating, for
+/// `diverged_heads` to check.
+pub async fn sample_heads(repository: &BmsRepository, count: usize) -> Result<Vec<(CoordId, Hash)>> {
+    let ids = repository.list_coordinate_ids().await?;
+    let step = ids.len().div_ceil(count.max(1)).max(1);
+    let mut heads = Vec::new();
+    for coord_id in ids.into_iter().step_by(step) {
+        if let Some(head) = repository.get_deltas(&coord_id).await?.pop() {
+            heads.push((coord_id, head.chain_hash));
+        }
+    }
+    Ok(heads)
+}
+
+/// Coordinates whose head on a standby at `lsn` is not in this store's chain
+///
+/// The source may have moved on since `lsn`, so a head only has to be part
+/// of the chain here, not its end. A coordinate deleted after `lsn` matches
+/// whatever head the standby has, since the delete is still to be replayed.
+pub async fn diverged_heads(
+    repository: &BmsRepository,
+    lsn: i64,
+    heads: &[(CoordId, Hash)],
+) -> Result<Vec<CoordId>> {
+    let mut diverged = Vec::new();
+    for (coord_id, chain_hash) in heads {
+        if repository.has_chain_hash(coord_id, chain_hash).await?
+            || repository.deleted_after(coord_id, lsn).await?
+            || repository.compacted_after(coord_id, lsn).await?
+        {
+            continue;
+        }
+        diverged.push(coord_id.clone());
+    }
+    Ok(diverged)
+}
+
+pub(crate) fn needs_payload(op: OpKind) -> bool {
+    matches!(
+        op,
+        OpKind::CoordinateCreated
+            | OpKind::DeltaAppended
+            | OpKind::SnapshotCreated
+            | OpKind::CoordinateCompacted
+    )
+}
+
+/// Check that a replayed delta extends the current head of its chain, which
+/// is hashed with `algorithm`, and, if signed, that its signature holds
+pub(crate) fn verify_append(head: Option<(DeltaId, Hash)>, delta: &Delta, algorithm: HashAlgorithm) -> Result<()> {
+    let broken = || BmsError::MerkleChainBroken {
+        delta_id: delta.id.0.clone(),
+    };
+
+    if DeltaEngine::hash_delta(&delta.ops)? != delta.delta_hash {
+        return Err(broken());
+    }
+    let expected_chain_hash = match (&head, &delta.parent_id, &delta.parent_hash) {
+        (None, None, None) => delta.delta_hash.clone(),
+        (Some((head_id, head_hash)), Some(parent_id), Some(parent_hash))
+            if head_id == parent_id && head_hash == parent_hash =>
+        {
+            MerkleChain::compute_chain_hash_with(parent_hash, &delta.delta_hash, algorithm)
+        }
+        _ => return Err(broken()),
+    };
+    if expected_chain_hash != delta.chain_hash {
+        return Err(broken());
+    }
+    if let Some(signature) = &delta.signature {
+        DeltaEngine::verify_signature(&delta.ops, signature)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDb;
+    use crate::{BmsFacade, StoreParams};
+    use serde_json::json;
+    use std::io::Cursor;
+
+    async fn store(facade: &BmsFacade, coord: &str, n: usize) {
+        facade
+            .store(StoreParams {
+                coord_id: Some(CoordId(coord.to_string())),
+                state: json!({"n": format!("{}-{}", coord, n)}),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    }
+
+    async fn verify_all(repository: &BmsRepository) {
+        for coord in repository.list_coordinates(None, None).await.unwrap() {
+            let deltas = repository.get_deltas(&coord.id).await.unwrap();
+            let (_, error) = MerkleChain::verify_chain_integrity_with(&deltas, coord.hash_algorithm);
+            assert!(error.is_none(), "{}: {:?}", coord.id, error);
+        }
+    }
+
+    async fn sorted_stats(repository: &BmsRepository) -> Vec<crate::models::CoordStats> {
+        let mut stats = repository.get_coord_stats(None).await.unwrap();
+        stats.sort_by(|a, b| a.coord_id.0.cmp(&b.coord_id.0));
+        stats
+    }
+
+    #[tokio::test]
+    async fn test_restore_backup_and_replay() {
+        let original = TempDb::new("oplog-original");
+        let backup = TempDb::new("oplog-backup");
+        let facade = original.facade(3).await;
+
+        for n in 0..4 {
+            store(&facade, "KEEP", n).await;
+            store(&facade, "GONE", n).await;
+        }
+        original
+            .execute(&format!("VACUUM INTO '{}'", backup.path.display()))
+            .await;
+        let backup_lsn = facade.repository().max_lsn().await.unwrap();
+
+        // Mutations after the backup: appends, snapshots, a prune, a delete
+        for n in 4..9 {
+            store(&facade, "KEEP", n).await;
+            store(&facade, "NEW", n).await;
+        }
+        facade.create_snapshot(&CoordId("NEW".to_string())).await.unwrap();
+        facade.prune_snapshots(&CoordId("KEEP".to_string()), 1).await.unwrap();
+        let patch = HashMap::from([("project".to_string(), json!("apollo"))]);
+        facade.patch_metadata(&CoordId("KEEP".to_string()), patch).await.unwrap();
+        facade.delete_coordinate(&CoordId("GONE".to_string())).await.unwrap();
+
+        let mut stream = Vec::new();
+        let exported = export(facade.repository(), backup_lsn, &mut stream).await.unwrap();
+        assert!(exported > 0);
+
+        let restored = backup.repository().await;
+        let report = apply(&restored, Cursor::new(&stream)).await.unwrap();
+        assert_eq!(report.applied, exported);
+        assert_eq!(report.skipped, 0);
+
+        assert_eq!(sorted_stats(&restored).await, sorted_stats(facade.repository()).await);
+        assert_eq!(restored.max_lsn().await.unwrap(), facade.repository().max_lsn().await.unwrap());
+        let keep = CoordId("KEEP".to_string());
+        let metadata = restored.get_coordinate(&keep).await.unwrap().unwrap().metadata;
+        assert_eq!(metadata.unwrap()["project"], json!("apollo"));
+        verify_all(&restored).await;
+
+        // Replaying again changes nothing
+        let again = apply(&restored, Cursor::new(&stream)).await.unwrap();
+        assert_eq!(again.applied, 0);
+        assert_eq!(again.skipped, exported);
+        assert_eq!(sorted_stats(&restored).await, sorted_stats(facade.repository()).await);
+    }
+
+    #[tokio::test]
+    async fn test_truncated_export_is_rejected_before_replay() {
+        let db = TempDb::new("oplog-truncated");
+        let facade = db.facade(3).await;
+        for n in 0..3 {
+            store(&facade, "CUT", n).await;
+        }
+        let path = db.path.with_extension("ndjson");
+        let mut out = bms_core::AtomicFile::create(&path).unwrap();
+        let exported = export(facade.repository(), 0, &mut out).await.unwrap();
+        out.commit().unwrap();
+        assert_eq!(verify_export(&path).unwrap().entries, exported);
+
+        // A crash mid-write loses the trailer and part of the last entry
+        let full = std::fs::read(&path).unwrap();
+        let trailer_at = full[..full.len() - 1].iter().rposition(|&b| b == b'\n').unwrap() + 1;
+        for cut in [trailer_at, trailer_at - 10] {
+            std::fs::write(&path, &full[..cut]).unwrap();
+            let err = verify_export(&path).unwrap_err();
+            assert!(matches!(err, BmsError::PartialFile { .. }), "{}", err);
+            assert!(err.to_string().starts_with(&format!("{} is truncated or partial", path.display())));
+        }
+
+        // The trailer catches an entry lost from the middle too
+        let lines: Vec<&[u8]> = full.split_inclusive(|&b| b == b'\n').collect();
+        std::fs::write(&path, [lines[0], &lines[2..].concat()].concat()).unwrap();
+        assert!(verify_export(&path).unwrap_err().to_string().contains("trailer lists"));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_timestamps_and_op_authors_round_trip() {
+        let original = TempDb::new("oplog-override");
+        let replica = TempDb::new("oplog-override-replica");
+        let facade = original.facade(2).await;
+        let coord = CoordId("HISTORY".to_string());
+        for n in 0..3 {
+            facade
+                .store(StoreParams {
+                    coord_id: Some(coord.clone()),
+                    state: json!({"n": n}),
+                    created_at: chrono::DateTime::from_timestamp(1_500_000_000 + n * 86_400, 0),
+                    op_authors: Some(vec![format!("agent-{}", n)]),
+                    ..Default::default()
+                })
+                .await
+                .unwrap();
+        }
+
+        let mut stream = Vec::new();
+        export(facade.repository(), 0, &mut stream).await.unwrap();
+        let restored = replica.repository().await;
+        apply(&restored, Cursor::new(&stream)).await.unwrap();
+
+        let times = |deltas: Vec<bms_core::types::Delta>| -> Vec<_> {
+            deltas.into_iter().map(|d| d.created_at).collect()
+        };
+        let original_times = times(facade.repository().get_deltas(&coord).await.unwrap());
+        assert_eq!(original_times[0].timestamp(), 1_500_000_000);
+        assert_eq!(times(restored.get_deltas(&coord).await.unwrap()), original_times);
+        let authors = |deltas: Vec<bms_core::types::Delta>| -> Vec<_> {
+            deltas.into_iter().map(|d| d.op_authors).collect()
+        };
+        assert_eq!(
+            authors(restored.get_deltas(&coord).await.unwrap()),
+            authors(facade.repository().get_deltas(&coord).await.unwrap())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_compaction_replays_from_backup_and_scratch() {
+        let original = TempDb::new("oplog-compact");
+        let backup = TempDb::new("oplog-compact-backup");
+        let scratch = TempDb::new("oplog-compact-scratch");
+        let facade = original.facade(2).await;
+        let coord = CoordId("SQUASH".to_string());
+        for n in 0..3 {
+            store(&facade, "SQUASH", n).await;
+        }
+        original
+            .execute(&format!("VACUUM INTO '{}'", backup.path.display()))
+            .await;
+        let backup_lsn = facade.repository().max_lsn().await.unwrap();
+
+        for n in 3..7 {
+            store(&facade, "SQUASH", n).await;
+        }
+        facade.compact(&coord, 2).await.unwrap();
+        store(&facade, "SQUASH", 7).await;
+        facade.compact(&coord, 1).await.unwrap();
+        store(&facade, "SQUASH", 8).await;
+
+        for (target, after) in [(backup.repository().await, backup_lsn), (scratch.repository().await, 0)] {
+            let mut stream = Vec::new();
+            export(facade.repository(), after, &mut stream).await.unwrap();
+            apply(&target, Cursor::new(&stream)).await.unwrap();
+            let chain = |deltas: Vec<Delta>| -> Vec<_> { deltas.into_iter().map(|d| (d.id, d.chain_hash)).collect() };
+            assert_eq!(
+                chain(target.get_deltas(&coord).await.unwrap()),
+                chain(facade.repository().get_deltas(&coord).await.unwrap())
+            );
+            assert_eq!(sorted_stats(&target).await, sorted_stats(facade.repository()).await);
+            verify_all(&target).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replay_rejects_broken_chain() {
+        let source = TempDb::new("oplog-source");
+        let target = TempDb::new("oplog-target");
+        let facade = source.facade(128).await;
+        for n in 0..3 {
+            store(&facade, "CHAIN", n).await;
+        }
+
+        let mut stream = Vec::new();
+        export(facade.repository(), 0, &mut stream).await.unwrap();
+        // Drop the first delta so the second no longer extends the head
+        let lines: Vec<&str> = std::str::from_utf8(&stream).unwrap().lines().collect();
+        let tampered: String = lines
+            .iter()
+            .filter(|l| !l.contains("\"lsn\":2,"))
+            .map(|l| format!("{}\n", l))
+            .collect();
+
+        let restored = target.repository().await;
+        let result = apply(&restored, Cursor::new(tampered)).await;
+        assert!(matches!(result, Err(BmsError::MerkleChainBroken { .. })), "{:?}", result);
+    }
+
+    #[tokio::test]
+    async fn test_trim_keeps_entries_after_retained_backups() {
+        let db = TempDb::new("oplog-trim");
+        let facade = db.facade(128).await;
+        let repository = facade.repository();
+
+        store(&facade, "TRIM", 0).await;
+        let first = repository.record_backup_marker(Some("first")).await.unwrap();
+        store(&facade, "TRIM", 1).await;
+        let second = repository.record_backup_marker(None).await.unwrap();
+        store(&facade, "TRIM", 2).await;
+
+        assert_eq!(repository.trim_oplog(2).await.unwrap(), first.lsn as u64);
+        assert_eq!(repository.get_oplog(0, 100).await.unwrap()[0].lsn, first.lsn + 1);
+
+        repository.trim_oplog(1).await.unwrap();
+        let remaining = repository.get_oplog(0, 100).await.unwrap();
+        assert_eq!(remaining.len(), 1);
+        assert!(remaining[0].lsn > second.lsn);
+    }
+}
@@ -0,0 +1,503 @@
+//! Compaction planner
+//!
+//! Turns per-coordinate storage aggregates into a ranked list of maintenance
+//! actions. Estimates come from a deliberately simple cost model over
+//! `CoordStats` (all sizes in MiB, times in milliseconds):
+//!
+//! ```text
+//! reconstruction = load_ms_per_mib × ops + replay_ms_per_delta × replay_deltas
+//!                + replay_ms_per_mib × replay_ops
+//! snapshot       : replay_deltas, replay_ops → 0
+//!                  costs one state, sized as the latest snapshot (or replay_ops if none)
+//! prune          : saves snapshot_bytes − latest_snapshot_bytes, reconstruction unchanged
+//! score          = ms_saved × read_weight + mib_saved × ms_per_mib_saved
+//! read_weight    = 1 + ln(1 + read_count) / (1 + days since last read)
+//! ```
+//!
+//! Reading the head loads the whole chain but only replays the deltas after the
+//! latest snapshot, hence the two terms. Coordinates whose decayed importance
+//! is below `importance_floor` get no snapshot recommendations; pruning still
+//! applies to them. Squashing a chain prefix is not offered
+//! because the store has no way to rewrite history yet.
+
+use crate::facade::BmsFacade;
+use crate::models::CoordStats;
+use bms_core::types::CoordId;
+use bms_core::{BmsError, ImportancePolicy, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+
+const MIB: f64 = 1024.0 * 1024.0;
+
+/// Head reads timed before and after applying an action
+const LATENCY_SAMPLES: usize = 3;
+
+/// Coefficients for the planner's estimates
+#[derive(Debug, Clone)]
+pub struct CostModel {
+    /// Time to fetch and decode one MiB of stored ops
+    pub load_ms_per_mib: f64,
+    /// Fixed cost of applying one delta
+    pub replay_ms_per_delta: f64,
+    /// Time to apply one MiB of ops
+    pub replay_ms_per_mib: f64,
+    /// Score credited per MiB reclaimed, in milliseconds of saved reconstruction
+    pub ms_per_mib_saved: f64,
+    /// Smallest replay tail worth a snapshot
+    pub min_replay_deltas: u64,
+    /// Effective importance below which a coordinate is not worth a snapshot
+    pub importance_floor: f32,
+    /// Decay applied to stored importance before comparing it to the floor
+    pub importance: ImportancePolicy,
+}
+
+impl Default for CostModel {
+    fn default() -> Self {
+        Self {
+            load_ms_per_mib: 8.0,
+            replay_ms_per_delta: 0.05,
+            replay_ms_per_mib: 40.0,
+            ms_per_mib_saved: 1.0,
+            min_replay_deltas: 32,
+            importance_floor: 0.0,
+            importance: ImportancePolicy::default(),
+        }
+    }
+}
+
+impl CostModel {
+    /// Estimated head reconstruction time for a coordinate
+    pub fn reconstruction_ms(&self, stats: &CoordStats) -> f64 {
+        self.load_ms_per_mib * stats.ops_bytes as f64 / MIB
+            + self.replay_ms_per_delta * stats.replay_deltas as f64
+            + self.replay_ms_per_mib * stats.replay_ops_bytes as f64 / MIB
+    }
+
+    fn read_weight(&self, stats: &CoordStats, now: DateTime<Utc>) -> f64 {
+        let Some(last_read_at) = stats.last_read_at else {
+            return 1.0;
+        };
+        let days = (now - last_read_at).num_seconds().max(0) as f64 / 86_400.0;
+        1.0 + (stats.read_count as f64).ln_1p() / (1.0 + days)
+    }
+}
+
+/// Machine-readable maintenance action
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum PlanAction {
+    /// Snapshot the current head so reads stop replaying the tail
+    Snapshot { coord_id: CoordId },
+    /// Delete all but the newest `keep` snapshots
+    PruneSnapshots { coord_id: CoordId, keep: u32 },
+}
+
+impl PlanAction {
+    pub fn coord_id(&self) -> &CoordId {
+        match self {
+            PlanAction::Snapshot { coord_id } | PlanAction::PruneSnapshots { coord_id, .. } => {
+                coord_id
+            }
+        }
+    }
+
+    /// CLI invocation that applies this action
+    pub fn command(&self) -> String {
+        match self {
+            PlanAction::Snapshot { coord_id } => {
+                format!("bms plan --apply snapshot --coord {}", coord_id)
+            }
+            PlanAction::PruneSnapshots { coord_id, keep } => {
+                format!("bms plan --apply prune-snapshots --coord {} --keep {}", coord_id, keep)
+            }
+        }
+    }
+
+    /// API request that applies this action
+    pub fn api_call(&self) -> String {
+        format!(
+            "POST /admin/plan/apply {}",
+            serde_json::to_string(self).unwrap_or_default()
+        )
+    }
+
+    /// Estimated effect of this action on a coordinate with the given stats
+    pub fn estimate(&self, stats: &CoordStats, model: &CostModel) -> Estimate {
+        let before = model.reconstruction_ms(stats);
+        match self {
+            PlanAction::Snapshot { .. } => {
+                let after = CoordStats {
+                    replay_deltas: 0,
+                    replay_ops_bytes: 0,
+                    ..stats.clone()
+                };
+                let state_bytes = match stats.latest_snapshot_bytes {
+                    0 => stats.replay_ops_bytes,
+                    bytes => bytes,
+                };
+                Estimate {
+                    bytes_saved: -(state_bytes as i64),
+                    reconstruction_ms_before: before,
+                    reconstruction_ms_after: model.reconstruction_ms(&after),
+                }
+            }
+            PlanAction::PruneSnapshots { keep, .. } => {
+                // Dropped snapshots are assumed to be of average size
+                let dropped = stats.snapshot_count.saturating_sub(u64::from(*keep));
+                let older = stats.snapshot_count.saturating_sub(1).max(1);
+                let older_bytes = stats.snapshot_bytes.saturating_sub(stats.latest_snapshot_bytes);
+                Estimate {
+                    bytes_saved: (older_bytes * dropped.min(older) / older) as i64,
+                    reconstruction_ms_before: before,
+                    reconstruction_ms_after: before,
+                }
+            }
+        }
+    }
+}
+
+/// Estimated or measured effect of an action
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Estimate {
+    /// Bytes reclaimed; negative when the action stores more
+    pub bytes_saved: i64,
+    pub reconstruction_ms_before: f64,
+    pub reconstruction_ms_after: f64,
+}
+
+impl Estimate {
+    pub fn ms_saved(&self) -> f64 {
+        self.reconstruction_ms_before - self.reconstruction_ms_after
+    }
+}
+
+/// One ranked entry of the plan
+#[derive(Debug, Clone, Serialize)]
+pub struct Recommendation {
+    #[serde(flatten)]
+    pub action: PlanAction,
+    pub summary: String,
+    pub estimate: Estimate,
+    pub score: f64,
+    pub command: String,
+    pub api_call: String,
+}
+
+/// Estimates next to measurements after applying an action
+#[derive(Debug, Clone, Serialize)]
+pub struct AppliedAction {
+    #[serde(flatten)]
+    pub action: PlanAction,
+    pub estimate: Estimate,
+    pub actual: Estimate,
+    pub before: CoordStats,
+    pub after: CoordStats,
+}
+
+/// Recommend and rank actions for every coordinate, best first
+pub fn plan(stats: &[CoordStats], model: &CostModel, now: DateTime<Utc>) -> Vec<Recommendation> {
+    let mut recommendations: Vec<Recommendation> = stats
+        .iter()
+        .flat_map(|s| recommend(s, model, now))
+        .collect();
+
+    recommendations.sort_by(|a, b| {
+        b.score
+            .total_cmp(&a.score)
+            .then_with(|| a.action.coord_id().0.cmp(&b.action.coord_id().0))
+    });
+    recommendations
+}
+
+fn recommend(stats: &CoordStats, model: &CostModel, now: DateTime<Utc>) -> Vec<Recommendation> {
+    let mut actions = Vec::new();
+    let importance = model
+        .importance
+        .effective(stats.importance, stats.importance_updated_at, now);
+    if stats.replay_deltas >= model.min_replay_deltas && importance >= model.importance_floor {
+        actions.push(PlanAction::Snapshot {
+            coord_id: stats.coord_id.clone(),
+        });
+    }
+    if stats.snapshot_count > 1 {
+        actions.push(PlanAction::PruneSnapshots {
+            coord_id: stats.coord_id.clone(),
+            keep: 1,
+        });
+    }
+
+    let read_weight = model.read_weight(stats, now);
+    actions
+        .into_iter()
+        .map(|action| {
+            let estimate = action.estimate(stats, model);
+            let score = estimate.ms_saved() * read_weight
+                + estimate.bytes_saved as f64 / MIB * model.ms_per_mib_saved;
+            Recommendation {
+                summary: summarize(&action, stats, &estimate),
+                command: action.command(),
+                api_call: action.api_call(),
+                action,
+                estimate,
+                score,
+            }
+        })
+        .collect()
+}
+
+fn summarize(action: &PlanAction, stats: &CoordStats, estimate: &Estimate) -> String {
+    match action {
+        PlanAction::Snapshot { coord_id } => format!(
+            "snapshot {}: stop replaying {} deltas, reconstruction {:.1}ms → est {:.1}ms (costs ~{})",
+            coord_id,
+            stats.replay_deltas,
+            estimate.reconstruction_ms_before,
+            estimate.reconstruction_ms_after,
+            format_bytes(-estimate.bytes_saved),
+        ),
+        PlanAction::PruneSnapshots { coord_id, keep } => format!(
+            "prune snapshots on {}: keep {} of {}, ~{}",
+            coord_id,
+            keep,
+            stats.snapshot_count,
+            format_bytes(estimate.bytes_saved),
+        ),
+    }
+}
+
+fn format_bytes(bytes: i64) -> String {
+    let magnitude = bytes.unsigned_abs() as f64;
+    let sign = if bytes < 0 { "-" } else { "" };
+    if magnitude >= MIB {
+        format!("{}{:.1}MB", sign, magnitude / MIB)
+    } else if magnitude >= 1024.0 {
+        format!("{}{:.1}KB", sign, magnitude / 1024.0)
+    } else {
+        format!("{}{}B", sign, magnitude)
+    }
+}
+
+/// Build the plan from the current store
+pub async fn plan_store(facade: &BmsFacade, model: &CostModel) -> Result<Vec<Recommendation>> {
+    let stats = facade.repository().get_coord_stats(None).await?;
+    Ok(plan(&stats, model, Utc::now()))
+}
+
+/// Apply an action, then re-run the analysis for its coordinate
+///
+/// Reconstruction times in `actual` are the median of a few timed head reads.
+pub async fn apply(facade: &BmsFacade, action: &PlanAction, model: &CostModel) -> Result<AppliedAction> {
+    let coord_id = action.coord_id();
+    let before = coord_stats(facade, coord_id).await?;
+    let estimate = action.estimate(&before, model);
+    let ms_before = time_head(facade, coord_id).await?;
+
+    match action {
+        PlanAction::Snapshot { .. } => {
+            facade.create_snapshot(coord_id).await?;
+        }
+        PlanAction::PruneSnapshots { keep, .. } => {
+            facade.prune_snapshots(coord_id, *keep).await?;
+        }
+    }
+
+    let after = coord_stats(facade, coord_id).await?;
+    let ms_after = time_head(facade, coord_id).await?;
+    let stored = |s: &CoordStats| (s.ops_bytes + s.snapshot_bytes) as i64;
+
+    Ok(AppliedAction {
+        action: action.clone(),
+        estimate,
+        actual: Estimate {
+            bytes_saved: stored(&before) - stored(&after),
+            reconstruction_ms_before: ms_before,
+            reconstruction_ms_after: ms_after,
+        },
+        before,
+        after,
+    })
+}
+
+async fn coord_stats(facade: &BmsFacade, coord_id: &CoordId) -> Result<CoordStats> {
+    facade
+        .repository()
+        .get_coord_stats(Some(coord_id))
+        .await?
+        .pop()
+        .ok_or_else(|| BmsError::InvalidCoordinate(format!("Unknown coordinate: {}", coord_id)))
+}
+
+async fn time_head(facade: &BmsFacade, coord_id: &CoordId) -> Result<f64> {
+    let mut samples = Vec::with_capacity(LATENCY_SAMPLES);
+    for _ in 0..LATENCY_SAMPLES {
+        let started = Instant::now();
+        facade.head(coord_id).await?;
+        samples.push(started.elapsed().as_secs_f64() * 1000.0);
+    }
+    samples.sort_by(f64::total_cmp);
+    Ok(samples[samples.len() / 2])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDb;
+    use crate::StoreParams;
+    use bms_core::importance::DEFAULT_IMPORTANCE;
+    use chrono::Duration;
+    use serde_json::json;
+
+    fn stats(id: &str, replay_deltas: u64) -> CoordStats {
+        CoordStats {
+            coord_id: CoordId(id.to_string()),
+            delta_count: replay_deltas,
+            ops_bytes: replay_deltas * 2048,
+            replay_deltas,
+            replay_ops_bytes: replay_deltas * 2048,
+            full_replace_deltas: 0,
+            snapshot_count: 0,
+            snapshot_bytes: 0,
+            latest_snapshot_bytes: 0,
+            latest_snapshot_at: None,
+            read_count: 0,
+            last_read_at: None,
+            importance: DEFAULT_IMPORTANCE,
+            importance_updated_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_ranking_prefers_long_hot_tails() {
+        let now = Utc::now();
+        let short = stats("SHORT", 10);
+        let cold = stats("COLD", 4000);
+        let hot = CoordStats {
+            read_count: 500,
+            last_read_at: Some(now - Duration::minutes(5)),
+            ..stats("HOT", 4000)
+        };
+        let stale = CoordStats {
+            read_count: 500,
+            last_read_at: Some(now - Duration::days(90)),
+            ..stats("STALE", 4000)
+        };
+
+        let ranked = plan(&[short, cold, stale, hot], &CostModel::default(), now);
+        let order: Vec<&str> = ranked.iter().map(|r| r.action.coord_id().0.as_str()).collect();
+
+        // SHORT is below the replay threshold; reads only boost recent coordinates
+        assert_eq!(order, ["HOT", "STALE", "COLD"]);
+        assert!(ranked[0].estimate.ms_saved() > 0.0);
+        assert_eq!(ranked[0].estimate.bytes_saved, -(4000 * 2048));
+        assert_eq!(ranked[0].command, "bms plan --apply snapshot --coord HOT");
+    }
+
+    #[test]
+    fn test_importance_floor_skips_snapshots() {
+        let now = Utc::now();
+        let model = CostModel {
+            importance_floor: 0.2,
+            ..CostModel::default()
+        };
+        let faded = CoordStats {
+            snapshot_count: 3,
+            snapshot_bytes: 3 * 1024 * 1024,
+            latest_snapshot_bytes: 1024 * 1024,
+            importance_updated_at: now - Duration::days(21),
+            ..stats("FADED", 4000)
+        };
+
+        // Three half-lives take the default importance to 0.0625
+        let ranked = plan(&[faded.clone(), stats("KEPT", 4000)], &model, now);
+        let actions: Vec<_> = ranked.iter().map(|r| &r.action).collect();
+        assert_eq!(
+            actions,
+            [
+                &PlanAction::Snapshot { coord_id: CoordId("KEPT".to_string()) },
+                &PlanAction::PruneSnapshots { coord_id: CoordId("FADED".to_string()), keep: 1 },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_prune_estimates_reclaimed_snapshots() {
+        let model = CostModel::default();
+        let single = CoordStats {
+            snapshot_count: 1,
+            snapshot_bytes: 10 * 1024,
+            latest_snapshot_bytes: 10 * 1024,
+            ..stats("ONE", 0)
+        };
+        let many = CoordStats {
+            snapshot_count: 5,
+            snapshot_bytes: 50 * 1024 * 1024,
+            latest_snapshot_bytes: 10 * 1024 * 1024,
+            ..stats("MANY", 0)
+        };
+        let few = CoordStats {
+            snapshot_count: 2,
+            snapshot_bytes: 2 * 1024 * 1024,
+            latest_snapshot_bytes: 1024 * 1024,
+            ..stats("FEW", 0)
+        };
+
+        let ranked = plan(&[single, few, many], &model, Utc::now());
+        assert_eq!(ranked.len(), 2);
+        assert_eq!(
+            ranked[0].action,
+            PlanAction::PruneSnapshots {
+                coord_id: CoordId("MANY".to_string()),
+                keep: 1
+            }
+        );
+        assert_eq!(ranked[0].estimate.bytes_saved, 40 * 1024 * 1024);
+        assert_eq!(ranked[0].estimate.ms_saved(), 0.0);
+        assert_eq!(ranked[1].action.coord_id().0, "FEW");
+
+        let wire = serde_json::to_value(&ranked[0].action).unwrap();
+        assert_eq!(wire, json!({"action": "prune_snapshots", "coord_id": "MANY", "keep": 1}));
+    }
+
+    #[tokio::test]
+    async fn test_apply_reports_actuals() {
+        let db = TempDb::new("planner-apply");
+        let facade = db.facade(1000).await;
+        let coord_id = CoordId("PLAN".to_string());
+        for n in 0..40 {
+            facade
+                .store(StoreParams {
+                    coord_id: Some(coord_id.clone()),
+                    state: json!({"n": n}),
+                    ..Default::default()
+                })
+                .await
+                .unwrap();
+        }
+
+        let model = CostModel::default();
+        let recommendations = plan_store(&facade, &model).await.unwrap();
+        assert_eq!(recommendations.len(), 1);
+
+        let applied = apply(&facade, &recommendations[0].action, &model).await.unwrap();
+        assert_eq!(applied.before.replay_deltas, 40);
+        assert_eq!(applied.after.replay_deltas, 0);
+        assert_eq!(applied.after.snapshot_count, 1);
+        assert!(applied.actual.bytes_saved < 0);
+        assert!(plan_store(&facade, &model).await.unwrap().is_empty());
+
+        facade
+            .store(StoreParams {
+                coord_id: Some(coord_id.clone()),
+                state: json!({"n": "last"}),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        facade.create_snapshot(&coord_id).await.unwrap();
+        let prune = PlanAction::PruneSnapshots { coord_id, keep: 1 };
+        let applied = apply(&facade, &prune, &model).await.unwrap();
+        assert_eq!(applied.after.snapshot_count, 1);
+        assert_eq!(applied.actual.bytes_saved, applied.estimate.bytes_saved);
+    }
+}
@@ -0,0 +1,587 @@
+//! Runs every repository query against a fresh schema
+//!
+//! Queries are plain strings checked only when they execute, so a misspelled
+//! column would otherwise surface the first time that path runs in
+//! production. Every public `BmsRepository` method is called here through
+//! `call!`, which records its name; the test fails if `repository.rs` gains a
+//! method that was never called.
+
+use crate::models::{AccessRecord, HeadRows, MaterializedHead, ReconstructionCheckpoint, SavedResult, SavedSearch};
+use crate::oplog::OpKind;
+use crate::test_support::TempDb;
+use crate::{StoreParams, StorePrecondition};
+use bms_core::types::{CoordId, CoordIdKind, Coordinate, Hash};
+use bms_core::{BmsError, CoordinateGenerator, HashAlgorithm, ImportancePolicy, Link, MerkleChain};
+use chrono::Utc;
+use serde_json::json;
+use std::collections::{BTreeSet, HashMap};
+
+/// Call a repository method, record its name, and unwrap the result
+macro_rules! call {
+    ($covered:ident, $repo:ident . $method:ident ( $($arg:expr),* $(,)? )) => {{
+        $covered.insert(stringify!($method));
+        $repo.$method($($arg),*).await.unwrap()
+    }};
+}
+
+/// Names of the public methods declared in `repository.rs`
+fn repository_methods() -> BTreeSet<&'static str> {
+    include_str!("repository.rs")
+        .lines()
+        .filter_map(|line| line.trim_start().strip_prefix("pub async fn "))
+        .filter_map(|rest| rest.split(['(', '<']).next())
+        .collect()
+}
+
+#[tokio::test]
+async fn test_every_repository_query_runs() {
+    let mut covered = BTreeSet::new();
+
+    // Rows are produced by the facade in a source database, then written
+    // directly so each insert query runs against an empty table
+    let source_db = TempDb::new("queries-source");
+    let source = source_db.facade(2).await;
+    let coord = CoordId("QUERYCOORD".to_string());
+    let group = [CoordId("QUERYGROUPA".to_string()), CoordId("QUERYGROUPB".to_string())];
+    for n in 0..2 {
+        source
+            .store(StoreParams {
+                coord_id: Some(coord.clone()),
+                state: json!({"n": n, "tags": ["a", "b"]}),
+                metadata: Some([("label".to_string(), json!("query test"))].into()),
+                author: Some("tester".to_string()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    }
+    for id in &group {
+        source
+            .store(StoreParams {
+                coord_id: Some(id.clone()),
+                state: json!({"group": id.0}),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    }
+    let src = source.repository();
+    let coordinate = src.get_coordinate(&coord).await.unwrap().unwrap();
+    let deltas = src.get_deltas(&coord).await.unwrap();
+    let snapshot = src.get_latest_snapshot(&coord).await.unwrap().unwrap();
+    let mut group_coords = Vec::new();
+    let mut group_deltas = Vec::new();
+    for id in &group {
+        group_coords.push(src.get_coordinate(id).await.unwrap().unwrap());
+        group_deltas.extend(src.get_deltas(id).await.unwrap());
+    }
+
+    let db = TempDb::new("queries");
+    let repo = db.repository().await;
+    covered.insert("new");
+
+    // Coordinates
+    call!(covered, repo.insert_coordinate(&coordinate));
+    assert!(!call!(covered, repo.insert_coordinate_if_absent(&coordinate)));
+    let fetched = call!(covered, repo.get_coordinate(&coord)).unwrap();
+    assert!(call!(covered, repo.get_children(&coord)).is_empty());
+    assert_eq!(fetched.metadata, coordinate.metadata);
+    assert_eq!(call!(covered, repo.get_hash_algorithm(&coord)), HashAlgorithm::Sha3_256);
+    assert_eq!(call!(covered, repo.list_coordinate_ids()), std::slice::from_ref(&coord));
+    assert!(call!(covered, repo.coordinate_exists(&coord)));
+    assert_eq!(call!(covered, repo.find_coordinates_by_prefix("QUERYC", 10)), std::slice::from_ref(&coord));
+    assert_eq!(call!(covered, repo.resolve_coord_prefix("QUERYC")), coord);
+    assert!(call!(covered, repo.find_coordinates_by_alias("query")).is_empty());
+    let alias = coordinate.rune_alias.clone().unwrap();
+    assert_eq!(call!(covered, repo.get_coordinate_by_alias(&alias)).unwrap().id, coord);
+    let relabeled = [("label".to_string(), json!("patched"))].into();
+    assert!(call!(covered, repo.update_coordinate_metadata(&coord, &relabeled, Some(&[]))));
+    assert_eq!(repo.get_coordinate(&coord).await.unwrap().unwrap().metadata, Some(relabeled));
+
+    // Deltas and snapshots
+    call!(covered, repo.insert_delta(&deltas[0]));
+    let link = Link {
+        pointer: "/related/0".to_string(),
+        to_coord: group[0].clone(),
+    };
+    let head = HeadRows {
+        links: Some(vec![link.clone()]),
+        state_bytes: Some(40),
+        materialized: Some(json!({"head": 1})),
+    };
+    call!(covered, repo.insert_head(&deltas[1], &head, None));
+    // A precondition is checked against the tip in the insert's transaction
+    let stale = StorePrecondition::HeadDeltaId(deltas[0].id.clone());
+    match repo.insert_head(&deltas[1], &head, Some(&stale)).await {
+        Err(BmsError::PreconditionFailed { actual, head_chain_hash, .. }) => {
+            assert_eq!(actual, deltas[1].id.0);
+            assert_eq!(head_chain_hash, Some(deltas[1].chain_hash.0.clone()));
+        }
+        other => panic!("expected a precondition failure, got {:?}", other),
+    }
+    call!(covered, repo.insert_snapshot(&snapshot));
+    let stored = call!(covered, repo.get_deltas(&coord));
+    assert_eq!(stored.len(), 2);
+    assert_eq!(stored[1].chain_hash, deltas[1].chain_hash);
+    assert_eq!(stored[1].author.as_deref(), Some("tester"));
+    assert_eq!(call!(covered, repo.get_deltas_lenient(&coord)).len(), 2);
+    assert!(call!(covered, repo.scan_corrupt_rows()).is_empty());
+    assert_eq!(call!(covered, repo.recode_deltas()), 0);
+    assert!(call!(covered, repo.list_ephemeral()).is_empty());
+    let one = call!(covered, repo.get_delta(&deltas[0].id)).unwrap();
+    assert_eq!(one.ops.len(), deltas[0].ops.len());
+    assert_eq!(call!(covered, repo.get_delta_count(&coord)), 2);
+    let initial = bms_core::DeltaEngine::hash_state(&json!({"n": 0, "tags": ["a", "b"]})).unwrap();
+    assert_eq!(call!(covered, repo.coordinate_initial_state_hash(&coord)), Some(initial));
+    let prefix = &deltas[0].id.as_str()[..8];
+    assert_eq!(call!(covered, repo.find_deltas_by_prefix(&coord, prefix, 10))[0], deltas[0].id);
+    let latest = call!(covered, repo.get_latest_snapshot(&coord)).unwrap();
+    assert_eq!(call!(covered, repo.latest_snapshot_at(&coord)), Some(latest.created_at));
+    assert_eq!(latest.state, snapshot.state);
+    let by_id = call!(covered, repo.get_snapshot(&snapshot.id)).unwrap();
+    assert_eq!(by_id.head_delta_id, snapshot.head_delta_id);
+    assert_eq!(call!(covered, repo.list_links(true)).len(), 1);
+    let group_head = HeadRows {
+        links: Some(vec![link]),
+        state_bytes: Some(10),
+        materialized: None,
+    };
+    call!(covered, repo.insert_group(&group_coords, &group_deltas, &[(group[1].clone(), group_head)]));
+    assert_eq!(call!(covered, repo.list_coordinates(None, Some(10))).len(), 3);
+    let recent = bms_core::FilterExpr::parse("created >= -1d and not tag == x", Utc::now()).unwrap();
+    assert_eq!(call!(covered, repo.list_coordinates_where(&recent, Some(10))).len(), 3);
+
+    // Links
+    assert_eq!(call!(covered, repo.get_links(&coord))[0].to_coord, group[0]);
+    assert_eq!(call!(covered, repo.get_backlinks(&group[0])).len(), 2);
+    assert_eq!(call!(covered, repo.list_links(false)).len(), 2);
+    call!(covered, repo.replace_links(&group[1], &[]));
+    assert_eq!(repo.list_links(false).await.unwrap().len(), 1);
+    assert!(call!(covered, repo.has_chain_hash(&coord, &deltas[0].chain_hash)));
+    assert!(!repo.has_chain_hash(&group[0], &deltas[0].chain_hash).await.unwrap());
+
+    // Head state sizes; a size for a superseded head is ignored
+    assert!(!call!(covered, repo.record_state_size(&coord, &deltas[0].id, 5)));
+    assert!(repo.record_state_size(&group[0], &group_deltas[0].id, 20).await.unwrap());
+    let sizes = call!(covered, repo.list_state_sizes(Some(2)));
+    let listed: Vec<_> = sizes.iter().map(|s| (&s.coord_id, s.state_bytes)).collect();
+    assert_eq!(listed, [(&coord, 40), (&group[0], 20)]);
+    let compression = call!(covered, repo.get_compression_stats(&coord)).unwrap();
+    assert_eq!((compression.delta_count, compression.original_bytes), (2, 80));
+    assert!(repo.get_compression_stats(&CoordId("NONE".to_string())).await.unwrap().is_none());
+
+    // Materialized heads; a state for a superseded head is ignored
+    let mut materialized = call!(covered, repo.get_head_state(&coord)).unwrap();
+    assert_eq!((materialized.state, &materialized.chain_hash), (json!({"head": 1}), &deltas[1].chain_hash));
+    materialized = MaterializedHead {
+        head_delta_id: deltas[0].id.clone(),
+        state: json!({"head": 0}),
+        ..materialized
+    };
+    assert!(!call!(covered, repo.record_head_state(&materialized)));
+    assert_eq!(call!(covered, repo.list_head_state_coords()), std::slice::from_ref(&coord));
+    assert!(call!(covered, repo.delete_head_state(&coord)));
+
+    // Reconstruction checkpoints
+    let checkpoint = |seq: u64, created_at| ReconstructionCheckpoint {
+        coord_id: coord.clone(),
+        seq,
+        delta_id: deltas[seq as usize - 1].id.clone(),
+        state_hash: Hash(format!("state-{}", seq)),
+        state: json!({"seq": seq}),
+        created_at,
+    };
+    call!(covered, repo.put_checkpoint(&checkpoint(1, Utc::now() - chrono::Duration::hours(2))));
+    repo.put_checkpoint(&checkpoint(2, Utc::now())).await.unwrap();
+    let newest = call!(covered, repo.get_checkpoint(&coord, 5)).unwrap();
+    assert_eq!((newest.seq, newest.state), (2, json!({"seq": 2})));
+    assert_eq!(repo.get_checkpoint(&coord, 1).await.unwrap().unwrap().state, json!({"seq": 1}));
+    assert_eq!(call!(covered, repo.prune_checkpoints(Utc::now() - chrono::Duration::hours(1))), 1);
+    assert_eq!(call!(covered, repo.delete_checkpoints(&coord, None)), 1);
+
+    // Read statistics, importance, and aggregates
+    let policy = ImportancePolicy::default();
+    let reads = [AccessRecord {
+        coord_id: coord.clone(),
+        read_count: 2,
+        last_read_at: Utc::now(),
+    }];
+    call!(covered, repo.record_access_batch(&reads, Some(&policy)));
+    let hot = call!(covered, repo.get_hot_coordinates(10));
+    assert_eq!((hot[0].read_count, hot[0].delta_count), (2, 2));
+    let stats = call!(covered, repo.get_coord_stats(Some(&coord)));
+    assert_eq!((stats[0].delta_count, stats[0].snapshot_count, stats[0].read_count), (2, 1, 2));
+    assert_eq!(call!(covered, repo.get_coord_stats(None)).len(), 3);
+    assert_eq!(call!(covered, repo.get_importance(None)).len(), 3);
+    assert!(call!(covered, repo.reinforce_importance(&coord, 0.1, &policy, Utc::now())).is_some());
+    assert_eq!(call!(covered, repo.list_write_times()).len(), 3);
+    assert_eq!(call!(covered, repo.prune_snapshots(&coord, 1)), 0);
+    let totals = call!(covered, repo.get_stats());
+    assert_eq!((totals.coordinate_count, totals.delta_count, totals.snapshot_count), (3, 4, 1));
+    assert!(call!(covered, repo.quick_check()).is_empty());
+
+    // Saved searches
+    let saved = SavedSearch {
+        owner: "key:test".to_string(),
+        name: "nightly".to_string(),
+        request: json!({"query": "recent", "limit": 5}),
+        webhook_url: Some("http://localhost/hook".to_string()),
+        created_at: Utc::now(),
+        last_run_at: None,
+    };
+    assert!(call!(covered, repo.save_search(&saved)));
+    let result = SavedResult {
+        coord_id: coord.clone(),
+        head_hash: deltas[1].chain_hash.clone(),
+    };
+    call!(covered, repo.record_saved_search_run("key:test", "nightly", std::slice::from_ref(&result), Utc::now()));
+    assert_eq!(call!(covered, repo.get_saved_search_results("key:test", "nightly")), [result]);
+    let fetched = call!(covered, repo.get_saved_search("key:test", "nightly")).unwrap();
+    assert_eq!((fetched.request, fetched.last_run_at.is_some()), (saved.request.clone(), true));
+    assert_eq!(call!(covered, repo.list_saved_searches(Some("key:other"))).len(), 0);
+    assert_eq!(call!(covered, repo.list_saved_searches(None)).len(), 1);
+    // Replacing starts change detection over
+    assert!(!repo.save_search(&saved).await.unwrap());
+    assert!(repo.get_saved_search_results("key:test", "nightly").await.unwrap().is_empty());
+    assert!(call!(covered, repo.delete_saved_search("key:test", "nightly")));
+
+    // System metadata
+    call!(covered, repo.set_metadata("query_test", "1"));
+    assert_eq!(call!(covered, repo.get_metadata("query_test")).as_deref(), Some("1"));
+    assert_eq!(call!(covered, repo.snapshot_interval()), Some(bms_core::DEFAULT_SNAPSHOT_INTERVAL));
+    call!(covered, repo.set_snapshot_interval(64));
+    assert_eq!(repo.snapshot_interval().await.unwrap(), Some(64));
+
+    // Oplog and backup markers
+    let entries = call!(covered, repo.get_oplog(0, 100));
+    assert_eq!(call!(covered, repo.max_lsn()), entries.last().unwrap().lsn);
+    assert_eq!(call!(covered, repo.min_lsn()), Some(entries[0].lsn));
+    assert!(!call!(covered, repo.deleted_after(&coord, 0)));
+    call!(covered, repo.set_replication_lsn("http://primary", 7));
+    assert_eq!(call!(covered, repo.get_replication_lsn("http://primary")), Some(7));
+    let appended = entries.iter().find(|e| e.op == OpKind::DeltaAppended).unwrap();
+    assert!(call!(covered, repo.oplog_payload(appended)).is_some());
+    let marker = call!(covered, repo.record_backup_marker(Some("nightly")));
+    assert_eq!(call!(covered, repo.list_backup_markers())[0].lsn, marker.lsn);
+    assert_eq!(call!(covered, repo.trim_oplog(1)), entries.len() as u64);
+
+    let replica_db = TempDb::new("queries-replica");
+    let replica = replica_db.repository().await;
+    for mut entry in src.get_oplog(0, 100).await.unwrap() {
+        entry.payload = src.oplog_payload(&entry).await.unwrap();
+        assert!(call!(covered, replica.apply_oplog_entry(&entry)));
+    }
+    assert_eq!(replica.get_stats().await.unwrap().delta_count, 4);
+    assert!(!call!(covered, replica.compacted_after(&coord, 0)));
+    let report = call!(covered, replica.compact_coordinate(&coord, 0));
+    assert_eq!((report.squashed, report.kept), (2, 0));
+    assert!(replica.compacted_after(&coord, 0).await.unwrap());
+    assert_eq!(replica.get_stats().await.unwrap().delta_count, 3);
+
+    assert!(call!(covered, repo.delete_coordinate(&coord)));
+    assert!(repo.get_links(&coord).await.unwrap().is_empty());
+    assert_eq!(repo.list_state_sizes(None).await.unwrap().len(), 2);
+    assert_eq!(call!(covered, repo.insert_coordinates_batch(std::slice::from_ref(&coordinate))), 1);
+
+    let missing: Vec<_> = repository_methods()
+        .into_iter()
+        .filter(|method| !covered.contains(method))
+        .collect();
+    assert!(missing.is_empty(), "repository methods without a query test: {:?}", missing);
+}
+
+#[tokio::test]
+async fn test_resolve_coord_prefix() {
+    let db = TempDb::new("coord-prefix");
+    let repo = db.repository().await;
+    let mut high = [0; 16];
+    high[8..].fill(0xff);
+    let (a, b, c) = (CoordId::from_bytes([0; 16]), CoordId::from_bytes([1; 16]), CoordId::from_bytes(high));
+    for id in [&a, &b, &c] {
+        repo.insert_coordinate(&Coordinate {
+            id: id.clone(),
+            rune_alias: None,
+            created_at: Utc::now(),
+            metadata: None,
+            namespace: None,
+            hash_algorithm: Default::default(),
+            kind: Default::default(),
+            parent_coord_id: None,
+        })
+        .await
+        .unwrap();
+    }
+    // 64 shared bits are 12 whole characters
+    assert_eq!(a.0[..12], c.0[..12]);
+    assert_ne!(a.0[..13], c.0[..13]);
+
+    assert_eq!(repo.resolve_coord_prefix(&b.0[..8]).await.unwrap(), b);
+    assert_eq!(repo.resolve_coord_prefix(&a.0[..13]).await.unwrap(), a);
+    assert_eq!(repo.resolve_coord_prefix(&c.0).await.unwrap(), c);
+    let back = repo.resolve_coord_prefix(&c.0[..20]).await.unwrap();
+    assert_eq!(CoordId::from_bytes(back.to_bytes().unwrap()), c);
+
+    match repo.resolve_coord_prefix(&a.0[..12]).await {
+        Err(BmsError::AmbiguousPrefix { prefix, candidates }) => {
+            assert_eq!(prefix, a.0[..12]);
+            assert_eq!(candidates, [a.0.clone(), c.0.clone()]);
+        }
+        other => panic!("{:?}", other),
+    }
+    let invalid = |prefix: &'static str| {
+        let repo = &repo;
+        async move { matches!(repo.resolve_coord_prefix(prefix).await, Err(BmsError::InvalidCoordinate(_))) }
+    };
+    // Unused, lowercase, padding, and non-base32 characters, and lengths
+    assert!(invalid("ZZZZZZZZ").await);
+    assert!(invalid("aaaaaaaa").await);
+    assert!(invalid("AAAAAA==").await);
+    assert!(invalid("AAAA1AAA").await);
+    assert!(invalid("").await);
+    assert!(invalid("AAAAAAAAAAAAAAAAAAAAAAAAAAA").await);
+}
+
+#[tokio::test]
+async fn test_aliases_are_unique() {
+    let db = TempDb::new("aliases");
+    let facade = db.facade(10).await;
+    let repo = facade.repository();
+    let result = facade
+        .store(StoreParams {
+            state: json!({"aliased": 1}),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    let alias = CoordinateGenerator::generate_alias(&result.coord_id);
+    assert_eq!(repo.get_coordinate_by_alias(&alias).await.unwrap().unwrap().id, result.coord_id);
+
+    // A drawn alias that is taken gets a suffix
+    let twin = |id: &str| Coordinate {
+        id: CoordId(id.to_string()),
+        rune_alias: Some(alias.clone()),
+        created_at: Utc::now(),
+        metadata: None,
+        namespace: None,
+        hash_algorithm: Default::default(),
+        kind: Default::default(),
+        parent_coord_id: None,
+    };
+    repo.insert_coordinate(&twin("TWINA")).await.unwrap();
+    repo.insert_coordinate(&twin("TWINB")).await.unwrap();
+    let alias_of = |id: &'static str| async move {
+        repo.get_coordinate(&CoordId(id.to_string())).await.unwrap().unwrap().rune_alias.unwrap()
+    };
+    assert_eq!(alias_of("TWINA").await, format!("{}-2", alias));
+    assert_eq!(alias_of("TWINB").await, format!("{}-3", alias));
+    assert!(repo.get_coordinate_by_alias("no-such-alias").await.unwrap().is_none());
+
+    // Databases from before the unique index keep the oldest of a repeated alias
+    drop(facade);
+    db.execute("DROP INDEX idx_coords_alias_unique").await;
+    db.execute(&format!("UPDATE coordinates SET rune_alias = '{}' WHERE id_ascii LIKE 'TWIN%'", alias)).await;
+    let repo = db.repository().await;
+    assert_eq!(repo.get_coordinate_by_alias(&alias).await.unwrap().unwrap().id, result.coord_id);
+    assert!(repo.get_coordinate(&CoordId("TWINB".to_string())).await.unwrap().unwrap().rune_alias.is_none());
+    assert!(repo.insert_coordinate(&twin("TWINC")).await.is_ok());
+    let twin_c = repo.get_coordinate(&CoordId("TWINC".to_string())).await.unwrap().unwrap();
+    assert_eq!(twin_c.rune_alias, Some(format!("{}-2", alias)));
+}
+
+#[tokio::test]
+async fn test_namespaces() {
+    let db = TempDb::new("namespaces");
+    let facade = db.facade(10).await;
+    let mut ids = Vec::new();
+    for (n, namespace) in [None, Some("agents"), Some("agents/planner"), Some("agentsmith")].into_iter().enumerate() {
+        let outcome = facade
+            .store(StoreParams {
+                state: json!({"n": n}),
+                namespace: namespace.map(str::to_string),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        ids.push(outcome.coord_id.0);
+    }
+
+    let repo = facade.repository();
+    let listed = |namespace: &'static str| async move {
+        let coords = repo.list_coordinates(Some(namespace), None).await.unwrap();
+        coords.into_iter().map(|c| c.id.0).collect::<BTreeSet<_>>()
+    };
+    assert_eq!(listed("default").await, BTreeSet::from([ids[0].clone()]));
+    assert_eq!(listed("agents").await, BTreeSet::from([ids[1].clone(), ids[2].clone()]));
+    assert_eq!(listed("agents/planner").await, BTreeSet::from([ids[2].clone()]));
+    assert!(listed("agents/critic").await.is_empty());
+    assert_eq!(repo.list_coordinates(None, None).await.unwrap().len(), 4);
+    let planner = repo.get_coordinate(&CoordId(ids[2].clone())).await.unwrap().unwrap();
+    assert_eq!(planner.namespace(), "agents/planner");
+    let err = repo.list_coordinates(Some("agents/"), None).await.unwrap_err();
+    assert!(matches!(err, BmsError::InvalidCoordinate(_)));
+
+    // Databases from before namespaces gain the column, and their
+    // coordinates read as the default namespace
+    drop(facade);
+    db.execute("DROP INDEX idx_coords_namespace").await;
+    db.execute("ALTER TABLE coordinates DROP COLUMN namespace").await;
+    let repo = db.repository().await;
+    assert_eq!(repo.list_coordinates(Some("default"), None).await.unwrap().len(), 4);
+    assert!(repo.list_coordinates(Some("agents"), None).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_coordinate_batches() {
+    let db = TempDb::new("coord_batches");
+    let facade = db.facade(10).await;
+    let existing = facade.store(StoreParams { state: json!({"n": 0}), ..Default::default() }).await.unwrap();
+
+    // More rows than one statement takes, with repeated states
+    let states: Vec<_> = (0..1200).map(|n| json!({"n": n % 1000})).collect();
+    let now = Utc::now();
+    let ids = CoordinateGenerator::generate_batch(&states, &now).unwrap();
+    assert_eq!(ids.iter().map(CoordId::as_str).collect::<BTreeSet<_>>().len(), ids.len());
+    let mut coords: Vec<Coordinate> = ids
+        .into_iter()
+        .map(|id| Coordinate {
+            rune_alias: Some(CoordinateGenerator::generate_alias(&id)),
+            id,
+            created_at: now,
+            metadata: None,
+            namespace: None,
+            hash_algorithm: Default::default(),
+            kind: Default::default(),
+            parent_coord_id: None,
+        })
+        .collect();
+    // One row shares its alias with a stored coordinate
+    let taken = facade.repository().get_coordinate(&existing.coord_id).await.unwrap().unwrap();
+    coords[7].rune_alias = taken.rune_alias.clone();
+
+    assert_eq!(facade.create_coordinates(&coords).await.unwrap(), 1200);
+    assert_eq!(facade.create_coordinates(&coords).await.unwrap(), 0);
+    let repo = facade.repository();
+    let listed = repo.list_coordinates(None, Some(i64::MAX)).await.unwrap();
+    assert_eq!(listed.len(), 1201);
+    let aliases: BTreeSet<_> = listed.iter().map(|c| c.rune_alias.clone().unwrap()).collect();
+    assert_eq!(aliases.len(), 1201);
+    let created = repo.get_oplog(0, i64::MAX).await.unwrap();
+    assert_eq!(created.iter().filter(|e| e.op == OpKind::CoordinateCreated).count(), 1201);
+
+    // A skipped row, stored or repeated in the batch, does not hold its alias
+    let fresh = Coordinate { id: CoordId("FRESH".to_string()), ..taken.clone() };
+    let batch = [
+        Coordinate { rune_alias: Some("spare-alias".to_string()), ..taken.clone() },
+        Coordinate { rune_alias: Some("spare-alias".to_string()), ..fresh.clone() },
+        Coordinate { rune_alias: Some("other-alias".to_string()), ..fresh.clone() },
+        Coordinate { id: CoordId("FRESH-TOO".to_string()), rune_alias: Some("other-alias".to_string()), ..fresh },
+    ];
+    assert_eq!(repo.insert_coordinates_batch(&batch).await.unwrap(), 2);
+    let alias_of = |id: &'static str| async move {
+        repo.get_coordinate(&CoordId(id.to_string())).await.unwrap().unwrap().rune_alias.unwrap()
+    };
+    assert_eq!(alias_of("FRESH").await, "spare-alias");
+    assert_eq!(alias_of("FRESH-TOO").await, "other-alias");
+    assert_eq!(repo.get_coordinate(&taken.id).await.unwrap().unwrap().rune_alias, taken.rune_alias);
+
+    // Batched coordinates take stores like any other
+    facade.store(StoreParams { coord_id: Some(coords[0].id.clone()), state: json!({"n": "x"}), ..Default::default() })
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_named_coordinates() {
+    let db = TempDb::new("named_coords");
+    let facade = db.facade(10).await;
+    let store = |coord_id: Option<&str>, n: u32| {
+        facade.store(StoreParams {
+            coord_id: coord_id.map(|id| CoordId(id.to_string())),
+            state: json!({"n": n}),
+            ..Default::default()
+        })
+    };
+    let generated = store(None, 0).await.unwrap().coord_id;
+    let named = store(Some("agent-7.notes"), 1).await.unwrap().coord_id;
+    let err = store(Some("two words"), 2).await.unwrap_err();
+    assert!(matches!(err, BmsError::InvalidCoordinate(_)), "{:?}", err);
+
+    let repo = facade.repository();
+    assert_eq!(repo.get_coordinate(&generated).await.unwrap().unwrap().kind, CoordIdKind::Generated);
+    assert_eq!(repo.get_coordinate(&named).await.unwrap().unwrap().kind, CoordIdKind::Named);
+    assert!(repo.get_coordinate(&CoordId("two words".to_string())).await.unwrap().is_none());
+
+    // Databases from before kinds gain the column with their free-form IDs
+    // marked named, and those stay writable
+    drop(facade);
+    db.execute("INSERT INTO coordinates (id_ascii, created_at) VALUES ('old hint', CURRENT_TIMESTAMP)").await;
+    db.execute("ALTER TABLE coordinates DROP COLUMN id_kind").await;
+    let facade = db.facade(10).await;
+    let repo = facade.repository();
+    let kinds: HashMap<String, CoordIdKind> = repo
+        .list_coordinates(None, None)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|c| (c.id.0, c.kind))
+        .collect();
+    assert_eq!(kinds[generated.as_str()], CoordIdKind::Generated);
+    assert_eq!(kinds["agent-7.notes"], CoordIdKind::Named);
+    assert_eq!(kinds["old hint"], CoordIdKind::Named);
+    facade
+        .store(StoreParams {
+            coord_id: Some(CoordId("old hint".to_string())),
+            state: json!({"n": 3}),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_blake3_chains() {
+    let db = TempDb::new("blake3");
+    let facade = db.facade(10).await.with_integrity_checks(crate::IntegrityChecks::strict());
+    let mut coord_id = None;
+    for n in 0..4 {
+        let outcome = facade
+            .store(StoreParams {
+                coord_id: coord_id.clone(),
+                state: json!({"n": n}),
+                hash_algorithm: Some(HashAlgorithm::Blake3),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        coord_id = Some(outcome.coord_id);
+    }
+    let coord_id = coord_id.unwrap();
+    let repo = facade.repository();
+    assert_eq!(repo.get_hash_algorithm(&coord_id).await.unwrap(), HashAlgorithm::Blake3);
+    assert_eq!(repo.get_coordinate(&coord_id).await.unwrap().unwrap().hash_algorithm, HashAlgorithm::Blake3);
+
+    // Strict reads check the chain under its own algorithm
+    let head = facade.head(&coord_id).await.unwrap().unwrap();
+    assert_eq!(head.state, json!({"n": 3}));
+    let parent = &head.deltas[2].chain_hash;
+    let delta_hash = &head.deltas[3].delta_hash;
+    assert_eq!(
+        head.deltas[3].chain_hash,
+        MerkleChain::compute_chain_hash_with(parent, delta_hash, HashAlgorithm::Blake3)
+    );
+    assert!(MerkleChain::verify_chain_integrity_with(&head.deltas, HashAlgorithm::Blake3).1.is_none());
+    assert!(MerkleChain::verify_chain_integrity(&head.deltas).1.is_some());
+
+    // Compaction rechains kept deltas with the same algorithm
+    assert_eq!(facade.compact(&coord_id, 2).await.unwrap().kept, 2);
+    let head = facade.head(&coord_id).await.unwrap().unwrap();
+    assert_eq!(head.state, json!({"n": 3}));
+    assert!(MerkleChain::verify_chain_integrity_with(&head.deltas, HashAlgorithm::Blake3).1.is_none());
+
+    // Coordinates from before the column read as SHA3-256
+    drop(facade);
+    db.execute("ALTER TABLE coordinates DROP COLUMN hash_algorithm").await;
+    let repo = db.repository().await;
+    assert_eq!(repo.get_hash_algorithm(&coord_id).await.unwrap(), HashAlgorithm::Sha3_256);
+    assert_eq!(repo.get_hash_algorithm(&CoordId("MISSING".to_string())).await.unwrap(), HashAlgorithm::Sha3_256);
+}
@@ -1,15 +1,58 @@
-use crate::models::{CoordRow, DeltaRow, SnapshotRow};
+use crate::models::{
+    AccessRecord, BackupMarkerRow, CheckpointRow, CompactReport, CoordImportance, CoordLink, CoordRow, CoordStats, CorruptRow,
+    CoordStatsRow, DeltaRow, EphemeralCoord, EphemeralRow, HeadRows, HeadStateRow, HotCoordRow, HotCoordinate, ImportanceRow, LinkRow, OplogRow,
+    MaterializedHead, ReconstructionCheckpoint, SavedResult, SavedSearch, SavedSearchRow, SnapshotRow, StateSize,
+    StateSizeRow, deflate_json, inflate_json,
+};
+use crate::facade::StorePrecondition;
+use crate::filter_sql::{self, SqlArg};
+use crate::oplog::{self, BackupMarker, OpKind, OplogEntry, OplogRecord};
 use crate::schema::SCHEMA_SQL;
-use bms_core::types::{Coordinate, CoordId, Delta, DeltaId, Snapshot, SnapshotId};
-use bms_core::Result;
-use sqlx::sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions};
+use bms_core::types::{CompressionStats, Coordinate, CoordId, CoordIdKind, Delta, DeltaId, Hash, Snapshot, SnapshotId};
+use serde_json::Value;
+use bms_core::importance::DEFAULT_IMPORTANCE;
+use bms_core::snapshot::{externalize, externalize_pointers, inline};
+use bms_core::delta::SQUASHED_TAG;
+use bms_core::{
+    BmsError, CoordinateGenerator, DeltaEncoding, DeltaEngine, FilterExpr, HashAlgorithm, ImportancePolicy, Link,
+    MerkleChain, Result,
+    COORD_ID_CHARS, DEFAULT_NAMESPACE, DEFAULT_SNAPSHOT_INTERVAL,
+};
+use chrono::{DateTime, Utc};
+use sqlx::sqlite::{SqliteConnectOptions, SqliteConnection, SqlitePool, SqlitePoolOptions};
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
 use std::str::FromStr;
-use tracing::info;
+use tracing::{info, warn};
+
+/// Metadata key of the instance's snapshot interval, seeded from
+/// `DEFAULT_SNAPSHOT_INTERVAL` when the database is first opened
+pub const SNAPSHOT_INTERVAL_METADATA_KEY: &str = "snapshot_interval";
+
+/// Rows read per query by `scan_corrupt_rows` and `recode_deltas`
+const SCAN_PAGE_SIZE: i64 = 1000;
+
+/// Rows per multi-row insert of `insert_coordinates_batch`, well under
+/// SQLite's bound parameter limit
+const COORD_BATCH_ROWS: usize = 500;
+
+/// Candidates `resolve_coord_prefix` lists for an ambiguous prefix
+pub const MAX_PREFIX_CANDIDATES: usize = 10;
+
+/// Stored importance of all coordinates (`?1` NULL) or one, with `?2` as the default
+const IMPORTANCE_SQL: &str = r#"
+    SELECT c.id_ascii AS coord_id,
+           COALESCE(i.importance, ?2) AS importance,
+           COALESCE(i.updated_at, c.created_at) AS updated_at
+    FROM coordinates c
+    LEFT JOIN coord_importance i ON i.coord_id = c.id_ascii
+    WHERE ?1 IS NULL OR c.id_ascii = ?1
+"#;
 
 /// BMS repository for SQLite storage operations
 pub struct BmsRepository {
     pool: SqlitePool,
+    delta_encoding: DeltaEncoding,
 }
 
 impl BmsRepository {
@@ -27,48 +70,314 @@ impl BmsRepository {
             .connect_with(options)
             .await?;
 
-        let repo = Self { pool };
+        let repo = Self {
+            pool,
+            delta_encoding: DeltaEncoding::default(),
+        };
         repo.initialize_schema().await?;
 
         Ok(repo)
     }
 
+    /// Write delta ops in `encoding` from now on
+    ///
+    /// Rows already written keep their encoding until `recode_deltas`.
+    pub fn with_delta_encoding(mut self, encoding: DeltaEncoding) -> Self {
+        self.delta_encoding = encoding;
+        self
+    }
+
+    pub fn delta_encoding(&self) -> DeltaEncoding {
+        self.delta_encoding
+    }
+
     /// Initialize database schema
     async fn initialize_schema(&self) -> Result<()> {
         sqlx::query(SCHEMA_SQL).execute(&self.pool).await?;
+        // Databases from before deltas could be signed lack the signature
+        // columns
+        let signed: bool = sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM pragma_table_info('deltas') WHERE name = 'signature_pubkey')",
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        if !signed {
+            sqlx::query("ALTER TABLE deltas ADD COLUMN signature_pubkey BLOB").execute(&self.pool).await?;
+            sqlx::query("ALTER TABLE deltas ADD COLUMN signature_bytes BLOB").execute(&self.pool).await?;
+        }
+        // Coordinates from before namespaces keep a NULL namespace, which
+        // reads as the default one
+        let namespaced: bool = sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM pragma_table_info('coordinates') WHERE name = 'namespace')",
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        if !namespaced {
+            sqlx::query("ALTER TABLE coordinates ADD COLUMN namespace TEXT").execute(&self.pool).await?;
+        }
+        // Chains written before the algorithm was recorded are SHA3-256
+        let recorded: bool = sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM pragma_table_info('coordinates') WHERE name = 'hash_algorithm')",
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        if !recorded {
+            sqlx::query("ALTER TABLE coordinates ADD COLUMN hash_algorithm TEXT NOT NULL DEFAULT 'sha3-256'")
+                .execute(&self.pool)
+                .await?;
+        }
+        // Free-form coordinate hints from before kinds were recorded are named
+        let kinded: bool = sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM pragma_table_info('coordinates') WHERE name = 'id_kind')",
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        if !kinded {
+            let mut tx = self.pool.begin().await?;
+            sqlx::query("ALTER TABLE coordinates ADD COLUMN id_kind TEXT NOT NULL DEFAULT 'generated'")
+                .execute(&mut *tx)
+                .await?;
+            let ids: Vec<String> = sqlx::query_scalar("SELECT id_ascii FROM coordinates").fetch_all(&mut *tx).await?;
+            for id in ids.into_iter().map(CoordId) {
+                if CoordIdKind::of(&id) == CoordIdKind::Named {
+                    sqlx::query("UPDATE coordinates SET id_kind = 'named' WHERE id_ascii = ?")
+                        .bind(&id.0)
+                        .execute(&mut *tx)
+                        .await?;
+                }
+            }
+            tx.commit().await?;
+        }
+        // Coordinates from before derivation have no parent
+        let parented: bool = sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM pragma_table_info('coordinates') WHERE name = 'parent_coord_id')",
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        if !parented {
+            sqlx::query("ALTER TABLE coordinates ADD COLUMN parent_coord_id TEXT").execute(&self.pool).await?;
+        }
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_coords_namespace ON coordinates(namespace)")
+            .execute(&self.pool)
+            .await?;
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_coords_parent ON coordinates(parent_coord_id)")
+            .execute(&self.pool)
+            .await?;
+        // Aliases became unique once they were generated; in databases from
+        // before, only the oldest coordinate of a repeated alias keeps it
+        let unique_aliases: bool = sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'index' AND name = 'idx_coords_alias_unique')",
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        if !unique_aliases {
+            let mut tx = self.pool.begin().await?;
+            sqlx::query(
+                r#"
+                UPDATE coordinates SET rune_alias = NULL
+                WHERE rune_alias IS NOT NULL AND EXISTS (
+                    SELECT 1 FROM coordinates AS older
+                    WHERE older.rune_alias = coordinates.rune_alias
+                      AND (older.created_at, older.id_ascii) < (coordinates.created_at, coordinates.id_ascii)
+                )
+                "#,
+            )
+            .execute(&mut *tx)
+            .await?;
+            sqlx::query("DROP INDEX IF EXISTS idx_coords_alias").execute(&mut *tx).await?;
+            sqlx::query("CREATE UNIQUE INDEX idx_coords_alias_unique ON coordinates(rune_alias)")
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await?;
+        }
+        // Databases from before the interval was stored get this binary's
+        // default, which is what they were snapshotting with
+        sqlx::query("INSERT OR IGNORE INTO metadata (key, value) VALUES (?, ?)")
+            .bind(SNAPSHOT_INTERVAL_METADATA_KEY)
+            .bind(DEFAULT_SNAPSHOT_INTERVAL.to_string())
+            .execute(&self.pool)
+            .await?;
         info!("Database schema initialized");
         Ok(())
     }
 
     /// Insert a new coordinate
     pub async fn insert_coordinate(&self, coord: &Coordinate) -> Result<()> {
+        if !self.insert_coordinate_if_absent(coord).await? {
+            return Err(BmsError::CoordinateCollision(coord.id.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Insert a coordinate unless one with the same ID exists
+    ///
+    /// Returns false if the coordinate already existed.
+    pub async fn insert_coordinate_if_absent(&self, coord: &Coordinate) -> Result<bool> {
+        let mut tx = self.pool.begin().await?;
+        let created = Self::insert_coordinate_row(&mut tx, coord).await?;
+        if created {
+            Self::append_oplog(&mut tx, &OplogRecord::new(OpKind::CoordinateCreated, &coord.id)).await?;
+        }
+        tx.commit().await?;
+        Ok(created)
+    }
+
+    async fn insert_coordinate_row(conn: &mut SqliteConnection, coord: &Coordinate) -> Result<bool> {
         let metadata_json = coord
             .metadata
             .as_ref()
-            .map(|m| serde_json::to_string(m))
+            .map(serde_json::to_string)
             .transpose()?;
+        let alias = match &coord.rune_alias {
+            Some(alias) => Some(Self::free_alias(conn, alias, &HashSet::new()).await?),
+            None => None,
+        };
 
-        sqlx::query(
+        let result = sqlx::query(
             r#"
-            INSERT INTO coordinates (id_ascii, rune_alias, created_at, metadata)
-            VALUES (?, ?, ?, ?)
+            INSERT INTO coordinates (
+                id_ascii, rune_alias, created_at, metadata, namespace, hash_algorithm, id_kind, parent_coord_id
+            )
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT(id_ascii) DO NOTHING
             "#,
         )
         .bind(&coord.id.0)
-        .bind(&coord.rune_alias)
+        .bind(alias)
         .bind(coord.created_at)
         .bind(metadata_json)
-        .execute(&self.pool)
+        .bind(coord.namespace.as_deref().filter(|ns| *ns != DEFAULT_NAMESPACE))
+        .bind(coord.hash_algorithm.to_string())
+        .bind(coord.kind.to_string())
+        .bind(coord.parent_coord_id.as_ref().map(|parent| &parent.0))
+        .execute(&mut *conn)
         .await?;
 
-        Ok(())
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Insert many coordinates in one transaction, skipping IDs that exist
+    ///
+    /// Rows go in `COORD_BATCH_ROWS` to a statement. A repeated ID keeps its
+    /// first row. Aliases are made unique as by `insert_coordinate`, against
+    /// the batch as well as the database, and only for rows that are
+    /// inserted. Returns how many coordinates were inserted.
+    pub async fn insert_coordinates_batch(&self, coords: &[Coordinate]) -> Result<u64> {
+        let mut tx = self.pool.begin().await?;
+        let mut seen = HashSet::new();
+        let mut assigned = HashSet::new();
+        let mut inserted = 0;
+        for chunk in coords.chunks(COORD_BATCH_ROWS) {
+            let mut rows = Vec::with_capacity(chunk.len());
+            for coord in chunk {
+                let exists = !seen.insert(&coord.id.0)
+                    || sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM coordinates WHERE id_ascii = ?)")
+                        .bind(&coord.id.0)
+                        .fetch_one(&mut *tx)
+                        .await?;
+                if exists {
+                    continue;
+                }
+                let alias = match &coord.rune_alias {
+                    Some(alias) => Some(Self::free_alias(&mut tx, alias, &assigned).await?),
+                    None => None,
+                };
+                if let Some(alias) = &alias {
+                    assigned.insert(alias.clone());
+                }
+                rows.push((coord, alias));
+            }
+            if rows.is_empty() {
+                continue;
+            }
+
+            let sql = format!(
+                "INSERT INTO coordinates \
+                 (id_ascii, rune_alias, created_at, metadata, namespace, hash_algorithm, id_kind, parent_coord_id) \
+                 VALUES {} ON CONFLICT(id_ascii) DO NOTHING RETURNING id_ascii",
+                vec!["(?, ?, ?, ?, ?, ?, ?, ?)"; rows.len()].join(", ")
+            );
+            let mut query = sqlx::query_scalar(&sql);
+            for (coord, alias) in rows {
+                query = query
+                    .bind(&coord.id.0)
+                    .bind(alias)
+                    .bind(coord.created_at)
+                    .bind(coord.metadata.as_ref().map(serde_json::to_string).transpose()?)
+                    .bind(coord.namespace.as_deref().filter(|ns| *ns != DEFAULT_NAMESPACE))
+                    .bind(coord.hash_algorithm.to_string())
+                    .bind(coord.kind.to_string())
+                    .bind(coord.parent_coord_id.as_ref().map(|parent| &parent.0));
+            }
+            let created: Vec<String> = query.fetch_all(&mut *tx).await?;
+            for id in created {
+                Self::append_oplog(&mut tx, &OplogRecord::new(OpKind::CoordinateCreated, &CoordId(id))).await?;
+                inserted += 1;
+            }
+        }
+        tx.commit().await?;
+        Ok(inserted)
+    }
+
+    /// `alias` if no coordinate has it and it is not in `pending`, else the
+    /// first of `alias-2`, `alias-3`, ... that is free in the same way
+    async fn free_alias(conn: &mut SqliteConnection, alias: &str, pending: &HashSet<String>) -> Result<String> {
+        let mut candidate = alias.to_string();
+        for n in 2.. {
+            let taken = pending.contains(&candidate)
+                || sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM coordinates WHERE rune_alias = ?)")
+                    .bind(&candidate)
+                    .fetch_one(&mut *conn)
+                    .await?;
+            if !taken {
+                break;
+            }
+            candidate = format!("{}-{}", alias, n);
+        }
+        Ok(candidate)
+    }
+
+    /// The coordinate with the given rune alias
+    pub async fn get_coordinate_by_alias(&self, alias: &str) -> Result<Option<Coordinate>> {
+        let row: Option<CoordRow> = sqlx::query_as(
+            r#"
+            SELECT id_ascii, rune_alias, created_at, metadata, namespace, hash_algorithm, id_kind,
+                   parent_coord_id
+            FROM coordinates
+            WHERE rune_alias = ?
+            "#,
+        )
+        .bind(alias)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|r| r.into()))
+    }
+
+    /// Coordinates derived from `coord_id`, oldest first
+    pub async fn get_children(&self, coord_id: &CoordId) -> Result<Vec<Coordinate>> {
+        let rows: Vec<CoordRow> = sqlx::query_as(
+            r#"
+            SELECT id_ascii, rune_alias, created_at, metadata, namespace, hash_algorithm, id_kind,
+                   parent_coord_id
+            FROM coordinates
+            WHERE parent_coord_id = ?
+            ORDER BY created_at, id_ascii
+            "#,
+        )
+        .bind(&coord_id.0)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(Coordinate::from).collect())
     }
 
     /// Get a coordinate by ID
     pub async fn get_coordinate(&self, coord_id: &CoordId) -> Result<Option<Coordinate>> {
         let row: Option<CoordRow> = sqlx::query_as(
             r#"
-            SELECT id_ascii, rune_alias, created_at, metadata
+            SELECT id_ascii, rune_alias, created_at, metadata, namespace, hash_algorithm, id_kind,
+                   parent_coord_id
             FROM coordinates
             WHERE id_ascii = ?
             "#,
@@ -80,6 +389,60 @@ impl BmsRepository {
         Ok(row.map(|r| r.into()))
     }
 
+    /// Hash algorithm of a coordinate's chain; SHA3-256 for one that does
+    /// not exist yet
+    pub async fn get_hash_algorithm(&self, coord_id: &CoordId) -> Result<HashAlgorithm> {
+        let mut conn = self.pool.acquire().await?;
+        Self::hash_algorithm_of(&mut conn, coord_id).await
+    }
+
+    /// Hash of the state a coordinate's first delta produces, or `None` if
+    /// it has no deltas
+    ///
+    /// A generated ID belongs to the state it was generated from, so a store
+    /// whose state hashes differently has collided with the coordinate.
+    pub async fn coordinate_initial_state_hash(&self, coord_id: &CoordId) -> Result<Option<Hash>> {
+        let row: Option<DeltaRow> = sqlx::query_as(
+            r#"
+            SELECT d.id, d.coord_id, d.parent_id, d.parent_hash, d.delta_hash, d.chain_hash,
+                   d.ops, d.created_at, d.tags, d.author, a.op_authors, d.signature_pubkey, d.signature_bytes
+            FROM deltas d
+            LEFT JOIN delta_op_authors a ON a.delta_id = d.id
+            WHERE d.coord_id = ?
+            ORDER BY d.created_at ASC, d.rowid ASC
+            LIMIT 1
+            "#,
+        )
+        .bind(&coord_id.0)
+        .fetch_optional(&self.pool)
+        .await?;
+        let Some(row) = row else {
+            return Ok(None);
+        };
+
+        let first: Delta = row.try_into()?;
+        let mut state = serde_json::json!({});
+        DeltaEngine::apply_delta(&mut state, &first.ops)?;
+        DeltaEngine::hash_state(&state).map(Some)
+    }
+
+    async fn hash_algorithm_of(conn: &mut SqliteConnection, coord_id: &CoordId) -> Result<HashAlgorithm> {
+        let algorithm: Option<String> = sqlx::query_scalar("SELECT hash_algorithm FROM coordinates WHERE id_ascii = ?")
+            .bind(&coord_id.0)
+            .fetch_optional(&mut *conn)
+            .await?;
+        algorithm.map_or(Ok(HashAlgorithm::default()), |algorithm| algorithm.parse())
+    }
+
+    /// List every coordinate ID
+    pub async fn list_coordinate_ids(&self) -> Result<Vec<CoordId>> {
+        let ids: Vec<String> = sqlx::query_scalar("SELECT id_ascii FROM coordinates")
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(ids.into_iter().map(CoordId).collect())
+    }
+
     /// Check if coordinate exists
     pub async fn coordinate_exists(&self, coord_id: &CoordId) -> Result<bool> {
         let count: i64 = sqlx::query_scalar(
@@ -94,22 +457,232 @@ impl BmsRepository {
         Ok(count > 0)
     }
 
+    /// Replace a coordinate's metadata, and its outgoing links when `links`
+    /// is set
+    ///
+    /// Returns false if the coordinate does not exist.
+    pub async fn update_coordinate_metadata(
+        &self,
+        coord_id: &CoordId,
+        metadata: &HashMap<String, Value>,
+        links: Option<&[Link]>,
+    ) -> Result<bool> {
+        let mut tx = self.pool.begin().await?;
+        if !Self::update_metadata_row(&mut tx, coord_id, metadata).await? {
+            return Ok(false);
+        }
+        if let Some(links) = links {
+            Self::replace_link_rows(&mut tx, coord_id, links).await?;
+        }
+        Self::append_oplog(&mut tx, &OplogRecord::metadata(coord_id, metadata)).await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    async fn update_metadata_row(
+        conn: &mut SqliteConnection,
+        coord_id: &CoordId,
+        metadata: &HashMap<String, Value>,
+    ) -> Result<bool> {
+        let updated = sqlx::query("UPDATE coordinates SET metadata = ? WHERE id_ascii = ?")
+            .bind(serde_json::to_string(metadata)?)
+            .bind(&coord_id.0)
+            .execute(&mut *conn)
+            .await?
+            .rows_affected();
+
+        Ok(updated > 0)
+    }
+
+    /// Coordinates whose ID starts with `prefix`, in ID order
+    pub async fn find_coordinates_by_prefix(&self, prefix: &str, limit: i64) -> Result<Vec<CoordId>> {
+        let ids: Vec<String> = sqlx::query_scalar(
+            r#"
+            SELECT id_ascii FROM coordinates
+            WHERE id_ascii >= ? AND id_ascii < ?
+            ORDER BY id_ascii
+            LIMIT ?
+            "#,
+        )
+        .bind(prefix)
+        .bind(prefix_upper_bound(prefix))
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(ids.into_iter().map(CoordId).collect())
+    }
+
+    /// The one coordinate whose ID starts with `prefix`, like a git short hash
+    ///
+    /// The prefix must be uppercase base32, as generated IDs are, and no
+    /// longer than one. Fails with `AmbiguousPrefix` listing up to
+    /// `MAX_PREFIX_CANDIDATES` IDs when several match.
+    pub async fn resolve_coord_prefix(&self, prefix: &str) -> Result<CoordId> {
+        if prefix.is_empty() || prefix.len() > COORD_ID_CHARS {
+            return Err(BmsError::InvalidCoordinate(format!(
+                "A coordinate ID prefix has 1 to {} characters, got {}",
+                COORD_ID_CHARS,
+                prefix.len()
+            )));
+        }
+        if let Some((idx, c)) = prefix.char_indices().find(|&(_, c)| !matches!(c, 'A'..='Z' | '2'..='7')) {
+            return Err(BmsError::InvalidCoordinate(format!(
+                "Invalid base32 character {:?} at position {}",
+                c, idx
+            )));
+        }
+
+        let mut matches = self.find_coordinates_by_prefix(prefix, MAX_PREFIX_CANDIDATES as i64 + 1).await?;
+        match matches.len() {
+            0 => Err(BmsError::InvalidCoordinate(format!("No coordinate ID starts with {}", prefix))),
+            1 => Ok(matches.remove(0)),
+            _ => Err(BmsError::AmbiguousPrefix {
+                prefix: prefix.to_string(),
+                candidates: matches.into_iter().take(MAX_PREFIX_CANDIDATES).map(|id| id.0).collect(),
+            }),
+        }
+    }
+
+    /// Coordinates with the given rune alias, in ID order
+    pub async fn find_coordinates_by_alias(&self, alias: &str) -> Result<Vec<CoordId>> {
+        let ids: Vec<String> = sqlx::query_scalar(
+            "SELECT id_ascii FROM coordinates WHERE rune_alias = ? ORDER BY id_ascii",
+        )
+        .bind(alias)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(ids.into_iter().map(CoordId).collect())
+    }
+
+    /// Deltas of a coordinate whose ID starts with `prefix`, in ID order
+    pub async fn find_deltas_by_prefix(
+        &self,
+        coord_id: &CoordId,
+        prefix: &str,
+        limit: i64,
+    ) -> Result<Vec<DeltaId>> {
+        let ids: Vec<String> = sqlx::query_scalar(
+            r#"
+            SELECT id FROM deltas
+            WHERE id >= ? AND id < ? AND coord_id = ?
+            ORDER BY id
+            LIMIT ?
+            "#,
+        )
+        .bind(prefix)
+        .bind(prefix_upper_bound(prefix))
+        .bind(&coord_id.0)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(ids.into_iter().map(DeltaId).collect())
+    }
+
+    /// Delete a coordinate with its deltas, snapshots, read statistics, and
+    /// outgoing links
+    ///
+    /// Links from other coordinates are kept and become dangling.
+    ///
+    /// Returns false if the coordinate did not exist.
+    pub async fn delete_coordinate(&self, coord_id: &CoordId) -> Result<bool> {
+        let mut tx = self.pool.begin().await?;
+        let deleted = Self::delete_coordinate_rows(&mut tx, coord_id).await?;
+        if deleted {
+            Self::append_oplog(&mut tx, &OplogRecord::new(OpKind::CoordinateDeleted, coord_id)).await?;
+        }
+        tx.commit().await?;
+        Ok(deleted)
+    }
+
+    async fn delete_coordinate_rows(conn: &mut SqliteConnection, coord_id: &CoordId) -> Result<bool> {
+        sqlx::query("DELETE FROM delta_op_authors WHERE delta_id IN (SELECT id FROM deltas WHERE coord_id = ?)")
+            .bind(&coord_id.0)
+            .execute(&mut *conn)
+            .await?;
+        sqlx::query("DELETE FROM snapshot_blobs WHERE snapshot_id IN (SELECT id FROM snapshots WHERE coord_id = ?)")
+            .bind(&coord_id.0)
+            .execute(&mut *conn)
+            .await?;
+        for table in [
+            "snapshots",
+            "deltas",
+            "coord_access",
+            "coord_importance",
+            "coord_size",
+            "head_states",
+            "reconstruction_checkpoints",
+        ] {
+            sqlx::query(&format!("DELETE FROM {} WHERE coord_id = ?", table))
+                .bind(&coord_id.0)
+                .execute(&mut *conn)
+                .await?;
+        }
+        sqlx::query("DELETE FROM links WHERE from_coord = ?")
+            .bind(&coord_id.0)
+            .execute(&mut *conn)
+            .await?;
+        Self::delete_unreferenced_blobs(conn).await?;
+        let deleted = sqlx::query("DELETE FROM coordinates WHERE id_ascii = ?")
+            .bind(&coord_id.0)
+            .execute(&mut *conn)
+            .await?
+            .rows_affected();
+
+        Ok(deleted > 0)
+    }
+
     /// Insert a new delta
     pub async fn insert_delta(&self, delta: &Delta) -> Result<()> {
-        let ops_json = serde_json::to_string(&delta.ops)?;
+        self.insert_head(delta, &HeadRows::default(), None).await
+    }
+
+    /// Insert a new head delta and replace the head rows that are set in the
+    /// same transaction
+    ///
+    /// A `precondition` is checked against the coordinate's tip in that
+    /// transaction, so a writer in another process cannot move the head
+    /// between the check and the insert.
+    pub async fn insert_head(
+        &self,
+        delta: &Delta,
+        head: &HeadRows,
+        precondition: Option<&StorePrecondition>,
+    ) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        if let Some(precondition) = precondition {
+            let tip: Option<(String, String)> = sqlx::query_as(
+                "SELECT id, chain_hash FROM deltas WHERE coord_id = ? ORDER BY created_at DESC, rowid DESC LIMIT 1",
+            )
+            .bind(&delta.coord_id.0)
+            .fetch_optional(&mut *tx)
+            .await?;
+            precondition.check(tip.as_ref().map(|(id, hash)| (id.as_str(), hash.as_str())))?;
+        }
+        Self::insert_delta_row(&mut tx, delta, self.delta_encoding).await?;
+        Self::replace_head_rows(&mut tx, delta, head).await?;
+        Self::append_oplog(&mut tx, &OplogRecord::delta(delta)).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn insert_delta_row(conn: &mut SqliteConnection, delta: &Delta, encoding: DeltaEncoding) -> Result<()> {
+        let ops = DeltaEngine::encode_ops_as(&delta.ops, encoding)?;
         let tags_json = delta
             .tags
             .as_ref()
-            .map(|t| serde_json::to_string(t))
+            .map(serde_json::to_string)
             .transpose()?;
 
         sqlx::query(
             r#"
             INSERT INTO deltas (
                 id, coord_id, parent_id, parent_hash, delta_hash, chain_hash,
-                ops, created_at, tags, author
+                ops, created_at, tags, author, signature_pubkey, signature_bytes
             )
-            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
             "#,
         )
         .bind(&delta.id.0)
@@ -118,25 +691,321 @@ impl BmsRepository {
         .bind(delta.parent_hash.as_ref().map(|h| &h.0))
         .bind(&delta.delta_hash.0)
         .bind(&delta.chain_hash.0)
-        .bind(ops_json)
+        .bind(ops)
         .bind(delta.created_at)
         .bind(tags_json)
         .bind(&delta.author)
-        .execute(&self.pool)
+        .bind(delta.signature.as_ref().map(|s| s.public_key.to_vec()))
+        .bind(delta.signature.as_ref().map(|s| s.signature_bytes.to_vec()))
+        .execute(&mut *conn)
+        .await?;
+
+        if let Some(op_authors) = &delta.op_authors {
+            sqlx::query("INSERT INTO delta_op_authors (delta_id, op_authors) VALUES (?, ?)")
+                .bind(&delta.id.0)
+                .bind(serde_json::to_string(op_authors)?)
+                .execute(&mut *conn)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Insert coordinates and deltas in one transaction
+    ///
+    /// Coordinates that already exist are left as they are. Each entry of
+    /// `heads` replaces the head rows of its coordinate, whose head is its
+    /// last delta in `deltas`.
+    pub async fn insert_group(
+        &self,
+        coordinates: &[Coordinate],
+        deltas: &[Delta],
+        heads: &[(CoordId, HeadRows)],
+    ) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        for coord in coordinates {
+            if Self::insert_coordinate_row(&mut tx, coord).await? {
+                let record = OplogRecord::new(OpKind::CoordinateCreated, &coord.id);
+                Self::append_oplog(&mut tx, &record).await?;
+            }
+        }
+        for delta in deltas {
+            Self::insert_delta_row(&mut tx, delta, self.delta_encoding).await?;
+            Self::append_oplog(&mut tx, &OplogRecord::delta(delta)).await?;
+        }
+        for (coord_id, head) in heads {
+            let Some(head_delta) = deltas.iter().rev().find(|d| &d.coord_id == coord_id) else {
+                return Err(BmsError::InvalidState(format!("No delta for head of {}", coord_id)));
+            };
+            Self::replace_head_rows(&mut tx, head_delta, head).await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Squash all but the last `keep_last` deltas of a coordinate into one
+    ///
+    /// The squashed delta starts the chain, anchored by a fresh snapshot of
+    /// the state it produces. Kept deltas keep their IDs and ops; their parent
+    /// links and chain hashes are recomputed. The coordinate's other
+    /// snapshots and replay checkpoints are dropped. Nothing is written if
+    /// the chain does not verify, and everything else in one transaction.
+    /// Writers to the coordinate must be kept out meanwhile (the facade's
+    /// `compact` does); a head that moved fails with `PreconditionFailed`.
+    pub async fn compact_coordinate(&self, coord_id: &CoordId, keep_last: usize) -> Result<CompactReport> {
+        let deltas = self.get_deltas(coord_id).await?;
+        let algorithm = self.get_hash_algorithm(coord_id).await?;
+        let mut head = None;
+        for delta in &deltas {
+            oplog::verify_append(head, delta, algorithm)?;
+            head = Some((delta.id.clone(), delta.chain_hash.clone()));
+        }
+        let split = deltas.len().saturating_sub(keep_last);
+        if split < 2 {
+            return Ok(CompactReport {
+                coord_id: coord_id.clone(),
+                squashed: 0,
+                kept: deltas.len(),
+                ops: 0,
+                snapshot_id: None,
+            });
+        }
+
+        let (old, kept) = deltas.split_at(split);
+        let ops = DeltaEngine::squash(old)?;
+        let mut state = serde_json::json!({});
+        for delta in old {
+            DeltaEngine::apply_delta(&mut state, &delta.ops)?;
+        }
+        let mut squashed_state = serde_json::json!({});
+        DeltaEngine::apply_delta(&mut squashed_state, &ops)?;
+        if squashed_state != state {
+            return Err(BmsError::DeltaCompression(format!(
+                "Squashing {} deltas of {} changed the state they produce",
+                split, coord_id
+            )));
+        }
+
+        let delta_hash = DeltaEngine::hash_delta(&ops)?;
+        let mut chain = vec![Delta {
+            id: DeltaEngine::generate_delta_id(&ops)?,
+            coord_id: coord_id.clone(),
+            parent_id: None,
+            parent_hash: None,
+            chain_hash: delta_hash.clone(),
+            delta_hash,
+            ops,
+            created_at: old[split - 1].created_at,
+            tags: Some(HashMap::from([(SQUASHED_TAG.to_string(), Value::from(split))])),
+            author: None,
+            op_authors: None,
+            signature: None,
+        }];
+        for delta in kept {
+            let parent = &chain[chain.len() - 1];
+            let rewritten = Delta {
+                parent_id: Some(parent.id.clone()),
+                parent_hash: Some(parent.chain_hash.clone()),
+                chain_hash: MerkleChain::compute_chain_hash_with(&parent.chain_hash, &delta.delta_hash, algorithm),
+                ..delta.clone()
+            };
+            chain.push(rewritten);
+        }
+        let snapshot = Snapshot::capture(coord_id.clone(), chain[0].id.clone(), state)?;
+
+        let mut tx = self.pool.begin().await?;
+        let tip: Option<String> = sqlx::query_scalar(
+            "SELECT id FROM deltas WHERE coord_id = ? ORDER BY created_at DESC, rowid DESC LIMIT 1",
+        )
+        .bind(&coord_id.0)
+        .fetch_optional(&mut *tx)
         .await?;
+        let expected = &deltas[deltas.len() - 1].id.0;
+        if tip.as_ref() != Some(expected) {
+            return Err(BmsError::PreconditionFailed {
+                expected: expected.clone(),
+                actual: tip.unwrap_or_else(|| "none".to_string()),
+                head_chain_hash: None,
+            });
+        }
+        Self::replace_chain_rows(&mut tx, coord_id, &chain, self.delta_encoding).await?;
+        Self::insert_snapshot_row(&mut tx, &snapshot).await?;
+        Self::append_oplog(&mut tx, &OplogRecord::compacted(&chain[chain.len() - 1], keep_last)).await?;
+        Self::append_oplog(&mut tx, &OplogRecord::snapshot(&snapshot)).await?;
+        tx.commit().await?;
+        info!("Compacted {}: squashed {} deltas, kept {}", coord_id, split, kept.len());
+
+        Ok(CompactReport {
+            coord_id: coord_id.clone(),
+            squashed: split,
+            kept: kept.len(),
+            ops: chain[0].ops.len(),
+            snapshot_id: Some(snapshot.id),
+        })
+    }
+
+    /// Replace a coordinate's chain with `chain`, dropping the snapshots and
+    /// checkpoints of the old one and pointing the head rows at the new tip
+    ///
+    /// The head state must be the same, as it is after a compaction.
+    async fn replace_chain_rows(
+        conn: &mut SqliteConnection,
+        coord_id: &CoordId,
+        chain: &[Delta],
+        encoding: DeltaEncoding,
+    ) -> Result<()> {
+        let tip = chain
+            .last()
+            .ok_or_else(|| BmsError::InvalidState(format!("Empty replacement chain for {}", coord_id)))?;
+        sqlx::query("DELETE FROM delta_op_authors WHERE delta_id IN (SELECT id FROM deltas WHERE coord_id = ?)")
+            .bind(&coord_id.0)
+            .execute(&mut *conn)
+            .await?;
+        sqlx::query("DELETE FROM snapshot_blobs WHERE snapshot_id IN (SELECT id FROM snapshots WHERE coord_id = ?)")
+            .bind(&coord_id.0)
+            .execute(&mut *conn)
+            .await?;
+        for table in ["snapshots", "deltas", "reconstruction_checkpoints"] {
+            sqlx::query(&format!("DELETE FROM {} WHERE coord_id = ?", table))
+                .bind(&coord_id.0)
+                .execute(&mut *conn)
+                .await?;
+        }
+        Self::delete_unreferenced_blobs(conn).await?;
+
+        // Inserted in chain order, which ties on `created_at` fall back to
+        for delta in chain {
+            Self::insert_delta_row(conn, delta, encoding).await?;
+        }
+        sqlx::query("UPDATE coord_size SET head_delta_id = ? WHERE coord_id = ?")
+            .bind(&tip.id.0)
+            .bind(&coord_id.0)
+            .execute(&mut *conn)
+            .await?;
+        sqlx::query("UPDATE head_states SET head_delta_id = ?, chain_hash = ? WHERE coord_id = ?")
+            .bind(&tip.id.0)
+            .bind(&tip.chain_hash.0)
+            .bind(&coord_id.0)
+            .execute(&mut *conn)
+            .await?;
+        Ok(())
+    }
+
+    async fn replace_head_rows(conn: &mut SqliteConnection, head_delta: &Delta, head: &HeadRows) -> Result<()> {
+        let (coord_id, head_delta_id) = (&head_delta.coord_id, &head_delta.id);
+        if let Some(links) = &head.links {
+            Self::replace_link_rows(&mut *conn, coord_id, links).await?;
+        }
+        if let Some(state_bytes) = head.state_bytes {
+            sqlx::query(
+                r#"
+                INSERT INTO coord_size (coord_id, head_delta_id, state_bytes, updated_at)
+                VALUES (?, ?, ?, ?)
+                ON CONFLICT(coord_id) DO UPDATE SET
+                    head_delta_id = excluded.head_delta_id,
+                    state_bytes = excluded.state_bytes,
+                    updated_at = excluded.updated_at
+                "#,
+            )
+            .bind(&coord_id.0)
+            .bind(&head_delta_id.0)
+            .bind(state_bytes as i64)
+            .bind(Utc::now())
+            .execute(&mut *conn)
+            .await?;
+        }
+        if let Some(state) = &head.materialized {
+            sqlx::query(
+                r#"
+                INSERT OR REPLACE INTO head_states (coord_id, head_delta_id, chain_hash, state, updated_at)
+                VALUES (?, ?, ?, ?, ?)
+                "#,
+            )
+            .bind(&coord_id.0)
+            .bind(&head_delta_id.0)
+            .bind(&head_delta.chain_hash.0)
+            .bind(deflate_json(state)?)
+            .bind(Utc::now())
+            .execute(&mut *conn)
+            .await?;
+        }
+        Ok(())
+    }
+
+    async fn replace_link_rows(conn: &mut SqliteConnection, coord_id: &CoordId, links: &[Link]) -> Result<()> {
+        sqlx::query("DELETE FROM links WHERE from_coord = ?")
+            .bind(&coord_id.0)
+            .execute(&mut *conn)
+            .await?;
+        for link in links {
+            sqlx::query("INSERT INTO links (from_coord, pointer, to_coord) VALUES (?, ?, ?)")
+                .bind(&coord_id.0)
+                .bind(&link.pointer)
+                .bind(&link.to_coord.0)
+                .execute(&mut *conn)
+                .await?;
+        }
 
         Ok(())
     }
 
+    /// Links from a coordinate's head to other coordinates
+    pub async fn get_links(&self, coord_id: &CoordId) -> Result<Vec<CoordLink>> {
+        let rows: Vec<LinkRow> = sqlx::query_as(
+            "SELECT from_coord, pointer, to_coord FROM links WHERE from_coord = ? ORDER BY pointer",
+        )
+        .bind(&coord_id.0)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|r| r.into()).collect())
+    }
+
+    /// Links from other coordinates' heads to a coordinate, which need not exist
+    pub async fn get_backlinks(&self, coord_id: &CoordId) -> Result<Vec<CoordLink>> {
+        let rows: Vec<LinkRow> = sqlx::query_as(
+            r#"
+            SELECT from_coord, pointer, to_coord FROM links
+            WHERE to_coord = ? AND from_coord != to_coord
+            ORDER BY from_coord, pointer
+            "#,
+        )
+        .bind(&coord_id.0)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|r| r.into()).collect())
+    }
+
+    /// Every link, or only those whose target coordinate does not exist
+    pub async fn list_links(&self, dangling_only: bool) -> Result<Vec<CoordLink>> {
+        let rows: Vec<LinkRow> = sqlx::query_as(
+            r#"
+            SELECT l.from_coord, l.pointer, l.to_coord FROM links l
+            LEFT JOIN coordinates c ON c.id_ascii = l.to_coord
+            WHERE NOT ? OR c.id_ascii IS NULL
+            ORDER BY l.from_coord, l.pointer
+            "#,
+        )
+        .bind(dangling_only)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|r| r.into()).collect())
+    }
+
     /// Get deltas for a coordinate
     pub async fn get_deltas(&self, coord_id: &CoordId) -> Result<Vec<Delta>> {
         let rows: Vec<DeltaRow> = sqlx::query_as(
             r#"
-            SELECT id, coord_id, parent_id, parent_hash, delta_hash, chain_hash,
-                   ops, created_at, tags, author
-            FROM deltas
-            WHERE coord_id = ?
-            ORDER BY created_at ASC
+            SELECT d.id, d.coord_id, d.parent_id, d.parent_hash, d.delta_hash, d.chain_hash,
+                   d.ops, d.created_at, d.tags, d.author, a.op_authors, d.signature_pubkey, d.signature_bytes
+            FROM deltas d
+            LEFT JOIN delta_op_authors a ON a.delta_id = d.id
+            WHERE d.coord_id = ?
+            ORDER BY d.created_at ASC, d.rowid ASC
             "#,
         )
         .bind(&coord_id.0)
@@ -146,14 +1015,171 @@ impl BmsRepository {
         rows.into_iter().map(|r| r.try_into()).collect()
     }
 
+    /// Get deltas for a coordinate, leaving out what does not decode
+    ///
+    /// Ops that do not decode are skipped with a warning and rows that do
+    /// not decode at all are left out, so a chain with a few corrupt rows
+    /// can still be inspected; `scan_corrupt_rows` lists them.
+    pub async fn get_deltas_lenient(&self, coord_id: &CoordId) -> Result<Vec<Delta>> {
+        let rows: Vec<DeltaRow> = sqlx::query_as(
+            r#"
+            SELECT d.id, d.coord_id, d.parent_id, d.parent_hash, d.delta_hash, d.chain_hash,
+                   d.ops, d.created_at, d.tags, d.author, a.op_authors, d.signature_pubkey, d.signature_bytes
+            FROM deltas d
+            LEFT JOIN delta_op_authors a ON a.delta_id = d.id
+            WHERE d.coord_id = ?
+            ORDER BY d.created_at ASC, d.rowid ASC
+            "#,
+        )
+        .bind(&coord_id.0)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut deltas = Vec::with_capacity(rows.len());
+        for row in rows {
+            match row.decode(true) {
+                Ok((delta, _)) => deltas.push(delta),
+                Err(e) => warn!("Leaving out a row that cannot be decoded: {}", e),
+            }
+        }
+        Ok(deltas)
+    }
+
+    /// Rewrite stored delta ops that are not stored as this build writes
+    /// them: in the configured encoding, and compressed if large with the
+    /// `compressed-ops` feature
+    ///
+    /// Hashes are over canonical JSON, so no ID or chain hash changes. Rows
+    /// are read in pages of `SCAN_PAGE_SIZE`, each rewritten in its own
+    /// transaction, so an interrupted run can simply be repeated. Returns
+    /// the number of rows rewritten.
+    pub async fn recode_deltas(&self) -> Result<u64> {
+        let mut recoded = 0;
+        let mut after = String::new();
+        loop {
+            let rows: Vec<(String, Vec<u8>)> =
+                sqlx::query_as("SELECT id, CAST(ops AS BLOB) FROM deltas WHERE id > ? ORDER BY id LIMIT ?")
+                    .bind(&after)
+                    .bind(SCAN_PAGE_SIZE)
+                    .fetch_all(&self.pool)
+                    .await?;
+            let Some((last, _)) = rows.last() else { break };
+            after = last.clone();
+
+            let mut tx = self.pool.begin().await?;
+            for (id, stored) in rows {
+                let ops = DeltaEngine::encode_ops_as(&DeltaEngine::decode_ops(&stored)?, self.delta_encoding)?;
+                if ops == stored {
+                    continue;
+                }
+                sqlx::query("UPDATE deltas SET ops = ? WHERE id = ?")
+                    .bind(ops)
+                    .bind(&id)
+                    .execute(&mut *tx)
+                    .await?;
+                recoded += 1;
+            }
+            tx.commit().await?;
+        }
+        Ok(recoded)
+    }
+
+    /// Every delta and snapshot row that does not decode, or only decodes
+    /// by skipping ops
+    ///
+    /// Columns are cast to text, so values of the wrong storage type are
+    /// reported as well. Rows are read in pages of `SCAN_PAGE_SIZE`.
+    pub async fn scan_corrupt_rows(&self) -> Result<Vec<CorruptRow>> {
+        let mut corrupt = Vec::new();
+        let report = |table, id: &str, coord_id: &str, reason: String| CorruptRow {
+            table,
+            id: id.to_string(),
+            coord_id: coord_id.to_string(),
+            reason,
+        };
+
+        let mut after = String::new();
+        loop {
+            let rows: Vec<DeltaRow> = sqlx::query_as(
+                r#"
+                SELECT d.id, CAST(d.coord_id AS TEXT) AS coord_id, CAST(d.parent_id AS TEXT) AS parent_id,
+                       CAST(d.parent_hash AS TEXT) AS parent_hash, CAST(d.delta_hash AS TEXT) AS delta_hash,
+                       CAST(d.chain_hash AS TEXT) AS chain_hash, CAST(d.ops AS BLOB) AS ops,
+                       CAST(d.created_at AS TEXT) AS created_at, CAST(d.tags AS TEXT) AS tags,
+                       CAST(d.author AS TEXT) AS author, CAST(a.op_authors AS TEXT) AS op_authors,
+                       CAST(d.signature_pubkey AS BLOB) AS signature_pubkey,
+                       CAST(d.signature_bytes AS BLOB) AS signature_bytes
+                FROM deltas d
+                LEFT JOIN delta_op_authors a ON a.delta_id = d.id
+                WHERE d.id > ?
+                ORDER BY d.id
+                LIMIT ?
+                "#,
+            )
+            .bind(&after)
+            .bind(SCAN_PAGE_SIZE)
+            .fetch_all(&self.pool)
+            .await?;
+            let Some(last) = rows.last() else { break };
+            after = last.id.clone();
+            for row in rows {
+                let (id, coord_id) = (row.id.clone(), row.coord_id.clone());
+                match row.decode(true) {
+                    Ok((_, skipped)) if skipped.is_empty() => {}
+                    Ok((_, skipped)) => {
+                        corrupt.push(report("deltas", &id, &coord_id, format!("skipped {}", skipped.join("; "))))
+                    }
+                    Err(BmsError::CorruptRow { source, .. }) => {
+                        corrupt.push(report("deltas", &id, &coord_id, source.to_string()))
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
+        let mut after = String::new();
+        loop {
+            let rows: Vec<SnapshotRow> = sqlx::query_as(
+                r#"
+                SELECT id, CAST(coord_id AS TEXT) AS coord_id, CAST(head_delta_id AS TEXT) AS head_delta_id,
+                       CAST(state_hash AS TEXT) AS state_hash, CAST(state AS TEXT) AS state,
+                       CAST(created_at AS TEXT) AS created_at
+                FROM snapshots
+                WHERE id > ?
+                ORDER BY id
+                LIMIT ?
+                "#,
+            )
+            .bind(&after)
+            .bind(SCAN_PAGE_SIZE)
+            .fetch_all(&self.pool)
+            .await?;
+            let Some(last) = rows.last() else { break };
+            after = last.id.clone();
+            for row in rows {
+                let (id, coord_id) = (row.id.clone(), row.coord_id.clone());
+                match Snapshot::try_from(row) {
+                    Ok(_) => {}
+                    Err(BmsError::CorruptRow { source, .. }) => {
+                        corrupt.push(report("snapshots", &id, &coord_id, source.to_string()))
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
+        Ok(corrupt)
+    }
+
     /// Get delta by ID
     pub async fn get_delta(&self, delta_id: &DeltaId) -> Result<Option<Delta>> {
         let row: Option<DeltaRow> = sqlx::query_as(
             r#"
-            SELECT id, coord_id, parent_id, parent_hash, delta_hash, chain_hash,
-                   ops, created_at, tags, author
-            FROM deltas
-            WHERE id = ?
+            SELECT d.id, d.coord_id, d.parent_id, d.parent_hash, d.delta_hash, d.chain_hash,
+                   d.ops, d.created_at, d.tags, d.author, a.op_authors, d.signature_pubkey, d.signature_bytes
+            FROM deltas d
+            LEFT JOIN delta_op_authors a ON a.delta_id = d.id
+            WHERE d.id = ?
             "#,
         )
         .bind(&delta_id.0)
@@ -179,7 +1205,31 @@ impl BmsRepository {
 
     /// Insert a snapshot
     pub async fn insert_snapshot(&self, snapshot: &Snapshot) -> Result<()> {
-        let state_json = serde_json::to_string(&snapshot.state)?;
+        let mut tx = self.pool.begin().await?;
+        Self::insert_snapshot_row(&mut tx, snapshot).await?;
+        Self::append_oplog(&mut tx, &OplogRecord::snapshot(snapshot)).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn insert_snapshot_row(conn: &mut SqliteConnection, snapshot: &Snapshot) -> Result<()> {
+        // Fields listed in the coordinate's `snapshot_externalize` go to the blob store
+        let metadata: Option<Option<String>> =
+            sqlx::query_scalar("SELECT metadata FROM coordinates WHERE id_ascii = ?")
+                .bind(&snapshot.coord_id.0)
+                .fetch_optional(&mut *conn)
+                .await?;
+        let pointers = match metadata.flatten() {
+            Some(json) => externalize_pointers(&serde_json::from_str(&json)?)?.unwrap_or_default(),
+            None => Vec::new(),
+        };
+        let (state_json, blobs) = if pointers.is_empty() {
+            (serde_json::to_string(&snapshot.state)?, Vec::new())
+        } else {
+            let mut state = snapshot.state.clone();
+            let blobs = externalize(&mut state, &pointers);
+            (serde_json::to_string(&state)?, blobs)
+        };
 
         sqlx::query(
             r#"
@@ -193,7 +1243,40 @@ impl BmsRepository {
         .bind(&snapshot.state_hash.0)
         .bind(state_json)
         .bind(snapshot.created_at)
-        .execute(&self.pool)
+        .execute(&mut *conn)
+        .await?;
+
+        // An unchanged field is stored once, however many snapshots hold it
+        for (pointer, value) in blobs {
+            let hash = DeltaEngine::hash_state(&value)?;
+            sqlx::query("INSERT OR IGNORE INTO blobs (hash, value, created_at) VALUES (?, ?, ?)")
+                .bind(&hash.0)
+                .bind(deflate_json(&value)?)
+                .bind(snapshot.created_at)
+                .execute(&mut *conn)
+                .await?;
+            sqlx::query("INSERT OR REPLACE INTO snapshot_blobs (snapshot_id, pointer, hash) VALUES (?, ?, ?)")
+                .bind(&snapshot.id.0)
+                .bind(pointer)
+                .bind(&hash.0)
+                .execute(&mut *conn)
+                .await?;
+        }
+
+        // Checkpoints up to the snapshot's position in the chain are superseded
+        sqlx::query(
+            r#"
+            DELETE FROM reconstruction_checkpoints
+            WHERE coord_id = ?1 AND seq <= (
+                SELECT COUNT(*) FROM deltas d, deltas h
+                WHERE h.id = ?2 AND d.coord_id = ?1
+                  AND (d.created_at < h.created_at OR (d.created_at = h.created_at AND d.rowid <= h.rowid))
+            )
+            "#,
+        )
+        .bind(&snapshot.coord_id.0)
+        .bind(&snapshot.head_delta_id.0)
+        .execute(&mut *conn)
         .await?;
 
         Ok(())
@@ -214,7 +1297,18 @@ impl BmsRepository {
         .fetch_optional(&self.pool)
         .await?;
 
-        row.map(|r| r.try_into()).transpose()
+        self.inline_blobs(row).await
+    }
+
+    /// When the latest snapshot of a coordinate was taken, without loading it
+    pub async fn latest_snapshot_at(&self, coord_id: &CoordId) -> Result<Option<DateTime<Utc>>> {
+        let taken = sqlx::query_scalar(
+            "SELECT created_at FROM snapshots WHERE coord_id = ? ORDER BY created_at DESC LIMIT 1",
+        )
+        .bind(&coord_id.0)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(taken)
     }
 
     /// Get snapshot by ID
@@ -230,28 +1324,1093 @@ impl BmsRepository {
         .fetch_optional(&self.pool)
         .await?;
 
-        row.map(|r| r.try_into()).transpose()
+        self.inline_blobs(row).await
     }
 
-    /// Get all coordinates
-    pub async fn list_coordinates(&self, limit: Option<i64>) -> Result<Vec<Coordinate>> {
-        let limit = limit.unwrap_or(100);
+    /// Convert a snapshot row, putting its externalized fields back in place
+    async fn inline_blobs(&self, row: Option<SnapshotRow>) -> Result<Option<Snapshot>> {
+        let Some(row) = row else {
+            return Ok(None);
+        };
+        let mut snapshot: Snapshot = row.try_into()?;
 
-        let rows: Vec<CoordRow> = sqlx::query_as(
+        let blobs: Vec<(String, String, Option<Vec<u8>>)> = sqlx::query_as(
             r#"
-            SELECT id_ascii, rune_alias, created_at, metadata
-            FROM coordinates
-            ORDER BY created_at DESC
-            LIMIT ?
+            SELECT sb.pointer, sb.hash, b.value
+            FROM snapshot_blobs sb LEFT JOIN blobs b ON b.hash = sb.hash
+            WHERE sb.snapshot_id = ?
             "#,
         )
-        .bind(limit)
+        .bind(&snapshot.id.0)
         .fetch_all(&self.pool)
         .await?;
+        for (pointer, hash, value) in blobs {
+            let value = value.ok_or_else(|| {
+                BmsError::ReconstructionFailed(format!("Blob {} of snapshot {} is missing", hash, snapshot.id))
+            })?;
+            inline(&mut snapshot.state, &pointer, inflate_json(&value)?)?;
+        }
+
+        Ok(Some(snapshot))
+    }
+
+    /// Drop blobs no snapshot refers to any more
+    async fn delete_unreferenced_blobs(conn: &mut SqliteConnection) -> Result<u64> {
+        let deleted = sqlx::query("DELETE FROM blobs WHERE hash NOT IN (SELECT hash FROM snapshot_blobs)")
+            .execute(&mut *conn)
+            .await?
+            .rows_affected();
+        Ok(deleted)
+    }
+
+    /// Get all coordinates, or those in a namespace
+    ///
+    /// A namespace also matches the ones nested under it, so `agents` lists
+    /// `agents/planner` too. `DEFAULT_NAMESPACE` matches coordinates stored
+    /// without a namespace.
+    pub async fn list_coordinates(&self, namespace: Option<&str>, limit: Option<i64>) -> Result<Vec<Coordinate>> {
+        let limit = limit.unwrap_or(100);
+
+        let rows: Vec<CoordRow> = match namespace {
+            None => {
+                sqlx::query_as(
+                    r#"
+                    SELECT id_ascii, rune_alias, created_at, metadata, namespace, hash_algorithm, id_kind,
+                           parent_coord_id
+                    FROM coordinates
+                    ORDER BY created_at DESC
+                    LIMIT ?
+                    "#,
+                )
+                .bind(limit)
+                .fetch_all(&self.pool)
+                .await?
+            }
+            Some(namespace) => {
+                CoordinateGenerator::validate_namespace(namespace)?;
+                let nested = format!("{}/", namespace);
+                sqlx::query_as(
+                    r#"
+                    SELECT id_ascii, rune_alias, created_at, metadata, namespace, hash_algorithm, id_kind,
+                           parent_coord_id
+                    FROM coordinates
+                    WHERE (namespace IS NULL AND ? = ?)
+                       OR namespace = ?
+                       OR (namespace >= ? AND namespace < ?)
+                    ORDER BY created_at DESC
+                    LIMIT ?
+                    "#,
+                )
+                .bind(namespace)
+                .bind(DEFAULT_NAMESPACE)
+                .bind(namespace)
+                .bind(&nested)
+                .bind(prefix_upper_bound(&nested))
+                .bind(limit)
+                .fetch_all(&self.pool)
+                .await?
+            }
+        };
+
+        Ok(rows.into_iter().map(|r| r.into()).collect())
+    }
+
+    /// List coordinates matching a filter, newest first
+    ///
+    /// The filter must be free of author and head-state predicates, which
+    /// coordinate rows cannot answer; `FilterExpr::pushdown` gives that part
+    /// of a larger filter.
+    pub async fn list_coordinates_where(&self, filter: &FilterExpr, limit: Option<i64>) -> Result<Vec<Coordinate>> {
+        let Some((clause, args)) = filter_sql::where_clause(filter) else {
+            return Err(BmsError::InvalidState(
+                "Author and head state filters cannot run against coordinate rows".to_string(),
+            ));
+        };
+        let sql = format!(
+            "SELECT id_ascii, rune_alias, created_at, metadata, namespace, hash_algorithm, id_kind, parent_coord_id \
+             FROM coordinates WHERE {} ORDER BY created_at DESC LIMIT ?",
+            clause
+        );
+        let mut query = sqlx::query_as::<_, CoordRow>(&sql);
+        for arg in args {
+            query = match arg {
+                SqlArg::Text(text) => query.bind(text),
+                SqlArg::Real(number) => query.bind(number),
+                SqlArg::Time(time) => query.bind(time),
+            };
+        }
+        let rows = query.bind(limit.unwrap_or(100)).fetch_all(&self.pool).await?;
 
         Ok(rows.into_iter().map(|r| r.into()).collect())
     }
 
+    /// Add batched read counts to the access statistics
+    ///
+    /// With an importance policy, each read also adds its `access_bump` to the
+    /// coordinate's importance.
+    pub async fn record_access_batch(
+        &self,
+        entries: &[AccessRecord],
+        importance: Option<&ImportancePolicy>,
+    ) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        for entry in entries {
+            if let Some(policy) = importance.filter(|p| p.access_bump != 0.0) {
+                let delta = policy.access_bump * entry.read_count as f32;
+                Self::reinforce_importance_row(&mut tx, &entry.coord_id, delta, policy, entry.last_read_at)
+                    .await?;
+            }
+            sqlx::query(
+                r#"
+                INSERT INTO coord_access (coord_id, read_count, last_read_at)
+                SELECT ?, ?, ?
+                WHERE EXISTS (SELECT 1 FROM coordinates WHERE id_ascii = ?)
+                ON CONFLICT(coord_id) DO UPDATE SET
+                    read_count = read_count + excluded.read_count,
+                    last_read_at = MAX(last_read_at, excluded.last_read_at)
+                "#,
+            )
+            .bind(&entry.coord_id.0)
+            .bind(entry.read_count as i64)
+            .bind(entry.last_read_at)
+            .bind(&entry.coord_id.0)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Get the most-read coordinates with their chain length
+    pub async fn get_hot_coordinates(&self, limit: i64) -> Result<Vec<HotCoordinate>> {
+        let rows: Vec<HotCoordRow> = sqlx::query_as(
+            r#"
+            SELECT a.coord_id, a.read_count, a.last_read_at,
+                   (SELECT COUNT(*) FROM deltas d WHERE d.coord_id = a.coord_id) AS delta_count
+            FROM coord_access a
+            ORDER BY a.read_count DESC, a.last_read_at DESC
+            LIMIT ?
+            "#,
+        )
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|r| r.into()).collect())
+    }
+
+    /// Record a head state size measured outside a store
+    ///
+    /// Ignored unless `head_delta_id` is still the head, so a measurement
+    /// racing a store never replaces the newer size. Returns whether it was
+    /// recorded.
+    pub async fn record_state_size(
+        &self,
+        coord_id: &CoordId,
+        head_delta_id: &DeltaId,
+        state_bytes: u64,
+    ) -> Result<bool> {
+        let recorded = sqlx::query(
+            r#"
+            INSERT INTO coord_size (coord_id, head_delta_id, state_bytes, updated_at)
+            SELECT ?1, ?2, ?3, ?4
+            WHERE ?2 = (
+                SELECT id FROM deltas WHERE coord_id = ?1
+                ORDER BY created_at DESC, rowid DESC LIMIT 1
+            )
+            ON CONFLICT(coord_id) DO UPDATE SET
+                head_delta_id = excluded.head_delta_id,
+                state_bytes = excluded.state_bytes,
+                updated_at = excluded.updated_at
+            "#,
+        )
+        .bind(&coord_id.0)
+        .bind(&head_delta_id.0)
+        .bind(state_bytes as i64)
+        .bind(Utc::now())
+        .execute(&self.pool)
+        .await?
+        .rows_affected();
+
+        Ok(recorded > 0)
+    }
+
+    /// Head state sizes, largest first
+    pub async fn list_state_sizes(&self, limit: Option<i64>) -> Result<Vec<StateSize>> {
+        let rows: Vec<StateSizeRow> = sqlx::query_as(
+            r#"
+            SELECT coord_id, head_delta_id, state_bytes, updated_at
+            FROM coord_size
+            ORDER BY state_bytes DESC, coord_id ASC
+            LIMIT ?
+            "#,
+        )
+        .bind(limit.unwrap_or(-1))
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|r| r.into()).collect())
+    }
+
+    /// Stored delta bytes of a coordinate against storing its head state
+    /// once per delta
+    ///
+    /// Ops are counted as stored, after any CBOR encoding or compression.
+    /// The head size is the one its store recorded; `None` if the coordinate
+    /// has no deltas or no size is recorded for its current head.
+    pub async fn get_compression_stats(&self, coord_id: &CoordId) -> Result<Option<CompressionStats>> {
+        let row: Option<(i64, i64, i64)> = sqlx::query_as(
+            r#"
+            SELECT COUNT(*), COALESCE(SUM(LENGTH(CAST(d.ops AS BLOB))), 0), s.state_bytes
+            FROM deltas d
+            JOIN coord_size s ON s.coord_id = d.coord_id
+            WHERE d.coord_id = ?1 AND s.head_delta_id = (
+                SELECT id FROM deltas WHERE coord_id = ?1
+                ORDER BY created_at DESC, rowid DESC LIMIT 1
+            )
+            GROUP BY s.state_bytes
+            "#,
+        )
+        .bind(&coord_id.0)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|(deltas, ops_bytes, state_bytes)| {
+            CompressionStats::new((state_bytes * deltas) as usize, ops_bytes as usize, deltas as u32)
+        }))
+    }
+
+    /// Replace a coordinate's outgoing links outside a store
+    ///
+    /// Links are derived from the head, so this is not logged; standbys use it
+    /// after replaying deltas.
+    pub async fn replace_links(&self, coord_id: &CoordId, links: &[Link]) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        Self::replace_link_rows(&mut tx, coord_id, links).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Whether a coordinate's chain has a delta with this chain hash
+    pub async fn has_chain_hash(&self, coord_id: &CoordId, chain_hash: &Hash) -> Result<bool> {
+        let found: Option<i64> = sqlx::query_scalar("SELECT 1 FROM deltas WHERE coord_id = ? AND chain_hash = ? LIMIT 1")
+            .bind(&coord_id.0)
+            .bind(&chain_hash.0)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(found.is_some())
+    }
+
+    /// Materialized head state of a coordinate, whether or not it is current
+    pub async fn get_head_state(&self, coord_id: &CoordId) -> Result<Option<MaterializedHead>> {
+        let row: Option<HeadStateRow> = sqlx::query_as(
+            r#"
+            SELECT coord_id, head_delta_id, chain_hash, state, updated_at
+            FROM head_states
+            WHERE coord_id = ?
+            "#,
+        )
+        .bind(&coord_id.0)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        row.map(|r| r.try_into()).transpose()
+    }
+
+    /// Replace a materialized head state reconstructed outside a store
+    ///
+    /// Ignored unless `head.head_delta_id` is still the head, like
+    /// `record_state_size`. Returns whether it was recorded.
+    pub async fn record_head_state(&self, head: &MaterializedHead) -> Result<bool> {
+        let recorded = sqlx::query(
+            r#"
+            INSERT OR REPLACE INTO head_states (coord_id, head_delta_id, chain_hash, state, updated_at)
+            SELECT ?1, ?2, ?3, ?4, ?5
+            WHERE ?2 = (
+                SELECT id FROM deltas WHERE coord_id = ?1
+                ORDER BY created_at DESC, rowid DESC LIMIT 1
+            )
+            "#,
+        )
+        .bind(&head.coord_id.0)
+        .bind(&head.head_delta_id.0)
+        .bind(&head.chain_hash.0)
+        .bind(deflate_json(&head.state)?)
+        .bind(head.updated_at)
+        .execute(&self.pool)
+        .await?
+        .rows_affected();
+
+        Ok(recorded > 0)
+    }
+
+    /// Drop a coordinate's materialized head state
+    pub async fn delete_head_state(&self, coord_id: &CoordId) -> Result<bool> {
+        let deleted = sqlx::query("DELETE FROM head_states WHERE coord_id = ?")
+            .bind(&coord_id.0)
+            .execute(&self.pool)
+            .await?
+            .rows_affected();
+
+        Ok(deleted > 0)
+    }
+
+    /// Coordinates with a materialized head state
+    pub async fn list_head_state_coords(&self) -> Result<Vec<CoordId>> {
+        let ids: Vec<String> = sqlx::query_scalar("SELECT coord_id FROM head_states ORDER BY coord_id")
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(ids.into_iter().map(CoordId).collect())
+    }
+
+    /// Save a reconstruction checkpoint, replacing one at the same position
+    pub async fn put_checkpoint(&self, checkpoint: &ReconstructionCheckpoint) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT OR REPLACE INTO reconstruction_checkpoints
+                (coord_id, seq, delta_id, state_hash, state, created_at)
+            VALUES (?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(&checkpoint.coord_id.0)
+        .bind(checkpoint.seq as i64)
+        .bind(&checkpoint.delta_id.0)
+        .bind(&checkpoint.state_hash.0)
+        .bind(checkpoint.compressed_state()?)
+        .bind(checkpoint.created_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Newest checkpoint of a coordinate at or before `max_seq`
+    pub async fn get_checkpoint(
+        &self,
+        coord_id: &CoordId,
+        max_seq: u64,
+    ) -> Result<Option<ReconstructionCheckpoint>> {
+        let row: Option<CheckpointRow> = sqlx::query_as(
+            r#"
+            SELECT coord_id, seq, delta_id, state_hash, state, created_at
+            FROM reconstruction_checkpoints
+            WHERE coord_id = ? AND seq <= ?
+            ORDER BY seq DESC
+            LIMIT 1
+            "#,
+        )
+        .bind(&coord_id.0)
+        .bind(max_seq as i64)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        row.map(|r| r.try_into()).transpose()
+    }
+
+    /// Delete a coordinate's checkpoints up to `through_seq`, or all of them
+    pub async fn delete_checkpoints(&self, coord_id: &CoordId, through_seq: Option<u64>) -> Result<u64> {
+        let deleted = sqlx::query("DELETE FROM reconstruction_checkpoints WHERE coord_id = ? AND seq <= ?")
+            .bind(&coord_id.0)
+            .bind(through_seq.map_or(i64::MAX, |seq| seq as i64))
+            .execute(&self.pool)
+            .await?
+            .rows_affected();
+        Ok(deleted)
+    }
+
+    /// Delete checkpoints of every coordinate written before `cutoff`
+    pub async fn prune_checkpoints(&self, cutoff: DateTime<Utc>) -> Result<u64> {
+        let pruned = sqlx::query("DELETE FROM reconstruction_checkpoints WHERE created_at < ?")
+            .bind(cutoff)
+            .execute(&self.pool)
+            .await?
+            .rows_affected();
+        Ok(pruned)
+    }
+
+    /// Delete all but the `keep` most recent snapshots of a coordinate
+    ///
+    /// Returns the number of snapshots removed.
+    pub async fn prune_snapshots(&self, coord_id: &CoordId, keep: u32) -> Result<u64> {
+        let mut tx = self.pool.begin().await?;
+        let pruned = Self::prune_snapshot_rows(&mut tx, coord_id, keep).await?;
+        if pruned > 0 {
+            Self::append_oplog(&mut tx, &OplogRecord::prune(coord_id, keep)).await?;
+        }
+        tx.commit().await?;
+        Ok(pruned)
+    }
+
+    async fn prune_snapshot_rows(conn: &mut SqliteConnection, coord_id: &CoordId, keep: u32) -> Result<u64> {
+        let mut pruned = 0;
+        for table in ["snapshot_blobs", "snapshots"] {
+            let column = if table == "snapshots" { "id" } else { "snapshot_id" };
+            pruned = sqlx::query(&format!(
+                r#"
+                DELETE FROM {table}
+                WHERE {column} IN (SELECT id FROM snapshots WHERE coord_id = ?1) AND {column} NOT IN (
+                    SELECT id FROM snapshots WHERE coord_id = ?1 ORDER BY created_at DESC LIMIT ?2
+                )
+                "#,
+            ))
+            .bind(&coord_id.0)
+            .bind(keep as i64)
+            .execute(&mut *conn)
+            .await?
+            .rows_affected();
+        }
+        // Blobs shared with a retained snapshot stay
+        Self::delete_unreferenced_blobs(conn).await?;
+
+        Ok(pruned)
+    }
+
+    /// Every coordinate flagged ephemeral, with its last write or read
+    ///
+    /// Reads count once `record_access_batch` has flushed them.
+    pub async fn list_ephemeral(&self) -> Result<Vec<EphemeralCoord>> {
+        let rows: Vec<EphemeralRow> = sqlx::query_as(
+            r#"
+            SELECT c.id_ascii AS coord_id, c.created_at,
+                   MAX(d.created_at) AS last_write_at,
+                   a.last_read_at
+            FROM coordinates c
+            LEFT JOIN deltas d ON d.coord_id = c.id_ascii
+            LEFT JOIN coord_access a ON a.coord_id = c.id_ascii
+            WHERE CASE WHEN json_valid(c.metadata) THEN json_extract(c.metadata, '$.ephemeral') END = 1
+            GROUP BY c.id_ascii
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|r| r.into()).collect())
+    }
+
+    /// Get storage aggregates for one coordinate, or for all of them
+    pub async fn get_coord_stats(&self, coord_id: Option<&CoordId>) -> Result<Vec<CoordStats>> {
+        let coord_id = coord_id.map(|c| c.0.as_str());
+        let rows: Vec<CoordStatsRow> = sqlx::query_as(
+            r#"
+            WITH latest AS (
+                SELECT s.coord_id, s.created_at, LENGTH(s.state) AS bytes, d.rowid AS head_rowid,
+                       ROW_NUMBER() OVER (PARTITION BY s.coord_id ORDER BY s.created_at DESC) AS rn
+                FROM snapshots s JOIN deltas d ON d.id = s.head_delta_id
+            )
+            SELECT c.id_ascii AS coord_id,
+                   COUNT(d.id) AS delta_count,
+                   COALESCE(SUM(LENGTH(d.ops)), 0) AS ops_bytes,
+                   COUNT(CASE WHEN d.rowid > COALESCE(l.head_rowid, 0) THEN 1 END) AS replay_deltas,
+                   COALESCE(SUM(CASE WHEN d.rowid > COALESCE(l.head_rowid, 0) THEN LENGTH(d.ops) END), 0)
+                       AS replay_ops_bytes,
+                   COALESCE(SUM(CASE WHEN json_valid(d.tags) THEN json_extract(d.tags, '$.full_replace') = 1 END), 0)
+                       AS full_replace_deltas,
+                   (SELECT COUNT(*) FROM snapshots s WHERE s.coord_id = c.id_ascii) AS snapshot_count,
+                   (SELECT COALESCE(SUM(LENGTH(s.state)), 0) FROM snapshots s WHERE s.coord_id = c.id_ascii)
+                       AS snapshot_bytes,
+                   COALESCE(l.bytes, 0) AS latest_snapshot_bytes,
+                   l.created_at AS latest_snapshot_at,
+                   COALESCE(a.read_count, 0) AS read_count,
+                   a.last_read_at,
+                   COALESCE(i.importance, ?2) AS importance,
+                   COALESCE(i.updated_at, c.created_at) AS importance_updated_at
+            FROM coordinates c
+            LEFT JOIN deltas d ON d.coord_id = c.id_ascii
+            LEFT JOIN latest l ON l.coord_id = c.id_ascii AND l.rn = 1
+            LEFT JOIN coord_access a ON a.coord_id = c.id_ascii
+            LEFT JOIN coord_importance i ON i.coord_id = c.id_ascii
+            WHERE ?1 IS NULL OR c.id_ascii = ?1
+            GROUP BY c.id_ascii
+            "#,
+        )
+        .bind(coord_id)
+        .bind(DEFAULT_IMPORTANCE)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|r| r.into()).collect())
+    }
+
+    /// Stored importance of every coordinate, or of one
+    ///
+    /// Values are as last set; apply `ImportancePolicy::effective` for the
+    /// current importance.
+    pub async fn get_importance(&self, coord_id: Option<&CoordId>) -> Result<Vec<CoordImportance>> {
+        let rows: Vec<ImportanceRow> = sqlx::query_as(IMPORTANCE_SQL)
+            .bind(coord_id.map(|c| c.0.as_str()))
+            .bind(DEFAULT_IMPORTANCE)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(rows.into_iter().map(|r| r.into()).collect())
+    }
+
+    /// Add `delta` to the decayed importance of a coordinate
+    ///
+    /// Returns the new importance, or `None` if the coordinate does not exist.
+    pub async fn reinforce_importance(
+        &self,
+        coord_id: &CoordId,
+        delta: f32,
+        policy: &ImportancePolicy,
+        now: DateTime<Utc>,
+    ) -> Result<Option<f32>> {
+        let mut tx = self.pool.begin().await?;
+        let importance = Self::reinforce_importance_row(&mut tx, coord_id, delta, policy, now).await?;
+        tx.commit().await?;
+        Ok(importance)
+    }
+
+    async fn reinforce_importance_row(
+        conn: &mut SqliteConnection,
+        coord_id: &CoordId,
+        delta: f32,
+        policy: &ImportancePolicy,
+        now: DateTime<Utc>,
+    ) -> Result<Option<f32>> {
+        let current: Option<ImportanceRow> = sqlx::query_as(IMPORTANCE_SQL)
+            .bind(&coord_id.0)
+            .bind(DEFAULT_IMPORTANCE)
+            .fetch_optional(&mut *conn)
+            .await?;
+        let Some(current) = current else {
+            return Ok(None);
+        };
+
+        let importance = policy.reinforce(current.importance, current.updated_at, delta, now);
+        sqlx::query(
+            r#"
+            INSERT INTO coord_importance (coord_id, importance, updated_at) VALUES (?, ?, ?)
+            ON CONFLICT(coord_id) DO UPDATE SET
+                importance = excluded.importance,
+                updated_at = excluded.updated_at
+            "#,
+        )
+        .bind(&coord_id.0)
+        .bind(importance)
+        .bind(now)
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(Some(importance))
+    }
+
+    /// List coordinates with deltas, most recently written first
+    pub async fn list_write_times(&self) -> Result<Vec<(CoordId, DateTime<Utc>)>> {
+        let rows: Vec<(String, DateTime<Utc>)> = sqlx::query_as(
+            r#"
+            SELECT coord_id, MAX(created_at) AS last_write
+            FROM deltas
+            GROUP BY coord_id
+            ORDER BY last_write DESC
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|(id, at)| (CoordId(id), at)).collect())
+    }
+
+    /// Create or replace a saved search
+    ///
+    /// Replacing one clears its last results, so the next run reports every
+    /// result as new. Returns false if a search with that name was replaced.
+    pub async fn save_search(&self, search: &SavedSearch) -> Result<bool> {
+        let mut tx = self.pool.begin().await?;
+        let existed: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM saved_searches WHERE owner = ? AND name = ?",
+        )
+        .bind(&search.owner)
+        .bind(&search.name)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO saved_searches (owner, name, request, webhook_url, created_at, last_run_at)
+            VALUES (?, ?, ?, ?, ?, NULL)
+            ON CONFLICT(owner, name) DO UPDATE SET
+                request = excluded.request,
+                webhook_url = excluded.webhook_url,
+                last_run_at = NULL
+            "#,
+        )
+        .bind(&search.owner)
+        .bind(&search.name)
+        .bind(search.request.to_string())
+        .bind(&search.webhook_url)
+        .bind(search.created_at)
+        .execute(&mut *tx)
+        .await?;
+        Self::delete_saved_result_rows(&mut tx, &search.owner, &search.name).await?;
+
+        tx.commit().await?;
+        Ok(existed == 0)
+    }
+
+    /// Get one saved search of an owner
+    pub async fn get_saved_search(&self, owner: &str, name: &str) -> Result<Option<SavedSearch>> {
+        let row: Option<SavedSearchRow> = sqlx::query_as(
+            r#"
+            SELECT owner, name, request, webhook_url, created_at, last_run_at
+            FROM saved_searches
+            WHERE owner = ? AND name = ?
+            "#,
+        )
+        .bind(owner)
+        .bind(name)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|r| r.into()))
+    }
+
+    /// List the saved searches of one owner, or of all owners
+    pub async fn list_saved_searches(&self, owner: Option<&str>) -> Result<Vec<SavedSearch>> {
+        let rows: Vec<SavedSearchRow> = sqlx::query_as(
+            r#"
+            SELECT owner, name, request, webhook_url, created_at, last_run_at
+            FROM saved_searches
+            WHERE ?1 IS NULL OR owner = ?1
+            ORDER BY owner, name
+            "#,
+        )
+        .bind(owner)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|r| r.into()).collect())
+    }
+
+    /// Delete a saved search with its last results
+    ///
+    /// Returns false if it did not exist.
+    pub async fn delete_saved_search(&self, owner: &str, name: &str) -> Result<bool> {
+        let mut tx = self.pool.begin().await?;
+        Self::delete_saved_result_rows(&mut tx, owner, name).await?;
+        let deleted = sqlx::query("DELETE FROM saved_searches WHERE owner = ? AND name = ?")
+            .bind(owner)
+            .bind(name)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        tx.commit().await?;
+        Ok(deleted > 0)
+    }
+
+    /// Results of a saved search's last run
+    pub async fn get_saved_search_results(&self, owner: &str, name: &str) -> Result<Vec<SavedResult>> {
+        let rows: Vec<(String, String)> = sqlx::query_as(
+            r#"
+            SELECT coord_id, head_hash FROM saved_search_results
+            WHERE owner = ? AND name = ?
+            ORDER BY coord_id
+            "#,
+        )
+        .bind(owner)
+        .bind(name)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(coord_id, head_hash)| SavedResult {
+                coord_id: CoordId(coord_id),
+                head_hash: Hash(head_hash),
+            })
+            .collect())
+    }
+
+    /// Replace a saved search's last results and stamp the run time
+    pub async fn record_saved_search_run(
+        &self,
+        owner: &str,
+        name: &str,
+        results: &[SavedResult],
+        ran_at: DateTime<Utc>,
+    ) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        Self::delete_saved_result_rows(&mut tx, owner, name).await?;
+        for result in results {
+            sqlx::query(
+                r#"
+                INSERT INTO saved_search_results (owner, name, coord_id, head_hash)
+                VALUES (?, ?, ?, ?)
+                ON CONFLICT(owner, name, coord_id) DO UPDATE SET head_hash = excluded.head_hash
+                "#,
+            )
+            .bind(owner)
+            .bind(name)
+            .bind(&result.coord_id.0)
+            .bind(&result.head_hash.0)
+            .execute(&mut *tx)
+            .await?;
+        }
+        sqlx::query("UPDATE saved_searches SET last_run_at = ? WHERE owner = ? AND name = ?")
+            .bind(ran_at)
+            .bind(owner)
+            .bind(name)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn delete_saved_result_rows(conn: &mut SqliteConnection, owner: &str, name: &str) -> Result<()> {
+        sqlx::query("DELETE FROM saved_search_results WHERE owner = ? AND name = ?")
+            .bind(owner)
+            .bind(name)
+            .execute(&mut *conn)
+            .await?;
+        Ok(())
+    }
+
+    /// Set a system metadata value
+    pub async fn set_metadata(&self, key: &str, value: &str) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO metadata (key, value, updated_at) VALUES (?, ?, CURRENT_TIMESTAMP)
+            ON CONFLICT(key) DO UPDATE SET value = excluded.value, updated_at = excluded.updated_at
+            "#,
+        )
+        .bind(key)
+        .bind(value)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Get a system metadata value
+    pub async fn get_metadata(&self, key: &str) -> Result<Option<String>> {
+        let value = sqlx::query_scalar("SELECT value FROM metadata WHERE key = ?")
+            .bind(key)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(value)
+    }
+
+    /// The stored snapshot interval, if any
+    pub async fn snapshot_interval(&self) -> Result<Option<u32>> {
+        let Some(value) = self.get_metadata(SNAPSHOT_INTERVAL_METADATA_KEY).await? else {
+            return Ok(None);
+        };
+        let interval = value
+            .parse()
+            .ok()
+            .filter(|interval| *interval > 0)
+            .ok_or_else(|| BmsError::InvalidState(format!("Stored snapshot interval {:?} is not a positive integer", value)))?;
+        Ok(Some(interval))
+    }
+
+    /// Store the snapshot interval stores use from the next startup on
+    pub async fn set_snapshot_interval(&self, interval: u32) -> Result<()> {
+        if interval == 0 {
+            return Err(BmsError::InvalidState("Snapshot interval must be at least 1".to_string()));
+        }
+        self.set_metadata(SNAPSHOT_INTERVAL_METADATA_KEY, &interval.to_string()).await
+    }
+
+    /// Append an entry to the oplog within the caller's transaction
+    async fn append_oplog(conn: &mut SqliteConnection, record: &OplogRecord) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO oplog (op, coord_id, ref_id, args, created_at)
+            VALUES (?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(record.op.as_str())
+        .bind(&record.coord_id.0)
+        .bind(&record.ref_id)
+        .bind(record.args.as_ref().map(Value::to_string))
+        .bind(Utc::now())
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Get oplog entries after `after_lsn`, oldest first
+    pub async fn get_oplog(&self, after_lsn: i64, limit: i64) -> Result<Vec<OplogEntry>> {
+        let rows: Vec<OplogRow> = sqlx::query_as(
+            r#"
+            SELECT lsn, op, coord_id, ref_id, args, created_at
+            FROM oplog
+            WHERE lsn > ?
+            ORDER BY lsn ASC
+            LIMIT ?
+            "#,
+        )
+        .bind(after_lsn)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter().map(|r| r.try_into()).collect()
+    }
+
+    /// Highest LSN in the oplog, 0 when it is empty
+    pub async fn max_lsn(&self) -> Result<i64> {
+        let lsn: Option<i64> = sqlx::query_scalar("SELECT MAX(lsn) FROM oplog")
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(lsn.unwrap_or(0))
+    }
+
+    /// Lowest LSN still in the oplog, `None` when it is empty
+    pub async fn min_lsn(&self) -> Result<Option<i64>> {
+        let lsn: Option<i64> = sqlx::query_scalar("SELECT MIN(lsn) FROM oplog")
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(lsn)
+    }
+
+    /// Whether the oplog records a delete of the coordinate after `after_lsn`
+    pub async fn deleted_after(&self, coord_id: &CoordId, after_lsn: i64) -> Result<bool> {
+        self.logged_after(coord_id, OpKind::CoordinateDeleted, after_lsn).await
+    }
+
+    /// Whether the oplog records a compaction of the coordinate after `after_lsn`
+    pub async fn compacted_after(&self, coord_id: &CoordId, after_lsn: i64) -> Result<bool> {
+        self.logged_after(coord_id, OpKind::CoordinateCompacted, after_lsn).await
+    }
+
+    async fn logged_after(&self, coord_id: &CoordId, op: OpKind, after_lsn: i64) -> Result<bool> {
+        let lsn: Option<i64> = sqlx::query_scalar(
+            "SELECT lsn FROM oplog WHERE coord_id = ? AND op = ? AND lsn > ? LIMIT 1",
+        )
+        .bind(&coord_id.0)
+        .bind(op.as_str())
+        .bind(after_lsn)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(lsn.is_some())
+    }
+
+    /// Source LSN a standby last recorded as applied
+    pub async fn get_replication_lsn(&self, source: &str) -> Result<Option<i64>> {
+        let lsn: Option<i64> = sqlx::query_scalar("SELECT applied_lsn FROM replication_state WHERE source = ?")
+            .bind(source)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(lsn)
+    }
+
+    /// Record the source LSN a standby has applied up to
+    pub async fn set_replication_lsn(&self, source: &str, applied_lsn: i64) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO replication_state (source, applied_lsn, updated_at)
+            VALUES (?, ?, ?)
+            ON CONFLICT(source) DO UPDATE SET
+                applied_lsn = excluded.applied_lsn,
+                updated_at = excluded.updated_at
+            "#,
+        )
+        .bind(source)
+        .bind(applied_lsn)
+        .bind(Utc::now())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Current row referenced by an oplog entry, if it still exists
+    ///
+    /// Deltas and compactions followed by a later compaction of their
+    /// coordinate have none, since that compaction carries the chain.
+    pub async fn oplog_payload(&self, entry: &OplogEntry) -> Result<Option<Value>> {
+        let ref_id = entry.ref_id.clone().unwrap_or_default();
+        let rewritten = matches!(entry.op, OpKind::DeltaAppended | OpKind::CoordinateCompacted)
+            && self.compacted_after(&entry.coord_id, entry.lsn).await?;
+        if rewritten {
+            return Ok(None);
+        }
+        let payload = match entry.op {
+            OpKind::CoordinateCreated => self
+                .get_coordinate(&entry.coord_id)
+                .await?
+                .map(serde_json::to_value)
+                .transpose()?,
+            OpKind::DeltaAppended => self
+                .get_delta(&DeltaId(ref_id))
+                .await?
+                .map(serde_json::to_value)
+                .transpose()?,
+            OpKind::SnapshotCreated => self
+                .get_snapshot(&SnapshotId(ref_id))
+                .await?
+                .map(serde_json::to_value)
+                .transpose()?,
+            OpKind::CoordinateCompacted => {
+                let deltas = self.get_deltas(&entry.coord_id).await?;
+                match DeltaEngine::chain_to(&deltas, &DeltaId(ref_id)) {
+                    Ok(chain) => Some(serde_json::to_value(chain)?),
+                    Err(BmsError::DeltaNotFound(_)) => None,
+                    Err(e) => return Err(e),
+                }
+            }
+            OpKind::SnapshotsPruned | OpKind::CoordinateDeleted | OpKind::MetadataUpdated => None,
+        };
+        Ok(payload)
+    }
+
+    /// Replay one exported oplog entry, keeping its LSN
+    ///
+    /// Returns false if the LSN is already present. Deltas must extend the
+    /// current head of their chain.
+    pub async fn apply_oplog_entry(&self, entry: &OplogEntry) -> Result<bool> {
+        let mut tx = self.pool.begin().await?;
+
+        let present: Option<i64> = sqlx::query_scalar("SELECT lsn FROM oplog WHERE lsn = ?")
+            .bind(entry.lsn)
+            .fetch_optional(&mut *tx)
+            .await?;
+        if present.is_some() {
+            return Ok(false);
+        }
+
+        let payload = entry.payload.clone();
+        match (entry.op, payload) {
+            (OpKind::CoordinateCreated, Some(payload)) => {
+                let coord: Coordinate = serde_json::from_value(payload)?;
+                Self::insert_coordinate_row(&mut tx, &coord).await?;
+            }
+            (OpKind::DeltaAppended, Some(payload)) => {
+                let delta: Delta = serde_json::from_value(payload)?;
+                let head: Option<(String, String)> = sqlx::query_as(
+                    r#"
+                    SELECT id, chain_hash FROM deltas
+                    WHERE coord_id = ?
+                    ORDER BY created_at DESC, rowid DESC
+                    LIMIT 1
+                    "#,
+                )
+                .bind(&delta.coord_id.0)
+                .fetch_optional(&mut *tx)
+                .await?;
+                let algorithm = Self::hash_algorithm_of(&mut tx, &delta.coord_id).await?;
+                oplog::verify_append(head.map(|(id, hash)| (DeltaId(id), Hash(hash))), &delta, algorithm)?;
+                delta.check_op_authors()?;
+                Self::insert_delta_row(&mut tx, &delta, self.delta_encoding).await?;
+            }
+            (OpKind::SnapshotCreated, Some(payload)) => {
+                let snapshot: Snapshot = serde_json::from_value(payload)?;
+                Self::insert_snapshot_row(&mut tx, &snapshot).await?;
+            }
+            (OpKind::SnapshotsPruned, _) => {
+                Self::prune_snapshot_rows(&mut tx, &entry.coord_id, entry.keep()?).await?;
+            }
+            (OpKind::CoordinateDeleted, _) => {
+                Self::delete_coordinate_rows(&mut tx, &entry.coord_id).await?;
+            }
+            (OpKind::MetadataUpdated, _) => {
+                Self::update_metadata_row(&mut tx, &entry.coord_id, &entry.metadata()?).await?;
+            }
+            (OpKind::CoordinateCompacted, Some(payload)) => {
+                let chain: Vec<Delta> = serde_json::from_value(payload)?;
+                let algorithm = Self::hash_algorithm_of(&mut tx, &entry.coord_id).await?;
+                let mut head = None;
+                for delta in &chain {
+                    if delta.coord_id != entry.coord_id {
+                        return Err(BmsError::MerkleChainBroken { delta_id: delta.id.0.clone() });
+                    }
+                    oplog::verify_append(head, delta, algorithm)?;
+                    delta.check_op_authors()?;
+                    head = Some((delta.id.clone(), delta.chain_hash.clone()));
+                }
+                Self::replace_chain_rows(&mut tx, &entry.coord_id, &chain, self.delta_encoding).await?;
+            }
+            // The row was deleted after this entry; the delete is replayed later
+            (_, None) => {}
+        }
+
+        sqlx::query(
+            r#"
+            INSERT INTO oplog (lsn, op, coord_id, ref_id, args, created_at)
+            VALUES (?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(entry.lsn)
+        .bind(entry.op.as_str())
+        .bind(&entry.coord_id.0)
+        .bind(&entry.ref_id)
+        .bind(entry.args.as_ref().map(Value::to_string))
+        .bind(entry.created_at)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    /// Record that a backup containing everything up to the current LSN completed
+    pub async fn record_backup_marker(&self, label: Option<&str>) -> Result<BackupMarker> {
+        let row: BackupMarkerRow = sqlx::query_as(
+            r#"
+            INSERT INTO backup_markers (lsn, label, completed_at)
+            VALUES ((SELECT COALESCE(MAX(lsn), 0) FROM oplog), ?, ?)
+            RETURNING id, lsn, label, completed_at
+            "#,
+        )
+        .bind(label)
+        .bind(Utc::now())
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(row.into())
+    }
+
+    /// List backup markers, newest first
+    pub async fn list_backup_markers(&self) -> Result<Vec<BackupMarker>> {
+        let rows: Vec<BackupMarkerRow> = sqlx::query_as(
+            "SELECT id, lsn, label, completed_at FROM backup_markers ORDER BY id DESC",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(|r| r.into()).collect())
+    }
+
+    /// Drop oplog entries no retained backup needs
+    ///
+    /// Keeps everything after the oldest of the `keep_backups` most recent
+    /// markers, so each of those backups can still be rolled forward, and
+    /// forgets older markers. Returns the number of entries removed; nothing is
+    /// trimmed until that many backups have been marked.
+    pub async fn trim_oplog(&self, keep_backups: u32) -> Result<u64> {
+        let markers = self.list_backup_markers().await?;
+        let Some(oldest_kept) = markers.get(keep_backups.max(1) as usize - 1) else {
+            return Ok(0);
+        };
+
+        let mut tx = self.pool.begin().await?;
+        let trimmed = sqlx::query("DELETE FROM oplog WHERE lsn <= ?")
+            .bind(oldest_kept.lsn)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        sqlx::query("DELETE FROM backup_markers WHERE id < ?")
+            .bind(oldest_kept.id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        info!("Trimmed {} oplog entries up to LSN {}", trimmed, oldest_kept.lsn);
+        Ok(trimmed)
+    }
+
     /// Get storage statistics
     pub async fn get_stats(&self) -> Result<StorageStats> {
         let coord_count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM coordinates")
@@ -266,12 +2425,33 @@ impl BmsRepository {
             .fetch_one(&self.pool)
             .await?;
 
+        let blob_count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM blobs")
+            .fetch_one(&self.pool)
+            .await?;
+
+        let (head_state_count, head_state_bytes): (i64, i64) =
+            sqlx::query_as("SELECT COUNT(*), COALESCE(SUM(LENGTH(state)), 0) FROM head_states")
+                .fetch_one(&self.pool)
+                .await?;
+
         Ok(StorageStats {
             coordinate_count: coord_count as u64,
             delta_count: delta_count as u64,
             snapshot_count: snapshot_count as u64,
+            blob_count: blob_count as u64,
+            head_state_count: head_state_count as u64,
+            head_state_bytes: head_state_bytes as u64,
         })
     }
+
+    /// Run SQLite's `quick_check`, returning the problems it reports
+    pub async fn quick_check(&self) -> Result<Vec<String>> {
+        let rows: Vec<String> = sqlx::query_scalar("PRAGMA quick_check")
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(rows.into_iter().filter(|row| row != "ok").collect())
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -279,4 +2459,19 @@ pub struct StorageStats {
     pub coordinate_count: u64,
     pub delta_count: u64,
     pub snapshot_count: u64,
+    /// Distinct externalized snapshot fields
+    pub blob_count: u64,
+    /// Coordinates with a materialized head state
+    pub head_state_count: u64,
+    /// Compressed bytes of the materialized head states
+    pub head_state_bytes: u64,
+}
+
+/// Smallest string greater than every string starting with `prefix`
+///
+/// `id >= prefix AND id < bound` is the indexed form of `LIKE 'prefix%'`:
+/// SQLite only uses an index for LIKE on case-insensitive columns, and IDs
+/// compare case-sensitively.
+fn prefix_upper_bound(prefix: &str) -> String {
+    format!("{}{}", prefix, char::MAX)
 }
@@ -0,0 +1,369 @@
+//! Background integrity sampling
+//!
+//! Verifies a small random subset of chains (and their latest snapshots) on
+//! every tick instead of auditing the whole store at once. Recently written
+//! coordinates are over-represented, since fresh writes are where corruption
+//! is most likely to show up first.
+
+use crate::facade::BmsFacade;
+use async_trait::async_trait;
+use bms_core::types::CoordId;
+use bms_core::{DeltaEngine, MerkleChain, Result, SnapshotManager};
+use chrono::{DateTime, Utc};
+use rand::seq::SliceRandom;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::Serialize;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tracing::{error, info};
+
+/// Metadata key holding the JSON-encoded last `SampleReport`
+pub const LAST_SAMPLE_METADATA_KEY: &str = "integrity_last_sample";
+
+/// Sampler settings
+#[derive(Debug, Clone)]
+pub struct SamplerConfig {
+    /// Coordinates verified per cycle
+    pub sample_size: usize,
+    /// Time between cycles
+    pub interval: Duration,
+    /// Share of each sample drawn from the most recently written coordinates (0.0–1.0)
+    pub recent_fraction: f64,
+    /// Maximum chains verified at once
+    pub concurrency: usize,
+}
+
+impl Default for SamplerConfig {
+    fn default() -> Self {
+        Self {
+            sample_size: 16,
+            interval: Duration::from_secs(3600),
+            recent_fraction: 0.5,
+            concurrency: 2,
+        }
+    }
+}
+
+/// One coordinate that failed verification
+#[derive(Debug, Clone, Serialize)]
+pub struct SampleFailure {
+    pub coord_id: CoordId,
+    pub reason: String,
+}
+
+/// Result of one sampling cycle
+#[derive(Debug, Clone, Serialize)]
+pub struct SampleReport {
+    pub sampled_at: DateTime<Utc>,
+    pub checked: usize,
+    pub failures: Vec<SampleFailure>,
+}
+
+impl SampleReport {
+    pub fn is_ok(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// Source of sampling ticks; a real interval in production, manual in tests
+#[async_trait]
+pub trait SampleClock: Send {
+    async fn tick(&mut self);
+}
+
+#[async_trait]
+impl SampleClock for tokio::time::Interval {
+    async fn tick(&mut self) {
+        tokio::time::Interval::tick(self).await;
+    }
+}
+
+/// Periodic chain verifier
+pub struct IntegritySampler {
+    facade: Arc<BmsFacade>,
+    config: SamplerConfig,
+    last_report: RwLock<Option<SampleReport>>,
+}
+
+impl IntegritySampler {
+    pub fn new(facade: Arc<BmsFacade>, config: SamplerConfig) -> Self {
+        Self {
+            facade,
+            config,
+            last_report: RwLock::new(None),
+        }
+    }
+
+    pub fn config(&self) -> &SamplerConfig {
+        &self.config
+    }
+
+    /// Outcome of the most recent cycle, `None` before the first one completes
+    pub fn last_sample_ok(&self) -> Option<bool> {
+        self.last_report().map(|r| r.is_ok())
+    }
+
+    pub fn last_report(&self) -> Option<SampleReport> {
+        self.last_report.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Run one cycle on every tick until the task is dropped
+    ///
+    /// `on_failure` is called with each report that has failures.
+    pub async fn run<C, F>(&self, mut clock: C, mut on_failure: F)
+    where
+        C: SampleClock,
+        F: FnMut(&SampleReport) + Send,
+    {
+        let mut rng = StdRng::from_entropy();
+        loop {
+            clock.tick().await;
+            match self.sample_once(&mut rng).await {
+                Ok(report) if !report.is_ok() => on_failure(&report),
+                Ok(_) => {}
+                Err(e) => error!("Integrity sampling failed: {}", e),
+            }
+        }
+    }
+
+    /// Verify one sample and record the result
+    pub async fn sample_once<R: Rng + Send>(&self, rng: &mut R) -> Result<SampleReport> {
+        let sample = self.choose_sample(rng).await?;
+        let checked = sample.len();
+        let failures = verify_coordinates(&self.facade, sample, self.config.concurrency).await?;
+        for failure in &failures {
+            error!("Integrity sample failed for {}: {}", failure.coord_id, failure.reason);
+        }
+
+        let report = SampleReport {
+            sampled_at: Utc::now(),
+            checked,
+            failures,
+        };
+        info!(
+            "Integrity sample checked {} coordinates, {} failures",
+            report.checked,
+            report.failures.len()
+        );
+
+        self.facade
+            .repository()
+            .set_metadata(LAST_SAMPLE_METADATA_KEY, &serde_json::to_string(&report)?)
+            .await?;
+        *self.last_report.write().unwrap_or_else(|e| e.into_inner()) = Some(report.clone());
+
+        Ok(report)
+    }
+
+    /// Pick coordinates, drawing `recent_fraction` of them from the recent pool
+    async fn choose_sample<R: Rng>(&self, rng: &mut R) -> Result<Vec<CoordId>> {
+        let mut coords: Vec<CoordId> = self
+            .facade
+            .repository()
+            .list_write_times()
+            .await?
+            .into_iter()
+            .map(|(coord_id, _)| coord_id)
+            .collect();
+
+        let size = self.config.sample_size.min(coords.len());
+        let recent_count = ((size as f64) * self.config.recent_fraction.clamp(0.0, 1.0)).round() as usize;
+
+        // The recent pool is a few times larger than its share so repeated
+        // cycles don't keep picking the exact same coordinates
+        let pool_len = (recent_count * 4).min(coords.len());
+        let mut rest = coords.split_off(pool_len);
+        let mut sample: Vec<CoordId> = coords.choose_multiple(rng, recent_count).cloned().collect();
+
+        rest.extend(coords.into_iter().filter(|c| !sample.contains(c)));
+        sample.extend(rest.choose_multiple(rng, size - sample.len()).cloned());
+
+        Ok(sample)
+    }
+}
+
+/// Verify chains with `verify_coordinate`, at most `concurrency` at once
+///
+/// Returns the coordinates that failed; an error while verifying one counts
+/// as its failure.
+pub async fn verify_coordinates(
+    facade: &Arc<BmsFacade>,
+    coords: Vec<CoordId>,
+    concurrency: usize,
+) -> Result<Vec<SampleFailure>> {
+    let semaphore = Arc::new(Semaphore::new(concurrency.max(1)));
+    let mut tasks = JoinSet::new();
+    for coord_id in coords {
+        let facade = facade.clone();
+        let semaphore = semaphore.clone();
+        tasks.spawn(async move {
+            let _permit = semaphore.acquire_owned().await.expect("semaphore is never closed");
+            let reason = match verify_coordinate(&facade, &coord_id).await {
+                Ok(reason) => reason,
+                Err(e) => Some(e.to_string()),
+            };
+            (coord_id, reason)
+        });
+    }
+
+    let mut failures = Vec::new();
+    while let Some(joined) = tasks.join_next().await {
+        let (coord_id, reason) = joined
+            .map_err(|e| bms_core::BmsError::Other(format!("Verification task failed: {}", e)))?;
+        if let Some(reason) = reason {
+            failures.push(SampleFailure { coord_id, reason });
+        }
+    }
+    Ok(failures)
+}
+
+/// Verify a chain and its latest snapshot, returning the first problem found
+pub async fn verify_coordinate(facade: &BmsFacade, coord_id: &CoordId) -> Result<Option<String>> {
+    let repository = facade.repository();
+    let deltas = repository.get_deltas(coord_id).await?;
+    let algorithm = repository.get_hash_algorithm(coord_id).await?;
+
+    if let (verified, Some(e)) = MerkleChain::verify_chain_integrity_with(&deltas, algorithm) {
+        return Ok(Some(format!("chain broken at delta {}: {}", verified, e)));
+    }
+
+    for (i, delta) in deltas.iter().enumerate() {
+        if DeltaEngine::hash_delta(&delta.ops)? != delta.delta_hash {
+            return Ok(Some(format!("delta {} hash does not match its ops", delta.id)));
+        }
+        let expected_parent = i.checked_sub(1).map(|p| &deltas[p].chain_hash);
+        if delta.parent_hash.as_ref() != expected_parent {
+            return Ok(Some(format!("delta {} is not linked to its predecessor", delta.id)));
+        }
+    }
+
+    let Some(snapshot) = repository.get_latest_snapshot(coord_id).await? else {
+        return Ok(None);
+    };
+    if let Err(e) = facade.snapshot_manager().verify_snapshot(&snapshot) {
+        return Ok(Some(format!("snapshot {}: {}", snapshot.id, e)));
+    }
+
+    let Some(position) = deltas.iter().position(|d| d.id == snapshot.head_delta_id) else {
+        return Ok(Some(format!("snapshot {} head delta is not in the chain", snapshot.id)));
+    };
+    let replayed = SnapshotManager::reconstruct_head(None, &deltas[..=position])?;
+    if replayed != snapshot.state {
+        return Ok(Some(format!("snapshot {} does not match replayed state", snapshot.id)));
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDb;
+    use crate::StoreParams;
+    use rand_chacha::ChaCha8Rng;
+    use serde_json::json;
+    use tokio::sync::mpsc;
+
+    struct ManualClock(mpsc::Receiver<()>);
+
+    #[async_trait]
+    impl SampleClock for ManualClock {
+        async fn tick(&mut self) {
+            if self.0.recv().await.is_none() {
+                std::future::pending::<()>().await;
+            }
+        }
+    }
+
+    async fn seed(facade: &BmsFacade, coords: usize) {
+        for c in 0..coords {
+            for n in 0..3 {
+                facade
+                    .store(StoreParams {
+                        coord_id: Some(CoordId(format!("SAMPLE{}", c))),
+                        state: json!({"n": format!("{}-{}", c, n)}),
+                        ..Default::default()
+                    })
+                    .await
+                    .unwrap();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sample_clean_store() {
+        let db = TempDb::new("sampler-clean");
+        let facade = Arc::new(db.facade(2).await);
+        seed(&facade, 5).await;
+
+        let sampler = IntegritySampler::new(facade.clone(), SamplerConfig::default());
+        let report = sampler.sample_once(&mut ChaCha8Rng::seed_from_u64(1)).await.unwrap();
+
+        assert_eq!(report.checked, 5);
+        assert!(report.is_ok());
+        assert_eq!(sampler.last_sample_ok(), Some(true));
+        assert!(facade
+            .repository()
+            .get_metadata(LAST_SAMPLE_METADATA_KEY)
+            .await
+            .unwrap()
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn test_corrupted_chain_flagged_within_one_cycle() {
+        let db = TempDb::new("sampler-corrupt");
+        let facade = Arc::new(db.facade(128).await);
+        seed(&facade, 1).await;
+        db.execute("UPDATE deltas SET chain_hash = lower(hex(randomblob(32))) WHERE parent_id IS NOT NULL")
+            .await;
+
+        let sampler = Arc::new(IntegritySampler::new(facade, SamplerConfig::default()));
+        let (tick_tx, tick_rx) = mpsc::channel(1);
+        let (failure_tx, mut failure_rx) = mpsc::unbounded_channel();
+
+        let runner = sampler.clone();
+        let task = tokio::spawn(async move {
+            runner
+                .run(ManualClock(tick_rx), move |report| {
+                    let _ = failure_tx.send(report.clone());
+                })
+                .await
+        });
+
+        assert_eq!(sampler.last_sample_ok(), None);
+        tick_tx.send(()).await.unwrap();
+        let report = failure_rx.recv().await.unwrap();
+        task.abort();
+
+        assert_eq!(report.failures.len(), 1);
+        assert_eq!(report.failures[0].coord_id, CoordId("SAMPLE0".to_string()));
+        assert_eq!(sampler.last_sample_ok(), Some(false));
+    }
+
+    #[tokio::test]
+    async fn test_sample_prefers_recent_writes() {
+        let db = TempDb::new("sampler-recent");
+        let facade = Arc::new(db.facade(128).await);
+        seed(&facade, 20).await;
+
+        let config = SamplerConfig {
+            sample_size: 2,
+            recent_fraction: 1.0,
+            ..Default::default()
+        };
+        let sampler = IntegritySampler::new(facade, config);
+        let mut rng = ChaCha8Rng::seed_from_u64(7);
+        for _ in 0..10 {
+            let sample = sampler.choose_sample(&mut rng).await.unwrap();
+            assert_eq!(sample.len(), 2);
+            for coord in sample {
+                let index: usize = coord.0["SAMPLE".len()..].parse().unwrap();
+                assert!(index >= 12, "{} is not among the most recent writes", coord);
+            }
+        }
+    }
+}
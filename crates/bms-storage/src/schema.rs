@@ -5,10 +5,19 @@ CREATE TABLE IF NOT EXISTS coordinates (
     id_ascii TEXT PRIMARY KEY NOT NULL,
     rune_alias TEXT,
     created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
-    metadata TEXT
+    metadata TEXT,
+    namespace TEXT,
+    hash_algorithm TEXT NOT NULL DEFAULT 'sha3-256',
+    id_kind TEXT NOT NULL DEFAULT 'generated',
+    parent_coord_id TEXT
 );
 
 CREATE INDEX IF NOT EXISTS idx_coords_created ON coordinates(created_at);
+-- idx_coords_namespace and idx_coords_parent are created by
+-- BmsRepository::initialize_schema, after their columns are added to older
+-- databases
+-- Rune aliases are unique through idx_coords_alias_unique, which
+-- BmsRepository::initialize_schema creates once older duplicates are cleared
 
 -- Deltas table
 CREATE TABLE IF NOT EXISTS deltas (
@@ -22,6 +31,8 @@ CREATE TABLE IF NOT EXISTS deltas (
     created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
     tags TEXT,
     author TEXT,
+    signature_pubkey BLOB,
+    signature_bytes BLOB,
     FOREIGN KEY (coord_id) REFERENCES coordinates(id_ascii) ON DELETE CASCADE
 );
 
@@ -29,6 +40,14 @@ CREATE INDEX IF NOT EXISTS idx_deltas_coord ON deltas(coord_id, created_at);
 CREATE INDEX IF NOT EXISTS idx_deltas_parent ON deltas(parent_id);
 CREATE INDEX IF NOT EXISTS idx_deltas_created ON deltas(created_at);
 
+-- Per-op authors of deltas written by several authors, parallel to the
+-- delta's ops (JSON array). Kept out of the hashed delta row.
+CREATE TABLE IF NOT EXISTS delta_op_authors (
+    delta_id TEXT PRIMARY KEY NOT NULL,
+    op_authors TEXT NOT NULL,
+    FOREIGN KEY (delta_id) REFERENCES deltas(id) ON DELETE CASCADE
+);
+
 -- Snapshots table
 CREATE TABLE IF NOT EXISTS snapshots (
     id TEXT PRIMARY KEY NOT NULL,
@@ -44,6 +63,143 @@ CREATE TABLE IF NOT EXISTS snapshots (
 CREATE INDEX IF NOT EXISTS idx_snapshots_coord ON snapshots(coord_id, created_at DESC);
 CREATE INDEX IF NOT EXISTS idx_snapshots_hash ON snapshots(state_hash);
 
+-- Snapshot fields listed in a coordinate's `snapshot_externalize` metadata,
+-- stored once per distinct value. `hash` is the canonical state hash of the
+-- value; snapshot rows hold `null` at the pointer instead.
+CREATE TABLE IF NOT EXISTS blobs (
+    hash TEXT PRIMARY KEY NOT NULL,
+    value BLOB NOT NULL, -- deflated JSON
+    created_at TIMESTAMP NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS snapshot_blobs (
+    snapshot_id TEXT NOT NULL,
+    pointer TEXT NOT NULL,
+    hash TEXT NOT NULL,
+    PRIMARY KEY (snapshot_id, pointer),
+    FOREIGN KEY (snapshot_id) REFERENCES snapshots(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_snapshot_blobs_hash ON snapshot_blobs(hash);
+
+-- Per-coordinate read statistics (flushed in batches from memory)
+CREATE TABLE IF NOT EXISTS coord_access (
+    coord_id TEXT PRIMARY KEY NOT NULL,
+    read_count INTEGER NOT NULL DEFAULT 0,
+    last_read_at TIMESTAMP NOT NULL,
+    FOREIGN KEY (coord_id) REFERENCES coordinates(id_ascii) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_coord_access_count ON coord_access(read_count DESC);
+
+-- Per-coordinate importance as last set; decay is applied when read.
+-- Coordinates without a row have the default importance as of their creation.
+CREATE TABLE IF NOT EXISTS coord_importance (
+    coord_id TEXT PRIMARY KEY NOT NULL,
+    importance REAL NOT NULL,
+    updated_at TIMESTAMP NOT NULL,
+    FOREIGN KEY (coord_id) REFERENCES coordinates(id_ascii) ON DELETE CASCADE
+);
+
+-- Canonical byte size of each coordinate's head state, written with the
+-- head delta. Coordinates stored before sizes were tracked have no row until
+-- `bms stats --recompute-sizes`.
+CREATE TABLE IF NOT EXISTS coord_size (
+    coord_id TEXT PRIMARY KEY NOT NULL,
+    head_delta_id TEXT NOT NULL,
+    state_bytes INTEGER NOT NULL,
+    updated_at TIMESTAMP NOT NULL,
+    FOREIGN KEY (coord_id) REFERENCES coordinates(id_ascii) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_coord_size_bytes ON coord_size(state_bytes DESC);
+
+-- Progress of long head replays, so an interrupted replay resumes instead of
+-- starting over. Disposable: not verified, not in the oplog, and dropped once
+-- a snapshot covers them or they outlive their TTL.
+CREATE TABLE IF NOT EXISTS reconstruction_checkpoints (
+    coord_id TEXT NOT NULL,
+    seq INTEGER NOT NULL,
+    delta_id TEXT NOT NULL,
+    state_hash TEXT NOT NULL,
+    state BLOB NOT NULL, -- deflated JSON
+    created_at TIMESTAMP NOT NULL,
+    PRIMARY KEY (coord_id, seq),
+    FOREIGN KEY (coord_id) REFERENCES coordinates(id_ascii) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_checkpoints_created ON reconstruction_checkpoints(created_at);
+
+-- Full head state of coordinates with `materialize_head` set, written in the
+-- store transaction. Only trusted while chain_hash matches the head delta.
+CREATE TABLE IF NOT EXISTS head_states (
+    coord_id TEXT PRIMARY KEY,
+    head_delta_id TEXT NOT NULL,
+    chain_hash TEXT NOT NULL,
+    state BLOB NOT NULL, -- deflated JSON
+    updated_at TIMESTAMP NOT NULL,
+    FOREIGN KEY (coord_id) REFERENCES coordinates(id_ascii) ON DELETE CASCADE
+);
+
+-- References from each coordinate's head to other coordinates, extracted by
+-- the coordinate's link rules. Targets may be missing (dangling links).
+CREATE TABLE IF NOT EXISTS links (
+    from_coord TEXT NOT NULL,
+    pointer TEXT NOT NULL,
+    to_coord TEXT NOT NULL,
+    PRIMARY KEY (from_coord, pointer),
+    FOREIGN KEY (from_coord) REFERENCES coordinates(id_ascii) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_links_to ON links(to_coord);
+
+-- Searches saved through the API, scoped to the key that saved them
+CREATE TABLE IF NOT EXISTS saved_searches (
+    owner TEXT NOT NULL,
+    name TEXT NOT NULL,
+    request TEXT NOT NULL,
+    webhook_url TEXT,
+    created_at TIMESTAMP NOT NULL,
+    last_run_at TIMESTAMP,
+    PRIMARY KEY (owner, name)
+);
+
+-- Results of each saved search's last run, compared by the next run
+CREATE TABLE IF NOT EXISTS saved_search_results (
+    owner TEXT NOT NULL,
+    name TEXT NOT NULL,
+    coord_id TEXT NOT NULL,
+    head_hash TEXT NOT NULL,
+    PRIMARY KEY (owner, name, coord_id),
+    FOREIGN KEY (owner, name) REFERENCES saved_searches(owner, name) ON DELETE CASCADE
+);
+
+-- Append-only log of committed mutations, written in the mutation's transaction
+CREATE TABLE IF NOT EXISTS oplog (
+    lsn INTEGER PRIMARY KEY AUTOINCREMENT,
+    op TEXT NOT NULL,
+    coord_id TEXT NOT NULL,
+    ref_id TEXT,
+    args TEXT,
+    created_at TIMESTAMP NOT NULL
+);
+
+-- Last source LSN a standby applied, per replication source. The standby's
+-- own oplog holds the same position unless it was trimmed.
+CREATE TABLE IF NOT EXISTS replication_state (
+    source TEXT PRIMARY KEY,
+    applied_lsn INTEGER NOT NULL,
+    updated_at TIMESTAMP NOT NULL
+);
+
+-- Completed backups and the last LSN each one contains
+CREATE TABLE IF NOT EXISTS backup_markers (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    lsn INTEGER NOT NULL,
+    label TEXT,
+    completed_at TIMESTAMP NOT NULL
+);
+
 -- Metadata table for system info
 CREATE TABLE IF NOT EXISTS metadata (
     key TEXT PRIMARY KEY NOT NULL,
@@ -0,0 +1,517 @@
+//! Deterministic synthetic data generation
+//!
+//! Fills a database with realistic, evolving coordinate chains by driving the
+//! real `BmsFacade` store pipeline. With a fixed seed the generated states,
+//! coordinates, and resulting statistics are reproducible, which makes this
+//! usable as a fixture generator for benchmarks and tests.
+
+use crate::facade::{BmsFacade, SnapshotStatus, StoreParams};
+use bms_core::error::BmsError;
+use bms_core::types::{CompressionStats, CoordId};
+use bms_core::{CoordinateGenerator, DiffStats, HashAlgorithm, PatchRatios, Result};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde_json::{json, Map, Value};
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+const WORDS: &[&str] = &[
+    "memory", "vector", "delta", "snapshot", "agent", "planner", "signal", "river", "lattice",
+    "orbit", "ember", "quartz", "harbor", "meadow", "cipher", "beacon", "tundra", "willow",
+    "summit", "canyon", "prism", "echo", "falcon", "glacier", "kernel", "lantern", "mosaic",
+    "nebula", "oasis", "pylon", "relay", "sonar", "thicket", "umbra", "vertex", "zephyr",
+];
+
+const ROLES: &[&str] = &["user", "assistant", "system", "tool"];
+
+/// Probability that an evolution step rewrites a large part of the state
+const LARGE_REWRITE_PROBABILITY: f64 = 0.03;
+
+/// Shape of the generated states
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SimulationProfile {
+    /// Growing message log with occasional edits and summary rewrites
+    Chatlog,
+    /// Nested settings map with key churn
+    Config,
+    /// Sectioned text document with edits and full rewrites
+    Document,
+}
+
+impl FromStr for SimulationProfile {
+    type Err = BmsError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "chatlog" => Ok(Self::Chatlog),
+            "config" => Ok(Self::Config),
+            "document" => Ok(Self::Document),
+            other => Err(BmsError::Other(format!(
+                "Unknown simulation profile '{}' (expected chatlog, config, or document)",
+                other
+            ))),
+        }
+    }
+}
+
+/// Simulation parameters
+#[derive(Debug, Clone)]
+pub struct SimulationConfig {
+    /// Number of coordinates to generate
+    pub coords: usize,
+    /// Number of deltas per coordinate (sampled uniformly)
+    pub deltas: RangeInclusive<usize>,
+    /// Target serialized state size in bytes (sampled uniformly per coordinate)
+    pub state_size: RangeInclusive<usize>,
+    /// Number of distinct authors
+    pub authors: usize,
+    pub seed: u64,
+    pub profile: SimulationProfile,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        Self {
+            coords: 100,
+            deltas: 50..=500,
+            state_size: 1024..=100 * 1024,
+            authors: 5,
+            seed: 42,
+            profile: SimulationProfile::Chatlog,
+        }
+    }
+}
+
+/// Summary of a simulation run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimulationReport {
+    pub coordinates: u64,
+    pub deltas: u64,
+    pub snapshots: u64,
+    pub large_rewrites: u64,
+    /// Bytes that storing every state in full would have cost
+    pub state_bytes: u64,
+    /// Bytes actually stored as delta ops
+    pub ops_bytes: u64,
+    /// Array diff strategies chosen across all deltas
+    pub diff_stats: DiffStats,
+    /// Patch sizes relative to their states, and how many were stored whole
+    pub patch_ratios: PatchRatios,
+}
+
+impl SimulationReport {
+    /// Compression achieved by storing deltas instead of full states
+    pub fn compression(&self) -> CompressionStats {
+        CompressionStats::new(
+            self.state_bytes as usize,
+            self.ops_bytes as usize,
+            self.deltas as u32,
+        )
+    }
+}
+
+/// Parse a `min..max` range (or a single value) with optional `k`/`m` size suffixes
+pub fn parse_range(input: &str) -> Result<RangeInclusive<usize>> {
+    let (min, max) = match input.split_once("..") {
+        Some((min, max)) => (parse_size(min)?, parse_size(max.trim_start_matches('='))?),
+        None => {
+            let value = parse_size(input)?;
+            (value, value)
+        }
+    };
+
+    if min > max {
+        return Err(BmsError::Other(format!("Invalid range '{}': min exceeds max", input)));
+    }
+
+    Ok(min..=max)
+}
+
+fn parse_size(input: &str) -> Result<usize> {
+    let input = input.trim().to_ascii_lowercase();
+    let (digits, multiplier) = if let Some(d) = input.strip_suffix('k') {
+        (d, 1024)
+    } else if let Some(d) = input.strip_suffix('m') {
+        (d, 1024 * 1024)
+    } else {
+        (input.as_str(), 1)
+    };
+
+    digits
+        .parse::<usize>()
+        .map(|n| n * multiplier)
+        .map_err(|_| BmsError::Other(format!("Invalid size '{}'", input)))
+}
+
+/// Run a simulation, storing every generated state through the facade
+pub async fn run_simulation(
+    facade: &BmsFacade,
+    config: &SimulationConfig,
+) -> Result<SimulationReport> {
+    let mut rng = ChaCha8Rng::seed_from_u64(config.seed);
+    let authors: Vec<String> = (0..config.authors.max(1))
+        .map(|i| format!("agent-{}", i))
+        .collect();
+    let epoch: DateTime<Utc> = Utc.with_ymd_and_hms(2025, 1, 1, 0, 0, 0).unwrap();
+
+    let mut report = SimulationReport {
+        coordinates: 0,
+        deltas: 0,
+        snapshots: 0,
+        large_rewrites: 0,
+        state_bytes: 0,
+        ops_bytes: 0,
+        diff_stats: DiffStats::default(),
+        patch_ratios: PatchRatios::default(),
+    };
+
+    for index in 0..config.coords {
+        let target_size = rng.gen_range(config.state_size.clone());
+        let delta_count = rng.gen_range(config.deltas.clone()).max(1);
+
+        let mut state = config.profile.initial(&mut rng, target_size);
+        let timestamp = epoch + Duration::seconds(index as i64);
+        let coord_id: CoordId = CoordinateGenerator::generate(&state, &timestamp, HashAlgorithm::Sha3_256)?;
+
+        for step in 0..delta_count {
+            if step > 0 && config.profile.evolve(&mut state, &mut rng, target_size) {
+                report.large_rewrites += 1;
+            }
+
+            let author = authors.choose(&mut rng).cloned();
+            let outcome = facade
+                .store(StoreParams {
+                    coord_id: Some(coord_id.clone()),
+                    state: state.clone(),
+                    metadata: None,
+                    author,
+                    precondition: None,
+                    diff_options: None,
+                    explain: false,
+                    created_at: None,
+                    op_authors: None,
+                    policy: None,
+                    namespace: None,
+                    hash_algorithm: None,
+                    parent_coord_id: None,
+                    child_label: None,
+                })
+                .await?;
+
+            report.deltas += 1;
+            report.ops_bytes += outcome.ops_bytes as u64;
+            report.diff_stats.lcs_arrays += outcome.diff_stats.lcs_arrays;
+            report.diff_stats.replaced_arrays += outcome.diff_stats.replaced_arrays;
+            if let Some(ratio) = outcome.patch_ratio {
+                report.patch_ratios.record(ratio, outcome.full_replace);
+            }
+            report.state_bytes += serde_json::to_string(&state)?.len() as u64;
+            if outcome.snapshot == SnapshotStatus::Created {
+                report.snapshots += 1;
+            }
+        }
+
+        report.coordinates += 1;
+    }
+
+    Ok(report)
+}
+
+fn word(rng: &mut ChaCha8Rng) -> &'static str {
+    WORDS.choose(rng).copied().unwrap_or("memory")
+}
+
+/// Random text from the simulation vocabulary
+pub fn sentence(rng: &mut ChaCha8Rng, words: usize) -> String {
+    (0..words.max(1))
+        .map(|_| word(rng))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn serialized_len(value: &Value) -> usize {
+    serde_json::to_string(value).map(|s| s.len()).unwrap_or(0)
+}
+
+impl SimulationProfile {
+    /// Generate the first state of a coordinate
+    pub fn initial(&self, rng: &mut ChaCha8Rng, target_size: usize) -> Value {
+        let mut state = match self {
+            Self::Chatlog => json!({
+                "session": format!("{}-{}", word(rng), rng.gen::<u32>()),
+                "turn": 0,
+                "summary": sentence(rng, 12),
+                "messages": [],
+            }),
+            Self::Config => json!({
+                "version": 1,
+                "etag": format!("{:016x}", rng.gen::<u64>()),
+                "settings": {},
+            }),
+            Self::Document => json!({
+                "title": sentence(rng, 4),
+                "revision": 0,
+                "sections": [],
+            }),
+        };
+
+        // Grow to roughly half the target so evolution has room to churn
+        while serialized_len(&state) < target_size / 2 {
+            self.grow(&mut state, rng);
+        }
+        state
+    }
+
+    /// Evolve a state by one step, returning true for a large rewrite
+    pub fn evolve(&self, state: &mut Value, rng: &mut ChaCha8Rng, target_size: usize) -> bool {
+        let large_rewrite = rng.gen_bool(LARGE_REWRITE_PROBABILITY);
+
+        match self {
+            Self::Chatlog => {
+                let turn = state["turn"].as_u64().unwrap_or(0) + 1;
+                state["turn"] = json!(turn);
+                if large_rewrite {
+                    let length = rng.gen_range(40..120);
+                    state["summary"] = json!(sentence(rng, length));
+                }
+                if let Some(messages) = state["messages"].as_array_mut() {
+                    if !messages.is_empty() && rng.gen_bool(0.1) {
+                        let idx = rng.gen_range(0..messages.len());
+                        let length = rng.gen_range(4..20);
+                        messages[idx]["text"] = json!(sentence(rng, length));
+                    }
+                }
+                for _ in 0..rng.gen_range(1..=3) {
+                    self.grow(state, rng);
+                }
+            }
+            Self::Config => {
+                let version = state["version"].as_u64().unwrap_or(0) + 1;
+                state["version"] = json!(version);
+                state["etag"] = json!(format!("{:016x}", rng.gen::<u64>()));
+                if large_rewrite {
+                    if let Some(settings) = state["settings"].as_object_mut() {
+                        let sections: Vec<String> = settings.keys().cloned().collect();
+                        if let Some(section) = sections.choose(rng) {
+                            let rewritten = config_section(rng, 16);
+                            settings.insert(section.clone(), rewritten);
+                        }
+                    }
+                }
+                for _ in 0..rng.gen_range(1..=4) {
+                    churn_config_key(state, rng);
+                }
+                if rng.gen_bool(0.2) {
+                    self.grow(state, rng);
+                }
+            }
+            Self::Document => {
+                let revision = state["revision"].as_u64().unwrap_or(0) + 1;
+                state["revision"] = json!(revision);
+                if large_rewrite {
+                    if let Some(sections) = state["sections"].as_array_mut() {
+                        for section in sections.iter_mut() {
+                            let length = rng.gen_range(20..80);
+                            section["body"] = json!(sentence(rng, length));
+                        }
+                    }
+                } else if let Some(sections) = state["sections"].as_array_mut() {
+                    if !sections.is_empty() {
+                        let idx = rng.gen_range(0..sections.len());
+                        let length = rng.gen_range(20..80);
+                        sections[idx]["body"] = json!(sentence(rng, length));
+                    }
+                }
+                if rng.gen_bool(0.3) {
+                    self.grow(state, rng);
+                }
+            }
+        }
+
+        self.trim(state, target_size);
+        large_rewrite
+    }
+
+    /// Add one unit of content to a state
+    fn grow(&self, state: &mut Value, rng: &mut ChaCha8Rng) {
+        match self {
+            Self::Chatlog => {
+                let length = rng.gen_range(4..24);
+                let message = json!({
+                    "role": ROLES.choose(rng).copied().unwrap_or("user"),
+                    "text": sentence(rng, length),
+                });
+                if let Some(messages) = state["messages"].as_array_mut() {
+                    messages.push(message);
+                }
+            }
+            Self::Config => {
+                let name = format!("{}_{}", word(rng), rng.gen_range(0..1000));
+                let section = config_section(rng, 6);
+                if let Some(settings) = state["settings"].as_object_mut() {
+                    settings.insert(name, section);
+                }
+            }
+            Self::Document => {
+                let length = rng.gen_range(20..80);
+                let section = json!({
+                    "heading": sentence(rng, 3),
+                    "body": sentence(rng, length),
+                });
+                if let Some(sections) = state["sections"].as_array_mut() {
+                    sections.push(section);
+                }
+            }
+        }
+    }
+
+    /// Drop the oldest content until the state fits its target size
+    fn trim(&self, state: &mut Value, target_size: usize) {
+        while serialized_len(state) > target_size {
+            let removed = match self {
+                Self::Chatlog => remove_first(&mut state["messages"]),
+                Self::Document => remove_first(&mut state["sections"]),
+                Self::Config => state["settings"]
+                    .as_object_mut()
+                    .and_then(|settings| {
+                        let first = settings.keys().next().cloned()?;
+                        settings.remove(&first)
+                    })
+                    .is_some(),
+            };
+            if !removed {
+                break;
+            }
+        }
+    }
+}
+
+fn remove_first(value: &mut Value) -> bool {
+    match value.as_array_mut() {
+        Some(items) if !items.is_empty() => {
+            items.remove(0);
+            true
+        }
+        _ => false,
+    }
+}
+
+fn config_section(rng: &mut ChaCha8Rng, keys: usize) -> Value {
+    let mut section = Map::new();
+    for _ in 0..keys {
+        section.insert(format!("{}_{}", word(rng), rng.gen_range(0..100)), config_value(rng));
+    }
+    Value::Object(section)
+}
+
+fn config_value(rng: &mut ChaCha8Rng) -> Value {
+    match rng.gen_range(0..4) {
+        0 => json!(rng.gen_bool(0.5)),
+        1 => json!(rng.gen_range(0..10_000)),
+        2 => json!(word(rng)),
+        _ => json!(sentence(rng, 3)),
+    }
+}
+
+fn churn_config_key(state: &mut Value, rng: &mut ChaCha8Rng) {
+    let Some(settings) = state["settings"].as_object_mut() else {
+        return;
+    };
+    let sections: Vec<String> = settings.keys().cloned().collect();
+    let Some(section_name) = sections.choose(rng) else {
+        return;
+    };
+    let Some(section) = settings
+        .get_mut(section_name)
+        .and_then(|s| s.as_object_mut())
+    else {
+        return;
+    };
+
+    let keys: Vec<String> = section.keys().cloned().collect();
+    match rng.gen_range(0..3) {
+        // Add a key
+        0 => {
+            section.insert(format!("{}_{}", word(rng), rng.gen_range(0..100)), config_value(rng));
+        }
+        // Remove a key
+        1 if keys.len() > 1 => {
+            if let Some(key) = keys.choose(rng) {
+                section.remove(key);
+            }
+        }
+        // Modify a key
+        _ => {
+            if let Some(key) = keys.choose(rng) {
+                section.insert(key.clone(), config_value(rng));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDb;
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("50..500").unwrap(), 50..=500);
+        assert_eq!(parse_range("1k..100k").unwrap(), 1024..=102_400);
+        assert_eq!(parse_range("7").unwrap(), 7..=7);
+        assert!(parse_range("10..5").is_err());
+        assert!(parse_range("abc").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_simulation_is_deterministic() {
+        for profile in ["chatlog", "config", "document"] {
+            let config = SimulationConfig {
+                coords: 3,
+                deltas: 5..=25,
+                state_size: 512..=4096,
+                authors: 3,
+                seed: 7,
+                profile: profile.parse().unwrap(),
+            };
+
+            let db_a = TempDb::new(&format!("simulate-{}-a", profile));
+            let db_b = TempDb::new(&format!("simulate-{}-b", profile));
+            let facade_a = db_a.facade(10).await;
+            let facade_b = db_b.facade(10).await;
+
+            let report_a = run_simulation(&facade_a, &config).await.unwrap();
+            let report_b = run_simulation(&facade_b, &config).await.unwrap();
+
+            assert_eq!(report_a, report_b);
+            assert_eq!(report_a.coordinates, 3);
+            assert!(report_a.snapshots > 0);
+
+            let mut coords_a: Vec<_> = facade_a
+                .repository()
+                .list_coordinates(None, None)
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|c| c.id)
+                .collect();
+            let mut coords_b: Vec<_> = facade_b
+                .repository()
+                .list_coordinates(None, None)
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|c| c.id)
+                .collect();
+            coords_a.sort_by(|a, b| a.0.cmp(&b.0));
+            coords_b.sort_by(|a, b| a.0.cmp(&b.0));
+            assert_eq!(coords_a, coords_b);
+
+            let stats = facade_a.repository().get_stats().await.unwrap();
+            assert_eq!(stats.delta_count, report_a.deltas);
+            assert_eq!(stats.snapshot_count, report_a.snapshots);
+        }
+    }
+}
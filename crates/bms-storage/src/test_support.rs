@@ -0,0 +1,46 @@
+//! Shared helpers for storage tests
+
+use crate::facade::BmsFacade;
+use crate::repository::BmsRepository;
+use bms_core::SnapshotManager;
+use std::path::PathBuf;
+
+/// Temporary on-disk database removed when dropped
+pub struct TempDb {
+    pub path: PathBuf,
+}
+
+impl TempDb {
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!(
+            "bms-test-{}-{}.db",
+            std::process::id(),
+            name
+        ));
+        let _ = std::fs::remove_file(&path);
+        Self { path }
+    }
+
+    pub async fn repository(&self) -> BmsRepository {
+        BmsRepository::new(&self.path).await.unwrap()
+    }
+
+    pub async fn facade(&self, snapshot_interval: u32) -> BmsFacade {
+        BmsFacade::new(self.repository().await, SnapshotManager::new(snapshot_interval))
+    }
+
+    /// Run raw SQL on a separate connection, e.g. to simulate corruption
+    pub async fn execute(&self, sql: &str) {
+        let pool = sqlx::SqlitePool::connect(&format!("sqlite://{}", self.path.display()))
+            .await
+            .unwrap();
+        sqlx::query(sql).execute(&pool).await.unwrap();
+        pool.close().await;
+    }
+}
+
+impl Drop for TempDb {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
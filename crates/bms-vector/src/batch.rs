@@ -0,0 +1,129 @@
+//! Batched embedding with per-item retry
+//!
+//! One model call over many states is much cheaper than one call per state,
+//! but a single state the model rejects would fail the whole call. Items are
+//! embedded in chunks of `max_items`; when a chunk fails, each of its items is
+//! retried alone so only the bad ones come back as errors.
+
+use crate::VectorError;
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+/// Default number of states embedded per model call
+pub const DEFAULT_BATCH_SIZE: usize = 32;
+
+/// Running counters for batched embedding
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BatchStats {
+    pub batches: u64,
+    pub items: u64,
+    /// Items embedded alone after their batch failed
+    pub retried_items: u64,
+    /// Items that failed on their own as well
+    pub failed_items: u64,
+    pub last_batch_ms: f64,
+    pub total_ms: f64,
+}
+
+impl BatchStats {
+    fn record(&mut self, items: usize, elapsed: Duration) {
+        let ms = elapsed.as_secs_f64() * 1000.0;
+        self.batches += 1;
+        self.items += items as u64;
+        self.last_batch_ms = ms;
+        self.total_ms += ms;
+    }
+}
+
+/// Embed `items` in chunks of at most `max_items`
+///
+/// `embed` must return one vector per input, in order. The result has one
+/// entry per item, in order.
+pub fn embed_in_batches<T, F>(
+    items: &[T],
+    max_items: usize,
+    stats: &mut BatchStats,
+    mut embed: F,
+) -> Vec<Result<Vec<f32>, VectorError>>
+where
+    F: FnMut(&[T]) -> Result<Vec<Vec<f32>>, VectorError>,
+{
+    let mut results = Vec::with_capacity(items.len());
+    for chunk in items.chunks(max_items.max(1)) {
+        let started = Instant::now();
+        let batch = embed(chunk).and_then(|vectors| {
+            if vectors.len() == chunk.len() {
+                Ok(vectors)
+            } else {
+                Err(VectorError::Embedding(format!(
+                    "Expected {} embeddings, got {}",
+                    chunk.len(),
+                    vectors.len()
+                )))
+            }
+        });
+        match batch {
+            Ok(vectors) => results.extend(vectors.into_iter().map(Ok)),
+            Err(e) if chunk.len() > 1 => {
+                tracing::warn!("Embedding batch of {} failed, retrying items alone: {}", chunk.len(), e);
+                for item in chunk {
+                    stats.retried_items += 1;
+                    let single = embed(std::slice::from_ref(item)).and_then(|mut vectors| {
+                        vectors
+                            .pop()
+                            .ok_or_else(|| VectorError::Embedding("No embedding generated".to_string()))
+                    });
+                    if single.is_err() {
+                        stats.failed_items += 1;
+                    }
+                    results.push(single);
+                }
+            }
+            Err(e) => {
+                stats.failed_items += 1;
+                results.push(Err(e));
+            }
+        }
+        stats.record(chunk.len(), started.elapsed());
+    }
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Embeds a number as a one-element vector; fails any call containing 0
+    fn fake(items: &[u32]) -> Result<Vec<Vec<f32>>, VectorError> {
+        if items.contains(&0) {
+            return Err(VectorError::Embedding("zero".to_string()));
+        }
+        Ok(items.iter().map(|n| vec![*n as f32]).collect())
+    }
+
+    #[test]
+    fn test_chunks_preserve_order() {
+        let mut stats = BatchStats::default();
+        let mut calls = 0;
+        let results = embed_in_batches(&[1, 2, 3, 4, 5], 2, &mut stats, |items| {
+            calls += 1;
+            fake(items)
+        });
+
+        let vectors: Vec<Vec<f32>> = results.into_iter().map(Result::unwrap).collect();
+        assert_eq!(vectors, [[1.0], [2.0], [3.0], [4.0], [5.0]]);
+        assert_eq!(calls, 3);
+        assert_eq!((stats.batches, stats.items, stats.retried_items), (3, 5, 0));
+    }
+
+    #[test]
+    fn test_failed_batch_retries_items_alone() {
+        let mut stats = BatchStats::default();
+        let results = embed_in_batches(&[1, 0, 3], 8, &mut stats, fake);
+
+        assert_eq!(results[0].as_ref().unwrap(), &[1.0]);
+        assert!(results[1].is_err());
+        assert_eq!(results[2].as_ref().unwrap(), &[3.0]);
+        assert_eq!((stats.batches, stats.retried_items, stats.failed_items), (1, 3, 1));
+    }
+}
@@ -68,6 +68,17 @@ impl EmbeddingGenerator {
         
         self.generate(&text)
     }
+
+    /// Generate embeddings for several JSON states in one model call
+    pub fn generate_from_states(&mut self, states: &[serde_json::Value]) -> Result<Vec<Vec<f32>>, VectorError> {
+        let texts = states
+            .iter()
+            .map(serde_json::to_string)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| VectorError::Embedding(format!("Failed to serialize state: {}", e)))?;
+
+        self.generate_batch(texts.iter().map(String::as_str).collect())
+    }
 }
 
 #[cfg(test)]
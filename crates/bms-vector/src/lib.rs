@@ -8,12 +8,17 @@ use bms_core::types::CoordId;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+pub mod batch;
 mod embedding;
 mod memory_store;
+pub mod projection;
 mod types;
 
+pub use batch::{embed_in_batches, BatchStats};
 pub use embedding::EmbeddingGenerator;
+pub use bms_core::extract::{ExtractedText, ExtractionConfig, FACET_MARKER};
 pub use memory_store::InMemoryVectorStore;
+pub use projection::{Projection, ProjectionSource, DEFAULT_PROJECTION_SEED};
 pub use types::{SearchFilter, SearchQuery, SearchResult, VectorMetadata};
 
 #[derive(Error, Debug)]
@@ -29,11 +34,35 @@ pub enum VectorError {
     
     #[error("Collection not found: {0}")]
     CollectionNotFound(String),
+
+    #[error("Invalid vector configuration: {0}")]
+    InvalidConfig(String),
+
+    #[error("Index was built with projection {found}, but this store uses {expected}")]
+    ProjectionMismatch { expected: String, found: String },
     
+    #[error("{path} is truncated or partial: {reason}")]
+    PartialFile { path: String, reason: String },
+
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
 }
 
+impl VectorError {
+    /// Error for a JSON file that failed to parse, naming the path; one that
+    /// ends early is reported as partial
+    pub(crate) fn from_json_file(path: &std::path::Path, err: serde_json::Error) -> Self {
+        if err.is_eof() {
+            VectorError::PartialFile {
+                path: path.display().to_string(),
+                reason: err.to_string(),
+            }
+        } else {
+            VectorError::InvalidConfig(format!("{}: {}", path.display(), err))
+        }
+    }
+}
+
 /// Vector store trait for different implementations
 #[async_trait::async_trait]
 pub trait VectorStore: Send + Sync {
@@ -45,6 +74,20 @@ pub trait VectorStore: Send + Sync {
         metadata: VectorMetadata,
     ) -> Result<(), VectorError>;
 
+    /// Store several embeddings at once
+    ///
+    /// Implementations should write the whole batch in one transaction; the
+    /// default stores them one by one.
+    async fn store_embeddings_batch(
+        &self,
+        items: Vec<(CoordId, Vec<f32>, VectorMetadata)>,
+    ) -> Result<(), VectorError> {
+        for (coord_id, embedding, metadata) in items {
+            self.store_embedding(&coord_id, embedding, metadata).await?;
+        }
+        Ok(())
+    }
+
     /// Search for similar coordinates by embedding vector
     async fn search_by_vector(
         &self,
@@ -63,8 +106,13 @@ pub trait VectorStore: Send + Sync {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VectorStats {
     pub total_vectors: u64,
+    /// Dimension of the stored vectors, after any projection
     pub dimension: usize,
+    /// Dimension of the embeddings handed to the store
+    pub input_dimension: usize,
     pub indexed_vectors: u64,
+    /// Fingerprint of the projection, if vectors are reduced
+    pub projection: Option<String>,
 }
 
 /// Configuration for vector store
@@ -82,6 +130,16 @@ pub struct VectorConfig {
     /// HNSW index parameters
     pub hnsw_m: usize,
     pub hnsw_ef_construct: usize,
+
+    /// Project vectors to this many dimensions before storing and searching
+    pub reduce_to: Option<usize>,
+
+    /// Seed of the random projection used for `reduce_to`
+    pub projection_seed: u64,
+
+    /// Projection fitted with `bms index fit-projection`, used instead of the
+    /// random one; must map `dimension` to `reduce_to`
+    pub fitted_projection: Option<Projection>,
 }
 
 impl Default for VectorConfig {
@@ -92,6 +150,40 @@ impl Default for VectorConfig {
             dimension: 384, // all-MiniLM-L6-v2 embedding size
             hnsw_m: 32,
             hnsw_ef_construct: 200,
+            reduce_to: None,
+            projection_seed: DEFAULT_PROJECTION_SEED,
+            fitted_projection: None,
+        }
+    }
+}
+
+impl VectorConfig {
+    /// The projection for `reduce_to`, checked against `dimension`
+    ///
+    /// `None` when vectors are stored at full size.
+    pub fn projection(&self) -> Result<Option<Projection>, VectorError> {
+        match (self.reduce_to, &self.fitted_projection) {
+            (None, None) => Ok(None),
+            (None, Some(_)) => Err(VectorError::InvalidConfig(
+                "a fitted projection needs reduce_to".to_string(),
+            )),
+            (Some(reduce_to), None) => Projection::random(self.dimension, reduce_to, self.projection_seed).map(Some),
+            (Some(reduce_to), Some(fitted)) => {
+                if fitted.input_dimension() != self.dimension {
+                    return Err(VectorError::InvalidDimension {
+                        expected: self.dimension,
+                        actual: fitted.input_dimension(),
+                    });
+                }
+                if fitted.output_dimension() != reduce_to {
+                    return Err(VectorError::InvalidConfig(format!(
+                        "fitted projection reduces to {} dimensions, but reduce_to is {}",
+                        fitted.output_dimension(),
+                        reduce_to
+                    )));
+                }
+                Ok(Some(fitted.clone()))
+            }
         }
     }
 }
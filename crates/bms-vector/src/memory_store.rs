@@ -1,33 +1,130 @@
 //! Simple in-memory vector store implementation
 //!
 //! This is a basic implementation for Phase 2. Can be enhanced with Qdrant later.
+//! With `VectorConfig::reduce_to`, embeddings and queries are projected to
+//! fewer dimensions on the way in; see `projection`.
 
+use crate::projection::Projection;
 use crate::types::{SearchFilter, SearchResult, VectorMetadata};
 use crate::{VectorConfig, VectorError, VectorStats, VectorStore};
 use bms_core::types::CoordId;
+use bms_core::FilterRecord;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::path::Path;
 use std::sync::{Arc, RwLock};
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 struct VectorEntry {
     embedding: Vec<f32>,
     metadata: VectorMetadata,
 }
 
+/// An index written by `InMemoryVectorStore::save`
+#[derive(Serialize, Deserialize)]
+struct SavedIndex {
+    dimension: usize,
+    input_dimension: usize,
+    /// Fingerprint of the projection the vectors went through
+    projection: Option<String>,
+    entries: HashMap<String, VectorEntry>,
+}
+
 /// Simple in-memory vector store
 pub struct InMemoryVectorStore {
     vectors: Arc<RwLock<HashMap<String, VectorEntry>>>,
+    /// Dimension of embeddings handed to the store
     dimension: usize,
+    projection: Option<Projection>,
 }
 
 impl InMemoryVectorStore {
     /// Create new in-memory vector store
+    ///
+    /// Fails if the projection settings do not fit `config.dimension`.
     pub fn new(config: VectorConfig) -> Result<Self, VectorError> {
         Ok(Self {
             vectors: Arc::new(RwLock::new(HashMap::new())),
+            projection: config.projection()?,
             dimension: config.dimension,
         })
     }
+
+    /// Dimension of the stored vectors
+    fn stored_dimension(&self) -> usize {
+        self.projection.as_ref().map_or(self.dimension, Projection::output_dimension)
+    }
+
+    fn fingerprint(&self) -> Option<String> {
+        self.projection.as_ref().map(Projection::fingerprint)
+    }
+
+    /// Check an embedding's dimension and project it for storage or search
+    fn prepare(&self, embedding: Vec<f32>) -> Result<Vec<f32>, VectorError> {
+        if embedding.len() != self.dimension {
+            return Err(VectorError::InvalidDimension {
+                expected: self.dimension,
+                actual: embedding.len(),
+            });
+        }
+        match &self.projection {
+            Some(projection) => projection.apply(&embedding),
+            None => Ok(embedding),
+        }
+    }
+
+    /// Write every stored vector, with the projection fingerprint, as JSON
+    ///
+    /// The file is replaced atomically; see `bms_core::atomic`.
+    pub fn save(&self, path: &Path) -> Result<(), VectorError> {
+        let vectors = self.vectors.read()
+            .map_err(|e| VectorError::Embedding(format!("Lock error: {}", e)))?;
+        let saved = SavedIndex {
+            dimension: self.stored_dimension(),
+            input_dimension: self.dimension,
+            projection: self.fingerprint(),
+            entries: vectors.clone(),
+        };
+        let json = serde_json::to_vec(&saved).map_err(|e| VectorError::InvalidConfig(e.to_string()))?;
+        bms_core::atomic_write(path, &json)?;
+        Ok(())
+    }
+
+    /// Read an index written by `save` into a store built from `config`
+    ///
+    /// An index saved with a different projection, or none when `config`
+    /// has one, is rejected: its vectors are not comparable with new queries.
+    pub fn load(path: &Path, config: VectorConfig) -> Result<Self, VectorError> {
+        let store = Self::new(config)?;
+        let saved: SavedIndex = serde_json::from_slice(&std::fs::read(path)?)
+            .map_err(|e| VectorError::from_json_file(path, e))?;
+
+        if saved.projection != store.fingerprint() {
+            let describe = |fingerprint: Option<String>| fingerprint.unwrap_or_else(|| "none".to_string());
+            return Err(VectorError::ProjectionMismatch {
+                expected: describe(store.fingerprint()),
+                found: describe(saved.projection),
+            });
+        }
+        if saved.input_dimension != store.dimension {
+            return Err(VectorError::InvalidDimension {
+                expected: store.dimension,
+                actual: saved.input_dimension,
+            });
+        }
+        let dimension = store.stored_dimension();
+        if let Some(entry) = saved.entries.values().find(|e| e.embedding.len() != dimension) {
+            return Err(VectorError::InvalidDimension {
+                expected: dimension,
+                actual: entry.embedding.len(),
+            });
+        }
+
+        *store.vectors.write()
+            .map_err(|e| VectorError::Embedding(format!("Lock error: {}", e)))? = saved.entries;
+        Ok(store)
+    }
     
     /// Calculate cosine similarity between two vectors
     fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
@@ -60,6 +157,27 @@ impl InMemoryVectorStore {
             }
         }
         
+        if let Some(custom) = &filter.custom {
+            if !custom.iter().all(|(key, value)| metadata.custom.get(key) == Some(value)) {
+                return false;
+            }
+        }
+        
+        if let Some(expr) = &filter.expr {
+            let record = FilterRecord {
+                author: metadata.author.as_deref(),
+                tags: &metadata.tags,
+                created_at: DateTime::parse_from_rfc3339(&metadata.created_at)
+                    .ok()
+                    .map(|t| t.with_timezone(&Utc)),
+                metadata: Some(&metadata.custom),
+                state: None,
+            };
+            if !expr.matches(&record) {
+                return false;
+            }
+        }
+
         // TODO: Implement date filtering
         
         true
@@ -74,12 +192,7 @@ impl VectorStore for InMemoryVectorStore {
         embedding: Vec<f32>,
         metadata: VectorMetadata,
     ) -> Result<(), VectorError> {
-        if embedding.len() != self.dimension {
-            return Err(VectorError::InvalidDimension {
-                expected: self.dimension,
-                actual: embedding.len(),
-            });
-        }
+        let embedding = self.prepare(embedding)?;
         
         let entry = VectorEntry {
             embedding,
@@ -93,6 +206,25 @@ impl VectorStore for InMemoryVectorStore {
         
         Ok(())
     }
+
+    async fn store_embeddings_batch(
+        &self,
+        items: Vec<(CoordId, Vec<f32>, VectorMetadata)>,
+    ) -> Result<(), VectorError> {
+        // Validate and project everything first so a bad item leaves the store untouched
+        let items = items
+            .into_iter()
+            .map(|(coord_id, embedding, metadata)| Ok((coord_id, self.prepare(embedding)?, metadata)))
+            .collect::<Result<Vec<_>, VectorError>>()?;
+
+        let mut vectors = self.vectors.write()
+            .map_err(|e| VectorError::Embedding(format!("Lock error: {}", e)))?;
+        for (coord_id, embedding, metadata) in items {
+            vectors.insert(coord_id.to_string(), VectorEntry { embedding, metadata });
+        }
+
+        Ok(())
+    }
     
     async fn search_by_vector(
         &self,
@@ -100,12 +232,7 @@ impl VectorStore for InMemoryVectorStore {
         limit: usize,
         filter: Option<SearchFilter>,
     ) -> Result<Vec<SearchResult>, VectorError> {
-        if query_embedding.len() != self.dimension {
-            return Err(VectorError::InvalidDimension {
-                expected: self.dimension,
-                actual: query_embedding.len(),
-            });
-        }
+        let query_embedding = self.prepare(query_embedding)?;
         
         let vectors = self.vectors.read()
             .map_err(|e| VectorError::Embedding(format!("Lock error: {}", e)))?;
@@ -153,8 +280,172 @@ impl VectorStore for InMemoryVectorStore {
         
         Ok(VectorStats {
             total_vectors: vectors.len() as u64,
-            dimension: self.dimension,
+            dimension: self.stored_dimension(),
+            input_dimension: self.dimension,
             indexed_vectors: vectors.len() as u64,
+            projection: self.fingerprint(),
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::projection::DEFAULT_PROJECTION_SEED;
+    use bms_core::FilterExpr;
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+
+    const DIMENSION: usize = 192;
+
+    /// Unit vectors around 40 topics in a 32-dim subspace, with noise
+    fn corpus(seed: u64, n: usize) -> Vec<Vec<f32>> {
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        let latent = 32;
+        let mix: Vec<Vec<f32>> = (0..latent)
+            .map(|_| (0..DIMENSION).map(|_| rng.gen_range(-1.0..1.0)).collect())
+            .collect();
+        let topics: Vec<Vec<f32>> = (0..40)
+            .map(|_| (0..latent).map(|_| rng.gen_range(-1.0..1.0)).collect())
+            .collect();
+        (0..n)
+            .map(|_| {
+                let topic = &topics[rng.gen_range(0..topics.len())];
+                let z: Vec<f32> = topic.iter().map(|t| t + rng.gen_range(-0.6..0.6)).collect();
+                let mut v: Vec<f32> = (0..DIMENSION)
+                    .map(|d| mix.iter().zip(&z).map(|(m, z)| m[d] * z).sum::<f32>() + rng.gen_range(-0.3..0.3))
+                    .collect();
+                let norm = v.iter().map(|x| x * x).sum::<f32>().sqrt();
+                v.iter_mut().for_each(|x| *x /= norm);
+                v
+            })
+            .collect()
+    }
+
+    async fn index(config: VectorConfig, vectors: &[Vec<f32>]) -> InMemoryVectorStore {
+        let store = InMemoryVectorStore::new(config).unwrap();
+        let items = vectors
+            .iter()
+            .enumerate()
+            .map(|(i, v)| {
+                let coord_id = CoordId(format!("C{}", i));
+                (coord_id.clone(), v.clone(), VectorMetadata::new(coord_id))
+            })
+            .collect();
+        store.store_embeddings_batch(items).await.unwrap();
+        store
+    }
+
+    /// Mean share of the full store's top 10 that the reduced one also returns
+    async fn top10_overlap(full: &InMemoryVectorStore, reduced: &InMemoryVectorStore, queries: &[Vec<f32>]) -> f64 {
+        let mut total = 0.0;
+        for query in queries {
+            let top = |results: Vec<SearchResult>| -> Vec<CoordId> { results.into_iter().map(|r| r.coord_id).collect() };
+            let expected = top(full.search_by_vector(query.clone(), 10, None).await.unwrap());
+            let actual = top(reduced.search_by_vector(query.clone(), 10, None).await.unwrap());
+            total += expected.iter().filter(|id| actual.contains(id)).count() as f64 / 10.0;
+        }
+        total / queries.len() as f64
+    }
+
+    #[tokio::test]
+    async fn test_reduced_search_keeps_the_top_results() {
+        let vectors = corpus(1491, 1040);
+        let (stored, queries) = vectors.split_at(1000);
+        let full_config = VectorConfig { dimension: DIMENSION, ..VectorConfig::default() };
+        let random_config = VectorConfig { reduce_to: Some(64), ..full_config.clone() };
+        let fitted_config = VectorConfig {
+            fitted_projection: Some(Projection::fit(&stored[..500], 64, 1).unwrap()),
+            ..random_config.clone()
+        };
+
+        let full = index(full_config, stored).await;
+        let random = index(random_config, stored).await;
+        let fitted = index(fitted_config, stored).await;
+        let stats = random.get_stats().await.unwrap();
+        assert_eq!((stats.dimension, stats.input_dimension, stats.total_vectors), (64, DIMENSION, 1000));
+        let seeded = Projection::random(DIMENSION, 64, DEFAULT_PROJECTION_SEED).unwrap();
+        assert_eq!(stats.projection, Some(seeded.fingerprint()));
+
+        let random_overlap = top10_overlap(&full, &random, queries).await;
+        assert!(random_overlap >= 0.6, "random projection top-10 overlap {}", random_overlap);
+        let fitted_overlap = top10_overlap(&full, &fitted, queries).await;
+        assert!(fitted_overlap >= 0.9, "fitted projection top-10 overlap {}", fitted_overlap);
+    }
+
+    #[tokio::test]
+    async fn test_filter_expression() {
+        let store = InMemoryVectorStore::new(VectorConfig { dimension: 3, ..VectorConfig::default() }).unwrap();
+        let entries = [
+            ("alice", vec!["db"], serde_json::json!({"priority": 1})),
+            ("bob", vec!["db", "urgent"], serde_json::json!({"priority": 5})),
+            ("alice", vec![], serde_json::json!({})),
+        ];
+        for (i, (author, tags, custom)) in entries.into_iter().enumerate() {
+            let coord_id = CoordId(format!("C{}", i));
+            let mut metadata = VectorMetadata::new(coord_id.clone());
+            metadata.author = Some(author.to_string());
+            metadata.tags = tags.into_iter().map(String::from).collect();
+            metadata.custom = serde_json::from_value(custom).unwrap();
+            store.store_embedding(&coord_id, vec![1.0, i as f32, 0.0], metadata).await.unwrap();
+        }
+
+        let search = |text: &str| {
+            let filter = SearchFilter {
+                author: None,
+                tags: None,
+                created_after: None,
+                created_before: None,
+                custom: None,
+                expr: Some(FilterExpr::parse(text, Utc::now()).unwrap()),
+            };
+            let store = &store;
+            async move {
+                let mut ids: Vec<String> = store
+                    .search_by_vector(vec![1.0, 0.0, 0.0], 10, Some(filter))
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|r| r.coord_id.0)
+                    .collect();
+                ids.sort();
+                ids
+            }
+        };
+        assert_eq!(search("author == alice and not metadata.priority > 3").await, ["C0", "C2"]);
+        assert_eq!(search("tag == urgent or metadata.priority < 2").await, ["C0", "C1"]);
+        assert_eq!(search("created >= -1d and not exists metadata.priority").await, ["C2"]);
+        // The store has no head states to read
+        assert!(search("/status == open").await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_saved_index_needs_the_same_projection() {
+        let config = VectorConfig { dimension: DIMENSION, reduce_to: Some(64), ..VectorConfig::default() };
+        let store = index(config.clone(), &corpus(7, 20)).await;
+        let path = std::env::temp_dir().join(format!("bms-vector-index-{}.json", std::process::id()));
+        store.save(&path).unwrap();
+
+        let loaded = InMemoryVectorStore::load(&path, config.clone()).unwrap();
+        assert_eq!(loaded.get_stats().await.unwrap().total_vectors, 20);
+        let reseeded = VectorConfig { projection_seed: 1, ..config.clone() };
+        assert!(matches!(
+            InMemoryVectorStore::load(&path, reseeded),
+            Err(VectorError::ProjectionMismatch { .. })
+        ));
+        let unreduced = VectorConfig { reduce_to: None, ..config.clone() };
+        match InMemoryVectorStore::load(&path, unreduced) {
+            Err(VectorError::ProjectionMismatch { expected, .. }) => assert_eq!(expected, "none"),
+            other => panic!("expected a projection mismatch, got {:?}", other.err()),
+        }
+
+        // A file cut short is reported as partial, naming it
+        let saved = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &saved[..saved.len() / 2]).unwrap();
+        match InMemoryVectorStore::load(&path, config) {
+            Err(e @ VectorError::PartialFile { .. }) => assert!(e.to_string().contains(&path.display().to_string())),
+            other => panic!("expected a partial file error, got {:?}", other.err()),
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+}
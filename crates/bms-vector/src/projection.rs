@@ -0,0 +1,307 @@
+//! Projection of embeddings to fewer dimensions
+//!
+//! A 384-dim f32 vector costs 1.5 KB per coordinate in the in-memory store.
+//! Projecting every vector to e.g. 128 dims keeps most of the neighbourhood
+//! structure at a third of the memory. There are two kinds of matrix:
+//!
+//! - Random: ±1 entries drawn from a seed (Achlioptas). It needs no data,
+//!   and the same seed always gives the same matrix.
+//! - Fitted: the top principal directions of a sample of stored embeddings,
+//!   written by `bms index fit-projection`. The second moments are not
+//!   centered, so dot products, and with them cosine scores, are kept as well
+//!   as a linear map of that size can.
+//!
+//! Stored and query vectors must go through the same matrix. Its fingerprint
+//! is recorded in saved indexes, and a different one is rejected at load.
+
+use crate::VectorError;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
+use std::path::Path;
+
+/// Seed of the random projection when none is configured
+pub const DEFAULT_PROJECTION_SEED: u64 = 0x626d_735f_7072_6f6a;
+
+/// Subspace iterations when fitting; the top directions of embedding
+/// samples separate well before this
+const FIT_ITERATIONS: usize = 24;
+
+/// How a projection matrix was made
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ProjectionSource {
+    Random { seed: u64 },
+    Fitted {
+        samples: usize,
+        /// Share of the samples' squared length the kept directions carry
+        retained_energy: f64,
+    },
+}
+
+/// Linear map from `input` to `output` dimensions
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Projection {
+    input: usize,
+    output: usize,
+    source: ProjectionSource,
+    /// Row-major, `output` rows of `input` columns
+    matrix: Vec<f32>,
+}
+
+impl Projection {
+    /// Random projection, the same for the same dimensions and seed
+    pub fn random(input: usize, output: usize, seed: u64) -> Result<Self, VectorError> {
+        check_dimensions(input, output)?;
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        let scale = 1.0 / (output as f32).sqrt();
+        let matrix = (0..input * output)
+            .map(|_| if rng.gen::<bool>() { scale } else { -scale })
+            .collect();
+        Ok(Self {
+            input,
+            output,
+            source: ProjectionSource::Random { seed },
+            matrix,
+        })
+    }
+
+    /// Top `output` principal directions of `samples`
+    ///
+    /// Block power iteration on the uncentered second-moment matrix, started
+    /// from a seeded random block. Needs at least `output` samples, all of
+    /// one dimension.
+    pub fn fit(samples: &[Vec<f32>], output: usize, seed: u64) -> Result<Self, VectorError> {
+        let Some(first) = samples.first() else {
+            return Err(VectorError::InvalidConfig("no samples to fit a projection to".to_string()));
+        };
+        let input = first.len();
+        check_dimensions(input, output)?;
+        if let Some(sample) = samples.iter().find(|s| s.len() != input) {
+            return Err(VectorError::InvalidDimension {
+                expected: input,
+                actual: sample.len(),
+            });
+        }
+        if samples.len() < output {
+            return Err(VectorError::InvalidConfig(format!(
+                "fitting {} dimensions needs at least as many samples, got {}",
+                output,
+                samples.len()
+            )));
+        }
+
+        // Second moments in f64; only the upper triangle is summed
+        let mut moments = vec![0f64; input * input];
+        for sample in samples {
+            for i in 0..input {
+                let x = sample[i] as f64;
+                for j in i..input {
+                    moments[i * input + j] += x * sample[j] as f64;
+                }
+            }
+        }
+        let n = samples.len() as f64;
+        for i in 0..input {
+            for j in i..input {
+                moments[i * input + j] /= n;
+                moments[j * input + i] = moments[i * input + j];
+            }
+        }
+
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        let mut basis: Vec<Vec<f64>> = (0..output)
+            .map(|_| (0..input).map(|_| rng.gen_range(-1.0..1.0)).collect())
+            .collect();
+        orthonormalize(&mut basis);
+        for _ in 0..FIT_ITERATIONS {
+            basis = basis.iter().map(|v| multiply(&moments, v)).collect();
+            orthonormalize(&mut basis);
+        }
+
+        // Strongest direction first
+        let mut directions: Vec<(f64, Vec<f64>)> = basis
+            .into_iter()
+            .map(|v| (dot(&v, &multiply(&moments, &v)), v))
+            .collect();
+        directions.sort_by(|a, b| b.0.total_cmp(&a.0));
+        let trace: f64 = (0..input).map(|i| moments[i * input + i]).sum();
+        let kept: f64 = directions.iter().map(|(energy, _)| energy).sum();
+
+        Ok(Self {
+            input,
+            output,
+            source: ProjectionSource::Fitted {
+                samples: samples.len(),
+                retained_energy: if trace > 0.0 { (kept / trace).min(1.0) } else { 0.0 },
+            },
+            matrix: directions
+                .into_iter()
+                .flat_map(|(_, v)| v.into_iter().map(|x| x as f32))
+                .collect(),
+        })
+    }
+
+    pub fn input_dimension(&self) -> usize {
+        self.input
+    }
+
+    pub fn output_dimension(&self) -> usize {
+        self.output
+    }
+
+    pub fn source(&self) -> &ProjectionSource {
+        &self.source
+    }
+
+    /// SHA3-256 of the dimensions and matrix, in hex
+    ///
+    /// Two projections with the same fingerprint map vectors identically.
+    pub fn fingerprint(&self) -> String {
+        let mut hasher = Sha3_256::new();
+        hasher.update((self.input as u64).to_le_bytes());
+        hasher.update((self.output as u64).to_le_bytes());
+        for value in &self.matrix {
+            hasher.update(value.to_le_bytes());
+        }
+        format!("{:x}", hasher.finalize())
+    }
+
+    /// Project one vector
+    pub fn apply(&self, vector: &[f32]) -> Result<Vec<f32>, VectorError> {
+        if vector.len() != self.input {
+            return Err(VectorError::InvalidDimension {
+                expected: self.input,
+                actual: vector.len(),
+            });
+        }
+        Ok(self
+            .matrix
+            .chunks_exact(self.input)
+            .map(|row| row.iter().zip(vector).map(|(a, b)| a * b).sum())
+            .collect())
+    }
+
+    /// Write the projection as JSON, replacing any file at `path` atomically
+    pub fn save(&self, path: &Path) -> Result<(), VectorError> {
+        let json = serde_json::to_vec(self).map_err(|e| VectorError::InvalidConfig(e.to_string()))?;
+        bms_core::atomic_write(path, &json)?;
+        Ok(())
+    }
+
+    /// Read a projection written by `save`
+    pub fn load(path: &Path) -> Result<Self, VectorError> {
+        let projection: Self = serde_json::from_slice(&std::fs::read(path)?)
+            .map_err(|e| VectorError::from_json_file(path, e))?;
+        check_dimensions(projection.input, projection.output)?;
+        if projection.matrix.len() != projection.input * projection.output {
+            return Err(VectorError::InvalidConfig(format!(
+                "{}: matrix has {} values, expected {} × {}",
+                path.display(),
+                projection.matrix.len(),
+                projection.output,
+                projection.input
+            )));
+        }
+        Ok(projection)
+    }
+}
+
+fn check_dimensions(input: usize, output: usize) -> Result<(), VectorError> {
+    if output == 0 || output >= input {
+        return Err(VectorError::InvalidConfig(format!(
+            "cannot reduce {} dimensions to {}",
+            input, output
+        )));
+    }
+    Ok(())
+}
+
+fn dot(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+/// Symmetric `matrix` times `v`
+fn multiply(matrix: &[f64], v: &[f64]) -> Vec<f64> {
+    matrix.chunks_exact(v.len()).map(|row| dot(row, v)).collect()
+}
+
+/// Gram-Schmidt, in order; a vector that collapses is left at zero
+fn orthonormalize(basis: &mut [Vec<f64>]) {
+    for i in 0..basis.len() {
+        let (done, rest) = basis.split_at_mut(i);
+        let v = &mut rest[0];
+        for u in done.iter() {
+            let along = dot(v, u);
+            v.iter_mut().zip(u).for_each(|(x, y)| *x -= along * y);
+        }
+        let norm = dot(v, v).sqrt();
+        if norm > 1e-12 {
+            v.iter_mut().for_each(|x| *x /= norm);
+        } else {
+            v.iter_mut().for_each(|x| *x = 0.0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::VectorConfig;
+
+    #[test]
+    fn test_random_projection_is_seeded() {
+        let a = Projection::random(64, 16, 7).unwrap();
+        assert_eq!(a, Projection::random(64, 16, 7).unwrap());
+        assert_eq!(a.fingerprint(), Projection::random(64, 16, 7).unwrap().fingerprint());
+        assert_ne!(a.fingerprint(), Projection::random(64, 16, 8).unwrap().fingerprint());
+        assert_eq!(a.apply(&[1.0; 64]).unwrap().len(), 16);
+        assert!(matches!(a.apply(&[1.0; 32]), Err(VectorError::InvalidDimension { expected: 64, actual: 32 })));
+        assert!(matches!(Projection::random(64, 64, 7), Err(VectorError::InvalidConfig(_))));
+        assert!(matches!(Projection::random(64, 0, 7), Err(VectorError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn test_fitted_projection_round_trips() {
+        // Samples spanning four directions: fitting four keeps all of their length
+        let mut rng = ChaCha8Rng::seed_from_u64(3);
+        let samples: Vec<Vec<f32>> = (0..40)
+            .map(|_| {
+                let mut v = vec![0.0; 32];
+                for d in [1, 5, 9, 20] {
+                    v[d] = rng.gen_range(-1.0..1.0);
+                }
+                v
+            })
+            .collect();
+        let fitted = Projection::fit(&samples, 4, 1).unwrap();
+        let ProjectionSource::Fitted { samples: 40, retained_energy } = *fitted.source() else {
+            panic!("unexpected source {:?}", fitted.source());
+        };
+        assert!(retained_energy > 0.999, "{}", retained_energy);
+        assert!(matches!(Projection::fit(&samples[..3], 4, 1), Err(VectorError::InvalidConfig(_))));
+
+        let path = std::env::temp_dir().join(format!("bms-projection-{}.json", std::process::id()));
+        fitted.save(&path).unwrap();
+        let loaded = Projection::load(&path).unwrap();
+        assert_eq!(loaded.fingerprint(), fitted.fingerprint());
+        let saved = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &saved[..saved.len() - 1]).unwrap();
+        assert!(matches!(Projection::load(&path), Err(VectorError::PartialFile { .. })));
+        std::fs::remove_file(&path).unwrap();
+
+        // The fitted projection must agree with the configured dimensions
+        let config = |reduce_to| VectorConfig {
+            dimension: 32,
+            reduce_to,
+            fitted_projection: Some(loaded.clone()),
+            ..VectorConfig::default()
+        };
+        assert_eq!(config(Some(4)).projection().unwrap(), Some(fitted));
+        assert!(matches!(config(Some(8)).projection(), Err(VectorError::InvalidConfig(_))));
+        assert!(matches!(config(None).projection(), Err(VectorError::InvalidConfig(_))));
+        let wider = VectorConfig { dimension: 384, ..config(Some(4)) };
+        assert!(matches!(wider.projection(), Err(VectorError::InvalidDimension { expected: 384, actual: 32 })));
+    }
+}
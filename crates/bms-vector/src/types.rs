@@ -1,6 +1,7 @@
 //! Vector search types and models
 
 use bms_core::types::CoordId;
+use bms_core::FilterExpr;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
@@ -21,6 +22,10 @@ pub struct VectorMetadata {
     
     /// Custom metadata fields
     pub custom: HashMap<String, serde_json::Value>,
+
+    /// Metadata keys appended to the embedded text; see `bms_core::extract`
+    #[serde(default)]
+    pub facets: Vec<String>,
 }
 
 impl VectorMetadata {
@@ -31,6 +36,7 @@ impl VectorMetadata {
             author: None,
             tags: Vec::new(),
             custom: HashMap::new(),
+            facets: Vec::new(),
         }
     }
     
@@ -43,6 +49,11 @@ impl VectorMetadata {
         self.tags = tags;
         self
     }
+
+    pub fn with_facets(mut self, facets: Vec<String>) -> Self {
+        self.facets = facets;
+        self
+    }
 }
 
 /// Search query parameters
@@ -79,6 +90,15 @@ pub struct SearchFilter {
     /// Filter by date range
     pub created_after: Option<String>,
     pub created_before: Option<String>,
+    
+    /// Filter by custom metadata fields (all must be equal)
+    #[serde(default)]
+    pub custom: Option<HashMap<String, serde_json::Value>>,
+
+    /// Filter expression over author, tags, creation time and custom fields;
+    /// head-state predicates never match, since the store holds no states
+    #[serde(default)]
+    pub expr: Option<FilterExpr>,
 }
 
 /// Search result with score